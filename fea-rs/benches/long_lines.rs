@@ -0,0 +1,60 @@
+//! Benchmarks for sources with extremely long lines.
+//!
+//! Generated FEA sometimes contains a single glyph class that is megabytes
+//! long, all on one line. Parsing should be linear in the size of the input,
+//! and the cost of reporting a diagnostic should not depend on the line length.
+
+use std::{ffi::OsStr, sync::Arc};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use fea_rs::{Diagnostic, ParseTree};
+
+const N_GLYPHS: usize = 100_000;
+
+// a single ~1MB line, with an error every so often
+fn long_line() -> Arc<str> {
+    let mut text = String::from("@big = [");
+    for i in 0..N_GLYPHS {
+        if i % 1000 == 999 {
+            text.push_str(" 12");
+        }
+        text.push_str(&format!(" glyph.{i}"));
+    }
+    text.push_str(" ];\n");
+    text.into()
+}
+
+fn parse(text: Arc<str>) -> (ParseTree, Vec<Diagnostic>) {
+    fea_rs::parse::parse_root("long.fea".into(), None, move |_: &OsStr| Ok(text.clone())).unwrap()
+}
+
+fn long_lines(c: &mut Criterion) {
+    let text = long_line();
+    c.bench_function("parse long line", |b| {
+        b.iter(|| parse(black_box(text.clone())))
+    });
+
+    let (tree, diagnostics) = parse(text);
+    assert!(!diagnostics.is_empty());
+    // also report a diagnostic at the very end of the line
+    let end = tree.root().text_len() - 2;
+    let diagnostics = diagnostics
+        .iter()
+        .cloned()
+        .chain(Some(Diagnostic::warning(
+            diagnostics[0].message.file,
+            end..end + 1,
+            "the end",
+        )))
+        .collect::<Vec<_>>();
+    c.bench_function("format diagnostics on long line", |b| {
+        b.iter(|| {
+            for diagnostic in &diagnostics {
+                black_box(tree.format_diagnostic(diagnostic, false));
+            }
+        })
+    });
+}
+
+criterion_group!(benches, long_lines);
+criterion_main!(benches);
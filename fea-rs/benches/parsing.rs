@@ -27,5 +27,33 @@ fn parsing(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, parsing);
+/// Compare an incremental reparse after a small edit with a full parse.
+fn reparsing(c: &mut Criterion) {
+    let resolver: fea_rs::parse::MemoryResolver = [("roboto.fea", LATN)].into_iter().collect();
+    let (tree, diagnostics) =
+        fea_rs::parse::parse_root("roboto.fea".into(), None, resolver).unwrap();
+    let old_text = "sub Y macroncomb by uni0232;";
+    let start = LATN.find(old_text).unwrap();
+    let range = start..start + old_text.len();
+    let new_text = "sub Y macroncomb by uni0233;";
+    let edited: Arc<str> = format!("{}{new_text}{}", &LATN[..start], &LATN[range.end..]).into();
+
+    c.bench_function("reparse roboto-regular after edit", |b| {
+        b.iter(|| {
+            tree.reparse_range(
+                tree.root_id(),
+                black_box(range.clone()),
+                black_box(new_text),
+                None,
+                &diagnostics,
+            )
+            .unwrap()
+        })
+    });
+    c.bench_function("parse roboto-regular after edit", |b| {
+        b.iter(|| parse_source(black_box(edited.clone())))
+    });
+}
+
+criterion_group!(benches, parsing, reparsing);
 criterion_main!(benches);
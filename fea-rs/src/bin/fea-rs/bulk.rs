@@ -0,0 +1,253 @@
+//! Parse all the FEA files in a directory.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use fea_rs::{compile, Diagnostic, DiagnosticCode, GlyphMap, Level};
+
+use crate::{input, Error};
+
+/// The number of codes listed for each file, and for the whole run.
+const TOP_CODES: usize = 5;
+
+/// Parse all the FEA files in a directory
+#[derive(clap::Args, Debug)]
+pub(crate) struct Args {
+    /// A directory containing FEA files
+    dir: PathBuf,
+    /// Path to a file containing the glyph order, in any format supported
+    /// by the 'compile' subcommand.
+    ///
+    /// If absent, we look for a 'glyph_order.txt' file in the directory or
+    /// any of its parents.
+    #[arg(short, long)]
+    glyph_order: Option<PathBuf>,
+    /// Also validate the files that parse, so that the summary includes
+    /// problems found by the compiler (requires a glyph order)
+    #[arg(long)]
+    validate: bool,
+    /// Print the diagnostics for files that fail to parse
+    #[arg(short, long)]
+    verbose: bool,
+    /// Instead of the usual report, print a summary of each file to stdout
+    #[arg(long, value_name = "FORMAT")]
+    summary: Option<SummaryFormat>,
+}
+
+/// The format of the per-file summary.
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum SummaryFormat {
+    /// One line per file, with a header
+    Csv,
+    /// An array with one object per file
+    Json,
+}
+
+/// The diagnostics reported for a single file.
+#[derive(Debug, serde::Serialize)]
+struct FileSummary {
+    path: PathBuf,
+    panicked: bool,
+    errors: usize,
+    warnings: usize,
+    /// The most frequent diagnostic codes, most frequent first
+    top_codes: Vec<CodeCount>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct CodeCount {
+    code: DiagnosticCode,
+    count: usize,
+}
+
+/// Parse each file, reporting the files that fail (or panic).
+pub(crate) fn run(args: Args) -> Result<(), Error> {
+    let glyph_map = match args
+        .glyph_order
+        .clone()
+        .or_else(|| input::find_glyph_order_file(&args.dir))
+    {
+        Some(path) => Some(compile::load_glyph_order(&path)?),
+        None if args.validate => return Err(Error::MissingGlyphOrder),
+        None => None,
+    };
+
+    let mut paths = Vec::new();
+    for entry in args.dir.read_dir()? {
+        let path = entry?.path();
+        if path.extension() == Some("fea".as_ref()) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut summaries = Vec::with_capacity(paths.len());
+    let mut all_codes = HashMap::new();
+    for path in &paths {
+        log::info!("parsing '{}'", path.display());
+        let summary = match std::panic::catch_unwind(|| check_file(path, glyph_map.as_ref(), &args))
+        {
+            Err(_) => FileSummary::panicked(path),
+            Ok(Ok((summary, codes))) => {
+                for (code, count) in codes {
+                    *all_codes.entry(code).or_default() += count;
+                }
+                summary
+            }
+            Ok(Err(err)) => {
+                if args.verbose {
+                    eprintln!("{err}");
+                }
+                FileSummary::new(path, 1, 0, HashMap::new())
+            }
+        };
+        summaries.push(summary);
+    }
+    let n_failures = summaries.iter().filter(|s| s.failed()).count();
+
+    match args.summary {
+        Some(SummaryFormat::Csv) => print_csv(&summaries),
+        Some(SummaryFormat::Json) => {
+            let json = serde_json::to_string_pretty(&summaries).expect("summary is serializable");
+            println!("{json}");
+        }
+        None => print_report(&summaries, all_codes),
+    }
+    match n_failures {
+        0 => Ok(()),
+        n => Err(Error::Failures(n)),
+    }
+}
+
+/// Parse (and if requested, validate) a file, returning its summary and the
+/// number of diagnostics with each code.
+fn check_file(
+    path: &Path,
+    glyph_map: Option<&GlyphMap>,
+    args: &Args,
+) -> Result<(FileSummary, HashMap<DiagnosticCode, usize>), Error> {
+    let (tree, mut diagnostics) = fea_rs::parse::parse_root_file(path, glyph_map, None)?;
+    let parsed = !diagnostics.iter().any(Diagnostic::is_error);
+    if let Some(glyph_map) = glyph_map.filter(|_| args.validate && parsed) {
+        diagnostics.extend(compile::validate(&tree, glyph_map, None));
+    }
+    let count = |level| diagnostics.iter().filter(|d| d.level == level).count();
+    let (errors, warnings) = (count(Level::Error), count(Level::Warning));
+    if args.verbose && errors > 0 {
+        eprintln!("{}", tree.format_diagnostics(&diagnostics, false));
+    }
+    let mut codes = HashMap::new();
+    for code in diagnostics.iter().filter_map(|d| d.code) {
+        *codes.entry(code).or_default() += 1;
+    }
+    let summary = FileSummary::new(path, errors, warnings, codes.clone());
+    Ok((summary, codes))
+}
+
+impl FileSummary {
+    fn new(
+        path: &Path,
+        errors: usize,
+        warnings: usize,
+        codes: HashMap<DiagnosticCode, usize>,
+    ) -> Self {
+        FileSummary {
+            path: path.to_owned(),
+            panicked: false,
+            errors,
+            warnings,
+            top_codes: top_codes(codes),
+        }
+    }
+
+    fn panicked(path: &Path) -> Self {
+        FileSummary {
+            panicked: true,
+            ..FileSummary::new(path, 0, 0, HashMap::new())
+        }
+    }
+
+    fn failed(&self) -> bool {
+        self.panicked || self.errors > 0
+    }
+
+    fn status(&self) -> &'static str {
+        match (self.panicked, self.errors) {
+            (true, _) => "panic",
+            (false, 0) => "ok",
+            (false, _) => "fail",
+        }
+    }
+}
+
+/// The most frequent codes, most frequent first (and then by name).
+fn top_codes(codes: HashMap<DiagnosticCode, usize>) -> Vec<CodeCount> {
+    let mut codes = codes
+        .into_iter()
+        .map(|(code, count)| CodeCount { code, count })
+        .collect::<Vec<_>>();
+    codes.sort_by(|a, b| {
+        b.count
+            .cmp(&a.count)
+            .then_with(|| a.code.name().cmp(b.code.name()))
+    });
+    codes.truncate(TOP_CODES);
+    codes
+}
+
+/// The usual human-readable report: totals, the failing files, and the most
+/// frequent codes across all files.
+fn print_report(summaries: &[FileSummary], all_codes: HashMap<DiagnosticCode, usize>) {
+    let failures = summaries.iter().filter(|s| s.failed()).collect::<Vec<_>>();
+    println!(
+        "\nparsed {}/{} files.",
+        summaries.len() - failures.len(),
+        summaries.len()
+    );
+    let all_codes = top_codes(all_codes);
+    if !all_codes.is_empty() {
+        println!("\nMOST FREQUENT CODES:");
+        for CodeCount { code, count } in &all_codes {
+            println!(" {count:>5} {code}");
+        }
+    }
+    if failures.is_empty() {
+        return;
+    }
+    println!("\nFAILURES:");
+    for summary in failures {
+        let panic = if summary.panicked { "PANIC" } else { "     " };
+        println!(" {} {}", panic, summary.path.display());
+    }
+}
+
+fn print_csv(summaries: &[FileSummary]) {
+    println!("path,status,errors,warnings,top_codes");
+    for summary in summaries {
+        let codes = summary
+            .top_codes
+            .iter()
+            .map(|CodeCount { code, count }| format!("{code}:{count}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        println!(
+            "{},{},{},{},{}",
+            csv_field(&summary.path.display().to_string()),
+            summary.status(),
+            summary.errors,
+            summary.warnings,
+            codes
+        );
+    }
+}
+
+/// Quote a field if it contains any characters that are special in CSV.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
@@ -0,0 +1,47 @@
+//! Parse and validate a FEA file.
+
+use fea_rs::{compile, parse::ParserOptions};
+
+use crate::{
+    input::{InputArgs, LintArgs},
+    Error,
+};
+
+/// Check a FEA file for errors
+#[derive(clap::Args, Debug)]
+pub(crate) struct Args {
+    #[command(flatten)]
+    input: InputArgs,
+    /// Only parse the input; don't validate it
+    #[arg(long)]
+    parse_only: bool,
+    /// Stop reporting syntax errors after this many have been found
+    #[arg(long, value_name = "N")]
+    max_errors: Option<usize>,
+    #[command(flatten)]
+    lints: LintArgs,
+}
+
+/// Report all diagnostics, failing if there are any errors.
+///
+/// Validation requires a glyph order, and is skipped if parsing fails. Warnings
+/// can be silenced or treated as errors, as when compiling.
+pub(crate) fn run(args: Args) -> Result<(), Error> {
+    let mut options = ParserOptions::new();
+    if let Some(max_errors) = args.max_errors {
+        options = options.max_errors(max_errors);
+    }
+    let (glyph_map, tree, mut diagnostics) = args.input.parse(&options)?;
+    let parsed = !diagnostics.iter().any(|d| d.is_error());
+    match glyph_map {
+        _ if args.parse_only || !parsed => (),
+        Some(glyph_map) => diagnostics.extend(compile::validate(&tree, &glyph_map, None)),
+        None => log::warn!("no glyph order found; only checking syntax"),
+    }
+    args.lints.lint_config().apply(&mut diagnostics);
+    crate::print_diagnostics(&tree, &diagnostics);
+    match diagnostics.iter().filter(|d| d.is_error()).count() {
+        0 => Ok(()),
+        n => Err(Error::HasErrors(n)),
+    }
+}
@@ -0,0 +1,46 @@
+//! Compare our output for a single FEA file with that of fonttools.
+
+use fea_rs::util::{self, ttx};
+
+use crate::{input::InputArgs, Error};
+
+/// Compare our output for a FEA file with that of fonttools
+#[derive(clap::Args, Debug)]
+pub(crate) struct Args {
+    #[command(flatten)]
+    input: InputArgs,
+}
+
+/// Compile the input with fea-rs and feaLib, and print a diff of the ttx
+/// output.
+///
+/// Lines starting with '<' are from feaLib, and lines starting with '>'
+/// are from fea-rs.
+pub(crate) fn run(args: Args) -> Result<(), Error> {
+    let fea_path = args.input.fea_path();
+    if !fea_path.exists() {
+        return Err(Error::MissingFeatureFile);
+    }
+    let glyph_map = args.input.glyph_map()?.ok_or(Error::MissingGlyphOrder)?;
+    ttx::assert_has_ttx_executable();
+
+    match ttx::compare_with_fealib(&fea_path, &glyph_map) {
+        ttx::TestResult::Success => {
+            println!("output matches fonttools");
+            Ok(())
+        }
+        ttx::TestResult::CompareFail {
+            expected, result, ..
+        } => {
+            let mut diff = String::new();
+            util::write_line_diff(&mut diff, &expected, &result)
+                .expect("writing to a string cannot fail");
+            println!("{diff}");
+            Err(Error::Failures(1))
+        }
+        other => {
+            eprintln!("{}", other.printer(true));
+            Err(Error::Failures(1))
+        }
+    }
+}
@@ -0,0 +1,278 @@
+//! Compile features into a font file
+
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use fea_rs::{
+    compile::{
+        self, error::CompilerError, Compilation, Compiler, LintConfig, MockVariationInfo, Opts,
+    },
+    DetachedDiagnostic, GlyphMap,
+};
+use write_fonts::read::FontRef;
+
+use crate::{
+    input::{self, LintArgs},
+    Error,
+};
+
+/// Compile the input, writing the generated font to the output path.
+///
+/// The glyph order is taken from the UFO, a glyph order file, or a font.
+pub(crate) fn run(args: Args) -> Result<(), Error> {
+    let (fea, glyph_names) = args.get_inputs()?;
+    let fea_exists = fea.exists();
+    if !fea_exists && !args.is_ufo() {
+        return Err(Error::MissingFeatureFile);
+    }
+
+    let var_info = args.get_var_info().transpose()?;
+
+    let opts = Opts::new()
+        .make_post_table(args.post)
+        .stamp_version_info(args.version_info)
+        .compact_pair_pos(args.compact_pair_pos)
+        .dedupe_lookups(args.dedupe_lookups)
+        .share_context_class_defs(args.share_context_class_defs)
+        .dedupe_names(args.dedupe_names);
+    let mut compiler = Compiler::new(fea, &glyph_names)
+        .with_opts(opts.clone())
+        .with_lint_config(args.lint_config());
+    for dir in &args.include_dirs {
+        compiler = compiler.with_include_search_path(dir);
+    }
+    // UFOs (especially templates) often have no features; treat this as empty
+    if !fea_exists {
+        log::info!("UFO contains no features.fea, compiling empty source");
+        compiler = compiler.with_resolver(|_: &OsStr| Ok(Arc::<str>::from("")));
+    }
+    if let Some(var_info) = var_info.as_ref() {
+        log::info!("compiling with {} mock variation axes", var_info.axes.len());
+        for (tag, info) in &var_info.axes {
+            log::info!(
+                "{tag}: ({}, {}, {})",
+                info.min_value,
+                info.default_value,
+                info.max_value
+            );
+        }
+
+        compiler = compiler.with_variable_info(var_info);
+    }
+    if args.json {
+        compiler = compiler.with_warning_handler(|warning| print_json(&warning));
+    }
+    let compiled = match compiler.compile() {
+        Ok(compiled) => compiled,
+        Err(
+            CompilerError::ParseFail(errors)
+            | CompilerError::ValidationFail(errors)
+            | CompilerError::CompilationFail(errors),
+        ) if args.json => {
+            errors.detach().iter().for_each(print_json);
+            std::process::exit(1);
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if compiled.is_empty() {
+        log::info!("no tables were generated");
+    }
+    if let Some(font_path) = args.font.as_deref() {
+        check_cv_characters(&compiled, font_path)?;
+    }
+
+    let (path, raw_font) = match args.test_font.as_deref() {
+        Some(path) => (path, compiled.to_test_font(&glyph_names)),
+        None => (args.out_path(), compiled.to_binary(&glyph_names, opts)),
+    };
+    let raw_font = raw_font.expect("ttf compile failed");
+
+    log::info!("writing {} bytes to {}", raw_font.len(), path.display());
+    std::fs::write(path, raw_font).map_err(Into::into)
+}
+
+/// Print a diagnostic to stderr as a single line of JSON.
+fn print_json(diagnostic: &DetachedDiagnostic) {
+    let json = serde_json::to_string(diagnostic).expect("diagnostic is serializable");
+    eprintln!("{json}");
+}
+
+/// Warn about any cvParameters characters that are missing from the font's cmap.
+fn check_cv_characters(compiled: &Compilation, font_path: &Path) -> Result<(), Error> {
+    let bytes = std::fs::read(font_path)?;
+    let unmapped =
+        FontRef::new(&bytes).and_then(|font| compiled.unmapped_character_variant_characters(&font));
+    match unmapped {
+        Ok(unmapped) => {
+            for (tag, c) in unmapped {
+                log::warn!(
+                    "'{tag}' cvParameters character U+{:04X} is not mapped in the font's cmap",
+                    c as u32
+                );
+            }
+        }
+        Err(e) => log::warn!("couldn't check cvParameters characters against cmap: '{e}'"),
+    }
+    Ok(())
+}
+
+/// Arguments for compilation
+#[derive(clap::Args, Debug)]
+pub(crate) struct Args {
+    /// Display more information about failures
+    ///
+    /// This includes errors encountered, as well as the generated diffs when
+    /// comparison fails.
+    #[arg(short, long)]
+    verbose: bool,
+    /// The main input; either a FEA file or a UFO.
+    ///
+    /// If a FEA file, you will also need to provide a glyph order.
+    /// If a UFO file, the public.glyphOrder key must be present.
+    input: PathBuf,
+    /// Path to a file containing the glyph order.
+    ///
+    /// This can be a utf-8 encoded file with one name per line, sorted in
+    /// glyphid order, a UFO or designspace, or a font with glyph names in
+    /// its 'post' table.
+    #[arg(short, long, visible_alias = "glyphs", group = "glyph_source")]
+    glyph_order: Option<PathBuf>,
+
+    /// Path to a font file to be used to calculate glyph order.
+    #[arg(short, long, group = "glyph_source")]
+    font: Option<PathBuf>,
+
+    /// An additional directory to search for included files
+    ///
+    /// This can be passed multiple times; directories are searched in order,
+    /// after the project root and the directory of the including file.
+    #[arg(short = 'I', long = "include-dir", value_name = "DIR")]
+    include_dirs: Vec<PathBuf>,
+
+    /// Variable fonts only: a path to a file containing info on variation axes.
+    ///
+    /// Note that we can not correctly compile variable fonts: deltas are computed
+    /// with a simple model that does not match the one used by font compilers,
+    /// and there is no axis mapping ('avar'). This functionality is provided
+    /// for debugging.
+    ///
+    /// This should be a utf-8 encoded file containing a list of axes and their
+    /// (min, default, max) values, in user coordinates.
+    ///
+    /// Blank lines and lines beginning with '#' will be skipped.
+    ///
+    /// e.g. it might look like,
+    ///
+    /// ```
+    /// wght 100 400 900
+    /// wdth 50 100 200
+    /// ```
+    #[arg(short, long)]
+    axis_info: Option<PathBuf>,
+
+    /// path to write the generated font. Defaults to 'compile-out.ttf'
+    ///
+    /// The font contains only the tables generated from the FEA, along with a
+    /// minimal 'maxp' table, so it can be merged into a font built elsewhere.
+    #[arg(short, long)]
+    out_path: Option<PathBuf>,
+
+    /// Write a minimal font for shaping tests to this path, instead.
+    ///
+    /// In addition to the compiled tables, this font has an empty glyph for
+    /// each name in the glyph order, and a cmap guessed from the glyph names,
+    /// so it can be used with tools like 'hb-shape' without a source font.
+    #[arg(long, conflicts_with = "out_path")]
+    test_font: Option<PathBuf>,
+
+    /// Optionally write a post table to the generated font
+    #[arg(short, long)]
+    post: bool,
+
+    /// Record the fea-rs version and compile options in a 'Debg' table
+    #[arg(long)]
+    version_info: bool,
+
+    /// Use class-based pair positioning subtables where they are smaller
+    #[arg(long)]
+    compact_pair_pos: bool,
+
+    /// Merge identical lookups, where this does not change their order
+    #[arg(long)]
+    dedupe_lookups: bool,
+
+    /// Let class-based chaining contextual subtables share a single classdef
+    #[arg(long)]
+    share_context_class_defs: bool,
+
+    /// Give identical groups of generated names a single name id
+    #[arg(long)]
+    dedupe_names: bool,
+
+    /// Print diagnostics to stderr as JSON, one object per line
+    ///
+    /// Each object has the 'path' of the file, the byte 'range' and 1-based
+    /// 'line' and 0-based byte 'column' where the diagnostic starts, its
+    /// 'severity', 'code', and 'message'.
+    #[arg(long)]
+    json: bool,
+
+    #[command(flatten)]
+    lints: LintArgs,
+}
+
+impl Args {
+    fn lint_config(&self) -> LintConfig {
+        self.lints.lint_config()
+    }
+
+    fn is_ufo(&self) -> bool {
+        input::is_ufo(&self.input)
+    }
+
+    fn get_inputs(&self) -> Result<(PathBuf, GlyphMap), Error> {
+        if self.is_ufo() {
+            let glyph_order = compile::load_glyph_order(&self.input)?;
+            let fea_path = self.input.join("features.fea");
+            Ok((fea_path, glyph_order))
+        } else {
+            let Some(path) = self.glyph_order().or(self.font.as_deref()) else {
+                return Err(Error::MissingGlyphOrder);
+            };
+            let order = compile::load_glyph_order(path)?;
+            Ok((self.input.clone(), order))
+        }
+    }
+
+    fn get_var_info(&self) -> Option<Result<MockVariationInfo, Error>> {
+        let Some(path) = self.axis_info() else {
+            return None;
+        };
+
+        let contents = match std::fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(e) => return Some(Err(e.into())),
+        };
+        Some(
+            MockVariationInfo::from_cli_input(&contents)
+                .map_err(|(line, message)| Error::BadAxisInfo { line, message }),
+        )
+    }
+
+    fn axis_info(&self) -> Option<&Path> {
+        self.axis_info.as_deref()
+    }
+
+    fn glyph_order(&self) -> Option<&Path> {
+        self.glyph_order.as_deref()
+    }
+
+    fn out_path(&self) -> &Path {
+        self.out_path
+            .as_deref()
+            .unwrap_or_else(|| Path::new("compile-out.ttf"))
+    }
+}
@@ -0,0 +1,56 @@
+//! Print debugging information about a FEA file.
+
+use std::time::Instant;
+
+use ansi_term::{Colour, Style};
+
+use crate::{input::InputArgs, Error};
+
+/// Print the parse tree or a highlighted version of a FEA file
+#[derive(clap::Args, Debug)]
+pub(crate) struct Args {
+    #[command(flatten)]
+    input: InputArgs,
+    /// Print the source with syntax highlighting, instead of the tree
+    #[arg(long)]
+    highlight: bool,
+}
+
+/// Print the parse tree (even if there are errors) and any diagnostics.
+pub(crate) fn run(args: Args) -> Result<(), Error> {
+    let time = Instant::now();
+    let (_, tree, diagnostics) = args.input.parse(&Default::default())?;
+    let elapsed = time.elapsed();
+    if args.highlight {
+        print_highlighted(&tree);
+    } else {
+        println!("{}", tree.root().simple_parse_tree());
+    }
+    crate::print_diagnostics(&tree, &diagnostics);
+
+    let millis = (elapsed.as_micros() as f64) / 1000.0;
+    eprintln!("parsed in {:.2}ms", millis);
+    Ok(())
+}
+
+/// Print the tokens of the tree to stdout, with syntax highlighting.
+fn print_highlighted(tree: &fea_rs::ParseTree) {
+    let mut current_style = Style::new().fg(Colour::White);
+    let mut needs_paint = String::new();
+
+    for token in tree.root().iter_tokens() {
+        let style = fea_rs::util::style_for_kind(token.kind);
+        // if the style has changed, draw the previous range.
+        if style != current_style {
+            if !needs_paint.is_empty() {
+                print!("{}", current_style.paint(&needs_paint));
+            }
+            current_style = style;
+            needs_paint.clear();
+        }
+        needs_paint.push_str(token.as_str());
+    }
+
+    // draw the last span
+    println!("{}", current_style.paint(needs_paint));
+}
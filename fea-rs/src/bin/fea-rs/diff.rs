@@ -3,48 +3,74 @@
 
 use std::path::{Path, PathBuf};
 
-use clap::Parser;
 use fea_rs::util::ttx;
 
-static TEST_DATA: &str = "./fea-rs/test-data/fonttools-tests";
+use crate::Error;
+
 static WIP_DIFF_DIR: &str = "./wip";
 
-fn main() {
-    env_logger::init();
-    let args = Args::parse();
+/// Compare compilation output to expected results
+#[derive(clap::Args, Debug)]
+pub(crate) struct Args {
+    /// Optional comma separated list of words matching tests to run.
+    ///
+    /// e.g.: -t "spec5,GPOS" matches spec5h1.fea, spec5fi2.fea, GPOS_2.fea, etc
+    #[arg(short, long = "test")]
+    test_filter: Option<String>,
+    /// The directory containing the fonttools test files
+    #[arg(long, default_value = "./fea-rs/test-data/fonttools-tests")]
+    test_data: PathBuf,
+    /// Write diffs to a ./wip directory
+    #[arg(short, long)]
+    write_diff: bool,
+    /// Save the results to a file, for later comparison.
+    ///
+    /// You can compare runs with the --compare option
+    #[arg(short, long)]
+    save: Option<PathBuf>,
+    /// Compare results against those previously saved
+    #[arg(short, long)]
+    compare: Option<PathBuf>,
+    /// Rewrite any .expected_diff files that no longer match the output.
+    ///
+    /// Comments at the top of existing files are preserved. This can also
+    /// be enabled by setting the FEA_UPDATE_EXPECT environment variable.
+    #[arg(long)]
+    bless: bool,
+}
 
-    let results = ttx::run_all_tests(TEST_DATA, args.test_filter.as_ref());
+pub(crate) fn run(args: Args) -> Result<(), Error> {
+    let mut results = ttx::run_all_tests(&args.test_data, args.test_filter.as_ref());
+    if args.bless {
+        results.bless_expected_diffs();
+    }
 
-    if let Some(to_compare) = args
-        .compare
-        .as_ref()
-        .map(std::fs::read)
-        .transpose()
-        .unwrap()
-    {
-        let old_result: ttx::Report = serde_json::from_slice(&to_compare).unwrap();
+    if let Some(to_compare) = args.compare.as_ref().map(std::fs::read).transpose()? {
+        let old_result: ttx::Report = serde_json::from_slice(&to_compare)
+            .map_err(|e| Error::File(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))?;
         eprintln!("{:?}", results.compare_printer(&old_result));
     } else {
         eprintln!("{results:?}",);
     }
 
     if let Some(path) = args.save {
-        let serialized = serde_json::to_vec(&results).unwrap();
-        std::fs::write(path, serialized).unwrap();
+        let serialized = serde_json::to_vec(&results).expect("report is serializable");
+        std::fs::write(path, serialized)?;
     }
 
     if args.write_diff {
-        save_wip_diffs(&results);
+        save_wip_diffs(&results)?;
     }
 
     if results.has_failures() {
         std::process::exit(1);
     }
+    Ok(())
 }
 
-fn save_wip_diffs(results: &ttx::Report) {
+fn save_wip_diffs(results: &ttx::Report) -> Result<(), Error> {
     if !Path::new(WIP_DIFF_DIR).exists() {
-        std::fs::create_dir(WIP_DIFF_DIR).unwrap();
+        std::fs::create_dir(WIP_DIFF_DIR)?;
     }
     for failure in &results.results {
         if let ttx::TestResult::CompareFail {
@@ -57,29 +83,8 @@ fn save_wip_diffs(results: &ttx::Report) {
                 .with_extension("expected_diff");
             let diff = ttx::plain_text_diff(expected, result);
             log::info!("saved diff to {}", out_path.display());
-            std::fs::write(out_path, diff).unwrap();
+            std::fs::write(out_path, diff)?;
         }
     }
-}
-
-/// Compare compilation output to expected results
-#[derive(clap::Parser, Debug)]
-#[command(author, version, long_about = None)]
-struct Args {
-    /// Optional comma separated list of words matching tests to run.
-    ///
-    /// e.g.: -t "spec5,GPOS" matches spec5h1.fea, spec5fi2.fea, GPOS_2.fea, etc
-    #[arg(short, long = "test")]
-    test_filter: Option<String>,
-    /// Write diffs to a ./wip directory
-    #[arg(short, long)]
-    write_diff: bool,
-    /// Save the results to a file, for later comparison.
-    ///
-    /// You can compare runs with the --compare option
-    #[arg(short, long)]
-    save: Option<PathBuf>,
-    /// Compare results against those previously saved
-    #[arg(short, long)]
-    compare: Option<PathBuf>,
+    Ok(())
 }
@@ -0,0 +1,83 @@
+//! Apply the fixes suggested by diagnostics.
+
+use std::{collections::BTreeMap, path::Path};
+
+use fea_rs::{compile, SuggestedEdit};
+
+use crate::{input::InputArgs, Error};
+
+/// Apply suggested fixes
+#[derive(clap::Args, Debug)]
+pub(crate) struct Args {
+    /// The FEA file (or UFO) to fix. Any included files are fixed as well.
+    ///
+    /// Without a glyph order, only fixes that don't depend on the glyph
+    /// order are reliable.
+    #[command(flatten)]
+    input: InputArgs,
+    /// Print the fixes that would be applied, without changing any files
+    #[arg(long)]
+    dry_run: bool,
+}
+
+/// Apply the edits suggested by diagnostics to the source files.
+///
+/// If there are any parse errors, only fixes for those are applied; the source
+/// is only validated once it parses cleanly.
+pub(crate) fn run(args: Args) -> Result<(), Error> {
+    let (glyph_map, tree, mut diagnostics) = args.input.parse(&Default::default())?;
+    if !diagnostics.iter().any(|d| d.is_error()) {
+        let glyph_map = glyph_map.unwrap_or_default();
+        diagnostics.extend(compile::validate(&tree, &glyph_map, None));
+    }
+
+    let mut edits = BTreeMap::new();
+    if !diagnostics.iter().any(|d| d.edit.is_some()) {
+        println!("no fixes to apply");
+    }
+    for diagnostic in &diagnostics {
+        if let Some(edit) = diagnostic.edit.as_ref() {
+            edits
+                .entry(diagnostic.message.file)
+                .or_insert_with(Vec::new)
+                .push((diagnostic, edit));
+        }
+    }
+
+    for (file, edits) in edits {
+        let source = tree.get_source(file).expect("diagnostic file is in tree");
+        let path = Path::new(source.path());
+        if args.dry_run {
+            for (diagnostic, edit) in &edits {
+                let (line, col) = source.line_col_for_offset(edit.span.range().start);
+                println!(
+                    "{}:{}:{}: {} (replace {:?} with {:?})",
+                    path.display(),
+                    line,
+                    col,
+                    diagnostic.text(),
+                    &source.text()[edit.span.range()],
+                    edit.replacement,
+                );
+            }
+            continue;
+        }
+        let (text, applied) =
+            SuggestedEdit::apply_all(source.text(), edits.iter().map(|(_, edit)| *edit));
+        if applied != edits.len() {
+            log::warn!(
+                "skipped {} overlapping edits in {}; run again to apply them",
+                edits.len() - applied,
+                path.display()
+            );
+        }
+        println!("applied {applied} fixes to {}", path.display());
+        std::fs::write(path, text)?;
+    }
+
+    let remaining = diagnostics.iter().filter(|d| d.edit.is_none()).count();
+    if remaining > 0 {
+        println!("{remaining} diagnostics have no automatic fix");
+    }
+    Ok(())
+}
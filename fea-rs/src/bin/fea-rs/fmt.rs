@@ -0,0 +1,68 @@
+//! Format a FEA file.
+
+use std::path::PathBuf;
+
+use fea_rs::format::{BraceStyle, FormatOptions, Indent};
+
+use crate::Error;
+
+/// Format a FEA file
+#[derive(clap::Args, Debug)]
+pub(crate) struct Args {
+    /// The FEA file to format. Included files are not formatted.
+    input: PathBuf,
+    /// Don't change the file; exit with an error if it is not formatted
+    #[arg(long)]
+    check: bool,
+    /// The number of spaces to indent each level of nesting
+    #[arg(long, default_value_t = 4)]
+    indent: usize,
+    /// Indent with tabs instead of spaces
+    #[arg(long, conflicts_with = "indent")]
+    tabs: bool,
+    /// Put the opening brace of each block on its own line
+    #[arg(long)]
+    brace_next_line: bool,
+    /// Wrap glyph classes that would make a line longer than this
+    #[arg(long)]
+    max_width: Option<usize>,
+}
+
+impl Args {
+    fn options(&self) -> FormatOptions {
+        let indent = if self.tabs {
+            Indent::Tab
+        } else {
+            Indent::Spaces(self.indent)
+        };
+        let brace_style = if self.brace_next_line {
+            BraceStyle::NextLine
+        } else {
+            BraceStyle::SameLine
+        };
+        FormatOptions::new()
+            .with_indent(indent)
+            .with_brace_style(brace_style)
+            .with_max_width(self.max_width)
+    }
+}
+
+pub(crate) fn run(args: Args) -> Result<(), Error> {
+    let (tree, diagnostics) = fea_rs::parse::parse_root_file(&args.input, None, None)?;
+    if diagnostics.iter().any(|d| d.is_error()) {
+        return Err(Error::ParseFail(
+            tree.format_diagnostics(&diagnostics, false),
+        ));
+    }
+    let formatted = fea_rs::format::format_with_options(&tree, &args.options());
+    let source = tree.get_source(tree.root_id()).expect("root is in tree");
+    if formatted == source.text() {
+        return Ok(());
+    }
+    if args.check {
+        println!("{} is not formatted", args.input.display());
+        std::process::exit(1);
+    }
+    std::fs::write(&args.input, formatted)?;
+    Ok(())
+}
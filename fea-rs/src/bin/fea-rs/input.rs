@@ -0,0 +1,151 @@
+//! Argument handling shared between subcommands.
+
+use std::path::{Path, PathBuf};
+
+use fea_rs::{
+    compile::{self, LintConfig, LintLevel},
+    parse::{FileSystemResolver, ParserOptions},
+    Diagnostic, DiagnosticCode, GlyphMap, ParseTree,
+};
+
+use crate::Error;
+
+/// The name of the glyph order file we look for, if one is not provided.
+static GLYPH_ORDER_FILE: &str = "glyph_order.txt";
+
+/// A FEA input, with an optional glyph order.
+#[derive(clap::Args, Debug)]
+pub(crate) struct InputArgs {
+    /// The input; either a FEA file or a UFO.
+    pub(crate) input: PathBuf,
+    /// Path to a file containing the glyph order.
+    ///
+    /// This can be a utf-8 encoded file with one name per line, sorted in
+    /// glyphid order, a UFO or designspace, or a font with glyph names in
+    /// its 'post' table. It is used to disambiguate glyph names containing
+    /// hyphens, and to check that glyphs exist.
+    ///
+    /// If this is absent and the input is not a UFO, we look for a
+    /// 'glyph_order.txt' file in the directory of the input or any of its
+    /// parents.
+    #[arg(short, long, visible_alias = "glyphs")]
+    pub(crate) glyph_order: Option<PathBuf>,
+    /// An additional directory to search for included files
+    ///
+    /// This can be passed multiple times; directories are searched in order,
+    /// after the project root and the directory of the including file.
+    #[arg(short = 'I', long = "include-dir", value_name = "DIR")]
+    pub(crate) include_dirs: Vec<PathBuf>,
+}
+
+impl InputArgs {
+    fn is_ufo(&self) -> bool {
+        is_ufo(&self.input)
+    }
+
+    /// The path to the root FEA file.
+    pub(crate) fn fea_path(&self) -> PathBuf {
+        if self.is_ufo() {
+            self.input.join("features.fea")
+        } else {
+            self.input.clone()
+        }
+    }
+
+    /// Load the glyph order, if one was provided or we can find one.
+    pub(crate) fn glyph_map(&self) -> Result<Option<GlyphMap>, Error> {
+        if let Some(path) = self.glyph_order.as_deref() {
+            return compile::load_glyph_order(path)
+                .map(Some)
+                .map_err(Into::into);
+        }
+        if self.is_ufo() {
+            return compile::load_glyph_order(&self.input)
+                .map(Some)
+                .map_err(Into::into);
+        }
+        let Some(path) = find_glyph_order_file(&self.input) else {
+            return Ok(None);
+        };
+        log::info!("using glyph order at '{}'", path.display());
+        compile::load_glyph_order(&path)
+            .map(Some)
+            .map_err(Into::into)
+    }
+
+    /// Parse the input, returning the glyph order used, the tree, and any
+    /// diagnostics.
+    pub(crate) fn parse(
+        &self,
+        options: &ParserOptions,
+    ) -> Result<(Option<GlyphMap>, ParseTree, Vec<Diagnostic>), Error> {
+        let glyph_map = self.glyph_map()?;
+        let fea_path = self.fea_path();
+        let project_root = if self.is_ufo() {
+            self.input.clone()
+        } else {
+            fea_path.parent().map(PathBuf::from).unwrap_or_default()
+        };
+        let resolver = FileSystemResolver::new(project_root)
+            .with_search_paths(self.include_dirs.iter().cloned());
+        let (tree, diagnostics) = fea_rs::parse::parse_root_with_options(
+            fea_path.into_os_string(),
+            glyph_map.as_ref(),
+            options,
+            resolver,
+        )?;
+        Ok((glyph_map, tree, diagnostics))
+    }
+}
+
+/// Options for promoting or silencing warnings.
+#[derive(clap::Args, Debug)]
+pub(crate) struct LintArgs {
+    /// Treat all warnings as errors
+    #[arg(long)]
+    deny_warnings: bool,
+
+    /// Treat warnings with this code as errors, e.g. 'duplicate-glyph-class'
+    #[arg(long, value_name = "CODE")]
+    deny: Vec<DiagnosticCode>,
+
+    /// Don't report warnings with this code
+    ///
+    /// This takes precedence over '--deny' and '--deny-warnings'.
+    #[arg(long, value_name = "CODE")]
+    allow: Vec<DiagnosticCode>,
+}
+
+impl LintArgs {
+    pub(crate) fn lint_config(&self) -> LintConfig {
+        let mut config = LintConfig::new();
+        if self.deny_warnings {
+            config = config.with_default_level(LintLevel::Deny);
+        }
+        for code in &self.deny {
+            config = config.with_level(*code, LintLevel::Deny);
+        }
+        for code in &self.allow {
+            config = config.with_level(*code, LintLevel::Allow);
+        }
+        config
+    }
+}
+
+pub(crate) fn is_ufo(path: &Path) -> bool {
+    path.extension() == Some("ufo".as_ref())
+}
+
+/// Look for a glyph order file in the directory of `path` or any of its parents.
+pub(crate) fn find_glyph_order_file(path: &Path) -> Option<PathBuf> {
+    let path = path.canonicalize().ok()?;
+    let start = if path.is_dir() {
+        Some(path.as_path())
+    } else {
+        path.parent()
+    };
+    start?
+        .ancestors()
+        .map(|dir| dir.join(GLYPH_ORDER_FILE))
+        .find(|candidate| candidate.is_file())
+}
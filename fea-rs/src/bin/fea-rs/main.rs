@@ -0,0 +1,134 @@
+//! The fea-rs command line tool.
+//!
+//! This bundles a number of subcommands for working with FEA files:
+//!
+//! - `compile`: compile features into a font file
+//! - `check`: parse and validate a FEA file, reporting any problems
+//! - `fmt`: format a FEA file
+//! - `fix`: apply the fixes suggested by diagnostics
+//! - `debug`: print the parse tree or a highlighted version of a file
+//! - `bulk`: parse every FEA file in a directory, reporting failures and
+//!   (optionally as CSV or JSON) the diagnostics found in each file
+//! - `diff`: compare our output against that of fonttools (requires the
+//!   `test` feature)
+//! - `compare`: compare our output for a single file against that of
+//!   fonttools (requires the `test` feature)
+//!
+//! For compatibility, if no subcommand is given the arguments are treated as
+//! arguments to `compile`.
+
+use std::io::IsTerminal;
+
+use clap::{Parser, Subcommand};
+use fea_rs::{
+    compile::error::{CompilerError, GlyphOrderLoadError},
+    parse::SourceLoadError,
+    Diagnostic, ParseTree,
+};
+
+mod bulk;
+mod check;
+#[cfg(feature = "test")]
+mod compare;
+mod compile;
+mod debug;
+#[cfg(feature = "test")]
+mod diff;
+mod fix;
+mod fmt;
+mod input;
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("{err}");
+        std::process::exit(1)
+    }
+}
+
+fn run() -> Result<(), Error> {
+    env_logger::init();
+    let cli = Cli::parse();
+    match cli.command {
+        Some(Command::Compile(args)) => compile::run(args),
+        Some(Command::Check(args)) => check::run(args),
+        Some(Command::Fmt(args)) => fmt::run(args),
+        Some(Command::Fix(args)) => fix::run(args),
+        Some(Command::Debug(args)) => debug::run(args),
+        Some(Command::Bulk(args)) => bulk::run(args),
+        #[cfg(feature = "test")]
+        Some(Command::Diff(args)) => diff::run(args),
+        #[cfg(feature = "test")]
+        Some(Command::Compare(args)) => compare::run(args),
+        None => compile::run(cli.args.expect("clap requires args without a subcommand")),
+    }
+}
+
+/// Print diagnostics to stderr, colorized if stderr is a terminal.
+fn print_diagnostics(tree: &ParseTree, diagnostics: &[Diagnostic]) {
+    let colorize = std::io::stderr().is_terminal();
+    for diagnostic in diagnostics {
+        eprintln!("{}", tree.format_diagnostic(diagnostic, colorize));
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+enum Error {
+    #[error("io error: '{0}'")]
+    File(#[from] std::io::Error),
+    #[error("Couldn't load glyph order: '{0}'")]
+    GlyphOrder(#[from] GlyphOrderLoadError),
+    #[error("The provided feature file does not exist")]
+    MissingFeatureFile,
+    #[error("No glyph order provided")]
+    MissingGlyphOrder,
+    #[error("Error parsing axis info: L{line}, '{message}'")]
+    BadAxisInfo { line: usize, message: String },
+    #[error("{0}")]
+    CompileFail(#[from] CompilerError),
+    #[error("{0}")]
+    SourceLoad(#[from] SourceLoadError),
+    #[error("Couldn't parse the feature file:\n{0}")]
+    ParseFail(String),
+    #[error("Found {0} error(s)")]
+    HasErrors(usize),
+    #[error("{0} file(s) failed")]
+    Failures(usize),
+}
+
+/// Tools for working with FEA files
+#[derive(Parser, Debug)]
+#[command(
+    author,
+    version,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    args: Option<compile::Args>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Compile features into a font file
+    Compile(compile::Args),
+    /// Parse and validate a FEA file, reporting any errors or warnings
+    Check(check::Args),
+    /// Format a FEA file, in place
+    Fmt(fmt::Args),
+    /// Apply suggested fixes to a FEA file, in place
+    Fix(fix::Args),
+    /// Print the parse tree of a FEA file, or highlight its source
+    Debug(debug::Args),
+    /// Parse all the FEA files in a directory, reporting any failures
+    Bulk(bulk::Args),
+    /// Compare compilation output with the results of fonttools
+    #[cfg(feature = "test")]
+    Diff(diff::Args),
+    /// Compare our output for a single FEA file with that of fonttools
+    #[cfg(feature = "test")]
+    Compare(compare::Args),
+}
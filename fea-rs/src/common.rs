@@ -1,6 +1,9 @@
 //! Types and helpers shared across modules
 
-use std::fmt::{Display, Formatter};
+use std::{
+    borrow::Cow,
+    fmt::{Display, Formatter},
+};
 
 use smol_str::SmolStr;
 pub use write_fonts::types::GlyphId;
@@ -9,6 +12,7 @@ mod glyph_class;
 mod glyph_map;
 
 pub use glyph_class::GlyphClass;
+pub(crate) use glyph_class::GlyphClassArena;
 pub use glyph_map::GlyphMap;
 
 /// A glyph name
@@ -28,7 +32,7 @@ pub enum GlyphOrClass {
 }
 
 /// Either a glyph name or a CID
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum GlyphIdent {
     /// A glyph name
     Name(GlyphName),
@@ -42,6 +46,18 @@ impl<T: Into<GlyphName>> From<T> for GlyphIdent {
     }
 }
 
+impl GlyphIdent {
+    /// The name of this glyph, as written to the `post` table.
+    ///
+    /// CIDs are named 'cid' followed by the zero-padded CID, as in fonttools.
+    pub(crate) fn post_name(&self) -> Cow<'_, str> {
+        match self {
+            GlyphIdent::Name(name) => Cow::Borrowed(name.as_str()),
+            GlyphIdent::Cid(cid) => Cow::Owned(format!("cid{cid:05}")),
+        }
+    }
+}
+
 impl Display for GlyphIdent {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
         match self {
@@ -1,4 +1,4 @@
-use std::rc::Rc;
+use std::{borrow::Borrow, collections::HashSet, sync::Arc};
 
 use write_fonts::types::GlyphId;
 
@@ -10,7 +10,19 @@ use super::GlyphOrClass;
 /// that a glyph class is sorted and deduplicated, and in other places it expects
 /// a glyph class to be an arbitrary sequence of glyphs.
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct GlyphClass(Rc<[GlyphId]>);
+pub struct GlyphClass(Arc<[GlyphId]>);
+
+/// Shared storage for the glyph classes resolved during compilation.
+///
+/// Cloning a [`GlyphClass`] is cheap, but classes that are resolved separately
+/// (for instance the same inline class used in many rules, or a named class
+/// that is repeated inside a class literal) each get their own allocation.
+/// The arena returns a single shared class for each distinct sequence of
+/// glyphs, so that a large class is only stored once.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct GlyphClassArena {
+    classes: HashSet<GlyphClass>,
+}
 
 impl std::iter::FromIterator<GlyphId> for GlyphClass {
     fn from_iter<T: IntoIterator<Item = GlyphId>>(iter: T) -> Self {
@@ -34,7 +46,7 @@ impl GlyphClass {
     }
 
     pub fn empty() -> Self {
-        Self(Rc::new([]))
+        Self(Arc::new([]))
     }
 
     pub fn sort_and_dedupe(&self) -> GlyphClass {
@@ -54,6 +66,24 @@ impl GlyphClass {
     }
 }
 
+impl GlyphClassArena {
+    /// Return the class containing exactly these glyphs, in this order.
+    pub(crate) fn intern(&mut self, glyphs: Vec<GlyphId>) -> GlyphClass {
+        if let Some(class) = self.classes.get(glyphs.as_slice()) {
+            return class.clone();
+        }
+        let class = GlyphClass::from(glyphs);
+        self.classes.insert(class.clone());
+        class
+    }
+}
+
+impl Borrow<[GlyphId]> for GlyphClass {
+    fn borrow(&self) -> &[GlyphId] {
+        &self.0
+    }
+}
+
 impl From<Vec<GlyphId>> for GlyphClass {
     fn from(src: Vec<GlyphId>) -> GlyphClass {
         GlyphClass(src.into())
@@ -76,3 +106,21 @@ impl From<GlyphOrClass> for GlyphClass {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arena_shares_equal_classes() {
+        let glyphs = |ids: &[u16]| ids.iter().copied().map(GlyphId::new).collect::<Vec<_>>();
+        let mut arena = GlyphClassArena::default();
+        let one = arena.intern(glyphs(&[1, 2, 3]));
+        let two = arena.intern(glyphs(&[1, 2, 3]));
+        assert_eq!(one.items().as_ptr(), two.items().as_ptr());
+
+        // order matters
+        let three = arena.intern(glyphs(&[3, 2, 1]));
+        assert_ne!(one.items().as_ptr(), three.items().as_ptr());
+    }
+}
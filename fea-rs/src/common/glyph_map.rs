@@ -80,10 +80,7 @@ impl GlyphMap {
         let reverse = self.reverse_map();
         let rev_vec = reverse
             .values()
-            .map(|val| match val {
-                GlyphIdent::Name(s) => Cow::Borrowed(s.as_str()),
-                GlyphIdent::Cid(cid) => Cow::Owned(format!("cid{:05}", *cid)),
-            })
+            .map(GlyphIdent::post_name)
             .collect::<Vec<_>>();
 
         Post::new_v2(rev_vec.iter().map(Cow::as_ref))
@@ -6,46 +6,115 @@ use crate::{parse::ParseTree, Diagnostic, GlyphMap, GlyphName};
 
 use self::{
     compile_ctx::CompilationCtx,
-    error::{FontGlyphOrderError, GlyphOrderError},
+    error::{FontGlyphOrderError, GlyphOrderError, GlyphOrderLoadError},
+    lenient::ErrorRanges,
 };
 
 #[cfg(feature = "norad")]
-use self::error::UfoGlyphOrderError;
+use self::error::{DesignspaceGlyphOrderError, UfoGlyphOrderError};
 
+pub use closure::gsub_closure;
 pub use compiler::Compiler;
-pub use opts::Opts;
-pub use output::Compilation;
+pub use glyph_refs::{referenced_glyphs, ReferencedGlyphs};
+pub use lint::{LintConfig, LintLevel};
+pub use lookup_hook::LookupInfo;
+pub use opts::{GlyphClassInference, Opts};
+pub use output::{AnchorDefinition, AnonymousBlock, Compilation};
+pub use tables::VerticalMetrics;
 pub use variations::{AxisInfo, AxisLocation, VariationInfo};
 
 #[cfg(any(test, feature = "test", feature = "cli"))]
 pub use variations::MockVariationInfo;
 
+mod closure;
 mod compile_ctx;
 mod compiler;
 pub mod error;
 mod features;
 mod glyph_range;
+mod glyph_refs;
 mod language_system;
+mod lenient;
+mod lint;
+mod lookup_hook;
 mod lookups;
+mod merge;
 mod opts;
 mod output;
+mod overflow;
 mod tables;
 mod tags;
+mod test_font;
 mod validate;
 mod valuerecordext;
 mod variations;
 
 /// Run the validation pass, returning any diagnostics.
-pub(crate) fn validate(
+///
+/// This is run automatically during compilation; it is exposed for tools that
+/// want to check a source without compiling it, such as linters or editors.
+/// The tree should not contain any parse errors.
+pub fn validate(
+    node: &ParseTree,
+    glyph_map: &GlyphMap,
+    fvar: Option<&dyn VariationInfo>,
+) -> Vec<Diagnostic> {
+    validate_impl(node, glyph_map, fvar, false, false)
+}
+
+fn validate_impl(
     node: &ParseTree,
     glyph_map: &GlyphMap,
     fvar: Option<&dyn VariationInfo>,
+    late_mark_classes: bool,
+    class_arithmetic: bool,
 ) -> Vec<Diagnostic> {
-    let mut ctx = validate::ValidationCtx::new(node.source_map(), glyph_map, fvar);
+    let mut ctx = validate::ValidationCtx::new(node.source_map(), &node.sources, glyph_map, fvar)
+        .with_late_mark_classes(late_mark_classes)
+        .with_class_arithmetic(class_arithmetic);
     ctx.validate_root(&node.typed_root());
     ctx.errors
 }
 
+/// Run the validation pass in lenient mode.
+///
+/// Statements overlapping any range in `skip` are not validated. Any statement
+/// with an error is added to `skip`, and validation is repeated until no new
+/// errors are found.
+fn validate_lenient(
+    node: &ParseTree,
+    glyph_map: &GlyphMap,
+    fvar: Option<&dyn VariationInfo>,
+    late_mark_classes: bool,
+    class_arithmetic: bool,
+    skip: &mut ErrorRanges,
+) -> Vec<Diagnostic> {
+    let mut errors: Vec<Diagnostic> = Vec::new();
+    loop {
+        let mut ctx =
+            validate::ValidationCtx::new(node.source_map(), &node.sources, glyph_map, fvar)
+                .with_late_mark_classes(late_mark_classes)
+                .with_class_arithmetic(class_arithmetic)
+                .with_skipped(skip.clone());
+        ctx.validate_root(&node.typed_root());
+        let mut found_new = false;
+        for range in ctx.error_ranges.drain(..) {
+            found_new |= skip.insert(range);
+        }
+        let (new_errors, warnings): (Vec<_>, Vec<_>) =
+            ctx.errors.into_iter().partition(Diagnostic::is_error);
+        for error in new_errors {
+            if !errors.contains(&error) {
+                errors.push(error);
+            }
+        }
+        if !found_new {
+            errors.extend(warnings);
+            return errors;
+        }
+    }
+}
+
 /// A helper function for extracting the glyph order from a UFO
 ///
 /// If the public.glyphOrder key is missing, or the glyphOrder is malformed,
@@ -67,6 +136,105 @@ pub fn get_ufo_glyph_order(font: &norad::Font) -> Result<GlyphMap, UfoGlyphOrder
         .ok_or(UfoGlyphOrderError::Malformed)
 }
 
+/// A helper function for extracting the glyph order from a designspace
+///
+/// This finds the default source (the source at the default location on every
+/// axis) and returns the glyph order of that UFO. If the UFO does not have a
+/// public.glyphOrder key, the glyph names in its default layer are used, in
+/// sorted order, with '.notdef' first.
+#[cfg(feature = "norad")]
+pub fn get_designspace_glyph_order(
+    path: impl AsRef<std::path::Path>,
+) -> Result<GlyphMap, DesignspaceGlyphOrderError> {
+    let path = path.as_ref();
+    let designspace = norad::designspace::DesignSpaceDocument::load(path)?;
+    let source = default_designspace_source(&designspace)
+        .ok_or(DesignspaceGlyphOrderError::NoDefaultSource)?;
+    let ufo_path = path
+        .parent()
+        .unwrap_or(std::path::Path::new(""))
+        .join(&source.filename);
+
+    let request = norad::DataRequest::none().lib(true);
+    let font = norad::Font::load_requested_data(&ufo_path, request)
+        .map_err(|e| DesignspaceGlyphOrderError::UfoLoad(ufo_path.clone(), Box::new(e)))?;
+    match get_ufo_glyph_order(&font) {
+        Err(UfoGlyphOrderError::KeyNotSet) => (),
+        other => return other.map_err(Into::into),
+    }
+
+    let request = norad::DataRequest::none().layers(true);
+    let font = norad::Font::load_requested_data(&ufo_path, request)
+        .map_err(|e| DesignspaceGlyphOrderError::UfoLoad(ufo_path, Box::new(e)))?;
+    let mut names = font
+        .default_layer()
+        .iter()
+        .map(|glyph| glyph.name().as_str())
+        .collect::<Vec<_>>();
+    names.sort_by_key(|name| (*name != ".notdef", *name));
+    Ok(names.into_iter().map(GlyphName::new).collect())
+}
+
+/// The source at the default location, which is not a sparse layer source.
+#[cfg(feature = "norad")]
+fn default_designspace_source(
+    designspace: &norad::designspace::DesignSpaceDocument,
+) -> Option<&norad::designspace::Source> {
+    // the axis default is in user coordinates, but source locations are in
+    // design coordinates
+    let defaults = designspace
+        .axes
+        .iter()
+        .map(|axis| {
+            let default = match axis.map.as_deref() {
+                Some(map) if !map.is_empty() => {
+                    piecewise_linear_map(map.iter().map(|m| (m.input, m.output)), axis.default)
+                }
+                _ => axis.default,
+            };
+            (axis.name.as_str(), default)
+        })
+        .collect::<Vec<_>>();
+
+    designspace
+        .sources
+        .iter()
+        .filter(|source| source.layer.is_none())
+        .find(|source| {
+            defaults.iter().all(|(name, default)| {
+                source
+                    .location
+                    .iter()
+                    .find(|dim| dim.name == *name)
+                    .and_then(|dim| dim.xvalue)
+                    .map(|value| value == *default)
+                    .unwrap_or(true)
+            })
+        })
+}
+
+#[cfg(feature = "norad")]
+fn piecewise_linear_map(mapping: impl Iterator<Item = (f32, f32)>, value: f32) -> f32 {
+    let mut mapping = mapping.collect::<Vec<_>>();
+    mapping.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let (first, last) = (mapping[0], mapping[mapping.len() - 1]);
+    if value <= first.0 {
+        return first.1 + (value - first.0);
+    }
+    if value >= last.0 {
+        return last.1 + (value - last.0);
+    }
+    let upper = mapping
+        .iter()
+        .position(|(input, _)| *input >= value)
+        .unwrap();
+    let (a, b) = (mapping[upper - 1], mapping[upper]);
+    if b.0 == value {
+        return b.1;
+    }
+    a.1 + (b.1 - a.1) * (value - a.0) / (b.0 - a.0)
+}
+
 /// A helper function for extracting glyph order from a font with a 'post' table
 ///
 /// If 'post' is missing or malformed, this will return `None`.
@@ -120,6 +288,64 @@ pub fn parse_glyph_order(glyphs: &str) -> Result<GlyphMap, GlyphOrderError> {
     }
 }
 
+/// Load a glyph order from a file, in any of the supported formats.
+///
+/// The format is determined from the path and the file's contents:
+///
+/// - a '.ufo' directory uses the UFO's public.glyphOrder (see
+///   [`get_ufo_glyph_order`])
+/// - a '.designspace' file uses the glyph order of the default source (see
+///   [`get_designspace_glyph_order`])
+/// - a TrueType or OpenType font uses the names in the 'post' table (see
+///   [`get_post_glyph_order`])
+/// - anything else is treated as a text file with one glyph name per line
+///   (see [`parse_glyph_order`]); a '.glyphOrder' file is one example.
+///
+/// UFO and designspace sources require the 'norad' feature.
+pub fn load_glyph_order(
+    path: impl AsRef<std::path::Path>,
+) -> Result<GlyphMap, GlyphOrderLoadError> {
+    let path = path.as_ref();
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    if matches!(extension, Some("ufo" | "designspace")) {
+        return load_norad_glyph_order(path);
+    }
+
+    let bytes = std::fs::read(path).map_err(|e| GlyphOrderLoadError::Io(path.to_owned(), e))?;
+    if is_font_data(&bytes) {
+        return get_post_glyph_order(&bytes).map_err(Into::into);
+    }
+    let text = String::from_utf8(bytes).map_err(|e| {
+        let err = std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+        GlyphOrderLoadError::Io(path.to_owned(), err)
+    })?;
+    parse_glyph_order(&text).map_err(Into::into)
+}
+
+#[cfg(feature = "norad")]
+fn load_norad_glyph_order(path: &std::path::Path) -> Result<GlyphMap, GlyphOrderLoadError> {
+    if path.extension() == Some("designspace".as_ref()) {
+        return get_designspace_glyph_order(path).map_err(Into::into);
+    }
+    let request = norad::DataRequest::none().lib(true);
+    let font = norad::Font::load_requested_data(path, request)
+        .map_err(|e| GlyphOrderLoadError::UfoLoad(path.to_owned(), Box::new(e)))?;
+    get_ufo_glyph_order(&font).map_err(Into::into)
+}
+
+#[cfg(not(feature = "norad"))]
+fn load_norad_glyph_order(path: &std::path::Path) -> Result<GlyphMap, GlyphOrderLoadError> {
+    Err(GlyphOrderLoadError::Unsupported(path.to_owned()))
+}
+
+/// `true` if this looks like the start of a TrueType or OpenType font.
+fn is_font_data(bytes: &[u8]) -> bool {
+    matches!(
+        bytes.get(..4),
+        Some([0x00, 0x01, 0x00, 0x00] | b"OTTO" | b"true")
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -133,4 +359,72 @@ mod tests {
         assert_eq!(glyph_map.get("e.fina"), Some(GlyphId::new(214)));
         assert!(!glyph_map.contains("e.nada"));
     }
+
+    #[test]
+    fn load_glyph_order_from_text_or_font() {
+        let glyph_map = load_glyph_order("./test-data/simple_glyph_order.txt").unwrap();
+        assert_eq!(glyph_map.len(), 215);
+
+        let glyph_map: GlyphMap = [".notdef", "a", "b"]
+            .into_iter()
+            .map(GlyphName::new)
+            .collect();
+        let font = Compiler::new("test.fea", &glyph_map)
+            .with_resolver(|_: &std::ffi::OsStr| Ok(std::sync::Arc::<str>::from("")))
+            .compile()
+            .unwrap()
+            .to_test_font(&glyph_map)
+            .unwrap();
+        // tests run in parallel (and maybe in several processes), so use a
+        // unique file name
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let file_name = format!("fea-rs-glyph-order-{}-{nanos}.ttf", std::process::id());
+        let path = std::env::temp_dir().join(file_name);
+        std::fs::write(&path, font).unwrap();
+        let loaded = load_glyph_order(&path);
+        std::fs::remove_file(&path).unwrap();
+        let names = loaded
+            .unwrap()
+            .iter()
+            .map(|g| g.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, [".notdef", "a", "b"]);
+
+        assert!(matches!(
+            load_glyph_order("./test-data/does-not-exist.txt"),
+            Err(GlyphOrderLoadError::Io(..))
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "norad")]
+    fn designspace_glyph_order() {
+        // the default is at user location 400, which maps to design location 80
+        let glyph_map =
+            get_designspace_glyph_order("./test-data/designspace/Test.designspace").unwrap();
+        let names = glyph_map.iter().map(|g| g.to_string()).collect::<Vec<_>>();
+        assert_eq!(names, [".notdef", "a", "b", "c"]);
+
+        // without public.glyphOrder, names are sorted with .notdef first
+        let glyph_map =
+            get_designspace_glyph_order("./test-data/designspace/NoOrder.designspace").unwrap();
+        let names = glyph_map.iter().map(|g| g.to_string()).collect::<Vec<_>>();
+        assert_eq!(names, [".notdef", "a", "b"]);
+        // the generic loader handles both designspaces and UFOs
+        for path in [
+            "./test-data/designspace/Test.designspace",
+            "./test-data/designspace/Test-Regular.ufo",
+        ] {
+            let glyph_map = load_glyph_order(path).unwrap();
+            let names = glyph_map.iter().map(|g| g.to_string()).collect::<Vec<_>>();
+            assert_eq!(names, [".notdef", "a", "b", "c"], "{path}");
+        }
+        assert!(matches!(
+            load_glyph_order("./test-data/designspace/NoOrder.ufo"),
+            Err(GlyphOrderLoadError::Ufo(UfoGlyphOrderError::KeyNotSet))
+        ));
+    }
 }
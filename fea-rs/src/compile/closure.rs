@@ -0,0 +1,380 @@
+//! Computing the closure of a set of glyphs over GSUB
+
+use std::collections::{BTreeSet, HashSet};
+
+use write_fonts::{
+    tables::{
+        gsub::{
+            AlternateSubstFormat1, ExtensionSubtable, Gsub, LigatureSubstFormat1,
+            MultipleSubstFormat1, ReverseChainSingleSubstFormat1, SingleSubst, SubstitutionLookup,
+        },
+        layout::{ChainedSequenceContext, ClassDef, CoverageTable, SequenceContext},
+    },
+    types::GlyphId,
+    OffsetMarker,
+};
+
+/// Return the set of glyphs reachable from `glyphs` by the substitutions in `gsub`.
+///
+/// This is the set of glyphs that a subsetter needs to retain in order for
+/// the layout rules to work for the input glyphs; the result includes the
+/// input glyphs.
+///
+/// Only lookups that are referenced by a feature (including in a feature
+/// variation) are applied, as well as any lookups that they invoke. The
+/// result is conservative: a contextual rule is applied if each position in
+/// its context can be matched by some glyph in the set, without checking that
+/// the sequence itself is reachable, so the result may include glyphs that
+/// can never actually be produced.
+pub fn gsub_closure(gsub: &Gsub, glyphs: impl IntoIterator<Item = GlyphId>) -> BTreeSet<GlyphId> {
+    let mut roots = BTreeSet::new();
+    for record in &gsub.feature_list.feature_records {
+        roots.extend(record.feature.lookup_list_indices.iter().copied());
+    }
+    if let Some(variations) = gsub.feature_variations.as_ref() {
+        for record in &variations.feature_variation_records {
+            let Some(substitution) = record.feature_table_substitution.as_ref() else {
+                continue;
+            };
+            for sub in &substitution.substitutions {
+                roots.extend(sub.alternate_feature.lookup_list_indices.iter().copied());
+            }
+        }
+    }
+
+    let mut closure = Closure {
+        lookups: &gsub.lookup_list.lookups,
+        glyphs: glyphs.into_iter().collect(),
+        visited: HashSet::new(),
+    };
+    // new glyphs may allow earlier lookups to match, so repeat until stable
+    loop {
+        let len = closure.glyphs.len();
+        closure.visited.clear();
+        for idx in &roots {
+            closure.apply_lookup(*idx);
+        }
+        if closure.glyphs.len() == len {
+            break closure.glyphs;
+        }
+    }
+}
+
+struct Closure<'a> {
+    lookups: &'a [OffsetMarker<SubstitutionLookup>],
+    glyphs: BTreeSet<GlyphId>,
+    // lookups applied in the current pass; nested lookups can form cycles
+    visited: HashSet<u16>,
+}
+
+impl<'a> Closure<'a> {
+    fn apply_lookup(&mut self, idx: u16) {
+        if !self.visited.insert(idx) {
+            return;
+        }
+        let Some(lookup) = self.lookups.get(idx as usize) else {
+            return;
+        };
+        match &**lookup {
+            SubstitutionLookup::Single(lookup) => {
+                lookup.subtables.iter().for_each(|sub| self.single(sub))
+            }
+            SubstitutionLookup::Multiple(lookup) => {
+                lookup.subtables.iter().for_each(|sub| self.multiple(sub))
+            }
+            SubstitutionLookup::Alternate(lookup) => {
+                lookup.subtables.iter().for_each(|sub| self.alternate(sub))
+            }
+            SubstitutionLookup::Ligature(lookup) => {
+                lookup.subtables.iter().for_each(|sub| self.ligature(sub))
+            }
+            SubstitutionLookup::Contextual(lookup) => lookup
+                .subtables
+                .iter()
+                .for_each(|sub| self.contextual(sub.as_inner())),
+            SubstitutionLookup::ChainContextual(lookup) => lookup
+                .subtables
+                .iter()
+                .for_each(|sub| self.chain_contextual(sub.as_inner())),
+            SubstitutionLookup::Reverse(lookup) => {
+                lookup.subtables.iter().for_each(|sub| self.reverse(sub))
+            }
+            SubstitutionLookup::Extension(lookup) => {
+                for sub in &lookup.subtables {
+                    match &**sub {
+                        ExtensionSubtable::Single(ext) => self.single(&ext.extension),
+                        ExtensionSubtable::Multiple(ext) => self.multiple(&ext.extension),
+                        ExtensionSubtable::Alternate(ext) => self.alternate(&ext.extension),
+                        ExtensionSubtable::Ligature(ext) => self.ligature(&ext.extension),
+                        ExtensionSubtable::Contextual(ext) => {
+                            self.contextual(ext.extension.as_inner())
+                        }
+                        ExtensionSubtable::ChainContextual(ext) => {
+                            self.chain_contextual(ext.extension.as_inner())
+                        }
+                        ExtensionSubtable::Reverse(ext) => self.reverse(&ext.extension),
+                    }
+                }
+            }
+        }
+    }
+
+    fn single(&mut self, sub: &SingleSubst) {
+        let pairs: Vec<_> = match sub {
+            SingleSubst::Format1(sub) => sub
+                .coverage
+                .iter()
+                .map(|gid| {
+                    let target = gid.to_u16().wrapping_add(sub.delta_glyph_id as u16);
+                    (gid, GlyphId::new(target))
+                })
+                .collect(),
+            SingleSubst::Format2(sub) => sub
+                .coverage
+                .iter()
+                .zip(sub.substitute_glyph_ids.iter().copied())
+                .collect(),
+        };
+        for (target, replacement) in pairs {
+            if self.glyphs.contains(&target) {
+                self.glyphs.insert(replacement);
+            }
+        }
+    }
+
+    fn multiple(&mut self, sub: &MultipleSubstFormat1) {
+        for (target, sequence) in sub.coverage.iter().zip(&sub.sequences) {
+            if self.glyphs.contains(&target) {
+                self.glyphs
+                    .extend(sequence.substitute_glyph_ids.iter().copied());
+            }
+        }
+    }
+
+    fn alternate(&mut self, sub: &AlternateSubstFormat1) {
+        for (target, alternates) in sub.coverage.iter().zip(&sub.alternate_sets) {
+            if self.glyphs.contains(&target) {
+                self.glyphs
+                    .extend(alternates.alternate_glyph_ids.iter().copied());
+            }
+        }
+    }
+
+    fn ligature(&mut self, sub: &LigatureSubstFormat1) {
+        for (first, ligatures) in sub.coverage.iter().zip(&sub.ligature_sets) {
+            if !self.glyphs.contains(&first) {
+                continue;
+            }
+            for ligature in &ligatures.ligatures {
+                if self.contains_all(&ligature.component_glyph_ids) {
+                    self.glyphs.insert(ligature.ligature_glyph);
+                }
+            }
+        }
+    }
+
+    fn reverse(&mut self, sub: &ReverseChainSingleSubstFormat1) {
+        let context_matches = sub
+            .backtrack_coverages
+            .iter()
+            .chain(&sub.lookahead_coverages)
+            .all(|coverage| self.intersects(coverage));
+        if !context_matches {
+            return;
+        }
+        for (target, replacement) in sub.coverage.iter().zip(&sub.substitute_glyph_ids) {
+            if self.glyphs.contains(&target) {
+                self.glyphs.insert(*replacement);
+            }
+        }
+    }
+
+    fn contextual(&mut self, sub: &SequenceContext) {
+        let mut nested = Vec::new();
+        match sub {
+            SequenceContext::Format1(sub) => {
+                for (first, rules) in sub.coverage.iter().zip(&sub.seq_rule_sets) {
+                    let Some(rules) = rules.as_ref() else {
+                        continue;
+                    };
+                    if !self.glyphs.contains(&first) {
+                        continue;
+                    }
+                    for rule in &rules.seq_rules {
+                        if self.contains_all(&rule.input_sequence) {
+                            nested.extend(rule.seq_lookup_records.iter());
+                        }
+                    }
+                }
+            }
+            SequenceContext::Format2(sub) => {
+                for class in self.first_classes(&sub.coverage, &sub.class_def) {
+                    let Some(Some(rules)) = sub
+                        .class_seq_rule_sets
+                        .get(class as usize)
+                        .map(|x| x.as_ref())
+                    else {
+                        continue;
+                    };
+                    for rule in &rules.class_seq_rules {
+                        if self.may_match_classes(&sub.class_def, &rule.input_sequence) {
+                            nested.extend(rule.seq_lookup_records.iter());
+                        }
+                    }
+                }
+            }
+            SequenceContext::Format3(sub) => {
+                if sub.coverages.iter().all(|cov| self.intersects(cov)) {
+                    nested.extend(sub.seq_lookup_records.iter());
+                }
+            }
+        }
+        for record in nested {
+            self.apply_lookup(record.lookup_list_index);
+        }
+    }
+
+    fn chain_contextual(&mut self, sub: &ChainedSequenceContext) {
+        let mut nested = Vec::new();
+        match sub {
+            ChainedSequenceContext::Format1(sub) => {
+                for (first, rules) in sub.coverage.iter().zip(&sub.chained_seq_rule_sets) {
+                    let Some(rules) = rules.as_ref() else {
+                        continue;
+                    };
+                    if !self.glyphs.contains(&first) {
+                        continue;
+                    }
+                    for rule in &rules.chained_seq_rules {
+                        if self.contains_all(&rule.backtrack_sequence)
+                            && self.contains_all(&rule.input_sequence)
+                            && self.contains_all(&rule.lookahead_sequence)
+                        {
+                            nested.extend(rule.seq_lookup_records.iter());
+                        }
+                    }
+                }
+            }
+            ChainedSequenceContext::Format2(sub) => {
+                for class in self.first_classes(&sub.coverage, &sub.input_class_def) {
+                    let Some(Some(rules)) = sub
+                        .chained_class_seq_rule_sets
+                        .get(class as usize)
+                        .map(|x| x.as_ref())
+                    else {
+                        continue;
+                    };
+                    for rule in &rules.chained_class_seq_rules {
+                        if self
+                            .may_match_classes(&sub.backtrack_class_def, &rule.backtrack_sequence)
+                            && self.may_match_classes(&sub.input_class_def, &rule.input_sequence)
+                            && self.may_match_classes(
+                                &sub.lookahead_class_def,
+                                &rule.lookahead_sequence,
+                            )
+                        {
+                            nested.extend(rule.seq_lookup_records.iter());
+                        }
+                    }
+                }
+            }
+            ChainedSequenceContext::Format3(sub) => {
+                let matches = sub
+                    .backtrack_coverages
+                    .iter()
+                    .chain(&sub.input_coverages)
+                    .chain(&sub.lookahead_coverages)
+                    .all(|cov| self.intersects(cov));
+                if matches {
+                    nested.extend(sub.seq_lookup_records.iter());
+                }
+            }
+        }
+        for record in nested {
+            self.apply_lookup(record.lookup_list_index);
+        }
+    }
+
+    fn contains_all(&self, glyphs: &[GlyphId]) -> bool {
+        glyphs.iter().all(|gid| self.glyphs.contains(gid))
+    }
+
+    fn intersects(&self, coverage: &CoverageTable) -> bool {
+        coverage.iter().any(|gid| self.glyphs.contains(&gid))
+    }
+
+    /// The classes of the glyphs in the set that are in `coverage`.
+    fn first_classes(&self, coverage: &CoverageTable, class_def: &ClassDef) -> BTreeSet<u16> {
+        coverage
+            .iter()
+            .filter(|gid| self.glyphs.contains(gid))
+            .map(|gid| class_def.get(gid))
+            .collect()
+    }
+
+    /// `true` if each class has at least one glyph in the set.
+    ///
+    /// Class 0 contains every glyph not in the class def, so it always matches.
+    fn may_match_classes(&self, class_def: &ClassDef, classes: &[u16]) -> bool {
+        classes.iter().all(|class| {
+            *class == 0
+                || class_def
+                    .iter()
+                    .any(|(gid, cls)| cls == *class && self.glyphs.contains(&gid))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsStr, sync::Arc};
+
+    use crate::{Compiler, GlyphMap, GlyphName};
+
+    fn closure_of(fea: &'static str, glyphs: &[&str]) -> Vec<String> {
+        let glyph_map: GlyphMap = [
+            ".notdef", "a", "b", "c", "d", "e", "f", "i", "f_i", "a.sc", "b.sc", "c.alt", "x",
+        ]
+        .into_iter()
+        .map(GlyphName::new)
+        .collect();
+        let compilation = Compiler::new("closure.fea", &glyph_map)
+            .with_resolver(move |_: &OsStr| Ok(Arc::<str>::from(fea)))
+            .compile()
+            .unwrap();
+        let input = glyphs
+            .iter()
+            .map(|name| glyph_map.get(*name).unwrap())
+            .collect::<Vec<_>>();
+        let reverse = glyph_map.reverse_map();
+        compilation
+            .gsub_closure(input)
+            .iter()
+            .map(|gid| reverse.get(gid).unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn simple_substitutions() {
+        let fea = "\
+            lookup unused { sub a by x; } unused;\n\
+            feature smcp { sub [a b] by [a.sc b.sc]; } smcp;\n\
+            feature liga { sub f i by f_i; } liga;\n\
+            feature salt { sub c from [c.alt x]; } salt;\n";
+        assert_eq!(closure_of(fea, &["a", "f"]), ["a", "f", "a.sc"]);
+        assert_eq!(closure_of(fea, &["f", "i"]), ["f", "i", "f_i"]);
+        assert_eq!(closure_of(fea, &["c"]), ["c", "c.alt", "x"]);
+    }
+
+    #[test]
+    fn contextual_and_iterated() {
+        let fea = "\
+            lookup to_c { sub b by c; } to_c;\n\
+            feature calt { sub a b' lookup to_c; } calt;\n\
+            feature salt { sub c by c.alt; } salt;\n";
+        // the context doesn't match without 'a'
+        assert_eq!(closure_of(fea, &["b"]), ["b"]);
+        // 'c' is produced after the lookup for 'salt' has been applied once
+        assert_eq!(closure_of(fea, &["a", "b"]), ["a", "b", "c", "c.alt"]);
+    }
+}
@@ -3,7 +3,7 @@
 //! This is the final stage of the pipeline, which walks the parsed and validated
 //! AST and generates the output.
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, HashMap, HashSet},
     convert::TryInto,
     ops::Range,
 };
@@ -22,14 +22,14 @@ use write_fonts::{
 };
 
 use crate::{
-    common::{GlyphClass, GlyphId, GlyphOrClass},
-    parse::SourceMap,
+    common::{GlyphClass, GlyphClassArena, GlyphId, GlyphOrClass},
+    parse::{SourceList, SourceMap},
     token_tree::{
         typed::{self, AstNode},
         Token,
     },
     typed::ContextualRuleNode,
-    Diagnostic, GlyphIdent, GlyphMap, Kind, NodeOrToken,
+    Diagnostic, DiagnosticCode, GlyphIdent, GlyphMap, Kind, NodeOrToken,
 };
 
 use super::{
@@ -39,16 +39,24 @@ use super::{
     },
     glyph_range,
     language_system::{DefaultLanguageSystems, LanguageSystem},
+    lenient::ErrorRanges,
+    lookup_hook::{dedupe_gpos_lookups, dedupe_gsub_lookups},
     lookups::{
-        AllLookups, FilterSetId, LookupFlagInfo, LookupId, PreviouslyAssignedClass, SomeLookup,
+        compact_pair_pos, AllLookups, FilterSetId, LookupFlagInfo, LookupId,
+        PreviouslyAssignedClass, SomeLookup, MAX_LOOKUPS,
     },
-    output::Compilation,
+    output::{AnchorDefinition, AnonymousBlock, Compilation},
     tables::{ClassId, ScriptRecord, Tables},
     tags,
     valuerecordext::ValueRecordExt,
-    VariationInfo,
+    GlyphClassInference, VariationInfo,
 };
 
+/// The maximum number of classes that can be referenced by `MarkAttachmentType`.
+///
+/// The lookup flag has room for more, but the spec limits this to 15.
+const MAX_MARK_ATTACH_CLASSES: u16 = 15;
+
 /// Context that manages state for a compilation.
 ///
 /// This type is where all the actual compilation logic lives.
@@ -66,6 +74,7 @@ pub struct CompilationCtx<'a> {
     glyph_map: &'a GlyphMap,
     reverse_glyph_map: BTreeMap<GlyphId, GlyphIdent>,
     source_map: &'a SourceMap,
+    sources: &'a SourceList,
     variation_info: Option<&'a dyn VariationInfo>,
     /// Any errors or warnings generated during compilation.
     pub errors: Vec<Diagnostic>,
@@ -84,38 +93,74 @@ pub struct CompilationCtx<'a> {
     vertical_feature: SpecialVerticalFeatureState,
     script: Option<Tag>,
     glyph_class_defs: HashMap<SmolStr, GlyphClass>,
+    /// shared storage for resolved glyph classes, so that equal classes
+    /// are only stored once
+    class_arena: GlyphClassArena,
     mark_classes: HashMap<SmolStr, MarkClass>,
     anchor_defs: HashMap<SmolStr, (AnchorTable, usize)>,
+    anon_blocks: Vec<AnonymousBlock>,
+    /// the range of the name of each named anchor, for `Compilation::anchors`
+    anchor_def_names: HashMap<SmolStr, Range<usize>>,
     value_record_defs: HashMap<SmolStr, ValueRecord>,
     conditionset_defs: ConditionSetMap,
     mark_attach_class_id: HashMap<GlyphClass, u16>,
-    mark_filter_sets: HashMap<GlyphClass, FilterSetId>,
+    /// The range of the first statement referencing each mark filtering set
+    mark_filter_set_ranges: Vec<Range<usize>>,
+    /// lenient mode only: statements that contain errors
+    skip: ErrorRanges,
+    /// The range of the rule that added each target to the current GSUB
+    /// subtable, for reporting conflicting rules
+    gsub_target_ranges: HashMap<Vec<GlyphId>, Range<usize>>,
+    /// language systems set with `script` or `language` statements in feature
+    /// blocks, with the range of the first such statement
+    explicit_lang_systems: Vec<(LanguageSystem, Range<usize>)>,
+    warn_undeclared_lang_systems: bool,
+    glyph_class_inference: GlyphClassInference,
+    compact_pair_pos: bool,
+    dedupe_lookups: bool,
+    share_context_class_defs: bool,
+    dedupe_names: bool,
+    /// if `true`, all mark classes are defined before any rules are compiled
+    late_mark_classes: bool,
+    /// the range of the aalt feature block, if any
+    aalt_range: Option<Range<usize>>,
+    /// tables for which we have reported exceeding the lookup limit
+    lookup_limit_errors: HashSet<Tag>,
 }
 
 #[derive(Clone, Debug, Default)]
 struct MarkClass {
     members: Vec<(GlyphClass, Option<AnchorTable>)>,
+    /// all of the glyphs in the class, when it is used as a glyph class.
+    ///
+    /// This is computed when first needed, and reset when members are added.
+    glyphs: Option<GlyphClass>,
 }
 
 impl<'a> CompilationCtx<'a> {
     pub(crate) fn new(
         glyph_map: &'a GlyphMap,
         source_map: &'a SourceMap,
+        sources: &'a SourceList,
         variation_info: Option<&'a dyn VariationInfo>,
     ) -> Self {
         CompilationCtx {
             glyph_map,
             reverse_glyph_map: glyph_map.reverse_map(),
             source_map,
+            sources,
             variation_info,
             errors: Vec::new(),
             tables: Tables::default(),
             default_lang_systems: Default::default(),
             glyph_class_defs: Default::default(),
+            class_arena: Default::default(),
             lookups: Default::default(),
             features: Default::default(),
             mark_classes: Default::default(),
             anchor_defs: Default::default(),
+            anchor_def_names: Default::default(),
+            anon_blocks: Default::default(),
             value_record_defs: Default::default(),
             conditionset_defs: Default::default(),
             lookup_flags: Default::default(),
@@ -123,22 +168,104 @@ impl<'a> CompilationCtx<'a> {
             vertical_feature: Default::default(),
             script: Default::default(),
             mark_attach_class_id: Default::default(),
-            mark_filter_sets: Default::default(),
+            mark_filter_set_ranges: Default::default(),
+            skip: Default::default(),
+            gsub_target_ranges: Default::default(),
+            explicit_lang_systems: Default::default(),
+            warn_undeclared_lang_systems: true,
+            glyph_class_inference: Default::default(),
+            compact_pair_pos: false,
+            dedupe_lookups: false,
+            share_context_class_defs: false,
+            dedupe_names: false,
+            late_mark_classes: false,
+            aalt_range: None,
+            lookup_limit_errors: Default::default(),
         }
     }
 
+    /// Skip any statements that overlap these ranges (for lenient mode.)
+    pub(crate) fn with_skipped(mut self, skip: ErrorRanges) -> Self {
+        self.skip = skip;
+        self
+    }
+
+    /// See [`Opts::glyph_class_inference`].
+    ///
+    /// [`Opts::glyph_class_inference`]: super::Opts::glyph_class_inference
+    pub(crate) fn with_glyph_class_inference(mut self, inference: GlyphClassInference) -> Self {
+        self.glyph_class_inference = inference;
+        self
+    }
+
+    /// See [`Opts::compact_pair_pos`].
+    ///
+    /// [`Opts::compact_pair_pos`]: super::Opts::compact_pair_pos
+    pub(crate) fn with_compact_pair_pos(mut self, flag: bool) -> Self {
+        self.compact_pair_pos = flag;
+        self
+    }
+
+    /// See [`Opts::dedupe_lookups`].
+    ///
+    /// [`Opts::dedupe_lookups`]: super::Opts::dedupe_lookups
+    pub(crate) fn with_dedupe_lookups(mut self, flag: bool) -> Self {
+        self.dedupe_lookups = flag;
+        self
+    }
+
+    /// See [`Opts::share_context_class_defs`].
+    ///
+    /// [`Opts::share_context_class_defs`]: super::Opts::share_context_class_defs
+    pub(crate) fn with_share_context_class_defs(mut self, flag: bool) -> Self {
+        self.share_context_class_defs = flag;
+        self
+    }
+
+    /// See [`Opts::dedupe_names`].
+    ///
+    /// [`Opts::dedupe_names`]: super::Opts::dedupe_names
+    pub(crate) fn with_dedupe_names(mut self, flag: bool) -> Self {
+        self.dedupe_names = flag;
+        self
+    }
+
+    /// See [`Compiler::with_undeclared_language_system_warnings`].
+    ///
+    /// [`Compiler::with_undeclared_language_system_warnings`]: super::Compiler::with_undeclared_language_system_warnings
+    pub(crate) fn with_undeclared_lang_system_warnings(mut self, flag: bool) -> Self {
+        self.warn_undeclared_lang_systems = flag;
+        self
+    }
+
+    /// See [`Compiler::with_late_mark_classes`].
+    ///
+    /// [`Compiler::with_late_mark_classes`]: super::Compiler::with_late_mark_classes
+    pub(crate) fn with_late_mark_classes(mut self, flag: bool) -> Self {
+        self.late_mark_classes = flag;
+        self
+    }
+
     /// The main entry point for compilation.
     ///
     /// Walks the statements in the AST in order, accumulating state and any
     /// errors encountered.
     pub(crate) fn compile(&mut self, node: &typed::Root) {
+        if self.late_mark_classes {
+            self.define_mark_classes_early(node);
+        }
         for item in node.statements() {
+            if self.skip.should_skip(item) {
+                continue;
+            }
             if let Some(language_system) = typed::LanguageSystem::cast(item) {
                 self.add_language_system(language_system);
             } else if let Some(class_def) = typed::GlyphClassDef::cast(item) {
                 self.define_glyph_class(class_def);
             } else if let Some(mark_def) = typed::MarkClassDef::cast(item) {
-                self.define_mark_class(mark_def);
+                if !self.late_mark_classes {
+                    self.define_mark_class(mark_def);
+                }
             } else if let Some(anchor_def) = typed::AnchorDef::cast(item) {
                 self.define_named_anchor(anchor_def);
             } else if let Some(item) = typed::ValueRecordDef::cast(item) {
@@ -151,8 +278,8 @@ impl<'a> CompilationCtx<'a> {
                 self.add_feature_variation(node);
             } else if let Some(lookup) = typed::LookupBlock::cast(item) {
                 self.resolve_lookup_block(lookup);
-            } else if item.kind() == Kind::AnonBlockNode {
-                // noop
+            } else if let Some(block) = typed::AnonBlock::cast(item) {
+                self.add_anon_block(block);
             } else if let Some(table) = typed::Table::cast(item) {
                 self.resolve_table(table);
             } else if !item.kind().is_trivia() {
@@ -164,7 +291,45 @@ impl<'a> CompilationCtx<'a> {
         self.finalize_gdef_table();
         self.features
             .finalize_aalt(&mut self.lookups, &self.default_lang_systems);
+        if let Some(range) = self.aalt_range.clone() {
+            self.check_lookup_limits(range, |_| "feature 'aalt'".into());
+        }
         self.features.sort_and_dedupe_lookups();
+        self.warn_undeclared_lang_systems();
+    }
+
+    /// Warn if lookups are registered for a language system that is not
+    /// declared with a `languagesystem` statement.
+    ///
+    /// Features that are not explicitly registered for such a system will not
+    /// be applied to text in that script and language, which is rarely what
+    /// is intended.
+    fn warn_undeclared_lang_systems(&mut self) {
+        if !self.warn_undeclared_lang_systems {
+            return;
+        }
+        let used = self
+            .features
+            .iter()
+            .filter(|(_, lookups)| !lookups.is_empty())
+            .map(|(key, _)| LanguageSystem {
+                script: key.script,
+                language: key.language,
+            })
+            .collect::<HashSet<_>>();
+        for (system, range) in std::mem::take(&mut self.explicit_lang_systems) {
+            if used.contains(&system) && !self.default_lang_systems.contains(&system) {
+                self.warning(
+                    DiagnosticCode::UndeclaredLanguageSystem,
+                    range,
+                    format!(
+                        "'{} {}' is not declared with a 'languagesystem' statement; features that are not registered for it will not be applied",
+                        system.script.to_string().trim_end(),
+                        system.language.to_string().trim_end()
+                    ),
+                );
+            }
+        }
     }
 
     pub(crate) fn build(&mut self) -> Result<Compilation, Vec<Diagnostic>> {
@@ -173,6 +338,7 @@ impl<'a> CompilationCtx<'a> {
         }
 
         let mut name_builder = self.tables.name.clone();
+        name_builder.set_dedupe(self.dedupe_names);
         let stat = self
             .tables
             .stat
@@ -190,11 +356,24 @@ impl<'a> CompilationCtx<'a> {
             self.lookups.update_variation_index_tables(&key_map);
         }
 
-        let (mut gsub, mut gpos) = self.lookups.build(&self.features);
+        if self.share_context_class_defs {
+            self.lookups.share_context_class_defs();
+        }
+        // we only get here with too many lookups in lenient mode, where the
+        // errors have already been taken; lookup indices would not fit in
+        // the tables, so we don't build them.
+        let (mut gsub, mut gpos) = if self.lookup_limit_errors.is_empty() {
+            self.lookups.build(&self.features)
+        } else {
+            (None, None)
+        };
 
         let feature_params = self.features.build_feature_params(&mut name_builder);
 
         if let Some(gsub) = gsub.as_mut() {
+            if self.dedupe_lookups {
+                dedupe_gsub_lookups(gsub);
+            }
             if let Some(variations) = gsub.feature_variations.as_mut() {
                 sort_feature_variations(variations, |condset| {
                     self.conditionset_defs.sort_order(condset)
@@ -207,6 +386,12 @@ impl<'a> CompilationCtx<'a> {
             }
         }
         if let Some(gpos) = gpos.as_mut() {
+            if self.compact_pair_pos {
+                compact_pair_pos(gpos);
+            }
+            if self.dedupe_lookups {
+                dedupe_gpos_lookups(gpos);
+            }
             if let Some(variations) = gpos.feature_variations.as_mut() {
                 sort_feature_variations(variations, |condset| {
                     self.conditionset_defs.sort_order(condset)
@@ -222,19 +407,53 @@ impl<'a> CompilationCtx<'a> {
 
         Ok(Compilation {
             warnings: self.errors.clone(),
+            errors: Vec::new(),
             head: self.tables.head.as_ref().map(|raw| raw.build(None)),
             hhea: self.tables.hhea.clone(),
             vhea: self.tables.vhea.clone(),
+            vmtx: self.tables.vmtx.clone(),
             os2: self.tables.os2.as_ref().map(|raw| raw.build()),
+            explicit_fields: self.tables.explicit_fields.clone(),
             gdef,
             base: self.tables.base.as_ref().map(|raw| raw.build()),
             name: name_builder.build(),
+            first_anon_name_id: name_builder.first_anon_id(),
             stat,
             gsub,
             gpos,
+            anchors: self.anchor_definitions(),
+            anon_blocks: self.anon_blocks.clone(),
         })
     }
 
+    fn anchor_definitions(&self) -> BTreeMap<String, AnchorDefinition> {
+        self.anchor_defs
+            .iter()
+            .filter_map(|(name, (anchor, _))| {
+                let (x, y, contour_point) = match anchor {
+                    AnchorTable::Format1(a) => (a.x_coordinate, a.y_coordinate, None),
+                    AnchorTable::Format2(a) => {
+                        (a.x_coordinate, a.y_coordinate, Some(a.anchor_point))
+                    }
+                    // these can't be defined with a name
+                    AnchorTable::Format3(_) => return None,
+                };
+                let range = self.anchor_def_names.get(name)?;
+                let (file, range) = self.source_map.resolve_range(range.clone());
+                Some((
+                    name.to_string(),
+                    AnchorDefinition {
+                        x,
+                        y,
+                        contour_point,
+                        file,
+                        range,
+                    },
+                ))
+            })
+            .collect()
+    }
+
     /// Infer/update GDEF table as required.
     ///
     /// If a GDEF table is not explicitly defined, we are supposed to create one,
@@ -249,9 +468,15 @@ impl<'a> CompilationCtx<'a> {
         // if the FEA included a GDEF block, use that, otherwise create an empty table
         let mut gdef = self.tables.gdef.take().unwrap_or_default();
         // infer glyph classes, if they were not declared explicitly
-        if gdef.glyph_classes.is_empty() {
+        let infer = match self.glyph_class_inference {
+            GlyphClassInference::Never => false,
+            GlyphClassInference::IfMissing => gdef.glyph_classes.is_empty(),
+            GlyphClassInference::Merge => true,
+        };
+        if infer {
+            let mut inferred = HashMap::new();
             self.lookups.infer_glyph_classes(|glyph, class_id| {
-                gdef.glyph_classes.insert(glyph, class_id);
+                inferred.insert(glyph, class_id);
             });
             for glyph in self
                 .mark_classes
@@ -259,7 +484,11 @@ impl<'a> CompilationCtx<'a> {
                 .flat_map(|class| class.members.iter().map(|(cls, _)| cls.iter()))
                 .flatten()
             {
-                gdef.glyph_classes.insert(glyph, ClassId::Mark);
+                inferred.insert(glyph, ClassId::Mark);
+            }
+            // when merging, explicitly declared classes take precedence
+            for (glyph, class_id) in inferred {
+                gdef.glyph_classes.entry(glyph).or_insert(class_id);
             }
         }
 
@@ -271,14 +500,19 @@ impl<'a> CompilationCtx<'a> {
             );
         }
 
-        if !self.mark_filter_sets.is_empty() {
-            let mut sorted = self
-                .mark_filter_sets
-                .iter()
-                .map(|(cls, id)| (*id, cls.clone()))
-                .collect::<Vec<_>>();
-            sorted.sort_unstable();
-            gdef.mark_glyph_sets = sorted.into_iter().map(|(_, cls)| cls).collect();
+        // report each filtering set once, for the first glyph that won't be filtered
+        let mut non_marks = gdef
+            .non_mark_glyphs_in_mark_glyph_sets()
+            .collect::<Vec<_>>();
+        non_marks.dedup_by_key(|(set, _, _)| *set);
+        for (set, glyph, class) in non_marks {
+            let glyph_name = self.reverse_glyph_map.get(&glyph).unwrap();
+            let range = self.mark_filter_set_ranges[set as usize].clone();
+            self.warning(
+                DiagnosticCode::NonMarkInMarkFilterSet,
+                range,
+                format!("mark filtering set includes glyph '{glyph_name}', which has GDEF class {class} and will not be filtered"),
+            );
         }
 
         if !gdef.is_empty() {
@@ -288,12 +522,48 @@ impl<'a> CompilationCtx<'a> {
 
     fn error(&mut self, range: Range<usize>, message: impl Into<String>) {
         let (file, range) = self.source_map.resolve_range(range);
-        self.errors.push(Diagnostic::error(file, range, message));
+        self.errors
+            .push(Diagnostic::error(file, range, message).with_code(DiagnosticCode::CompileError));
     }
 
-    fn warning(&mut self, range: Range<usize>, message: impl Into<String>) {
+    fn warning(&mut self, code: DiagnosticCode, range: Range<usize>, message: impl Into<String>) {
         let (file, range) = self.source_map.resolve_range(range);
-        self.errors.push(Diagnostic::warning(file, range, message));
+        self.errors
+            .push(Diagnostic::warning(file, range, message).with_code(code));
+    }
+
+    /// Report an error if we have allocated more lookups than a table can hold.
+    ///
+    /// This is checked after each rule, so that the error points at the first
+    /// rule that needs a lookup past the limit; `location` describes where
+    /// that rule is.
+    fn check_lookup_limits(&mut self, range: Range<usize>, location: impl Fn(&Self) -> String) {
+        let (n_gsub, n_gpos) = self.lookups.lookup_counts();
+        for (table, count) in [(tags::GSUB, n_gsub), (tags::GPOS, n_gpos)] {
+            if count <= MAX_LOOKUPS || !self.lookup_limit_errors.insert(table) {
+                continue;
+            }
+            let location = location(self);
+            self.error(
+                range.clone(),
+                format!(
+                    "too many {table} lookups: {location} needs lookup index {}, but the maximum is {}",
+                    count - 1,
+                    MAX_LOOKUPS - 1
+                ),
+            );
+        }
+    }
+
+    /// Describe the lookup and feature that the current rule belongs to.
+    fn current_rule_location(&self) -> String {
+        let feature = self.active_feature.as_ref().map(ActiveFeature::tag);
+        match (self.lookups.current_name(), feature) {
+            (Some(name), Some(tag)) => format!("lookup '{name}' in feature '{tag}'"),
+            (Some(name), None) => format!("lookup '{name}'"),
+            (None, Some(tag)) => format!("rule in feature '{tag}'"),
+            (None, None) => "rule".into(),
+        }
     }
 
     fn add_language_system(&mut self, language_system: typed::LanguageSystem) {
@@ -333,7 +603,7 @@ impl<'a> CompilationCtx<'a> {
         self.lookup_flags.clear();
     }
 
-    fn start_lookup_block(&mut self, name: &Token) {
+    fn start_lookup_block(&mut self, name: &Token, use_extension: bool) {
         if let Some((id, _name)) = self.lookups.finish_current() {
             assert!(_name.is_none(), "lookup blocks cannot be nested");
             self.add_lookup_to_current_feature_if_present(id);
@@ -344,12 +614,22 @@ impl<'a> CompilationCtx<'a> {
         }
 
         self.vertical_feature.begin_lookup_block();
-        self.lookups.start_named(name.text.clone());
+        self.lookups.start_named(name.text.clone(), use_extension);
     }
 
-    fn end_lookup_block(&mut self) {
+    fn end_lookup_block(&mut self, name: &Token) {
         // end first, regardless of whether we're in an active feature
         let current = self.lookups.finish_current();
+        if let Some((LookupId::Empty, _)) = current {
+            self.warning(
+                DiagnosticCode::EmptyLookup,
+                name.range(),
+                format!(
+                    "lookup '{}' contains no rules, and will be ignored",
+                    name.text
+                ),
+            );
+        }
         // if this lookup is inside a feature block, it gets added to the feature
         if self.active_feature.is_some() {
             if let Some((id, _)) = current {
@@ -365,11 +645,14 @@ impl<'a> CompilationCtx<'a> {
     fn set_language(&mut self, stmt: typed::Language) {
         let language = stmt.tag().to_raw();
         let script = self.script.unwrap_or(tags::SCRIPT_DFLT);
+        let exclude_dflt = stmt.exclude_dflt().is_some();
+        self.record_explicit_lang_system(script, language, stmt.range());
         self.set_script_language(
             script,
             language,
-            stmt.exclude_dflt().is_some(),
+            exclude_dflt,
             stmt.required().is_some(),
+            stmt.range(),
         );
     }
 
@@ -381,8 +664,45 @@ impl<'a> CompilationCtx<'a> {
 
         self.script = Some(script);
         self.lookup_flags.clear();
+        self.record_explicit_lang_system(script, tags::LANG_DFLT, stmt.range());
+
+        self.set_script_language(script, tags::LANG_DFLT, false, false, stmt.range());
+    }
+
+    /// Warn if the rules at the start of the current feature will not be
+    /// applied to this language system.
+    ///
+    /// This follows the spec, but is rarely what was intended: usually the
+    /// language system is just missing a `languagesystem` statement.
+    fn warn_if_default_rules_omitted(
+        &mut self,
+        system: LanguageSystem,
+        exclude_dflt: bool,
+        range: Range<usize>,
+    ) {
+        let active = self.active_feature.as_ref().unwrap();
+        if active.omits_default_lookups(system, exclude_dflt) {
+            let (script, language) = (
+                system.script.to_string().trim_end().to_owned(),
+                system.language.to_string().trim_end().to_owned(),
+            );
+            let message = format!(
+                "rules before the first 'script' or 'language' statement in feature '{}' will not be applied to '{script} {language}'; declare it with 'languagesystem {script} {language};' to include them, or use 'exclude_dflt' to silence this warning",
+                active.tag()
+            );
+            self.warning(DiagnosticCode::UninheritedDefaultRules, range, message);
+        }
+    }
 
-        self.set_script_language(script, tags::LANG_DFLT, false, false);
+    fn record_explicit_lang_system(&mut self, script: Tag, language: Tag, range: Range<usize>) {
+        let system = LanguageSystem { script, language };
+        if !self
+            .explicit_lang_systems
+            .iter()
+            .any(|(sys, _)| *sys == system)
+        {
+            self.explicit_lang_systems.push((system, range));
+        }
     }
 
     fn set_script_language(
@@ -391,11 +711,13 @@ impl<'a> CompilationCtx<'a> {
         language: Tag,
         exclude_dflt: bool,
         required: bool,
+        range: Range<usize>,
     ) {
         let system = LanguageSystem { script, language };
         if let Some((id, _name)) = self.lookups.finish_current() {
             self.add_lookup_to_current_feature_if_present(id);
         }
+        self.warn_if_default_rules_omitted(system, exclude_dflt, range);
         let key = self
             .active_feature
             .as_mut()
@@ -425,8 +747,6 @@ impl<'a> CompilationCtx<'a> {
                 Kind::IgnoreLigaturesKw => flags.set_ignore_ligatures(true),
                 Kind::IgnoreMarksKw => flags.set_ignore_marks(true),
 
-                //FIXME: we are not enforcing some requirements here. in particular,
-                // The glyph sets of the referenced classes must not overlap, and the MarkAttachmentType statement can reference at most 15 different classes.
                 Kind::MarkAttachmentTypeKw => {
                     let node = iter
                         .next()
@@ -450,31 +770,63 @@ impl<'a> CompilationCtx<'a> {
         self.lookup_flags = LookupFlagInfo::new(flags, mark_filter_set);
     }
 
-    fn resolve_mark_attach_class(&mut self, glyphs: &typed::GlyphClass) -> u16 {
-        let glyphs = self.resolve_glyph_class(glyphs);
+    /// Return the id of the mark attachment class for these glyphs.
+    ///
+    /// The spec requires that the classes referenced by `MarkAttachmentType`
+    /// statements do not overlap, and that there are at most 15 of them.
+    fn resolve_mark_attach_class(&mut self, class: &typed::GlyphClass) -> u16 {
+        let glyphs = self.resolve_glyph_class(class);
         let mark_set = glyphs.sort_and_dedupe();
         if let Some(id) = self.mark_attach_class_id.get(&mark_set) {
             return *id;
         }
 
-        let id = self.mark_attach_class_id.len() as u16 + 1;
-        //FIXME: I don't understand what is not allowed here
+        let overlap = self.mark_attach_class_id.iter().find_map(|(other, id)| {
+            other
+                .iter()
+                .find(|gid| mark_set.items().binary_search(gid).is_ok())
+                .map(|gid| (gid, *id))
+        });
+        if let Some((glyph, other_id)) = overlap {
+            let glyph_name = self.reverse_glyph_map.get(&glyph).unwrap();
+            self.error(
+                class.range(),
+                format!("MarkAttachmentType class includes glyph '{glyph_name}', already in mark attachment class {other_id}"),
+            );
+            return 0;
+        }
 
+        let id = self.mark_attach_class_id.len() as u16 + 1;
+        if id > MAX_MARK_ATTACH_CLASSES {
+            self.error(
+                class.range(),
+                format!("MarkAttachmentType can reference at most {MAX_MARK_ATTACH_CLASSES} distinct classes"),
+            );
+            return 0;
+        }
         self.mark_attach_class_id.insert(mark_set, id);
         id
     }
 
-    fn resolve_mark_filter_set(&mut self, glyphs: &typed::GlyphClass) -> u16 {
-        let glyphs = self.resolve_glyph_class(glyphs);
-        let set = glyphs.sort_and_dedupe();
-        let id = self.mark_filter_sets.len();
-        *self
-            .mark_filter_sets
-            .entry(set)
-            .or_insert_with(|| id.try_into().unwrap())
+    /// Return the id of the mark filtering set for these glyphs.
+    ///
+    /// Sets are stored directly in the GDEF builder, so that they are kept
+    /// alongside any explicitly declared GDEF data.
+    fn resolve_mark_filter_set(&mut self, class: &typed::GlyphClass) -> FilterSetId {
+        let glyphs = self.resolve_glyph_class(class);
+        let id = self
+            .tables
+            .gdef
+            .get_or_insert_with(Default::default)
+            .add_mark_glyph_set(&glyphs);
+        if id as usize == self.mark_filter_set_ranges.len() {
+            self.mark_filter_set_ranges.push(class.range());
+        }
+        id
     }
 
     pub fn add_subtable_break(&mut self) {
+        self.gsub_target_ranges.clear();
         if !self.lookups.add_subtable_break() {
             //TODO: report that we weren't in a lookup?
         }
@@ -482,9 +834,11 @@ impl<'a> CompilationCtx<'a> {
 
     fn ensure_current_lookup_type(&mut self, kind: Kind) -> &mut SomeLookup {
         if !self.lookups.has_current_kind(kind) {
-            //FIXME: find another way of ensuring that named lookup blocks don't
-            //contain mismatched rules
-            //assert!(!self.lookups.is_named(), "ensure rule type in validation");
+            debug_assert!(
+                !(self.lookups.is_named() && self.lookups.has_current()),
+                "validation ensures named lookups contain a single lookup type"
+            );
+            self.gsub_target_ranges.clear();
             if let Some(lookup) = self.lookups.start_lookup(kind, self.lookup_flags) {
                 self.add_lookup_to_current_feature_if_present(lookup);
             }
@@ -492,6 +846,51 @@ impl<'a> CompilationCtx<'a> {
         self.lookups.current_mut().expect("we just created it")
     }
 
+    /// Record the targets added to the current GSUB subtable by a rule, and warn
+    /// about any that conflict with an earlier rule.
+    ///
+    /// Each item is a target, and whether the rule conflicts with an earlier
+    /// rule for that target (the earlier rule is the one that applies.)
+    fn record_gsub_targets(
+        &mut self,
+        targets: impl IntoIterator<Item = (Vec<GlyphId>, bool)>,
+        range: Range<usize>,
+    ) {
+        let mut conflicts: Vec<(Range<usize>, Vec<String>)> = Vec::new();
+        for (target, conflict) in targets {
+            let earlier = self
+                .gsub_target_ranges
+                .entry(target.clone())
+                .or_insert_with(|| range.clone());
+            if !conflict {
+                continue;
+            }
+            let name = target
+                .iter()
+                .map(|gid| self.reverse_glyph_map.get(gid).unwrap().to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            match conflicts.iter_mut().find(|(prev, _)| prev == earlier) {
+                Some((_, names)) => names.push(name),
+                None => conflicts.push((earlier.clone(), vec![name])),
+            }
+        }
+        for (earlier, names) in conflicts {
+            let message = format!(
+                "'{}' {} already substituted by an earlier rule in this lookup (at {}); this rule is ignored for {}",
+                names.join("', '"),
+                if names.len() == 1 { "is" } else { "are" },
+                self.sources.describe_location(self.source_map, earlier),
+                if names.len() == 1 { "it" } else { "them" },
+            );
+            self.warning(
+                DiagnosticCode::DuplicateSubstitution,
+                range.clone(),
+                message,
+            );
+        }
+    }
+
     fn add_lookup_to_current_feature_if_present(&mut self, lookup: LookupId) {
         if lookup != LookupId::Empty {
             if let Some(active) = self.active_feature.as_mut() {
@@ -522,7 +921,11 @@ impl<'a> CompilationCtx<'a> {
             typed::GsubStatement::Type6(rule) => self.add_contextual_sub(&rule),
             typed::GsubStatement::Ignore(rule) => self.add_contextual_sub_ignore(&rule),
             typed::GsubStatement::Type8(rule) => self.add_reverse_contextual_sub(&rule),
-            _ => self.warning(node.range(), "unimplemented rule type"),
+            _ => self.warning(
+                DiagnosticCode::UnimplementedStatement,
+                node.range(),
+                "unimplemented rule type",
+            ),
         }
     }
 
@@ -530,29 +933,43 @@ impl<'a> CompilationCtx<'a> {
         let Some((target, replacement)) = self.resolve_single_sub_glyphs(node) else {
             return;
         };
-        if replacement.is_null() {
+        let targets = if replacement.is_null() {
             // when the replacement is null, it means we are 'deleting' a glyph
             // which uses a trick: we represent it as a multiple substitution
             // rule, with the target sequence being empty.
             // This is explicitly forbidden in the OpenType spec, and
             // explicitly encouraged in the FEA spec, and everyone else does it.
             // see https://github.com/adobe-type-tools/afdko/issues/1438
+            self.lookups.promote_single_sub_to_multi_if_necessary();
             let lookup = self.ensure_current_lookup_type(Kind::GsubType2);
-            for target in target.iter() {
-                lookup.add_gsub_type_2(target, vec![]);
-            }
+            target
+                .iter()
+                .map(|target| (vec![target], lookup.add_gsub_type_2(target, vec![])))
+                .collect::<Vec<_>>()
         } else if self.lookups.has_current_kind(Kind::GsubType2) {
             // we combine chains of mixed single & multi-sub rules into multi-sub lookups
             let lookup = self.ensure_current_lookup_type(Kind::GsubType2);
-            for (target, replacement) in target.iter().zip(replacement.into_iter_for_target()) {
-                lookup.add_gsub_type_2(target, vec![replacement]);
-            }
+            target
+                .iter()
+                .zip(replacement.into_iter_for_target())
+                .map(|(target, replacement)| {
+                    (
+                        vec![target],
+                        lookup.add_gsub_type_2(target, vec![replacement]),
+                    )
+                })
+                .collect()
         } else {
             let lookup = self.ensure_current_lookup_type(Kind::GsubType1);
-            for (target, replacement) in target.iter().zip(replacement.into_iter_for_target()) {
-                lookup.add_gsub_type_1(target, replacement);
-            }
-        }
+            target
+                .iter()
+                .zip(replacement.into_iter_for_target())
+                .map(|(target, replacement)| {
+                    (vec![target], lookup.add_gsub_type_1(target, replacement))
+                })
+                .collect()
+        };
+        self.record_gsub_targets(targets, node.range());
     }
 
     fn resolve_single_sub_glyphs(
@@ -582,20 +999,124 @@ impl<'a> CompilationCtx<'a> {
                 None
             }
             (GlyphOrClass::Class(c1), GlyphOrClass::Class(c2)) if c1.len() != c2.len() => {
-                self.error(
-                    replace.unwrap().range(),
-                    format!(
-                        "class has different length ({}) than target ({})",
-                        c1.len(),
-                        c2.len()
-                    ),
-                );
+                let message = self.describe_class_length_mismatch(&c1, &c2);
+                self.error(replace.unwrap().range(), message);
                 None
             }
+            (GlyphOrClass::Class(c1), GlyphOrClass::Class(c2)) => {
+                self.check_class_pairing_order(replace.unwrap().range(), &c1, &c2);
+                Some((GlyphOrClass::Class(c1), GlyphOrClass::Class(c2)))
+            }
             other => Some(other),
         }
     }
 
+    /// Warn if the glyphs in a class-to-class substitution look misordered.
+    ///
+    /// Glyphs in the two classes are paired by position, after ranges are
+    /// expanded. If every glyph in the target has a counterpart in the
+    /// replacement with the same base name (the part before the first '.',
+    /// e.g. 'a' and 'a.sc') but these are not at the same positions, the
+    /// classes were probably written in different orders.
+    fn check_class_pairing_order(
+        &mut self,
+        range: Range<usize>,
+        target: &GlyphClass,
+        replace: &GlyphClass,
+    ) {
+        let base_names = |class: &GlyphClass| {
+            class
+                .iter()
+                .map(|gid| match self.reverse_glyph_map.get(&gid) {
+                    Some(GlyphIdent::Name(name)) => Some(name.clone()),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()
+        };
+        let (Some(target_names), Some(replace_names)) = (base_names(target), base_names(replace))
+        else {
+            return;
+        };
+        let base = |name: &SmolStr| name.split('.').next().unwrap_or_default().to_owned();
+        let target_bases = target_names.iter().map(base).collect::<Vec<_>>();
+        let replace_bases = replace_names.iter().map(base).collect::<Vec<_>>();
+
+        // only check if the base names are unique, and are the same set in both classes
+        let unique = target_bases.iter().collect::<HashSet<_>>();
+        if unique.len() != target_bases.len()
+            || unique != replace_bases.iter().collect::<HashSet<_>>()
+        {
+            return;
+        }
+
+        let Some(pos) = (0..target_bases.len()).find(|i| target_bases[*i] != replace_bases[*i])
+        else {
+            return;
+        };
+        let expected = replace_bases
+            .iter()
+            .position(|name| *name == target_bases[pos])
+            .unwrap();
+        self.warning(
+            DiagnosticCode::MismatchedClassOrder,
+            range,
+            format!(
+                "'{}' is paired with '{}', but '{}' is at position {} of the replacement class; \
+                 glyphs in class-to-class substitutions are paired by their position in each class, \
+                 after ranges are expanded",
+                target_names[pos],
+                replace_names[pos],
+                replace_names[expected],
+                expected + 1,
+            ),
+        );
+    }
+
+    /// Generate an error message for a single sub rule with mismatched classes.
+    ///
+    /// When the classes are small we include their contents, along with a hint
+    /// about the unmatched glyphs, since the classes are often generated and
+    /// the lengths alone are hard to act on.
+    fn describe_class_length_mismatch(&self, target: &GlyphClass, replace: &GlyphClass) -> String {
+        const MAX_GLYPHS_TO_LIST: usize = 10;
+        let mut message = format!(
+            "class has different length ({}) than target ({})",
+            replace.len(),
+            target.len()
+        );
+        if target.len().max(replace.len()) > MAX_GLYPHS_TO_LIST {
+            return message;
+        }
+
+        let names = |glyphs: &[GlyphId]| {
+            glyphs
+                .iter()
+                .map(|gid| self.reverse_glyph_map.get(gid).unwrap().to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let (target, replace) = (target.items(), replace.items());
+        let common = target.len().min(replace.len());
+        message.push_str(&format!(
+            ": target is [{}], replacement is [{}]; ",
+            names(target),
+            names(replace)
+        ));
+        if replace.len() > common {
+            message.push_str(&format!(
+                "remove [{}] from the replacement, or add {} glyph(s) to the target",
+                names(&replace[common..]),
+                replace.len() - common
+            ));
+        } else {
+            message.push_str(&format!(
+                "add replacements for [{}], or remove them from the target",
+                names(&target[common..])
+            ));
+        }
+        message
+    }
+
     fn add_multiple_sub(&mut self, node: &typed::Gsub2) {
         let target = node.target();
         let target_id = self.resolve_glyph(&target);
@@ -605,14 +1126,16 @@ impl<'a> CompilationCtx<'a> {
         // we need to promote the current single-sub lookup before continuing.
         self.lookups.promote_single_sub_to_multi_if_necessary();
         let lookup = self.ensure_current_lookup_type(Kind::GsubType2);
-        lookup.add_gsub_type_2(target_id, replacement);
+        let conflict = lookup.add_gsub_type_2(target_id, replacement);
+        self.record_gsub_targets([(vec![target_id], conflict)], node.range());
     }
 
     fn add_alternate_sub(&mut self, node: &typed::Gsub3) {
         let target = self.resolve_glyph(&node.target());
         let alts = self.resolve_glyph_class(&node.alternates());
         let lookup = self.ensure_current_lookup_type(Kind::GsubType3);
-        lookup.add_gsub_type_3(target, alts.iter().collect());
+        let conflict = lookup.add_gsub_type_3(target, alts.iter().collect());
+        self.record_gsub_targets([(vec![target], conflict)], node.range());
     }
 
     fn add_ligature_sub(&mut self, node: &typed::Gsub4) {
@@ -623,9 +1146,14 @@ impl<'a> CompilationCtx<'a> {
         let replacement = self.resolve_glyph(&node.replacement());
         let lookup = self.ensure_current_lookup_type(Kind::GsubType4);
 
-        for target in sequence_enumerator(&target) {
-            lookup.add_gsub_type_4(target, replacement);
-        }
+        let targets = sequence_enumerator(&target)
+            .into_iter()
+            .map(|target| {
+                let conflict = lookup.add_gsub_type_4(target.clone(), replacement);
+                (target, conflict)
+            })
+            .collect::<Vec<_>>();
+        self.record_gsub_targets(targets, node.range());
     }
 
     fn add_contextual_sub(&mut self, node: &typed::Gsub6) {
@@ -709,7 +1237,9 @@ impl<'a> CompilationCtx<'a> {
                 }
 
                 for lookup in item.lookups() {
-                    let id = self.lookups.get_named(&lookup.label().text).unwrap(); // validated already
+                    let Some(id) = self.resolve_named_lookup(lookup.label()) else {
+                        continue;
+                    };
                     if matches!(id, LookupId::Gpos(_)) {
                         self.error(
                             lookup.label().range(),
@@ -737,8 +1267,8 @@ impl<'a> CompilationCtx<'a> {
         let lookahead = self.resolve_lookahead_sequence(node.lookahead().items());
         let input = node.input().items().next().unwrap();
         let target = input.target();
+        // validation ensures there is a replacement, and no lookups
         let replacement = node.inline_rule().and_then(|r| r.replacements().next());
-        //FIXME: warn if there are actual lookups here, we don't support that
         if let Some((target, replacement)) =
             self.validate_single_sub_inputs(&target, replacement.as_ref())
         {
@@ -817,12 +1347,14 @@ impl<'a> CompilationCtx<'a> {
 
             let mark_class_node = mark.mark_class_name().expect("checked in validation");
             let class_name = mark_class_node.text().to_owned();
-            let mark_class = self.mark_classes.get(&class_name).unwrap();
+            let Some(mark_class) = self.mark_classes.get(&class_name) else {
+                self.error(mark_class_node.range(), "undefined mark class");
+                continue;
+            };
 
             // access the lookup through the field, so the borrow checker
-            // doesn't think we're borrowing all of self
-            //TODO: we do validation here because our validation pass isn't smart
-            //enough. We need to not just validate a rule, but every rule in a lookup.
+            // doesn't think we're borrowing all of self. Overlapping mark
+            // classes are reported during validation; this is a backstop.
             let maybe_err = self
                 .lookups
                 .current_mut()
@@ -879,12 +1411,14 @@ impl<'a> CompilationCtx<'a> {
                 };
                 let component_anchor = component_anchor.unwrap();
                 let class_name = mark_class_node.text();
-                let mark_class = self.mark_classes.get(class_name).unwrap();
+                let Some(mark_class) = self.mark_classes.get(class_name) else {
+                    self.error(mark_class_node.range(), "undefined mark class");
+                    continue;
+                };
 
                 // access the lookup through the field, so the borrow checker
-                // doesn't think we're borrowing all of self
-                //TODO: we do validation here because our validation pass isn't smart
-                //enough. We need to not just validate a rule, but every rule in a lookup.
+                // doesn't think we're borrowing all of self. Overlapping mark
+                // classes are reported during validation; this is a backstop.
                 anchor_records.insert(class_name.clone(), component_anchor);
                 let maybe_err = self
                     .lookups
@@ -926,10 +1460,13 @@ impl<'a> CompilationCtx<'a> {
             let base_anchor = self.resolve_anchor(&mark.anchor());
             let mark_class_node = mark.mark_class_name().expect("checked in validation");
             let class_name = mark_class_node.text();
-            let mark_class = self.mark_classes.get(mark_class_node.text()).unwrap();
+            let Some(mark_class) = self.mark_classes.get(class_name) else {
+                self.error(mark_class_node.range(), "undefined mark class");
+                continue;
+            };
 
-            //TODO: we do validation here because our validation pass isn't smart
-            //enough. We need to not just validate a rule, but every rule in a lookup.
+            // overlapping mark classes are reported during validation; this
+            // is a backstop
             let maybe_err = self
                 .lookups
                 .current_mut()
@@ -963,10 +1500,13 @@ impl<'a> CompilationCtx<'a> {
         range: Range<usize>,
         maybe_err: Option<PreviouslyAssignedClass>,
     ) {
-        if let Some(PreviouslyAssignedClass { class, .. }) = maybe_err {
+        if let Some(PreviouslyAssignedClass { glyph_id, class }) = maybe_err {
+            let glyph = self.reverse_glyph_map.get(&glyph_id).unwrap();
             self.error(
                 range,
-                format!("mark class includes glyph in class '{class}', already used in lookup.",),
+                format!(
+                    "mark class includes glyph '{glyph}' in class '{class}', already used in lookup."
+                ),
             );
         };
     }
@@ -997,7 +1537,9 @@ impl<'a> CompilationCtx<'a> {
                 }
 
                 for lookup in item.lookups() {
-                    let id = self.lookups.get_named(&lookup.label().text).unwrap();
+                    let Some(id) = self.resolve_named_lookup(lookup.label()) else {
+                        continue;
+                    };
                     if matches!(id, LookupId::Gsub(_)) {
                         self.error(
                             lookup.label().range(),
@@ -1099,7 +1641,7 @@ impl<'a> CompilationCtx<'a> {
                         | ValueFormat::X_ADVANCE_DEVICE
                         | ValueFormat::Y_ADVANCE_DEVICE
                 ),
-                "checked during parsing"
+                "checked in validation"
             );
             return result
                 .with_device(x_place_dev.compile(), ValueFormat::X_PLACEMENT_DEVICE)
@@ -1167,17 +1709,46 @@ impl<'a> CompilationCtx<'a> {
         self.glyph_class_defs.insert(name.text().clone(), glyphs);
     }
 
+    /// Define all of the mark classes, before compiling any rules.
+    ///
+    /// This allows mark classes to be used before they are defined. Each
+    /// definition is resolved against the glyph classes and anchors defined
+    /// before it; those definitions are then discarded, and defined again as
+    /// normal when compiling the rest of the source.
+    fn define_mark_classes_early(&mut self, node: &typed::Root) {
+        for item in node.definitions() {
+            if self.skip.should_skip(item) {
+                continue;
+            }
+            // errors in these definitions are reported in the main pass
+            let n_errors = self.errors.len();
+            if let Some(class_def) = typed::GlyphClassDef::cast(item) {
+                self.define_glyph_class(class_def);
+                self.errors.truncate(n_errors);
+            } else if let Some(anchor_def) = typed::AnchorDef::cast(item) {
+                self.define_named_anchor(anchor_def);
+                self.errors.truncate(n_errors);
+            } else if let Some(mark_def) = typed::MarkClassDef::cast(item) {
+                self.define_mark_class(mark_def);
+            }
+        }
+        self.glyph_class_defs.clear();
+        self.anchor_defs.clear();
+        self.anchor_def_names.clear();
+    }
+
     fn define_mark_class(&mut self, class_decl: typed::MarkClassDef) {
         let class_items = class_decl.glyph_class();
         let class_items = self.resolve_glyph_or_class(&class_items).into();
 
         let anchor = self.resolve_anchor(&class_decl.anchor());
         let class_name = class_decl.mark_class_name();
-        self.mark_classes
+        let mark_class = self
+            .mark_classes
             .entry(class_name.text().clone())
-            .or_default()
-            .members
-            .push((class_items, anchor));
+            .or_default();
+        mark_class.members.push((class_items, anchor));
+        mark_class.glyphs = None;
     }
 
     fn add_feature(&mut self, feature: typed::Feature) {
@@ -1227,6 +1798,7 @@ impl<'a> CompilationCtx<'a> {
             }
         }
         self.features.aalt = Some(aalt);
+        self.aalt_range = Some(feature.range());
     }
 
     fn resolve_stylistic_set_feature(&mut self, tag: Tag, feature: &typed::Feature) {
@@ -1269,12 +1841,6 @@ impl<'a> CompilationCtx<'a> {
                     .map(|x| self.resolve_name_spec(&x))
                     .collect();
             }
-            if let Some(node) = cv_params.sample_text_name() {
-                params.sample_text_name = node
-                    .statements()
-                    .map(|x| self.resolve_name_spec(&x))
-                    .collect();
-            }
             for node in cv_params.param_ui_label_name() {
                 params.param_ui_label_names.push(
                     node.statements()
@@ -1298,17 +1864,8 @@ impl<'a> CompilationCtx<'a> {
     }
 
     fn resolve_size_feature(&mut self, feature: &typed::Feature) {
-        //FIXME: I thought this was signed, but I now think it's
-        // unsigned? Double check with spec and ensure this is validated
         fn resolve_decipoint(node: &typed::FloatLike) -> u16 {
-            match node {
-                typed::FloatLike::Number(n) => n.parse_unsigned(),
-                typed::FloatLike::Float(f) => {
-                    let f = f.parse();
-                    ((f * 10.0).round() as i16).try_into().ok()
-                }
-            }
-            .expect("validated at parse time")
+            node.parse_decipoints().expect("validated")
         }
 
         let mut size = SizeFeature::default();
@@ -1390,61 +1947,110 @@ impl<'a> CompilationCtx<'a> {
     }
 
     fn resolve_os2(&mut self, table: &typed::Os2Table) {
+        use super::tables::Os2Field;
         let mut os2 = super::tables::Os2Builder::default();
+        let mut fields = Vec::new();
         for item in table.statements() {
             match item {
                 typed::Os2TableItem::Number(val) => {
                     let value = val.number().parse_unsigned().unwrap();
-                    match val.keyword().text.as_str() {
-                        "WeightClass" => os2.us_weight_class = value,
-                        "WidthClass" => os2.us_width_class = value,
-                        "LowerOpSize" => os2.us_lower_optical_point_size = Some(value),
-                        "UpperOpSize" => os2.us_upper_optical_point_size = Some(value),
-                        "FSType" => os2.fs_type = value,
+                    let field = match val.keyword().text.as_str() {
+                        "WeightClass" => {
+                            os2.us_weight_class = value;
+                            Os2Field::WeightClass
+                        }
+                        "WidthClass" => {
+                            os2.us_width_class = value;
+                            Os2Field::WidthClass
+                        }
+                        "LowerOpSize" => {
+                            os2.us_lower_optical_point_size = Some(value);
+                            Os2Field::LowerOpSize
+                        }
+                        "UpperOpSize" => {
+                            os2.us_upper_optical_point_size = Some(value);
+                            Os2Field::UpperOpSize
+                        }
+                        "FSType" => {
+                            os2.fs_type = value;
+                            Os2Field::FsType
+                        }
                         _ => unreachable!("checked at parse time"),
-                    }
+                    };
+                    fields.push(field);
                 }
                 typed::Os2TableItem::Metric(val) => {
                     let value = val.metric().parse_simple().expect("checked in validation");
-                    match val.keyword().kind {
-                        Kind::TypoAscenderKw => os2.s_typo_ascender = value,
-                        Kind::TypoDescenderKw => os2.s_typo_descender = value,
-                        Kind::TypoLineGapKw => os2.s_typo_line_gap = value,
-                        Kind::XHeightKw => os2.sx_height = value,
-                        Kind::CapHeightKw => os2.s_cap_height = value,
-                        Kind::WinAscentKw => os2.us_win_ascent = value as u16,
-                        Kind::WinDescentKw => os2.us_win_descent = value as u16,
+                    let field = match val.keyword().kind {
+                        Kind::TypoAscenderKw => {
+                            os2.s_typo_ascender = value;
+                            Os2Field::TypoAscender
+                        }
+                        Kind::TypoDescenderKw => {
+                            os2.s_typo_descender = value;
+                            Os2Field::TypoDescender
+                        }
+                        Kind::TypoLineGapKw => {
+                            os2.s_typo_line_gap = value;
+                            Os2Field::TypoLineGap
+                        }
+                        Kind::XHeightKw => {
+                            os2.sx_height = value;
+                            Os2Field::XHeight
+                        }
+                        Kind::CapHeightKw => {
+                            os2.s_cap_height = value;
+                            Os2Field::CapHeight
+                        }
+                        Kind::WinAscentKw => {
+                            os2.us_win_ascent = value as u16;
+                            Os2Field::WinAscent
+                        }
+                        Kind::WinDescentKw => {
+                            os2.us_win_descent = value as u16;
+                            Os2Field::WinDescent
+                        }
                         _ => unreachable!("checked at parse time"),
-                    }
+                    };
+                    fields.push(field);
                 }
                 typed::Os2TableItem::NumberList(list) => match list.keyword().kind {
                     Kind::PanoseKw => {
                         for (i, val) in list.values().enumerate() {
                             os2.panose_10[i] = val.parse_signed() as u8;
                         }
+                        fields.push(Os2Field::Panose);
                     }
                     Kind::UnicodeRangeKw => {
                         for val in list.values() {
                             os2.unicode_range.set_bit(val.parse_signed() as _);
                         }
+                        fields.push(Os2Field::UnicodeRange);
                     }
                     Kind::CodePageRangeKw => {
                         for val in list.values() {
                             os2.code_page_range
                                 .add_code_page(val.parse_unsigned().unwrap());
                         }
+                        fields.push(Os2Field::CodePageRange);
                     }
                     _ => unreachable!("checked at parse time"),
                 },
                 typed::Os2TableItem::Vendor(item) => {
-                    os2.ach_vend_id = item.parse_tag().expect("validated");
+                    // errors are reported during validation
+                    if let Ok(tag) = item.parse_tag() {
+                        os2.ach_vend_id = tag;
+                        fields.push(Os2Field::Vendor);
+                    }
                 }
                 typed::Os2TableItem::FamilyClass(item) => {
-                    os2.s_family_class = item.value().parse().unwrap() as i16
+                    os2.s_family_class = item.value().parse().unwrap() as i16;
+                    fields.push(Os2Field::FamilyClass);
                 }
             }
         }
         self.tables.os2 = Some(os2);
+        self.tables.explicit_fields.os2 = fields;
     }
 
     fn resolve_stat(&mut self, table: &typed::StatTable) {
@@ -1472,8 +2078,10 @@ impl<'a> CompilationCtx<'a> {
                 }
                 typed::StatTableItem::DesignAxis(value) => {
                     let tag = value.tag().to_raw();
-                    let ordering = value.ordering().parse_unsigned().unwrap();
-                    //FIXME: validate
+                    let ordering = value
+                        .ordering()
+                        .parse_unsigned()
+                        .expect("checked in validation");
                     let name = value.names().map(|n| self.resolve_name_spec(&n)).collect();
                     stat.records.push(super::tables::AxisRecord {
                         tag,
@@ -1546,26 +2154,44 @@ impl<'a> CompilationCtx<'a> {
     }
 
     fn resolve_hhea(&mut self, table: &typed::HheaTable) {
+        use super::tables::MetricsField;
         let mut hhea = tables::hhea::Hhea::default();
+        let mut fields = Vec::new();
         for record in table.metrics() {
             let keyword = record.keyword();
             let value = record
                 .metric()
                 .parse_simple()
                 .expect("checked during validation");
-            match keyword.kind {
-                Kind::CaretOffsetKw => hhea.caret_offset = value,
-                Kind::AscenderKw => hhea.ascender = value.into(),
-                Kind::DescenderKw => hhea.descender = value.into(),
-                Kind::LineGapKw => hhea.line_gap = value.into(),
+            let field = match keyword.kind {
+                Kind::CaretOffsetKw => {
+                    hhea.caret_offset = value;
+                    MetricsField::CaretOffset
+                }
+                Kind::AscenderKw => {
+                    hhea.ascender = value.into();
+                    MetricsField::Ascender
+                }
+                Kind::DescenderKw => {
+                    hhea.descender = value.into();
+                    MetricsField::Descender
+                }
+                Kind::LineGapKw => {
+                    hhea.line_gap = value.into();
+                    MetricsField::LineGap
+                }
                 other => panic!("bug in parser, unexpected token '{}'", other),
-            }
+            };
+            fields.push(field);
         }
         self.tables.hhea = Some(hhea);
+        self.tables.explicit_fields.hhea = fields;
     }
 
     fn resolve_vhea(&mut self, table: &typed::VheaTable) {
+        use super::tables::MetricsField;
         let mut vhea = tables::vhea::Vhea::default();
+        let mut fields = Vec::new();
         for record in table.metrics() {
             let keyword = record.keyword();
             let value = record
@@ -1573,24 +2199,40 @@ impl<'a> CompilationCtx<'a> {
                 .parse_simple()
                 .expect("checked during validation");
 
-            match keyword.kind {
-                Kind::VertTypoAscenderKw => vhea.ascender = value.into(),
-                Kind::VertTypoDescenderKw => vhea.descender = value.into(),
-                Kind::VertTypoLineGapKw => vhea.line_gap = value.into(),
+            let field = match keyword.kind {
+                Kind::VertTypoAscenderKw => {
+                    vhea.ascender = value.into();
+                    MetricsField::Ascender
+                }
+                Kind::VertTypoDescenderKw => {
+                    vhea.descender = value.into();
+                    MetricsField::Descender
+                }
+                Kind::VertTypoLineGapKw => {
+                    vhea.line_gap = value.into();
+                    MetricsField::LineGap
+                }
                 other => panic!("bug in parser, unexpected token '{}'", other),
-            }
+            };
+            fields.push(field);
         }
         self.tables.vhea = Some(vhea);
+        self.tables.explicit_fields.vhea = fields;
     }
 
     fn resolve_vmtx(&mut self, table: &typed::VmtxTable) {
-        let mut vmtx = super::tables::VmtxBuilder::default();
+        let mut vmtx = super::tables::VerticalMetrics::default();
         for item in table.statements() {
             let glyph = self.resolve_glyph(&item.glyph());
             let value = item.value().parse_signed();
             match item.keyword().kind {
-                Kind::VertAdvanceYKw => vmtx.advances_y.push((glyph, value)),
-                Kind::VertOriginYKw => vmtx.origins_y.push((glyph, value)),
+                Kind::VertAdvanceYKw => {
+                    let advance = value.try_into().expect("checked during validation");
+                    vmtx.advances.insert(glyph, advance);
+                }
+                Kind::VertOriginYKw => {
+                    vmtx.origins.insert(glyph, value);
+                }
                 _ => unreachable!(),
             }
         }
@@ -1598,7 +2240,9 @@ impl<'a> CompilationCtx<'a> {
     }
 
     fn resolve_gdef(&mut self, table: &typed::GdefTable) {
-        let mut gdef = super::tables::GdefBuilder::default();
+        // the builder may already hold data collected from earlier statements,
+        // such as mark filtering sets or the variation store
+        let mut gdef = self.tables.gdef.take().unwrap_or_default();
         for statement in table.statements() {
             match statement {
                 typed::GdefTableItem::Attach(rule) => {
@@ -1627,6 +2271,16 @@ impl<'a> CompilationCtx<'a> {
                             .values()
                             .map(|n| CaretValue::format_2(n.parse_unsigned().unwrap()))
                             .collect(),
+                        // a caret with a null device is just a coordinate
+                        typed::LigatureCaretValue::Device(items) => items
+                            .with_devices()
+                            .map(|(n, device)| match device.compile() {
+                                Some(device) => {
+                                    CaretValue::format_3(n.parse_signed(), device.into())
+                                }
+                                None => CaretValue::format_1(n.parse_signed()),
+                            })
+                            .collect(),
                     };
                     carets.sort_by_key(|c| match c {
                         CaretValue::Format1(table) => table.coordinate as i32,
@@ -1699,24 +2353,45 @@ impl<'a> CompilationCtx<'a> {
     }
 
     fn resolve_lookup_ref(&mut self, lookup: typed::LookupRef) {
+        if let Some(id) = self.resolve_named_lookup(lookup.label()) {
+            self.add_lookup_to_current_feature_if_present(id);
+        }
+    }
+
+    /// Return the id for a referenced named lookup.
+    ///
+    /// If the referenced lookup is empty, we warn and return `None`.
+    fn resolve_named_lookup(&mut self, label: &Token) -> Option<LookupId> {
         let id = self
             .lookups
-            .get_named(&lookup.label().text)
+            .get_named(&label.text)
             .expect("checked in validation pass");
-        self.add_lookup_to_current_feature_if_present(id);
+        if id == LookupId::Empty {
+            self.warning(
+                DiagnosticCode::EmptyLookup,
+                label.range(),
+                format!(
+                    "lookup '{}' contains no rules, this reference will be ignored",
+                    label.text
+                ),
+            );
+            return None;
+        }
+        Some(id)
     }
 
     fn resolve_lookup_block(&mut self, lookup: typed::LookupBlock) {
-        self.start_lookup_block(lookup.label());
-
-        //let use_extension = lookup.use_extension().is_some();
+        self.start_lookup_block(lookup.label(), lookup.use_extension().is_some());
         for item in lookup.statements() {
             self.resolve_statement(item);
         }
-        self.end_lookup_block();
+        self.end_lookup_block(lookup.label());
     }
 
     fn resolve_statement(&mut self, item: &NodeOrToken) {
+        if self.skip.should_skip(item) {
+            return;
+        }
         if let Some(script) = typed::Script::cast(item) {
             self.set_script(script);
         } else if let Some(language) = typed::Language::cast(item) {
@@ -1726,7 +2401,9 @@ impl<'a> CompilationCtx<'a> {
         } else if let Some(glyph_def) = typed::GlyphClassDef::cast(item) {
             self.define_glyph_class(glyph_def);
         } else if let Some(glyph_def) = typed::MarkClassDef::cast(item) {
-            self.define_mark_class(glyph_def);
+            if !self.late_mark_classes {
+                self.define_mark_class(glyph_def);
+            }
         } else if item.kind() == Kind::SubtableNode {
             self.add_subtable_break();
         } else if let Some(lookup) = typed::LookupRef::cast(item) {
@@ -1735,8 +2412,10 @@ impl<'a> CompilationCtx<'a> {
             self.resolve_lookup_block(lookup);
         } else if let Some(rule) = typed::GsubStatement::cast(item) {
             self.add_gsub_statement(rule);
+            self.check_lookup_limits(item.range(), Self::current_rule_location);
         } else if let Some(rule) = typed::GposStatement::cast(item) {
-            self.add_gpos_statement(rule)
+            self.add_gpos_statement(rule);
+            self.check_lookup_limits(item.range(), Self::current_rule_location);
         } else if item.kind() == Kind::Semi {
             // continue
         } else {
@@ -1759,6 +2438,16 @@ impl<'a> CompilationCtx<'a> {
         self.value_record_defs.insert(name.text.clone(), resolved);
     }
 
+    fn add_anon_block(&mut self, block: typed::AnonBlock) {
+        let (file, range) = self.source_map.resolve_range(block.range());
+        self.anon_blocks.push(AnonymousBlock {
+            label: block.label().as_str().to_owned(),
+            contents: block.contents(),
+            file,
+            range,
+        });
+    }
+
     fn define_named_anchor(&mut self, anchor_def: typed::AnchorDef) {
         let anchor_block = anchor_def.anchor();
         let name = anchor_def.name();
@@ -1778,6 +2467,8 @@ impl<'a> CompilationCtx<'a> {
         {
             self.error(name.range(), "duplicate anchor definition");
         }
+        self.anchor_def_names
+            .insert(name.text.clone(), name.range());
     }
 
     fn resolve_anchor(&mut self, item: &typed::Anchor) -> Option<AnchorTable> {
@@ -1905,26 +2596,36 @@ impl<'a> CompilationCtx<'a> {
                 self.add_glyphs_from_range(&range, &mut glyphs);
             } else if let Some(alias) = typed::GlyphClassName::cast(item) {
                 glyphs.extend(self.resolve_named_glyph_class(&alias).items());
+            } else if let Some(op) = typed::GlyphClassOperation::cast(item) {
+                let operand = self.resolve_named_glyph_class(&op.class().unwrap());
+                let operand = operand.iter().collect::<HashSet<_>>();
+                let intersect = op.is_intersection();
+                glyphs.retain(|gid| operand.contains(gid) == intersect);
             } else {
                 panic!("unexptected kind in class literal: '{}'", item.kind());
             }
         }
-        glyphs.into()
+        self.class_arena.intern(glyphs)
     }
 
     fn resolve_named_glyph_class(&mut self, name: &typed::GlyphClassName) -> GlyphClass {
-        self.glyph_class_defs
-            .get(name.text())
-            .cloned()
-            .or_else(|| {
-                self.mark_classes.get(name.text()).map(|cls| {
-                    cls.members
+        if let Some(class) = self.glyph_class_defs.get(name.text()) {
+            return class.clone();
+        }
+        let mark_class = self.mark_classes.get_mut(name.text()).unwrap();
+        let arena = &mut self.class_arena;
+        mark_class
+            .glyphs
+            .get_or_insert_with(|| {
+                arena.intern(
+                    mark_class
+                        .members
                         .iter()
                         .flat_map(|(glyphs, _)| glyphs.iter())
-                        .collect()
-                })
+                        .collect(),
+                )
             })
-            .unwrap()
+            .clone()
     }
 
     fn resolve_glyph_name(&mut self, name: &typed::GlyphName) -> GlyphId {
@@ -2055,7 +2756,53 @@ fn get_reasonable_length_span(node: &NodeOrToken) -> Range<usize> {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
+    use write_fonts::{tables::layout::CoverageTable, OffsetMarker};
+
     use super::*;
+    use crate::{parse::ParseContext, GlyphName};
+
+    // parse, validate and compile the provided source, which should not error
+    fn compile_str(fea: &'static str, glyphs: &[&str]) -> Compilation {
+        compile_str_with_inference(fea, glyphs, Default::default())
+    }
+
+    fn compile_str_with_inference(
+        fea: &'static str,
+        glyphs: &[&str],
+        inference: GlyphClassInference,
+    ) -> Compilation {
+        compile_str_impl(fea, glyphs, inference, None)
+    }
+
+    fn compile_str_impl(
+        fea: &'static str,
+        glyphs: &[&str],
+        inference: GlyphClassInference,
+        var_info: Option<&dyn VariationInfo>,
+    ) -> Compilation {
+        let glyph_map: GlyphMap = glyphs.iter().copied().map(GlyphName::new).collect();
+        let resolver = move |_: &std::ffi::OsStr| Ok(Arc::<str>::from(fea));
+        let (tree, mut diagnostics) = ParseContext::parse(
+            "test.fea".into(),
+            Some(&glyph_map),
+            &Default::default(),
+            &Default::default(),
+            Box::new(resolver),
+        )
+        .unwrap()
+        .generate_parse_tree();
+        diagnostics.extend(crate::compile::validate(&tree, &glyph_map, var_info));
+        assert!(
+            !diagnostics.iter().any(Diagnostic::is_error),
+            "{diagnostics:?}"
+        );
+        let mut ctx = CompilationCtx::new(&glyph_map, tree.source_map(), &tree.sources, var_info)
+            .with_glyph_class_inference(inference);
+        ctx.compile(&tree.typed_root());
+        ctx.build().unwrap()
+    }
 
     fn glyph_id_vec<const N: usize>(ids: [u16; N]) -> Vec<GlyphId> {
         ids.iter().copied().map(GlyphId::new).collect()
@@ -2081,4 +2828,1359 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn glyph_class_inference() {
+        let fea = "\
+            table GDEF {\n\
+                GlyphClassDef [b], , , ;\n\
+            } GDEF;\n\
+            markClass m <anchor 0 0> @TOP;\n\
+            feature mark {\n\
+                pos base [a b] <anchor 10 10> mark @TOP;\n\
+            } mark;\n";
+        let glyphs = [".notdef", "a", "b", "m"];
+        let classes = |inference| {
+            let gdef = compile_str_with_inference(fea, &glyphs, inference)
+                .gdef
+                .unwrap();
+            let class_def = gdef.glyph_class_def.as_ref().unwrap();
+            [1, 2, 3].map(|gid| class_def.get(GlyphId::new(gid)))
+        };
+        // the GDEF block declares classes, so by default nothing is inferred
+        assert_eq!(classes(GlyphClassInference::IfMissing), [0, 1, 0]);
+        assert_eq!(classes(GlyphClassInference::Never), [0, 1, 0]);
+        // explicit classes are kept; 'a' is inferred as a base, 'm' as a mark
+        assert_eq!(classes(GlyphClassInference::Merge), [1, 1, 3]);
+    }
+
+    #[test]
+    fn named_anchor_definitions() {
+        let fea = "\
+            anchorDef 120 30 TOP;\n\
+            anchorDef 10 -20 contourpoint 5 BOTTOM;\n\
+            markClass a <anchor TOP> @TOP_MARKS;\n";
+        let compilation = compile_str(fea, &[".notdef", "a"]);
+        let top = &compilation.anchors["TOP"];
+        assert_eq!((top.x, top.y, top.contour_point), (120, 30, None));
+        assert_eq!(&fea[top.range.clone()], "TOP");
+        let bottom = &compilation.anchors["BOTTOM"];
+        assert_eq!(
+            (bottom.x, bottom.y, bottom.contour_point),
+            (10, -20, Some(5))
+        );
+        assert_eq!(bottom.range.start, fea.find("BOTTOM").unwrap());
+    }
+
+    #[test]
+    fn undeclared_language_system() {
+        let fea = "\
+            languagesystem DFLT dflt;\n\
+            languagesystem latn dflt;\n\
+            feature locl {\n\
+                script latn;\n\
+                language TRK;\n\
+                sub a by b;\n\
+                language DEU exclude_dflt;\n\
+            } locl;\n";
+        let compilation = compile_str(fea, &[".notdef", "a", "b"]);
+        let texts = compilation
+            .warnings
+            .iter()
+            .map(Diagnostic::text)
+            .collect::<Vec<_>>();
+        // DEU has no lookups, so it isn't reported
+        assert_eq!(
+            texts,
+            ["'latn TRK' is not declared with a 'languagesystem' statement; features that are not registered for it will not be applied"]
+        );
+    }
+
+    #[test]
+    fn default_rules_not_inherited_by_unregistered_language() {
+        let warnings = |fea| {
+            compile_str(fea, &[".notdef", "a", "b", "c"])
+                .warnings
+                .iter()
+                .map(|w| w.text().to_string())
+                .collect::<Vec<_>>()
+        };
+        // TRK is not registered, so does not get 'sub a by b'
+        assert_eq!(
+            warnings(
+                "languagesystem DFLT dflt;\n\
+                feature locl {\n\
+                    sub a by b;\n\
+                    language TRK;\n\
+                    sub b by c;\n\
+                } locl;\n"
+            ),
+            [
+                "rules before the first 'script' or 'language' statement in feature 'locl' will not be applied to 'DFLT TRK'; declare it with 'languagesystem DFLT TRK;' to include them, or use 'exclude_dflt' to silence this warning",
+                "'DFLT TRK' is not declared with a 'languagesystem' statement; features that are not registered for it will not be applied",
+            ]
+        );
+        // registered, or explicitly excluded: nothing is dropped unexpectedly
+        assert!(warnings(
+            "languagesystem DFLT dflt;\n\
+            languagesystem DFLT TRK;\n\
+            feature locl { sub a by b; language TRK; sub b by c; } locl;\n"
+        )
+        .is_empty());
+        assert!(warnings(
+            "languagesystem DFLT dflt;\n\
+            languagesystem DFLT TRK;\n\
+            feature locl { sub a by b; language TRK exclude_dflt; sub b by c; } locl;\n"
+        )
+        .is_empty());
+        // latn TRK inherits through the registered 'latn dflt'
+        assert!(warnings(
+            "languagesystem DFLT dflt;\n\
+            languagesystem latn dflt;\n\
+            languagesystem latn TRK;\n\
+            feature locl { sub a by b; script latn; language TRK; sub b by c; } locl;\n"
+        )
+        .is_empty());
+        // no default rules, so nothing to drop
+        assert!(warnings(
+            "languagesystem DFLT dflt;\n\
+            languagesystem DFLT TRK;\n\
+            feature locl { language TRK; sub b by c; } locl;\n"
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn duplicate_substitutions() {
+        let glyphs = [".notdef", "a", "b", "c", "d", "f", "i", "f_i", "fi"];
+        let glyph_map: GlyphMap = glyphs.iter().copied().map(GlyphName::new).collect();
+        let fea = "\
+            lookup single {\n\
+                sub a by b;\n\
+                sub a by b;\n\
+                sub [a c] by [c d];\n\
+                subtable;\n\
+                sub a by d;\n\
+            } single;\n\
+            lookup lig {\n\
+                sub f i by f_i;\n\
+                sub [f] i by fi;\n\
+            } lig;\n\
+            lookup alt {\n\
+                sub a from [b c];\n\
+                sub a from [c d];\n\
+            } alt;\n";
+        let compilation = compile_str(fea, &glyphs);
+        let texts = compilation
+            .warnings
+            .iter()
+            .map(Diagnostic::text)
+            .collect::<Vec<_>>();
+        // the exact duplicate, and the rule after the subtable break, are fine
+        assert_eq!(
+            texts,
+            [
+                "'a' is already substituted by an earlier rule in this lookup (at test.fea:2); this rule is ignored for it",
+                "'f i' is already substituted by an earlier rule in this lookup (at test.fea:9); this rule is ignored for it",
+                "'a' is already substituted by an earlier rule in this lookup (at test.fea:13); this rule is ignored for it",
+            ]
+        );
+        // the first rule wins
+        let lookup = |idx| {
+            compilation
+                .lookup_to_fea(tags::GSUB, idx, &glyph_map)
+                .unwrap()
+        };
+        assert_eq!(
+            lookup(0),
+            "lookup GSUB_0 {\n    sub a by b;\n    sub c by d;\n    subtable;\n    sub a by d;\n} GSUB_0;\n"
+        );
+        assert_eq!(
+            lookup(1),
+            "lookup GSUB_1 {\n    sub f i by f_i;\n} GSUB_1;\n"
+        );
+        assert_eq!(
+            lookup(2),
+            "lookup GSUB_2 {\n    sub a from [b c];\n} GSUB_2;\n"
+        );
+    }
+
+    #[test]
+    fn empty_named_lookup_references() {
+        let fea = "\
+            lookup empty {\n\
+            } empty;\n\
+            feature liga {\n\
+                lookup empty;\n\
+                sub a' lookup empty b;\n\
+            } liga;\n";
+        let compilation = compile_str(fea, &[".notdef", "a", "b"]);
+        assert!(compilation.gsub.is_some());
+        let texts = compilation
+            .warnings
+            .iter()
+            .map(Diagnostic::text)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            texts,
+            [
+                "lookup 'empty' contains no rules, and will be ignored",
+                "lookup 'empty' contains no rules, this reference will be ignored",
+                "lookup 'empty' contains no rules, this reference will be ignored",
+            ]
+        );
+    }
+
+    #[test]
+    fn class_pairing_order() {
+        let glyphs = [
+            ".notdef", "a", "b", "c", "a.sc", "b.sc", "c.sc", "A", "B", "C",
+        ];
+        let warnings = |fea| {
+            compile_str(fea, &glyphs)
+                .warnings
+                .iter()
+                .map(|w| w.text().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert!(warnings("feature smcp { sub [a - c] by [a.sc b.sc c.sc]; } smcp;").is_empty());
+        // no name relationship, so no way to guess intent
+        assert!(warnings("feature test { sub [a b c] by [C A B]; } test;").is_empty());
+        assert_eq!(
+            warnings("feature smcp { sub [a b c] by [b.sc a.sc c.sc]; } smcp;"),
+            [
+                "'a' is paired with 'b.sc', but 'a.sc' is at position 2 of the replacement class; \
+             glyphs in class-to-class substitutions are paired by their position in each class, \
+             after ranges are expanded"
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_sources() {
+        for fea in ["", "   \n\t\n", "# just a comment\n\n#another\n"] {
+            let compilation = compile_str(fea, &[".notdef", "a"]);
+            assert!(compilation.warnings.is_empty(), "{fea:?}");
+            assert!(compilation.is_empty(), "{fea:?}");
+        }
+    }
+
+    #[test]
+    fn name_ids_are_deterministic() {
+        let fea = "\
+            table STAT {\n\
+                ElidedFallbackName { name \"Regular\"; };\n\
+                DesignAxis wght 0 { name \"Weight\"; };\n\
+                AxisValue { location wght 400; name \"Regular\"; };\n\
+            } STAT;\n\
+            feature ss02 { featureNames { name \"Two\"; }; sub a by b; } ss02;\n\
+            feature cv01 { cvParameters { FeatUILabelNameID { name \"Cv\"; }; }; sub a by b; } cv01;\n\
+            feature ss01 { featureNames { name \"One\"; }; sub a by b; } ss01;\n\
+            feature ss03 { featureNames { name \"Three\"; }; sub a by b; } ss03;\n";
+        let glyphs = [".notdef", "a", "b"];
+        let first = compile_str(fea, &glyphs);
+        let name = first.name.as_ref().unwrap();
+        let ids = name
+            .name_record
+            .iter()
+            .map(|rec| (rec.name_id.to_u16(), rec.string.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            [
+                (256, "Regular"),
+                (257, "Weight"),
+                (258, "Regular"),
+                (259, "One"),
+                (260, "Two"),
+                (261, "Three"),
+                (262, "Cv"),
+            ]
+        );
+
+        let first = first.to_font_builder().unwrap().build();
+        for _ in 0..5 {
+            let next = compile_str(fea, &glyphs).to_font_builder().unwrap().build();
+            assert_eq!(first, next);
+        }
+    }
+
+    #[test]
+    fn stylistic_set_and_character_variant_params() {
+        let fea = "\
+            feature ss01 { featureNames { name \"Alt a\"; name 3 1 0x0407 \"Alternatives a\"; }; sub a by b; } ss01;\n\
+            feature cv01 {\n\
+                cvParameters {\n\
+                    FeatUILabelNameID { name \"Cv a\"; };\n\
+                    SampleTextNameID { name \"aaa\"; };\n\
+                    ParamUILabelNameID { name \"first\"; };\n\
+                    ParamUILabelNameID { name \"second\"; };\n\
+                    Character 0x61;\n\
+                    Character 0x62;\n\
+                };\n\
+                sub a from [b c];\n\
+            } cv01;\n";
+        let compilation = compile_str(fea, &[".notdef", "a", "b", "c"]);
+        let names = compilation
+            .name
+            .as_ref()
+            .unwrap()
+            .name_record
+            .iter()
+            .map(|rec| (rec.name_id.to_u16(), rec.string.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                // records are sorted by language id
+                (256, "Alternatives a"),
+                (256, "Alt a"),
+                (257, "Cv a"),
+                (258, "aaa"),
+                (259, "first"),
+                (260, "second"),
+            ]
+        );
+
+        let gsub = compilation.gsub.as_ref().unwrap();
+        let params = |tag: &[u8; 4]| {
+            gsub.feature_list
+                .feature_records
+                .iter()
+                .find(|rec| rec.feature_tag == Tag::new(tag))
+                .and_then(|rec| rec.feature.feature_params.as_ref())
+                .cloned()
+                .unwrap()
+        };
+        let tables::layout::FeatureParams::StylisticSet(ss01) = params(b"ss01") else {
+            panic!("expected stylistic set params");
+        };
+        assert_eq!(ss01.ui_name_id.to_u16(), 256);
+        let tables::layout::FeatureParams::CharacterVariant(cv01) = params(b"cv01") else {
+            panic!("expected character variant params");
+        };
+        assert_eq!(cv01.feat_ui_label_name_id.to_u16(), 257);
+        assert_eq!(cv01.feat_ui_tooltip_text_name_id.to_u16(), 0);
+        assert_eq!(cv01.sample_text_name_id.to_u16(), 258);
+        assert_eq!(cv01.num_named_parameters, 2);
+        assert_eq!(cv01.first_param_ui_label_name_id.to_u16(), 259);
+        assert_eq!(
+            cv01.character
+                .iter()
+                .map(|c| c.to_u32())
+                .collect::<Vec<_>>(),
+            [0x61, 0x62]
+        );
+    }
+
+    #[test]
+    fn size_feature_params() {
+        let fea = "\
+            feature ss01 { featureNames { name \"Alt a\"; }; sub a by b; } ss01;\n\
+            feature size {\n\
+                parameters 10.0 3 80 139;\n\
+                sizemenuname \"Text\";\n\
+                sizemenuname 1 \"Text (Mac)\";\n\
+            } size;\n";
+        let compilation = compile_str(fea, &[".notdef", "a", "b"]);
+        let names = compilation
+            .name
+            .as_ref()
+            .unwrap()
+            .name_record
+            .iter()
+            .map(|rec| (rec.name_id.to_u16(), rec.string.as_str()))
+            .collect::<Vec<_>>();
+        // the size feature's names are allocated first
+        assert_eq!(names, [(256, "Text (Mac)"), (256, "Text"), (257, "Alt a")]);
+
+        let gpos = compilation.gpos.as_ref().unwrap();
+        let size = gpos
+            .feature_list
+            .feature_records
+            .iter()
+            .find(|rec| rec.feature_tag == tags::SIZE)
+            .and_then(|rec| rec.feature.feature_params.as_ref())
+            .cloned()
+            .unwrap();
+        let tables::layout::FeatureParams::Size(size) = size else {
+            panic!("expected size params");
+        };
+        assert_eq!(
+            (
+                size.design_size,
+                size.identifier,
+                size.name_entry,
+                size.range_start,
+                size.range_end
+            ),
+            (100, 3, 256, 80, 139)
+        );
+    }
+
+    #[test]
+    fn anon_blocks_are_passed_through() {
+        let fea = "\
+            anon sbit {\n  72 % dpi\n  sizes { 10, 12 } # } sbit;\n} sbit;\n\
+            feature test { sub a by b; } test;\n\
+            anon foo {} foo;\n";
+        let compilation = compile_str(fea, &[".notdef", "a", "b"]);
+        let blocks = compilation
+            .anon_blocks
+            .iter()
+            .map(|block| (block.label.as_str(), block.contents.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            blocks,
+            [
+                ("sbit", "\n  72 % dpi\n  sizes { 10, 12 } # } sbit;\n"),
+                ("foo", "")
+            ]
+        );
+        let first = &compilation.anon_blocks[0];
+        assert_eq!(first.range.start, 0);
+        assert!(fea[first.range.clone()].ends_with("} sbit;"));
+    }
+
+    #[test]
+    fn feature_variations_ordered_by_first_use() {
+        let fea = "\
+            conditionset LIGHT { wght 200 300; } LIGHT;\n\
+            conditionset WIDE { wdth 150 200; } WIDE;\n\
+            conditionset HEAVY { wght 700 900; } HEAVY;\n\
+            lookup swap { sub a by b; } swap;\n\
+            variation rvrn HEAVY { lookup swap; } rvrn;\n\
+            variation rvrn NULL { lookup swap; } rvrn;\n\
+            variation rvrn LIGHT { lookup swap; } rvrn;\n\
+            variation rvrn HEAVY { sub b by a; } rvrn;\n\
+            variation rvrn WIDE { lookup swap; } rvrn;\n";
+        let var_info = crate::compile::MockVariationInfo::new(&[
+            ("wght", 200, 200, 1000),
+            ("wdth", 100, 100, 200),
+        ]);
+        let compilation = compile_str_impl(
+            fea,
+            &[".notdef", "a", "b"],
+            Default::default(),
+            Some(&var_info),
+        );
+        // (axis index, min) of the first condition in each record
+        let conditions = compilation
+            .gsub
+            .as_ref()
+            .unwrap()
+            .feature_variations
+            .as_ref()
+            .unwrap()
+            .feature_variation_records
+            .iter()
+            .map(|record| {
+                record.condition_set.as_ref().map(|set| {
+                    let condition = &set.conditions[0];
+                    (condition.axis_index, condition.filter_range_min_value)
+                })
+            })
+            .collect::<Vec<_>>();
+        let norm = |val: f32| write_fonts::types::F2Dot14::from_f32(val);
+        assert_eq!(
+            conditions,
+            [
+                Some((0, norm(0.625))),
+                None,
+                Some((0, norm(0.0))),
+                Some((1, norm(0.5))),
+            ]
+        );
+    }
+
+    // the subtables of the first chain contextual lookup in the GSUB table
+    fn gsub_chain_subtables(
+        compilation: &Compilation,
+    ) -> Vec<tables::layout::ChainedSequenceContext> {
+        compilation
+            .gsub
+            .as_ref()
+            .unwrap()
+            .lookup_list
+            .lookups
+            .iter()
+            .find_map(|lookup| match &**lookup {
+                tables::gsub::SubstitutionLookup::ChainContextual(lookup) => Some(
+                    lookup
+                        .subtables
+                        .iter()
+                        .map(|sub| sub.as_inner().clone())
+                        .collect(),
+                ),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    fn gpos_pair_subtables(compilation: &Compilation) -> Vec<tables::gpos::PairPos> {
+        let gpos = compilation.gpos.as_ref().unwrap();
+        match &*gpos.lookup_list.lookups[0] {
+            tables::gpos::PositionLookup::Pair(lookup) => {
+                lookup.subtables.iter().map(|sub| (**sub).clone()).collect()
+            }
+            _ => panic!("expected a pair pos lookup"),
+        }
+    }
+
+    #[test]
+    fn variable_pair_value_record() {
+        let var_info = crate::compile::MockVariationInfo::new(&[("wght", 200, 200, 1000)]);
+        let compilation = compile_str_impl(
+            "feature kern { pos a b (wght=200:-10 wght=1000:-30); } kern;",
+            &[".notdef", "a", "b"],
+            Default::default(),
+            Some(&var_info),
+        );
+        let subtables = gpos_pair_subtables(&compilation);
+        let [tables::gpos::PairPos::Format1(subtable)] = subtables.as_slice() else {
+            panic!("expected a single glyph pair subtable: {subtables:?}");
+        };
+        let record = &subtable.pair_sets[0].pair_value_records[0].value_record1;
+        // the default location is the default value
+        assert_eq!(record.x_advance, Some(-10));
+        assert_eq!(
+            record.x_advance_device.as_ref(),
+            Some(&tables::layout::DeviceOrVariationIndex::variation_index(
+                0, 0
+            ))
+        );
+        // and the delta lives in the GDEF variation store
+        let var_store = compilation
+            .gdef
+            .as_ref()
+            .and_then(|gdef| gdef.item_var_store.as_ref())
+            .expect("variation store is written to GDEF");
+        assert_eq!(var_store.item_variation_data.len(), 1);
+        assert_eq!(
+            var_store.item_variation_data[0]
+                .as_ref()
+                .unwrap()
+                .item_count,
+            1
+        );
+    }
+
+    #[test]
+    fn variable_mark_to_base_anchors() {
+        let var_info = crate::compile::MockVariationInfo::new(&[("wght", 200, 200, 1000)]);
+        let compilation = compile_str_impl(
+            "markClass acutecomb <anchor 0 (wght=200:500 wght=1000:600)> @TOP;\n\
+            feature mark { pos base a <anchor (wght=200:250 wght=1000:300) 500> mark @TOP; } mark;",
+            &[".notdef", "a", "acutecomb"],
+            Default::default(),
+            Some(&var_info),
+        );
+        let gpos = compilation.gpos.as_ref().unwrap();
+        let tables::gpos::PositionLookup::MarkToBase(lookup) = &*gpos.lookup_list.lookups[0] else {
+            panic!("expected a mark-to-base lookup");
+        };
+        let tables::gpos::MarkBasePosFormat1 {
+            mark_array,
+            base_array,
+            ..
+        } = &*lookup.subtables[0];
+        let mark_anchor = &*mark_array.mark_records[0].mark_anchor;
+        let base_anchor = base_array.base_records[0].base_anchors[0].as_ref().unwrap();
+        let (
+            tables::gpos::AnchorTable::Format3(mark_anchor),
+            tables::gpos::AnchorTable::Format3(base_anchor),
+        ) = (mark_anchor, base_anchor)
+        else {
+            panic!("expected format 3 anchors: {mark_anchor:?} {base_anchor:?}");
+        };
+
+        // only the variable coordinates get a device
+        assert_eq!(
+            (mark_anchor.x_coordinate, mark_anchor.y_coordinate),
+            (0, 500)
+        );
+        assert!(mark_anchor.x_device.is_none());
+        assert!(mark_anchor.y_device.is_some());
+        assert_eq!(
+            (base_anchor.x_coordinate, base_anchor.y_coordinate),
+            (250, 500)
+        );
+        assert!(base_anchor.x_device.is_some());
+        assert!(base_anchor.y_device.is_none());
+
+        // both deltas are in the same variation store, in GDEF
+        let var_store = compilation
+            .gdef
+            .as_ref()
+            .and_then(|gdef| gdef.item_var_store.as_ref())
+            .unwrap();
+        let item_count = var_store
+            .item_variation_data
+            .iter()
+            .map(|data| data.as_ref().unwrap().item_count)
+            .sum::<u16>();
+        assert_eq!(item_count, 2);
+    }
+
+    #[test]
+    fn value_record_devices() {
+        let compilation = compile_str(
+            "feature kern {\n\
+                pos a <1 0 0 0 <device 11 -1, 13 2> <device NULL> <device NULL> <device NULL>>;\n\
+            } kern;",
+            &[".notdef", "a"],
+        );
+        let gpos = compilation.gpos.as_ref().unwrap();
+        let tables::gpos::PositionLookup::Single(lookup) = &*gpos.lookup_list.lookups[0] else {
+            panic!("expected a single pos lookup");
+        };
+        let tables::gpos::SinglePos::Format1(subtable) = &*lookup.subtables[0] else {
+            panic!("expected a format 1 subtable");
+        };
+        let record = &subtable.value_record;
+        assert_eq!(record.x_placement, Some(1));
+        assert!(record.y_placement_device.is_none());
+        // the missing size is filled in with a zero
+        assert_eq!(
+            record.x_placement_device.as_ref(),
+            Some(&tables::layout::DeviceOrVariationIndex::device(
+                11,
+                13,
+                &[-1, 0, 2]
+            ))
+        );
+    }
+
+    #[test]
+    fn mark_attachment_type_with_mark_classes() {
+        let compilation = compile_str(
+            "markClass [acute grave] <anchor 0 500> @TOP;\n\
+            markClass cedilla <anchor 0 0> @BOTTOM;\n\
+            feature test {\n\
+                lookup top { lookupflag MarkAttachmentType @TOP; pos a 10; } top;\n\
+                lookup bottom { lookupflag MarkAttachmentType @BOTTOM; pos a 20; } bottom;\n\
+                lookup top2 { lookupflag MarkAttachmentType [grave acute]; pos a 30; } top2;\n\
+            } test;",
+            &[".notdef", "a", "acute", "grave", "cedilla"],
+        );
+        let gpos = compilation.gpos.as_ref().unwrap();
+        let flags = gpos
+            .lookup_list
+            .lookups
+            .iter()
+            .map(|lookup| match &**lookup {
+                tables::gpos::PositionLookup::Single(lookup) => {
+                    lookup.lookup_flag.mark_attachment_type_mask()
+                }
+                _ => panic!("expected single pos lookups"),
+            })
+            .collect::<Vec<_>>();
+        // an identical glyph set reuses the same class
+        assert_eq!(flags, [Some(1), Some(2), Some(1)]);
+        let classes = compilation.gdef.as_ref().unwrap();
+        let classes = classes.mark_attach_class_def.as_ref().unwrap();
+        assert_eq!(
+            classes.iter().collect::<Vec<_>>(),
+            [
+                (GlyphId::new(2), 1),
+                (GlyphId::new(3), 1),
+                (GlyphId::new(4), 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn ligature_caret_by_device() {
+        let compilation = compile_str(
+            "table GDEF {\n\
+                LigatureCaretByDev f_i 300 <device 11 -1, 12 -1>;\n\
+                LigatureCaretByDev f_l 400 <device 12 1> 200 <device NULL>;\n\
+            } GDEF;",
+            &[".notdef", "f_i", "f_l"],
+        );
+        let gdef = compilation.gdef.as_ref().unwrap();
+        let lig_carets = gdef.lig_caret_list.as_ref().unwrap();
+        assert_eq!(lig_carets.coverage.len(), 2);
+        let f_i = &lig_carets.lig_glyphs[0].caret_values;
+        assert_eq!(
+            *f_i[0],
+            CaretValue::format_3(
+                300,
+                tables::layout::DeviceOrVariationIndex::device(11, 12, &[-1, -1])
+            )
+        );
+        // carets are sorted, and a null device is a plain coordinate
+        let f_l = &lig_carets.lig_glyphs[1].caret_values;
+        assert_eq!(*f_l[0], CaretValue::format_1(200));
+        assert_eq!(
+            *f_l[1],
+            CaretValue::format_3(
+                400,
+                tables::layout::DeviceOrVariationIndex::device(12, 12, &[1])
+            )
+        );
+    }
+
+    #[test]
+    fn device_entries_are_sorted() {
+        let compilation = compile_str(
+            "table GDEF {\n\
+                LigatureCaretByDev f_i 300 <device 13 1, 11 -1>;\n\
+            } GDEF;",
+            &[".notdef", "f_i"],
+        );
+        let gdef = compilation.gdef.as_ref().unwrap();
+        let f_i = &gdef.lig_caret_list.as_ref().unwrap().lig_glyphs[0].caret_values;
+        assert_eq!(
+            *f_i[0],
+            CaretValue::format_3(
+                300,
+                tables::layout::DeviceOrVariationIndex::device(11, 13, &[-1, 0, 1])
+            )
+        );
+    }
+
+    #[test]
+    fn explicit_gdef_keeps_collected_data() {
+        let var_info = crate::compile::MockVariationInfo::new(&[("wght", 200, 200, 1000)]);
+        let compilation = compile_str_impl(
+            "feature kern {\n\
+                lookupflag UseMarkFilteringSet [acute grave];\n\
+                pos a b (wght=200:-10 wght=1000:-30);\n\
+            } kern;\n\
+            table GDEF {\n\
+                GlyphClassDef [a b], , [acute grave], ;\n\
+            } GDEF;",
+            &[".notdef", "a", "b", "acute", "grave"],
+            Default::default(),
+            Some(&var_info),
+        );
+        assert!(
+            compilation.warnings.is_empty(),
+            "{:?}",
+            compilation.warnings
+        );
+        let gdef = compilation.gdef.as_ref().unwrap();
+        assert!(gdef.glyph_class_def.is_some());
+        assert!(gdef.item_var_store.is_some());
+        let mark_sets = gdef.mark_glyph_sets_def.as_ref().unwrap();
+        assert_eq!(mark_sets.coverages.len(), 1);
+        assert_eq!(
+            mark_sets.coverages[0].iter().collect::<Vec<_>>(),
+            glyph_id_vec([3, 4])
+        );
+    }
+
+    #[test]
+    fn mark_filter_set_with_non_mark_glyphs() {
+        let fea = "\
+            table GDEF {\n\
+                GlyphClassDef [a b], , [acute], ;\n\
+            } GDEF;\n\
+            feature test {\n\
+                lookupflag UseMarkFilteringSet [acute];\n\
+                pos a 10;\n\
+                lookupflag UseMarkFilteringSet [acute b];\n\
+                pos a 20;\n\
+                lookupflag UseMarkFilteringSet [b a acute];\n\
+                pos a 30;\n\
+            } test;";
+        let compilation = compile_str(fea, &[".notdef", "a", "b", "acute"]);
+        let texts = compilation
+            .warnings
+            .iter()
+            .map(Diagnostic::text)
+            .collect::<Vec<_>>();
+        // each set is reported once, for its first non-mark glyph
+        assert_eq!(
+            texts,
+            [
+                "mark filtering set includes glyph 'b', which has GDEF class Base and will not be filtered",
+                "mark filtering set includes glyph 'a', which has GDEF class Base and will not be filtered",
+            ]
+        );
+        let gdef = compilation.gdef.as_ref().unwrap();
+        assert_eq!(
+            gdef.mark_glyph_sets_def.as_ref().unwrap().coverages.len(),
+            3
+        );
+    }
+
+    #[test]
+    fn class_and_glyph_pairs_use_class_subtable() {
+        let compilation = compile_str(
+            "@A = [a b c];\nfeature kern { pos @A d -10; pos e @A 20; pos [f] g 5; } kern;",
+            &[".notdef", "a", "b", "c", "d", "e", "f", "g"],
+        );
+        // each glyph is a singleton class, so no pairs are expanded
+        let subtables = gpos_pair_subtables(&compilation);
+        let [tables::gpos::PairPos::Format2(subtable)] = subtables.as_slice() else {
+            panic!("expected a single class pair subtable: {subtables:?}");
+        };
+        assert_eq!(subtable.coverage.len(), 5);
+        assert_eq!(subtable.class1_records.len(), 3);
+        // class 0, plus one each for 'd', @A and 'g'
+        assert_eq!(subtable.class1_records[0].class2_records.len(), 4);
+    }
+
+    #[test]
+    fn enumerated_class_pairs_are_expanded() {
+        let compilation = compile_str(
+            "@A = [a b c];\nfeature kern { enum pos @A d -10; } kern;",
+            &[".notdef", "a", "b", "c", "d"],
+        );
+        let subtables = gpos_pair_subtables(&compilation);
+        let [tables::gpos::PairPos::Format1(subtable)] = subtables.as_slice() else {
+            panic!("expected a single glyph pair subtable: {subtables:?}");
+        };
+        assert_eq!(subtable.coverage.len(), 3);
+        assert!(subtable
+            .pair_sets
+            .iter()
+            .all(|set| set.pair_value_records.len() == 1));
+    }
+
+    #[test]
+    fn aalt_collects_single_and_alternate_subs() {
+        let compilation = compile_str(
+            "\
+            languagesystem DFLT dflt;\n\
+            languagesystem latn dflt;\n\
+            languagesystem latn TRK;\n\
+            feature aalt { feature smcp; feature salt; } aalt;\n\
+            feature smcp { sub a by a.sc; } smcp;\n\
+            feature salt { sub a from [a.alt a.sc]; sub b by b.alt; } salt;\n",
+            &[".notdef", "a", "b", "a.sc", "a.alt", "b.alt"],
+        );
+        let gsub = compilation.gsub.as_ref().unwrap();
+        // the aalt lookups are inserted at the front of the lookup list:
+        // glyphs with one alternate go in a single sub lookup, and the rest
+        // in an alternate sub lookup
+        let tables::gsub::SubstitutionLookup::Single(_) = &*gsub.lookup_list.lookups[0] else {
+            panic!(
+                "expected a single lookup: {:?}",
+                gsub.lookup_list.lookups[0]
+            );
+        };
+        let tables::gsub::SubstitutionLookup::Alternate(lookup) = &*gsub.lookup_list.lookups[1]
+        else {
+            panic!(
+                "expected an alternate lookup: {:?}",
+                gsub.lookup_list.lookups[1]
+            );
+        };
+        let sub = &lookup.subtables[0];
+        // the duplicate 'a.sc' is only included once, in the order it was seen
+        let alts = sub
+            .alternate_sets
+            .iter()
+            .map(|set| {
+                set.alternate_glyph_ids
+                    .iter()
+                    .map(|gid| gid.to_u16())
+                    .collect()
+            })
+            .collect::<Vec<Vec<_>>>();
+        assert_eq!(alts, [vec![3, 4]]);
+
+        // and the feature is registered for every language system
+        let aalt_indices = gsub
+            .feature_list
+            .feature_records
+            .iter()
+            .enumerate()
+            .filter_map(|(i, rec)| (rec.feature_tag == tags::AALT).then_some(i as u16))
+            .collect::<Vec<_>>();
+        let lang_systems = gsub
+            .script_list
+            .script_records
+            .iter()
+            .flat_map(|script| {
+                let script = &script.script;
+                script
+                    .default_lang_sys
+                    .as_ref()
+                    .into_iter()
+                    .chain(script.lang_sys_records.iter().map(|rec| &*rec.lang_sys))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(lang_systems.len(), 3);
+        for lang_sys in lang_systems {
+            assert!(lang_sys
+                .feature_indices
+                .iter()
+                .any(|idx| aalt_indices.contains(idx)));
+        }
+    }
+
+    fn gsub_context_subtables(compilation: &Compilation) -> Vec<tables::layout::SequenceContext> {
+        compilation
+            .gsub
+            .as_ref()
+            .unwrap()
+            .lookup_list
+            .lookups
+            .iter()
+            .find_map(|lookup| match &**lookup {
+                tables::gsub::SubstitutionLookup::Contextual(lookup) => Some(
+                    lookup
+                        .subtables
+                        .iter()
+                        .map(|sub| sub.as_inner().clone())
+                        .collect(),
+                ),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn chained_context_with_inline_multiple_sub() {
+        let compilation = compile_str(
+            "feature test { sub a' b by c d e; } test;",
+            &[".notdef", "a", "b", "c", "d", "e"],
+        );
+        let gsub = compilation.gsub.as_ref().unwrap();
+        // the anonymous multiple sub lookup follows the contextual lookup
+        let tables::gsub::SubstitutionLookup::Multiple(multi) = &*gsub.lookup_list.lookups[1]
+        else {
+            panic!("expected a multiple sub lookup");
+        };
+        assert_eq!(
+            multi.subtables[0].sequences[0]
+                .substitute_glyph_ids
+                .iter()
+                .map(|gid| gid.to_u16())
+                .collect::<Vec<_>>(),
+            [3, 4, 5]
+        );
+        let subtables = gsub_chain_subtables(&compilation);
+        let [tables::layout::ChainedSequenceContext::Format3(subtable)] = subtables.as_slice()
+        else {
+            panic!("expected a single format 3 subtable: {subtables:?}");
+        };
+        assert_eq!(subtable.input_coverages.len(), 1);
+        assert_eq!(subtable.lookahead_coverages.len(), 1);
+        let [record] = subtable.seq_lookup_records.as_slice() else {
+            panic!("expected a single lookup record");
+        };
+        assert_eq!((record.sequence_index, record.lookup_list_index), (0, 1));
+    }
+
+    #[test]
+    fn unchained_context_with_inline_ligature() {
+        let compilation = compile_str(
+            "feature test { sub a' b' by c; } test;",
+            &[".notdef", "a", "b", "c"],
+        );
+        let gsub = compilation.gsub.as_ref().unwrap();
+        assert!(matches!(
+            &*gsub.lookup_list.lookups[1],
+            tables::gsub::SubstitutionLookup::Ligature(_)
+        ));
+        let subtables = gsub_context_subtables(&compilation);
+        let [tables::layout::SequenceContext::Format3(sub)] = subtables.as_slice() else {
+            panic!("expected a single format 3 subtable: {subtables:?}");
+        };
+        assert_eq!(coverage_glyphs(&sub.coverages), [vec![1], vec![2]]);
+        assert_eq!(sub.seq_lookup_records.len(), 1);
+        assert_eq!(sub.seq_lookup_records[0].lookup_list_index, 1);
+    }
+
+    #[test]
+    fn unchained_context_with_classes_uses_format_2() {
+        // format 2 only pays for its class def when there are lots of rules
+        // sharing a few classes, so generate a rule for each pair of classes
+        let classes = ["ONE", "TWO", "THREE", "FOUR", "FIVE", "SIX"];
+        let mut fea = String::new();
+        for (i, class) in classes.iter().enumerate() {
+            let glyphs = (0..4)
+                .map(|j| char::from(b'a' + (i + j * classes.len()) as u8).to_string())
+                .collect::<Vec<_>>();
+            fea.push_str(&format!("@{class} = [{}];\n", glyphs.join(" ")));
+        }
+        fea.push_str("lookup swap { sub [a - x] by z; } swap;\nfeature test {\n");
+        for first in classes {
+            for second in classes {
+                fea.push_str(&format!("sub @{first}' lookup swap @{second}';\n"));
+            }
+        }
+        fea.push_str("} test;\n");
+        let glyphs = [
+            ".notdef", "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o",
+            "p", "q", "r", "s", "t", "u", "v", "w", "x", "z",
+        ];
+        let compilation = compile_str(fea.leak(), &glyphs);
+        let subtables = gsub_context_subtables(&compilation);
+        let [tables::layout::SequenceContext::Format2(sub)] = subtables.as_slice() else {
+            panic!("expected a single format 2 subtable: {subtables:?}");
+        };
+        assert_eq!(sub.coverage.len(), 24);
+        let rule_counts = sub
+            .class_seq_rule_sets
+            .iter()
+            .map(|set| set.as_ref().map(|set| set.class_seq_rules.len()))
+            .collect::<Vec<_>>();
+        // class 0 is unused
+        assert_eq!(
+            rule_counts,
+            [None, Some(6), Some(6), Some(6), Some(6), Some(6), Some(6)]
+        );
+    }
+
+    fn coverage_glyphs(coverages: &[OffsetMarker<CoverageTable>]) -> Vec<Vec<u16>> {
+        coverages
+            .iter()
+            .map(|cov| cov.iter().map(GlyphId::to_u16).collect())
+            .collect()
+    }
+
+    #[test]
+    fn overlapping_named_classes_in_context() {
+        // the backtrack classes overlap, so this can't use format 2
+        let fea = "\
+            @LETTERS = [a b c d];\n\
+            @VOWELS = [a e];\n\
+            lookup upper { sub [a b c d e] by [A B C D E]; } upper;\n\
+            feature test {\n\
+                sub @LETTERS [b c]' lookup upper @VOWELS;\n\
+                sub @VOWELS d' lookup upper @LETTERS @LETTERS;\n\
+                sub @LETTERS b' lookup upper @LETTERS;\n\
+            } test;\n";
+        let glyphs = [".notdef", "a", "b", "c", "d", "e", "A", "B", "C", "D", "E"];
+        let compilation = compile_str(fea, &glyphs);
+        let subtables = gsub_chain_subtables(&compilation);
+        let coverages = subtables
+            .iter()
+            .map(|sub| match sub {
+                tables::layout::ChainedSequenceContext::Format3(sub) => (
+                    coverage_glyphs(&sub.backtrack_coverages),
+                    coverage_glyphs(&sub.input_coverages),
+                    coverage_glyphs(&sub.lookahead_coverages),
+                ),
+                _ => panic!("expected format 3"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            coverages,
+            [
+                (vec![vec![1, 2, 3, 4]], vec![vec![2, 3]], vec![vec![1, 5]]),
+                (
+                    vec![vec![1, 5]],
+                    vec![vec![4]],
+                    vec![vec![1, 2, 3, 4], vec![1, 2, 3, 4]]
+                ),
+                (
+                    vec![vec![1, 2, 3, 4]],
+                    vec![vec![2]],
+                    vec![vec![1, 2, 3, 4]]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn overlapping_named_classes_in_class_literals_in_context() {
+        let fea = "\
+            @LETTERS = [a b c d];\n\
+            @VOWELS = [a e];\n\
+            lookup upper { sub [a b c d e] by [A B C D E]; } upper;\n\
+            feature test {\n\
+                sub [@VOWELS @LETTERS] b' lookup upper [@LETTERS e];\n\
+                sub [@VOWELS @LETTERS] c' lookup upper @VOWELS;\n\
+            } test;\n";
+        let glyphs = [".notdef", "a", "b", "c", "d", "e", "A", "B", "C", "D", "E"];
+        let compilation = compile_str(fea, &glyphs);
+        let subtables = gsub_chain_subtables(&compilation);
+        let coverages = subtables
+            .iter()
+            .map(|sub| match sub {
+                tables::layout::ChainedSequenceContext::Format3(sub) => (
+                    coverage_glyphs(&sub.backtrack_coverages),
+                    coverage_glyphs(&sub.lookahead_coverages),
+                ),
+                _ => panic!("expected format 3"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            coverages,
+            [
+                (vec![vec![1, 2, 3, 4, 5]], vec![vec![1, 2, 3, 4, 5]]),
+                (vec![vec![1, 2, 3, 4, 5]], vec![vec![1, 5]]),
+            ]
+        );
+    }
+
+    #[test]
+    fn named_lookup_rules_share_one_lookup() {
+        let fea = "\
+            lookup delete {\n\
+                sub a by b;\n\
+                sub c by NULL;\n\
+            } delete;\n\
+            lookup context {\n\
+                ignore sub a b';\n\
+                sub a' c by d;\n\
+            } context;\n\
+            feature test {\n\
+                lookup delete;\n\
+                lookup context;\n\
+            } test;\n";
+        let glyphs = [".notdef", "a", "b", "c", "d"];
+        let compilation = compile_str(fea, &glyphs);
+        let lookups = &compilation.gsub.as_ref().unwrap().lookup_list.lookups;
+        // the context lookup also has an anonymous single sub lookup
+        assert_eq!(lookups.len(), 3);
+        let tables::gsub::SubstitutionLookup::Multiple(delete) = &*lookups[0] else {
+            panic!("expected multiple substitution lookup");
+        };
+        let sequences = delete.subtables[0]
+            .sequences
+            .iter()
+            .map(|seq| seq.substitute_glyph_ids.len())
+            .collect::<Vec<_>>();
+        assert_eq!(sequences, [1, 0]);
+        let n_chain_lookups = lookups
+            .iter()
+            .filter(|lookup| {
+                matches!(
+                    &***lookup,
+                    tables::gsub::SubstitutionLookup::ChainContextual(_)
+                )
+            })
+            .count();
+        assert_eq!(n_chain_lookups, 1);
+    }
+
+    #[test]
+    fn ignore_rules_keep_their_order_with_overlapping_rules() {
+        // the input classes overlap, so each rule gets its own subtable
+        let fea = "\
+            feature test {\n\
+                ignore sub a' b, c' d;\n\
+                sub [a c]' [b d] by e;\n\
+                ignore sub a c;\n\
+            } test;\n";
+        let glyphs = [".notdef", "a", "b", "c", "d", "e"];
+        let compilation = compile_str(fea, &glyphs);
+        let subtables = gsub_chain_subtables(&compilation);
+        let rules = subtables
+            .iter()
+            .map(|sub| match sub {
+                tables::layout::ChainedSequenceContext::Format3(sub) => (
+                    coverage_glyphs(&sub.input_coverages),
+                    coverage_glyphs(&sub.lookahead_coverages),
+                    sub.seq_lookup_records.len(),
+                ),
+                _ => panic!("expected format 3"),
+            })
+            .collect::<Vec<_>>();
+        // an unmarked ignore rule uses its first glyph as input
+        assert_eq!(
+            rules,
+            [
+                (vec![vec![1]], vec![vec![2]], 0),
+                (vec![vec![3]], vec![vec![4]], 0),
+                (vec![vec![1, 3]], vec![vec![2, 4]], 1),
+                (vec![vec![1]], vec![vec![3]], 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn ignore_and_positive_rules_share_rule_sets() {
+        let fea = "\
+            feature test {\n\
+                ignore sub b a' c;\n\
+                sub a' c by d;\n\
+                ignore sub c' a;\n\
+            } test;\n";
+        let glyphs = [".notdef", "a", "b", "c", "d"];
+        let compilation = compile_str(fea, &glyphs);
+        let subtables = gsub_chain_subtables(&compilation);
+        let [tables::layout::ChainedSequenceContext::Format1(subtable)] = subtables.as_slice()
+        else {
+            panic!("expected a single format 1 subtable: {subtables:?}");
+        };
+        let rule_sets = subtable
+            .chained_seq_rule_sets
+            .iter()
+            .map(|set| {
+                set.as_ref()
+                    .unwrap()
+                    .chained_seq_rules
+                    .iter()
+                    .map(|rule| (rule.backtrack_sequence.len(), rule.seq_lookup_records.len()))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        // rules for the same glyph are tried in source order
+        assert_eq!(rule_sets, [vec![(1, 0), (0, 1)], vec![(0, 0)]]);
+    }
+
+    #[test]
+    fn os2_vendor_is_padded() {
+        let compilation = compile_str(r#"table OS/2 { Vendor "AB"; } OS/2;"#, &[".notdef"]);
+        assert_eq!(compilation.os2.unwrap().ach_vend_id, Tag::new(b"AB  "));
+    }
+
+    // parse and compile `count` standalone lookups, followed by `rest`
+    fn compile_many_lookups(count: usize, rest: &str) -> Result<Compilation, Vec<Diagnostic>> {
+        let mut fea = String::from("languagesystem DFLT dflt;\n");
+        for i in 0..count {
+            fea.push_str(&format!("lookup l{i} {{ sub a by b; }} l{i};\n"));
+        }
+        fea.push_str(rest);
+        let fea: Arc<str> = fea.into();
+        let glyph_map: GlyphMap = [".notdef", "a", "b", "c"]
+            .into_iter()
+            .map(GlyphName::new)
+            .collect();
+        let resolver = move |_: &std::ffi::OsStr| Ok(fea.clone());
+        let (tree, diagnostics) = ParseContext::parse(
+            "test.fea".into(),
+            Some(&glyph_map),
+            &Default::default(),
+            &Default::default(),
+            Box::new(resolver),
+        )
+        .unwrap()
+        .generate_parse_tree();
+        assert!(
+            !diagnostics.iter().any(Diagnostic::is_error),
+            "{diagnostics:?}"
+        );
+        let mut ctx = CompilationCtx::new(&glyph_map, tree.source_map(), &tree.sources, None);
+        ctx.compile(&tree.typed_root());
+        ctx.build()
+    }
+
+    fn single_error(result: Result<Compilation, Vec<Diagnostic>>) -> String {
+        let errors = result
+            .err()
+            .unwrap()
+            .into_iter()
+            .filter(Diagnostic::is_error)
+            .collect::<Vec<_>>();
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        errors[0].text().to_owned()
+    }
+
+    #[test]
+    fn lookup_limits() {
+        let compilation = compile_many_lookups(MAX_LOOKUPS, "").unwrap();
+        assert_eq!(
+            compilation.gsub.unwrap().lookup_list.lookups.len(),
+            MAX_LOOKUPS
+        );
+
+        assert_eq!(
+            single_error(compile_many_lookups(MAX_LOOKUPS + 1, "")),
+            "too many GSUB lookups: lookup 'l65535' needs lookup index 65535, but the maximum is 65534"
+        );
+
+        // the anonymous lookup for the inline rule is one too many
+        let calt = "feature calt { sub a' b by c; } calt;";
+        assert!(compile_many_lookups(MAX_LOOKUPS - 2, calt).is_ok());
+        assert_eq!(
+            single_error(compile_many_lookups(MAX_LOOKUPS - 1, calt)),
+            "too many GSUB lookups: rule in feature 'calt' needs lookup index 65535, but the maximum is 65534"
+        );
+
+        // the aalt lookup is added at the end of compilation
+        let aalt = "feature salt { sub a by c; } salt;\nfeature aalt { feature salt; } aalt;";
+        assert!(compile_many_lookups(MAX_LOOKUPS - 2, aalt).is_ok());
+        assert_eq!(
+            single_error(compile_many_lookups(MAX_LOOKUPS - 1, aalt)),
+            "too many GSUB lookups: feature 'aalt' needs lookup index 65535, but the maximum is 65534"
+        );
+    }
+
+    #[test]
+    fn lookup_limits_lenient() {
+        let glyph_map: GlyphMap = [".notdef", "a", "b"]
+            .into_iter()
+            .map(GlyphName::new)
+            .collect();
+        let mut fea = String::new();
+        // the index of the last lookup doesn't fit in a u16
+        for i in 0..=MAX_LOOKUPS + 1 {
+            fea.push_str(&format!("lookup l{i} {{ sub a by b; }} l{i};\n"));
+        }
+        fea.push_str("feature test { lookup l65536; } test;\n");
+        fea.push_str("feature kern { pos a b -10; } kern;\n");
+        let fea: Arc<str> = fea.into();
+        let compilation = crate::Compiler::new("test.fea", &glyph_map)
+            .with_resolver(move |_: &std::ffi::OsStr| Ok(fea.clone()))
+            .with_lenient_mode(true)
+            .compile()
+            .unwrap();
+        let errors = compilation
+            .errors
+            .iter()
+            .map(Diagnostic::text)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            ["too many GSUB lookups: lookup 'l65535' needs lookup index 65535, but the maximum is 65534"]
+        );
+        assert_eq!(
+            compilation.errors[0].code,
+            Some(crate::DiagnosticCode::CompileError)
+        );
+        assert!(compilation.gsub.is_none());
+        assert!(compilation.gpos.is_none());
+    }
+
+    #[test]
+    fn use_extension() {
+        let compilation = compile_str(
+            "\
+            lookup ext_sub useExtension { sub a by b; } ext_sub;
+            lookup plain_sub { sub b by c; } plain_sub;
+            lookup ext_pos useExtension { pos a b -10; } ext_pos;
+            feature test {
+                lookup ext_sub;
+                lookup plain_sub;
+                lookup ext_pos;
+            } test;
+            ",
+            &[".notdef", "a", "b", "c"],
+        );
+        let gsub = compilation.gsub.unwrap();
+        let tables::gsub::SubstitutionLookup::Extension(ext_sub) = &*gsub.lookup_list.lookups[0]
+        else {
+            panic!("expected extension lookup");
+        };
+        assert!(matches!(
+            &*ext_sub.subtables[0],
+            tables::gsub::ExtensionSubtable::Single(sub) if sub.extension_lookup_type == 1
+        ));
+        assert!(matches!(
+            &*gsub.lookup_list.lookups[1],
+            tables::gsub::SubstitutionLookup::Single(_)
+        ));
+        let gpos = compilation.gpos.unwrap();
+        let tables::gpos::PositionLookup::Extension(ext_pos) = &*gpos.lookup_list.lookups[0] else {
+            panic!("expected extension lookup");
+        };
+        assert!(matches!(
+            &*ext_pos.subtables[0],
+            tables::gpos::ExtensionSubtable::Pair(sub) if sub.extension_lookup_type == 2
+        ));
+
+        // and the wrapped subtables are written correctly
+        use write_fonts::read::FontRead;
+        let data = write_fonts::dump_table(&gsub).unwrap();
+        let gsub = write_fonts::read::tables::gsub::Gsub::read(data.as_slice().into()).unwrap();
+        let lookups = gsub.lookup_list().unwrap().lookups();
+        assert!(matches!(
+            lookups.get(0).unwrap(),
+            write_fonts::read::tables::gsub::SubstitutionLookup::Extension(_)
+        ));
+        assert!(matches!(
+            lookups.get(1).unwrap(),
+            write_fonts::read::tables::gsub::SubstitutionLookup::Single(_)
+        ));
+    }
 }
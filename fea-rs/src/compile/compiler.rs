@@ -6,17 +6,21 @@ use std::{
 };
 
 use crate::{
-    parse::{FileSystemResolver, SourceResolver},
-    Diagnostic, GlyphMap, ParseTree,
+    parse::{FileSystemResolver, IncludeLimits, ParserOptions, SourceResolver},
+    DetachedDiagnostic, Diagnostic, GlyphMap, ParseTree,
 };
 
 use super::{
     error::{CompilerError, DiagnosticSet},
-    Compilation, Opts, VariationInfo,
+    lenient::ErrorRanges,
+    Compilation, LintConfig, Opts, VariationInfo,
 };
 
 const DEFAULT_N_MESSAGES_TO_PRINT: usize = 100;
 
+type LookupHook<'a> = Box<dyn FnMut(&mut Compilation) + 'a>;
+type WarningHandler<'a> = Box<dyn FnMut(DetachedDiagnostic) + 'a>;
+
 /// A builder-style entry point for the compiler.
 ///
 /// This is intended as the principal public API for this crate.
@@ -32,6 +36,7 @@ const DEFAULT_N_MESSAGES_TO_PRINT: usize = 100;
 pub struct Compiler<'a> {
     root_path: OsString,
     project_root: Option<PathBuf>,
+    include_search_paths: Vec<PathBuf>,
     glyph_map: &'a GlyphMap,
     // variable fonts only
     var_info: Option<&'a dyn VariationInfo>,
@@ -39,6 +44,15 @@ pub struct Compiler<'a> {
     max_n_errors: usize,
     opts: Opts,
     resolver: Option<Box<dyn SourceResolver>>,
+    lenient: bool,
+    parser_options: ParserOptions,
+    warn_undeclared_lang_systems: bool,
+    late_mark_classes: bool,
+    class_arithmetic: bool,
+    include_limits: IncludeLimits,
+    lints: LintConfig,
+    lookup_hook: Option<LookupHook<'a>>,
+    warning_handler: Option<WarningHandler<'a>>,
 }
 
 impl<'a> Compiler<'a> {
@@ -59,17 +73,55 @@ impl<'a> Compiler<'a> {
             print_warnings: true,
             resolver: Default::default(),
             project_root: Default::default(),
+            include_search_paths: Vec::new(),
             max_n_errors: DEFAULT_N_MESSAGES_TO_PRINT,
+            lenient: false,
+            parser_options: Default::default(),
+            warn_undeclared_lang_systems: true,
+            late_mark_classes: false,
+            class_arithmetic: false,
+            include_limits: Default::default(),
+            lints: Default::default(),
+            lookup_hook: None,
+            warning_handler: None,
         }
     }
 
     /// Provide a custom `SourceResolver`, for mapping paths to their contents.
+    ///
+    /// This allows compiling sources that do not live on disk:
+    ///
+    /// ```
+    /// # use fea_rs::{parse::MemoryResolver, Compiler, GlyphMap, GlyphName};
+    /// let glyph_map: GlyphMap = [".notdef", "a", "b"].into_iter().map(GlyphName::new).collect();
+    /// let resolver: MemoryResolver = [
+    ///     ("features.fea", "include(test.fea);"),
+    ///     ("test.fea", "feature test { sub a by b; } test;"),
+    /// ]
+    /// .into_iter()
+    /// .collect();
+    /// let compilation = Compiler::new("features.fea", &glyph_map)
+    ///     .with_resolver(resolver)
+    ///     .compile()
+    ///     .unwrap();
+    /// assert!(compilation.gsub.is_some());
+    /// ```
     pub fn with_resolver(mut self, resolver: impl SourceResolver + 'static) -> Self {
         self.resolver = Some(Box::new(resolver));
         self
     }
 
     /// Provide [`VariationInfo`], necessary when compiling features for a variable font.
+    ///
+    /// This is required for variable metrics, and for `conditionset` and
+    /// `variation` blocks, which are compiled into the `FeatureVariations`
+    /// tables of GSUB and GPOS.
+    ///
+    /// A shaper applies the first feature variation record whose conditions
+    /// match, so the order of records matters. Records are ordered by the
+    /// first `variation` block that uses each conditionset, not by the order
+    /// in which the conditionsets are declared; a `variation` block with the
+    /// `NULL` conditionset is treated the same as any other.
     pub fn with_variable_info(mut self, var_info: &'a dyn VariationInfo) -> Self {
         self.var_info = Some(var_info);
         self
@@ -89,6 +141,19 @@ impl<'a> Compiler<'a> {
         self
     }
 
+    /// Provide a function to handle warnings, instead of printing them.
+    ///
+    /// The function is called with each warning (including those from parsing
+    /// and validation) as it is reported, regardless of the value of
+    /// [`print_warnings`]. This can be used to report warnings in another
+    /// format, such as JSON.
+    ///
+    /// [`print_warnings`]: Self::print_warnings
+    pub fn with_warning_handler(mut self, handler: impl FnMut(DetachedDiagnostic) + 'a) -> Self {
+        self.warning_handler = Some(Box::new(handler));
+        self
+    }
+
     /// Specify a maximum number of messages to print when errors occur.
     ///
     /// Default is some arbitrary 'reasonable' number (currently 100.) To
@@ -108,6 +173,149 @@ impl<'a> Compiler<'a> {
         self
     }
 
+    /// Add a directory to search for included files.
+    ///
+    /// Include paths are resolved relative to the project root or the
+    /// including file, as described in the spec; if neither contains the file,
+    /// directories added with this method are tried in the order they were
+    /// added.
+    ///
+    /// This has no effect if a custom resolver is set with [`with_resolver`].
+    ///
+    /// [`with_resolver`]: Self::with_resolver
+    pub fn with_include_search_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.include_search_paths.push(path.into());
+        self
+    }
+
+    /// Compile as much as possible, even if there are errors (default is `false`)
+    ///
+    /// In lenient mode, any statement that contains an error is skipped, along
+    /// with any statements that depend on it (for instance rules that reference
+    /// a glyph class whose definition contains an error.) All other statements
+    /// are compiled normally, and [`compile`] will succeed unless the root
+    /// source cannot be loaded. The exception is a `GSUB` or `GPOS` table with
+    /// more lookups than can be indexed, in which case neither table is
+    /// generated.
+    ///
+    /// Errors are not printed; they are available in [`Compilation::errors`].
+    /// This is intended for things like font editors, which may want to show
+    /// a preview while the user is still editing.
+    ///
+    /// [`compile`]: Self::compile
+    pub fn with_lenient_mode(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// If `true`, glyph names that could also be read as a range are errors.
+    ///
+    /// Hyphens are legal in glyph names, so a name like `a-z` could be either
+    /// a glyph or the range from `a` to `z`. Following the spec, if a glyph
+    /// with the full name exists it is used, and otherwise the name is treated
+    /// as a range; if both interpretations are possible a warning is reported.
+    /// In strict mode this is an error instead.
+    ///
+    /// In either mode, a name can be marked as a glyph name by escaping it
+    /// with a backslash (`\a-z`), and a range can be written unambiguously
+    /// by putting spaces around the hyphen (`a - z`).
+    pub fn with_strict_glyph_names(mut self, strict: bool) -> Self {
+        self.parser_options.strict_glyph_names = strict;
+        self
+    }
+
+    /// Specify options for parsing, such as the maximum number of errors to
+    /// report.
+    ///
+    /// This replaces any value set with [`with_strict_glyph_names`].
+    ///
+    /// [`with_strict_glyph_names`]: Self::with_strict_glyph_names
+    pub fn with_parser_options(mut self, options: ParserOptions) -> Self {
+        self.parser_options = options;
+        self
+    }
+
+    /// If `true` (the default), warn about undeclared language systems.
+    ///
+    /// A `script` or `language` statement in a feature block can register
+    /// lookups for a script and language that was not declared with a
+    /// `languagesystem` statement. Other features are only registered for
+    /// the declared language systems, so they will not be applied to text in
+    /// that script and language; this is a common mistake, and so by default
+    /// it produces a warning.
+    pub fn with_undeclared_language_system_warnings(mut self, flag: bool) -> Self {
+        self.warn_undeclared_lang_systems = flag;
+        self
+    }
+
+    /// If `true`, mark classes may be used before they are defined (default is `false`)
+    ///
+    /// The spec requires all `markClass` definitions to precede any use of a
+    /// mark class, but some generated sources interleave them. In this mode all
+    /// mark class definitions are collected before any rules are compiled, so
+    /// every rule sees every glyph in the class; each use of a mark class before
+    /// its definition produces a warning.
+    pub fn with_late_mark_classes(mut self, flag: bool) -> Self {
+        self.late_mark_classes = flag;
+        self
+    }
+
+    /// If `true`, glyph classes may be built by subtracting or intersecting
+    /// other classes (default is `false`).
+    ///
+    /// This is an extension to the FEA syntax. In a glyph class, `- @class`
+    /// removes the glyphs in `@class` from the glyphs that precede it, and
+    /// `& @class` keeps only those that are also in `@class`. Operators apply
+    /// from left to right, so `[@a @b - @c]` contains the glyphs in `@a` or
+    /// `@b` that are not in `@c`. The operand must be a named glyph class.
+    ///
+    /// Without this option, these operators are errors.
+    pub fn with_class_arithmetic(mut self, flag: bool) -> Self {
+        self.class_arithmetic = flag;
+        self
+    }
+
+    /// Limit the depth of nested includes, and the number and total size of
+    /// the sources that are loaded.
+    ///
+    /// Includes that would exceed these limits are errors. See
+    /// [`IncludeLimits`] for the defaults.
+    pub fn with_include_limits(mut self, limits: IncludeLimits) -> Self {
+        self.include_limits = limits;
+        self
+    }
+
+    /// Promote or silence warnings, by their [`DiagnosticCode`].
+    ///
+    /// Warnings that are denied are reported as errors, and so cause
+    /// compilation to fail (or, in lenient mode, are added to
+    /// [`Compilation::errors`].) Allowed warnings are discarded.
+    ///
+    /// [`DiagnosticCode`]: crate::DiagnosticCode
+    pub fn with_lint_config(mut self, lints: LintConfig) -> Self {
+        self.lints = lints;
+        self
+    }
+
+    /// Provide a function to be run on the output of a successful compilation.
+    ///
+    /// This is run after all features and lookups have been built, and before
+    /// anything is serialized (including by [`compile_binary`]). It can be
+    /// used to apply custom optimizations or filtering without forking the
+    /// crate: lookups can be removed with [`Compilation::retain_lookups`],
+    /// and the `GSUB` and `GPOS` tables can be modified directly.
+    ///
+    /// The hook receives the [`write-fonts`][] table types, and so it may need
+    /// updating whenever our `write-fonts` dependency has a breaking change.
+    /// [`LookupInfo`][super::LookupInfo] may gain fields in minor releases.
+    ///
+    /// [`compile_binary`]: Self::compile_binary
+    /// [`write-fonts`]: https://docs.rs/write-fonts/
+    pub fn with_lookup_hook(mut self, hook: impl FnMut(&mut Compilation) + 'a) -> Self {
+        self.lookup_hook = Some(Box::new(hook));
+        self
+    }
+
     /// Specify additional compiler options.
     pub fn with_opts(mut self, opts: Opts) -> Self {
         self.opts = opts;
@@ -121,38 +329,96 @@ impl<'a> Compiler<'a> {
     /// to a binary font, you can use [`compile_binary`] instead.
     ///
     /// [`compile_binary`]: Self::compile_binary
-    pub fn compile(self) -> Result<Compilation, CompilerError> {
-        let resolver = self.resolver.unwrap_or_else(|| {
-            let project_root = self.project_root.unwrap_or_else(|| {
-                Path::new(&self.root_path)
+    pub fn compile(mut self) -> Result<Compilation, CompilerError> {
+        let root_path = std::mem::take(&mut self.root_path);
+        let resolver = self.resolver.take().unwrap_or_else(|| {
+            let project_root = self.project_root.take().unwrap_or_else(|| {
+                Path::new(&root_path)
                     .parent()
                     .map(PathBuf::from)
                     .unwrap_or_default()
             });
-            Box::new(FileSystemResolver::new(project_root))
+            let search_paths = std::mem::take(&mut self.include_search_paths);
+            Box::new(FileSystemResolver::new(project_root).with_search_paths(search_paths))
         });
 
-        let (tree, diagnostics) =
-            crate::parse::ParseContext::parse(self.root_path, Some(self.glyph_map), resolver)?
-                .generate_parse_tree();
-        print_warnings_return_errors(diagnostics, &tree, self.print_warnings, self.max_n_errors)
+        let (tree, diagnostics) = crate::parse::ParseContext::parse(
+            root_path,
+            Some(self.glyph_map),
+            &self.parser_options,
+            &self.include_limits,
+            resolver,
+        )?
+        .generate_parse_tree();
+        self.compile_parsed(&tree, diagnostics)
+    }
+
+    /// Validate and compile a source that has already been parsed.
+    ///
+    /// This allows a source to be parsed once and then compiled multiple
+    /// times, for instance once per master of a variable font. Since
+    /// [`ParseTree`] is `Send` and `Sync`, these compilations can run in
+    /// parallel, with each thread creating its own `Compiler`.
+    /// (A tree created by editing another tree shares nodes with it, and the
+    /// two should not be compiled at the same time; see the [`ParseTree`]
+    /// docs.)
+    ///
+    /// The tree should have been parsed with the same glyph map, and should
+    /// not contain any parse errors; parse diagnostics are the caller's
+    /// responsibility. Options that only affect parsing (the root path,
+    /// resolver, project root, parser options, and include limits) are
+    /// ignored.
+    pub fn compile_tree(self, tree: &ParseTree) -> Result<Compilation, CompilerError> {
+        self.compile_parsed(tree, Vec::new())
+    }
+
+    fn compile_parsed(
+        mut self,
+        tree: &ParseTree,
+        diagnostics: Vec<Diagnostic>,
+    ) -> Result<Compilation, CompilerError> {
+        if self.lenient {
+            let mut compilation = self.compile_lenient(tree, diagnostics);
+            if let Some(hook) = self.lookup_hook.as_mut() {
+                hook(&mut compilation);
+            }
+            return Ok(compilation);
+        }
+        self.report_warnings_return_errors(diagnostics, tree)
             .map_err(CompilerError::ParseFail)?;
-        let diagnostics = super::validate(&tree, self.glyph_map, self.var_info);
-        print_warnings_return_errors(diagnostics, &tree, self.print_warnings, self.max_n_errors)
+        let diagnostics = super::validate_impl(
+            tree,
+            self.glyph_map,
+            self.var_info,
+            self.late_mark_classes,
+            self.class_arithmetic,
+        );
+        self.report_warnings_return_errors(diagnostics, tree)
             .map_err(CompilerError::ValidationFail)?;
-        let mut ctx = super::CompilationCtx::new(self.glyph_map, tree.source_map(), self.var_info);
+        let mut ctx = super::CompilationCtx::new(
+            self.glyph_map,
+            tree.source_map(),
+            &tree.sources,
+            self.var_info,
+        )
+        .with_undeclared_lang_system_warnings(self.warn_undeclared_lang_systems)
+        .with_glyph_class_inference(self.opts.glyph_class_inference)
+        .with_compact_pair_pos(self.opts.compact_pair_pos)
+        .with_dedupe_lookups(self.opts.dedupe_lookups)
+        .with_share_context_class_defs(self.opts.share_context_class_defs)
+        .with_dedupe_names(self.opts.dedupe_names)
+        .with_late_mark_classes(self.late_mark_classes);
         ctx.compile(&tree.typed_root());
 
         // we 'take' the errors here because it's easier for us to handle the
         // warnings using our helper method.
-        print_warnings_return_errors(
-            std::mem::take(&mut ctx.errors),
-            &tree,
-            self.print_warnings,
-            self.max_n_errors,
-        )
-        .map_err(CompilerError::CompilationFail)?;
-        Ok(ctx.build().unwrap()) // we've taken the errors, so this can't fail
+        self.report_warnings_return_errors(std::mem::take(&mut ctx.errors), tree)
+            .map_err(CompilerError::CompilationFail)?;
+        let mut compilation = ctx.build().unwrap(); // we've taken the errors, so this can't fail
+        if let Some(hook) = self.lookup_hook.as_mut() {
+            hook(&mut compilation);
+        }
+        Ok(compilation)
     }
 
     /// Compile to a binary font.
@@ -161,34 +427,560 @@ impl<'a> Compiler<'a> {
         let glyph_map = self.glyph_map;
         Ok(self.compile()?.to_binary(glyph_map, opts)?)
     }
+
+    fn compile_lenient(
+        &mut self,
+        tree: &ParseTree,
+        mut diagnostics: Vec<Diagnostic>,
+    ) -> Compilation {
+        let mut skip = ErrorRanges::default();
+        for error in diagnostics.iter().filter(|diag| diag.is_error()) {
+            if let Some(range) = tree
+                .source_map()
+                .global_range(error.message.file, error.span())
+            {
+                skip.insert(range);
+            }
+        }
+        diagnostics.extend(super::validate_lenient(
+            tree,
+            self.glyph_map,
+            self.var_info,
+            self.late_mark_classes,
+            self.class_arithmetic,
+            &mut skip,
+        ));
+        let mut ctx = super::CompilationCtx::new(
+            self.glyph_map,
+            tree.source_map(),
+            &tree.sources,
+            self.var_info,
+        )
+        .with_skipped(skip)
+        .with_undeclared_lang_system_warnings(self.warn_undeclared_lang_systems)
+        .with_glyph_class_inference(self.opts.glyph_class_inference)
+        .with_compact_pair_pos(self.opts.compact_pair_pos)
+        .with_dedupe_lookups(self.opts.dedupe_lookups)
+        .with_share_context_class_defs(self.opts.share_context_class_defs)
+        .with_dedupe_names(self.opts.dedupe_names)
+        .with_late_mark_classes(self.late_mark_classes);
+        ctx.compile(&tree.typed_root());
+        diagnostics.extend(std::mem::take(&mut ctx.errors));
+        self.lints.apply(&mut diagnostics);
+
+        let (errors, warnings): (Vec<_>, Vec<_>) =
+            diagnostics.into_iter().partition(Diagnostic::is_error);
+        self.report_warnings(&warnings, tree);
+        let mut compilation = ctx.build().unwrap(); // we've taken the errors
+        compilation.warnings = warnings;
+        compilation.errors = errors;
+        compilation
+    }
+
+    fn report_warnings_return_errors(
+        &mut self,
+        mut diagnostics: Vec<Diagnostic>,
+        tree: &ParseTree,
+    ) -> Result<(), DiagnosticSet> {
+        self.lints.apply(&mut diagnostics);
+        diagnostics.sort_unstable_by_key(|diag| diag.level);
+        let split_at = diagnostics
+            .iter()
+            .position(|x| !x.is_error())
+            .unwrap_or(diagnostics.len());
+        let warnings = diagnostics.split_off(split_at);
+        self.report_warnings(&warnings, tree);
+        if diagnostics.is_empty() {
+            Ok(())
+        } else {
+            Err(DiagnosticSet {
+                messages: diagnostics,
+                sources: tree.sources.clone(),
+                max_to_print: self.max_n_errors,
+            })
+        }
+    }
+
+    fn report_warnings(&mut self, warnings: &[Diagnostic], tree: &ParseTree) {
+        if let Some(handler) = self.warning_handler.as_mut() {
+            for warning in warnings {
+                handler(tree.detach_diagnostic(warning));
+            }
+        } else if self.print_warnings {
+            use std::io::IsTerminal as _;
+            let is_tty = std::io::stderr().is_terminal();
+            for warning in warnings {
+                eprintln!("{}", tree.format_diagnostic(warning, is_tty));
+            }
+        }
+    }
 }
 
-fn print_warnings_return_errors(
-    mut diagnostics: Vec<Diagnostic>,
-    tree: &ParseTree,
-    print_warnings: bool,
-    max_to_print: usize,
-) -> Result<(), DiagnosticSet> {
-    use std::io::IsTerminal as _;
-    let is_tty = std::io::stderr().is_terminal();
-    diagnostics.sort_unstable_by_key(|diag| diag.level);
-    let split_at = diagnostics
-        .iter()
-        .position(|x| !x.is_error())
-        .unwrap_or(diagnostics.len());
-    let warnings = diagnostics.split_off(split_at);
-    if print_warnings {
-        for w in warnings {
-            eprintln!("{}", tree.format_diagnostic(&w, is_tty));
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsStr, sync::Arc};
+
+    use super::*;
+    use crate::{
+        compile::{tags, MockVariationInfo},
+        GlyphName,
+    };
+
+    static LENIENT_FEA: &str = "\
+        @bad = [a nope];\n\
+        lookup good { sub a by b; } good;\n\
+        feature liga {\n\
+            sub c by d;\n\
+            sub @bad by c;\n\
+            sub d by ;\n\
+            lookup good;\n\
+        } liga;\n\
+        feature kern {\n\
+            pos a b -10;\n\
+        } kern;\n";
+
+    fn glyph_map() -> GlyphMap {
+        [".notdef", "a", "b", "c", "d"]
+            .into_iter()
+            .map(GlyphName::new)
+            .collect()
+    }
+
+    #[test]
+    fn lenient_mode() {
+        let glyph_map = glyph_map();
+        let compilation = Compiler::new("lenient.fea", &glyph_map)
+            .with_resolver(|_: &OsStr| Ok(Arc::<str>::from(LENIENT_FEA)))
+            .print_warnings(false)
+            .with_lenient_mode(true)
+            .compile()
+            .unwrap();
+
+        let errors = compilation
+            .errors
+            .iter()
+            .map(|err| err.text())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            [
+                "Expected glyph name or CID",
+                "glyph not in font",
+                // the definition of @bad was skipped
+                "undefined glyph class"
+            ]
+        );
+        let codes = compilation
+            .errors
+            .iter()
+            .map(|err| err.code)
+            .collect::<Vec<_>>();
+        use crate::DiagnosticCode::{ParseError, ValidationError};
+        assert_eq!(
+            codes,
+            [
+                Some(ParseError),
+                Some(ValidationError),
+                Some(ValidationError)
+            ]
+        );
+        let lookup = |table, idx| compilation.lookup_to_fea(table, idx, &glyph_map);
+        assert_eq!(
+            lookup(tags::GSUB, 0).unwrap(),
+            "lookup GSUB_0 {\n    sub a by b;\n} GSUB_0;\n"
+        );
+        assert_eq!(
+            lookup(tags::GSUB, 1).unwrap(),
+            "lookup GSUB_1 {\n    sub c by d;\n} GSUB_1;\n"
+        );
+        assert!(lookup(tags::GSUB, 2).is_none());
+        assert_eq!(
+            lookup(tags::GPOS, 0).unwrap(),
+            "lookup GPOS_0 {\n    pos a b -10;\n} GPOS_0;\n"
+        );
+    }
+
+    #[test]
+    fn strict_mode_fails() {
+        let glyph_map = glyph_map();
+        let result = Compiler::new("lenient.fea", &glyph_map)
+            .with_resolver(|_: &OsStr| Ok(Arc::<str>::from(LENIENT_FEA)))
+            .print_warnings(false)
+            .max_error_messages(0)
+            .compile();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn lint_config() {
+        use crate::{compile::LintLevel, DiagnosticCode};
+        let fea = "\
+            @A = [a];\n\
+            @A = [b];\n\
+            lookup empty { } empty;\n\
+            feature test { sub a by b; } test;\n";
+        let glyph_map = glyph_map();
+        let compile = |lints: LintConfig, lenient: bool| {
+            Compiler::new("lint.fea", &glyph_map)
+                .with_resolver(move |_: &OsStr| Ok(Arc::<str>::from(fea)))
+                .print_warnings(false)
+                .max_error_messages(0)
+                .with_lenient_mode(lenient)
+                .with_lint_config(lints)
+                .compile()
+        };
+        let codes = |diagnostics: &[Diagnostic]| {
+            diagnostics
+                .iter()
+                .map(|diag| diag.code.unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let compilation = compile(LintConfig::new(), true).unwrap();
+        assert!(compilation.errors.is_empty());
+        assert_eq!(
+            codes(&compilation.warnings),
+            [
+                DiagnosticCode::DuplicateGlyphClass,
+                DiagnosticCode::EmptyLookup
+            ]
+        );
+
+        let lints = LintConfig::new()
+            .with_level(DiagnosticCode::DuplicateGlyphClass, LintLevel::Allow)
+            .with_level(DiagnosticCode::EmptyLookup, LintLevel::Deny);
+        let compilation = compile(lints.clone(), true).unwrap();
+        assert!(compilation.warnings.is_empty());
+        assert_eq!(codes(&compilation.errors), [DiagnosticCode::EmptyLookup]);
+        assert!(matches!(
+            compile(lints, false),
+            Err(CompilerError::CompilationFail(_))
+        ));
+    }
+
+    #[test]
+    fn warning_handler() {
+        let fea = "@A = [a];\n@A = [b];\nfeature test { sub a by b; } test;\n";
+        let glyph_map = glyph_map();
+        let mut warnings = Vec::new();
+        Compiler::new("warn.fea", &glyph_map)
+            .with_resolver(move |_: &OsStr| Ok(Arc::<str>::from(fea)))
+            .with_warning_handler(|warning| warnings.push(warning))
+            .compile()
+            .unwrap();
+        let [warning] = warnings.as_slice() else {
+            panic!("unexpected warnings: {warnings:?}");
+        };
+        assert_eq!(
+            warning.code,
+            Some(crate::DiagnosticCode::DuplicateGlyphClass)
+        );
+        assert_eq!(warning.path, "warn.fea");
+        assert_eq!((warning.line, warning.column), (2, 0));
+        assert_eq!(warning.range, 10..12);
+    }
+
+    static LATE_MARK_CLASS_FEA: &str = "\
+        markClass b <anchor 0 0> @TOP;\n\
+        feature mark {\n\
+            pos base a <anchor 10 10> mark @TOP <anchor 20 20> mark @BOTTOM;\n\
+        } mark;\n\
+        @MARKS = [c];\n\
+        markClass @MARKS <anchor 0 0> @TOP;\n\
+        markClass d <anchor 0 0> @BOTTOM;\n";
+
+    fn compile_late_mark_classes(late: bool, lenient: bool) -> Result<Compilation, CompilerError> {
+        let glyph_map = glyph_map();
+        Compiler::new("late.fea", &glyph_map)
+            .with_resolver(|_: &OsStr| Ok(Arc::<str>::from(LATE_MARK_CLASS_FEA)))
+            .print_warnings(false)
+            .max_error_messages(0)
+            .with_late_mark_classes(late)
+            .with_lenient_mode(lenient)
+            .compile()
+    }
+
+    #[test]
+    fn late_mark_classes() {
+        assert!(compile_late_mark_classes(false, false).is_err());
+
+        let compilation = compile_late_mark_classes(true, true).unwrap();
+        assert!(compilation.errors.is_empty(), "{:?}", compilation.errors);
+        let warnings = compilation
+            .warnings
+            .iter()
+            .map(|warning| warning.text())
+            .collect::<Vec<_>>();
+        assert_eq!(warnings, ["mark class is used before it is defined"]);
+
+        // all of the marks are included, including those added to @TOP after use
+        let compilation = compile_late_mark_classes(true, false).unwrap();
+        let gpos = compilation.gpos.unwrap();
+        let write_fonts::tables::gpos::PositionLookup::MarkToBase(lookup) =
+            &*gpos.lookup_list.lookups[0]
+        else {
+            panic!("expected mark-to-base lookup");
+        };
+        let marks = lookup.subtables[0]
+            .mark_coverage
+            .iter()
+            .map(|gid| gid.to_u16())
+            .collect::<Vec<_>>();
+        assert_eq!(marks, [2, 3, 4]);
+    }
+
+    #[test]
+    fn late_mark_class_in_variation_block() {
+        let fea = "\
+            conditionset heavy { wght 700 900; } heavy;\n\
+            feature mark {\n\
+                pos base a <anchor 10 10> mark @TOP;\n\
+            } mark;\n\
+            variation rvrn heavy {\n\
+                markClass c <anchor 0 0> @TOP;\n\
+                sub b by d;\n\
+            } rvrn;\n";
+        let glyph_map = glyph_map();
+        let var_info = MockVariationInfo::new(&[("wght", 200, 200, 1000)]);
+        let compilation = Compiler::new("late.fea", &glyph_map)
+            .with_resolver(move |_: &OsStr| Ok(Arc::<str>::from(fea)))
+            .with_variable_info(&var_info)
+            .print_warnings(false)
+            .with_late_mark_classes(true)
+            .compile()
+            .unwrap();
+        let gpos = compilation.gpos.unwrap();
+        let write_fonts::tables::gpos::PositionLookup::MarkToBase(lookup) =
+            &*gpos.lookup_list.lookups[0]
+        else {
+            panic!("expected mark-to-base lookup");
+        };
+        let marks = lookup.subtables[0]
+            .mark_coverage
+            .iter()
+            .map(|gid| gid.to_u16())
+            .collect::<Vec<_>>();
+        assert_eq!(marks, [3]);
+    }
+
+    #[test]
+    fn class_arithmetic() {
+        let glyph_map = glyph_map();
+        let compile = |flag: bool| {
+            Compiler::new("arith.fea", &glyph_map)
+                .with_resolver(|_: &OsStr| {
+                    Ok(Arc::<str>::from(
+                        "\
+                        @ALL = [a b c d];\n\
+                        @LOW = [a b];\n\
+                        @SOME = [b c];\n\
+                        feature test { sub [@ALL - @LOW] by a; } test;\n\
+                        feature tst2 { sub [a b d & @SOME] by d; } tst2;\n",
+                    ))
+                })
+                .print_warnings(false)
+                .max_error_messages(0)
+                .with_class_arithmetic(flag)
+                .compile()
+        };
+        assert!(compile(false).is_err());
+
+        let gsub = compile(true).unwrap().gsub.unwrap();
+        // the glyphs covered by each lookup; the features have one lookup each
+        let coverage = |idx: usize| {
+            let write_fonts::tables::gsub::SubstitutionLookup::Single(lookup) =
+                &*gsub.lookup_list.lookups[idx]
+            else {
+                panic!("expected single substitution lookup");
+            };
+            let mut glyphs = lookup
+                .subtables
+                .iter()
+                .flat_map(|sub| match &**sub {
+                    write_fonts::tables::gsub::SingleSubst::Format1(sub) => {
+                        sub.coverage.iter().collect::<Vec<_>>()
+                    }
+                    write_fonts::tables::gsub::SingleSubst::Format2(sub) => {
+                        sub.coverage.iter().collect()
+                    }
+                })
+                .map(|gid| gid.to_u16())
+                .collect::<Vec<_>>();
+            glyphs.sort();
+            glyphs
+        };
+        // the difference is c and d
+        assert_eq!(coverage(0), [3, 4]);
+        // the intersection is b
+        assert_eq!(coverage(1), [2]);
+    }
+
+    fn compile_pair_pos(
+        fea: &'static str,
+        compact: bool,
+    ) -> Vec<write_fonts::tables::gpos::PairPos> {
+        use write_fonts::tables::gpos::PositionLookup;
+        let glyph_map: GlyphMap = std::iter::once(".notdef".to_string())
+            .chain(
+                ["l", "r", "s"]
+                    .into_iter()
+                    .flat_map(|prefix| (0..10).map(move |i| format!("{prefix}{i}"))),
+            )
+            .map(GlyphName::new)
+            .collect();
+        let compilation = Compiler::new("kern.fea", &glyph_map)
+            .with_resolver(move |_: &OsStr| Ok(Arc::<str>::from(fea)))
+            .with_opts(Opts::new().compact_pair_pos(compact))
+            .print_warnings(false)
+            .compile()
+            .unwrap();
+        let gpos = compilation.gpos.unwrap();
+        match &*gpos.lookup_list.lookups[0] {
+            PositionLookup::Pair(lookup) => {
+                lookup.subtables.iter().map(|sub| (**sub).clone()).collect()
+            }
+            _ => panic!("expected pair pos lookup"),
         }
     }
-    if diagnostics.is_empty() {
-        Ok(())
-    } else {
-        Err(DiagnosticSet {
-            messages: diagnostics,
-            sources: tree.sources.clone(),
-            max_to_print,
-        })
+
+    #[test]
+    fn compact_pair_pos() {
+        use write_fonts::tables::gpos::PairPos;
+        let fea = "\
+            @L = [l0 l1 l2 l3 l4 l5 l6 l7 l8 l9];\n\
+            @R = [r0 r1 r2 r3 r4 r5 r6 r7 r8 r9];\n\
+            feature kern {\n\
+                enum pos @L @R -50;\n\
+                enum pos [l0 l1] [s0 s1] -30;\n\
+            } kern;";
+
+        let glyph_pairs = compile_pair_pos(fea, false);
+        let [PairPos::Format1(glyph_pairs)] = glyph_pairs.as_slice() else {
+            panic!("expected a single format 1 subtable");
+        };
+        let n_pairs = glyph_pairs
+            .pair_sets
+            .iter()
+            .map(|set| set.pair_value_records.len())
+            .sum::<usize>();
+        assert_eq!(n_pairs, 104);
+
+        let class_pairs = compile_pair_pos(fea, true);
+        let [PairPos::Format2(class_pairs)] = class_pairs.as_slice() else {
+            panic!("expected a single format 2 subtable");
+        };
+        assert_eq!(class_pairs.coverage.len(), 10);
+        // [l0 l1] and the other first glyphs
+        assert_eq!(class_pairs.class1_records.len(), 2);
+        // class 0, @R and [s0 s1]
+        assert_eq!(class_pairs.class1_records[0].class2_records.len(), 3);
+        assert!(
+            write_fonts::dump_table(class_pairs).unwrap().len()
+                < write_fonts::dump_table(glyph_pairs).unwrap().len()
+        );
+
+        // and every pair gets the same adjustment
+        for (first, set) in glyph_pairs.coverage.iter().zip(&glyph_pairs.pair_sets) {
+            let class1 = class_pairs.class_def1.get(first);
+            for record in &set.pair_value_records {
+                let class2 = class_pairs.class_def2.get(record.second_glyph);
+                let compact =
+                    &class_pairs.class1_records[class1 as usize].class2_records[class2 as usize];
+                assert_eq!(compact.value_record1, record.value_record1);
+                assert_eq!(compact.value_record2, record.value_record2);
+            }
+        }
+    }
+
+    fn compile_chain_context(
+        fea: String,
+        share_class_defs: bool,
+    ) -> Vec<write_fonts::tables::layout::ChainedSequenceContext> {
+        use write_fonts::tables::gsub::SubstitutionLookup;
+        let glyph_map: GlyphMap = std::iter::once(".notdef".to_string())
+            .chain(
+                ["l", "r", "s"]
+                    .into_iter()
+                    .flat_map(|prefix| (0..10).map(move |i| format!("{prefix}{i}"))),
+            )
+            .map(GlyphName::new)
+            .collect();
+        let compilation = Compiler::new("context.fea", &glyph_map)
+            .with_resolver(move |_: &OsStr| Ok(Arc::<str>::from(fea.as_str())))
+            .with_opts(Opts::new().share_context_class_defs(share_class_defs))
+            .print_warnings(false)
+            .compile()
+            .unwrap();
+        let gsub = compilation.gsub.unwrap();
+        match &*gsub.lookup_list.lookups[0] {
+            SubstitutionLookup::ChainContextual(lookup) => lookup
+                .subtables
+                .iter()
+                .map(|sub| sub.as_inner().clone())
+                .collect(),
+            _ => panic!("expected chain contextual lookup"),
+        }
+    }
+
+    #[test]
+    fn share_context_class_defs() {
+        use write_fonts::tables::layout::ChainedSequenceContext;
+        // the same classes are used in different sequences; separately, each
+        // sequence would need its own classdef.
+        let mut fea = "\
+            @L = [l0 l1 l2 l3 l4 l5 l6 l7 l8 l9];\n\
+            @R = [r0 r1 r2 r3 r4 r5 r6 r7 r8 r9];\n\
+            @S = [s0 s1 s2 s3 s4 s5 s6 s7 s8 s9];\n\
+            feature test {\n"
+            .to_string();
+        for backtrack in ["@L @L", "@L @R", "@R @L", "@R @R"] {
+            for input in ["@S", "@L"] {
+                for lookahead in ["@R @R", "@R @S", "@S @R", "@S @S"] {
+                    fea.push_str(&format!("ignore sub {backtrack} {input}' {lookahead};\n"));
+                }
+            }
+        }
+        fea.push_str("} test;\n");
+
+        let separate = compile_chain_context(fea.clone(), false);
+        let [ChainedSequenceContext::Format2(separate)] = separate.as_slice() else {
+            panic!("expected a single format 2 subtable: {separate:?}");
+        };
+        assert_ne!(*separate.backtrack_class_def, *separate.input_class_def);
+
+        let shared = compile_chain_context(fea, true);
+        let [ChainedSequenceContext::Format2(shared)] = shared.as_slice() else {
+            panic!("expected a single format 2 subtable: {shared:?}");
+        };
+        assert_eq!(*shared.backtrack_class_def, *shared.input_class_def);
+        assert_eq!(*shared.lookahead_class_def, *shared.input_class_def);
+        assert!(
+            write_fonts::dump_table(shared).unwrap().len()
+                < write_fonts::dump_table(separate).unwrap().len()
+        );
+    }
+
+    #[test]
+    fn compact_pair_pos_does_not_shadow_later_subtables() {
+        use write_fonts::tables::gpos::PairPos;
+        // the glyph pairs come before the class pairs, which also cover 'l0';
+        // as a class subtable, the first subtable would match every pair
+        // starting with 'l0', so the class rule would never apply.
+        let fea = "\
+            feature kern {\n\
+                enum pos l0 [r0 r1 r2 r3 r4 r5 r6 r7 r8 r9] -50;\n\
+                pos [l0 l1] [s0 s1] -30;\n\
+            } kern;";
+        let subtables = compile_pair_pos(fea, true);
+        let [PairPos::Format1(_), PairPos::Format2(_)] = subtables.as_slice() else {
+            panic!("unexpected subtables: {subtables:?}");
+        };
+
+        // without the class pairs, the glyph pairs are compacted
+        let fea = "feature kern { enum pos l0 [r0 r1 r2 r3 r4 r5 r6 r7 r8 r9] -50; } kern;";
+        let subtables = compile_pair_pos(fea, true);
+        let [PairPos::Format2(_)] = subtables.as_slice() else {
+            panic!("unexpected subtables: {subtables:?}");
+        };
     }
 }
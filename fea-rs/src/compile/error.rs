@@ -6,7 +6,7 @@ use write_fonts::{read::ReadError, BuilderError};
 
 use crate::{
     parse::{SourceList, SourceLoadError},
-    Diagnostic,
+    DetachedDiagnostic, Diagnostic,
 };
 
 /// An error that occurs when extracting a glyph order from a UFO.
@@ -20,6 +20,24 @@ pub enum UfoGlyphOrderError {
     Malformed,
 }
 
+/// An error that occurs when extracting a glyph order from a designspace.
+#[cfg(feature = "norad")]
+#[derive(Debug, thiserror::Error)]
+pub enum DesignspaceGlyphOrderError {
+    /// Failed to load the designspace
+    #[error("Failed to load designspace: '{0}'")]
+    Load(#[from] norad::error::DesignSpaceLoadError),
+    /// No source is at the default location
+    #[error("No source in the designspace is at the default location")]
+    NoDefaultSource,
+    /// Failed to load the default source
+    #[error("Failed to load default source '{}': '{1}'", .0.display())]
+    UfoLoad(std::path::PathBuf, Box<norad::error::FontLoadError>),
+    /// The default source has a malformed glyph order
+    #[error("{0}")]
+    Ufo(#[from] UfoGlyphOrderError),
+}
+
 /// An error that occurs when extracting a glyph order from a font file.
 #[derive(Clone, Debug, thiserror::Error)]
 pub enum FontGlyphOrderError {
@@ -35,6 +53,29 @@ pub enum FontGlyphOrderError {
     MissingNames,
 }
 
+/// An error that occurs when applying or merging a compilation to an existing font.
+///
+/// See [`Compilation::apply_to_font`][super::Compilation::apply_to_font] and
+/// [`Compilation::merge_into_font`][super::Compilation::merge_into_font].
+#[derive(Debug, thiserror::Error)]
+pub enum MergeError {
+    /// A table in the font could not be read
+    #[error("Failed to read the font's '{0}' table: '{1}'")]
+    ReadTable(write_fonts::types::Tag, #[source] ReadError),
+    /// Both the font and the compilation have an item variation store
+    #[error("Cannot merge the GDEF item variation store with the one in the font")]
+    VariationStore,
+    /// A glyph is in a mark attachment class in both the font and the compilation
+    #[error("Glyph {0} is in a mark attachment class in both the font and the compilation")]
+    MarkAttachClassConflict(write_fonts::types::GlyphId),
+    /// There are more mark attachment classes than fit in a lookup flag
+    #[error("Merged mark attachment class {0} does not fit in a lookup flag (max 255)")]
+    TooManyMarkAttachClasses(u16),
+    /// Failed to write the merged font
+    #[error("{0}")]
+    WriteFail(#[from] BuilderError),
+}
+
 /// An error that occurs when loading a raw glyph order.
 #[derive(Clone, Debug, thiserror::Error)]
 pub enum GlyphOrderError {
@@ -47,6 +88,41 @@ pub enum GlyphOrderError {
     MissingNotDef,
 }
 
+/// An error that occurs when loading a glyph order with [`load_glyph_order`].
+///
+/// Some variants only exist with (or without) the `norad` feature, so this
+/// enum is non-exhaustive.
+///
+/// [`load_glyph_order`]: super::load_glyph_order
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum GlyphOrderLoadError {
+    /// Failed to read the file
+    #[error("Failed to read '{}': '{1}'", .0.display())]
+    Io(std::path::PathBuf, #[source] std::io::Error),
+    /// The text glyph order is invalid
+    #[error("{0}")]
+    Text(#[from] GlyphOrderError),
+    /// The font has no usable glyph names
+    #[error("{0}")]
+    Font(#[from] FontGlyphOrderError),
+    /// The UFO has no usable glyph order
+    #[error("{0}")]
+    Ufo(#[from] UfoGlyphOrderError),
+    /// Failed to load a UFO
+    #[cfg(feature = "norad")]
+    #[error("Failed to load UFO '{}': '{1}'", .0.display())]
+    UfoLoad(std::path::PathBuf, Box<norad::error::FontLoadError>),
+    /// Failed to get a glyph order from a designspace
+    #[cfg(feature = "norad")]
+    #[error("{0}")]
+    Designspace(#[from] DesignspaceGlyphOrderError),
+    /// Loading from this source requires the 'norad' feature
+    #[cfg(not(feature = "norad"))]
+    #[error("Loading a glyph order from '{}' requires the 'norad' feature", .0.display())]
+    Unsupported(std::path::PathBuf),
+}
+
 /// An error reported by the compiler
 #[derive(Debug, thiserror::Error)]
 #[allow(missing_docs)]
@@ -81,6 +157,15 @@ pub struct DiagnosticSet {
 struct DiagnosticDisplayer<'a>(&'a DiagnosticSet);
 
 impl DiagnosticSet {
+    /// Copy these diagnostics, along with their source text, so that they can
+    /// be kept after the sources are dropped.
+    pub fn detach(&self) -> Vec<DetachedDiagnostic> {
+        self.messages
+            .iter()
+            .map(|err| self.sources.detach_diagnostic(err))
+            .collect()
+    }
+
     pub(crate) fn write(&self, f: &mut impl std::fmt::Write, colorize: bool) -> std::fmt::Result {
         let mut first = true;
         for err in self.messages.iter().take(self.max_to_print) {
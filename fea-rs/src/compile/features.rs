@@ -5,7 +5,7 @@ use std::collections::{BTreeMap, HashMap, HashSet};
 use smol_str::SmolStr;
 use write_fonts::{
     tables::layout::{ConditionSet, FeatureParams, SizeParams, StylisticSetParams},
-    types::{GlyphId, Tag, Uint24},
+    types::{GlyphId, NameId, Tag, Uint24},
 };
 
 use super::{
@@ -31,8 +31,9 @@ pub(crate) struct AllFeatures {
     required_features: HashSet<FeatureKey>,
     pub(crate) size: Option<SizeFeature>,
     pub(crate) aalt: Option<AaltFeature>,
-    pub(crate) stylistic_sets: HashMap<Tag, Vec<NameSpec>>,
-    pub(crate) character_variants: HashMap<Tag, CvParams>,
+    // these are BTreeMaps so that name ids are assigned in a stable order
+    pub(crate) stylistic_sets: BTreeMap<Tag, Vec<NameSpec>>,
+    pub(crate) character_variants: BTreeMap<Tag, CvParams>,
 }
 
 /// Tracking state within a feature block
@@ -94,8 +95,8 @@ pub(crate) enum SpecialVerticalFeatureState {
     InnerLookup,
 }
 
-/// maps names to conditionsets, also tracking declaration order (which
-/// is maintained in the final output table)
+/// maps names to conditionsets, also tracking the order in which they are
+/// first used (which determines their order in the final output table)
 #[derive(Clone, Debug, Default)]
 pub(crate) struct ConditionSetMap {
     named_conditionsets: HashMap<SmolStr, ConditionSet>,
@@ -197,6 +198,12 @@ impl AllFeatures {
         }
     }
 
+    /// Build the `FeatureParams` for the size, ssXX and cvXX features.
+    ///
+    /// Any names these need are allocated in `name_builder`, in a fixed order:
+    /// first the size feature, then stylistic sets in tag order, then
+    /// character variants in tag order. This runs after the STAT table has
+    /// been built, so these ids always follow any used by STAT.
     pub(crate) fn build_feature_params(
         &self,
         name_builder: &mut NameBuilder,
@@ -229,6 +236,10 @@ impl AllFeatures {
 }
 
 impl FeatureLookups {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.base.is_empty() && self.variations.values().all(Vec::is_empty)
+    }
+
     fn adjust_gsub_ids(&mut self, delta: usize) {
         self.base
             .iter_mut()
@@ -268,6 +279,11 @@ impl ActiveFeature {
         }
     }
 
+    /// The tag of this feature.
+    pub(crate) fn tag(&self) -> Tag {
+        self.tag
+    }
+
     /// Change the active language system.
     ///
     /// This method is called when encountering 'script' and 'language' statements
@@ -276,6 +292,13 @@ impl ActiveFeature {
     ///
     /// This method handles figuring out what previously declared lookups should
     /// be included with the newly assigned language system.
+    ///
+    /// As in feaLib, each `language` statement replaces the lookups of that
+    /// language system: with the default lookups declared so far for
+    /// `include_dflt` (the default), or with nothing for `exclude_dflt`.
+    /// Default lookups declared later are not propagated, and if a language
+    /// is set more than once, any lookups added after an earlier statement for
+    /// it are discarded.
     pub(crate) fn set_system(&mut self, system: LanguageSystem, exclude_dflt: bool) -> FeatureKey {
         // if the language is default, this is either the DFLT dflt system
         // or a script default (like latn dflt). In this second case, we keep
@@ -286,40 +309,63 @@ impl ActiveFeature {
         // but if this is a fully-resolved language system, we add the default
         // lookups now, when we have access to the 'exclude_dflt' flag.
         if system.language != tags::LANG_DFLT {
-            let mut lookups = Vec::new();
-            if !exclude_dflt {
-                let script_dflt = LanguageSystem {
-                    script: system.script,
-                    language: tags::LANG_DFLT,
-                };
-                // if *either* this is an explicit default, or this is part of
-                // a script where script/dflt is an explicit default, and we have
-                // seen a script keyword, add the default lookups
-                if self.default_systems.contains(&system)
-                    || (self.default_systems.contains(&script_dflt)
-                        && self.script_default_lookups.contains_key(&system.script))
-                {
-                    lookups.extend(
-                        self.lookups
-                            .get(&LanguageSystem::default())
-                            .into_iter()
-                            .flat_map(|v| v.iter().copied()),
-                    );
-                }
-                lookups.extend(
-                    self.script_default_lookups
-                        .get(&system.script)
-                        .into_iter()
-                        .flat_map(|v| v.iter().copied()),
-                );
-            }
-            self.lookups.entry(system).or_insert_with(|| lookups);
+            let lookups = if exclude_dflt {
+                Vec::new()
+            } else {
+                self.inherited_lookups(system)
+            };
+            self.lookups.insert(system, lookups);
         }
 
         self.current_lang_sys = Some(system);
         system.to_feature_key(self.tag)
     }
 
+    /// Returns `true` if setting this language system would silently omit the
+    /// lookups declared before the first `script` or `language` statement.
+    ///
+    /// Per the spec, those lookups are only inherited by language systems that
+    /// are registered with a `languagesystem` statement (or that belong to a
+    /// script whose default language system is registered), which is easy to
+    /// miss.
+    pub(crate) fn omits_default_lookups(&self, system: LanguageSystem, exclude_dflt: bool) -> bool {
+        !exclude_dflt
+            && system.language != tags::LANG_DFLT
+            && !self.lookups.contains_key(&system)
+            && self
+                .lookups
+                .get(&LanguageSystem::default())
+                .is_some_and(|lookups| !lookups.is_empty())
+            && !self.inherits_default_lookups(system)
+    }
+
+    /// The default lookups that a language system inherits, unless it is
+    /// declared with `exclude_dflt`.
+    fn inherited_lookups(&self, system: LanguageSystem) -> Vec<LookupId> {
+        let root = self
+            .lookups
+            .get(&LanguageSystem::default())
+            .filter(|_| self.inherits_default_lookups(system));
+        let script = self.script_default_lookups.get(&system.script);
+        root.into_iter()
+            .chain(script)
+            .flat_map(|v| v.iter().copied())
+            .collect()
+    }
+
+    // if *either* this is an explicit default, or this is part of a script
+    // where script/dflt is an explicit default, and we have seen a script
+    // keyword, the system gets the default lookups
+    fn inherits_default_lookups(&self, system: LanguageSystem) -> bool {
+        let script_dflt = LanguageSystem {
+            script: system.script,
+            language: tags::LANG_DFLT,
+        };
+        self.default_systems.contains(&system)
+            || (self.default_systems.contains(&script_dflt)
+                && self.script_default_lookups.contains_key(&system.script))
+    }
+
     pub(crate) fn add_lookup(&mut self, lookup: LookupId) {
         // there is a distinction between "implicit DFLT/dflt" and having
         // an explicit 'DFLT' script in the lookup block.
@@ -407,8 +453,8 @@ fn split_lookups(lookups: &[LookupId]) -> (Vec<u16>, Vec<u16>) {
     let mut gsub = Vec::new();
     for lookup in lookups {
         match lookup {
-            LookupId::Gpos(_) => gpos.push(lookup.to_gpos_id_or_die()),
-            LookupId::Gsub(_) => gsub.push(lookup.to_gsub_id_or_die()),
+            LookupId::Gpos(_) => gpos.push(lookup.to_gpos_id()),
+            LookupId::Gsub(_) => gsub.push(lookup.to_gsub_id()),
             LookupId::Empty => (),
         }
     }
@@ -464,7 +510,14 @@ impl CvParams {
         &self,
         names: &mut NameBuilder,
     ) -> write_fonts::tables::layout::CharacterVariantParams {
-        let mut out = write_fonts::tables::layout::CharacterVariantParams::default();
+        // unset name ids are NULL (0), not the 0xFFFF default
+        let mut out = write_fonts::tables::layout::CharacterVariantParams {
+            feat_ui_label_name_id: NameId::new(0),
+            feat_ui_tooltip_text_name_id: NameId::new(0),
+            sample_text_name_id: NameId::new(0),
+            first_param_ui_label_name_id: NameId::new(0),
+            ..Default::default()
+        };
         if !self.feat_ui_label_name.is_empty() {
             out.feat_ui_label_name_id = names.add_anon_group(&self.feat_ui_label_name);
         }
@@ -613,6 +666,38 @@ mod tests {
         assert_eq!(built.get_base(&key), Some([id_1, id_2].as_slice()));
     }
 
+    #[test]
+    fn repeated_language_statements() {
+        let defaults = default_systems([DFLT_DFLT, LATN_DFLT, LATN_TRK, LATN_DEU]);
+        let [id1, id2, id3, id4, id5, id6, id7] = make_ids();
+
+        let mut feature = ActiveFeature::new(TAG_TEST, defaults, None);
+        feature.add_lookup(id1);
+        feature.set_system(LATN_DFLT, false);
+        feature.add_lookup(id2);
+        feature.set_system(LATN_TRK, true);
+        feature.add_lookup(id3);
+        feature.set_system(LATN_DEU, false);
+        feature.add_lookup(id4);
+        // default lookups declared after a language are not propagated to it
+        feature.set_system(LATN_DFLT, false);
+        feature.add_lookup(id5);
+        // setting a language again replaces its lookups with the defaults
+        feature.set_system(LATN_TRK, false);
+        feature.add_lookup(id6);
+        // or with nothing, for exclude_dflt
+        feature.set_system(LATN_DEU, true);
+        feature.add_lookup(id7);
+
+        let built = feature.build_features();
+        let key = LATN_DFLT.to_feature_key(TAG_TEST);
+        assert_eq!(built.get_base(&key), Some([id1, id2, id5].as_slice()));
+        let key = LATN_TRK.to_feature_key(TAG_TEST);
+        assert_eq!(built.get_base(&key), Some([id1, id2, id5, id6].as_slice()));
+        let key = LATN_DEU.to_feature_key(TAG_TEST);
+        assert_eq!(built.get_base(&key), Some([id7].as_slice()));
+    }
+
     const DFLT_FRE: LanguageSystem = langsys(b"DFLT", b"FRE ");
     const DFLT_ABC: LanguageSystem = langsys(b"DFLT", b"ABC ");
     const LATN_ABC: LanguageSystem = langsys(b"latn", b"ABC ");
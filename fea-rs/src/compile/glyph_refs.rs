@@ -0,0 +1,140 @@
+//! Finding the glyphs referenced by a feature file
+
+use std::collections::BTreeSet;
+
+use write_fonts::types::GlyphId;
+
+use crate::{
+    token_tree::typed::{self, AstNode},
+    GlyphIdent, GlyphMap, GlyphName, Kind, Node, NodeOrToken, ParseTree,
+};
+
+use super::glyph_range;
+
+/// The set of glyphs referenced by a feature file.
+///
+/// This is returned by [`referenced_glyphs`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReferencedGlyphs {
+    /// All glyphs referenced in the source that exist in the glyph map.
+    pub glyphs: BTreeSet<GlyphId>,
+    /// Any referenced glyph names or CIDs that are not in the glyph map.
+    pub unresolved: BTreeSet<GlyphIdent>,
+}
+
+/// Return the set of all glyphs referenced by this tree.
+///
+/// This includes every glyph named anywhere in the source (including in
+/// glyph class definitions that are never used) with glyph ranges expanded.
+/// It is intended for things like subsetters and QA tools that need to know
+/// which glyphs the layout rules depend on.
+///
+/// The tree should be parsed with the same glyph map, so that glyph names
+/// containing hyphens are correctly disambiguated from glyph ranges.
+/// Malformed ranges are skipped; these are reported during validation.
+pub fn referenced_glyphs(tree: &ParseTree, glyph_map: &GlyphMap) -> ReferencedGlyphs {
+    let mut result = ReferencedGlyphs::default();
+    collect_glyphs(tree.root(), glyph_map, &mut result);
+    result
+}
+
+fn collect_glyphs(node: &Node, glyph_map: &GlyphMap, out: &mut ReferencedGlyphs) {
+    for child in node.iter_children() {
+        if let Some(range) = typed::GlyphRange::cast(child) {
+            add_range(&range, glyph_map, out);
+        } else if let Some(name) = typed::GlyphName::cast(child) {
+            out.add(GlyphIdent::Name(GlyphName::new(name.text())), glyph_map);
+        } else if let Some(cid) = typed::Cid::cast(child) {
+            out.add(GlyphIdent::Cid(cid.parse()), glyph_map);
+        } else if let NodeOrToken::Node(node) = child {
+            collect_glyphs(node, glyph_map, out);
+        }
+    }
+}
+
+fn add_range(range: &typed::GlyphRange, glyph_map: &GlyphMap, out: &mut ReferencedGlyphs) {
+    let start = range.start();
+    let end = range.end();
+    // errors here are reported in validation
+    let _ = match (start.kind, end.kind) {
+        (Kind::Cid, Kind::Cid) => glyph_range::cid(start, end, |cid| {
+            out.add(GlyphIdent::Cid(cid), glyph_map);
+        }),
+        (Kind::GlyphName, Kind::GlyphName) => glyph_range::named(start, end, |name| {
+            out.add(GlyphIdent::Name(GlyphName::new(name)), glyph_map);
+        }),
+        _ => Ok(()),
+    };
+}
+
+impl ReferencedGlyphs {
+    fn add(&mut self, glyph: GlyphIdent, glyph_map: &GlyphMap) {
+        match glyph_map.get(&glyph) {
+            Some(gid) => {
+                self.glyphs.insert(gid);
+            }
+            None => {
+                self.unresolved.insert(glyph);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glyph_names(glyph_map: &GlyphMap, refs: &ReferencedGlyphs) -> Vec<String> {
+        let reverse = glyph_map.reverse_map();
+        refs.glyphs
+            .iter()
+            .map(|gid| reverse.get(gid).unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn smoke_test() {
+        let glyph_map: GlyphMap = [
+            ".notdef",
+            "a",
+            "b",
+            "c",
+            "d",
+            "e",
+            "f",
+            "f_i",
+            "one",
+            "two",
+            "a-b",
+            "acutecomb",
+        ]
+        .into_iter()
+        .map(GlyphName::new)
+        .collect();
+        let fea = "\
+            @unused = [two];\n\
+            markClass acutecomb <anchor 0 500> @TOP;\n\
+            feature test {\n\
+                sub [a - c] by \\a-b;\n\
+                sub f i by f_i;\n\
+                pos base one <anchor 0 0> mark @TOP;\n\
+            } test;\n";
+        let (tree, errs) = crate::parse::parse_root(
+            "test.fea".into(),
+            Some(&glyph_map),
+            move |_: &std::ffi::OsStr| Ok(fea.into()),
+        )
+        .unwrap();
+        assert!(errs.is_empty(), "{errs:?}");
+
+        let refs = referenced_glyphs(&tree, &glyph_map);
+        assert_eq!(
+            glyph_names(&glyph_map, &refs),
+            ["a", "b", "c", "f", "f_i", "one", "two", "a-b", "acutecomb"]
+        );
+        assert_eq!(
+            refs.unresolved.into_iter().collect::<Vec<_>>(),
+            [GlyphIdent::Name(GlyphName::new("i"))]
+        );
+    }
+}
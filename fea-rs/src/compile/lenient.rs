@@ -0,0 +1,99 @@
+//! Support for compiling sources that contain errors.
+//!
+//! In lenient mode, any statement that contains an error is skipped during
+//! validation and compilation. Skipping a statement (such as a glyph class
+//! definition) can cause errors in statements that depend on it, so validation
+//! is repeated until no new errors are found. Whatever remains is compiled.
+
+use std::ops::Range;
+
+use crate::{
+    token_tree::typed::{self, AstNode},
+    NodeOrToken,
+};
+
+use super::tags;
+
+/// The ranges, in the combined tree, of statements that should be skipped.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ErrorRanges {
+    ranges: Vec<Range<usize>>,
+}
+
+impl ErrorRanges {
+    /// Add a range, returning `true` if it was not already present.
+    pub(crate) fn insert(&mut self, range: Range<usize>) -> bool {
+        if self.ranges.contains(&range) {
+            return false;
+        }
+        self.ranges.push(range);
+        true
+    }
+
+    /// Returns `true` if this statement contains an error, and should be skipped.
+    ///
+    /// Feature and lookup blocks are only skipped if the error is not inside
+    /// one of their statements; otherwise the individual statements are
+    /// skipped as the block is processed.
+    pub(crate) fn should_skip(&self, item: &NodeOrToken) -> bool {
+        if self.ranges.is_empty() {
+            return false;
+        }
+        let range = item.range();
+        let mut errors = self.ranges.iter().filter(|err| overlaps(err, &range));
+        match block_statements(item) {
+            Some(statements) => {
+                errors.any(|err| !statements.iter().any(|stmt| overlaps(err, stmt)))
+            }
+            None => errors.next().is_some(),
+        }
+    }
+}
+
+/// The ranges of the statements in a block, if this is a block where each
+/// statement is processed individually.
+fn block_statements(item: &NodeOrToken) -> Option<Vec<Range<usize>>> {
+    let statements: Vec<_> = if let Some(feature) = typed::Feature::cast(item) {
+        // these features are processed as a unit
+        let tag = feature.tag().to_raw();
+        if tag == tags::AALT
+            || tag == tags::SIZE
+            || tags::is_stylistic_set(tag)
+            || tags::is_character_variant(tag)
+        {
+            return None;
+        }
+        feature.statements().map(NodeOrToken::range).collect()
+    } else if let Some(lookup) = typed::LookupBlock::cast(item) {
+        lookup.statements().map(NodeOrToken::range).collect()
+    } else if let Some(variation) = typed::FeatureVariation::cast(item) {
+        variation.statements().map(NodeOrToken::range).collect()
+    } else {
+        return None;
+    };
+    Some(statements)
+}
+
+// empty error ranges (such as for a missing token) are considered to overlap
+// the item that they immediately follow.
+fn overlaps(err: &Range<usize>, item: &Range<usize>) -> bool {
+    if err.is_empty() {
+        item.start <= err.start && err.start <= item.end
+    } else {
+        err.start < item.end && item.start < err.end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_ranges_overlap_preceding_item() {
+        assert!(overlaps(&(10..10), &(5..10)));
+        assert!(overlaps(&(7..7), &(5..10)));
+        assert!(!overlaps(&(11..11), &(5..10)));
+        assert!(!overlaps(&(10..12), &(5..10)));
+        assert!(overlaps(&(9..12), &(5..10)));
+    }
+}
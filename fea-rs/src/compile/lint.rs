@@ -0,0 +1,133 @@
+//! Configuring how warnings are reported.
+
+use std::collections::HashMap;
+
+use crate::{Diagnostic, DiagnosticCode, Level};
+
+/// What to do with warnings of a given kind.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum LintLevel {
+    /// Silently ignore the warning
+    Allow,
+    /// Report a warning (the default)
+    #[default]
+    Warn,
+    /// Report an error, so that compilation fails
+    Deny,
+}
+
+/// Configuration for promoting or silencing warnings, by [`DiagnosticCode`].
+///
+/// This only affects diagnostics that are warnings; errors are always
+/// reported.
+///
+/// ```
+/// # use fea_rs::{compile::{LintConfig, LintLevel}, DiagnosticCode};
+/// // fail on any warning, except for duplicate glyph classes
+/// let config = LintConfig::new()
+///     .with_default_level(LintLevel::Deny)
+///     .with_level(DiagnosticCode::DuplicateGlyphClass, LintLevel::Allow);
+/// assert_eq!(config.level(Some(DiagnosticCode::EmptyLookup)), LintLevel::Deny);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LintConfig {
+    default_level: LintLevel,
+    levels: HashMap<DiagnosticCode, LintLevel>,
+}
+
+impl LintConfig {
+    /// Create a new configuration, where all warnings are reported as warnings.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Set the level for warnings that have no more specific level.
+    ///
+    /// This also applies to warnings that don't have a code.
+    pub fn with_default_level(mut self, level: LintLevel) -> Self {
+        self.default_level = level;
+        self
+    }
+
+    /// Set the level for warnings with a specific code.
+    pub fn with_level(mut self, code: DiagnosticCode, level: LintLevel) -> Self {
+        self.levels.insert(code, level);
+        self
+    }
+
+    /// The level for warnings with the given code.
+    pub fn level(&self, code: Option<DiagnosticCode>) -> LintLevel {
+        code.and_then(|code| self.levels.get(&code).copied())
+            .unwrap_or(self.default_level)
+    }
+
+    /// Remove allowed warnings, and turn denied warnings into errors.
+    ///
+    /// This is applied automatically by the [`Compiler`][super::Compiler];
+    /// it is useful when diagnostics are collected some other way, such as
+    /// by calling [`validate`][super::validate] directly.
+    pub fn apply(&self, diagnostics: &mut Vec<Diagnostic>) {
+        diagnostics.retain_mut(|diagnostic| {
+            if diagnostic.level != Level::Warning {
+                return true;
+            }
+            match self.level(diagnostic.code) {
+                LintLevel::Allow => false,
+                LintLevel::Warn => true,
+                LintLevel::Deny => {
+                    diagnostic.level = Level::Error;
+                    true
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse::FileId;
+
+    use super::*;
+
+    fn warning(code: Option<DiagnosticCode>) -> Diagnostic {
+        let mut warning = Diagnostic::warning(FileId::CURRENT_FILE, 0..1, "oops");
+        warning.code = code;
+        warning
+    }
+
+    #[test]
+    fn apply_levels() {
+        let config = LintConfig::new()
+            .with_level(DiagnosticCode::EmptyLookup, LintLevel::Allow)
+            .with_level(DiagnosticCode::DuplicateGlyphClass, LintLevel::Deny);
+        let mut diagnostics = vec![
+            warning(Some(DiagnosticCode::EmptyLookup)),
+            warning(Some(DiagnosticCode::DuplicateGlyphClass)),
+            warning(Some(DiagnosticCode::DuplicateAnchor)),
+            warning(None),
+            Diagnostic::error(FileId::CURRENT_FILE, 0..1, "bad"),
+        ];
+        config.apply(&mut diagnostics);
+        let levels = diagnostics
+            .iter()
+            .map(|diag| (diag.code, diag.level))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            levels,
+            [
+                (Some(DiagnosticCode::DuplicateGlyphClass), Level::Error),
+                (Some(DiagnosticCode::DuplicateAnchor), Level::Warning),
+                (None, Level::Warning),
+                (None, Level::Error),
+            ]
+        );
+    }
+
+    #[test]
+    fn code_names_round_trip() {
+        for code in DiagnosticCode::ALL {
+            assert_eq!(code.name().parse::<DiagnosticCode>(), Ok(*code));
+        }
+        assert!("not-a-code".parse::<DiagnosticCode>().is_err());
+    }
+}
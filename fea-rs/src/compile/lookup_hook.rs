@@ -0,0 +1,835 @@
+//! Removing compiled lookups, for use in post-compilation hooks.
+//!
+//! See [`Compiler::with_lookup_hook`][super::Compiler::with_lookup_hook].
+//!
+//! This also implements merging duplicate lookups; see
+//! [`Opts::dedupe_lookups`][super::Opts::dedupe_lookups].
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    hash::Hash,
+};
+
+use write_fonts::{
+    tables::{
+        gpos::{self as wgpos, PositionLookup},
+        gsub::{self as wgsub, SubstitutionLookup},
+        layout::{
+            ChainedSequenceContext, FeatureList, FeatureVariations, Lookup, LookupFlag, LookupList,
+            ScriptList, SequenceContext, SequenceLookupRecord,
+        },
+    },
+    types::Tag,
+};
+
+use super::{tags, Compilation};
+
+/// Information about a compiled lookup, passed to the predicate of
+/// [`Compilation::retain_lookups`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct LookupInfo {
+    /// The table containing the lookup, either `GSUB` or `GPOS`
+    pub table: Tag,
+    /// The index of the lookup in the table's lookup list
+    pub index: u16,
+    /// The lookup type.
+    ///
+    /// For extension lookups, this is the type of the wrapped subtables.
+    pub lookup_type: u16,
+    /// The tags of the features that reference this lookup, sorted.
+    ///
+    /// This is empty for lookups that are only referenced from contextual
+    /// lookups, or not referenced at all.
+    pub features: Vec<Tag>,
+}
+
+impl Compilation {
+    /// Remove the `GSUB` and `GPOS` lookups for which `keep` returns `false`.
+    ///
+    /// Lookup indices in features, feature variations, and contextual lookups
+    /// are updated to match; references to removed lookups are dropped, and
+    /// features that no longer contain any lookups (and have no parameters)
+    /// are removed. For instance, to keep only kerning:
+    ///
+    /// ```no_run
+    /// # fn get_compilation() -> fea_rs::compile::Compilation { todo!() }
+    /// use write_fonts::types::Tag;
+    ///
+    /// let mut compilation = get_compilation();
+    /// compilation.retain_lookups(|info| info.features.contains(&Tag::new(b"kern")));
+    /// ```
+    ///
+    /// A lookup that is only used by a contextual lookup has no features, so
+    /// a predicate like this one also removes it; check [`LookupInfo::features`]
+    /// and [`LookupInfo::lookup_type`] to decide how to handle these.
+    pub fn retain_lookups(&mut self, mut keep: impl FnMut(&LookupInfo) -> bool) {
+        if let Some(gsub) = self.gsub.as_mut() {
+            let wgsub::Gsub {
+                script_list,
+                feature_list,
+                lookup_list,
+                feature_variations,
+            } = gsub;
+            retain_in_table(
+                tags::GSUB,
+                script_list,
+                feature_list,
+                feature_variations.as_mut(),
+                lookup_list,
+                &mut keep,
+            );
+        }
+        if let Some(gpos) = self.gpos.as_mut() {
+            let wgpos::Gpos {
+                script_list,
+                feature_list,
+                lookup_list,
+                feature_variations,
+            } = gpos;
+            retain_in_table(
+                tags::GPOS,
+                script_list,
+                feature_list,
+                feature_variations.as_mut(),
+                lookup_list,
+                &mut keep,
+            );
+        }
+    }
+}
+
+/// The parts of a lookup we need to know about to remove or merge lookups.
+pub(super) trait LayoutLookup {
+    fn lookup_type(&self) -> u16;
+    /// Update the lookup indices in any nested lookup records.
+    ///
+    /// Records for which `remap` returns `None` are removed.
+    fn remap_lookup_records(&mut self, remap: &dyn Fn(u16) -> Option<u16>);
+    /// The lookup's flags and mark filtering set index.
+    fn flags_mut(&mut self) -> (&mut LookupFlag, &mut u16);
+}
+
+fn retain_in_table<T: LayoutLookup>(
+    table: Tag,
+    script_list: &mut ScriptList,
+    feature_list: &mut FeatureList,
+    feature_variations: Option<&mut FeatureVariations>,
+    lookup_list: &mut LookupList<T>,
+    keep: &mut impl FnMut(&LookupInfo) -> bool,
+) {
+    let mut features = vec![BTreeSet::new(); lookup_list.lookups.len()];
+    for record in &feature_list.feature_records {
+        for idx in &record.feature.lookup_list_indices {
+            if let Some(tags) = features.get_mut(*idx as usize) {
+                tags.insert(record.feature_tag);
+            }
+        }
+    }
+    for substitution in feature_variations
+        .iter()
+        .flat_map(|variations| variations.feature_variation_records.iter())
+        .filter_map(|record| record.feature_table_substitution.as_ref())
+        .flat_map(|subst| subst.substitutions.iter())
+    {
+        let Some(record) = feature_list
+            .feature_records
+            .get(substitution.feature_index as usize)
+        else {
+            continue;
+        };
+        for idx in &substitution.alternate_feature.lookup_list_indices {
+            if let Some(tags) = features.get_mut(*idx as usize) {
+                tags.insert(record.feature_tag);
+            }
+        }
+    }
+
+    // the new index of each lookup, or `None` if it is removed
+    let mut new_indices = Vec::with_capacity(lookup_list.lookups.len());
+    let mut next_index = 0u16;
+    for (i, (lookup, features)) in lookup_list.lookups.iter().zip(features).enumerate() {
+        let info = LookupInfo {
+            table,
+            index: i.try_into().unwrap(),
+            lookup_type: lookup.lookup_type(),
+            features: features.into_iter().collect(),
+        };
+        if keep(&info) {
+            new_indices.push(Some(next_index));
+            next_index += 1;
+        } else {
+            new_indices.push(None);
+        }
+    }
+    remap_lookups_in_table(
+        script_list,
+        feature_list,
+        feature_variations,
+        lookup_list,
+        &new_indices,
+    );
+}
+
+/// Merge identical lookups in `GSUB`.
+///
+/// See [`Opts::dedupe_lookups`][super::Opts::dedupe_lookups].
+pub(crate) fn dedupe_gsub_lookups(gsub: &mut wgsub::Gsub) {
+    let wgsub::Gsub {
+        script_list,
+        feature_list,
+        lookup_list,
+        feature_variations,
+    } = gsub;
+    dedupe_in_table(
+        script_list,
+        feature_list,
+        feature_variations.as_mut(),
+        lookup_list,
+    );
+}
+
+/// Merge identical lookups in `GPOS`.
+///
+/// See [`Opts::dedupe_lookups`][super::Opts::dedupe_lookups].
+pub(crate) fn dedupe_gpos_lookups(gpos: &mut wgpos::Gpos) {
+    let wgpos::Gpos {
+        script_list,
+        feature_list,
+        lookup_list,
+        feature_variations,
+    } = gpos;
+    dedupe_in_table(
+        script_list,
+        feature_list,
+        feature_variations.as_mut(),
+        lookup_list,
+    );
+}
+
+fn dedupe_in_table<T: LayoutLookup + Eq + Hash>(
+    script_list: &mut ScriptList,
+    feature_list: &mut FeatureList,
+    mut feature_variations: Option<&mut FeatureVariations>,
+    lookup_list: &mut LookupList<T>,
+) {
+    // merging lookups can make contextual lookups that reference them
+    // identical, so we repeat until nothing changes.
+    loop {
+        let new_indices = find_duplicate_lookups(
+            script_list,
+            feature_list,
+            feature_variations.as_deref(),
+            lookup_list,
+        );
+        if new_indices
+            .iter()
+            .enumerate()
+            .all(|(i, idx)| *idx == Some(i as u16))
+        {
+            break;
+        }
+        remap_lookups_in_table(
+            script_list,
+            feature_list,
+            feature_variations.as_deref_mut(),
+            lookup_list,
+            &new_indices,
+        );
+    }
+}
+
+/// Find lookups that can be merged into an identical earlier lookup.
+///
+/// Returns the new index of each lookup, in the form expected by
+/// [`remap_lookups_in_table`].
+///
+/// Lookups are applied in lookup list order, and each lookup is applied at
+/// most once for a given language system. Merging a lookup into an earlier
+/// one moves it to the earlier position, and so we only do this if no
+/// language system also applies a lookup between the two, or the earlier
+/// lookup itself.
+fn find_duplicate_lookups<T: Eq + Hash>(
+    script_list: &ScriptList,
+    feature_list: &FeatureList,
+    feature_variations: Option<&FeatureVariations>,
+    lookup_list: &LookupList<T>,
+) -> Vec<Option<u16>> {
+    let feature_lookups = |feature_idx: u16| {
+        let base = feature_list
+            .feature_records
+            .get(feature_idx as usize)
+            .map(|record| record.feature.lookup_list_indices.as_slice())
+            .unwrap_or_default();
+        let alternates = feature_variations
+            .into_iter()
+            .flat_map(|variations| variations.feature_variation_records.iter())
+            .filter_map(|record| record.feature_table_substitution.as_ref())
+            .flat_map(|subst| subst.substitutions.iter())
+            .filter(move |subst| subst.feature_index == feature_idx)
+            .flat_map(|subst| subst.alternate_feature.lookup_list_indices.iter());
+        base.iter().chain(alternates).copied()
+    };
+
+    // the lookups that may be applied by each language system
+    let mut lang_sys_lookups = Vec::new();
+    for script in &script_list.script_records {
+        let script = &*script.script;
+        let lang_systems = script
+            .default_lang_sys
+            .as_ref()
+            .into_iter()
+            .chain(script.lang_sys_records.iter().map(|rec| &*rec.lang_sys));
+        for lang_sys in lang_systems {
+            let required = (lang_sys.required_feature_index != 0xFFFF)
+                .then_some(lang_sys.required_feature_index);
+            let lookups = lang_sys
+                .feature_indices
+                .iter()
+                .copied()
+                .chain(required)
+                .flat_map(feature_lookups)
+                .collect::<BTreeSet<_>>();
+            lang_sys_lookups.push(lookups);
+        }
+    }
+
+    // the index of the lookup each lookup is merged into
+    let mut targets = Vec::with_capacity(lookup_list.lookups.len());
+    let mut first_seen = HashMap::new();
+    for (i, lookup) in lookup_list.lookups.iter().enumerate() {
+        let i = i as u16;
+        let Some(&prev) = first_seen.get(&**lookup) else {
+            first_seen.insert(&**lookup, i);
+            targets.push(i);
+            continue;
+        };
+        let can_merge = lang_sys_lookups
+            .iter()
+            .filter(|lookups| lookups.contains(&i))
+            .all(|lookups| lookups.range(prev..i).next().is_none());
+        if can_merge {
+            for lookups in lang_sys_lookups.iter_mut() {
+                if lookups.remove(&i) {
+                    lookups.insert(prev);
+                }
+            }
+            targets.push(prev);
+        } else {
+            targets.push(i);
+        }
+    }
+
+    let mut new_indices: Vec<Option<u16>> = Vec::with_capacity(targets.len());
+    let mut next_index = 0u16;
+    for (i, target) in targets.into_iter().enumerate() {
+        if target as usize == i {
+            new_indices.push(Some(next_index));
+            next_index += 1;
+        } else {
+            new_indices.push(new_indices[target as usize]);
+        }
+    }
+    new_indices
+}
+
+/// Remove and merge lookups, updating all references to them.
+///
+/// `new_indices` contains the new index of each lookup, or `None` if it is
+/// removed. A lookup with the same new index as an earlier lookup is merged
+/// into that lookup: it is removed, and references to it are updated to
+/// point to the earlier lookup.
+fn remap_lookups_in_table<T: LayoutLookup>(
+    script_list: &mut ScriptList,
+    feature_list: &mut FeatureList,
+    mut feature_variations: Option<&mut FeatureVariations>,
+    lookup_list: &mut LookupList<T>,
+    new_indices: &[Option<u16>],
+) {
+    if new_indices
+        .iter()
+        .enumerate()
+        .all(|(i, new_idx)| *new_idx == Some(i as u16))
+    {
+        return;
+    }
+
+    let remap = |idx: u16| new_indices.get(idx as usize).copied().flatten();
+    let remap_indices = |indices: &mut Vec<u16>| {
+        *indices = indices.iter().copied().filter_map(remap).collect();
+    };
+
+    let mut seen = HashSet::new();
+    let old_lookups = std::mem::take(&mut lookup_list.lookups);
+    lookup_list.lookups = old_lookups
+        .into_iter()
+        .zip(new_indices)
+        .filter(|(_, new_idx)| new_idx.is_some_and(|idx| seen.insert(idx)))
+        .map(|(mut lookup, _)| {
+            lookup.remap_lookup_records(&remap);
+            lookup
+        })
+        .collect();
+
+    // remove any features that are emptied, unless they have parameters or
+    // are replaced by a non-empty feature in some feature variation
+    let mut keep_features = Vec::with_capacity(feature_list.feature_records.len());
+    for record in feature_list.feature_records.iter_mut() {
+        let was_empty = record.feature.lookup_list_indices.is_empty();
+        remap_indices(&mut record.feature.lookup_list_indices);
+        keep_features.push(
+            was_empty
+                || !record.feature.lookup_list_indices.is_empty()
+                || record.feature.feature_params.is_some(),
+        );
+    }
+    for substitution in feature_variations
+        .iter_mut()
+        .flat_map(|variations| variations.feature_variation_records.iter_mut())
+        .filter_map(|record| record.feature_table_substitution.as_mut())
+        .flat_map(|subst| subst.substitutions.iter_mut())
+    {
+        remap_indices(&mut substitution.alternate_feature.lookup_list_indices);
+        if !substitution
+            .alternate_feature
+            .lookup_list_indices
+            .is_empty()
+        {
+            if let Some(keep) = keep_features.get_mut(substitution.feature_index as usize) {
+                *keep = true;
+            }
+        }
+    }
+    if keep_features.iter().all(|keep| *keep) {
+        return;
+    }
+
+    let mut new_feature_indices = Vec::with_capacity(keep_features.len());
+    let mut next_index = 0u16;
+    for keep in &keep_features {
+        new_feature_indices.push(keep.then_some(next_index));
+        next_index += *keep as u16;
+    }
+    let remap_feature = |idx: u16| new_feature_indices.get(idx as usize).copied().flatten();
+
+    let old_features = std::mem::take(&mut feature_list.feature_records);
+    feature_list.feature_records = old_features
+        .into_iter()
+        .zip(&keep_features)
+        .filter_map(|(record, keep)| keep.then_some(record))
+        .collect();
+
+    for script in script_list.script_records.iter_mut() {
+        let script = &mut *script.script;
+        let lang_systems = script.default_lang_sys.as_mut().into_iter().chain(
+            script
+                .lang_sys_records
+                .iter_mut()
+                .map(|rec| &mut *rec.lang_sys),
+        );
+        for lang_sys in lang_systems {
+            lang_sys.feature_indices = lang_sys
+                .feature_indices
+                .iter()
+                .copied()
+                .filter_map(remap_feature)
+                .collect();
+            if lang_sys.required_feature_index != 0xFFFF {
+                lang_sys.required_feature_index =
+                    remap_feature(lang_sys.required_feature_index).unwrap_or(0xFFFF);
+            }
+        }
+    }
+
+    for subst in feature_variations
+        .iter_mut()
+        .flat_map(|variations| variations.feature_variation_records.iter_mut())
+        .filter_map(|record| record.feature_table_substitution.as_mut())
+    {
+        subst
+            .substitutions
+            .retain_mut(|record| match remap_feature(record.feature_index) {
+                Some(idx) => {
+                    record.feature_index = idx;
+                    true
+                }
+                None => false,
+            });
+    }
+}
+
+fn flags<T>(lookup: &mut Lookup<T>) -> (&mut LookupFlag, &mut u16) {
+    (&mut lookup.lookup_flag, &mut lookup.mark_filtering_set)
+}
+
+fn remap_records(records: &mut Vec<SequenceLookupRecord>, remap: &dyn Fn(u16) -> Option<u16>) {
+    records.retain_mut(|record| match remap(record.lookup_list_index) {
+        Some(idx) => {
+            record.lookup_list_index = idx;
+            true
+        }
+        None => false,
+    })
+}
+
+fn remap_sequence_context(context: &mut SequenceContext, remap: &dyn Fn(u16) -> Option<u16>) {
+    match context {
+        SequenceContext::Format1(table) => table
+            .seq_rule_sets
+            .iter_mut()
+            .filter_map(|set| set.as_mut())
+            .flat_map(|set| set.seq_rules.iter_mut())
+            .for_each(|rule| remap_records(&mut rule.seq_lookup_records, remap)),
+        SequenceContext::Format2(table) => table
+            .class_seq_rule_sets
+            .iter_mut()
+            .filter_map(|set| set.as_mut())
+            .flat_map(|set| set.class_seq_rules.iter_mut())
+            .for_each(|rule| remap_records(&mut rule.seq_lookup_records, remap)),
+        SequenceContext::Format3(table) => remap_records(&mut table.seq_lookup_records, remap),
+    }
+}
+
+fn remap_chained_context(context: &mut ChainedSequenceContext, remap: &dyn Fn(u16) -> Option<u16>) {
+    match context {
+        ChainedSequenceContext::Format1(table) => table
+            .chained_seq_rule_sets
+            .iter_mut()
+            .filter_map(|set| set.as_mut())
+            .flat_map(|set| set.chained_seq_rules.iter_mut())
+            .for_each(|rule| remap_records(&mut rule.seq_lookup_records, remap)),
+        ChainedSequenceContext::Format2(table) => table
+            .chained_class_seq_rule_sets
+            .iter_mut()
+            .filter_map(|set| set.as_mut())
+            .flat_map(|set| set.chained_class_seq_rules.iter_mut())
+            .for_each(|rule| remap_records(&mut rule.seq_lookup_records, remap)),
+        ChainedSequenceContext::Format3(table) => {
+            remap_records(&mut table.seq_lookup_records, remap)
+        }
+    }
+}
+
+impl LayoutLookup for SubstitutionLookup {
+    fn lookup_type(&self) -> u16 {
+        match self {
+            SubstitutionLookup::Single(_) => 1,
+            SubstitutionLookup::Multiple(_) => 2,
+            SubstitutionLookup::Alternate(_) => 3,
+            SubstitutionLookup::Ligature(_) => 4,
+            SubstitutionLookup::Contextual(_) => 5,
+            SubstitutionLookup::ChainContextual(_) => 6,
+            SubstitutionLookup::Extension(lookup) => lookup
+                .subtables
+                .first()
+                .map(|sub| match &**sub {
+                    wgsub::ExtensionSubtable::Single(sub) => sub.extension_lookup_type,
+                    wgsub::ExtensionSubtable::Multiple(sub) => sub.extension_lookup_type,
+                    wgsub::ExtensionSubtable::Alternate(sub) => sub.extension_lookup_type,
+                    wgsub::ExtensionSubtable::Ligature(sub) => sub.extension_lookup_type,
+                    wgsub::ExtensionSubtable::Contextual(sub) => sub.extension_lookup_type,
+                    wgsub::ExtensionSubtable::ChainContextual(sub) => sub.extension_lookup_type,
+                    wgsub::ExtensionSubtable::Reverse(sub) => sub.extension_lookup_type,
+                })
+                .unwrap_or(7),
+            SubstitutionLookup::Reverse(_) => 8,
+        }
+    }
+
+    fn remap_lookup_records(&mut self, remap: &dyn Fn(u16) -> Option<u16>) {
+        match self {
+            SubstitutionLookup::Contextual(lookup) => lookup
+                .subtables
+                .iter_mut()
+                .for_each(|sub| remap_sequence_context(sub, remap)),
+            SubstitutionLookup::ChainContextual(lookup) => lookup
+                .subtables
+                .iter_mut()
+                .for_each(|sub| remap_chained_context(sub, remap)),
+            SubstitutionLookup::Extension(lookup) => {
+                for sub in lookup.subtables.iter_mut() {
+                    match &mut **sub {
+                        wgsub::ExtensionSubtable::Contextual(sub) => {
+                            remap_sequence_context(&mut sub.extension, remap)
+                        }
+                        wgsub::ExtensionSubtable::ChainContextual(sub) => {
+                            remap_chained_context(&mut sub.extension, remap)
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn flags_mut(&mut self) -> (&mut LookupFlag, &mut u16) {
+        match self {
+            SubstitutionLookup::Single(lookup) => flags(lookup),
+            SubstitutionLookup::Multiple(lookup) => flags(lookup),
+            SubstitutionLookup::Alternate(lookup) => flags(lookup),
+            SubstitutionLookup::Ligature(lookup) => flags(lookup),
+            SubstitutionLookup::Contextual(lookup) => flags(lookup),
+            SubstitutionLookup::ChainContextual(lookup) => flags(lookup),
+            SubstitutionLookup::Extension(lookup) => flags(lookup),
+            SubstitutionLookup::Reverse(lookup) => flags(lookup),
+        }
+    }
+}
+
+impl LayoutLookup for PositionLookup {
+    fn lookup_type(&self) -> u16 {
+        match self {
+            PositionLookup::Single(_) => 1,
+            PositionLookup::Pair(_) => 2,
+            PositionLookup::Cursive(_) => 3,
+            PositionLookup::MarkToBase(_) => 4,
+            PositionLookup::MarkToLig(_) => 5,
+            PositionLookup::MarkToMark(_) => 6,
+            PositionLookup::Contextual(_) => 7,
+            PositionLookup::ChainContextual(_) => 8,
+            PositionLookup::Extension(lookup) => lookup
+                .subtables
+                .first()
+                .map(|sub| match &**sub {
+                    wgpos::ExtensionSubtable::Single(sub) => sub.extension_lookup_type,
+                    wgpos::ExtensionSubtable::Pair(sub) => sub.extension_lookup_type,
+                    wgpos::ExtensionSubtable::Cursive(sub) => sub.extension_lookup_type,
+                    wgpos::ExtensionSubtable::MarkToBase(sub) => sub.extension_lookup_type,
+                    wgpos::ExtensionSubtable::MarkToLig(sub) => sub.extension_lookup_type,
+                    wgpos::ExtensionSubtable::MarkToMark(sub) => sub.extension_lookup_type,
+                    wgpos::ExtensionSubtable::Contextual(sub) => sub.extension_lookup_type,
+                    wgpos::ExtensionSubtable::ChainContextual(sub) => sub.extension_lookup_type,
+                })
+                .unwrap_or(9),
+        }
+    }
+
+    fn remap_lookup_records(&mut self, remap: &dyn Fn(u16) -> Option<u16>) {
+        match self {
+            PositionLookup::Contextual(lookup) => lookup
+                .subtables
+                .iter_mut()
+                .for_each(|sub| remap_sequence_context(sub, remap)),
+            PositionLookup::ChainContextual(lookup) => lookup
+                .subtables
+                .iter_mut()
+                .for_each(|sub| remap_chained_context(sub, remap)),
+            PositionLookup::Extension(lookup) => {
+                for sub in lookup.subtables.iter_mut() {
+                    match &mut **sub {
+                        wgpos::ExtensionSubtable::Contextual(sub) => {
+                            remap_sequence_context(&mut sub.extension, remap)
+                        }
+                        wgpos::ExtensionSubtable::ChainContextual(sub) => {
+                            remap_chained_context(&mut sub.extension, remap)
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn flags_mut(&mut self) -> (&mut LookupFlag, &mut u16) {
+        match self {
+            PositionLookup::Single(lookup) => flags(lookup),
+            PositionLookup::Pair(lookup) => flags(lookup),
+            PositionLookup::Cursive(lookup) => flags(lookup),
+            PositionLookup::MarkToBase(lookup) => flags(lookup),
+            PositionLookup::MarkToLig(lookup) => flags(lookup),
+            PositionLookup::MarkToMark(lookup) => flags(lookup),
+            PositionLookup::Contextual(lookup) => flags(lookup),
+            PositionLookup::ChainContextual(lookup) => flags(lookup),
+            PositionLookup::Extension(lookup) => flags(lookup),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{Compiler, GlyphMap, GlyphName};
+
+    static FEA: &str = "\
+languagesystem DFLT dflt;
+lookup single { sub a by b; } single;
+feature liga { sub f i by f_i; } liga;
+feature calt { sub a' c by b; } calt;
+feature smcp { lookup single; } smcp;
+feature kern { pos a b -10; } kern;
+";
+
+    fn compile(hook: impl FnMut(&mut Compilation)) -> Compilation {
+        let glyph_map: GlyphMap = [".notdef", "a", "b", "c", "f", "i", "f_i"]
+            .into_iter()
+            .map(GlyphName::new)
+            .collect();
+        Compiler::new("test.fea", &glyph_map)
+            .with_resolver(|_: &std::ffi::OsStr| Ok(Arc::<str>::from(FEA)))
+            .print_warnings(false)
+            .with_lookup_hook(hook)
+            .compile()
+            .unwrap()
+    }
+
+    fn feature_tags(feature_list: &FeatureList) -> Vec<(String, Vec<u16>)> {
+        feature_list
+            .feature_records
+            .iter()
+            .map(|rec| {
+                (
+                    rec.feature_tag.to_string(),
+                    rec.feature.lookup_list_indices.clone(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn retain_lookups_remaps_indices() {
+        let mut infos = Vec::new();
+        let compilation = compile(|compilation| {
+            compilation.retain_lookups(|info| {
+                infos.push(info.clone());
+                info.table != tags::GSUB || !info.features.contains(&Tag::new(b"liga"))
+            })
+        });
+        let gsub_info = infos
+            .iter()
+            .filter(|info| info.table == tags::GSUB)
+            .map(|info| (info.lookup_type, info.features.len()))
+            .collect::<Vec<_>>();
+        // single (in smcp), ligature (in liga), contextual (in calt), anonymous
+        assert_eq!(gsub_info, [(1, 1), (4, 1), (6, 1), (1, 0)]);
+
+        let gsub = compilation.gsub.unwrap();
+        assert_eq!(gsub.lookup_list.lookups.len(), 3);
+        // liga is gone, and the other features are renumbered
+        assert_eq!(
+            feature_tags(&gsub.feature_list),
+            [("calt".to_string(), vec![1]), ("smcp".to_string(), vec![0])]
+        );
+        let lang_sys = gsub.script_list.script_records[0]
+            .script
+            .default_lang_sys
+            .as_ref()
+            .unwrap();
+        assert_eq!(lang_sys.feature_indices, [0, 1]);
+        // the contextual lookup points at the anonymous lookup's new index
+        let SubstitutionLookup::ChainContextual(calt) = &*gsub.lookup_list.lookups[1] else {
+            panic!("expected contextual lookup");
+        };
+        let ChainedSequenceContext::Format3(calt) = calt.subtables[0].as_inner() else {
+            panic!("expected format 3");
+        };
+        assert_eq!(calt.seq_lookup_records[0].lookup_list_index, 2);
+        // GPOS is untouched
+        assert_eq!(compilation.gpos.unwrap().lookup_list.lookups.len(), 1);
+    }
+
+    fn compile_deduped(fea: &str, dedupe: bool) -> Compilation {
+        let glyph_map: GlyphMap = [".notdef", "a", "b", "c", "f", "i", "f_i"]
+            .into_iter()
+            .map(GlyphName::new)
+            .collect();
+        let fea: Arc<str> = fea.into();
+        Compiler::new("test.fea", &glyph_map)
+            .with_resolver(move |_: &std::ffi::OsStr| Ok(fea.clone()))
+            .with_opts(crate::compile::Opts::new().dedupe_lookups(dedupe))
+            .print_warnings(false)
+            .compile()
+            .unwrap()
+    }
+
+    #[test]
+    fn dedupe_lookups_across_scripts() {
+        let fea = "\
+languagesystem DFLT dflt;
+languagesystem latn dflt;
+feature liga {
+    script DFLT;
+    sub f i by f_i;
+    script latn;
+    sub f i by f_i;
+} liga;
+feature calt {
+    script DFLT;
+    sub a' c by b;
+    script latn;
+    sub a' c by b;
+} calt;
+";
+        let gsub = compile_deduped(fea, false).gsub.unwrap();
+        assert_eq!(gsub.lookup_list.lookups.len(), 6);
+
+        let gsub = compile_deduped(fea, true).gsub.unwrap();
+        // the contextual lookups are only identical once the lookups they
+        // reference have been merged
+        let lookup_types = gsub
+            .lookup_list
+            .lookups
+            .iter()
+            .map(|lookup| lookup.lookup_type())
+            .collect::<Vec<_>>();
+        assert_eq!(lookup_types, [4, 6, 1]);
+        let features = feature_tags(&gsub.feature_list);
+        assert!(features.iter().all(|(tag, indices)| match tag.as_str() {
+            "liga" => indices == &[0],
+            "calt" => indices == &[1],
+            _ => false,
+        }));
+        let SubstitutionLookup::ChainContextual(calt) = &*gsub.lookup_list.lookups[1] else {
+            panic!("expected contextual lookup");
+        };
+        let ChainedSequenceContext::Format3(calt) = calt.subtables[0].as_inner() else {
+            panic!("expected format 3");
+        };
+        assert_eq!(calt.seq_lookup_records[0].lookup_list_index, 2);
+    }
+
+    #[test]
+    fn dedupe_lookups_preserves_order() {
+        // the kern and dist lookups are identical, but merging them would
+        // mean latn applies the second after the mark lookup, not before it.
+        let fea = "\
+languagesystem DFLT dflt;
+languagesystem latn dflt;
+feature kern { script DFLT; pos a b -10; } kern;
+feature mark { pos a c -20; } mark;
+feature dist { script latn; pos a b -10; } dist;
+";
+        let gpos = compile_deduped(fea, true).gpos.unwrap();
+        assert_eq!(gpos.lookup_list.lookups.len(), 3);
+        let fea = fea.replace("pos a c -20;", "");
+        let gpos = compile_deduped(&fea, true).gpos.unwrap();
+        assert_eq!(gpos.lookup_list.lookups.len(), 1);
+
+        // and a lookup applied twice by the same language system is kept
+        let fea = "\
+languagesystem DFLT dflt;
+feature kern { pos a b -10; } kern;
+feature dist { pos a b -10; } dist;
+";
+        let gpos = compile_deduped(fea, true).gpos.unwrap();
+        assert_eq!(gpos.lookup_list.lookups.len(), 2);
+    }
+
+    #[test]
+    fn retain_only_kern() {
+        let kern = Tag::new(b"kern");
+        let compilation =
+            compile(|compilation| compilation.retain_lookups(|info| info.features.contains(&kern)));
+        let gsub = compilation.gsub.unwrap();
+        assert!(gsub.lookup_list.lookups.is_empty());
+        assert!(gsub.feature_list.feature_records.is_empty());
+        let gpos = compilation.gpos.unwrap();
+        assert_eq!(
+            feature_tags(&gpos.feature_list),
+            [("kern".to_string(), vec![0])]
+        );
+    }
+}
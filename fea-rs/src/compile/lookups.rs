@@ -39,6 +39,7 @@ use contextual::{
     ContextualLookupBuilder, PosChainContextBuilder, PosContextBuilder, ReverseChainBuilder,
     SubChainContextBuilder, SubContextBuilder,
 };
+pub(crate) use gpos::compact_pair_pos;
 pub use gpos::PreviouslyAssignedClass;
 use gpos::{
     CursivePosBuilder, MarkToBaseBuilder, MarkToLigBuilder, MarkToMarkBuilder, PairPosBuilder,
@@ -52,12 +53,32 @@ pub trait Builder {
     fn build(self) -> Self::Output;
 }
 
+/// `Send`, if lookups are built in parallel.
+///
+/// This lets us use the same bounds whether or not the `parallel` feature
+/// is enabled.
+#[cfg(feature = "parallel")]
+pub(crate) trait MaybeSend: Send {}
+#[cfg(feature = "parallel")]
+impl<T: Send> MaybeSend for T {}
+#[cfg(not(feature = "parallel"))]
+pub(crate) trait MaybeSend {}
+#[cfg(not(feature = "parallel"))]
+impl<T> MaybeSend for T {}
+
 pub(crate) type FilterSetId = u16;
 
+/// The maximum number of lookups in a GSUB or GPOS table.
+///
+/// The lookup count is stored as a `u16`, and so lookup indices go up to 65534.
+pub(crate) const MAX_LOOKUPS: usize = u16::MAX as usize;
+
 #[derive(Clone, Debug, Default)]
 pub(crate) struct AllLookups {
     current: Option<SomeLookup>,
     current_name: Option<SmolStr>,
+    /// `true` if the current lookup block has the `useExtension` keyword
+    use_extension: bool,
     gpos: Vec<PositionLookup>,
     gsub: Vec<SubstitutionLookup>,
     named: HashMap<SmolStr, LookupId>,
@@ -68,6 +89,8 @@ pub(crate) struct LookupBuilder<T> {
     flags: LookupFlag,
     mark_set: Option<FilterSetId>,
     subtables: Vec<T>,
+    /// if `true`, the subtables are wrapped in extension subtables
+    use_extension: bool,
 }
 
 #[derive(Clone, Debug)]
@@ -154,6 +177,7 @@ impl<T: Default> LookupBuilder<T> {
             flags,
             mark_set,
             subtables: vec![Default::default()],
+            use_extension: false,
         }
     }
 
@@ -166,6 +190,7 @@ impl<T: Default> LookupBuilder<T> {
             flags,
             mark_set,
             subtables,
+            use_extension: false,
         }
     }
 
@@ -198,11 +223,13 @@ impl<U> LookupBuilder<U> {
             flags,
             mark_set,
             subtables,
+            use_extension,
         } = self;
         LookupBuilder {
             flags,
             mark_set,
             subtables: subtables.into_iter().map(Into::into).collect(),
+            use_extension,
         }
     }
 }
@@ -221,6 +248,32 @@ impl PositionLookup {
         }
     }
 
+    fn set_use_extension(&mut self) {
+        match self {
+            PositionLookup::Single(lookup) => lookup.use_extension = true,
+            PositionLookup::Pair(lookup) => lookup.use_extension = true,
+            PositionLookup::Cursive(lookup) => lookup.use_extension = true,
+            PositionLookup::MarkToBase(lookup) => lookup.use_extension = true,
+            PositionLookup::MarkToLig(lookup) => lookup.use_extension = true,
+            PositionLookup::MarkToMark(lookup) => lookup.use_extension = true,
+            PositionLookup::Contextual(lookup) => lookup.use_extension = true,
+            PositionLookup::ChainedContextual(lookup) => lookup.use_extension = true,
+        }
+    }
+
+    fn use_extension(&self) -> bool {
+        match self {
+            PositionLookup::Single(lookup) => lookup.use_extension,
+            PositionLookup::Pair(lookup) => lookup.use_extension,
+            PositionLookup::Cursive(lookup) => lookup.use_extension,
+            PositionLookup::MarkToBase(lookup) => lookup.use_extension,
+            PositionLookup::MarkToLig(lookup) => lookup.use_extension,
+            PositionLookup::MarkToMark(lookup) => lookup.use_extension,
+            PositionLookup::Contextual(lookup) => lookup.use_extension,
+            PositionLookup::ChainedContextual(lookup) => lookup.use_extension,
+        }
+    }
+
     fn update_variation_index_tables(&mut self, key_map: &VariationIndexRemapping) {
         match self {
             PositionLookup::Single(lookup) => lookup.update_variation_index_tables(key_map),
@@ -246,6 +299,30 @@ impl SubstitutionLookup {
             SubstitutionLookup::ChainedContextual(lookup) => lookup.force_subtable_break(),
         }
     }
+
+    fn set_use_extension(&mut self) {
+        match self {
+            SubstitutionLookup::Single(lookup) => lookup.use_extension = true,
+            SubstitutionLookup::Multiple(lookup) => lookup.use_extension = true,
+            SubstitutionLookup::Alternate(lookup) => lookup.use_extension = true,
+            SubstitutionLookup::Ligature(lookup) => lookup.use_extension = true,
+            SubstitutionLookup::Contextual(lookup) => lookup.use_extension = true,
+            SubstitutionLookup::Reverse(lookup) => lookup.use_extension = true,
+            SubstitutionLookup::ChainedContextual(lookup) => lookup.use_extension = true,
+        }
+    }
+
+    fn use_extension(&self) -> bool {
+        match self {
+            SubstitutionLookup::Single(lookup) => lookup.use_extension,
+            SubstitutionLookup::Multiple(lookup) => lookup.use_extension,
+            SubstitutionLookup::Alternate(lookup) => lookup.use_extension,
+            SubstitutionLookup::Ligature(lookup) => lookup.use_extension,
+            SubstitutionLookup::Contextual(lookup) => lookup.use_extension,
+            SubstitutionLookup::Reverse(lookup) => lookup.use_extension,
+            SubstitutionLookup::ChainedContextual(lookup) => lookup.use_extension,
+        }
+    }
 }
 
 impl<U, T> Builder for LookupBuilder<T>
@@ -269,7 +346,8 @@ impl Builder for PositionLookup {
     type Output = write_gpos::PositionLookup;
 
     fn build(self) -> Self::Output {
-        match self {
+        let use_extension = self.use_extension();
+        let lookup = match self {
             PositionLookup::Single(lookup) => write_gpos::PositionLookup::Single(lookup.build()),
             PositionLookup::Pair(lookup) => write_gpos::PositionLookup::Pair(lookup.build()),
             PositionLookup::Cursive(lookup) => write_gpos::PositionLookup::Cursive(lookup.build()),
@@ -288,6 +366,11 @@ impl Builder for PositionLookup {
             PositionLookup::ChainedContextual(lookup) => {
                 write_gpos::PositionLookup::ChainContextual(lookup.build().into_concrete())
             }
+        };
+        if use_extension {
+            into_gpos_extension(lookup)
+        } else {
+            lookup
         }
     }
 }
@@ -296,7 +379,8 @@ impl Builder for SubstitutionLookup {
     type Output = write_gsub::SubstitutionLookup;
 
     fn build(self) -> Self::Output {
-        match self {
+        let use_extension = self.use_extension();
+        let lookup = match self {
             SubstitutionLookup::Single(lookup) => {
                 write_gsub::SubstitutionLookup::Single(lookup.build())
             }
@@ -318,12 +402,110 @@ impl Builder for SubstitutionLookup {
             SubstitutionLookup::Reverse(lookup) => {
                 write_gsub::SubstitutionLookup::Reverse(lookup.build())
             }
+        };
+        if use_extension {
+            into_gsub_extension(lookup)
+        } else {
+            lookup
         }
     }
 }
 
+/// Convert a lookup into an extension lookup (GPOS type 9.)
+///
+/// This is only needed for lookups with the `useExtension` keyword: when
+/// serializing, write-fonts already promotes lookups to extension lookups
+/// if this is necessary to avoid offset overflows.
+fn into_gpos_extension(lookup: write_gpos::PositionLookup) -> write_gpos::PositionLookup {
+    use write_gpos::{ExtensionPosFormat1 as Ext, ExtensionSubtable, PositionLookup as Lookup};
+    let lookup = match lookup {
+        Lookup::Single(lookup) => {
+            wrap_subtables(lookup, |sub| ExtensionSubtable::Single(Ext::new(1, sub)))
+        }
+        Lookup::Pair(lookup) => {
+            wrap_subtables(lookup, |sub| ExtensionSubtable::Pair(Ext::new(2, sub)))
+        }
+        Lookup::Cursive(lookup) => {
+            wrap_subtables(lookup, |sub| ExtensionSubtable::Cursive(Ext::new(3, sub)))
+        }
+        Lookup::MarkToBase(lookup) => wrap_subtables(lookup, |sub| {
+            ExtensionSubtable::MarkToBase(Ext::new(4, sub))
+        }),
+        Lookup::MarkToLig(lookup) => {
+            wrap_subtables(lookup, |sub| ExtensionSubtable::MarkToLig(Ext::new(5, sub)))
+        }
+        Lookup::MarkToMark(lookup) => wrap_subtables(lookup, |sub| {
+            ExtensionSubtable::MarkToMark(Ext::new(6, sub))
+        }),
+        Lookup::Contextual(lookup) => wrap_subtables(lookup, |sub| {
+            ExtensionSubtable::Contextual(Ext::new(7, sub))
+        }),
+        Lookup::ChainContextual(lookup) => wrap_subtables(lookup, |sub| {
+            ExtensionSubtable::ChainContextual(Ext::new(8, sub))
+        }),
+        Lookup::Extension(lookup) => lookup,
+    };
+    Lookup::Extension(lookup)
+}
+
+/// Convert a lookup into an extension lookup (GSUB type 7.)
+fn into_gsub_extension(lookup: write_gsub::SubstitutionLookup) -> write_gsub::SubstitutionLookup {
+    use write_gsub::{
+        ExtensionSubstFormat1 as Ext, ExtensionSubtable, SubstitutionLookup as Lookup,
+    };
+    let lookup = match lookup {
+        Lookup::Single(lookup) => {
+            wrap_subtables(lookup, |sub| ExtensionSubtable::Single(Ext::new(1, sub)))
+        }
+        Lookup::Multiple(lookup) => {
+            wrap_subtables(lookup, |sub| ExtensionSubtable::Multiple(Ext::new(2, sub)))
+        }
+        Lookup::Alternate(lookup) => {
+            wrap_subtables(lookup, |sub| ExtensionSubtable::Alternate(Ext::new(3, sub)))
+        }
+        Lookup::Ligature(lookup) => {
+            wrap_subtables(lookup, |sub| ExtensionSubtable::Ligature(Ext::new(4, sub)))
+        }
+        Lookup::Contextual(lookup) => wrap_subtables(lookup, |sub| {
+            ExtensionSubtable::Contextual(Ext::new(5, sub))
+        }),
+        Lookup::ChainContextual(lookup) => wrap_subtables(lookup, |sub| {
+            ExtensionSubtable::ChainContextual(Ext::new(6, sub))
+        }),
+        Lookup::Reverse(lookup) => {
+            wrap_subtables(lookup, |sub| ExtensionSubtable::Reverse(Ext::new(8, sub)))
+        }
+        Lookup::Extension(lookup) => lookup,
+    };
+    Lookup::Extension(lookup)
+}
+
+fn wrap_subtables<T, U>(lookup: RawLookup<T>, wrap: impl Fn(T) -> U) -> RawLookup<U> {
+    RawLookup::new(
+        lookup.lookup_flag,
+        lookup
+            .subtables
+            .into_iter()
+            .map(|sub| wrap(sub.into_inner()))
+            .collect(),
+        lookup.mark_filtering_set,
+    )
+}
+
 impl AllLookups {
     fn push(&mut self, lookup: SomeLookup) -> LookupId {
+        let id = self.push_impl(lookup);
+        if self.use_extension {
+            match id {
+                LookupId::Gpos(idx) => self.gpos[idx].set_use_extension(),
+                LookupId::Gsub(idx) => self.gsub[idx].set_use_extension(),
+                LookupId::Empty => (),
+            }
+        }
+        id
+    }
+
+    fn push_impl(&mut self, lookup: SomeLookup) -> LookupId {
         match lookup {
             SomeLookup::GsubLookup(sub) => {
                 self.gsub.push(sub);
@@ -368,6 +550,27 @@ impl AllLookups {
         }
     }
 
+    /// The number of GSUB and GPOS lookups allocated so far.
+    ///
+    /// This includes the current lookup, and any anonymous lookups that have
+    /// been created for its inline rules.
+    pub(crate) fn lookup_counts(&self) -> (usize, usize) {
+        let (mut gsub, mut gpos) = (self.gsub.len(), self.gpos.len());
+        match self.current.as_ref() {
+            Some(SomeLookup::GsubLookup(_)) => gsub += 1,
+            Some(SomeLookup::GposLookup(_)) => gpos += 1,
+            Some(SomeLookup::GsubContextual(lookup)) => gsub += 1 + lookup.anon_lookup_count(),
+            Some(SomeLookup::GposContextual(lookup)) => gpos += 1 + lookup.anon_lookup_count(),
+            None => (),
+        }
+        (gsub, gpos)
+    }
+
+    /// The name of the current lookup block, if we are in one.
+    pub(crate) fn current_name(&self) -> Option<&SmolStr> {
+        self.current_name.as_ref()
+    }
+
     pub(crate) fn get_named(&self, name: &str) -> Option<LookupId> {
         self.named.get(name).copied()
     }
@@ -380,6 +583,11 @@ impl AllLookups {
         self.current.is_some()
     }
 
+    /// `true` if we are currently in a named lookup block
+    pub(crate) fn is_named(&self) -> bool {
+        self.current_name.is_some()
+    }
+
     /// Returns `true` if there is an active lookup of this kind
     pub(crate) fn has_current_kind(&self, kind: Kind) -> bool {
         self.current.as_ref().map(SomeLookup::kind) == Some(kind)
@@ -401,8 +609,9 @@ impl AllLookups {
     }
 
     // doesn't start it, just stashes the name
-    pub(crate) fn start_named(&mut self, name: SmolStr) {
+    pub(crate) fn start_named(&mut self, name: SmolStr, use_extension: bool) {
         self.current_name = Some(name);
+        self.use_extension = use_extension;
     }
 
     pub(crate) fn start_lookup(&mut self, kind: Kind, flags: LookupFlagInfo) -> Option<LookupId> {
@@ -428,6 +637,7 @@ impl AllLookups {
     pub(crate) fn finish_current(&mut self) -> Option<(LookupId, Option<SmolStr>)> {
         if let Some(lookup) = self.current.take() {
             let id = self.push(lookup);
+            self.use_extension = false;
             if let Some(name) = self.current_name.take() {
                 self.named.insert(name.clone(), id);
                 Some((id, Some(name)))
@@ -435,6 +645,7 @@ impl AllLookups {
                 Some((id, None))
             }
         } else if let Some(name) = self.current_name.take() {
+            self.use_extension = false;
             self.named.insert(name.clone(), LookupId::Empty);
             // there was a named block with no rules, return the empty lookup
             Some((LookupId::Empty, Some(name)))
@@ -459,6 +670,7 @@ impl AllLookups {
                 .into_iter()
                 .map(SingleSubBuilder::promote_to_multi_sub)
                 .collect(),
+            use_extension: lookup.use_extension,
         };
         self.current = Some(SomeLookup::GsubLookup(SubstitutionLookup::Multiple(
             promoted,
@@ -587,6 +799,28 @@ impl AllLookups {
         lookup_ids
     }
 
+    /// Allow chain contextual subtables to share a single classdef.
+    ///
+    /// See [`Opts::share_context_class_defs`][super::Opts::share_context_class_defs].
+    pub(crate) fn share_context_class_defs(&mut self) {
+        for lookup in self.gpos.iter_mut() {
+            if let PositionLookup::ChainedContextual(lookup) = lookup {
+                lookup
+                    .subtables
+                    .iter_mut()
+                    .for_each(PosChainContextBuilder::share_class_defs);
+            }
+        }
+        for lookup in self.gsub.iter_mut() {
+            if let SubstitutionLookup::ChainedContextual(lookup) = lookup {
+                lookup
+                    .subtables
+                    .iter_mut()
+                    .for_each(SubChainContextBuilder::share_class_defs);
+            }
+        }
+    }
+
     pub(crate) fn update_variation_index_tables(&mut self, key_map: &VariationIndexRemapping) {
         for lookup in self.gpos.iter_mut() {
             lookup.update_variation_index_tables(key_map);
@@ -646,6 +880,9 @@ impl AllLookups {
             }
         }
 
+        #[cfg(feature = "parallel")]
+        return rayon::join(|| gsub_builder.build(), || gpos_builder.build());
+        #[cfg(not(feature = "parallel"))]
         (gsub_builder.build(), gpos_builder.build())
     }
 }
@@ -665,18 +902,28 @@ impl LookupId {
         }
     }
 
-    pub(crate) fn to_gpos_id_or_die(self) -> u16 {
+    /// The index of this lookup in the GPOS lookup list.
+    ///
+    /// If more than [`MAX_LOOKUPS`] lookups are allocated, compilation fails
+    /// (or in lenient mode, the tables are not built), so when we are
+    /// building tables every index fits in a `u16`.
+    pub(crate) fn to_gpos_id(self) -> u16 {
         let LookupId::Gpos(x) = self else {
             panic!("this *really* shouldn't happen")
         };
-        x.try_into().unwrap()
+        x.try_into()
+            .expect("lookup count is checked before building")
     }
 
-    pub(crate) fn to_gsub_id_or_die(self) -> u16 {
+    /// The index of this lookup in the GSUB lookup list.
+    ///
+    /// See [`LookupId::to_gpos_id`] for details.
+    pub(crate) fn to_gsub_id(self) -> u16 {
         let LookupId::Gsub(x) = self else {
             panic!("this *really* shouldn't happen")
         };
-        x.try_into().unwrap()
+        x.try_into()
+            .expect("lookup count is checked before building")
     }
 }
 
@@ -858,37 +1105,42 @@ impl SomeLookup {
         }
     }
 
-    pub(crate) fn add_gsub_type_1(&mut self, id: GlyphId, replacement: GlyphId) {
+    // The `add_gsub_type_*` methods return `true` if the rule conflicts with an
+    // earlier rule in the current subtable (that is, if it has the same target
+    // and a different replacement.) The earlier rule wins, and an exact
+    // duplicate is silently ignored.
+
+    pub(crate) fn add_gsub_type_1(&mut self, id: GlyphId, replacement: GlyphId) -> bool {
         if let SomeLookup::GsubLookup(SubstitutionLookup::Single(table)) = self {
             let subtable = table.last_mut().unwrap();
-            subtable.insert(id, replacement);
+            subtable.insert(id, replacement)
         } else {
             panic!("lookup mismatch");
         }
     }
 
-    pub(crate) fn add_gsub_type_2(&mut self, id: GlyphId, replacement: Vec<GlyphId>) {
+    pub(crate) fn add_gsub_type_2(&mut self, id: GlyphId, replacement: Vec<GlyphId>) -> bool {
         if let SomeLookup::GsubLookup(SubstitutionLookup::Multiple(table)) = self {
             let subtable = table.last_mut().unwrap();
-            subtable.insert(id, replacement);
+            subtable.insert(id, replacement)
         } else {
             panic!("lookup mismatch");
         }
     }
 
-    pub(crate) fn add_gsub_type_3(&mut self, id: GlyphId, alternates: Vec<GlyphId>) {
+    pub(crate) fn add_gsub_type_3(&mut self, id: GlyphId, alternates: Vec<GlyphId>) -> bool {
         if let SomeLookup::GsubLookup(SubstitutionLookup::Alternate(table)) = self {
             let subtable = table.last_mut().unwrap();
-            subtable.insert(id, alternates);
+            subtable.insert(id, alternates)
         } else {
             panic!("lookup mismatch");
         }
     }
 
-    pub(crate) fn add_gsub_type_4(&mut self, target: Vec<GlyphId>, replacement: GlyphId) {
+    pub(crate) fn add_gsub_type_4(&mut self, target: Vec<GlyphId>, replacement: GlyphId) -> bool {
         if let SomeLookup::GsubLookup(SubstitutionLookup::Ligature(table)) = self {
             let subtable = table.last_mut().unwrap();
-            subtable.insert(target, replacement);
+            subtable.insert(target, replacement)
         } else {
             panic!("lookup mismatch");
         }
@@ -977,8 +1229,8 @@ impl<T> PosSubBuilder<T> {
 
 impl<T> PosSubBuilder<T>
 where
-    T: Builder,
-    T::Output: Default,
+    T: Builder + MaybeSend,
+    T::Output: Default + MaybeSend,
 {
     #[allow(clippy::type_complexity)] // i love my big dumb tuple
     fn build_raw(
@@ -1017,7 +1269,7 @@ where
             })
             .collect::<Vec<_>>();
 
-        let lookups = self.lookups.into_iter().map(|x| x.build()).collect();
+        let lookups = build_lookups(self.lookups);
 
         let variations = if self.variations.is_empty() {
             None
@@ -1056,6 +1308,27 @@ where
     }
 }
 
+/// Build each lookup in a lookup list.
+///
+/// Lookups do not depend on one another, so with the `parallel` feature they
+/// are built on the rayon thread pool. This is where most of the time goes
+/// when compiling large kerning features. Results are collected in their
+/// original order, so the output is the same either way.
+#[cfg(feature = "parallel")]
+fn build_lookups<T>(lookups: Vec<T>) -> Vec<T::Output>
+where
+    T: Builder + Send,
+    T::Output: Send,
+{
+    use rayon::prelude::*;
+    lookups.into_par_iter().map(Builder::build).collect()
+}
+
+#[cfg(not(feature = "parallel"))]
+fn build_lookups<T: Builder>(lookups: Vec<T>) -> Vec<T::Output> {
+    lookups.into_iter().map(Builder::build).collect()
+}
+
 impl Builder for PosSubBuilder<PositionLookup> {
     type Output = Option<write_gpos::Gpos>;
 
@@ -10,14 +10,14 @@ use write_fonts::{
         gpos::ValueRecord,
         gsub as write_gsub,
         gsub::ReverseChainSingleSubstFormat1,
-        layout::{self as write_layout, CoverageTableBuilder, LookupFlag},
+        layout::{self as write_layout, ClassDef, CoverageTable, CoverageTableBuilder, LookupFlag},
     },
     types::GlyphId,
     validate::Validate,
     FontWrite,
 };
 
-use crate::common::GlyphOrClass;
+use crate::common::{GlyphClass, GlyphOrClass};
 
 use super::{
     Builder, ClassDefBuilder2, FilterSetId, LookupBuilder, LookupId, PositionLookup,
@@ -100,6 +100,11 @@ impl<T> ContextualLookupBuilder<T> {
         (lookup, finished_anon_lookups)
     }
 
+    /// The number of anonymous lookups created for inline rules so far.
+    pub(crate) fn anon_lookup_count(&self) -> usize {
+        self.finished_anon_lookups.len() + self.current_anon_lookups.len()
+    }
+
     /// Returns a mutable reference to the active builder
     pub fn last_mut(&mut self) -> &mut ContextBuilder {
         self.subtables.last_mut().unwrap()
@@ -260,6 +265,8 @@ impl ContextualLookupBuilder<SubstitutionLookup> {
 #[derive(Clone, Debug, Default)]
 pub(crate) struct ContextBuilder {
     rules: Vec<ContextRule>,
+    /// If `true`, format 2 chain subtables may use one classdef for all sequences
+    share_class_defs: bool,
 }
 
 // we use separate types here to ensure we don't mix lookups
@@ -389,6 +396,45 @@ impl ContextBuilder {
 
         Some(write_layout::SequenceContext::format_1(coverage, rule_sets))
     }
+
+    /// If the input sequences can be expressed as a classdef, generate format 2
+    fn build_format_2(&self, in_gpos: bool) -> Option<write_layout::SequenceContext> {
+        let (class_def, input_map) = self.input_class_def()?.build();
+        let coverage = self
+            .rules
+            .iter()
+            .flat_map(|rule| rule.first_input_sequence_item().iter())
+            .collect::<CoverageTableBuilder>()
+            .build();
+
+        let mut rule_sets = vec![Vec::new(); input_map.len() + 1];
+        for rule in &self.rules {
+            let cls_idx = *input_map
+                .get(&rule.first_input_sequence_item().to_class().unwrap())
+                .unwrap();
+            let input = rule
+                .context
+                .iter()
+                .skip(1)
+                .map(|(cls, _)| input_map.get(&cls.to_class().unwrap()).unwrap())
+                .copied()
+                .collect();
+            rule_sets[cls_idx as usize].push(write_layout::ClassSequenceRule::new(
+                input,
+                rule.lookup_records(in_gpos),
+            ));
+        }
+        let rule_sets = rule_sets
+            .into_iter()
+            .map(|rules| {
+                (!rules.is_empty()).then(|| write_layout::ClassSequenceRuleSet::new(rules))
+            })
+            .collect();
+
+        Some(write_layout::SequenceContext::format_2(
+            coverage, class_def, rule_sets,
+        ))
+    }
 }
 
 impl SubContextBuilder {
@@ -427,9 +473,11 @@ impl ContextRule {
             .enumerate()
             .flat_map(|(i, (_, lookups))| {
                 lookups.iter().map(move |lookup_id| {
-                    let lookup_id = in_gpos
-                        .then(|| lookup_id.to_gpos_id_or_die())
-                        .unwrap_or_else(|| lookup_id.to_gsub_id_or_die());
+                    let lookup_id = if in_gpos {
+                        lookup_id.to_gpos_id()
+                    } else {
+                        lookup_id.to_gsub_id()
+                    };
                     write_layout::SequenceLookupRecord::new(i.try_into().unwrap(), lookup_id)
                 })
             })
@@ -457,8 +505,8 @@ impl ContextBuilder {
     fn build(self, in_gpos: bool) -> Vec<write_layout::SequenceContext> {
         assert!(self.rules.iter().all(|rule| !rule.is_chain_rule()));
         let format_1 = self.build_format_1(in_gpos);
-        //NOTE: I'm skipping format_2 because it seems consistently larger
-        // than format 3? but I have no verified this.
+        let format_2 = self.build_format_2(in_gpos);
+        let mut coverages = CoverageCache::default();
         let format_3 = self
             .rules
             .into_iter()
@@ -466,7 +514,7 @@ impl ContextBuilder {
                 let cov_tables = rule
                     .context
                     .iter()
-                    .map(|(seq, _)| seq.iter().collect::<CoverageTableBuilder>().build())
+                    .map(|(seq, _)| coverages.get(seq))
                     .collect();
                 let seq_lookups = rule.lookup_records(in_gpos);
 
@@ -474,7 +522,11 @@ impl ContextBuilder {
             })
             .collect();
 
-        pick_best_format([format_1.map(|x| vec![x]), None, Some(format_3)])
+        pick_best_format([
+            format_1.map(|x| vec![x]),
+            format_2.map(|x| vec![x]),
+            Some(format_3),
+        ])
     }
 }
 
@@ -557,12 +609,59 @@ impl ChainContextBuilder {
         Some((backtrack, input, lookahead))
     }
 
-    /// If this lookup can be expressed as format 2, generate it
+    /// If the classes in backtrack, input, and lookahead are all disjoint, we
+    /// can use a single classdef for all three sequences.
+    ///
+    /// Identical tables are only serialized once, so when the same classes
+    /// appear throughout the context this is often smaller than using
+    /// three separate classdefs.
+    fn format_2_shared_class_def(&self) -> Option<ClassDefBuilder2> {
+        let mut builder = ClassDefBuilder2::default();
+        for class in self.0.rules.iter().flat_map(|rule| {
+            rule.backtrack
+                .iter()
+                .chain(rule.context.iter().map(|x| &x.0))
+                .chain(rule.lookahead.iter())
+        }) {
+            if !builder.checked_add(class.to_class().unwrap()) {
+                return None;
+            }
+        }
+        Some(builder)
+    }
+
+    /// If this lookup can be expressed as format 2, generate it.
+    ///
+    /// If possible we also try sharing a single classdef between all three
+    /// sequences, and return whichever of these is smaller.
     fn build_format_2(&self, in_gpos: bool) -> Option<write_layout::ChainedSequenceContext> {
         let (backtrack, input, lookahead) = self.format_2_class_defs()?;
-        let (backtrack_class_def, backtrack_map) = backtrack.build();
-        let (input_class_def, input_map) = input.build();
-        let (lookahead_class_def, lookahead_map) = lookahead.build();
+        let separate =
+            self.build_format_2_impl(in_gpos, backtrack.build(), input.build(), lookahead.build());
+        let Some(shared) = self
+            .0
+            .share_class_defs
+            .then(|| self.format_2_shared_class_def())
+            .flatten()
+        else {
+            return Some(separate);
+        };
+        let shared = shared.build();
+        let shared = self.build_format_2_impl(in_gpos, shared.clone(), shared.clone(), shared);
+        if compute_size(&shared) < compute_size(&separate) {
+            Some(shared)
+        } else {
+            Some(separate)
+        }
+    }
+
+    fn build_format_2_impl(
+        &self,
+        in_gpos: bool,
+        (backtrack_class_def, backtrack_map): (ClassDef, HashMap<GlyphClass, u16>),
+        (input_class_def, input_map): (ClassDef, HashMap<GlyphClass, u16>),
+        (lookahead_class_def, lookahead_map): (ClassDef, HashMap<GlyphClass, u16>),
+    ) -> write_layout::ChainedSequenceContext {
         let coverage = self
             .0
             .rules
@@ -613,17 +712,18 @@ impl ChainContextBuilder {
             })
             .collect();
 
-        Some(write_layout::ChainedSequenceContext::format_2(
+        write_layout::ChainedSequenceContext::format_2(
             coverage,
             backtrack_class_def,
             input_class_def,
             lookahead_class_def,
             rule_sets,
-        ))
+        )
     }
 
     /// format 3 is always possible; it also generates a subtable for each rule.
     fn build_format_3(self, in_gpos: bool) -> Vec<write_layout::ChainedSequenceContext> {
+        let mut coverages = CoverageCache::default();
         self.0
             .rules
             .into_iter()
@@ -631,17 +731,17 @@ impl ChainContextBuilder {
                 let backtrack = rule
                     .backtrack
                     .iter()
-                    .map(|seq| seq.iter().collect::<CoverageTableBuilder>().build())
+                    .map(|seq| coverages.get(seq))
                     .collect();
                 let lookahead = rule
                     .lookahead
                     .iter()
-                    .map(|seq| seq.iter().collect::<CoverageTableBuilder>().build())
+                    .map(|seq| coverages.get(seq))
                     .collect();
                 let input = rule
                     .context
                     .iter()
-                    .map(|(seq, _)| seq.iter().collect::<CoverageTableBuilder>().build())
+                    .map(|(seq, _)| coverages.get(seq))
                     .collect();
                 let seq_lookups = rule.lookup_records(in_gpos);
 
@@ -656,6 +756,24 @@ impl ChainContextBuilder {
     }
 }
 
+/// Coverage tables for the items in a set of format 3 rules.
+///
+/// The same named class is often used in the backtrack or lookahead of many
+/// rules; this ensures we only build the coverage table for each distinct
+/// class once.
+#[derive(Default)]
+struct CoverageCache(HashMap<GlyphClass, CoverageTable>);
+
+impl CoverageCache {
+    fn get(&mut self, item: &GlyphOrClass) -> CoverageTable {
+        let class = item.to_class().unwrap_or_else(GlyphClass::empty);
+        self.0
+            .entry(class)
+            .or_insert_with_key(|class| class.iter().collect::<CoverageTableBuilder>().build())
+            .clone()
+    }
+}
+
 impl SubContextBuilder {
     pub(crate) fn bump_all_lookup_ids(&mut self, by: usize) {
         self.0.bump_all_lookup_ids(by)
@@ -666,11 +784,21 @@ impl SubChainContextBuilder {
         self.0 .0.bump_all_lookup_ids(by)
     }
 
+    pub(crate) fn share_class_defs(&mut self) {
+        self.0 .0.share_class_defs = true;
+    }
+
     pub(crate) fn iter_lookups(&self) -> impl Iterator<Item = LookupId> + '_ {
         self.0.iter_lookups()
     }
 }
 
+impl PosChainContextBuilder {
+    pub(crate) fn share_class_defs(&mut self) {
+        self.0 .0.share_class_defs = true;
+    }
+}
+
 impl Builder for PosChainContextBuilder {
     type Output = Vec<write_layout::ChainedSequenceContext>;
 
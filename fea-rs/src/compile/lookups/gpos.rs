@@ -1,6 +1,6 @@
 //! GPOS subtable builders
 
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use smol_str::SmolStr;
 use write_fonts::{
@@ -12,7 +12,7 @@ use write_fonts::{
     types::GlyphId,
 };
 
-use crate::common::GlyphClass;
+use crate::{common::GlyphClass, compile::valuerecordext::ValueRecordExt};
 
 use super::{Builder, ClassDefBuilder2, VariationIndexContainingLookup};
 
@@ -22,15 +22,19 @@ pub struct SinglePosBuilder {
 }
 
 impl SinglePosBuilder {
-    //TODO: should we track the valueformat here?
+    /// Add a value record for this glyph.
+    ///
+    /// The value format of the record is minimized: fields that are zero (and
+    /// have no device) are dropped, so that e.g. a placement-only adjustment
+    /// (as commonly used in 'cpsp') does not also include an advance.
     pub fn insert(&mut self, glyph: GlyphId, record: ValueRecord) {
-        self.items.insert(glyph, record);
+        self.items.insert(glyph, record.clear_zeros());
     }
 
     pub(crate) fn can_add_rule(&self, glyph: GlyphId, value: &ValueRecord) -> bool {
         self.items
             .get(&glyph)
-            .map(|existing| existing == value)
+            .map(|existing| existing == &value.clone().clear_zeros())
             .unwrap_or(true)
     }
 }
@@ -313,6 +317,133 @@ impl Builder for ClassPairPosSubtable {
     }
 }
 
+/// Rewrite glyph pair subtables as class pair subtables, where that is smaller.
+///
+/// The classes are inferred from the pairs: second glyphs that have the same
+/// adjustments with every first glyph share a class, as do first glyphs that
+/// have the same adjustments with every second glyph class.
+///
+/// A class-based subtable matches every pair whose first glyph is covered
+/// (pairs that are not listed get an empty adjustment), which would prevent any
+/// later subtable in the lookup from applying to that glyph. We only convert
+/// a subtable if no later subtable covers any of the same first glyphs.
+pub(crate) fn compact_pair_pos(gpos: &mut write_gpos::Gpos) {
+    for lookup in gpos.lookup_list.lookups.iter_mut() {
+        if let write_gpos::PositionLookup::Pair(lookup) = &mut **lookup {
+            let mut later_coverage = HashSet::new();
+            for subtable in lookup.subtables.iter_mut().rev() {
+                let coverage = match &**subtable {
+                    write_gpos::PairPos::Format1(table) => table.coverage.iter(),
+                    write_gpos::PairPos::Format2(table) => table.coverage.iter(),
+                }
+                .collect::<Vec<_>>();
+                if let write_gpos::PairPos::Format1(table) = &**subtable {
+                    if !coverage.iter().any(|gid| later_coverage.contains(gid)) {
+                        if let Some(compact) = infer_pair_classes(table) {
+                            **subtable = compact;
+                        }
+                    }
+                }
+                later_coverage.extend(coverage);
+            }
+        }
+    }
+}
+
+/// Build a class pair subtable equivalent to this glyph pair subtable, if it is smaller.
+fn infer_pair_classes(table: &write_gpos::PairPosFormat1) -> Option<write_gpos::PairPos> {
+    type Adjustment<'a> = (&'a ValueRecord, &'a ValueRecord);
+
+    let pairs = table
+        .coverage
+        .iter()
+        .zip(&table.pair_sets)
+        .flat_map(|(first, set)| {
+            set.pair_value_records.iter().map(move |rec| {
+                (
+                    first,
+                    rec.second_glyph,
+                    (&rec.value_record1, &rec.value_record2),
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    // this table was built by us, so every record has the same format
+    let (_, _, (first_v1, first_v2)) = pairs.first()?;
+    let empty_record = write_gpos::Class2Record::new(
+        ValueRecord::new().with_explicit_value_format(first_v1.format()),
+        ValueRecord::new().with_explicit_value_format(first_v2.format()),
+    );
+
+    // second glyphs with identical columns share a class
+    let mut columns = BTreeMap::<GlyphId, Vec<(GlyphId, Adjustment)>>::new();
+    for (first, second, adjustment) in &pairs {
+        columns
+            .entry(*second)
+            .or_default()
+            .push((*first, *adjustment));
+    }
+    let mut class2_members = HashMap::<_, Vec<GlyphId>>::new();
+    for (second, column) in columns {
+        class2_members.entry(column).or_default().push(second);
+    }
+    let mut classdef_2 = ClassDefBuilder2::new(false);
+    let class2_ids = class2_members
+        .into_values()
+        .map(|glyphs| {
+            let class = GlyphClass::from(glyphs);
+            classdef_2.checked_add(class.clone());
+            class
+        })
+        .collect::<Vec<_>>();
+    let (class2def, class2map) = classdef_2.build();
+    let class2_for_glyph = class2_ids
+        .iter()
+        .flat_map(|class| class.iter().map(|gid| (gid, class2map[class])))
+        .collect::<HashMap<_, _>>();
+
+    // and then first glyphs with identical rows of classes do too
+    let mut rows = BTreeMap::<GlyphId, BTreeMap<u16, Adjustment>>::new();
+    for (first, second, adjustment) in &pairs {
+        rows.entry(*first)
+            .or_default()
+            .insert(class2_for_glyph[second], *adjustment);
+    }
+    let mut class1_members = HashMap::<_, Vec<GlyphId>>::new();
+    for (first, row) in rows {
+        class1_members.entry(row).or_default().push(first);
+    }
+    let mut classdef_1 = ClassDefBuilder2::new(true);
+    let class1_rows = class1_members
+        .into_iter()
+        .map(|(row, glyphs)| {
+            let class = GlyphClass::from(glyphs);
+            classdef_1.checked_add(class.clone());
+            (class, row)
+        })
+        .collect::<Vec<_>>();
+    let (class1def, class1map) = classdef_1.build();
+
+    let mut class1_records = vec![write_gpos::Class1Record::default(); class1_rows.len()];
+    for (class, row) in class1_rows {
+        let mut records = vec![empty_record.clone(); class2map.len() + 1];
+        for (class2, (v1, v2)) in row {
+            records[class2 as usize] = write_gpos::Class2Record::new(v1.clone(), v2.clone());
+        }
+        class1_records[class1map[&class] as usize] = write_gpos::Class1Record::new(records);
+    }
+
+    let compact = write_gpos::PairPos::format_2(
+        table.coverage.iter().collect(),
+        class1def,
+        class2def,
+        class1_records,
+    );
+    let size = |table: &write_gpos::PairPos| write_fonts::dump_table(table).ok().map(|t| t.len());
+    let original = write_gpos::PairPos::Format1(table.clone());
+    (size(&compact)? < size(&original)?).then_some(compact)
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct CursivePosBuilder {
     items: BTreeMap<GlyphId, write_gpos::EntryExitRecord>,
@@ -440,7 +571,7 @@ impl VariationIndexContainingLookup for MarkToBaseBuilder {
     }
 }
 
-/// An error indicating a given glyph is has be
+/// An error indicating a glyph has already been assigned to another mark class
 pub struct PreviouslyAssignedClass {
     pub glyph_id: GlyphId,
     pub class: SmolStr,
@@ -474,6 +605,9 @@ impl MarkToBaseBuilder {
     }
 }
 
+// NOTE: we don't intern anchors ourselves; identical `AnchorTable`s are
+// deduplicated by the write-fonts serializer, so equal anchors across mark and
+// base records end up sharing a single offset in the compiled table.
 impl Builder for MarkToBaseBuilder {
     type Output = Vec<write_gpos::MarkBasePosFormat1>;
 
@@ -653,3 +787,116 @@ impl Builder for MarkToMarkBuilder {
         )]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use write_fonts::dump_table;
+
+    use super::*;
+
+    fn gid(id: u16) -> GlyphId {
+        GlyphId::new(id)
+    }
+
+    // build a mark-to-base lookup where every mark and base gets an anchor,
+    // calling `make_anchor` with a unique index for each.
+    fn mark_base_size(
+        n_marks: u16,
+        n_bases: u16,
+        make_anchor: impl Fn(u16) -> AnchorTable,
+    ) -> usize {
+        let class = SmolStr::new("@top");
+        let mut builder = MarkToBaseBuilder::default();
+        for i in 0..n_marks {
+            assert!(builder
+                .insert_mark(gid(100 + i), class.clone(), make_anchor(i))
+                .is_ok());
+        }
+        for i in 0..n_bases {
+            builder.insert_base(gid(1 + i), &class, make_anchor(n_marks + i));
+        }
+        let subtables = builder.build();
+        assert_eq!(subtables.len(), 1);
+        dump_table(&subtables[0]).unwrap().len()
+    }
+
+    #[test]
+    fn single_pos_minimal_value_format() {
+        let mut builder = SinglePosBuilder::default();
+        let placement = |x| ValueRecord::new().with_x_placement(x).with_x_advance(0);
+        builder.insert(gid(1), placement(5));
+        builder.insert(gid(2), placement(5));
+        builder.insert(gid(3), placement(8));
+        builder.insert(
+            gid(4),
+            ValueRecord::new().with_x_advance(10).with_y_advance(0),
+        );
+        assert!(builder.can_add_rule(gid(1), &ValueRecord::new().with_x_placement(5)));
+        assert!(!builder.can_add_rule(gid(1), &placement(6)));
+
+        let subtables = builder.build();
+        assert_eq!(subtables.len(), 2);
+        let write_gpos::SinglePos::Format2(placements) = &subtables[0] else {
+            panic!("expected format 2, found {:?}", subtables[0]);
+        };
+        assert!(placements
+            .value_records
+            .iter()
+            .all(|rec| rec.format() == ValueFormat::X_PLACEMENT));
+        assert_eq!(placements.value_records.len(), 3);
+        let write_gpos::SinglePos::Format1(advance) = &subtables[1] else {
+            panic!("expected format 1, found {:?}", subtables[1]);
+        };
+        assert_eq!(advance.value_record.format(), ValueFormat::X_ADVANCE);
+    }
+
+    #[test]
+    fn mark_in_two_classes() {
+        let anchor = || AnchorTable::format_1(0, 0);
+        let mut builder = MarkToBaseBuilder::default();
+        assert!(builder.insert_mark(gid(5), "@one".into(), anchor()).is_ok());
+        // the same class again is fine
+        assert!(builder.insert_mark(gid(5), "@one".into(), anchor()).is_ok());
+        let err = builder
+            .insert_mark(gid(5), "@two".into(), anchor())
+            .err()
+            .unwrap();
+        assert_eq!(err.glyph_id, gid(5));
+        assert_eq!(err.class, "@one");
+    }
+
+    #[test]
+    fn identical_anchors_are_shared() {
+        const N_MARKS: u16 = 20;
+        const N_BASES: u16 = 30;
+        // format 1 anchors are 6 bytes
+        const ANCHOR_SIZE: usize = 6;
+
+        let shared = mark_base_size(N_MARKS, N_BASES, |_| AnchorTable::format_1(100, 500));
+        let distinct = mark_base_size(N_MARKS, N_BASES, |i| {
+            AnchorTable::format_1(100, 500 + i as i16)
+        });
+        let n_anchors = (N_MARKS + N_BASES) as usize;
+        assert_eq!(distinct - shared, (n_anchors - 1) * ANCHOR_SIZE);
+    }
+
+    #[test]
+    fn identical_anchors_shared_in_mark_to_mark() {
+        let class = SmolStr::new("@top");
+        let anchor = || AnchorTable::format_2(0, 600, 3);
+        let mut builder = MarkToMarkBuilder::default();
+        for i in 0..10 {
+            assert!(builder
+                .insert_mark(gid(100 + i), class.clone(), anchor())
+                .is_ok());
+            builder.insert_base(gid(200 + i), &class, anchor());
+        }
+        let subtables = builder.build();
+        let bytes = dump_table(&subtables[0]).unwrap();
+
+        // header (12) + 2 * coverage (format 2, one range: 4 + 6)
+        // + MarkArray (2 + 4 * 10) + Mark2Array (2 + 2 * 10)
+        // + a single format 2 anchor (8)
+        assert_eq!(bytes.len(), 12 + 2 * 10 + 42 + 22 + 8);
+    }
+}
@@ -1,6 +1,9 @@
 //! GSUB lookup builders
 
-use std::{collections::BTreeMap, convert::TryFrom};
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    convert::TryFrom,
+};
 
 use write_fonts::{
     tables::gsub as write_gsub,
@@ -24,12 +27,21 @@ enum PossibleSingleSubFormat {
 }
 
 impl SingleSubBuilder {
-    pub fn insert(&mut self, target: GlyphId, replacement: GlyphId) {
+    /// Add a rule, unless the target already has a replacement.
+    ///
+    /// As in makeotf, the first rule for a target is the one that applies, and
+    /// later rules are ignored. Returns `true` if this rule was ignored and its
+    /// replacement differs from the existing one.
+    pub fn insert(&mut self, target: GlyphId, replacement: GlyphId) -> bool {
+        if let Some((existing, _)) = self.items.get(&target) {
+            return *existing != replacement;
+        }
         let delta = replacement.to_u16() as i32 - target.to_u16() as i32;
         let delta = i16::try_from(delta)
             .map(PossibleSingleSubFormat::Delta)
             .unwrap_or(PossibleSingleSubFormat::Format2);
         self.items.insert(target, (replacement, delta));
+        false
     }
 
     pub fn contains_target(&self, target: GlyphId) -> bool {
@@ -159,8 +171,17 @@ impl Builder for MultipleSubBuilder {
 }
 
 impl MultipleSubBuilder {
-    pub fn insert(&mut self, target: GlyphId, replacement: Vec<GlyphId>) {
-        self.items.insert(target, replacement);
+    /// Add a rule, unless the target already has a replacement.
+    ///
+    /// Returns `true` if this rule was ignored and differs from the existing one.
+    pub fn insert(&mut self, target: GlyphId, replacement: Vec<GlyphId>) -> bool {
+        match self.items.entry(target) {
+            Entry::Occupied(entry) => *entry.get() != replacement,
+            Entry::Vacant(entry) => {
+                entry.insert(replacement);
+                false
+            }
+        }
     }
 
     pub fn contains_target(&self, target: GlyphId) -> bool {
@@ -174,8 +195,17 @@ pub struct AlternateSubBuilder {
 }
 
 impl AlternateSubBuilder {
-    pub fn insert(&mut self, target: GlyphId, replacement: Vec<GlyphId>) {
-        self.items.insert(target, replacement);
+    /// Add a rule, unless the target already has alternates.
+    ///
+    /// Returns `true` if this rule was ignored and differs from the existing one.
+    pub fn insert(&mut self, target: GlyphId, replacement: Vec<GlyphId>) -> bool {
+        match self.items.entry(target) {
+            Entry::Occupied(entry) => *entry.get() != replacement,
+            Entry::Vacant(entry) => {
+                entry.insert(replacement);
+                false
+            }
+        }
     }
 
     pub(crate) fn is_empty(&self) -> bool {
@@ -210,14 +240,19 @@ pub struct LigatureSubBuilder {
 }
 
 impl LigatureSubBuilder {
-    pub fn insert(&mut self, target: Vec<GlyphId>, replacement: GlyphId) {
+    /// Add a rule, unless the target sequence already has a replacement.
+    ///
+    /// Returns `true` if this rule was ignored and differs from the existing one.
+    pub fn insert(&mut self, target: Vec<GlyphId>, replacement: GlyphId) -> bool {
         let mut iter = target.into_iter();
         let first = iter.next().unwrap();
         let rest = iter.collect::<Vec<_>>();
-        self.items
-            .entry(first)
-            .or_default()
-            .push((rest, replacement));
+        let ligatures = self.items.entry(first).or_default();
+        if let Some((_, existing)) = ligatures.iter().find(|(seq, _)| *seq == rest) {
+            return *existing != replacement;
+        }
+        ligatures.push((rest, replacement));
+        false
     }
 
     pub fn contains_target(&self, target: GlyphId) -> bool {
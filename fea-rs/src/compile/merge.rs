@@ -0,0 +1,644 @@
+//! Merging compiled layout tables into those of an existing font.
+//!
+//! See [`Compilation::merge_into_font`][super::Compilation::merge_into_font].
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use write_fonts::{
+    from_obj::ToOwnedTable,
+    read::{FontRef, ReadError, TableProvider},
+    tables::{
+        gdef::{AttachList, Gdef, LigCaretList, MarkGlyphSets},
+        gpos::{self as wgpos, Gpos},
+        gsub::{self as wgsub, Gsub},
+        layout::{
+            ClassDef, ConditionSet, CoverageTable, Feature, FeatureList, FeatureRecord,
+            FeatureTableSubstitutionRecord, FeatureVariationRecord, FeatureVariations, LangSys,
+            LangSysRecord, LookupList, Script, ScriptList, ScriptRecord,
+        },
+    },
+    types::{F2Dot14, GlyphId, Tag},
+    OffsetMarker,
+};
+
+use super::{error::MergeError, lookup_hook::LayoutLookup, tags};
+
+/// The highest mark attachment class that can be used in a lookup flag.
+const MAX_MARK_ATTACH_CLASS: u16 = 0xFF;
+
+/// The layout tables to write when merging a compilation into a font.
+///
+/// A table is `None` if the font's version of it should be kept unchanged.
+#[derive(Default)]
+pub(crate) struct MergedLayout {
+    pub(crate) gdef: Option<Gdef>,
+    pub(crate) gsub: Option<Gsub>,
+    pub(crate) gpos: Option<Gpos>,
+}
+
+/// The parts of a GSUB or GPOS table.
+struct LayoutTable<'a, T> {
+    script_list: &'a ScriptList,
+    feature_list: &'a FeatureList,
+    feature_variations: Option<&'a FeatureVariations>,
+    lookup_list: &'a LookupList<T>,
+}
+
+/// Merge the generated layout tables into those of `font`.
+pub(crate) fn merge_layout(
+    gdef: Option<&Gdef>,
+    gsub: Option<&Gsub>,
+    gpos: Option<&Gpos>,
+    font: &FontRef,
+) -> Result<MergedLayout, MergeError> {
+    let mut result = MergedLayout::default();
+    let mut mark_set_offset = 0;
+    let mut mark_attach_offset = 0;
+    if let Some(new) = gdef {
+        let gdef = match existing_table(font.gdef(), tags::GDEF)? {
+            Some(existing) => {
+                let existing: Gdef = existing.to_owned_table();
+                mark_set_offset = existing
+                    .mark_glyph_sets_def
+                    .as_ref()
+                    .map(|sets| sets.coverages.len() as u16)
+                    .unwrap_or(0);
+                mark_attach_offset = existing
+                    .mark_attach_class_def
+                    .as_ref()
+                    .and_then(|class_def| class_def.iter().map(|(_, class)| class).max())
+                    .unwrap_or(0);
+                merge_gdef(existing, new, mark_attach_offset)?
+            }
+            None => new.clone(),
+        };
+        result.gdef = Some(gdef);
+    }
+
+    if let Some(new) = gsub {
+        let gsub = match existing_table(font.gsub(), tags::GSUB)? {
+            Some(existing) => {
+                let wgsub::Gsub {
+                    mut script_list,
+                    mut feature_list,
+                    mut lookup_list,
+                    feature_variations,
+                } = existing.to_owned_table();
+                let mut feature_variations = feature_variations.into_inner();
+                merge_layout_table(
+                    &mut script_list,
+                    &mut feature_list,
+                    &mut feature_variations,
+                    &mut lookup_list,
+                    LayoutTable {
+                        script_list: &new.script_list,
+                        feature_list: &new.feature_list,
+                        feature_variations: new.feature_variations.as_ref(),
+                        lookup_list: &new.lookup_list,
+                    },
+                    mark_set_offset,
+                    mark_attach_offset,
+                );
+                wgsub::Gsub {
+                    script_list,
+                    feature_list,
+                    lookup_list,
+                    feature_variations: feature_variations.into(),
+                }
+            }
+            None => new.clone(),
+        };
+        result.gsub = Some(gsub);
+    }
+
+    if let Some(new) = gpos {
+        let gpos = match existing_table(font.gpos(), tags::GPOS)? {
+            Some(existing) => {
+                let wgpos::Gpos {
+                    mut script_list,
+                    mut feature_list,
+                    mut lookup_list,
+                    feature_variations,
+                } = existing.to_owned_table();
+                let mut feature_variations = feature_variations.into_inner();
+                merge_layout_table(
+                    &mut script_list,
+                    &mut feature_list,
+                    &mut feature_variations,
+                    &mut lookup_list,
+                    LayoutTable {
+                        script_list: &new.script_list,
+                        feature_list: &new.feature_list,
+                        feature_variations: new.feature_variations.as_ref(),
+                        lookup_list: &new.lookup_list,
+                    },
+                    mark_set_offset,
+                    mark_attach_offset,
+                );
+                wgpos::Gpos {
+                    script_list,
+                    feature_list,
+                    lookup_list,
+                    feature_variations: feature_variations.into(),
+                }
+            }
+            None => new.clone(),
+        };
+        result.gpos = Some(gpos);
+    }
+    Ok(result)
+}
+
+/// Returns `None` if the table is not in the font.
+fn existing_table<T>(table: Result<T, ReadError>, tag: Tag) -> Result<Option<T>, MergeError> {
+    match table {
+        Ok(table) => Ok(Some(table)),
+        Err(ReadError::TableIsMissing(_)) => Ok(None),
+        Err(e) => Err(MergeError::ReadTable(tag, e)),
+    }
+}
+
+/// Merge the generated GDEF table into the font's.
+///
+/// Where a glyph is in both tables, the font's class (or attachment points,
+/// or ligature carets) are kept. The generated mark glyph sets are added after
+/// those of the font, and the generated mark attachment classes are numbered
+/// after the font's highest class, `mark_attach_offset`.
+fn merge_gdef(mut existing: Gdef, new: &Gdef, mark_attach_offset: u16) -> Result<Gdef, MergeError> {
+    existing.glyph_class_def = merge_class_defs(
+        existing.glyph_class_def.as_ref(),
+        new.glyph_class_def.as_ref(),
+    )
+    .into();
+    existing.mark_attach_class_def = merge_mark_attach_classes(
+        existing.mark_attach_class_def.as_ref(),
+        new.mark_attach_class_def.as_ref(),
+        mark_attach_offset,
+    )?
+    .into();
+
+    if let Some(new_list) = new.attach_list.as_ref() {
+        let old = existing
+            .attach_list
+            .as_ref()
+            .map(|list| list.coverage.iter().zip(&list.attach_points));
+        let (coverage, points) = merge_by_glyph(
+            old.into_iter().flatten(),
+            new_list.coverage.iter().zip(&new_list.attach_points),
+        );
+        existing.attach_list.set(AttachList {
+            coverage: coverage.into(),
+            attach_points: points,
+        });
+    }
+
+    if let Some(new_list) = new.lig_caret_list.as_ref() {
+        let old = existing
+            .lig_caret_list
+            .as_ref()
+            .map(|list| list.coverage.iter().zip(&list.lig_glyphs));
+        let (coverage, lig_glyphs) = merge_by_glyph(
+            old.into_iter().flatten(),
+            new_list.coverage.iter().zip(&new_list.lig_glyphs),
+        );
+        existing.lig_caret_list.set(LigCaretList {
+            coverage: coverage.into(),
+            lig_glyphs,
+        });
+    }
+
+    if let Some(new_sets) = new.mark_glyph_sets_def.as_ref() {
+        let mut coverages = existing
+            .mark_glyph_sets_def
+            .as_ref()
+            .map(|sets| sets.coverages.clone())
+            .unwrap_or_default();
+        coverages.extend(new_sets.coverages.iter().cloned());
+        existing
+            .mark_glyph_sets_def
+            .set(MarkGlyphSets { coverages });
+    }
+
+    if let Some(var_store) = new.item_var_store.as_ref() {
+        if existing.item_var_store.is_some() {
+            return Err(MergeError::VariationStore);
+        }
+        existing.item_var_store.set(var_store.clone());
+    }
+    Ok(existing)
+}
+
+fn merge_class_defs(existing: Option<&ClassDef>, new: Option<&ClassDef>) -> Option<ClassDef> {
+    match (existing, new) {
+        (Some(existing), Some(new)) => {
+            let mut classes = new.iter().collect::<BTreeMap<_, _>>();
+            classes.extend(existing.iter());
+            Some(classes.into_iter().collect())
+        }
+        (existing, new) => existing.or(new).cloned(),
+    }
+}
+
+/// Add the new mark attachment classes after the existing ones.
+///
+/// A glyph can only be in one class, and the class must fit in the high byte
+/// of the lookup flag.
+fn merge_mark_attach_classes(
+    existing: Option<&ClassDef>,
+    new: Option<&ClassDef>,
+    offset: u16,
+) -> Result<Option<ClassDef>, MergeError> {
+    let Some(new) = new else {
+        return Ok(existing.cloned());
+    };
+    let mut classes = existing
+        .map(|class_def| class_def.iter().collect::<BTreeMap<_, _>>())
+        .unwrap_or_default();
+    for (glyph, class) in new.iter() {
+        let class = class + offset;
+        if class > MAX_MARK_ATTACH_CLASS {
+            return Err(MergeError::TooManyMarkAttachClasses(class));
+        }
+        if classes.insert(glyph, class).is_some() {
+            return Err(MergeError::MarkAttachClassConflict(glyph));
+        }
+    }
+    Ok(Some(classes.into_iter().collect()))
+}
+
+/// Combine two coverage-indexed lists, preferring the items in `existing`.
+fn merge_by_glyph<'a, T: Clone + 'a, const N: usize>(
+    existing: impl Iterator<Item = (GlyphId, &'a OffsetMarker<T, N>)>,
+    new: impl Iterator<Item = (GlyphId, &'a OffsetMarker<T, N>)>,
+) -> (CoverageTable, Vec<OffsetMarker<T, N>>) {
+    let mut items = new.collect::<BTreeMap<_, _>>();
+    items.extend(existing);
+    let coverage = items.keys().copied().collect();
+    (coverage, items.into_values().cloned().collect())
+}
+
+/// Add the lookups and features of `new` to an existing table.
+///
+/// The new lookups are added after the existing ones, so that they are
+/// applied after them. Each language system in the result has the features
+/// it would have in either table, following the usual fallbacks to the
+/// default language and the `DFLT` script. Where both tables have a feature
+/// with the same tag for a language system, these are combined into a single
+/// feature containing the lookups of both, since shapers only use the first
+/// feature with a given tag.
+///
+/// Similarly, shapers only apply the first feature variation record whose
+/// conditions match, so a new record with the same conditions as one in the
+/// existing table is merged into it. Other new records are added after the
+/// existing ones; where their conditions overlap those of an existing record
+/// (but are not the same), only the existing substitutions apply, and we log
+/// a warning.
+fn merge_layout_table<T: LayoutLookup + Clone>(
+    script_list: &mut ScriptList,
+    feature_list: &mut FeatureList,
+    feature_variations: &mut Option<FeatureVariations>,
+    lookup_list: &mut LookupList<T>,
+    new: LayoutTable<T>,
+    mark_set_offset: u16,
+    mark_attach_offset: u16,
+) {
+    let lookup_offset = lookup_list.lookups.len() as u16;
+    let feature_offset = feature_list.feature_records.len() as u16;
+    let shift_lookup = |idx: u16| Some(idx + lookup_offset);
+    let shift_feature = |feature: &Feature| Feature {
+        feature_params: feature.feature_params.clone(),
+        lookup_list_indices: feature
+            .lookup_list_indices
+            .iter()
+            .map(|idx| idx + lookup_offset)
+            .collect(),
+    };
+
+    for lookup in &new.lookup_list.lookups {
+        let mut lookup = (**lookup).clone();
+        lookup.remap_lookup_records(&shift_lookup);
+        let (flags, mark_set) = lookup.flags_mut();
+        if flags.use_mark_filtering_set() {
+            *mark_set += mark_set_offset;
+        }
+        if let Some(class) = flags.mark_attachment_type_mask() {
+            flags.set_mark_attachment_type(class + mark_attach_offset);
+        }
+        lookup_list.lookups.push(lookup.into());
+    }
+
+    let new_features = new
+        .feature_list
+        .feature_records
+        .iter()
+        .map(|record| FeatureRecord::new(record.feature_tag, shift_feature(&record.feature)))
+        .collect::<Vec<_>>();
+    feature_list
+        .feature_records
+        .extend(new_features.iter().cloned());
+
+    // maps (existing feature, new feature) to the index of their combination
+    let mut combined = HashMap::new();
+    let mut merger = LangSysMerger {
+        features: &mut feature_list.feature_records,
+        new_features: &new_features,
+        feature_offset,
+        combined: &mut combined,
+    };
+    let script_tags = script_list
+        .script_records
+        .iter()
+        .chain(&new.script_list.script_records)
+        .map(|record| record.script_tag)
+        .collect::<BTreeSet<_>>();
+    let mut script_records = Vec::with_capacity(script_tags.len());
+    for script_tag in script_tags {
+        let old = find_script(script_list, script_tag);
+        let added = find_script(new.script_list, script_tag);
+        let lang_tags = old
+            .iter()
+            .chain(added.iter())
+            .flat_map(|script| script.lang_sys_records.iter().map(|rec| rec.lang_sys_tag))
+            .collect::<BTreeSet<_>>();
+        let default_lang_sys = merger.merge(
+            old.and_then(|script| lang_sys(script, None)),
+            added.and_then(|script| lang_sys(script, None)),
+        );
+        let lang_sys_records = lang_tags
+            .into_iter()
+            .filter_map(|tag| {
+                merger
+                    .merge(
+                        old.and_then(|script| lang_sys(script, Some(tag))),
+                        added.and_then(|script| lang_sys(script, Some(tag))),
+                    )
+                    .map(|lang_sys| LangSysRecord::new(tag, lang_sys))
+            })
+            .collect();
+        script_records.push(ScriptRecord::new(
+            script_tag,
+            Script::new(default_lang_sys, lang_sys_records),
+        ));
+    }
+    script_list.script_records = script_records;
+
+    // features that are combined should be substituted like their parts
+    let combined = combined
+        .into_iter()
+        .map(|(parts, idx)| (idx, parts))
+        .collect::<BTreeMap<_, _>>();
+    let mut records = feature_variations
+        .as_mut()
+        .map(|variations| std::mem::take(&mut variations.feature_variation_records))
+        .unwrap_or_default();
+    let n_existing = records.len();
+    for record in records.iter_mut() {
+        if let Some(subst) = record.feature_table_substitution.as_mut() {
+            let mut added = Vec::new();
+            for (idx, (old_idx, new_idx)) in &combined {
+                if let Some(alternate) = subst
+                    .substitutions
+                    .iter()
+                    .find(|rec| rec.feature_index == *old_idx)
+                {
+                    let mut feature = (*alternate.alternate_feature).clone();
+                    feature
+                        .lookup_list_indices
+                        .extend(&new_features[*new_idx as usize].feature.lookup_list_indices);
+                    added.push(FeatureTableSubstitutionRecord::new(*idx, feature));
+                }
+            }
+            subst.substitutions.extend(added);
+        }
+    }
+    for record in new
+        .feature_variations
+        .iter()
+        .flat_map(|variations| &variations.feature_variation_records)
+    {
+        let mut record = record.clone();
+        if let Some(subst) = record.feature_table_substitution.as_mut() {
+            let mut added = Vec::new();
+            for (idx, (old_idx, new_idx)) in &combined {
+                if let Some(alternate) = subst
+                    .substitutions
+                    .iter()
+                    .find(|rec| rec.feature_index == *new_idx)
+                {
+                    let old_feature = &feature_list.feature_records[*old_idx as usize].feature;
+                    let mut feature = shift_feature(&alternate.alternate_feature);
+                    feature.feature_params = old_feature.feature_params.clone();
+                    feature.lookup_list_indices = old_feature
+                        .lookup_list_indices
+                        .iter()
+                        .chain(&feature.lookup_list_indices)
+                        .copied()
+                        .collect();
+                    added.push(FeatureTableSubstitutionRecord::new(*idx, feature));
+                }
+            }
+            for rec in subst.substitutions.iter_mut() {
+                rec.feature_index += feature_offset;
+                *rec.alternate_feature = shift_feature(&rec.alternate_feature);
+            }
+            subst.substitutions.extend(added);
+        }
+
+        let conditions = condition_ranges(record.condition_set.as_ref());
+        let same = records[..n_existing]
+            .iter()
+            .position(|existing| condition_ranges(existing.condition_set.as_ref()) == conditions);
+        let shadowed = records[..same.unwrap_or(n_existing)]
+            .iter()
+            .any(|existing| {
+                ranges_overlap(
+                    &condition_ranges(existing.condition_set.as_ref()),
+                    &conditions,
+                )
+            });
+        if shadowed {
+            log::warn!(
+                "feature variations overlap those in the font; where both apply, only the font's are used"
+            );
+        }
+        match same {
+            Some(idx) => merge_substitutions(&mut records[idx], record, &combined, feature_offset),
+            None => records.push(record),
+        }
+    }
+    if !records.is_empty() {
+        *feature_variations = Some(FeatureVariations::new(records));
+    }
+}
+
+/// The ranges of a condition set, as (axis, min, max), sorted.
+///
+/// A record without a condition set always applies.
+fn condition_ranges(conditions: Option<&ConditionSet>) -> Vec<(u16, F2Dot14, F2Dot14)> {
+    let mut ranges = conditions
+        .iter()
+        .flat_map(|set| set.conditions.iter())
+        .map(|cond| {
+            (
+                cond.axis_index,
+                cond.filter_range_min_value,
+                cond.filter_range_max_value,
+            )
+        })
+        .collect::<Vec<_>>();
+    ranges.sort_by_key(|(axis, min, max)| (*axis, min.to_bits(), max.to_bits()));
+    ranges
+}
+
+/// `true` if some location satisfies both sets of conditions.
+///
+/// Two sets are disjoint only if they have disjoint ranges for the same axis.
+fn ranges_overlap(a: &[(u16, F2Dot14, F2Dot14)], b: &[(u16, F2Dot14, F2Dot14)]) -> bool {
+    !a.iter().any(|(axis_a, min_a, max_a)| {
+        b.iter().any(|(axis_b, min_b, max_b)| {
+            axis_a == axis_b
+                && (max_a.to_bits() < min_b.to_bits() || max_b.to_bits() < min_a.to_bits())
+        })
+    })
+}
+
+/// Merge the substitutions of a new feature variation record into an existing
+/// record with the same conditions.
+///
+/// The new record's feature indices have already been shifted. A combined
+/// feature may be substituted in both records; the result then has the
+/// lookups of the existing alternate feature followed by those of the new one.
+fn merge_substitutions(
+    existing: &mut FeatureVariationRecord,
+    new: FeatureVariationRecord,
+    combined: &BTreeMap<u16, (u16, u16)>,
+    feature_offset: u16,
+) {
+    let Some(new) = new.feature_table_substitution.into_inner() else {
+        return;
+    };
+    let Some(subst) = existing.feature_table_substitution.as_mut() else {
+        existing.feature_table_substitution = new.into();
+        return;
+    };
+    let alternate = |records: &[FeatureTableSubstitutionRecord], idx: u16| {
+        records
+            .iter()
+            .find(|rec| rec.feature_index == idx)
+            .map(|rec| (*rec.alternate_feature).clone())
+    };
+    for rec in new.substitutions.iter() {
+        let Some(pos) = subst
+            .substitutions
+            .iter()
+            .position(|existing| existing.feature_index == rec.feature_index)
+        else {
+            subst.substitutions.push(rec.clone());
+            continue;
+        };
+        // only combined features can be substituted in both records
+        let Some((old_idx, new_idx)) = combined.get(&rec.feature_index).copied() else {
+            continue;
+        };
+        let (Some(mut feature), Some(new_alternate)) = (
+            alternate(&subst.substitutions, old_idx),
+            alternate(&new.substitutions, new_idx + feature_offset),
+        ) else {
+            continue;
+        };
+        feature
+            .lookup_list_indices
+            .extend(new_alternate.lookup_list_indices);
+        *subst.substitutions[pos].alternate_feature = feature;
+    }
+    subst.substitutions.sort_by_key(|rec| rec.feature_index);
+}
+
+/// Combines the language systems of two tables, adding features as needed.
+struct LangSysMerger<'a> {
+    features: &'a mut Vec<FeatureRecord>,
+    new_features: &'a [FeatureRecord],
+    feature_offset: u16,
+    combined: &'a mut HashMap<(u16, u16), u16>,
+}
+
+impl LangSysMerger<'_> {
+    fn merge(&mut self, old: Option<&LangSys>, new: Option<&LangSys>) -> Option<LangSys> {
+        let new = match (old, new) {
+            (None, None) => return None,
+            (Some(old), None) => return Some(old.clone()),
+            (_, Some(new)) => new,
+        };
+        let mut result = old.cloned().unwrap_or_default();
+        let mut new_indices = new.feature_indices.clone();
+        if new.required_feature_index != 0xFFFF {
+            if result.required_feature_index == 0xFFFF {
+                result.required_feature_index = new.required_feature_index + self.feature_offset;
+            } else {
+                new_indices.insert(0, new.required_feature_index);
+            }
+        }
+        for new_idx in new_indices {
+            let tag = self.new_features[new_idx as usize].feature_tag;
+            let existing = result.feature_indices.iter_mut().find(|idx| {
+                **idx < self.feature_offset && self.features[**idx as usize].feature_tag == tag
+            });
+            match existing {
+                Some(old_idx) => *old_idx = self.combine(*old_idx, new_idx),
+                None => result.feature_indices.push(new_idx + self.feature_offset),
+            }
+        }
+        Some(result)
+    }
+
+    /// Returns the index of a feature with the lookups of both features.
+    fn combine(&mut self, old_idx: u16, new_idx: u16) -> u16 {
+        if let Some(idx) = self.combined.get(&(old_idx, new_idx)) {
+            return *idx;
+        }
+        let old = &self.features[old_idx as usize];
+        let new = &self.new_features[new_idx as usize];
+        let feature = Feature {
+            feature_params: match old.feature.feature_params.as_ref() {
+                Some(_) => old.feature.feature_params.clone(),
+                None => new.feature.feature_params.clone(),
+            },
+            lookup_list_indices: old
+                .feature
+                .lookup_list_indices
+                .iter()
+                .chain(&new.feature.lookup_list_indices)
+                .copied()
+                .collect(),
+        };
+        let idx = self.features.len() as u16;
+        self.features
+            .push(FeatureRecord::new(old.feature_tag, feature));
+        self.combined.insert((old_idx, new_idx), idx);
+        idx
+    }
+}
+
+/// Find a script, falling back to `DFLT` as shapers do.
+fn find_script(script_list: &ScriptList, tag: Tag) -> Option<&Script> {
+    let find = |tag| {
+        script_list
+            .script_records
+            .iter()
+            .find(|rec| rec.script_tag == tag)
+            .map(|rec| &*rec.script)
+    };
+    find(tag).or_else(|| find(tags::SCRIPT_DFLT))
+}
+
+/// Find a language system, falling back to the script's default.
+fn lang_sys(script: &Script, lang: Option<Tag>) -> Option<&LangSys> {
+    lang.and_then(|tag| {
+        script
+            .lang_sys_records
+            .iter()
+            .find(|rec| rec.lang_sys_tag == tag)
+            .map(|rec| &*rec.lang_sys)
+    })
+    .or(script.default_lang_sys.as_ref())
+}
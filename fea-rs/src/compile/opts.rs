@@ -5,10 +5,45 @@
 // a very important part of our API, and a more natural place for us to specify
 // options is in the 'Compiler' struct itself.
 
+const DEBG_KEY: &str = "com.github.cmyr.fea-rs";
+
 /// Options for configuring compilation behaviour.
 #[derive(Clone, Debug, Default)]
 pub struct Opts {
     pub(crate) make_post_table: bool,
+    pub(crate) stamp_version_info: bool,
+    pub(crate) glyph_class_inference: GlyphClassInference,
+    pub(crate) compact_pair_pos: bool,
+    pub(crate) dedupe_lookups: bool,
+    pub(crate) share_context_class_defs: bool,
+    pub(crate) dedupe_names: bool,
+}
+
+/// Whether to infer the glyph classes in the `GDEF` table.
+///
+/// The spec says that if the glyph classes are not declared explicitly (with
+/// a `GlyphClassDef` statement in a `GDEF` table block) they should be inferred
+/// from the lookups: glyphs used as bases, ligatures and marks in mark
+/// attachment rules, and glyphs in mark classes.
+///
+/// Note that the `IgnoreBaseGlyphs`, `IgnoreLigatures` and `IgnoreMarks`
+/// lookup flags, as well as mark-to-mark attachment, depend on these classes.
+/// If they are not inferred, they must be provided some other way (for
+/// instance by a GDEF table that is compiled elsewhere) for these lookups to
+/// work as expected.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GlyphClassInference {
+    /// Never infer glyph classes; only explicitly declared classes are used.
+    Never,
+    /// Infer glyph classes if none are declared explicitly.
+    ///
+    /// This is the behaviour described in the spec.
+    #[default]
+    IfMissing,
+    /// Always infer glyph classes, and add them to the declared classes.
+    ///
+    /// Glyphs that are explicitly assigned a class keep that class.
+    Merge,
 }
 
 impl Opts {
@@ -22,4 +57,91 @@ impl Opts {
         self.make_post_table = flag;
         self
     }
+
+    /// Configure how glyph classes in the `GDEF` table are inferred.
+    ///
+    /// See [`GlyphClassInference`] for details.
+    pub fn glyph_class_inference(mut self, inference: GlyphClassInference) -> Self {
+        self.glyph_class_inference = inference;
+        self
+    }
+
+    /// If `true`, pair positioning subtables may be rewritten to use classes.
+    ///
+    /// Rules between individual glyphs (such as those written with `enum pos`)
+    /// are normally compiled to format 1 `PairPos` subtables, which list every
+    /// pair, matching the output of other compilers. With this option, the
+    /// compiler infers glyph classes from these pairs and uses a class-based
+    /// (format 2) subtable wherever that is smaller, which can greatly reduce
+    /// the size of large kerning lookups.
+    ///
+    /// A subtable is only rewritten if doing so does not change which pairs
+    /// are adjusted by the lookup.
+    pub fn compact_pair_pos(mut self, flag: bool) -> Self {
+        self.compact_pair_pos = flag;
+        self
+    }
+
+    /// If `true`, identical lookups in the same table are merged.
+    ///
+    /// The same rules are often written more than once, for instance in the
+    /// blocks for different scripts in a feature. Each of these is normally
+    /// compiled to its own lookup, matching the output of other compilers.
+    /// With this option, a lookup that is identical to an earlier lookup is
+    /// removed, and references to it are replaced with references to the
+    /// earlier lookup.
+    ///
+    /// A lookup is only merged if doing so does not change the order in which
+    /// lookups are applied for any language system.
+    pub fn dedupe_lookups(mut self, flag: bool) -> Self {
+        self.dedupe_lookups = flag;
+        self
+    }
+
+    /// If `true`, chaining contextual subtables may share a single classdef.
+    ///
+    /// Class-based (format 2) chaining contextual subtables normally have
+    /// separate classdefs for the backtrack, input, and lookahead sequences,
+    /// matching the output of other compilers. When the same classes are used
+    /// throughout the rules, this option allows the compiler to use a single
+    /// classdef for all three sequences wherever that is smaller; identical
+    /// tables are only written once, and the smaller subtable may also make
+    /// format 2 preferable to format 3.
+    pub fn share_context_class_defs(mut self, flag: bool) -> Self {
+        self.share_context_class_defs = flag;
+        self
+    }
+
+    /// If `true`, identical groups of generated names share a single name id.
+    ///
+    /// Names used by the `size` feature, `featureNames` and `cvParameters`
+    /// blocks and the STAT table are normally each given a new id above 255,
+    /// matching the output of other compilers. With this option, a group of
+    /// names that is identical to one that already has an id (for instance,
+    /// the same axis value name used in several places) reuses that id.
+    pub fn dedupe_names(mut self, flag: bool) -> Self {
+        self.dedupe_names = flag;
+        self
+    }
+
+    /// If `true`, we will add a `Debg` table recording the compiler version.
+    ///
+    /// This is intended to help trace a shipped font back to the version of
+    /// fea-rs (and the options) used to compile it. The table contains a JSON
+    /// object, following the convention used by fontTools, with our information
+    /// under the `"com.github.cmyr.fea-rs"` key.
+    pub fn stamp_version_info(mut self, flag: bool) -> Self {
+        self.stamp_version_info = flag;
+        self
+    }
+
+    /// The contents of the `Debg` table written when stamping version info.
+    pub(crate) fn version_info(&self) -> String {
+        format!(
+            r#"{{"{}":{{"version":"{}","options":{{"make_post_table":{}}}}}}}"#,
+            DEBG_KEY,
+            crate::version(),
+            self.make_post_table,
+        )
+    }
 }
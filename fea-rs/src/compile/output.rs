@@ -1,13 +1,28 @@
 //! The result of a compilation
 
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+    ops::Range,
+};
+
 use write_fonts::{
-    tables::{self as wtables, maxp::Maxp},
+    from_obj::ToOwnedTable,
+    read::{FontRef, ReadError, TableProvider},
+    tables::{self as wtables, layout::FeatureParams, maxp::Maxp},
+    types::{GlyphId, NameId, Tag},
     BuilderError, FontBuilder,
 };
 
-use super::Opts;
+use super::{
+    error::MergeError,
+    merge::MergedLayout,
+    overflow,
+    tables::{update_vhea, AnonNameShift, ExplicitFields, VerticalMetrics},
+    tags, Opts,
+};
 
-use crate::{Diagnostic, GlyphMap};
+use crate::{decompile::LookupWriter, parse::FileId, Diagnostic, GlyphMap};
 
 /// The tables generated by this compilation.
 ///
@@ -18,64 +33,474 @@ use crate::{Diagnostic, GlyphMap};
 /// may either interact with these directly, or else they may use the [`to_binary`]
 /// method to generate a binary font.
 ///
+/// A `Compilation` is `Send` and `Sync`, so compilations for different masters
+/// can be produced on different threads and collected afterwards.
+///
+/// Because the tables are constructed before they are serialized, they can
+/// be modified (for instance to merge in data from another source) before
+/// being added to a font:
+///
+/// ```
+/// # use fea_rs::{parse::MemoryResolver, Compiler, GlyphMap, GlyphName};
+/// # use write_fonts::{tables::gsub::Gsub, FontBuilder};
+/// let glyph_map: GlyphMap = [".notdef", "a", "b"].into_iter().map(GlyphName::new).collect();
+/// let resolver: MemoryResolver = [("features.fea", "feature test { sub a by b; } test;")]
+///     .into_iter()
+///     .collect();
+/// let mut compilation = Compiler::new("features.fea", &glyph_map)
+///     .with_resolver(resolver)
+///     .compile()
+///     .unwrap();
+///
+/// // take ownership of a table, and handle it ourselves
+/// let gsub: Gsub = compilation.gsub.take().unwrap();
+/// assert_eq!(gsub.lookup_list.lookups.len(), 1);
+///
+/// let mut builder = FontBuilder::new();
+/// compilation.apply(&mut builder).unwrap();
+/// builder.add_table(&gsub).unwrap();
+/// let font_data = builder.build();
+/// # assert!(!font_data.is_empty());
+/// ```
+///
 /// [`to_binary`]: Compilation::to_binary
 pub struct Compilation {
     /// Any warnings encountered during parsing or compilation
     pub warnings: Vec<Diagnostic>,
+    /// Any errors encountered during parsing or compilation.
+    ///
+    /// This is only non-empty when compiling in lenient mode; see
+    /// [`Compiler::with_lenient_mode`][crate::Compiler::with_lenient_mode].
+    pub errors: Vec<Diagnostic>,
     /// The `head` table, if one was generated
     pub head: Option<wtables::head::Head>,
     /// The `hhea` table, if one was generated
     pub hhea: Option<wtables::hhea::Hhea>,
     /// The `vhea` table, if one was generated
     pub vhea: Option<wtables::vhea::Vhea>,
+    /// The vertical metrics set in a `vmtx` table block, if any.
+    ///
+    /// The `vmtx` table is built from these when the compilation is applied
+    /// to a font that has one; see [`apply_to_font`](Compilation::apply_to_font).
+    pub vmtx: Option<VerticalMetrics>,
     /// The `OS/2` table, if one was generated
     pub os2: Option<wtables::os2::Os2>,
+    /// The fields of `OS/2`, `hhea` and `vhea` that were set in the FEA
+    pub(crate) explicit_fields: ExplicitFields,
     /// The `GDEF` table, if one was generated
     pub gdef: Option<wtables::gdef::Gdef>,
     /// The `BASE` table, if one was generated
     pub base: Option<wtables::base::Base>,
     /// The `name` table, if one was generated
     pub name: Option<wtables::name::Name>,
+    /// The first name id allocated for names without an explicit id
+    pub(crate) first_anon_name_id: Option<NameId>,
     /// The `STAT` table, if one was generated
     pub stat: Option<wtables::stat::Stat>,
     /// The `GSUB` table, if one was generated
     pub gsub: Option<wtables::gsub::Gsub>,
     /// The `GPOS` table, if one was generated
     pub gpos: Option<wtables::gpos::Gpos>,
+    /// The named anchors defined with `anchorDef` statements, by name.
+    ///
+    /// This is intended for editor tooling, for instance to show the
+    /// coordinates of an `<anchor NAME>` reference, or to jump to its
+    /// definition.
+    pub anchors: BTreeMap<String, AnchorDefinition>,
+    /// The anonymous (`anon`) blocks in the source, in order.
+    ///
+    /// The compiler does not interpret the contents of these blocks; they are
+    /// provided so that they can be handled by other tools.
+    pub anon_blocks: Vec<AnonymousBlock>,
+}
+
+/// An anonymous block, such as `anon sbit { ... } sbit;`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnonymousBlock {
+    /// The label of the block, e.g. `sbit`
+    pub label: String,
+    /// The raw text between the braces, including comments and whitespace
+    pub contents: String,
+    /// The file containing the block
+    pub file: FileId,
+    /// The range of the block in `file`
+    pub range: Range<usize>,
+}
+
+/// A named anchor, defined with an `anchorDef` statement.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnchorDefinition {
+    /// The x coordinate
+    pub x: i16,
+    /// The y coordinate
+    pub y: i16,
+    /// The contour point, if one was specified
+    pub contour_point: Option<u16>,
+    /// The file containing the definition
+    pub file: FileId,
+    /// The range of the anchor's name in the definition, in `file`
+    pub range: Range<usize>,
 }
 
 impl Compilation {
-    /// Assemble the output tables into a `FontBuilder`.
+    /// Returns `true` if no tables were generated.
     ///
-    /// This is a convenience method. To compile a binary font you can use
-    /// [`to_binary`] instead, and for more fine-grained control you can inspect
-    /// and manipulate the raw tables directly.
+    /// This is the case for empty sources, or those that contain only
+    /// whitespace and comments.
+    pub fn is_empty(&self) -> bool {
+        self.head.is_none()
+            && self.hhea.is_none()
+            && self.vhea.is_none()
+            && self.vmtx.is_none()
+            && self.os2.is_none()
+            && self.gdef.is_none()
+            && self.base.is_none()
+            && self.name.is_none()
+            && self.stat.is_none()
+            && self.gsub.is_none()
+            && self.gpos.is_none()
+    }
+
+    /// Return the characters listed in any `cvParameters` blocks, by feature tag.
     ///
-    /// [`to_binary`]: Compilation::to_binary
-    pub fn to_font_builder(&self) -> Result<FontBuilder, BuilderError> {
-        let mut builder = FontBuilder::default();
+    /// These are the `Character` statements in the source, which identify the
+    /// characters for which a `cvXX` feature provides glyph variants.
+    pub fn character_variant_characters(&self) -> BTreeMap<Tag, Vec<char>> {
+        let Some(gsub) = self.gsub.as_ref() else {
+            return Default::default();
+        };
+        gsub.feature_list
+            .feature_records
+            .iter()
+            .filter_map(|record| match record.feature.feature_params.as_ref() {
+                Some(FeatureParams::CharacterVariant(params)) => Some((
+                    record.feature_tag,
+                    params
+                        .character
+                        .iter()
+                        .filter_map(|c| char::from_u32(c.to_u32()))
+                        .collect(),
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Return any `cvParameters` characters that are not mapped by the font's cmap.
+    ///
+    /// This can be used to verify that the characters declared in the source
+    /// actually exist in the target font. Returns an error if the font does
+    /// not have a readable `cmap` table.
+    pub fn unmapped_character_variant_characters(
+        &self,
+        font: &FontRef,
+    ) -> Result<Vec<(Tag, char)>, ReadError> {
+        let cmap = font.cmap()?;
+        Ok(self
+            .character_variant_characters()
+            .into_iter()
+            .flat_map(|(tag, chars)| chars.into_iter().map(move |c| (tag, c)))
+            .filter(|(_, c)| cmap.map_codepoint(*c).is_none())
+            .collect())
+    }
+
+    /// Write a compiled lookup as (approximate) FEA.
+    ///
+    /// `table` should be either `GSUB` or `GPOS`, and `lookup_index` is the
+    /// index of the lookup in that table's lookup list. Returns `None` if the
+    /// table was not generated or the index is out of bounds.
+    ///
+    /// This is a best-effort reconstruction, intended to help with debugging
+    /// (for instance to see what ended up in a particularly large lookup). The
+    /// output is not guaranteed to compile to an identical lookup; device
+    /// tables are omitted, mark filtering sets and attachment classes are
+    /// written as comments, and nested lookups are referred to by index.
+    ///
+    /// To write all of the layout tables as FEA, see [`crate::decompile`].
+    pub fn lookup_to_fea(
+        &self,
+        table: Tag,
+        lookup_index: usize,
+        glyph_map: &GlyphMap,
+    ) -> Option<String> {
+        let name = format!("{table}_{lookup_index}");
+        let writer = LookupWriter::new(glyph_map);
+        match table {
+            tags::GSUB => {
+                let lookup = self.gsub.as_ref()?.lookup_list.lookups.get(lookup_index)?;
+                Some(writer.write_gsub_lookup(&name, lookup))
+            }
+            tags::GPOS => {
+                let lookup = self.gpos.as_ref()?.lookup_list.lookups.get(lookup_index)?;
+                Some(writer.write_gpos_lookup(&name, lookup))
+            }
+            _ => None,
+        }
+    }
+
+    /// Return the set of glyphs reachable from `glyphs` by the generated `GSUB`.
+    ///
+    /// If no `GSUB` table was generated, this is just the input glyphs.
+    /// See [`gsub_closure`][super::gsub_closure] for details.
+    pub fn gsub_closure(&self, glyphs: impl IntoIterator<Item = GlyphId>) -> BTreeSet<GlyphId> {
+        match &self.gsub {
+            Some(gsub) => super::gsub_closure(gsub, glyphs),
+            None => glyphs.into_iter().collect(),
+        }
+    }
+
+    /// Add the output tables to a `FontBuilder`.
+    ///
+    /// Any tables in the builder with the same tags are replaced.
+    ///
+    /// Metrics set in a `vmtx` block are ignored (with a warning), since they
+    /// can only be applied to the metrics of an existing font; see
+    /// [`apply_to_font`](Compilation::apply_to_font).
+    pub fn apply(&self, builder: &mut FontBuilder) -> Result<(), BuilderError> {
+        if self.vmtx.is_some() {
+            log::warn!("ignoring vmtx table: it can only be applied to an existing font");
+        }
+        self.apply_impl(
+            builder,
+            OutputTables {
+                head: self.head.as_ref(),
+                hhea: self.hhea.as_ref(),
+                vhea: self.vhea.as_ref(),
+                vmtx: None,
+                vorg: None,
+                os2: self.os2.as_ref(),
+                name: self.name.as_ref(),
+                stat: self.stat.as_ref(),
+                gdef: self.gdef.as_ref(),
+                gsub: self.gsub.as_ref(),
+                gpos: self.gpos.as_ref(),
+            },
+        )
+    }
+
+    /// Add the output tables to an existing font, returning the new font data.
+    ///
+    /// Tables in the font that were not generated by this compilation are
+    /// copied unchanged, and other tables are replaced, with some exceptions
+    /// for tables that contain fields that cannot be set from FEA: in the
+    /// `head` table only the font revision is updated, and in the `OS/2`,
+    /// `hhea` and `vhea` tables only the fields set in the FEA are updated.
+    /// (If the font does not have one of these tables, the generated table is
+    /// added as is.)
+    ///
+    /// Generated name records are merged into the font's `name` table,
+    /// replacing any existing records with the same platform, encoding,
+    /// language and name id. Names that were not given an id in the FEA (such
+    /// as those in `featureNames` blocks) are first renumbered to follow the
+    /// font's existing names, so that they do not replace them.
+    ///
+    /// Metrics set in a `vmtx` block are merged with the font's existing
+    /// `vmtx` table, and the `vhea` table is updated to match. This fails if
+    /// the font has no `vmtx` table. Vertical origins in a font with CFF
+    /// outlines are written to the font's `VORG` table, and are ignored if it
+    /// does not have one.
+    ///
+    /// The font's `GDEF`, `GSUB` and `GPOS` tables are replaced; to add the
+    /// generated lookups and features to them instead, use
+    /// [`merge_into_font`](Compilation::merge_into_font).
+    pub fn apply_to_font(&self, font: &FontRef) -> Result<Vec<u8>, MergeError> {
+        let named = self.named_tables(font);
+        self.apply_to_font_impl(font, &named, None)
+    }
+
+    /// Add the output tables to an existing font, preserving its layout.
+    ///
+    /// This is like [`apply_to_font`](Compilation::apply_to_font), except
+    /// that the generated `GDEF`, `GSUB` and `GPOS` tables are merged into
+    /// those of the font, for instance when patching the features of a
+    /// binary font:
+    ///
+    /// - the generated lookups are added after the font's lookups, so they
+    ///   are applied after them;
+    /// - the generated features are added to each language system, following
+    ///   the usual fallbacks to the default language and the `DFLT` script;
+    ///   where a language system already has a feature with the same tag, the
+    ///   two are combined into one feature with the lookups of both;
+    /// - generated feature variations with the same conditions as a record in
+    ///   the font are added to that record, and others are added after the
+    ///   font's records (where these overlap, only the font's are applied);
+    /// - in `GDEF`, glyphs that are already classified (or that have
+    ///   attachment points or ligature carets) keep their existing values,
+    ///   and the generated mark glyph sets are added after the font's.
+    ///
+    /// Merging fails if both the font and the compilation have a variation
+    /// store in `GDEF`.
+    pub fn merge_into_font(&self, font: &FontRef) -> Result<Vec<u8>, MergeError> {
+        let named = self.named_tables(font);
+        let layout = super::merge::merge_layout(
+            self.gdef.as_ref(),
+            named.gsub.as_deref(),
+            named.gpos.as_deref(),
+            font,
+        )?;
+        self.apply_to_font_impl(font, &named, Some(&layout))
+    }
+
+    /// The generated tables that refer to names, with any names that were
+    /// not given an id moved above the ids already used in `font`.
+    fn named_tables(&self, font: &FontRef) -> NamedTables<'_> {
+        let mut named = NamedTables {
+            name: self.name.as_ref().map(Cow::Borrowed),
+            stat: self.stat.as_ref().map(Cow::Borrowed),
+            gsub: self.gsub.as_ref().map(Cow::Borrowed),
+            gpos: self.gpos.as_ref().map(Cow::Borrowed),
+        };
+        let Some(shift) = AnonNameShift::new(self.first_anon_name_id, font) else {
+            return named;
+        };
+        if let Some(name) = named.name.as_mut() {
+            shift.shift_name(name.to_mut());
+        }
+        if let Some(stat) = named.stat.as_mut() {
+            shift.shift_stat(stat.to_mut());
+        }
+        if let Some(gsub) = named.gsub.as_mut() {
+            let gsub = gsub.to_mut();
+            shift.shift_features(&mut gsub.feature_list, gsub.feature_variations.as_mut());
+        }
+        if let Some(gpos) = named.gpos.as_mut() {
+            let gpos = gpos.to_mut();
+            shift.shift_features(&mut gpos.feature_list, gpos.feature_variations.as_mut());
+        }
+        named
+    }
+
+    fn apply_to_font_impl(
+        &self,
+        font: &FontRef,
+        named: &NamedTables,
+        layout: Option<&MergedLayout>,
+    ) -> Result<Vec<u8>, MergeError> {
+        let head = self.head.as_ref().map(|head| match font.head() {
+            Ok(existing) => wtables::head::Head {
+                font_revision: head.font_revision,
+                ..existing.to_owned_table()
+            },
+            Err(_) => head.clone(),
+        });
+        let fields = &self.explicit_fields;
+        let hhea = self.hhea.as_ref().map(|hhea| match font.hhea() {
+            Ok(existing) => {
+                let mut existing = existing.to_owned_table();
+                fields.apply_hhea(hhea, &mut existing);
+                existing
+            }
+            Err(_) => hhea.clone(),
+        });
+        let vhea = self.vhea.as_ref().map(|vhea| match font.vhea() {
+            Ok(existing) => {
+                let mut existing = existing.to_owned_table();
+                fields.apply_vhea(vhea, &mut existing);
+                existing
+            }
+            Err(_) => vhea.clone(),
+        });
+        let os2 = self.os2.as_ref().map(|os2| match font.os2() {
+            Ok(existing) => {
+                let mut existing = existing.to_owned_table();
+                fields.apply_os2(os2, &mut existing);
+                existing
+            }
+            Err(_) => os2.clone(),
+        });
+        let vertical = self
+            .vmtx
+            .as_ref()
+            .map(|vmtx| vmtx.build(font))
+            .transpose()?;
+        let vmtx = vertical.as_ref().map(|tables| &tables.vmtx);
+        let vhea = vhea_for_vmtx(
+            vhea.or_else(|| font.vhea().ok().map(|vhea| vhea.to_owned_table())),
+            vmtx,
+        );
+
+        let name = named
+            .name
+            .as_deref()
+            .map(|name| super::tables::merge_name_tables(name, font));
+
+        let mut builder = FontBuilder::new();
+        self.apply_impl(
+            &mut builder,
+            OutputTables {
+                head: head.as_ref(),
+                hhea: hhea.as_ref(),
+                vhea: vhea.as_ref(),
+                vmtx,
+                vorg: vertical.as_ref().and_then(|tables| tables.vorg.as_deref()),
+                os2: os2.as_ref(),
+                name: name.as_ref(),
+                stat: named.stat.as_deref(),
+                gdef: layout.map_or(self.gdef.as_ref(), |layout| layout.gdef.as_ref()),
+                gsub: layout.map_or(named.gsub.as_deref(), |layout| layout.gsub.as_ref()),
+                gpos: layout.map_or(named.gpos.as_deref(), |layout| layout.gpos.as_ref()),
+            },
+        )?;
+        builder.copy_missing_tables(font.clone());
+        Ok(builder.build())
+    }
+
+    fn apply_impl(
+        &self,
+        builder: &mut FontBuilder,
+        tables: OutputTables,
+    ) -> Result<(), BuilderError> {
         macro_rules! add_if_some {
             ($table:expr) => {
-                if let Some(table) = $table.as_ref() {
+                if let Some(table) = $table {
                     builder.add_table(table)?;
                 }
             };
         }
-        add_if_some!(self.head);
-        add_if_some!(self.hhea);
-        add_if_some!(self.vhea);
-        add_if_some!(self.os2);
-        add_if_some!(self.gdef);
-        add_if_some!(self.base);
-        add_if_some!(self.name);
-        add_if_some!(self.stat);
-        add_if_some!(self.gsub);
-        add_if_some!(self.gpos);
+        add_if_some!(tables.head);
+        add_if_some!(tables.hhea);
+        add_if_some!(tables.vhea);
+        add_if_some!(tables.vmtx);
+        if let Some(vorg) = tables.vorg {
+            builder.add_raw(tags::VORG, vorg.to_vec());
+        }
+        add_if_some!(tables.os2);
+        add_if_some!(tables.gdef);
+        add_if_some!(self.base.as_ref());
+        add_if_some!(tables.name);
+        add_if_some!(tables.stat);
+        if let Some(gsub) = tables.gsub {
+            overflow::add_gsub(builder, gsub)?;
+        }
+        if let Some(gpos) = tables.gpos {
+            overflow::add_gpos(builder, gpos)?;
+        }
+        Ok(())
+    }
+
+    /// Assemble the output tables into a new `FontBuilder`.
+    ///
+    /// This is a convenience method. To compile a binary font you can use
+    /// [`to_binary`] instead; to add the tables to an existing font, use
+    /// [`apply_to_font`]. For more fine-grained control you can inspect
+    /// and manipulate the raw tables directly.
+    ///
+    /// [`to_binary`]: Compilation::to_binary
+    /// [`apply_to_font`]: Compilation::apply_to_font
+    pub fn to_font_builder(&self) -> Result<FontBuilder, BuilderError> {
+        let mut builder = FontBuilder::default();
+        self.apply(&mut builder)?;
         Ok(builder)
     }
 
     /// Compile the output tables into a font.
     ///
+    /// The result does not require a source font: it contains only the tables
+    /// generated from the FEA (such as `GSUB`, `GPOS`, and `GDEF`), along with
+    /// a minimal `maxp` table and, if requested in the [`Opts`], a `post`
+    /// table. This makes it suitable as a fragment to be merged into a font
+    /// built elsewhere.
+    ///
     /// This is a convenience method used for things like testing; if you are
     /// building a font compiler you will probably prefer to manipulate the
     /// generated tables directly.
@@ -89,7 +514,740 @@ impl Compilation {
             let post = glyph_map.make_post_table();
             builder.add_table(&post)?;
         }
+        if opts.stamp_version_info {
+            builder.add_raw(tags::DEBG, opts.version_info().into_bytes());
+        }
 
         Ok(builder.build())
     }
+
+    /// Compile the output tables into a minimal font for shaping tests.
+    ///
+    /// This does not require a source font: in addition to the compiled
+    /// tables, the font contains an empty outline for each glyph in the glyph
+    /// map, with a fixed advance of 500 units (on a 1000 unit em), a `post`
+    /// table with the glyph names, and a `cmap` table guessed from the glyph
+    /// names, such as `a`, `period`, or `uni0041`.
+    ///
+    /// The result can be loaded by a shaping engine (such as `hb-shape`) in
+    /// order to test the behaviour of the layout tables.
+    pub fn to_test_font(&self, glyph_map: &GlyphMap) -> Result<Vec<u8>, BuilderError> {
+        super::test_font::build(self, glyph_map)
+    }
+}
+
+/// The tables to add to a font, where these may differ from the compilation's.
+struct OutputTables<'a> {
+    head: Option<&'a wtables::head::Head>,
+    hhea: Option<&'a wtables::hhea::Hhea>,
+    vhea: Option<&'a wtables::vhea::Vhea>,
+    vmtx: Option<&'a wtables::vmtx::Vmtx>,
+    /// The raw `VORG` table, which write-fonts does not support
+    vorg: Option<&'a [u8]>,
+    os2: Option<&'a wtables::os2::Os2>,
+    name: Option<&'a wtables::name::Name>,
+    stat: Option<&'a wtables::stat::Stat>,
+    gdef: Option<&'a wtables::gdef::Gdef>,
+    gsub: Option<&'a wtables::gsub::Gsub>,
+    gpos: Option<&'a wtables::gpos::Gpos>,
+}
+
+/// The generated tables that refer to name ids, renumbered for a font if needed.
+struct NamedTables<'a> {
+    name: Option<Cow<'a, wtables::name::Name>>,
+    stat: Option<Cow<'a, wtables::stat::Stat>>,
+    gsub: Option<Cow<'a, wtables::gsub::Gsub>>,
+    gpos: Option<Cow<'a, wtables::gpos::Gpos>>,
+}
+
+/// A `vmtx` table requires a `vhea` table that describes it.
+fn vhea_for_vmtx(
+    vhea: Option<wtables::vhea::Vhea>,
+    vmtx: Option<&wtables::vmtx::Vmtx>,
+) -> Option<wtables::vhea::Vhea> {
+    let Some(vmtx) = vmtx else {
+        return vhea;
+    };
+    let mut vhea = vhea.unwrap_or_default();
+    update_vhea(&mut vhea, vmtx);
+    Some(vhea)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsStr, sync::Arc};
+
+    use write_fonts::{
+        tables::cmap::Cmap,
+        types::{GlyphId, NameId},
+    };
+
+    use super::*;
+    use crate::{compile::MockVariationInfo, Compiler, GlyphName};
+
+    #[test]
+    fn version_info() {
+        let glyph_map: GlyphMap = [".notdef", "a", "b"]
+            .into_iter()
+            .map(GlyphName::new)
+            .collect();
+        let compilation = Compiler::new("test.fea", &glyph_map)
+            .with_resolver(|_: &OsStr| Ok(Arc::<str>::from("feature test { sub a by b; } test;")))
+            .compile()
+            .unwrap();
+
+        let bytes = compilation.to_binary(&glyph_map, Opts::new()).unwrap();
+        let font = FontRef::new(&bytes).unwrap();
+        assert!(font.table_data(tags::DEBG).is_none());
+
+        let opts = Opts::new().make_post_table(true).stamp_version_info(true);
+        let bytes = compilation.to_binary(&glyph_map, opts).unwrap();
+        let font = FontRef::new(&bytes).unwrap();
+        let debg = font.table_data(tags::DEBG).unwrap();
+        assert_eq!(
+            std::str::from_utf8(debg.as_bytes()).unwrap(),
+            format!(
+                r#"{{"com.github.cmyr.fea-rs":{{"version":"{}","options":{{"make_post_table":true}}}}}}"#,
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+    }
+
+    #[test]
+    fn binary_contains_only_generated_tables() {
+        let glyph_map: GlyphMap = [".notdef", "a", "b", "acutecomb"]
+            .into_iter()
+            .map(GlyphName::new)
+            .collect();
+        let fea = "\
+            table GDEF { GlyphClassDef [a b], , [acutecomb], ; } GDEF;\n\
+            feature test { sub a by b; } test;\n\
+            feature kern { pos a b -20; } kern;\n";
+        let compilation = Compiler::new("test.fea", &glyph_map)
+            .with_resolver(move |_: &OsStr| Ok(Arc::<str>::from(fea)))
+            .compile()
+            .unwrap();
+
+        let bytes = compilation.to_binary(&glyph_map, Opts::new()).unwrap();
+        let font = FontRef::new(&bytes).unwrap();
+        let tags = font
+            .table_directory
+            .table_records()
+            .iter()
+            .map(|record| record.tag())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tags,
+            [
+                Tag::new(b"GDEF"),
+                Tag::new(b"GPOS"),
+                Tag::new(b"GSUB"),
+                Tag::new(b"maxp")
+            ]
+        );
+        assert_eq!(font.maxp().unwrap().num_glyphs(), 4);
+    }
+
+    #[test]
+    fn lookup_to_fea() {
+        let fea = "\
+            lookup single { sub a by b; sub [c d] by [e f]; } single;\n\
+            lookup lig { lookupflag IgnoreMarks; sub f i by f_i; sub f f i by f_f_i; } lig;\n\
+            lookup multi { sub f_i by f i; } multi;\n\
+            lookup alt { sub a from [b c]; } alt;\n\
+            lookup single_pos { pos a -10; pos b <1 2 3 4>; } single_pos;\n\
+            lookup pair { pos a b -20; pos c <1 0 1 0> d <0 0 5 0>; pos [e f] [a b] 30; } pair;\n";
+        let glyph_map: GlyphMap = [".notdef", "a", "b", "c", "d", "e", "f", "i", "f_i", "f_f_i"]
+            .into_iter()
+            .map(GlyphName::new)
+            .collect();
+        let compilation = Compiler::new("lookups.fea", &glyph_map)
+            .with_resolver(move |_: &OsStr| Ok(Arc::<str>::from(fea)))
+            .compile()
+            .unwrap();
+        let lookup = |table, idx| compilation.lookup_to_fea(table, idx, &glyph_map).unwrap();
+
+        assert_eq!(
+            lookup(tags::GSUB, 0),
+            "lookup GSUB_0 {\n    sub a by b;\n    sub c by e;\n    sub d by f;\n} GSUB_0;\n"
+        );
+        assert_eq!(
+            lookup(tags::GSUB, 1),
+            "lookup GSUB_1 {\n    lookupflag IgnoreMarks;\n    sub f f i by f_f_i;\n    sub f i by f_i;\n} GSUB_1;\n"
+        );
+        assert_eq!(
+            lookup(tags::GSUB, 2),
+            "lookup GSUB_2 {\n    sub f_i by f i;\n} GSUB_2;\n"
+        );
+        assert_eq!(
+            lookup(tags::GSUB, 3),
+            "lookup GSUB_3 {\n    sub a from [b c];\n} GSUB_3;\n"
+        );
+        assert_eq!(
+            lookup(tags::GPOS, 0),
+            "lookup GPOS_0 {\n    pos a -10;\n    subtable;\n    pos b <1 2 3 4>;\n} GPOS_0;\n"
+        );
+        assert_eq!(
+            lookup(tags::GPOS, 1),
+            "lookup GPOS_1 {\n    pos a b -20;\n    subtable;\n    pos c <1 0 1 0> d <0 0 5 0>;\n    subtable;\n    pos [e f] [a b] 30;\n} GPOS_1;\n"
+        );
+        assert!(compilation
+            .lookup_to_fea(tags::GPOS, 2, &glyph_map)
+            .is_none());
+        assert!(compilation
+            .lookup_to_fea(Tag::new(b"GDEF"), 0, &glyph_map)
+            .is_none());
+    }
+
+    #[test]
+    fn apply_to_font() {
+        let base = FontBuilder::new()
+            .add_table(&wtables::head::Head {
+                units_per_em: 1000,
+                ..Default::default()
+            })
+            .unwrap()
+            .add_table(&wtables::hhea::Hhea {
+                ascender: 700.into(),
+                number_of_long_metrics: 3,
+                ..Default::default()
+            })
+            .unwrap()
+            .add_raw(Tag::new(b"TEST"), vec![1, 2, 3, 4])
+            .build();
+        let base = FontRef::new(&base).unwrap();
+
+        let fea = "\
+            table head { FontRevision 2.500; } head;\n\
+            table hhea { Ascender 900; } hhea;\n\
+            feature test { sub a by b; } test;\n";
+        let glyph_map: GlyphMap = [".notdef", "a", "b"]
+            .into_iter()
+            .map(GlyphName::new)
+            .collect();
+        let compilation = Compiler::new("test.fea", &glyph_map)
+            .with_resolver(move |_: &OsStr| Ok(Arc::<str>::from(fea)))
+            .compile()
+            .unwrap();
+
+        let bytes = compilation.apply_to_font(&base).unwrap();
+        let font = FontRef::new(&bytes).unwrap();
+        let head = font.head().unwrap();
+        assert_eq!(head.units_per_em(), 1000);
+        assert_eq!(head.font_revision().to_f64(), 2.5);
+        let hhea = font.hhea().unwrap();
+        assert_eq!(hhea.ascender().to_i16(), 900);
+        assert_eq!(hhea.number_of_long_metrics(), 3);
+        assert_eq!(
+            font.table_data(Tag::new(b"TEST")).unwrap().as_bytes(),
+            [1, 2, 3, 4]
+        );
+        assert!(font.gsub().is_ok());
+    }
+
+    #[test]
+    fn apply_only_explicit_fields_to_font() {
+        let base = FontBuilder::new()
+            .add_table(&wtables::os2::Os2 {
+                us_weight_class: 700,
+                fs_selection: wtables::os2::SelectionFlags::BOLD,
+                panose_10: [2, 11, 8, 3, 0, 0, 0, 0, 0, 0],
+                s_typo_ascender: 800,
+                us_win_ascent: 1000,
+                ..Default::default()
+            })
+            .unwrap()
+            .add_table(&wtables::hhea::Hhea {
+                ascender: 700.into(),
+                descender: (-200).into(),
+                advance_width_max: 1200.into(),
+                ..Default::default()
+            })
+            .unwrap()
+            .build();
+        let base = FontRef::new(&base).unwrap();
+
+        let fea = "\
+            table OS/2 { TypoAscender 750; XHeight 500; } OS/2;\n\
+            table hhea { LineGap 100; } hhea;\n";
+        let glyph_map: GlyphMap = [".notdef", "a"].into_iter().map(GlyphName::new).collect();
+        let compilation = Compiler::new("test.fea", &glyph_map)
+            .with_resolver(move |_: &OsStr| Ok(Arc::<str>::from(fea)))
+            .compile()
+            .unwrap();
+
+        let bytes = compilation.apply_to_font(&base).unwrap();
+        let font = FontRef::new(&bytes).unwrap();
+        let os2 = font.os2().unwrap();
+        assert_eq!(os2.s_typo_ascender(), 750);
+        assert_eq!(os2.sx_height(), Some(500));
+        // the table is upgraded to include the x-height
+        assert_eq!(os2.version(), 4);
+        assert_eq!(os2.us_weight_class(), 700);
+        assert_eq!(os2.fs_selection(), wtables::os2::SelectionFlags::BOLD);
+        assert_eq!(os2.panose_10(), [2, 11, 8, 3, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(os2.us_win_ascent(), 1000);
+        let hhea = font.hhea().unwrap();
+        assert_eq!(hhea.line_gap().to_i16(), 100);
+        assert_eq!(hhea.ascender().to_i16(), 700);
+        assert_eq!(hhea.descender().to_i16(), -200);
+        assert_eq!(hhea.advance_width_max().to_u16(), 1200);
+    }
+
+    #[test]
+    fn apply_name_to_font() {
+        let record = |id: u16, string: &str| {
+            wtables::name::NameRecord::new(3, 1, 0x409, NameId::new(id), string.to_string().into())
+        };
+        let base = FontBuilder::new()
+            .add_table(&wtables::name::Name::new(
+                [record(1, "Old Family"), record(2, "Regular")].into(),
+            ))
+            .unwrap()
+            .build();
+        let base = FontRef::new(&base).unwrap();
+
+        let fea = "\
+            table name { nameid 1 \"New Family\"; } name;\n\
+            feature ss01 { featureNames { name \"Alt a\"; }; sub a by b; } ss01;\n";
+        let glyph_map: GlyphMap = [".notdef", "a", "b"]
+            .into_iter()
+            .map(GlyphName::new)
+            .collect();
+        let compilation = Compiler::new("test.fea", &glyph_map)
+            .with_resolver(move |_: &OsStr| Ok(Arc::<str>::from(fea)))
+            .compile()
+            .unwrap();
+
+        let bytes = compilation.apply_to_font(&base).unwrap();
+        let font = FontRef::new(&bytes).unwrap();
+        let name = font.name().unwrap();
+        let records = name
+            .name_record()
+            .iter()
+            .map(|rec| {
+                let string = rec.string(name.string_data()).unwrap().to_string();
+                (rec.name_id().to_u16(), string)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            records,
+            [
+                (1, "New Family".to_string()),
+                (2, "Regular".to_string()),
+                (256, "Alt a".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn apply_anon_names_above_font_names() {
+        let record = |id: u16, string: &str| {
+            wtables::name::NameRecord::new(3, 1, 0x409, NameId::new(id), string.to_string().into())
+        };
+        let base = FontBuilder::new()
+            .add_table(&wtables::name::Name::new(
+                [record(1, "Family"), record(256, "Weight")].into(),
+            ))
+            .unwrap()
+            .build();
+        let base = FontRef::new(&base).unwrap();
+
+        let fea = "feature ss01 { featureNames { name \"Alt a\"; }; sub a by b; } ss01;\n";
+        let glyph_map: GlyphMap = [".notdef", "a", "b"]
+            .into_iter()
+            .map(GlyphName::new)
+            .collect();
+        let compilation = Compiler::new("test.fea", &glyph_map)
+            .with_resolver(move |_: &OsStr| Ok(Arc::<str>::from(fea)))
+            .compile()
+            .unwrap();
+
+        for bytes in [
+            compilation.apply_to_font(&base).unwrap(),
+            compilation.merge_into_font(&base).unwrap(),
+        ] {
+            let font = FontRef::new(&bytes).unwrap();
+            let name = font.name().unwrap();
+            let records = name
+                .name_record()
+                .iter()
+                .map(|rec| {
+                    let string = rec.string(name.string_data()).unwrap().to_string();
+                    (rec.name_id().to_u16(), string)
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(
+                records,
+                [
+                    (1, "Family".to_string()),
+                    (256, "Weight".to_string()),
+                    (257, "Alt a".to_string()),
+                ]
+            );
+            let gsub = font.gsub().unwrap();
+            let feature = gsub.feature_list().unwrap().feature_records()[0]
+                .feature(gsub.feature_list().unwrap().offset_data())
+                .unwrap();
+            let Some(Ok(write_fonts::read::tables::layout::FeatureParams::StylisticSet(params))) =
+                feature.feature_params()
+            else {
+                panic!("expected stylistic set params");
+            };
+            assert_eq!(params.ui_name_id().to_u16(), 257);
+        }
+        // the compilation itself is unchanged
+        assert_eq!(
+            compilation
+                .name
+                .as_ref()
+                .unwrap()
+                .name_record
+                .first()
+                .unwrap()
+                .name_id,
+            NameId::new(256)
+        );
+    }
+
+    #[test]
+    fn merge_layout_into_font() {
+        let glyph_map: GlyphMap = [".notdef", "a", "b", "c", "f", "i", "f_i", "a.sc"]
+            .into_iter()
+            .map(GlyphName::new)
+            .collect();
+        let compile = |fea: &'static str| {
+            Compiler::new("test.fea", &glyph_map)
+                .with_resolver(move |_: &OsStr| Ok(Arc::<str>::from(fea)))
+                .compile()
+                .unwrap()
+        };
+        let base = compile(
+            "\
+            languagesystem DFLT dflt;\n\
+            languagesystem latn dflt;\n\
+            languagesystem latn TRK;\n\
+            feature liga { sub f i by f_i; } liga;\n\
+            feature smcp { sub a by a.sc; } smcp;\n",
+        )
+        .to_binary(&glyph_map, Opts::new())
+        .unwrap();
+        let base = FontRef::new(&base).unwrap();
+
+        let compilation = compile(
+            "\
+            languagesystem DFLT dflt;\n\
+            languagesystem latn dflt;\n\
+            lookup ab { sub a by b; } ab;\n\
+            feature liga { sub a' lookup ab b; } liga;\n\
+            feature ss01 { sub b by c; } ss01;\n",
+        );
+        let bytes = compilation.merge_into_font(&base).unwrap();
+        let font = FontRef::new(&bytes).unwrap();
+        let gsub: wtables::gsub::Gsub = font.gsub().unwrap().to_owned_table();
+
+        // the new lookups come after the existing ones, and nested lookup
+        // references are updated to match
+        assert_eq!(gsub.lookup_list.lookups.len(), 5);
+        let wtables::gsub::SubstitutionLookup::ChainContextual(calt) =
+            &*gsub.lookup_list.lookups[3]
+        else {
+            panic!("expected a chain contextual lookup");
+        };
+        let wtables::layout::ChainedSequenceContext::Format3(sub) = &**calt.subtables[0] else {
+            panic!("expected format 3");
+        };
+        assert_eq!(sub.seq_lookup_records[0].lookup_list_index, 2);
+
+        // every language system (including TRK, which only exists in the font)
+        // has a single liga feature with the lookups of both, and the new ss01
+        let features = &gsub.feature_list.feature_records;
+        for script in &gsub.script_list.script_records {
+            let lang_systems = script.script.default_lang_sys.iter().chain(
+                script
+                    .script
+                    .lang_sys_records
+                    .iter()
+                    .map(|rec| &*rec.lang_sys),
+            );
+            for lang_sys in lang_systems {
+                let mut lookups = lang_sys
+                    .feature_indices
+                    .iter()
+                    .map(|idx| {
+                        let record = &features[*idx as usize];
+                        (
+                            record.feature_tag,
+                            record.feature.lookup_list_indices.clone(),
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                lookups.sort();
+                assert_eq!(
+                    lookups,
+                    [
+                        (Tag::new(b"liga"), vec![0, 3]),
+                        (Tag::new(b"smcp"), vec![1]),
+                        (Tag::new(b"ss01"), vec![4]),
+                    ],
+                    "{}",
+                    script.script_tag
+                );
+            }
+        }
+        assert_eq!(
+            gsub.script_list.script_records[1].script.lang_sys_records[0].lang_sys_tag,
+            Tag::new(b"TRK ")
+        );
+    }
+
+    #[test]
+    fn merge_feature_variations_into_font() {
+        let glyph_map: GlyphMap = [".notdef", "a", "b", "c", "d"]
+            .into_iter()
+            .map(GlyphName::new)
+            .collect();
+        let var_info = MockVariationInfo::new(&[("wght", 200, 200, 1000)]);
+        let compile = |fea: &'static str| {
+            Compiler::new("test.fea", &glyph_map)
+                .with_resolver(move |_: &OsStr| Ok(Arc::<str>::from(fea)))
+                .with_variable_info(&var_info)
+                .compile()
+                .unwrap()
+        };
+        let base = compile(
+            "\
+            conditionset heavy { wght 700 1000; } heavy;\n\
+            variation rvrn heavy { sub a by b; } rvrn;\n",
+        )
+        .to_binary(&glyph_map, Opts::new())
+        .unwrap();
+        let base = FontRef::new(&base).unwrap();
+
+        let compilation = compile(
+            "\
+            conditionset heavy { wght 700 1000; } heavy;\n\
+            conditionset light { wght 200 300; } light;\n\
+            variation rvrn heavy { sub c by d; } rvrn;\n\
+            variation ss01 heavy { sub b by c; } ss01;\n\
+            variation ss01 light { sub d by a; } ss01;\n",
+        );
+        let bytes = compilation.merge_into_font(&base).unwrap();
+        let font = FontRef::new(&bytes).unwrap();
+        let gsub: wtables::gsub::Gsub = font.gsub().unwrap().to_owned_table();
+        let features = &gsub.feature_list.feature_records;
+        let records = &gsub
+            .feature_variations
+            .as_ref()
+            .unwrap()
+            .feature_variation_records;
+
+        // the new 'heavy' substitutions are in the font's record, since only
+        // the first matching record is applied; 'light' gets its own record
+        assert_eq!(records.len(), 2);
+        let substitutions = |idx: usize| {
+            records[idx]
+                .feature_table_substitution
+                .as_ref()
+                .unwrap()
+                .substitutions
+                .iter()
+                .map(|rec| {
+                    (
+                        features[rec.feature_index as usize].feature_tag,
+                        rec.alternate_feature.lookup_list_indices.clone(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        // lookup 0 is from the font, 1 and 2 are the new rvrn and ss01 'heavy'
+        // lookups, and 3 is ss01 'light'. There are two rvrn features: the
+        // font's, and the one combined with the new rvrn.
+        let heavy = substitutions(0);
+        assert!(
+            heavy.contains(&(Tag::new(b"rvrn"), vec![0, 1])),
+            "{heavy:?}"
+        );
+        assert!(heavy.contains(&(Tag::new(b"ss01"), vec![2])), "{heavy:?}");
+        assert_eq!(substitutions(1), [(Tag::new(b"ss01"), vec![3])]);
+    }
+
+    #[test]
+    fn merge_mark_attach_classes_into_font() {
+        let glyph_map: GlyphMap = [".notdef", "a", "b", "c", "acute", "cedilla"]
+            .into_iter()
+            .map(GlyphName::new)
+            .collect();
+        let compile = |fea: &'static str| {
+            Compiler::new("test.fea", &glyph_map)
+                .with_resolver(move |_: &OsStr| Ok(Arc::<str>::from(fea)))
+                .compile()
+                .unwrap()
+        };
+        let base = compile(
+            "\
+            feature liga { lookupflag MarkAttachmentType [acute]; sub a by b; } liga;\n",
+        )
+        .to_binary(&glyph_map, Opts::new())
+        .unwrap();
+        let base = FontRef::new(&base).unwrap();
+
+        let compilation = compile(
+            "\
+            feature ss01 { lookupflag MarkAttachmentType [cedilla]; sub b by c; } ss01;\n",
+        );
+        let bytes = compilation.merge_into_font(&base).unwrap();
+        let font = FontRef::new(&bytes).unwrap();
+
+        // the new class is numbered after the font's, and the lookup flag
+        // is updated to match
+        let gdef: wtables::gdef::Gdef = font.gdef().unwrap().to_owned_table();
+        let classes = gdef
+            .mark_attach_class_def
+            .as_ref()
+            .unwrap()
+            .iter()
+            .collect::<Vec<_>>();
+        assert_eq!(classes, [(GlyphId::new(4), 1), (GlyphId::new(5), 2)]);
+        let gsub: wtables::gsub::Gsub = font.gsub().unwrap().to_owned_table();
+        let flags = gsub
+            .lookup_list
+            .lookups
+            .iter()
+            .map(|lookup| {
+                let wtables::gsub::SubstitutionLookup::Single(lookup) = &**lookup else {
+                    panic!("expected a single substitution lookup");
+                };
+                lookup.lookup_flag.mark_attachment_type_mask()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(flags, [Some(1), Some(2)]);
+
+        // a glyph can't be in a class on both sides
+        let conflicting = compile(
+            "\
+            feature ss01 { lookupflag MarkAttachmentType [acute]; sub b by c; } ss01;\n",
+        );
+        assert!(matches!(
+            conflicting.merge_into_font(&base),
+            Err(MergeError::MarkAttachClassConflict(glyph)) if glyph == GlyphId::new(4)
+        ));
+    }
+
+    #[test]
+    fn apply_vmtx_to_font() {
+        let base = FontBuilder::new()
+            .add_table(&Maxp::new(3))
+            .unwrap()
+            .add_table(&wtables::vhea::Vhea {
+                number_of_long_ver_metrics: 3,
+                ..Default::default()
+            })
+            .unwrap()
+            .add_table(&wtables::vmtx::Vmtx::new(
+                vec![
+                    wtables::vmtx::LongMetric::new(1000, 10),
+                    wtables::vmtx::LongMetric::new(1000, 20),
+                    wtables::vmtx::LongMetric::new(900, 30),
+                ],
+                Vec::new(),
+            ))
+            .unwrap()
+            .build();
+        let base = FontRef::new(&base).unwrap();
+
+        let fea = "\
+            table vhea { VertTypoAscender 880; } vhea;\n\
+            table vmtx { VertAdvanceY b 1200; } vmtx;\n";
+        let glyph_map: GlyphMap = [".notdef", "a", "b"]
+            .into_iter()
+            .map(GlyphName::new)
+            .collect();
+        let compilation = Compiler::new("test.fea", &glyph_map)
+            .with_resolver(move |_: &OsStr| Ok(Arc::<str>::from(fea)))
+            .compile()
+            .unwrap();
+
+        let bytes = compilation.apply_to_font(&base).unwrap();
+        let font = FontRef::new(&bytes).unwrap();
+        let vhea = font.vhea().unwrap();
+        assert_eq!(vhea.ascender().to_i16(), 880);
+        assert_eq!(vhea.number_of_long_ver_metrics(), 3);
+        assert_eq!(vhea.advance_height_max().to_u16(), 1200);
+        let metrics = font
+            .vmtx()
+            .unwrap()
+            .v_metrics()
+            .iter()
+            .map(|metric| (metric.advance(), metric.side_bearing()))
+            .collect::<Vec<_>>();
+        assert_eq!(metrics, [(1000, 10), (1000, 20), (1200, 30)]);
+    }
+
+    #[test]
+    fn cv_characters() {
+        let fea = "\
+            feature cv01 {\n\
+                cvParameters {\n\
+                    Character 0x61;\n\
+                    Character 0x1F170;\n\
+                };\n\
+                sub a by b;\n\
+            } cv01;\n";
+        let glyph_map: GlyphMap = [".notdef", "a", "b"]
+            .into_iter()
+            .map(GlyphName::new)
+            .collect();
+        let compilation = Compiler::new("cv.fea", &glyph_map)
+            .with_resolver(move |_: &OsStr| Ok(Arc::<str>::from(fea)))
+            .compile()
+            .unwrap();
+
+        let cv01 = Tag::new(b"cv01");
+        let chars = compilation.character_variant_characters();
+        assert_eq!(chars.get(&cv01).unwrap(), &['a', '\u{1F170}']);
+
+        let mut builder = FontBuilder::default();
+        builder
+            .add_table(&Cmap::from_mappings([('a', GlyphId::new(1))]))
+            .unwrap();
+        let font_data = builder.build();
+        let font = FontRef::new(&font_data).unwrap();
+        assert_eq!(
+            compilation
+                .unmapped_character_variant_characters(&font)
+                .unwrap(),
+            [(cv01, '\u{1F170}')]
+        );
+    }
+
+    #[test]
+    fn test_font() {
+        let fea = "feature liga { sub f i by f_i; } liga;\nfeature kern { pos f i -50; } kern;";
+        let glyph_map: GlyphMap = [".notdef", "f", "i", "f_i", "uni0041"]
+            .into_iter()
+            .map(GlyphName::new)
+            .collect();
+        let compilation = Compiler::new("test.fea", &glyph_map)
+            .with_resolver(move |_: &OsStr| Ok(Arc::<str>::from(fea)))
+            .compile()
+            .unwrap();
+
+        let bytes = compilation.to_test_font(&glyph_map).unwrap();
+        let font = FontRef::new(&bytes).unwrap();
+        assert_eq!(font.maxp().unwrap().num_glyphs(), 5);
+        assert_eq!(font.head().unwrap().units_per_em(), 1000);
+        assert_eq!(font.hmtx().unwrap().h_metrics()[3].advance(), 500);
+        assert_eq!(font.loca(None).unwrap().len(), 5);
+        let cmap = font.cmap().unwrap();
+        assert_eq!(cmap.map_codepoint('f'), Some(GlyphId::new(1)));
+        assert_eq!(cmap.map_codepoint('A'), Some(GlyphId::new(4)));
+        assert_eq!(cmap.map_codepoint('x'), None);
+        assert_eq!(
+            font.post().unwrap().glyph_name(GlyphId::new(3)),
+            Some("f_i")
+        );
+        assert!(font.gsub().is_ok());
+        assert!(font.gpos().is_ok());
+    }
 }
@@ -0,0 +1,405 @@
+//! Recovering from offset overflows when serializing layout tables.
+//!
+//! When packing a table, write-fonts splits large pair positioning and
+//! mark-to-base subtables, and promotes lookups to extension lookups where
+//! needed. Other subtables can still be too large to pack: for instance a
+//! single substitution of tens of thousands of glyphs can't reach its coverage
+//! table with a 16-bit offset. If packing fails, we split these subtables in
+//! half by coverage (including those wrapped in extension subtables) and try
+//! again, similar to what fontTools does.
+
+use write_fonts::{
+    error::Error,
+    tables::{
+        gpos::{self, ExtensionPosFormat1, Gpos, PositionLookup, SinglePos},
+        gsub::{
+            self, AlternateSubstFormat1, ExtensionSubstFormat1, Gsub, LigatureSubstFormat1,
+            MultipleSubstFormat1, SingleSubst, SubstitutionLookup,
+        },
+        layout::{CoverageTable, Lookup},
+    },
+    types::GlyphId,
+    validate::Validate,
+    BuilderError, FontBuilder, FontWrite,
+};
+
+/// The maximum number of times we split subtables before giving up.
+///
+/// Each round halves every subtable that is too large, so this is plenty.
+const MAX_SPLIT_ROUNDS: usize = 8;
+
+/// Add `GSUB` to the builder, splitting subtables if it cannot be packed.
+pub(crate) fn add_gsub(builder: &mut FontBuilder, gsub: &Gsub) -> Result<(), BuilderError> {
+    add_with_splitting(builder, gsub, |gsub| {
+        let mut changed = false;
+        for lookup in gsub.lookup_list.lookups.iter_mut() {
+            changed |= match &mut **lookup {
+                SubstitutionLookup::Single(lookup) => split_lookup(lookup, split_single_subst),
+                SubstitutionLookup::Multiple(lookup) => split_lookup(lookup, split_multiple_subst),
+                SubstitutionLookup::Alternate(lookup) => {
+                    split_lookup(lookup, split_alternate_subst)
+                }
+                SubstitutionLookup::Ligature(lookup) => split_lookup(lookup, split_ligature_subst),
+                SubstitutionLookup::Extension(lookup) => split_lookup(lookup, split_gsub_extension),
+                _ => false,
+            };
+        }
+        changed
+    })
+}
+
+/// Add `GPOS` to the builder, splitting subtables if it cannot be packed.
+pub(crate) fn add_gpos(builder: &mut FontBuilder, gpos: &Gpos) -> Result<(), BuilderError> {
+    add_with_splitting(builder, gpos, |gpos| {
+        let mut changed = false;
+        for lookup in gpos.lookup_list.lookups.iter_mut() {
+            changed |= match &mut **lookup {
+                PositionLookup::Single(lookup) => split_lookup(lookup, split_single_pos),
+                PositionLookup::Extension(lookup) => split_lookup(lookup, split_gpos_extension),
+                _ => false,
+            };
+        }
+        changed
+    })
+}
+
+/// Add a table, calling `split` and retrying as long as packing fails.
+///
+/// `split` returns `false` if there was nothing left to split, in which case
+/// the original error is returned.
+fn add_with_splitting<T>(
+    builder: &mut FontBuilder,
+    table: &T,
+    split: impl Fn(&mut T) -> bool,
+) -> Result<(), BuilderError>
+where
+    T: Clone + FontWrite + Validate + write_fonts::read::TopLevelTable,
+{
+    let err = match builder.add_table(table) {
+        Ok(_) => return Ok(()),
+        Err(err) if is_packing_error(&err.inner) => err,
+        Err(err) => return Err(err),
+    };
+    let mut table = table.clone();
+    for _ in 0..MAX_SPLIT_ROUNDS {
+        if !split(&mut table) {
+            break;
+        }
+        match builder.add_table(&table) {
+            Ok(_) => {
+                log::info!("split subtables in '{}' to avoid overflow", err.tag);
+                return Ok(());
+            }
+            Err(err) if is_packing_error(&err.inner) => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Err(err)
+}
+
+fn is_packing_error(err: &Error) -> bool {
+    matches!(err, Error::PackingFailed(_))
+}
+
+/// Split each subtable of the lookup that is too large to be packed.
+///
+/// Returns `true` if any subtable was split.
+fn split_lookup<T>(lookup: &mut Lookup<T>, split: impl Fn(&T) -> Option<(T, T)>) -> bool
+where
+    T: FontWrite + Validate,
+{
+    let mut changed = false;
+    let mut subtables = Vec::with_capacity(lookup.subtables.len());
+    for subtable in lookup.subtables.drain(..) {
+        match too_large(&*subtable).then(|| split(&subtable)).flatten() {
+            Some((first, second)) => {
+                subtables.push(first.into());
+                subtables.push(second.into());
+                changed = true;
+            }
+            None => subtables.push(subtable),
+        }
+    }
+    lookup.subtables = subtables;
+    changed
+}
+
+/// `true` if a subtable can't be packed on its own, or is larger than the
+/// range of a 16-bit offset.
+fn too_large<T: FontWrite + Validate>(subtable: &T) -> bool {
+    match write_fonts::dump_table(subtable) {
+        Ok(data) => data.len() > u16::MAX as usize,
+        Err(err) => is_packing_error(&err),
+    }
+}
+
+/// A coverage table, and the items that correspond to its glyphs.
+type CoveredItems<T> = (CoverageTable, Vec<T>);
+
+/// Split the glyphs of a coverage table, and the items that correspond to them,
+/// in half.
+fn split_coverage<T: Clone>(
+    coverage: &CoverageTable,
+    items: &[T],
+) -> Option<(CoveredItems<T>, CoveredItems<T>)> {
+    let glyphs = coverage.iter().collect::<Vec<_>>();
+    if glyphs.len() < 2 || glyphs.len() != items.len() {
+        return None;
+    }
+    let mid = glyphs.len() / 2;
+    let half = |glyphs: &[GlyphId], items: &[T]| (glyphs.iter().copied().collect(), items.to_vec());
+    Some((
+        half(&glyphs[..mid], &items[..mid]),
+        half(&glyphs[mid..], &items[mid..]),
+    ))
+}
+
+fn split_single_subst(subtable: &SingleSubst) -> Option<(SingleSubst, SingleSubst)> {
+    match subtable {
+        SingleSubst::Format1(table) => {
+            let glyphs = table.coverage.iter().collect::<Vec<_>>();
+            let ((first, _), (second, _)) = split_coverage(&table.coverage, &glyphs)?;
+            Some((
+                SingleSubst::format_1(first, table.delta_glyph_id),
+                SingleSubst::format_1(second, table.delta_glyph_id),
+            ))
+        }
+        SingleSubst::Format2(table) => {
+            let ((cov1, subs1), (cov2, subs2)) =
+                split_coverage(&table.coverage, &table.substitute_glyph_ids)?;
+            Some((
+                SingleSubst::format_2(cov1, subs1),
+                SingleSubst::format_2(cov2, subs2),
+            ))
+        }
+    }
+}
+
+fn split_multiple_subst(
+    subtable: &MultipleSubstFormat1,
+) -> Option<(MultipleSubstFormat1, MultipleSubstFormat1)> {
+    let sequences = subtable
+        .sequences
+        .iter()
+        .map(|seq| (**seq).clone())
+        .collect::<Vec<_>>();
+    let ((cov1, seqs1), (cov2, seqs2)) = split_coverage(&subtable.coverage, &sequences)?;
+    Some((
+        MultipleSubstFormat1::new(cov1, seqs1),
+        MultipleSubstFormat1::new(cov2, seqs2),
+    ))
+}
+
+fn split_alternate_subst(
+    subtable: &AlternateSubstFormat1,
+) -> Option<(AlternateSubstFormat1, AlternateSubstFormat1)> {
+    let sets = subtable
+        .alternate_sets
+        .iter()
+        .map(|set| (**set).clone())
+        .collect::<Vec<_>>();
+    let ((cov1, sets1), (cov2, sets2)) = split_coverage(&subtable.coverage, &sets)?;
+    Some((
+        AlternateSubstFormat1::new(cov1, sets1),
+        AlternateSubstFormat1::new(cov2, sets2),
+    ))
+}
+
+fn split_ligature_subst(
+    subtable: &LigatureSubstFormat1,
+) -> Option<(LigatureSubstFormat1, LigatureSubstFormat1)> {
+    let sets = subtable
+        .ligature_sets
+        .iter()
+        .map(|set| (**set).clone())
+        .collect::<Vec<_>>();
+    let ((cov1, sets1), (cov2, sets2)) = split_coverage(&subtable.coverage, &sets)?;
+    Some((
+        LigatureSubstFormat1::new(cov1, sets1),
+        LigatureSubstFormat1::new(cov2, sets2),
+    ))
+}
+
+fn split_single_pos(subtable: &SinglePos) -> Option<(SinglePos, SinglePos)> {
+    match subtable {
+        // a single value record; only the coverage table can be large
+        SinglePos::Format1(_) => None,
+        SinglePos::Format2(table) => {
+            let ((cov1, values1), (cov2, values2)) =
+                split_coverage(&table.coverage, &table.value_records)?;
+            Some((
+                SinglePos::format_2(cov1, values1),
+                SinglePos::format_2(cov2, values2),
+            ))
+        }
+    }
+}
+
+/// Split the subtable wrapped by an extension subtable, if it is too large.
+///
+/// The extension subtable itself can't overflow (its offset is 32 bits), but
+/// the subtable it wraps can.
+fn split_gsub_extension(
+    subtable: &gsub::ExtensionSubtable,
+) -> Option<(gsub::ExtensionSubtable, gsub::ExtensionSubtable)> {
+    use gsub::ExtensionSubtable as Ext;
+    fn split<T: FontWrite + Validate>(
+        ext: &ExtensionSubstFormat1<T>,
+        split: impl Fn(&T) -> Option<(T, T)>,
+        wrap: impl Fn(ExtensionSubstFormat1<T>) -> Ext,
+    ) -> Option<(Ext, Ext)> {
+        if !too_large(&*ext.extension) {
+            return None;
+        }
+        let (first, second) = split(&ext.extension)?;
+        let lookup_type = ext.extension_lookup_type;
+        Some((
+            wrap(ExtensionSubstFormat1::new(lookup_type, first)),
+            wrap(ExtensionSubstFormat1::new(lookup_type, second)),
+        ))
+    }
+
+    match subtable {
+        Ext::Single(ext) => split(ext, split_single_subst, Ext::Single),
+        Ext::Multiple(ext) => split(ext, split_multiple_subst, Ext::Multiple),
+        Ext::Alternate(ext) => split(ext, split_alternate_subst, Ext::Alternate),
+        Ext::Ligature(ext) => split(ext, split_ligature_subst, Ext::Ligature),
+        _ => None,
+    }
+}
+
+/// Split the subtable wrapped by an extension subtable, if it is too large.
+fn split_gpos_extension(
+    subtable: &gpos::ExtensionSubtable,
+) -> Option<(gpos::ExtensionSubtable, gpos::ExtensionSubtable)> {
+    let gpos::ExtensionSubtable::Single(ext) = subtable else {
+        return None;
+    };
+    if !too_large(&*ext.extension) {
+        return None;
+    }
+    let (first, second) = split_single_pos(&ext.extension)?;
+    let lookup_type = ext.extension_lookup_type;
+    Some((
+        gpos::ExtensionSubtable::Single(ExtensionPosFormat1::new(lookup_type, first)),
+        gpos::ExtensionSubtable::Single(ExtensionPosFormat1::new(lookup_type, second)),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use write_fonts::{
+        read::{tables::gsub as read_gsub, FontRef, TableProvider},
+        tables::{
+            gpos::ValueRecord,
+            layout::{FeatureList, LookupFlag, LookupList, ScriptList},
+        },
+    };
+
+    use super::*;
+
+    // enough glyphs that the substitute array is too large for the coverage
+    // table to be reachable from the subtable.
+    const N_GLYPHS: u16 = 40_000;
+
+    fn big_single_subst() -> SingleSubst {
+        let glyphs = (1..N_GLYPHS).map(GlyphId::new);
+        // not a constant delta, so we use format 2
+        let substitutes = (1..N_GLYPHS).map(|gid| GlyphId::new(N_GLYPHS - gid));
+        SingleSubst::format_2(glyphs.collect(), substitutes.collect())
+    }
+
+    #[test]
+    fn split_large_single_subst() {
+        let subtable = big_single_subst();
+        assert!(too_large(&subtable));
+        let gsub = Gsub::new(
+            ScriptList::default(),
+            FeatureList::default(),
+            LookupList::new(vec![SubstitutionLookup::Single(Lookup::new(
+                LookupFlag::empty(),
+                vec![subtable],
+                0,
+            ))]),
+        );
+        // this can't be packed as is
+        assert!(FontBuilder::new().add_table(&gsub).is_err());
+
+        let mut builder = FontBuilder::new();
+        add_gsub(&mut builder, &gsub).unwrap();
+        let data = builder.build();
+        let font = FontRef::new(&data).unwrap();
+        let gsub = font.gsub().unwrap();
+        let lookup = gsub.lookup_list().unwrap().lookups().get(0).unwrap();
+        // the packer may have promoted the lookup to an extension lookup
+        let subtables = match lookup {
+            read_gsub::SubstitutionLookup::Single(lookup) => lookup.subtables().len(),
+            read_gsub::SubstitutionLookup::Extension(lookup) => lookup.subtables().len(),
+            _ => panic!("unexpected lookup type"),
+        };
+        assert_eq!(subtables, 2);
+    }
+
+    #[test]
+    fn split_large_extension_subtable() {
+        let subtable =
+            gsub::ExtensionSubtable::Single(ExtensionSubstFormat1::new(1, big_single_subst()));
+        let gsub = Gsub::new(
+            ScriptList::default(),
+            FeatureList::default(),
+            LookupList::new(vec![SubstitutionLookup::Extension(Lookup::new(
+                LookupFlag::empty(),
+                vec![subtable],
+                0,
+            ))]),
+        );
+        assert!(FontBuilder::new().add_table(&gsub).is_err());
+
+        let mut builder = FontBuilder::new();
+        add_gsub(&mut builder, &gsub).unwrap();
+        let data = builder.build();
+        let font = FontRef::new(&data).unwrap();
+        let gsub = font.gsub().unwrap();
+        let lookup = gsub.lookup_list().unwrap().lookups().get(0).unwrap();
+        let read_gsub::SubstitutionLookup::Extension(lookup) = lookup else {
+            panic!("expected an extension lookup");
+        };
+        assert_eq!(lookup.subtables().len(), 2);
+    }
+
+    #[test]
+    fn split_preserves_mappings() {
+        let SingleSubst::Format2(original) = big_single_subst() else {
+            unreachable!()
+        };
+        let (first, second) = split_single_subst(&SingleSubst::Format2(original.clone())).unwrap();
+        let (SingleSubst::Format2(first), SingleSubst::Format2(second)) = (first, second) else {
+            panic!("expected format 2");
+        };
+        assert!(!too_large(&first) && !too_large(&second));
+        let mappings = |table: &write_fonts::tables::gsub::SingleSubstFormat2| {
+            table
+                .coverage
+                .iter()
+                .zip(table.substitute_glyph_ids.iter().copied())
+                .collect::<Vec<_>>()
+        };
+        let mut split = mappings(&first);
+        split.extend(mappings(&second));
+        assert_eq!(split, mappings(&original));
+    }
+
+    #[test]
+    fn small_subtables_are_not_split() {
+        let subtable = SinglePos::format_2(
+            [GlyphId::new(1), GlyphId::new(2)].into_iter().collect(),
+            vec![
+                ValueRecord::new().with_x_advance(10),
+                ValueRecord::new().with_x_advance(20),
+            ],
+        );
+        let mut lookup = Lookup::new(LookupFlag::empty(), vec![subtable], 0);
+        assert!(!split_lookup(&mut lookup, split_single_pos));
+        assert_eq!(lookup.subtables.len(), 1);
+    }
+}
@@ -18,19 +18,20 @@ use write_fonts::{
     types::{Fixed, LongDateTime},
 };
 
-use crate::common::GlyphId;
-
 mod base;
 mod gdef;
 mod name;
 mod os2;
 mod stat;
+mod vmtx;
 
 pub(crate) use base::{BaseBuilder, ScriptRecord};
 pub(crate) use gdef::{ClassId, GdefBuilder};
-pub(crate) use name::{NameBuilder, NameSpec};
-pub(crate) use os2::{CodePageRange, Os2Builder};
+pub(crate) use name::{merge_name_tables, AnonNameShift, NameBuilder, NameSpec};
+pub(crate) use os2::{CodePageRange, Os2Builder, Os2Field};
 pub(crate) use stat::{AxisLocation, AxisRecord, AxisValue, StatBuilder, StatFallbackName};
+pub(crate) use vmtx::update_vhea;
+pub use vmtx::VerticalMetrics;
 
 /// The explicit tables allowed in a fea file
 #[derive(Clone, Debug, Default)]
@@ -38,12 +39,33 @@ pub(crate) struct Tables {
     pub head: Option<HeadBuilder>,
     pub hhea: Option<tables::hhea::Hhea>,
     pub vhea: Option<tables::vhea::Vhea>,
-    pub vmtx: Option<VmtxBuilder>,
+    pub vmtx: Option<VerticalMetrics>,
     pub name: NameBuilder,
     pub gdef: Option<GdefBuilder>,
     pub base: Option<BaseBuilder>,
     pub os2: Option<Os2Builder>,
     pub stat: Option<StatBuilder>,
+    pub explicit_fields: ExplicitFields,
+}
+
+/// The fields of the `OS/2`, `hhea` and `vhea` tables that are set in the FEA.
+///
+/// When a compilation is applied to an existing font, only these fields of
+/// the font's tables are replaced.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ExplicitFields {
+    pub os2: Vec<Os2Field>,
+    pub hhea: Vec<MetricsField>,
+    pub vhea: Vec<MetricsField>,
+}
+
+/// A field of the `hhea` or `vhea` table that can be set in FEA.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum MetricsField {
+    CaretOffset,
+    Ascender,
+    Descender,
+    LineGap,
 }
 
 #[derive(Clone, Debug, Default)]
@@ -51,10 +73,37 @@ pub(crate) struct HeadBuilder {
     pub font_revision: Fixed,
 }
 
-#[derive(Clone, Debug, Default)]
-pub(crate) struct VmtxBuilder {
-    pub origins_y: Vec<(GlyphId, i16)>,
-    pub advances_y: Vec<(GlyphId, i16)>,
+impl ExplicitFields {
+    /// Copy the explicit fields of the generated `OS/2` table into `existing`.
+    pub(crate) fn apply_os2(&self, new: &tables::os2::Os2, existing: &mut tables::os2::Os2) {
+        for field in &self.os2 {
+            field.copy(new, existing);
+        }
+    }
+
+    /// Copy the explicit fields of the generated `hhea` table into `existing`.
+    pub(crate) fn apply_hhea(&self, new: &tables::hhea::Hhea, existing: &mut tables::hhea::Hhea) {
+        for field in &self.hhea {
+            match field {
+                MetricsField::CaretOffset => existing.caret_offset = new.caret_offset,
+                MetricsField::Ascender => existing.ascender = new.ascender,
+                MetricsField::Descender => existing.descender = new.descender,
+                MetricsField::LineGap => existing.line_gap = new.line_gap,
+            }
+        }
+    }
+
+    /// Copy the explicit fields of the generated `vhea` table into `existing`.
+    pub(crate) fn apply_vhea(&self, new: &tables::vhea::Vhea, existing: &mut tables::vhea::Vhea) {
+        for field in &self.vhea {
+            match field {
+                MetricsField::CaretOffset => existing.caret_offset = new.caret_offset,
+                MetricsField::Ascender => existing.ascender = new.ascender,
+                MetricsField::Descender => existing.descender = new.descender,
+                MetricsField::LineGap => existing.line_gap = new.line_gap,
+            }
+        }
+    }
 }
 
 impl Tables {
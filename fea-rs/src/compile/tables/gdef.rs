@@ -137,6 +137,39 @@ impl GdefBuilder {
         Ok(())
     }
 
+    /// Return the index of this set in the MarkGlyphSets table, adding it if needed.
+    pub(crate) fn add_mark_glyph_set(&mut self, glyphs: &GlyphClass) -> u16 {
+        let glyphs = glyphs.sort_and_dedupe();
+        let idx = match self.mark_glyph_sets.iter().position(|set| *set == glyphs) {
+            Some(idx) => idx,
+            None => {
+                self.mark_glyph_sets.push(glyphs);
+                self.mark_glyph_sets.len() - 1
+            }
+        };
+        idx.try_into().unwrap()
+    }
+
+    /// Iterate over glyphs in mark glyph sets that have a non-mark glyph class.
+    ///
+    /// Mark filtering only applies to glyphs classified as marks, so these
+    /// glyphs are never skipped by lookups using the set.
+    pub(crate) fn non_mark_glyphs_in_mark_glyph_sets(
+        &self,
+    ) -> impl Iterator<Item = (u16, GlyphId, ClassId)> + '_ {
+        self.mark_glyph_sets
+            .iter()
+            .enumerate()
+            .flat_map(move |(idx, set)| {
+                set.iter().filter_map(move |glyph| {
+                    self.glyph_classes
+                        .get(&glyph)
+                        .filter(|class| **class != ClassId::Mark)
+                        .map(|class| (idx as u16, glyph, *class))
+                })
+            })
+    }
+
     pub(crate) fn is_empty(&self) -> bool {
         self.glyph_classes.is_empty()
             && self.attach.is_empty()
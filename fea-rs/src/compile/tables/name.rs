@@ -2,18 +2,42 @@
 //!
 //! The name table stores strings that are referenced by various other tables.
 
+use std::collections::{HashMap, HashSet};
+
 use smol_str::SmolStr;
-use write_fonts::{read::tables::name::Encoding, types::NameId};
+use write_fonts::{
+    from_obj::ToOwnedTable,
+    read::{tables::name::Encoding, FontRef, TableProvider},
+    tables::{
+        layout::{FeatureList, FeatureParams, FeatureVariations},
+        name::{Name, NameRecord},
+        stat::{AxisValue, Stat},
+    },
+    types::NameId,
+};
 
 use crate::compile::tags::{MAC_PLATFORM_ID, WIN_PLATFORM_ID};
 
+/// Collects the records of the name table.
+///
+/// Records come from the `name` table block, as well as from the constructs
+/// that refer to names by ID: the `size` feature's menu names, the
+/// `featureNames` and `cvParameters` blocks, and the STAT table. These all
+/// share this builder, which allocates ids above 255 (and above any explicit
+/// id) for names that are not given one. If [`Opts::dedupe_names`] is set,
+/// the id of an identical group of names is reused.
+///
+/// [`Opts::dedupe_names`]: crate::compile::Opts::dedupe_names
 #[derive(Clone, Debug)]
 pub(crate) struct NameBuilder {
     records: Vec<(NameId, NameSpec)>,
     last_anon_id: u16,
+    first_anon_id: Option<NameId>,
+    dedupe: bool,
+    anon_groups: HashMap<Vec<NameSpec>, NameId>,
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
 pub(crate) struct NameSpec {
     pub platform_id: u16,
     pub encoding_id: u16,
@@ -26,6 +50,9 @@ impl Default for NameBuilder {
         NameBuilder {
             records: Vec::new(),
             last_anon_id: 255,
+            first_anon_id: None,
+            dedupe: false,
+            anon_groups: HashMap::new(),
         }
     }
 }
@@ -36,11 +63,24 @@ impl NameBuilder {
         self.records.push((name_id, name_spec));
     }
 
+    /// If `true`, identical groups of names share a single id.
+    pub(crate) fn set_dedupe(&mut self, flag: bool) {
+        self.dedupe = flag;
+    }
+
+    /// Add a group of names that are referenced by a single id, returning that id.
     pub(crate) fn add_anon_group(&mut self, entries: &[NameSpec]) -> NameId {
+        if let Some(id) = self.anon_groups.get(entries).filter(|_| self.dedupe) {
+            return *id;
+        }
         let name_id = self.next_name_id();
+        self.first_anon_id.get_or_insert(name_id);
         for name in entries {
             self.add(name_id, name.clone());
         }
+        if self.dedupe {
+            self.anon_groups.insert(entries.to_owned(), name_id);
+        }
         name_id
     }
 
@@ -52,9 +92,17 @@ impl NameBuilder {
         NameId::new(self.last_anon_id + 1)
     }
 
-    pub(crate) fn build(&self) -> Option<write_fonts::tables::name::Name> {
+    /// The first id allocated by [`add_anon_group`](Self::add_anon_group).
+    ///
+    /// Every id from here on is anonymous, since explicit ids are all added
+    /// before any anonymous ones are allocated.
+    pub(crate) fn first_anon_id(&self) -> Option<NameId> {
+        self.first_anon_id
+    }
+
+    pub(crate) fn build(&self) -> Option<Name> {
         (!self.records.is_empty()).then(|| {
-            write_fonts::tables::name::Name::new(
+            Name::new(
                 self.records
                     .iter()
                     .filter(|(_, spec)| spec.is_implemented_in_fontations())
@@ -65,14 +113,145 @@ impl NameBuilder {
     }
 }
 
+/// Merge a generated name table into the existing name table of a font.
+///
+/// Existing records are kept, unless a generated record has the same platform,
+/// encoding, language, and name id, in which case it replaces them.
+pub(crate) fn merge_name_tables(generated: &Name, font: &FontRef) -> Name {
+    let Ok(existing) = font.name() else {
+        return generated.clone();
+    };
+    let key = |rec: &NameRecord| {
+        (
+            rec.platform_id,
+            rec.encoding_id,
+            rec.language_id,
+            rec.name_id,
+        )
+    };
+    let replaced = generated
+        .name_record
+        .iter()
+        .map(key)
+        .collect::<HashSet<_>>();
+    let mut merged: Name = existing.to_owned_table();
+    merged
+        .name_record
+        .retain(|rec| !replaced.contains(&key(rec)));
+    merged
+        .name_record
+        .extend(generated.name_record.iter().cloned());
+    merged
+}
+
+/// Moves the anonymous name ids of generated tables above the ids in a font.
+///
+/// Anonymous ids are allocated from 256, which is also where a font's own
+/// names (such as those of `fvar` and `STAT`) usually start, so when the
+/// generated tables are added to a font, their anonymous names would
+/// otherwise replace the font's.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct AnonNameShift {
+    first: NameId,
+    offset: u16,
+}
+
+impl AnonNameShift {
+    /// Returns `None` if the anonymous ids are already above the font's ids.
+    pub(crate) fn new(first_anon_id: Option<NameId>, font: &FontRef) -> Option<Self> {
+        let first = first_anon_id?;
+        let font_max = font
+            .name()
+            .ok()?
+            .name_record()
+            .iter()
+            .map(|rec| rec.name_id())
+            .max()?;
+        (font_max >= first).then(|| AnonNameShift {
+            first,
+            offset: font_max.to_u16() - first.to_u16() + 1,
+        })
+    }
+
+    fn shift(&self, id: &mut NameId) {
+        if *id >= self.first {
+            *id = NameId::new(id.to_u16().saturating_add(self.offset));
+        }
+    }
+
+    pub(crate) fn shift_name(&self, name: &mut Name) {
+        name.name_record = std::mem::take(&mut name.name_record)
+            .into_iter()
+            .map(|mut record| {
+                self.shift(&mut record.name_id);
+                record
+            })
+            .collect();
+    }
+
+    pub(crate) fn shift_stat(&self, stat: &mut Stat) {
+        for axis in stat.design_axes.iter_mut() {
+            self.shift(&mut axis.axis_name_id);
+        }
+        for value in stat.offset_to_axis_values.iter_mut() {
+            match &mut **value {
+                AxisValue::Format1(value) => self.shift(&mut value.value_name_id),
+                AxisValue::Format2(value) => self.shift(&mut value.value_name_id),
+                AxisValue::Format3(value) => self.shift(&mut value.value_name_id),
+                AxisValue::Format4(value) => self.shift(&mut value.value_name_id),
+            }
+        }
+        if let Some(id) = stat.elided_fallback_name_id.as_mut() {
+            self.shift(id);
+        }
+    }
+
+    /// Shift the name ids in the feature params of a `GSUB` or `GPOS` table.
+    pub(crate) fn shift_features(
+        &self,
+        features: &mut FeatureList,
+        variations: Option<&mut FeatureVariations>,
+    ) {
+        let alternates = variations
+            .into_iter()
+            .flat_map(|variations| variations.feature_variation_records.iter_mut())
+            .filter_map(|record| record.feature_table_substitution.as_mut())
+            .flat_map(|subs| subs.substitutions.iter_mut())
+            .map(|sub| &mut *sub.alternate_feature);
+        let features = features
+            .feature_records
+            .iter_mut()
+            .map(|record| &mut *record.feature)
+            .chain(alternates);
+        for feature in features {
+            match feature.feature_params.as_mut() {
+                Some(FeatureParams::Size(params)) if params.name_entry != 0 => {
+                    let mut id = NameId::new(params.name_entry);
+                    self.shift(&mut id);
+                    params.name_entry = id.to_u16();
+                }
+                Some(FeatureParams::StylisticSet(params)) => self.shift(&mut params.ui_name_id),
+                Some(FeatureParams::CharacterVariant(params)) => {
+                    // unset ids are 0, which is never shifted
+                    self.shift(&mut params.feat_ui_label_name_id);
+                    self.shift(&mut params.feat_ui_tooltip_text_name_id);
+                    self.shift(&mut params.sample_text_name_id);
+                    self.shift(&mut params.first_param_ui_label_name_id);
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
 impl NameSpec {
     fn is_implemented_in_fontations(&self) -> bool {
         Encoding::new(self.platform_id, self.encoding_id) != Encoding::Unknown
     }
 
-    pub fn build(&self, name_id: NameId) -> write_fonts::tables::name::NameRecord {
+    pub fn build(&self, name_id: NameId) -> NameRecord {
         let string = parse_string(self.platform_id, self.string.trim_matches('"'));
-        write_fonts::tables::name::NameRecord::new(
+        NameRecord::new(
             self.platform_id,
             self.encoding_id,
             self.language_id,
@@ -178,6 +357,30 @@ mod tests {
         assert_eq!(mac_roman_to_char(0x8e), 'é');
     }
 
+    #[test]
+    fn identical_anon_groups_share_an_id() {
+        let spec = |string: &str| NameSpec {
+            platform_id: WIN_PLATFORM_ID,
+            encoding_id: 1,
+            language_id: 0x409,
+            string: string.into(),
+        };
+        let mut builder = NameBuilder::default();
+        builder.add(NameId::new(300), spec("explicit"));
+        let mut plain = builder.clone();
+        let a = plain.add_anon_group(&[spec("Alt a")]);
+        assert_ne!(a, plain.add_anon_group(&[spec("Alt a")]));
+
+        builder.set_dedupe(true);
+        let first = builder.add_anon_group(&[spec("Alt a")]);
+        let second = builder.add_anon_group(&[spec("Alt b")]);
+        let again = builder.add_anon_group(&[spec("Alt a")]);
+        assert_eq!(first, NameId::new(301));
+        assert_eq!(second, NameId::new(302));
+        assert_eq!(again, first);
+        assert_eq!(builder.build().unwrap().name_record.len(), 3);
+    }
+
     #[test]
     fn parse_mac_str() {
         let inp = "M\\9fller";
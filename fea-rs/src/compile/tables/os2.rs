@@ -1,6 +1,6 @@
 //! The OS/2 table
 
-use write_fonts::types::Tag;
+use write_fonts::{tables::os2::Os2, types::Tag};
 
 /// [ulUnicodeRangeN](https://learn.microsoft.com/en-us/typography/opentype/spec/os2#ulunicoderange1-bits-031ulunicoderange2-bits-3263ulunicoderange3-bits-6495ulunicoderange4-bits-96127)
 #[derive(Clone, Debug, Default)]
@@ -10,6 +10,28 @@ pub(crate) struct UnicodeRange([u32; 4]);
 #[derive(Clone, Debug, Default)]
 pub(crate) struct CodePageRange([u32; 2]);
 
+/// A field (or group of fields) of the OS/2 table that can be set in FEA.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Os2Field {
+    WeightClass,
+    WidthClass,
+    LowerOpSize,
+    UpperOpSize,
+    FsType,
+    TypoAscender,
+    TypoDescender,
+    TypoLineGap,
+    XHeight,
+    CapHeight,
+    WinAscent,
+    WinDescent,
+    Panose,
+    UnicodeRange,
+    CodePageRange,
+    Vendor,
+    FamilyClass,
+}
+
 #[derive(Clone, Debug, Default)]
 pub(crate) struct Os2Builder {
     pub us_weight_class: u16,
@@ -97,6 +119,65 @@ impl Os2Builder {
     }
 }
 
+impl Os2Field {
+    /// Copy this field from the generated table into an existing one.
+    ///
+    /// If the field is not in the existing table's version, the table is
+    /// upgraded, and the other fields added by that version are zeroed.
+    pub(crate) fn copy(self, from: &Os2, to: &mut Os2) {
+        match self {
+            Os2Field::WeightClass => to.us_weight_class = from.us_weight_class,
+            Os2Field::WidthClass => to.us_width_class = from.us_width_class,
+            Os2Field::LowerOpSize => {
+                to.us_lower_optical_point_size = from.us_lower_optical_point_size
+            }
+            Os2Field::UpperOpSize => {
+                to.us_upper_optical_point_size = from.us_upper_optical_point_size
+            }
+            Os2Field::FsType => to.fs_type = from.fs_type,
+            Os2Field::TypoAscender => to.s_typo_ascender = from.s_typo_ascender,
+            Os2Field::TypoDescender => to.s_typo_descender = from.s_typo_descender,
+            Os2Field::TypoLineGap => to.s_typo_line_gap = from.s_typo_line_gap,
+            Os2Field::XHeight => to.sx_height = from.sx_height,
+            Os2Field::CapHeight => to.s_cap_height = from.s_cap_height,
+            Os2Field::WinAscent => to.us_win_ascent = from.us_win_ascent,
+            Os2Field::WinDescent => to.us_win_descent = from.us_win_descent,
+            Os2Field::Panose => to.panose_10 = from.panose_10,
+            Os2Field::UnicodeRange => {
+                to.ul_unicode_range_1 = from.ul_unicode_range_1;
+                to.ul_unicode_range_2 = from.ul_unicode_range_2;
+                to.ul_unicode_range_3 = from.ul_unicode_range_3;
+                to.ul_unicode_range_4 = from.ul_unicode_range_4;
+            }
+            Os2Field::CodePageRange => {
+                to.ul_code_page_range_1 = from.ul_code_page_range_1;
+                to.ul_code_page_range_2 = from.ul_code_page_range_2;
+            }
+            Os2Field::Vendor => to.ach_vend_id = from.ach_vend_id,
+            Os2Field::FamilyClass => to.s_family_class = from.s_family_class,
+        }
+        // keep the fields of each version consistent
+        let is_v5 =
+            to.us_lower_optical_point_size.is_some() || to.us_upper_optical_point_size.is_some();
+        if is_v5 {
+            to.us_lower_optical_point_size.get_or_insert(0);
+            to.us_upper_optical_point_size.get_or_insert(0);
+        }
+        let is_v2 = is_v5 || to.sx_height.is_some() || to.s_cap_height.is_some();
+        if is_v2 {
+            to.sx_height.get_or_insert(0);
+            to.s_cap_height.get_or_insert(0);
+            to.us_default_char.get_or_insert(0);
+            to.us_break_char.get_or_insert(0);
+            to.us_max_context.get_or_insert(0);
+        }
+        if is_v2 || to.ul_code_page_range_1.is_some() || to.ul_code_page_range_2.is_some() {
+            to.ul_code_page_range_1.get_or_insert(0);
+            to.ul_code_page_range_2.get_or_insert(0);
+        }
+    }
+}
+
 static CODEPAGE_TO_BIT: &[(u16, u8)] = &[
     (437, 63),
     (708, 61),
@@ -57,6 +57,11 @@ pub(crate) enum StatFallbackName {
 }
 
 impl StatBuilder {
+    /// Build the STAT table, allocating any required names.
+    ///
+    /// Names are added to `name_builder` in a fixed order: the elided fallback
+    /// name, then each design axis in source order, each followed by the
+    /// values for that axis (in source order), and finally any format 4 values.
     pub(crate) fn build(&self, name_builder: &mut NameBuilder) -> write_stat::Stat {
         let elided_fallback_name_id = match &self.name {
             StatFallbackName::Id(id) if name_builder.contains_id(*id) => *id,
@@ -105,10 +110,9 @@ impl StatBuilder {
                 let flags = write_stat::AxisValueTableFlags::from_bits(axis_value.flags).unwrap();
                 let name_id = name_builder.add_anon_group(&axis_value.name);
                 let value = match &axis_value.location {
-                    AxisLocation::One { value, .. } => write_stat::AxisValue::format_1(
-                        //TODO: validate that all referenced tags refer to existing axes
-                        i as u16, flags, name_id, *value,
-                    ),
+                    AxisLocation::One { value, .. } => {
+                        write_stat::AxisValue::format_1(i as u16, flags, name_id, *value)
+                    }
                     AxisLocation::Two {
                         nominal, min, max, ..
                     } => write_stat::AxisValue::format_2(
@@ -0,0 +1,316 @@
+//! The vmtx table
+
+use std::collections::BTreeMap;
+
+use write_fonts::{
+    read::{
+        tables::{
+            glyf::{Glyf, Glyph},
+            loca::Loca,
+            vmtx::Vmtx as ReadVmtx,
+        },
+        FontData, FontRef, ReadError, TableProvider, TopLevelTable,
+    },
+    tables::{
+        vhea::Vhea,
+        vmtx::{LongMetric, Vmtx},
+    },
+    types::GlyphId,
+};
+
+use crate::compile::{error::MergeError, tags};
+
+/// Vertical metrics set in a `vmtx` table block.
+///
+/// The FEA syntax only overrides the metrics of individual glyphs, so these
+/// can only be applied to an existing font with a `vmtx` table; see
+/// [`Compilation::apply_to_font`].
+///
+/// [`Compilation::apply_to_font`]: crate::compile::Compilation::apply_to_font
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VerticalMetrics {
+    /// Advance heights set with `VertAdvanceY`
+    pub advances: BTreeMap<GlyphId, u16>,
+    /// Vertical origins set with `VertOriginY`
+    pub origins: BTreeMap<GlyphId, i16>,
+}
+
+/// The tables built from [`VerticalMetrics`] and an existing font.
+pub(crate) struct VerticalTables {
+    pub(crate) vmtx: Vmtx,
+    /// The raw `VORG` table, if the font has CFF outlines and a `VORG` table
+    pub(crate) vorg: Option<Vec<u8>>,
+}
+
+impl VerticalMetrics {
+    /// Build the `vmtx` table (and `VORG`, if needed) from the metrics in `font`.
+    ///
+    /// The font must have `maxp` and `vmtx` tables. In a font with `glyf`
+    /// outlines an explicit origin sets the top side bearing of the glyph, the
+    /// distance from the origin to the top of its bounding box; in a font with
+    /// CFF outlines it is stored in the font's `VORG` table. Origins are
+    /// ignored (with a warning) if the font has CFF outlines but no `VORG`
+    /// table, since adding one would change the origin of every other glyph.
+    pub(crate) fn build(&self, font: &FontRef) -> Result<VerticalTables, MergeError> {
+        let existing = font
+            .vmtx()
+            .map_err(|e| MergeError::ReadTable(tags::VMTX, e))?;
+        let num_glyphs = font
+            .maxp()
+            .map_err(|e| MergeError::ReadTable(tags::MAXP, e))?
+            .num_glyphs();
+        let mut metrics = (0..num_glyphs as usize)
+            .map(|gid| {
+                existing_metric(&existing, gid)
+                    .ok_or(MergeError::ReadTable(tags::VMTX, ReadError::OutOfBounds))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        for (gid, advance) in &self.advances {
+            if let Some(metric) = metrics.get_mut(gid.to_u16() as usize) {
+                metric.advance = *advance;
+            }
+        }
+
+        let mut vorg = None;
+        match Outlines::new(font)? {
+            Outlines::Glyf(loca, glyf) => {
+                for (gid, origin) in &self.origins {
+                    let Some(metric) = metrics.get_mut(gid.to_u16() as usize) else {
+                        continue;
+                    };
+                    let y_max = match loca.get_glyf(*gid, &glyf) {
+                        Ok(Some(Glyph::Simple(glyph))) => glyph.y_max(),
+                        Ok(Some(Glyph::Composite(glyph))) => glyph.y_max(),
+                        Ok(None) => 0,
+                        Err(e) => return Err(MergeError::ReadTable(Glyf::TAG, e)),
+                    };
+                    metric.side_bearing = origin.saturating_sub(y_max);
+                }
+            }
+            Outlines::Cff(Some(mut existing)) => {
+                existing.origins.extend(self.origins.iter());
+                vorg = Some(existing.to_bytes());
+            }
+            Outlines::Cff(None) if !self.origins.is_empty() => {
+                log::warn!("ignoring VertOriginY: font has CFF outlines but no VORG table")
+            }
+            Outlines::Cff(None) => (),
+            Outlines::Unknown if !self.origins.is_empty() => {
+                log::warn!("ignoring VertOriginY: font has no glyf or CFF outlines")
+            }
+            Outlines::Unknown => (),
+        }
+
+        // glyphs at the end with the same advance only need a side bearing
+        let mut n_long = metrics.len();
+        while n_long > 1 && metrics[n_long - 2].advance == metrics[n_long - 1].advance {
+            n_long -= 1;
+        }
+        let top_side_bearings = metrics
+            .split_off(n_long)
+            .into_iter()
+            .map(|metric| metric.side_bearing)
+            .collect();
+        Ok(VerticalTables {
+            vmtx: Vmtx::new(metrics, top_side_bearings),
+            vorg,
+        })
+    }
+}
+
+/// Update the fields of `vhea` that are derived from the `vmtx` table.
+pub(crate) fn update_vhea(vhea: &mut Vhea, vmtx: &Vmtx) {
+    vhea.number_of_long_ver_metrics = vmtx.v_metrics.len() as u16;
+    vhea.advance_height_max = vmtx
+        .v_metrics
+        .iter()
+        .map(|metric| metric.advance)
+        .max()
+        .unwrap_or_default()
+        .into();
+}
+
+fn existing_metric(vmtx: &ReadVmtx, idx: usize) -> Option<LongMetric> {
+    let long_metrics = vmtx.v_metrics();
+    if let Some(metric) = long_metrics.get(idx) {
+        return Some(LongMetric::new(metric.advance(), metric.side_bearing()));
+    }
+    let advance = long_metrics.last()?.advance();
+    let side_bearing = vmtx.top_side_bearings().get(idx - long_metrics.len())?;
+    Some(LongMetric::new(advance, side_bearing.get()))
+}
+
+/// The outlines of a font, which determine where vertical origins are stored.
+enum Outlines<'a> {
+    /// Origins are stored as top side bearings, relative to the bounding box
+    Glyf(Loca<'a>, Glyf<'a>),
+    /// Origins are stored in the `VORG` table, if the font has one
+    Cff(Option<Vorg>),
+    /// A font without outlines
+    Unknown,
+}
+
+impl<'a> Outlines<'a> {
+    fn new(font: &FontRef<'a>) -> Result<Self, MergeError> {
+        if let (Ok(loca), Ok(glyf)) = (font.loca(None), font.glyf()) {
+            return Ok(Outlines::Glyf(loca, glyf));
+        }
+        if font.table_data(tags::CFF).is_none() && font.table_data(tags::CFF2).is_none() {
+            return Ok(Outlines::Unknown);
+        }
+        font.table_data(tags::VORG)
+            .map(|data| Vorg::read(data).map_err(|e| MergeError::ReadTable(tags::VORG, e)))
+            .transpose()
+            .map(Outlines::Cff)
+    }
+}
+
+/// The vertical origin table.
+///
+/// This is not (yet) supported by write-fonts, so we read and write it here.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Vorg {
+    default: i16,
+    origins: BTreeMap<GlyphId, i16>,
+}
+
+impl Vorg {
+    fn read(data: FontData) -> Result<Self, ReadError> {
+        let major_version = data.read_at::<u16>(0)?;
+        if major_version != 1 {
+            return Err(ReadError::InvalidFormat(major_version.into()));
+        }
+        let default = data.read_at::<i16>(4)?;
+        let count = data.read_at::<u16>(6)? as usize;
+        let origins = (0..count)
+            .map(|i| {
+                let offset = 8 + i * 4;
+                let gid = data.read_at::<u16>(offset)?;
+                let origin = data.read_at::<i16>(offset + 2)?;
+                Ok((GlyphId::new(gid), origin))
+            })
+            .collect::<Result<_, ReadError>>()?;
+        Ok(Vorg { default, origins })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let origins = self
+            .origins
+            .iter()
+            .filter(|(_, origin)| **origin != self.default)
+            .collect::<Vec<_>>();
+        let mut bytes = Vec::with_capacity(8 + origins.len() * 4);
+        bytes.extend(1u16.to_be_bytes());
+        bytes.extend(0u16.to_be_bytes());
+        bytes.extend(self.default.to_be_bytes());
+        bytes.extend((origins.len() as u16).to_be_bytes());
+        for (gid, origin) in origins {
+            bytes.extend(gid.to_u16().to_be_bytes());
+            bytes.extend(origin.to_be_bytes());
+        }
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use write_fonts::{tables::maxp::Maxp, FontBuilder};
+
+    use super::*;
+
+    fn metrics(advances: &[(u16, u16)], origins: &[(u16, i16)]) -> VerticalMetrics {
+        VerticalMetrics {
+            advances: advances
+                .iter()
+                .map(|(gid, adv)| (GlyphId::new(*gid), *adv))
+                .collect(),
+            origins: origins
+                .iter()
+                .map(|(gid, origin)| (GlyphId::new(*gid), *origin))
+                .collect(),
+        }
+    }
+
+    /// A font with four glyphs, each with an advance of 1000, and no outlines.
+    fn font_builder() -> FontBuilder<'static> {
+        let mut builder = FontBuilder::new();
+        builder.add_table(&Maxp::new(4)).unwrap();
+        builder
+            .add_table(&Vhea {
+                number_of_long_ver_metrics: 1,
+                ..Default::default()
+            })
+            .unwrap();
+        builder
+            .add_table(&Vmtx::new(vec![LongMetric::new(1000, 0)], vec![10, 20, 30]))
+            .unwrap();
+        builder
+    }
+
+    #[test]
+    fn trailing_advances_are_compacted() {
+        let font = font_builder().build();
+        let font = FontRef::new(&font).unwrap();
+        let tables = metrics(&[(1, 500)], &[]).build(&font).unwrap();
+        let advances = tables
+            .vmtx
+            .v_metrics
+            .iter()
+            .map(|metric| metric.advance)
+            .collect::<Vec<_>>();
+        assert_eq!(advances, [1000, 500, 1000]);
+        assert_eq!(tables.vmtx.top_side_bearings, [30]);
+        assert!(tables.vorg.is_none());
+    }
+
+    #[test]
+    fn update_vhea_fields() {
+        let font = font_builder().build();
+        let font = FontRef::new(&font).unwrap();
+        let vmtx = metrics(&[(1, 1200), (3, 500)], &[])
+            .build(&font)
+            .unwrap()
+            .vmtx;
+        let mut vhea = Vhea::default();
+        update_vhea(&mut vhea, &vmtx);
+        assert_eq!(vhea.number_of_long_ver_metrics, 4);
+        assert_eq!(vhea.advance_height_max.to_u16(), 1200);
+    }
+
+    #[test]
+    fn font_without_vmtx() {
+        let mut builder = FontBuilder::new();
+        builder.add_table(&Maxp::new(4)).unwrap();
+        let font = builder.build();
+        let font = FontRef::new(&font).unwrap();
+        assert!(matches!(
+            metrics(&[(1, 500)], &[]).build(&font),
+            Err(MergeError::ReadTable(tag, _)) if tag == tags::VMTX
+        ));
+    }
+
+    #[test]
+    fn cff_origins_update_vorg() {
+        let vorg = Vorg {
+            default: 880,
+            origins: [(GlyphId::new(2), 700)].into_iter().collect(),
+        };
+        let mut builder = font_builder();
+        builder.add_raw(tags::CFF, vec![0; 4]);
+        builder.add_raw(tags::VORG, vorg.to_bytes());
+        let font = builder.build();
+        let font = FontRef::new(&font).unwrap();
+
+        let tables = metrics(&[], &[(1, 900), (3, 880)]).build(&font).unwrap();
+        // side bearings are unchanged
+        assert_eq!(tables.vmtx.top_side_bearings, [10, 20, 30]);
+        let vorg = Vorg::read(FontData::new(&tables.vorg.unwrap())).unwrap();
+        assert_eq!(vorg.default, 880);
+        let origins = vorg
+            .origins
+            .iter()
+            .map(|(gid, origin)| (gid.to_u16(), *origin))
+            .collect::<Vec<_>>();
+        assert_eq!(origins, [(1, 900), (2, 700)]);
+    }
+}
@@ -8,8 +8,15 @@ pub const AALT: Tag = Tag::new(b"aalt");
 pub const SIZE: Tag = Tag::new(b"size");
 pub const LANG_DFLT: Tag = Tag::new(b"dflt");
 pub const SCRIPT_DFLT: Tag = Tag::new(b"DFLT");
+pub const GDEF: Tag = Tag::new(b"GDEF");
 pub const GSUB: Tag = Tag::new(b"GSUB");
 pub const GPOS: Tag = Tag::new(b"GPOS");
+pub const DEBG: Tag = Tag::new(b"Debg");
+pub const MAXP: Tag = Tag::new(b"maxp");
+pub const VMTX: Tag = Tag::new(b"vmtx");
+pub const VORG: Tag = Tag::new(b"VORG");
+pub const CFF: Tag = Tag::new(b"CFF ");
+pub const CFF2: Tag = Tag::new(b"CFF2");
 
 pub const WIN_PLATFORM_ID: u16 = 3;
 pub const MAC_PLATFORM_ID: u16 = 1;
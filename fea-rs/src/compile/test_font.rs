@@ -0,0 +1,224 @@
+//! Building minimal fonts for shaping tests.
+//!
+//! These fonts contain the compiled layout tables, along with just enough
+//! other tables (empty glyphs, fixed metrics, and a cmap derived from the
+//! glyph names) for a shaping engine such as HarfBuzz to load them.
+
+use write_fonts::{
+    tables::{
+        cmap::Cmap,
+        glyf::Glyf,
+        head::Head,
+        hhea::Hhea,
+        hmtx::{Hmtx, LongMetric},
+        loca::Loca,
+        maxp::Maxp,
+    },
+    BuilderError, FontBuilder,
+};
+
+use super::Compilation;
+use crate::{GlyphIdent, GlyphMap};
+
+const UNITS_PER_EM: u16 = 1000;
+const ASCENDER: i16 = 800;
+const DESCENDER: i16 = -200;
+/// The advance of every glyph in the font.
+const ADVANCE: u16 = 500;
+
+/// The glyph names of the printable ASCII characters, from the Adobe Glyph
+/// List, excluding letters (which are named for themselves.)
+static ASCII_GLYPH_NAMES: &[(&str, char)] = &[
+    ("space", ' '),
+    ("exclam", '!'),
+    ("quotedbl", '"'),
+    ("numbersign", '#'),
+    ("dollar", '$'),
+    ("percent", '%'),
+    ("ampersand", '&'),
+    ("quotesingle", '\''),
+    ("parenleft", '('),
+    ("parenright", ')'),
+    ("asterisk", '*'),
+    ("plus", '+'),
+    ("comma", ','),
+    ("hyphen", '-'),
+    ("period", '.'),
+    ("slash", '/'),
+    ("zero", '0'),
+    ("one", '1'),
+    ("two", '2'),
+    ("three", '3'),
+    ("four", '4'),
+    ("five", '5'),
+    ("six", '6'),
+    ("seven", '7'),
+    ("eight", '8'),
+    ("nine", '9'),
+    ("colon", ':'),
+    ("semicolon", ';'),
+    ("less", '<'),
+    ("equal", '='),
+    ("greater", '>'),
+    ("question", '?'),
+    ("at", '@'),
+    ("bracketleft", '['),
+    ("backslash", '\\'),
+    ("bracketright", ']'),
+    ("asciicircum", '^'),
+    ("underscore", '_'),
+    ("grave", '`'),
+    ("braceleft", '{'),
+    ("bar", '|'),
+    ("braceright", '}'),
+    ("asciitilde", '~'),
+];
+
+pub(crate) fn build(
+    compilation: &Compilation,
+    glyph_map: &GlyphMap,
+) -> Result<Vec<u8>, BuilderError> {
+    let num_glyphs: u16 = glyph_map.len().try_into().unwrap();
+    let mut builder = FontBuilder::new();
+
+    // every glyph is empty, so all the offsets are zero
+    let glyf = Glyf::default();
+    let loca = Loca::new(vec![0; num_glyphs as usize + 1]);
+    let loca_format = loca.format();
+
+    let head = Head {
+        units_per_em: UNITS_PER_EM,
+        index_to_loc_format: loca_format as i16,
+        ..compilation.head.clone().unwrap_or_default()
+    };
+    let hhea = Hhea {
+        advance_width_max: ADVANCE.into(),
+        number_of_long_metrics: num_glyphs,
+        ..compilation.hhea.clone().unwrap_or_else(|| Hhea {
+            ascender: ASCENDER.into(),
+            descender: DESCENDER.into(),
+            ..Default::default()
+        })
+    };
+    let hmtx = Hmtx::new(
+        (0..num_glyphs)
+            .map(|_| LongMetric::new(ADVANCE, 0))
+            .collect(),
+        Vec::new(),
+    );
+    // version 1.0, as required for fonts with TrueType outlines
+    let maxp = Maxp {
+        num_glyphs,
+        max_points: Some(0),
+        max_contours: Some(0),
+        max_composite_points: Some(0),
+        max_composite_contours: Some(0),
+        max_zones: Some(1),
+        max_twilight_points: Some(0),
+        max_storage: Some(0),
+        max_function_defs: Some(0),
+        max_instruction_defs: Some(0),
+        max_stack_elements: Some(0),
+        max_size_of_instructions: Some(0),
+        max_component_elements: Some(0),
+        max_component_depth: Some(0),
+    };
+
+    compilation.apply(&mut builder)?;
+    builder
+        .add_table(&head)?
+        .add_table(&hhea)?
+        .add_table(&hmtx)?
+        .add_table(&maxp)?
+        .add_table(&glyf)?
+        .add_table(&loca)?
+        .add_table(&make_cmap(glyph_map))?
+        .add_table(&glyph_map.make_post_table())?;
+    Ok(builder.build())
+}
+
+/// Map characters to glyphs, based on their names.
+///
+/// If multiple glyphs map to the same character, the first is used.
+fn make_cmap(glyph_map: &GlyphMap) -> Cmap {
+    let mut mappings = std::collections::BTreeMap::new();
+    for (gid, ident) in glyph_map.reverse_map() {
+        if let Some(c) = char_for_glyph(&ident) {
+            mappings.entry(c).or_insert(gid);
+        }
+    }
+    Cmap::from_mappings(mappings)
+}
+
+/// Guess the character for a glyph, based on its name.
+///
+/// We understand the 'uniXXXX' and 'uXXXX[XX]' conventions, single letters,
+/// and the names of the ASCII punctuation and digits; other glyphs (including
+/// any with a suffix, like 'a.sc') are unmapped.
+fn char_for_glyph(ident: &GlyphIdent) -> Option<char> {
+    let GlyphIdent::Name(name) = ident else {
+        return None;
+    };
+    let hex = |s: &str| u32::from_str_radix(s, 16).ok().and_then(char::from_u32);
+    let is_hex = |s: &str| s.bytes().all(|b| b.is_ascii_hexdigit());
+    if let Some(code) = name.strip_prefix("uni") {
+        if code.len() == 4 && is_hex(code) {
+            return hex(code);
+        }
+    }
+    if let Some(code) = name.strip_prefix('u') {
+        if (4..=6).contains(&code.len()) && is_hex(code) {
+            return hex(code);
+        }
+    }
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return c.is_ascii_alphabetic().then_some(c);
+    }
+    ASCII_GLYPH_NAMES
+        .iter()
+        .find_map(|(glyph_name, c)| (*glyph_name == name.as_str()).then_some(*c))
+}
+
+#[cfg(test)]
+mod tests {
+    use write_fonts::{
+        read::{FontRef, TableProvider},
+        types::GlyphId,
+    };
+
+    use super::*;
+
+    fn char_for(name: &str) -> Option<char> {
+        char_for_glyph(&GlyphIdent::from(name))
+    }
+
+    #[test]
+    fn chars_from_names() {
+        assert_eq!(char_for("a"), Some('a'));
+        assert_eq!(char_for("Z"), Some('Z'));
+        assert_eq!(char_for("period"), Some('.'));
+        assert_eq!(char_for("uni0041"), Some('A'));
+        assert_eq!(char_for("u1F600"), Some('\u{1F600}'));
+        assert_eq!(char_for("a.sc"), None);
+        assert_eq!(char_for("uni00410042"), None);
+        assert_eq!(char_for("f_i"), None);
+        assert_eq!(char_for(".notdef"), None);
+        assert_eq!(char_for_glyph(&GlyphIdent::Cid(65)), None);
+    }
+
+    #[test]
+    fn first_glyph_wins() {
+        let glyph_map: GlyphMap = [".notdef", "uni0061", "a"]
+            .into_iter()
+            .map(crate::GlyphName::new)
+            .collect();
+        let cmap = make_cmap(&glyph_map);
+        let font_data = FontBuilder::new().add_table(&cmap).unwrap().build();
+        let font = FontRef::new(&font_data).unwrap();
+        assert_eq!(
+            font.cmap().unwrap().map_codepoint('a'),
+            Some(GlyphId::new(1))
+        );
+    }
+}
@@ -5,7 +5,7 @@
 //! and that other constraints of the spec are upheld.
 
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
     ops::Range,
 };
 
@@ -14,75 +14,162 @@ use write_fonts::{read::tables::name::Encoding, types::Tag};
 
 use super::{
     glyph_range,
+    lenient::ErrorRanges,
     tags::{self, WIN_PLATFORM_ID},
     VariationInfo,
 };
 use crate::{
-    parse::SourceMap,
+    common::GlyphId,
+    parse::{SourceList, SourceMap},
     token_tree::{
         typed::{self, AstNode},
         Token,
     },
     typed::ContextualRuleNode,
-    Diagnostic, GlyphMap, Kind, NodeOrToken,
+    Diagnostic, DiagnosticCode, GlyphMap, Kind, NodeOrToken,
 };
 
 pub struct ValidationCtx<'a> {
     pub errors: Vec<Diagnostic>,
     glyph_map: &'a GlyphMap,
     source_map: &'a SourceMap,
+    sources: &'a SourceList,
     variation_info: Option<&'a dyn VariationInfo>,
-    default_lang_systems: HashSet<(SmolStr, SmolStr)>,
+    default_lang_systems: HashMap<(SmolStr, SmolStr), Range<usize>>,
     seen_non_default_script: bool,
     lookup_defs: HashMap<SmolStr, Token>,
     // class and position
-    glyph_class_defs: HashMap<SmolStr, Token>,
+    glyph_class_defs: HashMap<SmolStr, typed::GlyphClassDef>,
+    // the glyphs in each glyph class, resolved when the class is defined so
+    // that a redefinition sees the previous value of any class it references
+    glyph_class_glyphs: HashMap<SmolStr, Vec<GlyphId>>,
     mark_class_defs: HashSet<SmolStr>,
     mark_class_used: Option<Token>,
+    // if mark classes may be used before they are defined, all of the mark
+    // classes defined in the source
+    allow_late_mark_classes: bool,
+    late_mark_classes: HashSet<SmolStr>,
+    // if '- @class' and '& @class' are allowed in glyph classes
+    allow_class_arithmetic: bool,
+    // the glyphs in each mark class, for checking that the mark classes used
+    // in a lookup are disjoint
+    mark_class_glyphs: HashMap<SmolStr, Vec<GlyphId>>,
+    lookup_marks: LookupMarks,
     anchor_defs: HashMap<SmolStr, Token>,
     value_record_defs: HashMap<SmolStr, Token>,
     condition_set_defs: HashMap<SmolStr, Token>,
     aalt_referenced_features: HashMap<Tag, typed::Tag>,
     all_features: HashSet<Tag>,
+    // the first feature block, for checking that languagesystems precede it
+    first_feature: Option<typed::Tag>,
+    // lenient mode only: statements to skip, and the ranges of new errors
+    skip: ErrorRanges,
+    pub(crate) error_ranges: Vec<Range<usize>>,
 }
 
 impl<'a> ValidationCtx<'a> {
     pub(crate) fn new(
         source_map: &'a SourceMap,
+        sources: &'a SourceList,
         glyph_map: &'a GlyphMap,
         variation_info: Option<&'a dyn VariationInfo>,
     ) -> Self {
         ValidationCtx {
             glyph_map,
             source_map,
+            sources,
             errors: Vec::new(),
             variation_info,
             default_lang_systems: Default::default(),
             seen_non_default_script: false,
             glyph_class_defs: Default::default(),
+            glyph_class_glyphs: Default::default(),
             lookup_defs: Default::default(),
             mark_class_defs: Default::default(),
             mark_class_used: None,
+            allow_late_mark_classes: false,
+            late_mark_classes: Default::default(),
+            allow_class_arithmetic: false,
+            mark_class_glyphs: Default::default(),
+            lookup_marks: Default::default(),
             anchor_defs: Default::default(),
             value_record_defs: Default::default(),
             condition_set_defs: Default::default(),
             aalt_referenced_features: Default::default(),
             all_features: Default::default(),
+            first_feature: None,
+            skip: Default::default(),
+            error_ranges: Default::default(),
         }
     }
 
+    /// Skip any statements that overlap these ranges (for lenient mode.)
+    pub(crate) fn with_skipped(mut self, skip: ErrorRanges) -> Self {
+        self.skip = skip;
+        self
+    }
+
+    /// See [`Compiler::with_late_mark_classes`].
+    ///
+    /// [`Compiler::with_late_mark_classes`]: super::Compiler::with_late_mark_classes
+    pub(crate) fn with_late_mark_classes(mut self, flag: bool) -> Self {
+        self.allow_late_mark_classes = flag;
+        self
+    }
+
+    /// See [`Compiler::with_class_arithmetic`].
+    ///
+    /// [`Compiler::with_class_arithmetic`]: super::Compiler::with_class_arithmetic
+    pub(crate) fn with_class_arithmetic(mut self, flag: bool) -> Self {
+        self.allow_class_arithmetic = flag;
+        self
+    }
+
     fn error(&mut self, range: Range<usize>, message: impl Into<String>) {
+        self.error_ranges.push(range.clone());
+        let (file, range) = self.source_map.resolve_range(range);
+        self.errors.push(
+            Diagnostic::error(file, range, message).with_code(DiagnosticCode::ValidationError),
+        );
+    }
+
+    fn warning(&mut self, code: DiagnosticCode, range: Range<usize>, message: impl Into<String>) {
         let (file, range) = self.source_map.resolve_range(range);
-        self.errors.push(Diagnostic::error(file, range, message));
+        self.errors
+            .push(Diagnostic::warning(file, range, message).with_code(code));
     }
 
-    fn warning(&mut self, range: Range<usize>, message: impl Into<String>) {
+    /// Add a warning with a suggested edit.
+    fn warning_with_edit(
+        &mut self,
+        code: DiagnosticCode,
+        range: Range<usize>,
+        message: impl Into<String>,
+        edit_range: Range<usize>,
+        replacement: impl Into<String>,
+    ) {
         let (file, range) = self.source_map.resolve_range(range);
-        self.errors.push(Diagnostic::warning(file, range, message));
+        let (_, edit_range) = self.source_map.resolve_range(edit_range);
+        let diagnostic = Diagnostic::warning(file, range, message)
+            .with_code(code)
+            .with_edit(edit_range, replacement);
+        self.errors.push(diagnostic);
     }
 
     pub(crate) fn validate_root(&mut self, node: &typed::Root) {
+        if self.allow_late_mark_classes {
+            self.late_mark_classes = node
+                .definitions()
+                .into_iter()
+                .filter(|item| !self.skip.should_skip(item))
+                .filter_map(typed::MarkClassDef::cast)
+                .map(|def| def.mark_class_name().text().clone())
+                .collect();
+        }
         for item in node.statements() {
+            if self.skip.should_skip(item) {
+                continue;
+            }
             if let Some(language_system) = typed::LanguageSystem::cast(item) {
                 self.validate_language_system(&language_system)
             } else if let Some(class_def) = typed::GlyphClassDef::cast(item) {
@@ -103,8 +190,6 @@ impl<'a> ValidationCtx<'a> {
                 self.validate_condition_set(&node);
             } else if let Some(node) = typed::FeatureVariation::cast(item) {
                 self.validate_feature_variation(&node);
-            } else if item.kind() == Kind::AnonKw {
-                unimplemented!("anon")
             }
         }
         self.finalize();
@@ -131,6 +216,22 @@ impl<'a> ValidationCtx<'a> {
     fn validate_language_system(&mut self, node: &typed::LanguageSystem) {
         let script = node.script();
         let lang = node.language();
+        let key = (script.text().clone(), lang.text().clone());
+
+        // check this first, since the same statement is often repeated in
+        // multiple included files, and this would otherwise be an ordering error.
+        if let Some(prev) = self.default_lang_systems.get(&key).cloned() {
+            let message = format!(
+                "Duplicate languagesystem definition (first defined at {})",
+                self.sources.describe_location(self.source_map, prev)
+            );
+            self.warning(
+                DiagnosticCode::DuplicateLanguageSystem,
+                node.range(),
+                message,
+            );
+            return;
+        }
 
         if script.text() == "DFLT" && lang.text() == "dflt" && !self.default_lang_systems.is_empty()
         {
@@ -152,31 +253,57 @@ impl<'a> ValidationCtx<'a> {
             self.seen_non_default_script = true;
         }
 
-        if !self
-            .default_lang_systems
-            .insert((script.text().clone(), lang.text().clone()))
-        {
-            self.warning(node.range(), "Duplicate languagesystem definition");
+        // languagesystems only apply to the features that follow them
+        if let Some(feature) = self.first_feature.clone() {
+            let message = format!(
+                "languagesystem statements should precede all feature blocks; feature '{}' (at {}) and any others before this statement will not be registered for '{} {}'",
+                feature.text(),
+                self.sources.describe_location(self.source_map, feature.range()),
+                script.text(),
+                lang.text(),
+            );
+            self.warning(DiagnosticCode::LateLanguageSystem, node.range(), message);
         }
+
+        self.default_lang_systems.insert(key, node.range());
     }
 
     fn validate_glyph_class_def(&mut self, node: &typed::GlyphClassDef) {
         let name = node.class_name();
-        if let Some(_prev) = self
+        if let Some(prev) = self
             .glyph_class_defs
-            .insert(name.text().to_owned(), name.token().clone())
+            .insert(name.text().to_owned(), node.clone())
         {
-            self.warning(name.range(), "duplicate glyph class definition");
+            // if the definitions are identical, the second one can just be removed
+            if class_def_contents(&prev).eq(class_def_contents(node)) {
+                self.warning_with_edit(
+                    DiagnosticCode::DuplicateGlyphClass,
+                    name.range(),
+                    "duplicate glyph class definition (identical to previous definition)",
+                    node.range(),
+                    "",
+                );
+            } else {
+                self.warning(
+                    DiagnosticCode::DuplicateGlyphClass,
+                    name.range(),
+                    "duplicate glyph class definition",
+                );
+            }
             //TODO: use previous span to show previous declaration
-            //TODO: have help message
         }
+        let mut glyphs = Vec::new();
         if let Some(literal) = node.class_def() {
             self.validate_glyph_class_literal(&literal, false);
+            self.resolve_class_literal(&literal, &mut glyphs);
         } else if let Some(alias) = node.class_alias() {
             self.validate_glyph_class_ref(&alias, false);
+            self.resolve_named_class(&alias, &mut glyphs);
         } else {
             self.error(node.range(), "unknown parser bug?");
         }
+        self.glyph_class_glyphs
+            .insert(name.text().to_owned(), glyphs);
     }
 
     fn validate_anchor_def(&mut self, node: &typed::AnchorDef) {
@@ -184,7 +311,11 @@ impl<'a> ValidationCtx<'a> {
             .anchor_defs
             .insert(node.name().text.clone(), node.name().clone())
         {
-            self.warning(node.name().range(), "duplicate anchor name");
+            self.warning(
+                DiagnosticCode::DuplicateAnchor,
+                node.name().range(),
+                "duplicate anchor name",
+            );
         }
     }
 
@@ -195,15 +326,15 @@ impl<'a> ValidationCtx<'a> {
                 "all markClass definitions must precede any use of a mark class in the file",
             );
             //TODO: annotate error with site of use
-            //TODO: figure out this:
-            //
-            // "Note: The mark classes used within a single lookup must be
-            // disjoint: none may include a glyph which is in another mark class
-            // that is used within the same lookup."
         }
         self.validate_glyph_or_class(&node.glyph_class());
-        self.mark_class_defs
-            .insert(node.mark_class_name().text().clone());
+        let glyphs = self.resolve_glyphs(&node.glyph_class());
+        let name = node.mark_class_name().text().clone();
+        self.mark_class_glyphs
+            .entry(name.clone())
+            .or_default()
+            .extend(glyphs);
+        self.mark_class_defs.insert(name);
         self.validate_anchor(&node.anchor());
     }
 
@@ -215,7 +346,11 @@ impl<'a> ValidationCtx<'a> {
             .value_record_defs
             .insert(name.text.clone(), name.clone())
         {
-            self.warning(name.range(), "duplicate value record name");
+            self.warning(
+                DiagnosticCode::DuplicateValueRecord,
+                name.range(),
+                "duplicate value record name",
+            );
         }
     }
 
@@ -228,7 +363,11 @@ impl<'a> ValidationCtx<'a> {
             );
         }
         if let Some(_prev) = self.condition_set_defs.insert(label.text.clone(), label) {
-            self.warning(node.label().range(), "duplicate condition set definition");
+            self.warning(
+                DiagnosticCode::DuplicateConditionSet,
+                node.label().range(),
+                "duplicate condition set definition",
+            );
         }
 
         for condition in node.conditions() {
@@ -260,6 +399,7 @@ impl<'a> ValidationCtx<'a> {
     }
 
     fn validate_feature_variation(&mut self, node: &typed::FeatureVariation) {
+        self.lookup_marks.clear();
         let feature_tag = node.tag();
         if let Some(cond_set) = node.condition_set() {
             if !self.condition_set_defs.contains_key(cond_set.as_str()) {
@@ -272,11 +412,80 @@ impl<'a> ValidationCtx<'a> {
     }
 
     fn validate_mark_class(&mut self, node: &typed::GlyphClassName) {
-        if !self.mark_class_defs.contains(node.text()) {
+        if !self.mark_class_is_defined(node) {
             self.error(node.range(), "undefined mark class");
         }
     }
 
+    /// Returns `true` if this mark class has been defined.
+    ///
+    /// If mark classes may be used before they are defined, this also returns
+    /// `true` (with a warning) for mark classes that are defined later.
+    fn mark_class_is_defined(&mut self, node: &typed::GlyphClassName) -> bool {
+        if self.mark_class_defs.contains(node.text()) {
+            return true;
+        }
+        if self.late_mark_classes.contains(node.text()) {
+            self.warning(
+                DiagnosticCode::LateMarkClass,
+                node.range(),
+                "mark class is used before it is defined",
+            );
+            return true;
+        }
+        false
+    }
+
+    /// Check that a mark class used in a rule does not share glyphs with any
+    /// other mark class used in the same lookup.
+    ///
+    /// From the spec: "The mark classes used within a single lookup must be
+    /// disjoint: none may include a glyph which is in another mark class that
+    /// is used within the same lookup."
+    fn check_mark_class_overlap(&mut self, name: &typed::GlyphClassName, rule: Range<usize>) {
+        let Some(glyphs) = self.mark_class_glyphs.get(name.text()) else {
+            // undefined; reported elsewhere
+            return;
+        };
+        // the other classes, with the rule that used them and the shared glyphs
+        let mut conflicts: BTreeMap<SmolStr, (Range<usize>, Vec<GlyphId>)> = BTreeMap::new();
+        for gid in glyphs {
+            match self.lookup_marks.glyphs.entry(*gid) {
+                Entry::Vacant(entry) => {
+                    entry.insert((name.text().clone(), rule.clone()));
+                }
+                Entry::Occupied(entry) if entry.get().0 != *name.text() => {
+                    let (other, other_rule) = entry.get();
+                    conflicts
+                        .entry(other.clone())
+                        .or_insert_with(|| (other_rule.clone(), Vec::new()))
+                        .1
+                        .push(*gid);
+                }
+                Entry::Occupied(_) => (),
+            }
+        }
+        if conflicts.is_empty() {
+            return;
+        }
+        let names = self.glyph_map.reverse_map();
+        for (other, (other_rule, mut shared)) in conflicts {
+            shared.sort();
+            shared.dedup();
+            let shared = shared
+                .iter()
+                .filter_map(|gid| names.get(gid).map(ToString::to_string))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let message = format!(
+                "mark class '{}' shares glyphs with '{other}', which is used in the same lookup (at {}): {shared}",
+                name.text(),
+                self.sources.describe_location(self.source_map, other_rule),
+            );
+            self.error(name.range(), message);
+        }
+    }
+
     fn validate_table(&mut self, node: &typed::Table) {
         match node {
             typed::Table::Base(table) => self.validate_base(table),
@@ -328,6 +537,10 @@ impl<'a> ValidationCtx<'a> {
     fn validate_vmtx(&mut self, node: &typed::VmtxTable) {
         for statement in node.statements() {
             self.validate_glyph(&statement.glyph());
+            let value = statement.value();
+            if statement.keyword().kind == Kind::VertAdvanceYKw && value.parse_signed() < 0 {
+                self.error(value.range(), "advance must be non-negative");
+            }
         }
     }
 
@@ -375,6 +588,7 @@ impl<'a> ValidationCtx<'a> {
                         Ok(raw_val) => {
                             if let Err((cls, sub)) = validate_os2_family_class(raw_val) {
                                 self.warning(
+                                    DiagnosticCode::UnknownFamilyClass,
                                     val.range(),
                                     format!(
                                         "Class {cls}, subclass {sub} is not a known sFamilyClass"
@@ -407,7 +621,7 @@ impl<'a> ValidationCtx<'a> {
                 }
                 typed::Os2TableItem::Vendor(item) => {
                     if let Err(e) = item.parse_tag() {
-                        self.error(item.value().range(), format!("invalid tag: '{}'", e));
+                        self.error(item.value().range(), e);
                     }
                 }
             }
@@ -415,6 +629,34 @@ impl<'a> ValidationCtx<'a> {
     }
 
     fn validate_stat(&mut self, node: &typed::StatTable) {
+        // axis values can refer to axes that are defined later in the table
+        let mut design_axes = HashSet::new();
+        let mut orderings = HashSet::new();
+        for item in node.statements() {
+            let typed::StatTableItem::DesignAxis(axis) = item else {
+                continue;
+            };
+            let tag = axis.tag();
+            if !design_axes.insert(tag.to_raw()) {
+                self.error(
+                    tag.range(),
+                    format!("DesignAxis already defined for tag '{}'", tag.text()),
+                );
+            }
+            let ordering = axis.ordering();
+            match ordering.parse_unsigned() {
+                Some(value) if !orderings.insert(value) => self.error(
+                    ordering.range(),
+                    format!("DesignAxis already defined for axis ordering {value}"),
+                ),
+                Some(_) => (),
+                None => self.error(
+                    ordering.range(),
+                    "axis ordering must be an integer in the range 0..=65535",
+                ),
+            }
+        }
+
         let mut seen_fallback_name = false;
         for item in node.statements() {
             match item {
@@ -428,6 +670,16 @@ impl<'a> ValidationCtx<'a> {
                     let mut seen_location_format = None;
                     for item in axis.statements() {
                         if let typed::StatAxisValueItem::Location(loc) = item {
+                            let tag = loc.tag();
+                            if !design_axes.contains(&tag.to_raw()) {
+                                self.error(
+                                    tag.range(),
+                                    format!(
+                                        "axis '{}' is not defined by a DesignAxis statement",
+                                        tag.text()
+                                    ),
+                                );
+                            }
                             let format = match loc.value() {
                                 typed::StatLocationValue::Value(_) => 'a',
                                 typed::StatLocationValue::MinMax { .. } => 'b',
@@ -481,9 +733,11 @@ impl<'a> ValidationCtx<'a> {
         }
         if let Some((platspec, language)) = spec.platform_and_language_ids() {
             match (platspec.parse(), language.parse()) {
-                (Ok(a), Ok(_)) if Encoding::new(platform, a) == Encoding::Unknown => {
-                    self.warning(spec.range(), "character encoding unsupported")
-                }
+                (Ok(a), Ok(_)) if Encoding::new(platform, a) == Encoding::Unknown => self.warning(
+                    DiagnosticCode::UnsupportedEncoding,
+                    spec.range(),
+                    "character encoding unsupported",
+                ),
                 (a, b) => {
                     if let Err(e) = a {
                         self.error(platspec.range(), e);
@@ -528,10 +782,21 @@ impl<'a> ValidationCtx<'a> {
                 //to resolve glyphs here in order to track that.
                 typed::GdefTableItem::LigatureCaret(node) => {
                     self.validate_glyph_or_class(&node.target());
-                    if let typed::LigatureCaretValue::Pos(node) = node.values() {
-                        for idx in node.values() {
-                            if idx.parse_unsigned().is_none() {
-                                self.error(idx.range(), "contourpoint index must be non-negative");
+                    match node.values() {
+                        typed::LigatureCaretValue::Pos(_) => (),
+                        typed::LigatureCaretValue::Index(node) => {
+                            for idx in node.values() {
+                                if idx.parse_unsigned().is_none() {
+                                    self.error(
+                                        idx.range(),
+                                        "contourpoint index must be non-negative",
+                                    );
+                                }
+                            }
+                        }
+                        typed::LigatureCaretValue::Device(node) => {
+                            for (_, device) in node.with_devices() {
+                                self.validate_device(&device);
                             }
                         }
                     }
@@ -544,7 +809,11 @@ impl<'a> ValidationCtx<'a> {
         let mut prev = None;
         for statement in node.statements() {
             if let Some(prev) = prev.replace(statement.range()) {
-                self.warning(prev, "FontRevision overwritten by subsequent statement");
+                self.warning(
+                    DiagnosticCode::FontRevisionOverwritten,
+                    prev,
+                    "FontRevision overwritten by subsequent statement",
+                );
             }
             let value = statement.value();
             let (int, fract) = value.text().split_once('.').expect("checked at parse time");
@@ -554,11 +823,16 @@ impl<'a> ValidationCtx<'a> {
             }
             if fract.len() != 3 {
                 let start = value.range().start + int.len();
-                self.warning(
+                let suggested = format!("{:.3}", value.parse());
+                self.warning_with_edit(
+                    DiagnosticCode::FontRevisionPrecision,
                     start..start + fract.len(),
-                    "version number should have exactly three decimal places",
+                    format!(
+                        "version number should have exactly three decimal places ('{suggested}')"
+                    ),
+                    value.range(),
+                    suggested,
                 );
-                //TODO: richer error, showing suggested input
             }
         }
     }
@@ -568,9 +842,11 @@ impl<'a> ValidationCtx<'a> {
     // decls: 'markClass', GCLASS}
     // special: 'feature', 'parameters', 'featureNames', 'cvParameters', 'sizemenuname'
     fn validate_feature(&mut self, node: &typed::Feature) {
+        self.lookup_marks.clear();
         let tag = node.tag();
         let tag_raw = tag.to_raw();
         self.all_features.insert(tag_raw);
+        self.first_feature.get_or_insert_with(|| tag.clone());
 
         if tag_raw == tags::SIZE {
             return self.validate_size_feature(node);
@@ -599,11 +875,16 @@ impl<'a> ValidationCtx<'a> {
         iter: impl Iterator<Item = &'b NodeOrToken>,
     ) {
         for item in iter {
-            if item.kind() == Kind::ScriptNode
-                || item.kind() == Kind::LanguageNode
-                || item.kind() == Kind::SubtableNode
-                || item.kind() == Kind::Semi
-            {
+            if self.skip.should_skip(item) {
+                continue;
+            }
+            if item.kind().is_rule() {
+                self.lookup_marks.set_rule_kind(item.kind());
+            }
+            if item.kind() == Kind::ScriptNode || item.kind() == Kind::LanguageNode {
+                // a new script or language starts a new lookup
+                self.lookup_marks.clear();
+            } else if item.kind() == Kind::SubtableNode || item.kind() == Kind::Semi {
                 // lgtm
             } else if let Some(node) = typed::LookupRef::cast(item) {
                 self.validate_lookup_ref(&node);
@@ -620,7 +901,7 @@ impl<'a> ValidationCtx<'a> {
             } else if let Some(node) = typed::MarkClassDef::cast(item) {
                 self.validate_mark_class_def(&node);
             } else if let Some(_node) = typed::FeatureNames::cast(item) {
-                self.warning(item.range(), "Only one featureNames block is allowed, it must preceed all rules, and it is only valid in features ss01-ss20");
+                self.warning(DiagnosticCode::MisplacedFeatureNames, item.range(), "Only one featureNames block is allowed, it must preceed all rules, and it is only valid in features ss01-ss20");
             } else if let Some(node) = typed::FeatureRef::cast(item) {
                 self.error(
                     node.keyword().range(),
@@ -661,7 +942,11 @@ impl<'a> ValidationCtx<'a> {
                 Kind::ParamUiLabelNameIdKw,
             ] {
                 if !node.iter().any(|x| x.kind() == kind) {
-                    self.warning(node.keyword().range(), format!("missing '{kind}' node"));
+                    self.warning(
+                        DiagnosticCode::IncompleteCvParameters,
+                        node.keyword().range(),
+                        format!("missing '{kind}' node"),
+                    );
                 }
             }
 
@@ -686,7 +971,11 @@ impl<'a> ValidationCtx<'a> {
                 let range = tag.range();
                 let raw_tag = tag.to_raw();
                 if self.aalt_referenced_features.insert(raw_tag, tag).is_some() {
-                    self.warning(range, "feature already declared")
+                    self.warning(
+                        DiagnosticCode::DuplicateAaltFeature,
+                        range,
+                        "feature already declared",
+                    )
                 }
             } else if !item.kind().is_trivia() {
                 self.error(
@@ -707,8 +996,9 @@ impl<'a> ValidationCtx<'a> {
                         node.range(),
                         "size feature can have only one 'parameters' statement",
                     );
+                } else {
+                    param = Some(node);
                 }
-                param = Some(node);
             } else if let Some(node) = typed::SizeMenuName::cast(item) {
                 self.validate_name_spec(&node.spec());
                 menu_name_count += 1;
@@ -725,19 +1015,75 @@ impl<'a> ValidationCtx<'a> {
                 node.tag().range(),
                 "size feature must include a 'parameters' statement",
             ),
-            Some(param) => {
-                if param.subfamily().parse_signed() == 0
-                    && param.range_start().map(|x| x.parse() as i32).unwrap_or(0) == 0
-                    && param.range_end().map(|x| x.parse() as i32).unwrap_or(0) == 0
-                    && menu_name_count != 0
-                {
-                    //TODO: better diagnostics
-                    self.error(
-                        param.range(),
-                        "if subfamily is omitted, there must be no 'sizemenuname' statements",
-                    );
-                }
+            Some(param) => self.validate_size_parameters(&param, menu_name_count),
+        }
+    }
+
+    // https://learn.microsoft.com/en-us/typography/opentype/spec/features_pt#size
+    fn validate_size_parameters(&mut self, param: &typed::Parameters, menu_name_count: usize) {
+        let mut decipoints = |node: Option<typed::FloatLike>| {
+            let node = node?;
+            let value = node.parse_decipoints();
+            if value.is_none() {
+                self.error(
+                    node.range(),
+                    "size values must be between 0 and 65535 decipoints",
+                );
+            }
+            value
+        };
+        let design_size = decipoints(Some(param.design_size()));
+        let range_start = decipoints(param.range_start());
+        let range_end = decipoints(param.range_end());
+        if design_size == Some(0) {
+            self.error(
+                param.design_size().range(),
+                "design size must be greater than zero",
+            );
+        }
+        let subfamily = param.subfamily();
+        let Some(subfamily_id) = subfamily.parse_unsigned() else {
+            self.error(
+                subfamily.range(),
+                "subfamily identifier must be between 0 and 65535",
+            );
+            return;
+        };
+
+        if subfamily_id == 0 {
+            if range_start.unwrap_or(0) != 0 || range_end.unwrap_or(0) != 0 {
+                self.error(
+                    param.range(),
+                    "if subfamily is omitted, the range start and end must be 0",
+                );
+            } else if menu_name_count != 0 {
+                self.error(
+                    param.range(),
+                    "if subfamily is omitted, there must be no 'sizemenuname' statements",
+                );
             }
+            return;
+        }
+
+        if param.range_start().is_none() {
+            self.error(
+                param.range(),
+                "a subfamily identifier requires a range start and end",
+            );
+        } else if let (Some(size), Some(start), Some(end)) = (design_size, range_start, range_end) {
+            // the start of the range is exclusive, and the end inclusive
+            if (start, end) != (0, 0) && !(start < size && size <= end) {
+                self.error(
+                    param.range(),
+                    format!("design size ({size}) must be greater than the range start ({start}) and no greater than the range end ({end})"),
+                );
+            }
+        }
+        if menu_name_count == 0 {
+            self.error(
+                param.range(),
+                "a subfamily identifier requires at least one 'sizemenuname' statement",
+            );
         }
     }
 
@@ -758,6 +1104,7 @@ impl<'a> ValidationCtx<'a> {
         // and not anywhere else. Instead of a bool we store the decl range,
         // for error reporting
         let mut has_reset_lookup_flag = None;
+        self.lookup_marks.clear();
         if let Some(_prev) = self.lookup_defs.insert(name.text.clone(), name.clone()) {
             //TODO: annotate with previous location
             self.error(
@@ -766,26 +1113,34 @@ impl<'a> ValidationCtx<'a> {
             );
         }
         for item in node.statements() {
-            if item.kind().is_rule() {
+            if self.skip.should_skip(item) {
+                continue;
+            }
+            let is_ignore_rule = matches!(item.kind(), Kind::GsubIgnore | Kind::GposIgnore);
+            if item.kind().is_rule() || is_ignore_rule {
+                if !is_ignore_rule {
+                    self.lookup_marks.set_rule_kind(item.kind());
+                }
                 if let Some(lookup_flag) = has_reset_lookup_flag.take() {
                     self.error(
                         lookup_flag,
                         "all rules in named lookup must have same lookup flags",
                     );
                 }
+                // the first rule determines the type of the lookup
                 match kind {
-                    // we allow mixed rules in this specific case
-                    Some(Kind::GsubType1 | Kind::GsubType2)
-                        if matches!(item.kind(), Kind::GsubType1 | Kind::GsubType2) => {}
-                    Some(kind) if kind != item.kind() => self.error(
-                        item.range(),
-                        format!(
-                            "multiple rule types in lookup block (saw '{}' after '{}')",
-                            item.kind(),
-                            kind
-                        ),
-                    ),
-                    _ => kind = Some(item.kind()),
+                    Some(first) if lookup_type_for_rule(first) != lookup_type_for_rule(item.kind()) => {
+                        self.error(
+                            item.range(),
+                            format!(
+                                "multiple rule types in lookup block: {} rule in lookup of {} rules",
+                                rule_type_name(item.kind()),
+                                rule_type_name(first),
+                            ),
+                        )
+                    }
+                    Some(_) => (),
+                    None => kind = Some(item.kind()),
                 }
             }
             if item.kind() == Kind::ScriptNode || item.kind() == Kind::LanguageNode {
@@ -801,6 +1156,7 @@ impl<'a> ValidationCtx<'a> {
                 if in_feature.is_none() {
                     //TODO: verify that this is accurate
                     self.warning(
+                        DiagnosticCode::LookupReferenceOutsideFeature,
                         node.range(),
                         "lookup reference outside of feature is ignored",
                     );
@@ -833,6 +1189,7 @@ impl<'a> ValidationCtx<'a> {
                 );
             }
         }
+        self.lookup_marks.clear();
     }
 
     fn validate_gpos_statement(&mut self, node: &typed::GposStatement) {
@@ -854,15 +1211,15 @@ impl<'a> ValidationCtx<'a> {
                 self.validate_anchor(&rule.entry());
                 self.validate_anchor(&rule.exit());
             }
-            //NOTE: this should be also checking that all mark classes referenced
-            //in this rule are disjoint, but we don't know the glyph ids in validation
-            //so we validate this in compile_ctx
             typed::GposStatement::Type4(rule) => {
                 self.validate_glyph_or_class(&rule.base());
                 for mark in rule.attachments() {
                     self.validate_anchor(&mark.anchor());
                     match mark.mark_class_name() {
-                        Some(name) => self.validate_mark_class(&name),
+                        Some(name) => {
+                            self.validate_mark_class(&name);
+                            self.check_mark_class_overlap(&name, rule.range());
+                        }
                         None => {
                             self.error(mark.range(), "mark-to-base attachments should not be null")
                         }
@@ -877,7 +1234,10 @@ impl<'a> ValidationCtx<'a> {
                     for mark in component.attachments() {
                         let anchor = mark.anchor();
                         match mark.mark_class_name() {
-                            Some(name) => self.validate_mark_class(&name),
+                            Some(name) => {
+                                self.validate_mark_class(&name);
+                                self.check_mark_class_overlap(&name, rule.range());
+                            }
                             None => {
                                 if anchor.null().is_none() {
                                     self.error(
@@ -896,7 +1256,10 @@ impl<'a> ValidationCtx<'a> {
                 for mark in rule.attachments() {
                     self.validate_anchor(&mark.anchor());
                     match mark.mark_class_name() {
-                        Some(name) => self.validate_mark_class(&name),
+                        Some(name) => {
+                            self.validate_mark_class(&name);
+                            self.check_mark_class_overlap(&name, rule.range());
+                        }
                         None => {
                             self.error(mark.range(), "mark-to-mark attachments should not be null")
                         }
@@ -1013,6 +1376,18 @@ impl<'a> ValidationCtx<'a> {
                 }
 
                 let input_seq = rule.input();
+                if let Some(inline) = rule.inline_rule() {
+                    if inline.replacement_glyphs().nth(1).is_some()
+                        && input_seq.items().nth(1).is_some()
+                    {
+                        let range = range_for_iter(inline.replacement_glyphs())
+                            .unwrap_or_else(|| inline.range());
+                        self.error(
+                            range,
+                            "inline rule cannot replace a sequence with multiple glyphs",
+                        );
+                    }
+                }
                 for (i, item) in input_seq.items().enumerate() {
                     let target = item.target();
                     if i == 0 && inline_class_sub && !target.is_class() {
@@ -1049,7 +1424,10 @@ impl<'a> ValidationCtx<'a> {
                         self.validate_glyph_or_class(&target);
                         input_class = item.target().is_class();
                         if let Some(lookup) = item.lookups().next() {
-                            self.error(lookup.range(), "explicit lookups in rsub rules are not supported, although they should be. Please file an issue at https://github.com/cmyr/fea-rs/issues");
+                            self.error(
+                                lookup.range(),
+                                "lookups are not allowed in rsub rules; the replacement must be specified with 'by'",
+                            );
                         }
                     }
                 }
@@ -1063,6 +1441,15 @@ impl<'a> ValidationCtx<'a> {
                     } else if let Some(glyph) = inline.replacement_glyphs().next() {
                         self.validate_glyph(&glyph);
                     }
+                } else if rule
+                    .input()
+                    .items()
+                    .all(|item| item.lookups().next().is_none())
+                {
+                    self.error(
+                        rule.input().range(),
+                        "rsub rule must specify a replacement with 'by'",
+                    );
                 }
             }
             typed::GsubStatement::Ignore(node) => {
@@ -1169,7 +1556,7 @@ impl<'a> ValidationCtx<'a> {
         node: &typed::GlyphClassLiteral,
         accept_mark_class: bool,
     ) {
-        for item in node.items() {
+        for (i, item) in node.items().enumerate() {
             if let Some(id) = typed::GlyphName::cast(item) {
                 self.validate_glyph_name(&id);
             } else if let Some(id) = typed::Cid::cast(item) {
@@ -1178,12 +1565,28 @@ impl<'a> ValidationCtx<'a> {
                 self.validate_glyph_range(&range);
             } else if let Some(alias) = typed::GlyphClassName::cast(item) {
                 self.validate_glyph_class_ref(&alias, accept_mark_class);
+            } else if let Some(op) = typed::GlyphClassOperation::cast(item) {
+                if !self.allow_class_arithmetic {
+                    self.error(
+                        op.range(),
+                        "glyph class arithmetic is an extension, and must be enabled",
+                    );
+                } else if i == 0 {
+                    self.error(op.range(), "expected glyphs before class operator");
+                }
+                if let Some(class) = op.class() {
+                    self.validate_glyph_class_ref(&class, accept_mark_class);
+                }
                 // these two cases indicate existing errors
             } else if !item.kind().is_trivia()
                 && item.kind() != Kind::Ident
                 && item.kind() != Kind::GlyphNameOrRange
             {
-                self.warning(item.range(), format!("unexpected item {}", item.kind()));
+                self.warning(
+                    DiagnosticCode::UnexpectedClassItem,
+                    item.range(),
+                    format!("unexpected item {}", item.kind()),
+                );
             }
         }
     }
@@ -1201,7 +1604,7 @@ impl<'a> ValidationCtx<'a> {
     }
 
     fn validate_glyph_class_ref(&mut self, node: &typed::GlyphClassName, accept_mark_class: bool) {
-        if accept_mark_class && self.mark_class_defs.contains(node.text()) {
+        if accept_mark_class && self.mark_class_is_defined(node) {
             return;
         }
         if !self.glyph_class_defs.contains_key(node.text()) {
@@ -1225,6 +1628,7 @@ impl<'a> ValidationCtx<'a> {
                     if self.glyph_map.get(&cid).is_none() {
                         // this is techincally allowed, but we error for now
                         self.warning(
+                            DiagnosticCode::MissingRangeMember,
                             range.range(),
                             format!("Range member '{}' does not exist in font", cid),
                         );
@@ -1237,6 +1641,7 @@ impl<'a> ValidationCtx<'a> {
                 if let Err(err) = glyph_range::named(start, end, |name| {
                     if self.glyph_map.get(name).is_none() {
                         self.warning(
+                            DiagnosticCode::MissingRangeMember,
                             range.range(),
                             format!("Range member '{}' does not exist in font", name),
                         );
@@ -1249,6 +1654,66 @@ impl<'a> ValidationCtx<'a> {
         }
     }
 
+    /// The ids of the glyphs in a glyph or class.
+    ///
+    /// Glyphs and classes that can't be resolved are skipped; they are
+    /// reported elsewhere.
+    fn resolve_glyphs(&self, node: &typed::GlyphOrClass) -> Vec<GlyphId> {
+        let mut out = Vec::new();
+        match node {
+            typed::GlyphOrClass::Glyph(name) => out.extend(self.glyph_map.get(name.text())),
+            typed::GlyphOrClass::Cid(cid) => out.extend(self.glyph_map.get(&cid.parse())),
+            typed::GlyphOrClass::Class(class) => self.resolve_class_literal(class, &mut out),
+            typed::GlyphOrClass::NamedClass(name) => self.resolve_named_class(name, &mut out),
+            typed::GlyphOrClass::Null(_) => (),
+        }
+        out
+    }
+
+    fn resolve_class_literal(&self, node: &typed::GlyphClassLiteral, out: &mut Vec<GlyphId>) {
+        let start = out.len();
+        for item in node.items() {
+            if let Some(name) = typed::GlyphName::cast(item) {
+                out.extend(self.glyph_map.get(name.text()));
+            } else if let Some(cid) = typed::Cid::cast(item) {
+                out.extend(self.glyph_map.get(&cid.parse()));
+            } else if let Some(range) = typed::GlyphRange::cast(item) {
+                let (start, end) = (range.start(), range.end());
+                let _ = match (start.kind, end.kind) {
+                    (Kind::Cid, Kind::Cid) => glyph_range::cid(start, end, |cid| {
+                        out.extend(self.glyph_map.get(&cid));
+                    }),
+                    _ => glyph_range::named(start, end, |name| {
+                        out.extend(self.glyph_map.get(name));
+                    }),
+                };
+            } else if let Some(alias) = typed::GlyphClassName::cast(item) {
+                self.resolve_named_class(&alias, out);
+            } else if let Some(op) = typed::GlyphClassOperation::cast(item) {
+                let mut operand = Vec::new();
+                if let Some(class) = op.class() {
+                    self.resolve_named_class(&class, &mut operand);
+                }
+                let operand = operand.into_iter().collect::<HashSet<_>>();
+                let intersect = op.is_intersection();
+                let mut glyphs = out.split_off(start);
+                glyphs.retain(|gid| operand.contains(gid) == intersect);
+                out.extend(glyphs);
+            }
+        }
+    }
+
+    /// The glyphs in a named class, as of the point where it is referenced.
+    fn resolve_named_class(&self, name: &typed::GlyphClassName, out: &mut Vec<GlyphId>) {
+        if let Some(glyphs) = self
+            .glyph_class_glyphs
+            .get(name.text())
+            .or_else(|| self.mark_class_glyphs.get(name.text()))
+        {
+            out.extend(glyphs.iter().copied());
+        }
+    }
+
     fn validate_value_record(&mut self, node: &typed::ValueRecord) {
         if let Some(name) = node.named() {
             if !self.value_record_defs.contains_key(&name.text) {
@@ -1256,8 +1721,19 @@ impl<'a> ValidationCtx<'a> {
             }
         }
 
+        let mut is_variable = false;
         for metric in node.all_metrics() {
             self.validate_metric(&metric);
+            is_variable |= matches!(metric, typed::Metric::Variable(_));
+        }
+        if is_variable && node.device().is_some() {
+            self.error(
+                node.range(),
+                "variable value record cannot have device tables",
+            );
+        }
+        for device in node.device().into_iter().flatten() {
+            self.validate_device(&device);
         }
     }
 
@@ -1270,6 +1746,40 @@ impl<'a> ValidationCtx<'a> {
         if let Some((one, two)) = anchor.coords() {
             self.validate_metric(&one);
             self.validate_metric(&two);
+            let is_variable = [&one, &two]
+                .into_iter()
+                .any(|metric| matches!(metric, typed::Metric::Variable(_)));
+            if is_variable && (anchor.contourpoint().is_some() || anchor.devices().is_some()) {
+                self.error(
+                    anchor.range(),
+                    "variable anchor cannot have a contourpoint or device tables",
+                );
+            }
+        }
+        if let Some((x_device, y_device)) = anchor.devices() {
+            self.validate_device(&x_device);
+            self.validate_device(&y_device);
+        }
+    }
+
+    fn validate_device(&mut self, device: &typed::Device) {
+        // entries are sorted when the device is compiled, so only duplicates
+        // are a problem
+        let mut seen = HashSet::new();
+        for (ppem, delta) in device.entries() {
+            match ppem.parse_unsigned() {
+                None => self.error(ppem.range(), "ppem size must be a positive 16 bit integer"),
+                Some(val) if !seen.insert(val) => {
+                    self.error(ppem.range(), format!("duplicate ppem size {val}"))
+                }
+                Some(_) => (),
+            }
+            if delta.text().parse::<i8>().is_err() {
+                self.error(
+                    delta.range(),
+                    "device delta must be in the range -128..=127",
+                );
+            }
         }
     }
 
@@ -1409,6 +1919,74 @@ fn validate_os2_family_class(raw: u16) -> Result<u16, (u8, u8)> {
     }
 }
 
+/// The mark glyphs used by the rules in the current lookup.
+#[derive(Default)]
+struct LookupMarks {
+    rule_kind: Option<Kind>,
+    // each glyph, with the mark class it was used in, and the range of that rule
+    glyphs: HashMap<GlyphId, (SmolStr, Range<usize>)>,
+}
+
+impl LookupMarks {
+    fn clear(&mut self) {
+        self.rule_kind = None;
+        self.glyphs.clear();
+    }
+
+    /// Rules of a different kind are compiled into a new lookup.
+    fn set_rule_kind(&mut self, kind: Kind) {
+        if self.rule_kind.replace(kind) != Some(kind) {
+            self.glyphs.clear();
+        }
+    }
+}
+
+/// The type of lookup that a rule is compiled into.
+///
+/// Ignore rules are added to contextual lookups, and single substitution rules
+/// can be combined with multiple substitution rules in a single lookup.
+fn lookup_type_for_rule(kind: Kind) -> Kind {
+    match kind {
+        Kind::GsubType1 => Kind::GsubType2,
+        Kind::GsubIgnore => Kind::GsubType6,
+        Kind::GposIgnore => Kind::GposType8,
+        other => other,
+    }
+}
+
+fn rule_type_name(kind: Kind) -> &'static str {
+    match kind {
+        Kind::GsubType1 => "single substitution",
+        Kind::GsubType2 => "multiple substitution",
+        Kind::GsubType3 => "alternate substitution",
+        Kind::GsubType4 => "ligature substitution",
+        Kind::GsubType5 => "contextual substitution",
+        Kind::GsubType6 => "chaining contextual substitution",
+        Kind::GsubType7 => "extension substitution",
+        Kind::GsubType8 => "reverse chaining substitution",
+        Kind::GsubIgnore => "ignore substitution",
+        Kind::GposType1 => "single positioning",
+        Kind::GposType2 => "pair positioning",
+        Kind::GposType3 => "cursive attachment",
+        Kind::GposType4 => "mark-to-base attachment",
+        Kind::GposType5 => "mark-to-ligature attachment",
+        Kind::GposType6 => "mark-to-mark attachment",
+        Kind::GposType7 => "contextual positioning",
+        Kind::GposType8 => "chaining contextual positioning",
+        Kind::GposIgnore => "ignore positioning",
+        _ => "unknown",
+    }
+}
+
+// the non-trivia tokens of a class definition, after the class name
+fn class_def_contents(node: &typed::GlyphClassDef) -> impl Iterator<Item = SmolStr> + '_ {
+    node.node()
+        .iter_tokens()
+        .filter(|t| !t.kind.is_trivia())
+        .skip(1)
+        .map(|t| t.text.clone())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1421,4 +1999,203 @@ mod tests {
         assert!(validate_os2_family_class(0x0203).is_err());
         assert!(validate_os2_family_class(0x0600).is_err());
     }
+
+    fn validate_str(fea: &'static str) -> (Vec<Diagnostic>, String) {
+        let glyph_map: GlyphMap = [".notdef", "a", "b"]
+            .into_iter()
+            .map(crate::GlyphName::new)
+            .collect();
+        let (tree, errs) = crate::parse::parse_root(
+            "test.fea".into(),
+            Some(&glyph_map),
+            move |_: &std::ffi::OsStr| Ok(fea.into()),
+        )
+        .unwrap();
+        assert!(errs.is_empty(), "{errs:?}");
+        let diagnostics = super::super::validate(&tree, &glyph_map, None);
+        // apply all suggested edits, last first
+        let mut fixed = fea.to_string();
+        for diagnostic in diagnostics.iter().rev() {
+            if let Some(edit) = &diagnostic.edit {
+                fixed.replace_range(edit.span.range(), &edit.replacement);
+            }
+        }
+        (diagnostics, fixed)
+    }
+
+    #[test]
+    fn duplicate_class_def_edit() {
+        let (diagnostics, fixed) =
+            validate_str("@a = [a b];\n@a = [a  b];\n@b = [a];\n@b = [b];\n");
+        assert_eq!(diagnostics.len(), 2);
+        assert!(diagnostics[0].edit.is_some());
+        // not identical, so we don't know what to do
+        assert!(diagnostics[1].edit.is_none());
+        assert_eq!(fixed, "@a = [a b];\n\n@b = [a];\n@b = [b];\n");
+    }
+
+    #[test]
+    fn head_version_edit() {
+        let (diagnostics, fixed) = validate_str("table head { FontRevision 1.1; } head;\n");
+        assert_eq!(
+            diagnostics[0].text(),
+            "version number should have exactly three decimal places ('1.100')"
+        );
+        assert_eq!(fixed, "table head { FontRevision 1.100; } head;\n");
+    }
+
+    #[test]
+    fn os2_vendor() {
+        let first_error = |fea| {
+            let (diagnostics, _) = validate_str(fea);
+            diagnostics.first().map(|d| d.text().to_string())
+        };
+        assert_eq!(first_error(r#"table OS/2 { Vendor "AB"; } OS/2;"#), None);
+        assert_eq!(
+            first_error(r#"table OS/2 { Vendor "ÀDBE"; } OS/2;"#).as_deref(),
+            Some("Vendor ID must contain only printable ASCII characters (found '\\u{c0}')")
+        );
+        assert_eq!(
+            first_error(r#"table OS/2 { Vendor ""; } OS/2;"#).as_deref(),
+            Some("Vendor ID must not be empty")
+        );
+        assert_eq!(
+            first_error(r#"table OS/2 { Vendor "ADOBE"; } OS/2;"#).as_deref(),
+            Some("Vendor ID must be at most four characters (found 5)")
+        );
+        assert_eq!(
+            first_error(r#"table OS/2 { Vendor " AB"; } OS/2;"#).as_deref(),
+            Some("Vendor ID may only contain spaces as trailing padding")
+        );
+    }
+
+    #[test]
+    fn duplicate_languagesystem_across_includes() {
+        let resolver = |path: &std::ffi::OsStr| {
+            Ok(match path.to_str().unwrap() {
+                "main.fea" => {
+                    "languagesystem DFLT dflt;\nlanguagesystem latn dflt;\ninclude(other.fea);\n"
+                }
+                "other.fea" => "# comment\nlanguagesystem DFLT dflt;\nlanguagesystem latn dflt;\n",
+                _ => panic!("unexpected path {path:?}"),
+            }
+            .into())
+        };
+        let glyph_map = GlyphMap::default();
+        let (tree, errs) =
+            crate::parse::parse_root("main.fea".into(), Some(&glyph_map), resolver).unwrap();
+        assert!(errs.is_empty(), "{errs:?}");
+        let diagnostics = super::super::validate(&tree, &glyph_map, None);
+        let messages = diagnostics
+            .iter()
+            .map(|diag| {
+                assert!(!diag.is_error());
+                let path = tree.get_source(diag.message.file).unwrap().path();
+                format!("{}: {}", path.to_string_lossy(), diag.message.text)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                "other.fea: Duplicate languagesystem definition (first defined at main.fea:1)",
+                "other.fea: Duplicate languagesystem definition (first defined at main.fea:2)",
+            ]
+        );
+    }
+
+    #[test]
+    fn languagesystem_after_feature() {
+        let (diagnostics, _) = validate_str(
+            "languagesystem DFLT dflt;\n\
+            feature liga { sub a by b; } liga;\n\
+            languagesystem latn dflt;\n",
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert!(!diagnostics[0].is_error());
+        assert_eq!(
+            diagnostics[0].text(),
+            "languagesystem statements should precede all feature blocks; feature 'liga' (at test.fea:2) and any others before this statement will not be registered for 'latn dflt'"
+        );
+    }
+
+    #[test]
+    fn mark_classes_disjoint_in_lookup() {
+        let fea = "\
+markClass [a b] <anchor 0 0> @TOP;
+markClass [b] <anchor 0 0> @OTHER;
+feature test {
+    pos base a <anchor 1 1> mark @TOP;
+    pos base a <anchor 1 1> mark @OTHER;
+} test;
+";
+        let (diagnostics, _) = validate_str(fea);
+        assert_eq!(diagnostics.len(), 1, "{diagnostics:?}");
+        assert_eq!(
+            diagnostics[0].text(),
+            "mark class '@OTHER' shares glyphs with '@TOP', which is used in the same lookup (at test.fea:4): b"
+        );
+        assert_eq!(&fea[diagnostics[0].span()], "@OTHER");
+    }
+
+    #[test]
+    fn class_redefined_in_terms_of_itself() {
+        // each reference should resolve to the previous definition, instead
+        // of recursing into the latest one
+        let fea = "\
+@A = [a];
+@A = [@A @A];
+@A = [@A @A];
+markClass @A <anchor 0 0> @MC;
+";
+        let (diagnostics, _) = validate_str(fea);
+        assert_eq!(diagnostics.len(), 2, "{diagnostics:?}");
+        assert!(diagnostics
+            .iter()
+            .all(|d| d.code == Some(DiagnosticCode::DuplicateGlyphClass)));
+    }
+
+    #[test]
+    fn mark_class_from_redefined_class() {
+        let fea = "\
+@B = [a];
+@B = [@B b];
+markClass @B <anchor 0 0> @MC1;
+markClass [a] <anchor 0 0> @MC2;
+feature test {
+    pos base b <anchor 1 1> mark @MC1;
+    pos base b <anchor 1 1> mark @MC2;
+} test;
+";
+        let (diagnostics, _) = validate_str(fea);
+        let errors = diagnostics
+            .iter()
+            .filter(|d| d.is_error())
+            .collect::<Vec<_>>();
+        assert_eq!(errors.len(), 1, "{diagnostics:?}");
+        assert_eq!(
+            errors[0].text(),
+            "mark class '@MC2' shares glyphs with '@MC1', which is used in the same lookup (at test.fea:6): a"
+        );
+    }
+
+    #[test]
+    fn mark_classes_in_different_lookups() {
+        let (diagnostics, _) = validate_str(
+            "\
+markClass [a b] <anchor 0 0> @TOP;
+markClass [b] <anchor 0 0> @OTHER;
+lookup one {
+    pos base a <anchor 1 1> mark @TOP;
+} one;
+lookup two {
+    pos base a <anchor 1 1> mark @OTHER;
+} two;
+feature test {
+    pos base a <anchor 1 1> mark @TOP;
+    pos mark a <anchor 1 1> mark @OTHER;
+} test;
+",
+        );
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+    }
 }
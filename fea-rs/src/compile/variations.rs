@@ -8,6 +8,9 @@ use write_fonts::{
     types::{F2Dot14, Fixed, Tag},
 };
 
+#[cfg(any(test, feature = "test", feature = "cli"))]
+use write_fonts::tables::variations::RegionAxisCoordinates;
+
 /// A trait for providing variable font information to the compiler.
 ///
 /// In order to compile a variable font, we need to know what axes
@@ -67,6 +70,7 @@ pub struct AxisInfo {
 }
 
 /// A type that implements [`VariationInfo`], for testing and debugging.
+#[cfg(any(test, feature = "test", feature = "cli"))]
 #[derive(Clone, Debug, Default)]
 pub struct MockVariationInfo {
     // Note: This is not considered public API for the purposes of semvar
@@ -74,6 +78,7 @@ pub struct MockVariationInfo {
     pub axes: Vec<(Tag, AxisInfo)>,
 }
 
+#[cfg(any(test, feature = "test", feature = "cli"))]
 impl MockVariationInfo {
     /// input is a tuple of (tag, min, default, max)
     #[cfg(any(test, feature = "test"))]
@@ -150,6 +155,7 @@ impl MockVariationInfo {
     }
 }
 
+#[cfg(any(test, feature = "test", feature = "cli"))]
 impl VariationInfo for MockVariationInfo {
     fn axis_info(&self, axis_tag: Tag) -> Option<AxisInfo> {
         self.axes.iter().find_map(
@@ -186,14 +192,104 @@ impl VariationInfo for MockVariationInfo {
         value.clamp(-Fixed::ONE, Fixed::ONE).to_f2dot14()
     }
 
+    /// A simple model, good enough for testing.
+    ///
+    /// Each non-default location gets a region that peaks at that location,
+    /// and is processed in order of increasing number of non-default axes;
+    /// each delta is the difference between the value at that location and the
+    /// value interpolated from the default and the previous deltas.
     fn resolve_variable_metric(
         &self,
-        _locations: &HashMap<Location, i16>,
-    ) -> Result<(i16, Vec<(VariationRegion, i16)>), Box<(dyn std::error::Error + 'static)>> {
-        Ok(Default::default())
+        locations: &HashMap<Location, i16>,
+    ) -> Result<(i16, Vec<(VariationRegion, i16)>), AnyError> {
+        let mut normalized = locations
+            .iter()
+            .map(|(loc, value)| Ok((self.normalize_location(loc)?, *value)))
+            .collect::<Result<Vec<_>, AnyError>>()?;
+        normalized.sort_by_key(|(loc, _)| {
+            let n_axes = loc.iter().filter(|coord| **coord != F2Dot14::ZERO).count();
+            (
+                n_axes,
+                loc.iter().map(|coord| coord.to_bits()).collect::<Vec<_>>(),
+            )
+        });
+
+        let default = match normalized.first() {
+            Some((loc, value)) if loc.iter().all(|coord| *coord == F2Dot14::ZERO) => *value,
+            _ => return Err("no value at the default location".into()),
+        };
+
+        let mut deltas: Vec<(Vec<F2Dot14>, i16)> = Vec::new();
+        for (loc, value) in normalized.into_iter().skip(1) {
+            let interpolated = deltas
+                .iter()
+                .map(|(peak, delta)| region_scalar(peak, &loc) * *delta as f32)
+                .sum::<f32>();
+            let delta = value as f32 - default as f32 - interpolated;
+            deltas.push((loc, delta.round() as i16));
+        }
+
+        let deltas = deltas
+            .into_iter()
+            .map(|(peak, delta)| {
+                let region = peak
+                    .into_iter()
+                    .map(|peak| {
+                        RegionAxisCoordinates::new(
+                            peak.min(F2Dot14::ZERO),
+                            peak,
+                            peak.max(F2Dot14::ZERO),
+                        )
+                    })
+                    .collect();
+                (VariationRegion::new(region), delta)
+            })
+            .collect();
+        Ok((default, deltas))
+    }
+}
+
+#[cfg(any(test, feature = "test", feature = "cli"))]
+impl MockVariationInfo {
+    /// Convert a location to normalized coordinates, one per axis.
+    fn normalize_location(&self, location: &Location) -> Result<Vec<F2Dot14>, AnyError> {
+        let mut result = vec![F2Dot14::ZERO; self.axes.len()];
+        for (tag, value) in location {
+            let axis = self
+                .axis_info(*tag)
+                .ok_or_else(|| format!("unknown axis '{tag}'"))?;
+            result[axis.index as usize] = match value {
+                AxisLocation::User(value) => {
+                    self.normalize_coordinate(*tag, Fixed::from_f64(value.0 as f64))
+                }
+                AxisLocation::Normalized(value) => F2Dot14::from_f32(value.0),
+                AxisLocation::Design(_) => {
+                    return Err("design coordinates are not supported".into())
+                }
+            };
+        }
+        Ok(result)
     }
 }
 
+/// The contribution of the region peaking at `peak` at the given location.
+#[cfg(any(test, feature = "test", feature = "cli"))]
+fn region_scalar(peak: &[F2Dot14], location: &[F2Dot14]) -> f32 {
+    peak.iter()
+        .zip(location)
+        .map(|(peak, coord)| {
+            let (peak, coord) = (peak.to_f32(), coord.to_f32());
+            if peak == 0.0 {
+                1.0
+            } else if coord == 0.0 || coord.signum() != peak.signum() || coord.abs() > peak.abs() {
+                0.0
+            } else {
+                coord / peak
+            }
+        })
+        .product()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -226,4 +322,46 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn mock_variable_metric() {
+        let var_info = MockVariationInfo::new(&[("wght", 200, 200, 1000), ("wdth", 100, 100, 200)]);
+        let loc = |items: &[(&str, f32)]| -> Location {
+            items
+                .iter()
+                .map(|(tag, val)| {
+                    (
+                        Tag::new_checked(tag.as_bytes()).unwrap(),
+                        AxisLocation::User((*val).into()),
+                    )
+                })
+                .collect()
+        };
+        // the same values as the variable_scalar_valuerecord fonttools test
+        let locations = HashMap::from([
+            (loc(&[("wght", 200.)]), 12),
+            (loc(&[("wght", 900.)]), 22),
+            (loc(&[("wdth", 150.), ("wght", 900.)]), 42),
+        ]);
+        let (default, deltas) = var_info.resolve_variable_metric(&locations).unwrap();
+        assert_eq!(default, 12);
+        assert_eq!(
+            deltas.iter().map(|(_, delta)| *delta).collect::<Vec<_>>(),
+            [10, 20]
+        );
+        let peaks = deltas
+            .iter()
+            .map(|(region, _)| {
+                region
+                    .region_axes
+                    .iter()
+                    .map(|axis| axis.peak_coord.to_f32())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(peaks, [[0.875, 0.0], [0.875, 0.5]]);
+
+        let no_default = HashMap::from([(loc(&[("wght", 900.)]), 22)]);
+        assert!(var_info.resolve_variable_metric(&no_default).is_err());
+    }
 }
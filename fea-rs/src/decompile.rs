@@ -0,0 +1,1427 @@
+//! Generating FEA from compiled layout tables
+//!
+//! This is the inverse of compilation: given the `GSUB`, `GPOS` and `GDEF`
+//! tables of a font, [`decompile`] produces FEA source describing the same
+//! layout rules. This is useful for debugging, and for migrating fonts that
+//! only exist in binary form into editable sources.
+//!
+//! The output should compile to equivalent tables, but not necessarily to
+//! identical ones: lookups that are only referenced by other lookups are moved
+//! so that they are defined before they are referenced (and a lookup that is
+//! also used by a feature is written twice, so that the order in which lookups
+//! are applied is unchanged), and subtables and class definitions may be split
+//! differently. Some things cannot be recovered, and are omitted or written as
+//! comments; these include device tables and variations, feature parameters,
+//! required features, and `GDEF` attachment points and ligature carets.
+//!
+//! Lookups are named after their table and index, such as `GSUB_3`, and mark
+//! classes are given generated names.
+
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+};
+
+use write_fonts::{
+    from_obj::ToOwnedTable,
+    read::{FontRef, ReadError, TableProvider},
+    tables::{
+        gdef::Gdef,
+        gpos::{
+            self, AnchorTable, Gpos, MarkArray, PairPos, PositionLookup, SinglePos, ValueRecord,
+        },
+        gsub::{self, Gsub, SingleSubst, SubstitutionLookup},
+        layout::{
+            ChainedSequenceContext, ClassDef, CoverageTable, FeatureList, Lookup, LookupFlag,
+            ScriptList, SequenceContext, SequenceLookupRecord,
+        },
+    },
+    types::{GlyphId, Tag},
+};
+
+use crate::{GlyphIdent, GlyphMap, Kind};
+
+const INDENT: &str = "    ";
+const DFLT_SCRIPT: Tag = Tag::new(b"DFLT");
+const DFLT_LANG: Tag = Tag::new(b"dflt");
+
+/// Generate FEA source for the layout tables in `font`.
+///
+/// The `GSUB`, `GPOS` and `GDEF` tables are used, if present; glyphs are named
+/// using `glyph_map`, and any glyphs that are not in the map are written as
+/// `glyphN`, where `N` is the glyph id. See the [module docs](self) for the
+/// limitations of the output.
+pub fn decompile(font: &FontRef, glyph_map: &GlyphMap) -> Result<String, ReadError> {
+    let gsub = optional_table(font.gsub())?.map(|table| table.to_owned_table());
+    let gpos = optional_table(font.gpos())?.map(|table| table.to_owned_table());
+    let gdef = optional_table(font.gdef())?.map(|table| table.to_owned_table());
+    Ok(decompile_tables(
+        gsub.as_ref(),
+        gpos.as_ref(),
+        gdef.as_ref(),
+        glyph_map,
+    ))
+}
+
+/// Generate FEA source for the provided layout tables.
+///
+/// This is the same as [`decompile`], but works on tables that have already
+/// been parsed, such as the tables in a [`Compilation`][crate::compile::Compilation].
+pub fn decompile_tables(
+    gsub: Option<&Gsub>,
+    gpos: Option<&Gpos>,
+    gdef: Option<&Gdef>,
+    glyph_map: &GlyphMap,
+) -> String {
+    let mut writer = LookupWriter::new(glyph_map);
+    writer.use_gdef_classes = true;
+
+    writer.write_language_systems(
+        gsub.map(|gsub| &*gsub.script_list)
+            .into_iter()
+            .chain(gpos.map(|gpos| &*gpos.script_list)),
+    );
+    if let Some(gdef) = gdef {
+        writer.write_gdef(gdef);
+    }
+    if let Some(gsub) = gsub {
+        let lookups = &gsub.lookup_list.lookups;
+        let order = lookup_order(
+            lookups.len(),
+            |idx| gsub_nested_lookups(&lookups[idx]),
+            &feature_lookups(&gsub.feature_list),
+        );
+        writer.write_lookups("GSUB", &order, |w, name, idx| {
+            w.gsub_lookup(name, &lookups[idx])
+        });
+    }
+    if let Some(gpos) = gpos {
+        let lookups = &gpos.lookup_list.lookups;
+        let order = lookup_order(
+            lookups.len(),
+            |idx| gpos_nested_lookups(&lookups[idx]),
+            &feature_lookups(&gpos.feature_list),
+        );
+        writer.write_lookups("GPOS", &order, |w, name, idx| {
+            w.gpos_lookup(name, &lookups[idx])
+        });
+    }
+    if let Some(gsub) = gsub {
+        writer.write_features("GSUB", &gsub.script_list, &gsub.feature_list);
+    }
+    if let Some(gpos) = gpos {
+        writer.write_features("GPOS", &gpos.script_list, &gpos.feature_list);
+    }
+    let out = writer.out;
+    out.trim_start().to_string()
+}
+
+fn optional_table<T>(table: Result<T, ReadError>) -> Result<Option<T>, ReadError> {
+    match table {
+        Ok(table) => Ok(Some(table)),
+        Err(ReadError::TableIsMissing(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// tags are padded with spaces, which FEA omits
+pub(crate) fn fea_tag(tag: Tag) -> String {
+    tag.to_string().trim_end().to_string()
+}
+
+fn lookup_name(table: &str, idx: usize) -> String {
+    format!("{table}_{idx}")
+}
+
+/// The name of the copy of a lookup; see [`LookupDef::Copy`].
+fn copy_name(table: &str, idx: usize) -> String {
+    format!("{table}_{idx}_copy")
+}
+
+/// A lookup definition in the decompiled output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LookupDef {
+    /// The lookup itself, named after its index
+    Original(usize),
+    /// A copy of a lookup that is used by a feature, written so that it can be
+    /// referenced by a lookup that comes before it
+    Copy(usize),
+}
+
+/// The lookups referenced by any feature.
+fn feature_lookups(features: &FeatureList) -> BTreeSet<u16> {
+    features
+        .feature_records
+        .iter()
+        .flat_map(|record| record.feature.lookup_list_indices.iter().copied())
+        .collect()
+}
+
+/// Order lookups so that each lookup comes after any lookups it references,
+/// without changing the order in which they are applied.
+///
+/// Lookups are written in their original order, except that a lookup that is
+/// not used by any feature (and so whose position has no effect) is moved
+/// before the first lookup that references it. A lookup that is used by a
+/// feature can't be moved, so if it is referenced by an earlier lookup, a copy
+/// of it is written before that lookup instead.
+fn lookup_order(
+    len: usize,
+    nested: impl Fn(usize) -> Vec<u16>,
+    in_feature: &BTreeSet<u16>,
+) -> Vec<LookupDef> {
+    struct Order<'a, F> {
+        nested: F,
+        in_feature: &'a BTreeSet<u16>,
+        placed: Vec<bool>,
+        copied: Vec<bool>,
+        order: Vec<LookupDef>,
+    }
+
+    impl<F: Fn(usize) -> Vec<u16>> Order<'_, F> {
+        fn place(&mut self, idx: usize) {
+            self.placed[idx] = true;
+            for child in (self.nested)(idx) {
+                self.require(child as usize);
+            }
+            self.order.push(LookupDef::Original(idx));
+        }
+
+        // ensure that a lookup referenced by the current lookup is defined
+        fn require(&mut self, idx: usize) {
+            if idx >= self.placed.len() || self.placed[idx] || self.copied[idx] {
+                return;
+            }
+            if !self.in_feature.contains(&(idx as u16)) {
+                return self.place(idx);
+            }
+            self.copied[idx] = true;
+            for child in (self.nested)(idx) {
+                self.require(child as usize);
+            }
+            self.order.push(LookupDef::Copy(idx));
+        }
+    }
+
+    let mut order = Order {
+        nested,
+        in_feature,
+        placed: vec![false; len],
+        copied: vec![false; len],
+        order: Vec::with_capacity(len),
+    };
+    for idx in 0..len {
+        if !order.placed[idx] {
+            order.place(idx);
+        }
+    }
+    order.order
+}
+
+fn context_nested_lookups(context: &SequenceContext) -> Vec<u16> {
+    let records: Vec<&SequenceLookupRecord> = match context {
+        SequenceContext::Format1(sub) => sub
+            .seq_rule_sets
+            .iter()
+            .flat_map(|set| set.as_ref())
+            .flat_map(|set| set.seq_rules.iter())
+            .flat_map(|rule| rule.seq_lookup_records.iter())
+            .collect(),
+        SequenceContext::Format2(sub) => sub
+            .class_seq_rule_sets
+            .iter()
+            .flat_map(|set| set.as_ref())
+            .flat_map(|set| set.class_seq_rules.iter())
+            .flat_map(|rule| rule.seq_lookup_records.iter())
+            .collect(),
+        SequenceContext::Format3(sub) => sub.seq_lookup_records.iter().collect(),
+    };
+    records.iter().map(|rec| rec.lookup_list_index).collect()
+}
+
+fn chain_nested_lookups(context: &ChainedSequenceContext) -> Vec<u16> {
+    let records: Vec<&SequenceLookupRecord> = match context {
+        ChainedSequenceContext::Format1(sub) => sub
+            .chained_seq_rule_sets
+            .iter()
+            .flat_map(|set| set.as_ref())
+            .flat_map(|set| set.chained_seq_rules.iter())
+            .flat_map(|rule| rule.seq_lookup_records.iter())
+            .collect(),
+        ChainedSequenceContext::Format2(sub) => sub
+            .chained_class_seq_rule_sets
+            .iter()
+            .flat_map(|set| set.as_ref())
+            .flat_map(|set| set.chained_class_seq_rules.iter())
+            .flat_map(|rule| rule.seq_lookup_records.iter())
+            .collect(),
+        ChainedSequenceContext::Format3(sub) => sub.seq_lookup_records.iter().collect(),
+    };
+    records.iter().map(|rec| rec.lookup_list_index).collect()
+}
+
+fn gsub_nested_lookups(lookup: &SubstitutionLookup) -> Vec<u16> {
+    match lookup {
+        SubstitutionLookup::Contextual(lookup) => lookup
+            .subtables
+            .iter()
+            .flat_map(|sub| context_nested_lookups(sub.as_inner()))
+            .collect(),
+        SubstitutionLookup::ChainContextual(lookup) => lookup
+            .subtables
+            .iter()
+            .flat_map(|sub| chain_nested_lookups(sub.as_inner()))
+            .collect(),
+        SubstitutionLookup::Extension(lookup) => unwrap_gsub_extension(lookup)
+            .map(|lookup| gsub_nested_lookups(&lookup))
+            .unwrap_or_default(),
+        _ => Vec::new(),
+    }
+}
+
+fn gpos_nested_lookups(lookup: &PositionLookup) -> Vec<u16> {
+    match lookup {
+        PositionLookup::Contextual(lookup) => lookup
+            .subtables
+            .iter()
+            .flat_map(|sub| context_nested_lookups(sub.as_inner()))
+            .collect(),
+        PositionLookup::ChainContextual(lookup) => lookup
+            .subtables
+            .iter()
+            .flat_map(|sub| chain_nested_lookups(sub.as_inner()))
+            .collect(),
+        PositionLookup::Extension(lookup) => unwrap_gpos_extension(lookup)
+            .map(|lookup| gpos_nested_lookups(&lookup))
+            .unwrap_or_default(),
+        _ => Vec::new(),
+    }
+}
+
+/// Convert an extension lookup into a lookup of the type it wraps.
+///
+/// Returns `None` if the lookup is empty, or its subtables have different types.
+fn unwrap_gsub_extension(lookup: &Lookup<gsub::ExtensionSubtable>) -> Option<SubstitutionLookup> {
+    use gsub::ExtensionSubtable as Ext;
+
+    macro_rules! unwrap_as {
+        ($variant:ident) => {{
+            let subtables = lookup
+                .subtables
+                .iter()
+                .map(|sub| match &**sub {
+                    Ext::$variant(ext) => Some((*ext.extension).clone()),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()?;
+            SubstitutionLookup::$variant(Lookup::new(
+                lookup.lookup_flag,
+                subtables,
+                lookup.mark_filtering_set,
+            ))
+        }};
+    }
+
+    Some(match &**lookup.subtables.first()? {
+        Ext::Single(_) => unwrap_as!(Single),
+        Ext::Multiple(_) => unwrap_as!(Multiple),
+        Ext::Alternate(_) => unwrap_as!(Alternate),
+        Ext::Ligature(_) => unwrap_as!(Ligature),
+        Ext::Contextual(_) => unwrap_as!(Contextual),
+        Ext::ChainContextual(_) => unwrap_as!(ChainContextual),
+        Ext::Reverse(_) => unwrap_as!(Reverse),
+    })
+}
+
+/// Convert an extension lookup into a lookup of the type it wraps.
+///
+/// Returns `None` if the lookup is empty, or its subtables have different types.
+fn unwrap_gpos_extension(lookup: &Lookup<gpos::ExtensionSubtable>) -> Option<PositionLookup> {
+    use gpos::ExtensionSubtable as Ext;
+
+    macro_rules! unwrap_as {
+        ($variant:ident) => {{
+            let subtables = lookup
+                .subtables
+                .iter()
+                .map(|sub| match &**sub {
+                    Ext::$variant(ext) => Some((*ext.extension).clone()),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()?;
+            PositionLookup::$variant(Lookup::new(
+                lookup.lookup_flag,
+                subtables,
+                lookup.mark_filtering_set,
+            ))
+        }};
+    }
+
+    Some(match &**lookup.subtables.first()? {
+        Ext::Single(_) => unwrap_as!(Single),
+        Ext::Pair(_) => unwrap_as!(Pair),
+        Ext::Cursive(_) => unwrap_as!(Cursive),
+        Ext::MarkToBase(_) => unwrap_as!(MarkToBase),
+        Ext::MarkToLig(_) => unwrap_as!(MarkToLig),
+        Ext::MarkToMark(_) => unwrap_as!(MarkToMark),
+        Ext::Contextual(_) => unwrap_as!(Contextual),
+        Ext::ChainContextual(_) => unwrap_as!(ChainContextual),
+    })
+}
+
+/// A contextual rule, with glyphs and classes already converted to FEA.
+struct ContextRule {
+    backtrack: Vec<String>,
+    input: Vec<String>,
+    lookahead: Vec<String>,
+    lookups: Vec<(u16, u16)>,
+}
+
+/// Writes lookups as FEA text.
+pub(crate) struct LookupWriter {
+    names: BTreeMap<GlyphId, GlyphIdent>,
+    num_glyphs: u16,
+    // if true, mark attachment classes and mark filtering sets are referenced
+    // by the names of the classes written by `write_gdef`
+    use_gdef_classes: bool,
+    use_extension: bool,
+    // statements that need to precede the current lookup, such as mark classes
+    prelude: String,
+    next_mark_class: usize,
+    // lookups that are referenced by their copy; see `lookup_order`
+    copied_lookups: BTreeSet<u16>,
+    out: String,
+}
+
+impl LookupWriter {
+    pub(crate) fn new(glyph_map: &GlyphMap) -> Self {
+        LookupWriter {
+            names: glyph_map.reverse_map(),
+            num_glyphs: glyph_map.len().try_into().unwrap_or(u16::MAX),
+            use_gdef_classes: false,
+            use_extension: false,
+            prelude: String::new(),
+            next_mark_class: 0,
+            copied_lookups: BTreeSet::new(),
+            out: String::new(),
+        }
+    }
+
+    /// Write the lookups of a table in the given order.
+    fn write_lookups(
+        &mut self,
+        table: &str,
+        order: &[LookupDef],
+        mut write: impl FnMut(&mut Self, &str, usize),
+    ) {
+        self.copied_lookups = order
+            .iter()
+            .filter_map(|def| match def {
+                LookupDef::Copy(idx) => Some(*idx as u16),
+                LookupDef::Original(_) => None,
+            })
+            .collect();
+        for def in order {
+            self.out.push('\n');
+            match *def {
+                LookupDef::Original(idx) => write(self, &lookup_name(table, idx), idx),
+                LookupDef::Copy(idx) => write(self, &copy_name(table, idx), idx),
+            }
+        }
+    }
+
+    pub(crate) fn write_gsub_lookup(mut self, name: &str, lookup: &SubstitutionLookup) -> String {
+        self.gsub_lookup(name, lookup);
+        self.out
+    }
+
+    pub(crate) fn write_gpos_lookup(mut self, name: &str, lookup: &PositionLookup) -> String {
+        self.gpos_lookup(name, lookup);
+        self.out
+    }
+
+    fn write_language_systems<'a>(&mut self, script_lists: impl Iterator<Item = &'a ScriptList>) {
+        let mut systems = BTreeSet::new();
+        for list in script_lists {
+            for record in &list.script_records {
+                if record.script.default_lang_sys.is_some() {
+                    systems.insert((record.script_tag, DFLT_LANG));
+                }
+                for lang in &record.script.lang_sys_records {
+                    systems.insert((record.script_tag, lang.lang_sys_tag));
+                }
+            }
+        }
+        // DFLT must come first, and each script's dflt before its languages
+        let mut systems = systems.into_iter().collect::<Vec<_>>();
+        systems.sort_by_key(|(script, lang)| {
+            (*script != DFLT_SCRIPT, *script, *lang != DFLT_LANG, *lang)
+        });
+        for (script, lang) in systems {
+            writeln!(
+                self.out,
+                "languagesystem {} {};",
+                fea_tag(script),
+                fea_tag(lang)
+            )
+            .unwrap();
+        }
+    }
+
+    fn write_gdef(&mut self, gdef: &Gdef) {
+        if let Some(classes) = gdef.mark_attach_class_def.as_ref() {
+            self.out.push('\n');
+            for (class, glyphs) in class_members(classes) {
+                let glyphs = self.glyph_seq(&glyphs);
+                writeln!(self.out, "@MarkAttachClass{class} = [{glyphs}];").unwrap();
+            }
+        }
+        if let Some(sets) = gdef.mark_glyph_sets_def.as_ref() {
+            self.out.push('\n');
+            for (i, coverage) in sets.coverages.iter().enumerate() {
+                let glyphs = self.glyph_seq(&coverage.iter().collect::<Vec<_>>());
+                writeln!(self.out, "@MarkFilteringSet{i} = [{glyphs}];").unwrap();
+            }
+        }
+        if let Some(classes) = gdef.glyph_class_def.as_ref() {
+            let members = class_members(classes);
+            let classes = (1..=4)
+                .map(|class| match members.get(&class) {
+                    Some(glyphs) => format!("[{}]", self.glyph_seq(glyphs)),
+                    None => String::new(),
+                })
+                .collect::<Vec<_>>();
+            writeln!(
+                self.out,
+                "\ntable GDEF {{\n{INDENT}GlyphClassDef {};\n}} GDEF;",
+                classes.join(", ")
+            )
+            .unwrap();
+        }
+        if gdef.attach_list.is_some() || gdef.lig_caret_list.is_some() {
+            writeln!(
+                self.out,
+                "# GDEF attachment points and ligature carets are not included"
+            )
+            .unwrap();
+        }
+    }
+
+    fn write_features(&mut self, table: &str, scripts: &ScriptList, features: &FeatureList) {
+        // feature tag -> [(script, language, lookups)]
+        let mut by_tag = BTreeMap::<Tag, Vec<(Tag, Tag, Vec<u16>)>>::new();
+        let mut tag_order = Vec::new();
+        let mut required = Vec::new();
+        for script in &scripts.script_records {
+            let langs = script
+                .script
+                .default_lang_sys
+                .as_ref()
+                .map(|lang| (DFLT_LANG, lang))
+                .into_iter()
+                .chain(
+                    script
+                        .script
+                        .lang_sys_records
+                        .iter()
+                        .map(|rec| (rec.lang_sys_tag, &*rec.lang_sys)),
+                );
+            for (lang_tag, lang) in langs {
+                if lang.required_feature_index != 0xFFFF {
+                    required.push((script.script_tag, lang_tag));
+                }
+                for idx in &lang.feature_indices {
+                    let Some(feature) = features.feature_records.get(*idx as usize) else {
+                        continue;
+                    };
+                    if !by_tag.contains_key(&feature.feature_tag) {
+                        tag_order.push(feature.feature_tag);
+                    }
+                    by_tag.entry(feature.feature_tag).or_default().push((
+                        script.script_tag,
+                        lang_tag,
+                        feature.feature.lookup_list_indices.clone(),
+                    ));
+                }
+            }
+        }
+
+        for (script, lang) in required {
+            writeln!(
+                self.out,
+                "\n# {table}: the required feature for {}/{} is not included",
+                fea_tag(script),
+                fea_tag(lang)
+            )
+            .unwrap();
+        }
+        for tag in tag_order {
+            let tag_text = fea_tag(tag);
+            writeln!(self.out, "\nfeature {tag_text} {{").unwrap();
+            let mut current_script = None;
+            for (script, lang, lookups) in &by_tag[&tag] {
+                if current_script != Some(*script) {
+                    writeln!(self.out, "{INDENT}script {};", fea_tag(*script)).unwrap();
+                    current_script = Some(*script);
+                }
+                if *lang != DFLT_LANG {
+                    writeln!(
+                        self.out,
+                        "{INDENT}language {} exclude_dflt;",
+                        fea_tag(*lang)
+                    )
+                    .unwrap();
+                }
+                for idx in lookups {
+                    let name = lookup_name(table, *idx as usize);
+                    writeln!(self.out, "{INDENT}lookup {name};").unwrap();
+                }
+            }
+            writeln!(self.out, "}} {tag_text};").unwrap();
+        }
+    }
+
+    fn gsub_lookup(&mut self, name: &str, lookup: &SubstitutionLookup) {
+        match lookup {
+            SubstitutionLookup::Single(lookup) => self.write_lookup(name, lookup, |w, sub| {
+                let pairs: Vec<_> = match sub {
+                    SingleSubst::Format1(sub) => sub
+                        .coverage
+                        .iter()
+                        .map(|gid| {
+                            let target = gid.to_u16().wrapping_add(sub.delta_glyph_id as u16);
+                            (gid, GlyphId::new(target))
+                        })
+                        .collect(),
+                    SingleSubst::Format2(sub) => sub
+                        .coverage
+                        .iter()
+                        .zip(sub.substitute_glyph_ids.iter().copied())
+                        .collect(),
+                };
+                for (target, replacement) in pairs {
+                    w.rule(format_args!(
+                        "sub {} by {}",
+                        w.glyph(target),
+                        w.glyph(replacement)
+                    ));
+                }
+            }),
+            SubstitutionLookup::Multiple(lookup) => self.write_lookup(name, lookup, |w, sub| {
+                for (target, seq) in sub.coverage.iter().zip(sub.sequences.iter()) {
+                    let replacement = w.glyph_seq(&seq.substitute_glyph_ids);
+                    w.rule(format_args!("sub {} by {replacement}", w.glyph(target)));
+                }
+            }),
+            SubstitutionLookup::Alternate(lookup) => self.write_lookup(name, lookup, |w, sub| {
+                for (target, set) in sub.coverage.iter().zip(sub.alternate_sets.iter()) {
+                    let alts = w.glyph_seq(&set.alternate_glyph_ids);
+                    w.rule(format_args!("sub {} from [{alts}]", w.glyph(target)));
+                }
+            }),
+            SubstitutionLookup::Ligature(lookup) => self.write_lookup(name, lookup, |w, sub| {
+                for (first, set) in sub.coverage.iter().zip(sub.ligature_sets.iter()) {
+                    for lig in set.ligatures.iter() {
+                        let mut target = w.glyph(first);
+                        for component in &lig.component_glyph_ids {
+                            target.push(' ');
+                            target.push_str(&w.glyph(*component));
+                        }
+                        w.rule(format_args!(
+                            "sub {target} by {}",
+                            w.glyph(lig.ligature_glyph)
+                        ));
+                    }
+                }
+            }),
+            SubstitutionLookup::Contextual(lookup) => self.write_lookup(name, lookup, |w, sub| {
+                let rules = w.context_rules(sub.as_inner());
+                w.context_rules_fea("GSUB", "sub", rules);
+            }),
+            SubstitutionLookup::ChainContextual(lookup) => {
+                self.write_lookup(name, lookup, |w, sub| {
+                    let rules = w.chain_context_rules(sub.as_inner());
+                    w.context_rules_fea("GSUB", "sub", rules);
+                })
+            }
+            SubstitutionLookup::Extension(lookup) => match unwrap_gsub_extension(lookup) {
+                Some(inner) => {
+                    self.use_extension = true;
+                    self.gsub_lookup(name, &inner);
+                    self.use_extension = false;
+                }
+                None => self.unsupported(name, "mixed extension substitution"),
+            },
+            SubstitutionLookup::Reverse(lookup) => self.write_lookup(name, lookup, |w, sub| {
+                let backtrack = sub
+                    .backtrack_coverages
+                    .iter()
+                    .rev()
+                    .map(|cov| w.glyph_set(cov.iter()))
+                    .collect::<Vec<_>>();
+                let lookahead = sub
+                    .lookahead_coverages
+                    .iter()
+                    .map(|cov| w.glyph_set(cov.iter()))
+                    .collect::<Vec<_>>();
+                let input = w.glyph_set(sub.coverage.iter());
+                let replacement = w.glyph_set(sub.substitute_glyph_ids.iter().copied());
+                let mut rule = String::from("rsub");
+                for item in backtrack {
+                    write!(rule, " {item}").unwrap();
+                }
+                write!(rule, " {input}'").unwrap();
+                for item in lookahead {
+                    write!(rule, " {item}").unwrap();
+                }
+                w.rule(format_args!("{rule} by {replacement}"));
+            }),
+        }
+    }
+
+    fn gpos_lookup(&mut self, name: &str, lookup: &PositionLookup) {
+        match lookup {
+            PositionLookup::Single(lookup) => self.write_lookup(name, lookup, |w, sub| {
+                let pairs: Vec<_> = match sub {
+                    SinglePos::Format1(sub) => sub
+                        .coverage
+                        .iter()
+                        .map(|gid| (gid, &sub.value_record))
+                        .collect(),
+                    SinglePos::Format2(sub) => {
+                        sub.coverage.iter().zip(sub.value_records.iter()).collect()
+                    }
+                };
+                for (glyph, value) in pairs {
+                    w.rule(format_args!(
+                        "pos {} {}",
+                        w.glyph(glyph),
+                        value_record(value)
+                    ));
+                }
+            }),
+            PositionLookup::Pair(lookup) => self.write_lookup(name, lookup, |w, sub| match sub {
+                PairPos::Format1(sub) => {
+                    for (first, set) in sub.coverage.iter().zip(sub.pair_sets.iter()) {
+                        for record in set.pair_value_records.iter() {
+                            let rule = pair_rule(
+                                &w.glyph(first),
+                                &w.glyph(record.second_glyph),
+                                &record.value_record1,
+                                &record.value_record2,
+                            );
+                            w.rule(rule);
+                        }
+                    }
+                }
+                PairPos::Format2(sub) => {
+                    let mut class1 = BTreeMap::<u16, Vec<GlyphId>>::new();
+                    for gid in sub.coverage.iter() {
+                        class1.entry(sub.class_def1.get(gid)).or_default().push(gid);
+                    }
+                    let class2 = class_members(&sub.class_def2);
+                    for (class1_id, firsts) in class1 {
+                        let Some(record) = sub.class1_records.get(class1_id as usize) else {
+                            continue;
+                        };
+                        let firsts = format!("[{}]", w.glyph_seq(&firsts));
+                        // class 0 is 'everything else' and can't be enumerated
+                        for (class2_id, seconds) in &class2 {
+                            let Some(values) = record.class2_records.get(*class2_id as usize)
+                            else {
+                                continue;
+                            };
+                            if is_zero(&values.value_record1) && is_zero(&values.value_record2) {
+                                continue;
+                            }
+                            let seconds = format!("[{}]", w.glyph_seq(seconds));
+                            let rule = pair_rule(
+                                &firsts,
+                                &seconds,
+                                &values.value_record1,
+                                &values.value_record2,
+                            );
+                            w.rule(rule);
+                        }
+                    }
+                }
+            }),
+            PositionLookup::Cursive(lookup) => self.write_lookup(name, lookup, |w, sub| {
+                for (glyph, record) in sub.coverage.iter().zip(&sub.entry_exit_record) {
+                    w.rule(format_args!(
+                        "pos cursive {} {} {}",
+                        w.glyph(glyph),
+                        anchor(record.entry_anchor.as_ref()),
+                        anchor(record.exit_anchor.as_ref())
+                    ));
+                }
+            }),
+            PositionLookup::MarkToBase(lookup) => self.write_lookup(name, lookup, |w, sub| {
+                let classes = w.mark_classes(&sub.mark_coverage, &sub.mark_array);
+                for (base, record) in sub.base_coverage.iter().zip(&sub.base_array.base_records) {
+                    let marks = attached_marks(&record.base_anchors, &classes);
+                    if !marks.is_empty() {
+                        w.rule(format_args!("pos base {} {marks}", w.glyph(base)));
+                    }
+                }
+            }),
+            PositionLookup::MarkToMark(lookup) => self.write_lookup(name, lookup, |w, sub| {
+                let classes = w.mark_classes(&sub.mark1_coverage, &sub.mark1_array);
+                for (base, record) in sub
+                    .mark2_coverage
+                    .iter()
+                    .zip(&sub.mark2_array.mark2_records)
+                {
+                    let marks = attached_marks(&record.mark2_anchors, &classes);
+                    if !marks.is_empty() {
+                        w.rule(format_args!("pos mark {} {marks}", w.glyph(base)));
+                    }
+                }
+            }),
+            PositionLookup::MarkToLig(lookup) => self.write_lookup(name, lookup, |w, sub| {
+                let classes = w.mark_classes(&sub.mark_coverage, &sub.mark_array);
+                for (lig, attach) in sub
+                    .ligature_coverage
+                    .iter()
+                    .zip(&sub.ligature_array.ligature_attaches)
+                {
+                    let components = attach
+                        .component_records
+                        .iter()
+                        .map(|component| {
+                            let marks = attached_marks(&component.ligature_anchors, &classes);
+                            if marks.is_empty() {
+                                anchor(None)
+                            } else {
+                                marks
+                            }
+                        })
+                        .collect::<Vec<_>>();
+                    w.rule(format_args!(
+                        "pos ligature {} {}",
+                        w.glyph(lig),
+                        components.join(" ligComponent ")
+                    ));
+                }
+            }),
+            PositionLookup::Contextual(lookup) => self.write_lookup(name, lookup, |w, sub| {
+                let rules = w.context_rules(sub.as_inner());
+                w.context_rules_fea("GPOS", "pos", rules);
+            }),
+            PositionLookup::ChainContextual(lookup) => self.write_lookup(name, lookup, |w, sub| {
+                let rules = w.chain_context_rules(sub.as_inner());
+                w.context_rules_fea("GPOS", "pos", rules);
+            }),
+            PositionLookup::Extension(lookup) => match unwrap_gpos_extension(lookup) {
+                Some(inner) => {
+                    self.use_extension = true;
+                    self.gpos_lookup(name, &inner);
+                    self.use_extension = false;
+                }
+                None => self.unsupported(name, "mixed extension positioning"),
+            },
+        }
+    }
+
+    fn write_lookup<T>(
+        &mut self,
+        name: &str,
+        lookup: &Lookup<T>,
+        mut f: impl FnMut(&mut Self, &T),
+    ) {
+        let start = self.out.len();
+        let extension = if self.use_extension {
+            " useExtension"
+        } else {
+            ""
+        };
+        writeln!(self.out, "lookup {name}{extension} {{").unwrap();
+        self.write_flags(lookup.lookup_flag, lookup.mark_filtering_set);
+        for (i, subtable) in lookup.subtables.iter().enumerate() {
+            if i > 0 {
+                writeln!(self.out, "{INDENT}subtable;").unwrap();
+            }
+            f(self, subtable);
+        }
+        writeln!(self.out, "}} {name};").unwrap();
+        let prelude = std::mem::take(&mut self.prelude);
+        self.out.insert_str(start, &prelude);
+    }
+
+    fn unsupported(&mut self, name: &str, kind: &str) {
+        writeln!(
+            self.out,
+            "# lookup {name}: {kind} lookups cannot be written as FEA"
+        )
+        .unwrap();
+    }
+
+    fn write_flags(&mut self, flags: LookupFlag, mark_filtering_set: u16) {
+        let mut names = Vec::new();
+        if flags.right_to_left() {
+            names.push("RightToLeft".to_string());
+        }
+        if flags.ignore_base_glyphs() {
+            names.push("IgnoreBaseGlyphs".to_string());
+        }
+        if flags.ignore_ligatures() {
+            names.push("IgnoreLigatures".to_string());
+        }
+        if flags.ignore_marks() {
+            names.push("IgnoreMarks".to_string());
+        }
+        let mut comments = Vec::new();
+        if let Some(class) = flags.mark_attachment_type_mask() {
+            if self.use_gdef_classes {
+                names.push(format!("MarkAttachmentType @MarkAttachClass{class}"));
+            } else {
+                // we don't know the names of the classes these refer to
+                comments.push(format!("# MarkAttachmentType: class {class}"));
+            }
+        }
+        if flags.use_mark_filtering_set() {
+            if self.use_gdef_classes {
+                names.push(format!(
+                    "UseMarkFilteringSet @MarkFilteringSet{mark_filtering_set}"
+                ));
+            } else {
+                comments.push(format!("# UseMarkFilteringSet: set {mark_filtering_set}"));
+            }
+        }
+        if !names.is_empty() {
+            writeln!(self.out, "{INDENT}lookupflag {};", names.join(" ")).unwrap();
+        }
+        for comment in comments {
+            writeln!(self.out, "{INDENT}{comment}").unwrap();
+        }
+    }
+
+    /// Write the mark classes for a mark attachment subtable to the prelude.
+    ///
+    /// Returns the names of the classes, by class index.
+    fn mark_classes(&mut self, coverage: &CoverageTable, marks: &MarkArray) -> Vec<String> {
+        let n_classes = marks
+            .mark_records
+            .iter()
+            .map(|record| record.mark_class as usize + 1)
+            .max()
+            .unwrap_or_default();
+        let names = (0..n_classes)
+            .map(|i| format!("@MC_{}", self.next_mark_class + i))
+            .collect::<Vec<_>>();
+        self.next_mark_class += n_classes;
+
+        // group glyphs with the same class and anchor into one statement
+        let mut groups = BTreeMap::<(u16, String), Vec<GlyphId>>::new();
+        for (glyph, record) in coverage.iter().zip(&marks.mark_records) {
+            groups
+                .entry((record.mark_class, anchor(Some(&record.mark_anchor))))
+                .or_default()
+                .push(glyph);
+        }
+        for ((class, anchor), glyphs) in groups {
+            writeln!(
+                self.prelude,
+                "markClass {} {anchor} {};",
+                self.glyph_set(glyphs.into_iter()),
+                names[class as usize]
+            )
+            .unwrap();
+        }
+        names
+    }
+
+    fn context_rules(&self, context: &SequenceContext) -> Vec<ContextRule> {
+        let mut rules = Vec::new();
+        match context {
+            SequenceContext::Format1(sub) => {
+                for (first, set) in sub.coverage.iter().zip(&sub.seq_rule_sets) {
+                    for rule in set.iter().flat_map(|set| set.seq_rules.iter()) {
+                        let input =
+                            std::iter::once(first).chain(rule.input_sequence.iter().copied());
+                        rules.push(ContextRule {
+                            backtrack: Vec::new(),
+                            input: input.map(|gid| self.glyph(gid)).collect(),
+                            lookahead: Vec::new(),
+                            lookups: lookup_records(&rule.seq_lookup_records),
+                        });
+                    }
+                }
+            }
+            SequenceContext::Format2(sub) => {
+                for (class, set) in sub.class_seq_rule_sets.iter().enumerate() {
+                    for rule in set.iter().flat_map(|set| set.class_seq_rules.iter()) {
+                        let first =
+                            self.class_glyphs(&sub.class_def, class as u16, Some(&sub.coverage));
+                        let rest = self.classes(&sub.class_def, &rule.input_sequence);
+                        let (Some(first), Some(rest)) = (first, rest) else {
+                            continue;
+                        };
+                        rules.push(ContextRule {
+                            backtrack: Vec::new(),
+                            input: std::iter::once(first).chain(rest).collect(),
+                            lookahead: Vec::new(),
+                            lookups: lookup_records(&rule.seq_lookup_records),
+                        });
+                    }
+                }
+            }
+            SequenceContext::Format3(sub) => rules.push(ContextRule {
+                backtrack: Vec::new(),
+                input: self.coverages(&sub.coverages),
+                lookahead: Vec::new(),
+                lookups: lookup_records(&sub.seq_lookup_records),
+            }),
+        }
+        rules
+    }
+
+    fn chain_context_rules(&self, context: &ChainedSequenceContext) -> Vec<ContextRule> {
+        let mut rules = Vec::new();
+        match context {
+            ChainedSequenceContext::Format1(sub) => {
+                for (first, set) in sub.coverage.iter().zip(&sub.chained_seq_rule_sets) {
+                    for rule in set.iter().flat_map(|set| set.chained_seq_rules.iter()) {
+                        let input =
+                            std::iter::once(first).chain(rule.input_sequence.iter().copied());
+                        rules.push(ContextRule {
+                            // the backtrack is stored in reverse order
+                            backtrack: rule
+                                .backtrack_sequence
+                                .iter()
+                                .rev()
+                                .map(|gid| self.glyph(*gid))
+                                .collect(),
+                            input: input.map(|gid| self.glyph(gid)).collect(),
+                            lookahead: rule
+                                .lookahead_sequence
+                                .iter()
+                                .map(|gid| self.glyph(*gid))
+                                .collect(),
+                            lookups: lookup_records(&rule.seq_lookup_records),
+                        });
+                    }
+                }
+            }
+            ChainedSequenceContext::Format2(sub) => {
+                for (class, set) in sub.chained_class_seq_rule_sets.iter().enumerate() {
+                    for rule in set
+                        .iter()
+                        .flat_map(|set| set.chained_class_seq_rules.iter())
+                    {
+                        let first = self.class_glyphs(
+                            &sub.input_class_def,
+                            class as u16,
+                            Some(&sub.coverage),
+                        );
+                        let rest = self.classes(&sub.input_class_def, &rule.input_sequence);
+                        let backtrack =
+                            self.classes(&sub.backtrack_class_def, &rule.backtrack_sequence);
+                        let lookahead =
+                            self.classes(&sub.lookahead_class_def, &rule.lookahead_sequence);
+                        let (Some(first), Some(rest), Some(mut backtrack), Some(lookahead)) =
+                            (first, rest, backtrack, lookahead)
+                        else {
+                            continue;
+                        };
+                        backtrack.reverse();
+                        rules.push(ContextRule {
+                            backtrack,
+                            input: std::iter::once(first).chain(rest).collect(),
+                            lookahead,
+                            lookups: lookup_records(&rule.seq_lookup_records),
+                        });
+                    }
+                }
+            }
+            ChainedSequenceContext::Format3(sub) => {
+                let mut backtrack = self.coverages(&sub.backtrack_coverages);
+                backtrack.reverse();
+                rules.push(ContextRule {
+                    backtrack,
+                    input: self.coverages(&sub.input_coverages),
+                    lookahead: self.coverages(&sub.lookahead_coverages),
+                    lookups: lookup_records(&sub.seq_lookup_records),
+                })
+            }
+        }
+        rules
+    }
+
+    fn context_rules_fea(&mut self, table: &str, keyword: &str, rules: Vec<ContextRule>) {
+        for rule in rules {
+            let mut text = if rule.lookups.is_empty() {
+                format!("ignore {keyword}")
+            } else {
+                keyword.to_string()
+            };
+            for item in &rule.backtrack {
+                write!(text, " {item}").unwrap();
+            }
+            for (i, item) in rule.input.iter().enumerate() {
+                write!(text, " {item}'").unwrap();
+                for (_, lookup) in rule.lookups.iter().filter(|(pos, _)| *pos as usize == i) {
+                    let name = if self.copied_lookups.contains(lookup) {
+                        copy_name(table, *lookup as usize)
+                    } else {
+                        lookup_name(table, *lookup as usize)
+                    };
+                    write!(text, " lookup {name}").unwrap();
+                }
+            }
+            for item in &rule.lookahead {
+                write!(text, " {item}").unwrap();
+            }
+            self.rule(text);
+        }
+    }
+
+    /// The glyphs in a class, as FEA, or `None` if the class is empty.
+    ///
+    /// Class 0 contains all glyphs that are not assigned another class.
+    fn class_glyphs(
+        &self,
+        class_def: &ClassDef,
+        class: u16,
+        coverage: Option<&CoverageTable>,
+    ) -> Option<String> {
+        let mut glyphs = if class == 0 {
+            let assigned = class_def
+                .iter()
+                .map(|(gid, _)| gid)
+                .collect::<BTreeSet<_>>();
+            (0..self.num_glyphs)
+                .map(GlyphId::new)
+                .filter(|gid| !assigned.contains(gid))
+                .collect::<Vec<_>>()
+        } else {
+            class_def
+                .iter()
+                .filter(|(_, cls)| *cls == class)
+                .map(|(gid, _)| gid)
+                .collect()
+        };
+        if let Some(coverage) = coverage {
+            let coverage = coverage.iter().collect::<BTreeSet<_>>();
+            glyphs.retain(|gid| coverage.contains(gid));
+        }
+        (!glyphs.is_empty()).then(|| self.glyph_set(glyphs.into_iter()))
+    }
+
+    fn classes(&self, class_def: &ClassDef, classes: &[u16]) -> Option<Vec<String>> {
+        classes
+            .iter()
+            .map(|class| self.class_glyphs(class_def, *class, None))
+            .collect()
+    }
+
+    fn coverages(&self, coverages: &[write_fonts::OffsetMarker<CoverageTable>]) -> Vec<String> {
+        coverages
+            .iter()
+            .map(|coverage| self.glyph_set(coverage.iter()))
+            .collect()
+    }
+
+    fn rule(&mut self, rule: impl std::fmt::Display) {
+        writeln!(self.out, "{INDENT}{rule};").unwrap();
+    }
+
+    fn glyph(&self, gid: GlyphId) -> String {
+        match self.names.get(&gid) {
+            Some(GlyphIdent::Name(name)) => escape_glyph_name(name).into_owned(),
+            Some(GlyphIdent::Cid(cid)) => format!("\\{cid}"),
+            None => format!("glyph{}", gid.to_u16()),
+        }
+    }
+
+    fn glyph_seq(&self, glyphs: &[GlyphId]) -> String {
+        glyphs
+            .iter()
+            .map(|gid| self.glyph(*gid))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// A single glyph, or a class if there is more than one glyph.
+    fn glyph_set(&self, glyphs: impl Iterator<Item = GlyphId>) -> String {
+        let glyphs = glyphs.collect::<Vec<_>>();
+        match glyphs.as_slice() {
+            [glyph] => self.glyph(*glyph),
+            _ => format!("[{}]", self.glyph_seq(&glyphs)),
+        }
+    }
+}
+
+/// A glyph name as it should be written in FEA, escaped with a backslash if needed.
+///
+/// Names need to be escaped if they would not otherwise be parsed as a glyph
+/// name: this is the case for names that match keywords, and for names
+/// containing hyphens, which may be interpreted as ranges.
+pub(crate) fn escape_glyph_name(name: &str) -> Cow<'_, str> {
+    let mut tokens = crate::parse::tokenize(name);
+    let is_ident = tokens.next().map(|token| token.kind) == Some(Kind::Ident);
+    if !is_ident || tokens.next().is_some() || name.contains('-') {
+        Cow::Owned(format!("\\{name}"))
+    } else {
+        Cow::Borrowed(name)
+    }
+}
+
+fn lookup_records(records: &[SequenceLookupRecord]) -> Vec<(u16, u16)> {
+    records
+        .iter()
+        .map(|rec| (rec.sequence_index, rec.lookup_list_index))
+        .collect()
+}
+
+/// The `<anchor> mark @CLASS` pairs for a base, ligature component, or mark.
+fn attached_marks(
+    anchors: &[write_fonts::NullableOffsetMarker<AnchorTable>],
+    classes: &[String],
+) -> String {
+    anchors
+        .iter()
+        .zip(classes)
+        .filter_map(|(anchor_table, class)| {
+            let anchor_table = anchor_table.as_ref()?;
+            Some(format!("{} mark {class}", anchor(Some(anchor_table))))
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// device tables are not included
+fn anchor(anchor: Option<&AnchorTable>) -> String {
+    match anchor {
+        None => "<anchor NULL>".into(),
+        Some(AnchorTable::Format1(anchor)) => {
+            format!("<anchor {} {}>", anchor.x_coordinate, anchor.y_coordinate)
+        }
+        Some(AnchorTable::Format2(anchor)) => format!(
+            "<anchor {} {} contourpoint {}>",
+            anchor.x_coordinate, anchor.y_coordinate, anchor.anchor_point
+        ),
+        Some(AnchorTable::Format3(anchor)) => {
+            format!("<anchor {} {}>", anchor.x_coordinate, anchor.y_coordinate)
+        }
+    }
+}
+
+fn class_members(class_def: &ClassDef) -> BTreeMap<u16, Vec<GlyphId>> {
+    let mut result = BTreeMap::<u16, BTreeSet<GlyphId>>::new();
+    for (gid, class) in class_def.iter() {
+        if class != 0 {
+            result.entry(class).or_default().insert(gid);
+        }
+    }
+    result
+        .into_iter()
+        .map(|(class, glyphs)| (class, glyphs.into_iter().collect()))
+        .collect()
+}
+
+fn pair_rule(first: &str, second: &str, value1: &ValueRecord, value2: &ValueRecord) -> String {
+    if value2.format().is_empty() {
+        format!("pos {first} {second} {}", value_record(value1))
+    } else {
+        format!(
+            "pos {first} {} {second} {}",
+            value_record_full(value1),
+            value_record_full(value2)
+        )
+    }
+}
+
+fn is_zero(record: &ValueRecord) -> bool {
+    record.format().is_empty()
+        || (record.x_placement.unwrap_or_default() == 0
+            && record.y_placement.unwrap_or_default() == 0
+            && record.x_advance.unwrap_or_default() == 0
+            && record.y_advance.unwrap_or_default() == 0)
+}
+
+// a record with only an x advance is written as a single number. (This is
+// approximate: in a vertical feature it would be interpreted as a y advance.)
+fn value_record(record: &ValueRecord) -> String {
+    let only_x_advance = record.x_placement.unwrap_or_default() == 0
+        && record.y_placement.unwrap_or_default() == 0
+        && record.y_advance.unwrap_or_default() == 0;
+    if only_x_advance {
+        record.x_advance.unwrap_or_default().to_string()
+    } else {
+        value_record_full(record)
+    }
+}
+
+// device tables are not included
+fn value_record_full(record: &ValueRecord) -> String {
+    format!(
+        "<{} {} {} {}>",
+        record.x_placement.unwrap_or_default(),
+        record.y_placement.unwrap_or_default(),
+        record.x_advance.unwrap_or_default(),
+        record.y_advance.unwrap_or_default()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsStr, sync::Arc};
+
+    use super::*;
+    use crate::{compile::Compilation, Compiler, GlyphName};
+
+    fn glyph_map() -> GlyphMap {
+        [
+            ".notdef", "a", "b", "c", "d", "e", "f", "i", "f_i", "sub", "a-b", "acute", "grave",
+        ]
+        .into_iter()
+        .map(GlyphName::new)
+        .collect()
+    }
+
+    fn compile(fea: String, glyph_map: &GlyphMap) -> Compilation {
+        Compiler::new("test.fea", glyph_map)
+            .with_resolver(move |_: &OsStr| Ok(Arc::<str>::from(fea.as_str())))
+            .compile()
+            .unwrap()
+    }
+
+    fn decompile_compilation(compilation: &Compilation, glyph_map: &GlyphMap) -> String {
+        decompile_tables(
+            compilation.gsub.as_ref(),
+            compilation.gpos.as_ref(),
+            compilation.gdef.as_ref(),
+            glyph_map,
+        )
+    }
+
+    const FEA: &str = "\
+languagesystem DFLT dflt;
+languagesystem latn dflt;
+languagesystem latn TRK;
+@MARKS = [acute grave];
+markClass acute <anchor 0 500> @TOP;
+markClass grave <anchor 10 500 contourpoint 2> @TOP;
+lookup single { sub a by b; } single;
+lookup lig useExtension { sub f i by f_i; } lig;
+feature calt {
+    sub [a b] c' lookup single d;
+    ignore sub e a' b;
+} calt;
+feature liga {
+    lookup lig;
+    rsub a [b c]' d by [d e];
+    script latn;
+    language TRK exclude_dflt;
+    sub \\sub by \\a-b;
+} liga;
+feature mark {
+    lookupflag UseMarkFilteringSet @MARKS;
+    pos base [a b] <anchor 250 450> mark @TOP;
+    pos ligature f_i <anchor 100 600> mark @TOP ligComponent <anchor NULL>;
+} mark;
+feature curs {
+    pos cursive a <anchor NULL> <anchor 500 20>;
+} curs;
+";
+
+    #[test]
+    fn round_trip() {
+        let glyph_map = glyph_map();
+        let compilation = compile(FEA.to_string(), &glyph_map);
+        let decompiled = decompile_compilation(&compilation, &glyph_map);
+
+        for expected in [
+            "languagesystem DFLT dflt;\nlanguagesystem latn dflt;\nlanguagesystem latn TRK;\n",
+            "lookup GSUB_1 useExtension {\n    sub f i by f_i;\n} GSUB_1;",
+            "sub [a b] c' lookup GSUB_0 d;",
+            "ignore sub e a' b;",
+            "rsub a [b c]' d by [d e];",
+            "sub \\sub by \\a-b;",
+            "markClass acute <anchor 0 500> @MC_0;",
+            "markClass grave <anchor 10 500 contourpoint 2> @MC_0;",
+            "lookupflag UseMarkFilteringSet @MarkFilteringSet0;",
+            "pos ligature f_i <anchor 100 600> mark @MC_1 ligComponent <anchor NULL>;",
+            "pos cursive a <anchor NULL> <anchor 500 20>;",
+            "    language TRK exclude_dflt;\n    lookup GSUB_4;\n",
+        ] {
+            assert!(
+                decompiled.contains(expected),
+                "missing '{expected}' in:\n{decompiled}"
+            );
+        }
+
+        // decompiling our own output gives the same result
+        let recompiled = compile(decompiled.clone(), &glyph_map);
+        assert_eq!(decompile_compilation(&recompiled, &glyph_map), decompiled);
+        // the lookups are applied in the same order, but subtable formats may
+        // differ, so we don't compare the GSUB lookups themselves
+        let (gsub, original) = (recompiled.gsub.unwrap(), compilation.gsub.unwrap());
+        assert_eq!(gsub.script_list, original.script_list);
+        assert_eq!(gsub.feature_list, original.feature_list);
+        assert_eq!(
+            gsub.lookup_list.lookups.len(),
+            original.lookup_list.lookups.len()
+        );
+        assert_eq!(recompiled.gpos, compilation.gpos);
+    }
+
+    #[test]
+    fn feature_lookup_used_by_earlier_lookup() {
+        let glyph_map = glyph_map();
+        let fea = "feature calt { sub a' b by c; } calt;".to_string();
+        let mut compilation = compile(fea, &glyph_map);
+        // the contextual lookup is first, and the lookup it references is
+        // also used by the feature, after it
+        let gsub = compilation.gsub.as_mut().unwrap();
+        assert_eq!(gsub.lookup_list.lookups.len(), 2);
+        gsub.feature_list.feature_records[0]
+            .feature
+            .lookup_list_indices = vec![0, 1];
+
+        let decompiled = decompile_compilation(&compilation, &glyph_map);
+        let copy = decompiled.find("lookup GSUB_1_copy {").unwrap();
+        let context = decompiled.find("lookup GSUB_0 {").unwrap();
+        let original = decompiled.find("lookup GSUB_1 {").unwrap();
+        assert!(copy < context && context < original, "{decompiled}");
+        assert!(decompiled.contains("sub a' lookup GSUB_1_copy b;"));
+        assert!(decompiled.contains("    lookup GSUB_0;\n    lookup GSUB_1;\n"));
+    }
+
+    #[test]
+    fn nested_lookups_come_first() {
+        let order = lookup_order(
+            4,
+            |idx| match idx {
+                0 => vec![2],
+                2 => vec![3],
+                _ => vec![],
+            },
+            &BTreeSet::new(),
+        );
+        use LookupDef::Original;
+        assert_eq!(order, [Original(3), Original(2), Original(0), Original(1)]);
+    }
+
+    #[test]
+    fn feature_lookups_are_copied() {
+        // lookup 2 is used by a feature after lookup 1, so it can't move
+        let order = lookup_order(
+            3,
+            |idx| if idx == 0 { vec![2] } else { vec![] },
+            &[1, 2].into_iter().collect(),
+        );
+        use LookupDef::{Copy, Original};
+        assert_eq!(order, [Copy(2), Original(0), Original(1), Original(2)]);
+    }
+
+    #[test]
+    fn escape_glyph_names() {
+        assert_eq!(escape_glyph_name("a"), "a");
+        assert_eq!(escape_glyph_name("f_i.alt"), "f_i.alt");
+        assert_eq!(escape_glyph_name("sub"), "\\sub");
+        assert_eq!(escape_glyph_name("a-b"), "\\a-b");
+    }
+}
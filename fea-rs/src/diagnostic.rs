@@ -1,6 +1,9 @@
 //! Reporting errors, warnings, and other information to the user.
-use crate::parse::FileId;
-use std::{convert::TryInto, ops::Range};
+use crate::parse::{FileId, Source};
+use std::{convert::TryInto, ops::Range, path::Path, str::FromStr};
+
+/// The maximum number of source lines kept by a [`DetachedDiagnostic`].
+const MAX_DETACHED_LINES: usize = 5;
 
 /// A span of a source file.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -11,6 +14,11 @@ pub struct Span {
 
 /// A diagnostic level
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "lowercase")
+)]
 #[repr(u8)]
 pub enum Level {
     /// An unrecoverable error
@@ -21,6 +29,136 @@ pub enum Level {
     Info,
 }
 
+/// Declare the diagnostic codes, along with their stable names.
+macro_rules! diagnostic_codes {
+    ($($(#[$meta:meta])* $variant:ident => $name:literal,)*) => {
+        /// A code identifying the kind of a diagnostic.
+        ///
+        /// Each code has a stable name (such as `duplicate-glyph-class`) that
+        /// can be used to refer to it in configuration, for instance to promote
+        /// warnings with that code to errors with a
+        /// [`LintConfig`][crate::compile::LintConfig].
+        ///
+        /// Warnings have a code for each kind of problem. Errors are
+        /// currently only identified by the stage that reports them: parsing,
+        /// resolving includes, validation, or compilation.
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        #[non_exhaustive]
+        pub enum DiagnosticCode {
+            $($(#[$meta])* $variant,)*
+        }
+
+        impl DiagnosticCode {
+            /// All of the known diagnostic codes.
+            pub const ALL: &'static [DiagnosticCode] = &[$(DiagnosticCode::$variant,)*];
+
+            /// The stable name of this code.
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(DiagnosticCode::$variant => $name,)*
+                }
+            }
+        }
+    };
+}
+
+diagnostic_codes! {
+    /// A `;` that does not follow a statement
+    StraySemicolon => "stray-semicolon",
+    /// An `include` statement without a trailing `;`
+    IncludeMissingSemicolon => "include-missing-semicolon",
+    /// A name that could be either a glyph name or a glyph range
+    AmbiguousGlyphName => "ambiguous-glyph-name",
+    /// A contextual rule in which no glyphs are marked
+    UnmarkedContextualGlyphs => "unmarked-contextual-glyphs",
+    /// A `languagesystem` statement that repeats an earlier one
+    DuplicateLanguageSystem => "duplicate-language-system",
+    /// A glyph class that is defined more than once
+    DuplicateGlyphClass => "duplicate-glyph-class",
+    /// An `anchorDef` name that is defined more than once
+    DuplicateAnchor => "duplicate-anchor",
+    /// A `valueRecordDef` name that is defined more than once
+    DuplicateValueRecord => "duplicate-value-record",
+    /// A `conditionset` that is defined more than once
+    DuplicateConditionSet => "duplicate-condition-set",
+    /// A feature that is referenced more than once in an `aalt` feature
+    DuplicateAaltFeature => "duplicate-aalt-feature",
+    /// A mark class that is used before it is defined
+    LateMarkClass => "late-mark-class",
+    /// An `OS/2` `FamilyClass` that is not defined by the spec
+    UnknownFamilyClass => "unknown-family-class",
+    /// A `name` record with an unsupported platform and encoding
+    UnsupportedEncoding => "unsupported-encoding",
+    /// A `FontRevision` that is overwritten by a later statement
+    FontRevisionOverwritten => "font-revision-overwritten",
+    /// A `FontRevision` without exactly three decimal places
+    FontRevisionPrecision => "font-revision-precision",
+    /// A `featureNames` block that is not at the start of an `ssXX` feature
+    MisplacedFeatureNames => "misplaced-feature-names",
+    /// A `cvParameters` block that is missing one of its name entries
+    IncompleteCvParameters => "incomplete-cv-parameters",
+    /// A lookup reference outside of a feature, which has no effect
+    LookupReferenceOutsideFeature => "lookup-reference-outside-feature",
+    /// An unexpected item in a glyph class
+    UnexpectedClassItem => "unexpected-class-item",
+    /// A member of a glyph range that is not in the glyph map
+    MissingRangeMember => "missing-range-member",
+    /// A script and language used in a feature without a `languagesystem` statement
+    UndeclaredLanguageSystem => "undeclared-language-system",
+    /// A `languagesystem` statement that follows a feature block
+    LateLanguageSystem => "late-language-system",
+    /// A `language` statement that does not inherit the rules at the start of its feature
+    UninheritedDefaultRules => "uninherited-default-rules",
+    /// A lookup (or a reference to a lookup) that contains no rules
+    EmptyLookup => "empty-lookup",
+    /// A statement that is parsed but not compiled
+    UnimplementedStatement => "unimplemented-statement",
+    /// A substitution whose target is already substituted by an earlier rule in the lookup
+    DuplicateSubstitution => "duplicate-substitution",
+    /// A class-to-class substitution whose classes are ordered differently
+    MismatchedClassOrder => "mismatched-class-order",
+    /// A glyph in a mark filtering set that is not classified as a mark in GDEF
+    NonMarkInMarkFilterSet => "non-mark-in-mark-filter-set",
+    /// An error in the syntax of a source
+    ParseError => "parse-error",
+    /// An `include` statement that cannot be resolved
+    IncludeError => "include-error",
+    /// An error found while validating the parsed sources
+    ValidationError => "validation-error",
+    /// An error found while compiling the validated sources
+    CompileError => "compile-error",
+}
+
+/// An error returned when parsing an unknown [`DiagnosticCode`] name.
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+#[error("unknown diagnostic code '{0}'")]
+pub struct UnknownDiagnosticCode(String);
+
+impl FromStr for DiagnosticCode {
+    type Err = UnknownDiagnosticCode;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DiagnosticCode::ALL
+            .iter()
+            .copied()
+            .find(|code| code.name() == s)
+            .ok_or_else(|| UnknownDiagnosticCode(s.to_owned()))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for DiagnosticCode {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl std::fmt::Display for DiagnosticCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
 /// A message, associated with a location in a file.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Message {
@@ -38,15 +176,179 @@ pub struct Diagnostic {
     pub message: Message,
     /// The diagnostic level
     pub level: Level,
+    /// A code identifying the kind of diagnostic, if it has one.
+    ///
+    /// All diagnostics reported by this crate have a code; see
+    /// [`DiagnosticCode`] for what they identify.
+    pub code: Option<DiagnosticCode>,
+    /// An optional edit to the source that would resolve this diagnostic
+    pub edit: Option<SuggestedEdit>,
+}
+
+/// A machine-applicable change to the source, suggested by a diagnostic.
+///
+/// The edit applies to the same file as the diagnostic's message. To apply it,
+/// replace the text in `span` with `replacement`; an empty span is an insertion.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SuggestedEdit {
+    /// The span of text to be replaced
+    pub span: Span,
+    /// The text to replace it with
+    pub replacement: String,
+}
+
+/// A diagnostic that carries its own copy of the relevant source text.
+///
+/// A [`Diagnostic`] refers to its source file by id, and so can only be
+/// displayed while the [`ParseTree`][crate::ParseTree] (or compiler error)
+/// that owns the sources is still around. This type instead includes the
+/// path, the location, and the offending source lines, as well as the
+/// rendered (uncolored) message, so it can be stored or logged after the
+/// sources have been dropped.
+///
+/// Create one with [`ParseTree::detach_diagnostic`][crate::ParseTree::detach_diagnostic]
+/// or [`DiagnosticSet::detach`][crate::compile::error::DiagnosticSet::detach].
+/// The `Display` impl writes the same text as `format_diagnostic` would
+/// without colors.
+///
+/// With the `serde` feature enabled this type can be serialized, for instance
+/// to report diagnostics to an editor as JSON.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DetachedDiagnostic {
+    /// The diagnostic level
+    #[cfg_attr(feature = "serde", serde(rename = "severity"))]
+    pub level: Level,
+    /// The code identifying the kind of diagnostic, if it has one
+    pub code: Option<DiagnosticCode>,
+    /// The message text
+    pub message: String,
+    /// The path of the file containing the diagnostic
+    pub path: String,
+    /// The byte range of the diagnostic in the file
+    pub range: Range<usize>,
+    /// The (1-indexed) line on which the diagnostic starts
+    pub line: usize,
+    /// The offset in bytes of the start of the diagnostic within its line
+    pub column: usize,
+    /// The text of the source lines covered by the diagnostic.
+    ///
+    /// Long spans are truncated to their first few lines.
+    pub source_lines: String,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    rendered: String,
+}
+
+impl DetachedDiagnostic {
+    pub(crate) fn new(
+        err: &Diagnostic,
+        source: &Source,
+        include_chain: &[(&Source, usize)],
+    ) -> Self {
+        let range = err.span();
+        let (line, column) = source.line_col_for_offset(range.start);
+        let (last_line, _) = source.line_col_for_offset(range.end.max(range.start + 1) - 1);
+        let last_line = last_line.min(line + MAX_DETACHED_LINES - 1);
+        let source_lines = (line..=last_line)
+            .map(|line| {
+                let offset = source.offset_for_line_number(line);
+                source.line_containing_offset(offset).1
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut rendered = String::new();
+        crate::util::highlighting::write_diagnostic(
+            &mut rendered,
+            err,
+            source,
+            include_chain,
+            None,
+            false,
+        );
+        DetachedDiagnostic {
+            level: err.level,
+            code: err.code,
+            message: err.message.text.clone(),
+            path: Path::new(source.path()).display().to_string(),
+            range,
+            line,
+            column,
+            source_lines,
+            rendered,
+        }
+    }
+
+    /// `true` if this diagnostic is an error
+    pub fn is_error(&self) -> bool {
+        matches!(self.level, Level::Error)
+    }
+}
+
+impl std::fmt::Display for DetachedDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.rendered)
+    }
 }
 
 impl Span {
+    fn new(range: Range<usize>) -> Self {
+        Span {
+            start: range.start.try_into().unwrap(),
+            end: range.end.try_into().unwrap(),
+        }
+    }
+
     /// Convert this span to a `Range<usize>`
     pub fn range(&self) -> Range<usize> {
         self.start as usize..self.end as usize
     }
 }
 
+impl SuggestedEdit {
+    /// Apply a set of edits to some source text, returning the new text.
+    ///
+    /// All edits are expected to refer to positions in `text`. Edits are
+    /// applied in order of position; an edit that overlaps one that has already
+    /// been accepted, or whose span is not valid for `text`, is skipped.
+    /// Identical duplicate edits are applied once.
+    ///
+    /// Returns the edited text and the number of edits that were applied.
+    pub fn apply_all<'a>(
+        text: &str,
+        edits: impl IntoIterator<Item = &'a SuggestedEdit>,
+    ) -> (String, usize) {
+        let mut edits = edits.into_iter().collect::<Vec<_>>();
+        edits.sort_by_key(|edit| (edit.span.start, edit.span.end));
+        edits.dedup();
+
+        let mut result = String::with_capacity(text.len());
+        let mut pos = 0;
+        let mut applied = 0;
+        let mut last_insertion = None;
+        for edit in edits {
+            let range = edit.span.range();
+            let is_valid = range.start >= pos
+                && range.start <= range.end
+                && text.is_char_boundary(range.start)
+                && text.is_char_boundary(range.end);
+            // two different insertions at the same position have an ambiguous order
+            let is_ambiguous = range.is_empty() && last_insertion == Some(range.start);
+            if !is_valid || is_ambiguous {
+                continue;
+            }
+            if range.is_empty() {
+                last_insertion = Some(range.start);
+            }
+            result.push_str(&text[pos..range.start]);
+            result.push_str(&edit.replacement);
+            pos = range.end;
+            applied += 1;
+        }
+        result.push_str(&text[pos..]);
+        (result, applied)
+    }
+}
+
 impl Diagnostic {
     /// Create a new diagnostic
     pub fn new(
@@ -58,16 +360,32 @@ impl Diagnostic {
         Diagnostic {
             message: Message {
                 text: message.into(),
-                span: Span {
-                    start: range.start.try_into().unwrap(),
-                    end: range.end.try_into().unwrap(),
-                },
+                span: Span::new(range),
                 file,
             },
             level,
+            code: None,
+            edit: None,
         }
     }
 
+    /// Set the code identifying the kind of this diagnostic.
+    pub fn with_code(mut self, code: DiagnosticCode) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    /// Attach a suggested edit to this diagnostic.
+    ///
+    /// The range is in the same file as the diagnostic.
+    pub fn with_edit(mut self, range: Range<usize>, replacement: impl Into<String>) -> Self {
+        self.edit = Some(SuggestedEdit {
+            span: Span::new(range),
+            replacement: replacement.into(),
+        });
+        self
+    }
+
     /// Create a new error, at the provided location
     pub fn error(file: FileId, span: Range<usize>, message: impl Into<String>) -> Self {
         Diagnostic::new(Level::Error, file, span, message)
@@ -92,4 +410,87 @@ impl Diagnostic {
     pub fn is_error(&self) -> bool {
         matches!(self.level, Level::Error)
     }
+
+    /// Move this diagnostic, and any suggested edit, by `delta` bytes.
+    pub(crate) fn shift(&mut self, delta: isize) {
+        let shift = |span: &mut Span| {
+            let range = span.range();
+            *span = Span::new(
+                range.start.saturating_add_signed(delta)..range.end.saturating_add_signed(delta),
+            );
+        };
+        shift(&mut self.message.span);
+        if let Some(edit) = self.edit.as_mut() {
+            shift(&mut edit.span);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edit(range: Range<usize>, replacement: &str) -> SuggestedEdit {
+        SuggestedEdit {
+            span: Span::new(range),
+            replacement: replacement.into(),
+        }
+    }
+
+    #[test]
+    fn apply_edits() {
+        let text = "sub a by b\nsub c by d\n";
+        let edits = [edit(21..21, ";"), edit(10..10, ";"), edit(4..5, "x")];
+        let (result, applied) = SuggestedEdit::apply_all(text, &edits);
+        assert_eq!(applied, 3);
+        assert_eq!(result, "sub x by b;\nsub c by d;\n");
+    }
+
+    #[test]
+    fn apply_edits_skips_bad_edits() {
+        let text = "sub a by b";
+        let edits = [
+            // overlaps the previous edit
+            edit(0..5, "pos a"),
+            edit(4..10, "b by a"),
+            // duplicate
+            edit(10..10, ";"),
+            edit(10..10, ";"),
+            // ambiguous insertion
+            edit(10..10, "#"),
+            // out of bounds
+            edit(12..14, "oops"),
+        ];
+        let (result, applied) = SuggestedEdit::apply_all(text, &edits);
+        assert_eq!(applied, 2);
+        assert_eq!(result, "pos a by b;");
+    }
+
+    #[test]
+    fn detached_diagnostic() {
+        let text = "languagesystem DFLT dflt;\nfeature liga {\n    sub f i by f_i\n} liga;\n";
+        let detached = {
+            let source = Source::new("test.fea", text.into());
+            let start = text.find("sub").unwrap();
+            let err = Diagnostic::error(source.id(), start..start + 14, "Expected ';'");
+            DetachedDiagnostic::new(&err, &source, &[])
+        };
+        assert_eq!(detached.line, 3);
+        assert_eq!(detached.column, 4);
+        assert_eq!(detached.path, "test.fea");
+        assert_eq!(detached.source_lines, "    sub f i by f_i");
+        let rendered = detached.to_string();
+        assert!(rendered.starts_with("error: Expected ';'\nin test.fea at 3:4\n"));
+        assert!(rendered.contains("sub f i by f_i"));
+
+        // a span covering several lines keeps each of them
+        let source = Source::new("test.fea", text.into());
+        let start = text.find("feature").unwrap();
+        let err = Diagnostic::warning(source.id(), start..text.len() - 1, "empty");
+        let detached = DetachedDiagnostic::new(&err, &source, &[]);
+        assert_eq!(
+            detached.source_lines,
+            "feature liga {\n    sub f i by f_i\n} liga;"
+        );
+    }
 }
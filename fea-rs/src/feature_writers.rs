@@ -0,0 +1,578 @@
+//! Generating kern and mark features from font source data
+//!
+//! Font sources such as UFOs store kerning and anchors as data, and the
+//! `kern`, `mark` and `mkmk` features that use them are generated when the
+//! font is built. The writers in this module are given this data
+//! programmatically, and [`merge_features`] combines the features they
+//! generate with the FEA written by the user.
+//!
+//! For each generated feature:
+//!
+//! - if the source contains a block for that feature with an insertion
+//!   marker (a comment reading `# Automatic Code`), the generated statements
+//!   are inserted after the marker;
+//! - otherwise, if the source already contains a block for that feature, the
+//!   generated statements are dropped, and the user's feature is used as is;
+//! - otherwise, a new feature block is appended to the source.
+//!
+//! Any definitions needed by the generated features, such as glyph and mark
+//! classes, are inserted before the first feature or lookup block in the source.
+//!
+//! Only the root source is inspected; markers in included files are ignored.
+
+use std::{collections::BTreeMap, fmt::Write, ops::Range};
+
+use write_fonts::types::Tag;
+
+use crate::{
+    decompile::{escape_glyph_name, fea_tag},
+    parse,
+    typed::{self, AstNode},
+    GlyphName, Kind, Node,
+};
+
+const INDENT: &str = "    ";
+const KERN: Tag = Tag::new(b"kern");
+const MARK: Tag = Tag::new(b"mark");
+const MKMK: Tag = Tag::new(b"mkmk");
+
+/// A source of generated features.
+pub trait FeatureWriter {
+    /// Top-level definitions, such as glyph or mark classes, used by the features.
+    ///
+    /// These are only written if at least one of the features is written.
+    fn definitions(&self) -> String {
+        String::new()
+    }
+
+    /// The tag and statements of each generated feature.
+    ///
+    /// Features without any statements are ignored.
+    fn features(&self) -> Vec<(Tag, String)>;
+}
+
+/// Merge the features generated by `writers` into the FEA source `fea`.
+///
+/// See the [module docs](self) for how generated features are placed.
+pub fn merge_features(fea: &str, writers: &[&dyn FeatureWriter]) -> String {
+    let (root, _) = parse::parse_string(fea);
+    let blocks = FeatureBlocks::new(&root);
+
+    // (position, text); insertions at the same position keep their order
+    let mut insertions = Vec::new();
+    let mut appended = String::new();
+    for writer in writers {
+        let mut uses_definitions = false;
+        for (tag, statements) in writer.features() {
+            if statements.is_empty() {
+                continue;
+            }
+            match blocks.features.get(&tag) {
+                Some(Some(marker)) => {
+                    let mut text = String::new();
+                    for line in statements.lines() {
+                        write!(text, "\n{}{line}", marker.indent).unwrap();
+                    }
+                    insertions.push((marker.pos, text));
+                }
+                Some(None) => continue,
+                None => {
+                    let tag = fea_tag(tag);
+                    writeln!(appended, "\nfeature {tag} {{").unwrap();
+                    for line in statements.lines() {
+                        writeln!(appended, "{INDENT}{line}").unwrap();
+                    }
+                    writeln!(appended, "}} {tag};").unwrap();
+                }
+            }
+            uses_definitions = true;
+        }
+        let definitions = writer.definitions();
+        if uses_definitions && !definitions.is_empty() {
+            insertions.push((blocks.definitions_pos, definitions));
+        }
+    }
+
+    let mut result = String::with_capacity(fea.len() + appended.len());
+    let mut pos = 0;
+    insertions.sort_by_key(|(pos, _)| *pos);
+    for (insert_pos, text) in insertions {
+        result.push_str(&fea[pos..insert_pos]);
+        result.push_str(&text);
+        pos = insert_pos;
+    }
+    result.push_str(&fea[pos..]);
+    if !appended.is_empty() && !result.is_empty() && !result.ends_with('\n') {
+        result.push('\n');
+    }
+    result.push_str(&appended);
+    result
+}
+
+/// The features defined at the top level of a source.
+struct FeatureBlocks {
+    /// The insertion marker in each feature, if there is one
+    features: BTreeMap<Tag, Option<Marker>>,
+    /// Where definitions should be inserted
+    definitions_pos: usize,
+}
+
+/// The position after an insertion marker, and the indentation of its line.
+struct Marker {
+    pos: usize,
+    indent: String,
+}
+
+impl FeatureBlocks {
+    fn new(root: &Node) -> Self {
+        let mut features = BTreeMap::new();
+        let mut definitions_pos = None;
+        for child in root.iter_children() {
+            if matches!(child.kind(), Kind::FeatureNode | Kind::LookupBlockNode) {
+                definitions_pos.get_or_insert(child.range().start);
+            }
+            let Some(feature) = child.as_node().filter(|n| n.kind() == Kind::FeatureNode) else {
+                continue;
+            };
+            let Some(Ok(tag)) = feature
+                .iter_children()
+                .find_map(typed::Tag::cast)
+                .map(|tag| tag.parse())
+            else {
+                continue;
+            };
+            let marker = feature
+                .iter_tokens()
+                .find(|token| token.kind == Kind::Comment && is_marker(&token.text))
+                .map(|token| Marker {
+                    pos: token.range().end,
+                    indent: line_indent(root, token.range()),
+                });
+            let entry = features.entry(tag).or_insert(None);
+            if entry.is_none() {
+                *entry = marker;
+            }
+        }
+        FeatureBlocks {
+            features,
+            definitions_pos: definitions_pos.unwrap_or(root.range().end),
+        }
+    }
+}
+
+/// Whether a comment is an insertion marker, ignoring case and whitespace.
+fn is_marker(comment: &str) -> bool {
+    comment
+        .trim_start_matches('#')
+        .trim()
+        .eq_ignore_ascii_case("automatic code")
+}
+
+/// The whitespace before `range` on its line.
+fn line_indent(root: &Node, range: Range<usize>) -> String {
+    let mut indent = String::new();
+    for token in root.iter_tokens() {
+        if token.range().end > range.start {
+            break;
+        }
+        if token.kind == Kind::Whitespace {
+            let text = token.text.as_str();
+            indent = text[text.rfind('\n').map(|i| i + 1).unwrap_or(0)..].to_string();
+        } else {
+            indent.clear();
+        }
+    }
+    indent
+}
+
+/// A name for a generated class, using only characters that are valid in FEA.
+fn class_name(prefix: &str, name: &str) -> String {
+    let name = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect::<String>();
+    format!("@{prefix}{name}")
+}
+
+/// One side of a kerning pair.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KernSide {
+    /// A single glyph
+    Glyph(GlyphName),
+    /// A group added with [`KernFeatureWriter::add_group`]
+    Group(String),
+}
+
+/// Generates the `kern` feature from kerning pairs.
+///
+/// Pairs of glyphs are written first, followed by pairs of a glyph and a group
+/// (as `enum pos` rules), and then pairs of groups, so that more specific
+/// pairs take precedence. Pairs referencing groups that have not been added
+/// are ignored.
+#[derive(Clone, Debug, Default)]
+pub struct KernFeatureWriter {
+    groups: BTreeMap<String, Vec<GlyphName>>,
+    pairs: Vec<(KernSide, KernSide, i16)>,
+}
+
+impl KernFeatureWriter {
+    /// Add a named group of glyphs, which can be referenced by pairs.
+    pub fn add_group(
+        &mut self,
+        name: impl Into<String>,
+        glyphs: impl IntoIterator<Item = GlyphName>,
+    ) {
+        self.groups
+            .insert(name.into(), glyphs.into_iter().collect());
+    }
+
+    /// Add a kerning pair, adjusting the advance of the first side by `value`.
+    pub fn add_pair(&mut self, first: KernSide, second: KernSide, value: i16) {
+        self.pairs.push((first, second, value));
+    }
+
+    fn side(&self, side: &KernSide) -> Option<String> {
+        match side {
+            KernSide::Glyph(name) => Some(escape_glyph_name(name).into_owned()),
+            KernSide::Group(name) => self
+                .groups
+                .contains_key(name)
+                .then(|| class_name("kern.", name)),
+        }
+    }
+}
+
+impl FeatureWriter for KernFeatureWriter {
+    fn definitions(&self) -> String {
+        let mut out = String::new();
+        for (name, glyphs) in &self.groups {
+            let used = self.pairs.iter().any(|(first, second, _)| {
+                [first, second]
+                    .into_iter()
+                    .any(|side| matches!(side, KernSide::Group(group) if group == name))
+            });
+            if used {
+                let glyphs = glyphs
+                    .iter()
+                    .map(|name| escape_glyph_name(name))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                writeln!(out, "{} = [{glyphs}];", class_name("kern.", name)).unwrap();
+            }
+        }
+        out
+    }
+
+    fn features(&self) -> Vec<(Tag, String)> {
+        // glyph pairs, then pairs with one group, then group pairs
+        let mut rules: [Vec<String>; 3] = Default::default();
+        for (first, second, value) in &self.pairs {
+            let (Some(first_fea), Some(second_fea)) = (self.side(first), self.side(second)) else {
+                continue;
+            };
+            let n_groups = [first, second]
+                .into_iter()
+                .filter(|side| matches!(side, KernSide::Group(_)))
+                .count();
+            let keyword = if n_groups == 1 { "enum pos" } else { "pos" };
+            rules[n_groups].push(format!("{keyword} {first_fea} {second_fea} {value};"));
+        }
+        let mut out = String::new();
+        for rule in rules.iter().flatten() {
+            writeln!(out, "{rule}").unwrap();
+        }
+        vec![(KERN, out)]
+    }
+}
+
+/// Generates the `mark` and `mkmk` features from anchors.
+///
+/// Glyphs with a mark anchor (the `_top` anchor of a UFO glyph, for instance)
+/// are marks; each mark anchor name defines a mark class. Base anchors with
+/// the same name on other glyphs are written as mark-to-base rules in the
+/// `mark` feature, and base anchors on marks are written as mark-to-mark
+/// rules in the `mkmk` feature.
+#[derive(Clone, Debug, Default)]
+pub struct MarkFeatureWriter {
+    marks: BTreeMap<String, Vec<(GlyphName, i16, i16)>>,
+    bases: BTreeMap<String, Vec<(GlyphName, i16, i16)>>,
+}
+
+impl MarkFeatureWriter {
+    /// Add the anchor by which the mark `glyph` attaches to anchors called `name`.
+    pub fn add_mark_anchor(&mut self, glyph: GlyphName, name: impl Into<String>, x: i16, y: i16) {
+        self.marks
+            .entry(name.into())
+            .or_default()
+            .push((glyph, x, y));
+    }
+
+    /// Add an anchor called `name` on `glyph`, to which marks can attach.
+    pub fn add_base_anchor(&mut self, glyph: GlyphName, name: impl Into<String>, x: i16, y: i16) {
+        self.bases
+            .entry(name.into())
+            .or_default()
+            .push((glyph, x, y));
+    }
+
+    fn is_mark(&self, name: &GlyphName) -> bool {
+        self.marks
+            .values()
+            .flatten()
+            .any(|(glyph, _, _)| glyph == name)
+    }
+
+    fn write_lookups(&self, out: &mut String, to_marks: bool) {
+        for (name, marks) in &self.marks {
+            let bases = self
+                .bases
+                .get(name)
+                .into_iter()
+                .flatten()
+                .filter(|(glyph, _, _)| self.is_mark(glyph) == to_marks)
+                .collect::<Vec<_>>();
+            if bases.is_empty() {
+                continue;
+            }
+            let (lookup, keyword) = if to_marks {
+                (class_name("mark2mark_", name), "mark")
+            } else {
+                (class_name("mark2base_", name), "base")
+            };
+            // lookup names are not prefixed with '@'
+            let lookup = &lookup[1..];
+            let mark_class = class_name("MC_", name);
+            writeln!(out, "lookup {lookup} {{").unwrap();
+            if to_marks {
+                let mut filter_set = marks.iter().map(|(glyph, _, _)| glyph).collect::<Vec<_>>();
+                for (glyph, _, _) in &bases {
+                    if !filter_set.contains(&glyph) {
+                        filter_set.push(glyph);
+                    }
+                }
+                let filter_set = filter_set
+                    .into_iter()
+                    .map(|name| escape_glyph_name(name))
+                    .collect::<Vec<_>>();
+                writeln!(
+                    out,
+                    "{INDENT}lookupflag UseMarkFilteringSet [{}];",
+                    filter_set.join(" ")
+                )
+                .unwrap();
+            }
+            for (base, x, y) in bases {
+                writeln!(
+                    out,
+                    "{INDENT}pos {keyword} {} <anchor {x} {y}> mark {mark_class};",
+                    escape_glyph_name(base)
+                )
+                .unwrap();
+            }
+            writeln!(out, "}} {lookup};").unwrap();
+        }
+    }
+}
+
+impl FeatureWriter for MarkFeatureWriter {
+    fn definitions(&self) -> String {
+        let mut out = String::new();
+        for (name, marks) in &self.marks {
+            let mark_class = class_name("MC_", name);
+            for (mark, x, y) in marks {
+                writeln!(
+                    out,
+                    "markClass {} <anchor {x} {y}> {mark_class};",
+                    escape_glyph_name(mark)
+                )
+                .unwrap();
+            }
+        }
+        out
+    }
+
+    fn features(&self) -> Vec<(Tag, String)> {
+        let mut mark = String::new();
+        self.write_lookups(&mut mark, false);
+        let mut mkmk = String::new();
+        self.write_lookups(&mut mkmk, true);
+        vec![(MARK, mark), (MKMK, mkmk)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsStr, sync::Arc};
+
+    use super::*;
+    use crate::{compile::Compilation, Compiler, GlyphMap};
+
+    fn glyph_map() -> GlyphMap {
+        [".notdef", "a", "b", "e", "o", "v", "acute", "grave", "a-b"]
+            .into_iter()
+            .map(GlyphName::new)
+            .collect()
+    }
+
+    fn compile(fea: String) -> Compilation {
+        let glyph_map = glyph_map();
+        Compiler::new("test.fea", &glyph_map)
+            .with_resolver(move |_: &OsStr| Ok(Arc::<str>::from(fea.as_str())))
+            .compile()
+            .unwrap()
+    }
+
+    fn kern_writer() -> KernFeatureWriter {
+        let mut kern = KernFeatureWriter::default();
+        kern.add_group("public.kern1.O", ["e", "o"].map(GlyphName::new));
+        kern.add_group("public.kern2.V", ["v"].map(GlyphName::new));
+        kern.add_pair(
+            KernSide::Group("public.kern1.O".into()),
+            KernSide::Group("public.kern2.V".into()),
+            -20,
+        );
+        kern.add_pair(
+            KernSide::Glyph("o".into()),
+            KernSide::Group("public.kern2.V".into()),
+            -30,
+        );
+        kern.add_pair(
+            KernSide::Glyph("a".into()),
+            KernSide::Glyph("a-b".into()),
+            5,
+        );
+        kern.add_pair(
+            KernSide::Glyph("a".into()),
+            KernSide::Group("missing".into()),
+            5,
+        );
+        kern
+    }
+
+    fn mark_writer() -> MarkFeatureWriter {
+        let mut mark = MarkFeatureWriter::default();
+        mark.add_mark_anchor("acute".into(), "top", 100, 500);
+        mark.add_mark_anchor("grave".into(), "top", 120, 500);
+        mark.add_base_anchor("a".into(), "top", 250, 500);
+        mark.add_base_anchor("acute".into(), "top", 100, 700);
+        mark
+    }
+
+    #[test]
+    fn kern_rules() {
+        let kern = kern_writer();
+        assert_eq!(
+            kern.definitions(),
+            "@kern.public.kern1.O = [e o];\n@kern.public.kern2.V = [v];\n"
+        );
+        assert_eq!(
+            kern.features(),
+            [(
+                KERN,
+                "pos a \\a-b 5;\n\
+                enum pos o @kern.public.kern2.V -30;\n\
+                pos @kern.public.kern1.O @kern.public.kern2.V -20;\n"
+                    .to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn mark_rules() {
+        let fea = merge_features("languagesystem DFLT dflt;\n", &[&mark_writer()]);
+        assert_eq!(
+            fea,
+            "languagesystem DFLT dflt;\n\
+            markClass acute <anchor 100 500> @MC_top;\n\
+            markClass grave <anchor 120 500> @MC_top;\n\
+            \n\
+            feature mark {\n\
+            \x20   lookup mark2base_top {\n\
+            \x20       pos base a <anchor 250 500> mark @MC_top;\n\
+            \x20   } mark2base_top;\n\
+            } mark;\n\
+            \n\
+            feature mkmk {\n\
+            \x20   lookup mark2mark_top {\n\
+            \x20       lookupflag UseMarkFilteringSet [acute grave];\n\
+            \x20       pos mark acute <anchor 100 700> mark @MC_top;\n\
+            \x20   } mark2mark_top;\n\
+            } mkmk;\n"
+        );
+        let compilation = compile(fea);
+        assert_eq!(compilation.gpos.unwrap().lookup_list.lookups.len(), 2);
+        let gdef = compilation.gdef.unwrap();
+        assert!(gdef.mark_glyph_sets_def.is_some());
+    }
+
+    #[test]
+    fn insert_at_marker() {
+        let fea = "\
+languagesystem DFLT dflt;
+feature kern {
+    pos b b -5;
+    # Automatic Code
+} kern;
+";
+        let merged = merge_features(fea, &[&kern_writer()]);
+        assert_eq!(
+            merged,
+            "\
+languagesystem DFLT dflt;
+@kern.public.kern1.O = [e o];
+@kern.public.kern2.V = [v];
+feature kern {
+    pos b b -5;
+    # Automatic Code
+    pos a \\a-b 5;
+    enum pos o @kern.public.kern2.V -30;
+    pos @kern.public.kern1.O @kern.public.kern2.V -20;
+} kern;
+"
+        );
+        let gpos = compile(merged).gpos.unwrap();
+        assert_eq!(gpos.feature_list.feature_records.len(), 1);
+    }
+
+    #[test]
+    fn existing_features_are_kept() {
+        let fea = "\
+markClass grave <anchor 0 0> @USER;
+feature kern {
+    pos b b -5;
+} kern;
+";
+        // kern is skipped, along with its definitions; mark is appended
+        let merged = merge_features(fea, &[&kern_writer(), &mark_writer()]);
+        assert!(merged.starts_with(
+            "\
+markClass grave <anchor 0 0> @USER;
+markClass acute <anchor 100 500> @MC_top;
+markClass grave <anchor 120 500> @MC_top;
+feature kern {
+    pos b b -5;
+} kern;
+"
+        ));
+        assert!(!merged.contains("@kern."));
+        assert!(merged.contains("feature mark {"));
+        assert!(merged.contains("feature mkmk {"));
+    }
+
+    #[test]
+    fn empty_features_are_not_written() {
+        let fea = "feature liga { sub a by b; } liga;";
+        let merged = merge_features(fea, &[&KernFeatureWriter::default()]);
+        assert_eq!(merged, fea);
+    }
+
+    #[test]
+    fn insertion_marker() {
+        assert!(is_marker("# Automatic Code"));
+        assert!(is_marker("#automatic code "));
+        assert!(!is_marker("# Automatic Code End"));
+    }
+}
@@ -0,0 +1,514 @@
+//! Formatting FEA source.
+//!
+//! The formatter works on the token stream of the root source: whitespace is
+//! regenerated, and everything else (including comments) is kept. Statements
+//! are placed on their own lines and indented by block depth; within a
+//! statement, tokens are separated by single spaces, except where the author
+//! broke a long statement over multiple lines, in which case those line breaks
+//! are kept and the continuation lines are indented.
+//!
+//! Anonymous blocks and include statements are copied verbatim.
+//!
+//! The indentation, the placement of opening braces, and the wrapping of long
+//! glyph classes can be configured with [`FormatOptions`].
+
+use crate::{parse::FileId, Kind, NodeOrToken, ParseTree};
+
+/// The indentation used for each level of nesting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Indent {
+    /// Indent with this many spaces
+    Spaces(usize),
+    /// Indent with a single tab
+    Tab,
+}
+
+impl Default for Indent {
+    fn default() -> Self {
+        Indent::Spaces(4)
+    }
+}
+
+impl Indent {
+    fn to_str(self) -> String {
+        match self {
+            Indent::Spaces(n) => " ".repeat(n),
+            Indent::Tab => "\t".into(),
+        }
+    }
+}
+
+/// Where the opening brace of a block is placed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BraceStyle {
+    /// At the end of the line that opens the block: `feature liga {`
+    #[default]
+    SameLine,
+    /// On its own line, at the indentation of the block's statement
+    NextLine,
+}
+
+/// Options for [`format_with_options`].
+///
+/// ```
+/// # use fea_rs::format::{BraceStyle, FormatOptions, Indent};
+/// let options = FormatOptions::new()
+///     .with_indent(Indent::Spaces(2))
+///     .with_brace_style(BraceStyle::NextLine)
+///     .with_max_width(Some(80));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FormatOptions {
+    indent: Indent,
+    brace_style: BraceStyle,
+    max_width: Option<usize>,
+}
+
+impl FormatOptions {
+    /// The default options: four space indentation, opening braces on the
+    /// same line, and no wrapping.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Set the indentation used for each level of nesting.
+    pub fn with_indent(mut self, indent: Indent) -> Self {
+        self.indent = indent;
+        self
+    }
+
+    /// Set where opening braces are placed.
+    pub fn with_brace_style(mut self, brace_style: BraceStyle) -> Self {
+        self.brace_style = brace_style;
+        self
+    }
+
+    /// Set the maximum width of a line, in characters.
+    ///
+    /// Glyph classes that would extend past this width are wrapped onto
+    /// continuation lines. Other statements are never wrapped, and a line
+    /// may still be longer if it contains a very long token.
+    pub fn with_max_width(mut self, max_width: Option<usize>) -> Self {
+        self.max_width = max_width;
+        self
+    }
+}
+
+/// Format the root source of a parse tree.
+///
+/// The result uses a canonical style: each statement is on its own line,
+/// indented by four spaces per level of nesting in `feature`, `lookup` and
+/// `table` blocks, and spacing within statements (such as in glyph classes)
+/// is normalized. Comments and single blank lines between statements are
+/// preserved.
+///
+/// Only the root source is formatted; include statements are kept as written,
+/// and the included files are not modified.
+///
+/// To customize the style, use [`format_with_options`].
+pub fn format(tree: &ParseTree) -> String {
+    format_with_options(tree, &FormatOptions::default())
+}
+
+/// Format the root source of a parse tree, using the provided options.
+///
+/// See [`format`] for details.
+pub fn format_with_options(tree: &ParseTree, options: &FormatOptions) -> String {
+    let root_id = tree.root_id();
+    let text = tree
+        .get_source(root_id)
+        .expect("root source is in tree")
+        .text();
+    let mut items = Vec::new();
+    let mut pos = 0;
+    collect_items(tree, root_id, tree.root(), &mut pos, &mut items);
+    if pos < text.len() {
+        items.push(Item::Verbatim(pos..text.len()));
+    }
+
+    let mut formatter = Formatter::new(options);
+    for item in items {
+        match item {
+            Item::Token(kind, range) => formatter.token(kind, &text[range]),
+            Item::Verbatim(range) => formatter.verbatim(text[range].trim()),
+        }
+    }
+    formatter.finish()
+}
+
+/// A piece of the root source, with its range in that source.
+enum Item {
+    Token(Kind, std::ops::Range<usize>),
+    // a statement that is copied without formatting
+    Verbatim(std::ops::Range<usize>),
+}
+
+/// Collect the items in the root source, in order.
+///
+/// Tokens from included files are skipped; the include statements themselves
+/// are not in the tree, and are found as the gaps between root tokens.
+fn collect_items(
+    tree: &ParseTree,
+    root_id: FileId,
+    node: &crate::Node,
+    pos: &mut usize,
+    items: &mut Vec<Item>,
+) {
+    for child in node.iter_children() {
+        let range = child.range();
+        if range.is_empty() {
+            continue;
+        }
+        let is_anon = child.kind() == Kind::AnonBlockNode;
+        if let (NodeOrToken::Node(node), false) = (child, is_anon) {
+            collect_items(tree, root_id, node, pos, items);
+            continue;
+        }
+        let (file, local) = tree.source_map().resolve_range(range);
+        if file != root_id || local.start < *pos {
+            continue;
+        }
+        if local.start > *pos {
+            items.push(Item::Verbatim(*pos..local.start));
+        }
+        *pos = local.end;
+        if is_anon {
+            items.push(Item::Verbatim(local));
+        } else {
+            items.push(Item::Token(child.kind(), local));
+        }
+    }
+}
+
+struct Formatter<'a> {
+    options: &'a FormatOptions,
+    indent: String,
+    out: String,
+    depth: usize,
+    paren_depth: usize,
+    in_glyph_class: bool,
+    // the last token written, excluding comments
+    prev: Option<Kind>,
+    // true if the next token continues the current statement
+    in_statement: bool,
+    // set after a comment, which runs to the end of the line
+    needs_newline: bool,
+    // the number of newlines in the whitespace since the last token
+    pending_newlines: usize,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(options: &'a FormatOptions) -> Self {
+        Formatter {
+            options,
+            indent: options.indent.to_str(),
+            out: String::new(),
+            depth: 0,
+            paren_depth: 0,
+            in_glyph_class: false,
+            prev: None,
+            in_statement: false,
+            needs_newline: false,
+            pending_newlines: 0,
+        }
+    }
+
+    fn token(&mut self, kind: Kind, text: &str) {
+        match kind {
+            Kind::Whitespace => {
+                self.pending_newlines += text.matches('\n').count();
+                return;
+            }
+            Kind::Comment => {
+                self.comment(text);
+                return;
+            }
+            Kind::RBrace => self.depth = self.depth.saturating_sub(1),
+            _ => (),
+        }
+
+        let brace_on_own_line =
+            kind == Kind::LBrace && self.options.brace_style == BraceStyle::NextLine;
+        let starts_line = if kind == Kind::LBrace && self.in_statement && !self.needs_newline {
+            brace_on_own_line
+        } else {
+            self.out.is_empty()
+                || self.needs_newline
+                || !self.in_statement
+                || self.pending_newlines > 0
+                || kind == Kind::RBrace
+                || self.should_wrap(kind, text)
+        };
+        if starts_line {
+            self.newline(kind == Kind::RBrace || brace_on_own_line);
+        } else if self.wants_space(kind) {
+            self.out.push(' ');
+        }
+        self.out.push_str(text);
+        self.prev = Some(kind);
+
+        match kind {
+            Kind::LBrace => {
+                self.depth += 1;
+                self.in_statement = false;
+            }
+            Kind::Semi => self.in_statement = false,
+            Kind::LSquare => {
+                self.in_glyph_class = true;
+                self.in_statement = true;
+            }
+            Kind::RSquare => {
+                self.in_glyph_class = false;
+                self.in_statement = true;
+            }
+            Kind::LParen => {
+                self.paren_depth += 1;
+                self.in_statement = true;
+            }
+            Kind::RParen => {
+                self.paren_depth = self.paren_depth.saturating_sub(1);
+                self.in_statement = true;
+            }
+            _ => self.in_statement = true,
+        }
+    }
+
+    fn comment(&mut self, text: &str) {
+        if self.out.is_empty() || self.needs_newline || self.pending_newlines > 0 {
+            self.newline(false);
+        } else {
+            self.out.push(' ');
+        }
+        self.out.push_str(text.trim_end());
+        self.needs_newline = true;
+    }
+
+    /// Write a statement that is not formatted.
+    fn verbatim(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        self.newline(false);
+        self.out.push_str(text);
+        self.prev = Some(Kind::Semi);
+        self.in_statement = false;
+    }
+
+    /// Start a new line, preserving a single blank line from the source.
+    ///
+    /// If `brace` is true the line starts with a brace, which is never
+    /// preceded by a blank line or indented as a continuation.
+    fn newline(&mut self, brace: bool) {
+        if !self.out.is_empty() {
+            let after_open_brace = self.prev == Some(Kind::LBrace) && !self.needs_newline;
+            if self.pending_newlines > 1 && !after_open_brace && !brace {
+                self.out.push('\n');
+            }
+            self.out.push('\n');
+        }
+        for _ in 0..self.depth {
+            self.out.push_str(&self.indent);
+        }
+        // continuation lines are indented an extra level
+        if self.in_statement && !brace {
+            self.out.push_str(&self.indent);
+        }
+        self.pending_newlines = 0;
+        self.needs_newline = false;
+    }
+
+    /// `true` if the next token is in a glyph class, and would not fit on
+    /// the current line.
+    fn should_wrap(&self, kind: Kind, text: &str) -> bool {
+        let Some(max_width) = self.options.max_width else {
+            return false;
+        };
+        if !self.in_glyph_class || !self.wants_space(kind) {
+            return false;
+        }
+        let line = self.out.rsplit('\n').next().unwrap_or_default();
+        let width = |s: &str| {
+            s.chars()
+                .map(|c| if c == '\t' { 4 } else { 1 })
+                .sum::<usize>()
+        };
+        width(line) + 1 + width(text) > max_width
+    }
+
+    fn wants_space(&self, next: Kind) -> bool {
+        let Some(prev) = self.prev else {
+            return false;
+        };
+        if matches!(
+            prev,
+            Kind::LSquare | Kind::LParen | Kind::LAngle | Kind::Backslash
+        ) {
+            return false;
+        }
+        if matches!(
+            next,
+            Kind::RSquare
+                | Kind::RParen
+                | Kind::RAngle
+                | Kind::Semi
+                | Kind::Comma
+                | Kind::SingleQuote
+                | Kind::NumberSuffix
+        ) {
+            return false;
+        }
+        if next == Kind::LParen && prev == Kind::IncludeKw {
+            return false;
+        }
+        // in variable metrics, such as '(wght=200:-10 wght=900:-20)'
+        let in_parens = self.paren_depth > 0;
+        if in_parens
+            && (matches!(prev, Kind::Eq | Kind::Colon) || matches!(next, Kind::Eq | Kind::Colon))
+        {
+            return false;
+        }
+        true
+    }
+
+    fn finish(mut self) -> String {
+        let len = self.out.trim_end().len();
+        self.out.truncate(len);
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, sync::Arc};
+
+    use super::*;
+    use crate::{parse, util::ttx as test_utils};
+
+    fn format_str(text: &str) -> String {
+        format_str_with_options(text, &FormatOptions::default())
+    }
+
+    fn format_str_with_options(text: &str, options: &FormatOptions) -> String {
+        let text: Arc<str> = text.into();
+        let (tree, _) = parse::parse_root("test.fea".into(), None, move |_: &std::ffi::OsStr| {
+            Ok(text.clone())
+        })
+        .unwrap();
+        format_with_options(&tree, options)
+    }
+
+    fn significant_tokens(text: &str) -> Vec<String> {
+        parse::tokenize(text)
+            .filter(|tok| tok.kind != Kind::Whitespace)
+            .map(|tok| tok.text.trim_end().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn indentation() {
+        let fea = "feature liga {\nlookup a {\nsub f i by f_i;\n} a;\n} liga;\n";
+        assert_eq!(
+            format_str(fea),
+            "feature liga {\n    lookup a {\n        sub f i by f_i;\n    } a;\n} liga;\n"
+        );
+    }
+
+    #[test]
+    fn glyph_class_spacing() {
+        assert_eq!(format_str("@a=[ a  b\tc ] ;"), "@a = [a b c];\n");
+        assert_eq!(
+            format_str("feature kern {pos [ a b ]' lookup x [c -  d] < 1 2 3 4 >;} kern;"),
+            "feature kern {\n    pos [a b]' lookup x [c - d] <1 2 3 4>;\n} kern;\n"
+        );
+    }
+
+    #[test]
+    fn comments() {
+        let fea = "# header\n\n\n\nfeature liga { # trailing\n  # own line\nsub a by b; # after\n\n\nsub c by d;\n} liga;";
+        assert_eq!(
+            format_str(fea),
+            "# header\n\nfeature liga { # trailing\n    # own line\n    sub a by b; # after\n\n    sub c by d;\n} liga;\n"
+        );
+    }
+
+    #[test]
+    fn line_breaks_in_statements() {
+        let fea = "@a = [a b\n   c d];";
+        assert_eq!(format_str(fea), "@a = [a b\n    c d];\n");
+    }
+
+    #[test]
+    fn verbatim_statements() {
+        let fea = "include(other.fea);\nanon sbit {\n  raw   stuff\n} sbit;\n";
+        let text: Arc<str> = fea.into();
+        let (tree, _) = parse::parse_root(
+            "test.fea".into(),
+            None,
+            move |path: &std::ffi::OsStr| match path.to_str() {
+                Some("test.fea") => Ok(text.clone()),
+                _ => Ok("languagesystem DFLT dflt;".into()),
+            },
+        )
+        .unwrap();
+        assert_eq!(format(&tree), fea);
+    }
+
+    #[test]
+    fn indent_and_brace_style() {
+        let fea = "feature liga {\nlookup a {\nsub f i by f_i;\n} a;\n} liga;\n";
+        let options = FormatOptions::new()
+            .with_indent(Indent::Tab)
+            .with_brace_style(BraceStyle::NextLine);
+        let formatted = format_str_with_options(fea, &options);
+        assert_eq!(
+            formatted,
+            "feature liga\n{\n\tlookup a\n\t{\n\t\tsub f i by f_i;\n\t} a;\n} liga;\n"
+        );
+        assert_eq!(format_str_with_options(&formatted, &options), formatted);
+        // and back again
+        assert_eq!(format_str(&formatted), format_str(fea));
+    }
+
+    #[test]
+    fn wrap_long_glyph_classes() {
+        let fea = "feature liga {\nsub [a b c d e f g h] x' by y;\n} liga;\n";
+        let options = FormatOptions::new()
+            .with_indent(Indent::Spaces(2))
+            .with_max_width(Some(16));
+        let formatted = format_str_with_options(fea, &options);
+        assert_eq!(
+            formatted,
+            "feature liga {\n  sub [a b c d e\n    f g h] x' by y;\n} liga;\n"
+        );
+        assert_eq!(format_str_with_options(&formatted, &options), formatted);
+    }
+
+    #[test]
+    fn format_good_files() {
+        let custom = FormatOptions::new()
+            .with_indent(Indent::Tab)
+            .with_brace_style(BraceStyle::NextLine)
+            .with_max_width(Some(60));
+        for path in test_utils::iter_fea_files(Path::new("./test-data/parse-tests/good")) {
+            let text = std::fs::read_to_string(&path).unwrap();
+            for options in [FormatOptions::default(), custom.clone()] {
+                let formatted = format_str_with_options(&text, &options);
+                assert_eq!(
+                    significant_tokens(&text),
+                    significant_tokens(&formatted),
+                    "tokens changed formatting {} with {options:?}",
+                    path.display()
+                );
+                assert_eq!(
+                    formatted,
+                    format_str_with_options(&formatted, &options),
+                    "formatting is not idempotent for {} with {options:?}",
+                    path.display()
+                );
+            }
+        }
+    }
+}
@@ -7,8 +7,12 @@
 
 mod common;
 pub mod compile;
+pub mod decompile;
 mod diagnostic;
+pub mod feature_writers;
+pub mod format;
 pub mod parse;
+mod support;
 mod token_tree;
 pub mod util;
 
@@ -17,6 +21,22 @@ mod tests;
 
 pub use common::{GlyphIdent, GlyphMap, GlyphName};
 pub use compile::Compiler;
-pub use diagnostic::{Diagnostic, Level};
+pub use diagnostic::{
+    DetachedDiagnostic, Diagnostic, DiagnosticCode, Level, SuggestedEdit, UnknownDiagnosticCode,
+};
+pub use format::format;
 pub use parse::{ParseTree, TokenSet};
-pub use token_tree::{typed, Kind, Node, NodeOrToken, Token};
+pub use support::{
+    capabilities, support_matrix, version, Capabilities, SpecSection, SupportEntry, SupportLevel,
+};
+pub use token_tree::{typed, visit, Kind, Node, NodeOrToken, Token};
+
+// Clients (such as fontc) parse once and then compile on multiple threads, so
+// we want to know if any of these types stop being thread-safe.
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<ParseTree>();
+    assert_send_sync::<Node>();
+    assert_send_sync::<Diagnostic>();
+    assert_send_sync::<compile::Compilation>();
+};
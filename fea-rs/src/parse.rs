@@ -5,15 +5,19 @@
 
 mod context;
 pub(crate) mod grammar;
+mod inject;
 mod lexer;
 mod parser;
+mod reparse;
 mod source;
 mod tree;
 
 use std::{ffi::OsString, path::PathBuf, sync::Arc};
 
-pub use lexer::TokenSet;
-pub use source::{FileSystemResolver, SourceLoadError, SourceResolver};
+pub use context::{IncludeLimits, ParserOptions};
+pub use inject::{Glyphs, InsertError, InsertionPoint, StatementBuilder};
+pub use lexer::{RawToken, RawTokens, TokenSet};
+pub use source::{FileSystemResolver, MemoryResolver, SourceLoadError, SourceResolver};
 pub use tree::ParseTree;
 
 pub(crate) use context::{IncludeStatement, ParseContext};
@@ -65,8 +69,42 @@ pub fn parse_root(
     glyph_map: Option<&GlyphMap>,
     resolver: impl SourceResolver + 'static,
 ) -> Result<(ParseTree, Vec<Diagnostic>), SourceLoadError> {
-    context::ParseContext::parse(path, glyph_map, Box::new(resolver))
-        .map(|ctx| ctx.generate_parse_tree())
+    parse_root_with_options(path, glyph_map, &Default::default(), resolver)
+}
+
+/// Like [`parse_root`], but with the provided [`ParserOptions`].
+///
+/// This is useful for things like editors, which may want to limit the number
+/// of diagnostics reported for a badly broken source.
+pub fn parse_root_with_options(
+    path: OsString,
+    glyph_map: Option<&GlyphMap>,
+    options: &ParserOptions,
+    resolver: impl SourceResolver + 'static,
+) -> Result<(ParseTree, Vec<Diagnostic>), SourceLoadError> {
+    context::ParseContext::parse(
+        path,
+        glyph_map,
+        options,
+        &Default::default(),
+        Box::new(resolver),
+    )
+    .map(|ctx| ctx.generate_parse_tree())
+}
+
+/// Iterate over the raw tokens in a block of FEA, without parsing it.
+///
+/// This is much cheaper than building a parse tree, and is intended for tools
+/// that only need tokenization, such as syntax highlighters or for gathering
+/// statistics over large sources. Trivia (whitespace and comments) is included,
+/// so the text of the tokens concatenated together is always equal to the input.
+///
+/// Because no parsing is performed, the token kinds are less precise than those
+/// in a [`ParseTree`]: for instance glyph names and tags are reported as
+/// [`Kind::Ident`][crate::Kind::Ident], and a glyph name that happens to match a
+/// keyword will be reported as that keyword. Include statements are not resolved.
+pub fn tokenize(text: &str) -> RawTokens<'_> {
+    RawTokens::new(text)
 }
 
 /// Convenience method to parse a block of FEA from memory.
@@ -77,6 +115,6 @@ pub fn parse_root(
 /// The input text can be any of `&str`, `String`, or `Arc<str>`.
 pub fn parse_string(text: impl Into<Arc<str>>) -> (Node, Vec<Diagnostic>) {
     let source = source::Source::new("<parse::parse_string>", text.into());
-    let (node, errs, _) = context::parse_src(&source, None);
+    let (node, errs, _) = context::parse_src(&source, None, &Default::default());
     (node, errs)
 }
@@ -2,8 +2,9 @@
 
 use std::{
     collections::{HashMap, HashSet},
-    ffi::OsString,
+    ffi::{OsStr, OsString},
     ops::Range,
+    path::Path,
     sync::Arc,
 };
 
@@ -14,10 +15,43 @@ use crate::{
         typed::{self, AstNode as _},
         AstSink,
     },
-    Diagnostic, GlyphMap, Node,
+    Diagnostic, DiagnosticCode, GlyphMap, Node,
 };
 
-const MAX_INCLUDE_DEPTH: usize = 50;
+const DEFAULT_MAX_INCLUDE_DEPTH: usize = 50;
+
+/// Limits on the sources that can be loaded via `include` statements.
+///
+/// The spec allows implementations to limit the depth of nested includes;
+/// we also allow limiting the total number of files and the total size of
+/// all sources, which is useful for things like build servers that compile
+/// untrusted input.
+///
+/// The root source is always loaded, but counts toward the totals. An include
+/// statement that would exceed a limit is an error, and is otherwise ignored.
+///
+/// By default the depth is limited to 50, and there is no limit on the
+/// number or size of files.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IncludeLimits {
+    max_depth: usize,
+    max_files: Option<usize>,
+    max_bytes: Option<usize>,
+}
+
+/// Options that control how sources are parsed.
+///
+/// By default every diagnostic is reported. On badly broken input this can
+/// produce a very long list, which is not useful to someone reading it in an
+/// editor or a terminal; [`max_errors`][Self::max_errors] can be used to stop
+/// reporting diagnostics once a certain number of errors have been found.
+/// Parsing continues after the limit is reached, so that a complete tree is
+/// still produced.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ParserOptions {
+    pub(crate) strict_glyph_names: bool,
+    pub(crate) max_errors: Option<usize>,
+}
 
 /// Oversees parsing, following, resolving and validating input statements.
 ///
@@ -58,13 +92,15 @@ pub(crate) struct ParseContext {
     sources: Arc<SourceList>,
     parsed_files: HashMap<FileId, (Node, Vec<Diagnostic>)>,
     graph: IncludeGraph,
+    max_include_depth: usize,
+    options: ParserOptions,
 }
 
 /// A simple graph of files and their includes.
 ///
 /// We maintain this in order to validate that the input does not contain
 /// any cyclical include statements, and does not exceed the maximum include
-/// depth of 50.
+/// depth.
 #[derive(Clone, Debug, Default)]
 struct IncludeGraph {
     // source file -> (destination file, span-in-source-for-error)
@@ -83,8 +119,78 @@ struct IncludeError {
 }
 
 enum IncludeErrorKind {
-    Cycle,
-    ToDeep,
+    /// The chain of files that ends in the problem statement, which includes
+    /// a file earlier in the chain
+    Cycle(Vec<FileId>),
+    /// The chain of files that ends in the problem statement
+    TooDeep(Vec<FileId>),
+}
+
+impl IncludeLimits {
+    /// Create the default limits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the maximum depth of nested include statements.
+    ///
+    /// A file included by the root source has depth 1, a file that it
+    /// includes has depth 2, and so on.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Set the maximum number of files, including the root source.
+    pub fn max_files(mut self, n_files: usize) -> Self {
+        self.max_files = Some(n_files);
+        self
+    }
+
+    /// Set the maximum total size, in bytes, of all sources.
+    pub fn max_bytes(mut self, n_bytes: usize) -> Self {
+        self.max_bytes = Some(n_bytes);
+        self
+    }
+}
+
+impl Default for IncludeLimits {
+    fn default() -> Self {
+        IncludeLimits {
+            max_depth: DEFAULT_MAX_INCLUDE_DEPTH,
+            max_files: None,
+            max_bytes: None,
+        }
+    }
+}
+
+impl ParserOptions {
+    /// Create the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// If `true`, glyph names that could also be read as a range of glyphs
+    /// are errors, instead of warnings.
+    ///
+    /// See [`Compiler::with_strict_glyph_names`] for more information.
+    ///
+    /// [`Compiler::with_strict_glyph_names`]: crate::Compiler::with_strict_glyph_names
+    pub fn strict_glyph_names(mut self, strict: bool) -> Self {
+        self.strict_glyph_names = strict;
+        self
+    }
+
+    /// Set the maximum number of errors to report.
+    ///
+    /// This limit applies to all sources, including those loaded by `include`
+    /// statements. Once it is reached, any further errors or warnings are
+    /// discarded, and a single error reporting the number of discarded
+    /// diagnostics is added to the end of each file where this happened.
+    pub fn max_errors(mut self, n_errors: usize) -> Self {
+        self.max_errors = Some(n_errors);
+        self
+    }
 }
 
 impl IncludeStatement {
@@ -115,10 +221,17 @@ impl ParseContext {
     /// After parsing, you can call [`generate_parse_tree`] in order to generate
     /// a unified parse tree suitable for compilation.
     ///
+    /// The `options` control how each source is parsed; see [`ParserOptions`].
+    ///
+    /// Includes that would exceed the provided `limits` are not loaded, and
+    /// are reported as errors.
+    ///
     /// [`generate_parse_tree`]: ParseContext::generate_parse_tree
     pub(crate) fn parse(
         path: OsString,
         glyph_map: Option<&GlyphMap>,
+        options: &ParserOptions,
+        limits: &IncludeLimits,
         resolver: Box<dyn SourceResolver>,
     ) -> Result<Self, SourceLoadError> {
         let mut sources = SourceLoader::new(resolver);
@@ -126,6 +239,9 @@ impl ParseContext {
         let mut queue = vec![root_id];
         let mut parsed_files = HashMap::new();
         let mut includes = IncludeGraph::default();
+        let mut n_files = 1;
+        let mut n_bytes = sources.get(&root_id).unwrap().text().len();
+        let mut n_errors = 0;
 
         while let Some(id) = queue.pop() {
             // skip things we've already parsed.
@@ -133,8 +249,14 @@ impl ParseContext {
                 continue;
             }
             let source = sources.get(&id).unwrap();
-            let (node, mut errors, include_stmts) = parse_src(source, glyph_map);
+            // the error limit is shared between all sources
+            let file_options = ParserOptions {
+                max_errors: options.max_errors.map(|max| max.saturating_sub(n_errors)),
+                ..options.clone()
+            };
+            let (node, mut errors, include_stmts) = parse_src(source, glyph_map, &file_options);
             errors.iter_mut().for_each(|e| e.message.file = id);
+            n_errors += errors.iter().filter(|e| e.is_error()).count();
 
             parsed_files.insert(source.id(), (node, errors));
             if include_stmts.is_empty() {
@@ -145,20 +267,52 @@ impl ParseContext {
             let source_id = source.id();
 
             for include in &include_stmts {
-                match sources.source_for_path(&include.path(), Some(source_id)) {
-                    Ok(included_id) => {
+                let error = match sources.source_for_path(&include.path(), Some(source_id)) {
+                    Ok(included_id)
+                        if included_id == root_id || sources.is_included(included_id) =>
+                    {
                         includes.add_edge(id, (included_id, include.stmt_range()));
                         queue.push(included_id);
+                        continue;
                     }
-                    Err(e) => {
-                        let range = include.path_range();
-                        parsed_files.get_mut(&id).unwrap().1.push(Diagnostic::error(
-                            id,
-                            range,
-                            e.to_string(),
-                        ));
+                    Ok(included_id) => {
+                        let len = sources.get(&included_id).unwrap().text().len();
+                        let limit = if limits.max_files.is_some_and(|max| n_files >= max) {
+                            Some("maximum number of included files")
+                        } else if limits.max_bytes.is_some_and(|max| n_bytes + len > max) {
+                            Some("maximum total size of included files")
+                        } else {
+                            None
+                        };
+                        match limit {
+                            Some(limit) => {
+                                let chain = sources
+                                    .include_chain(id)
+                                    .into_iter()
+                                    .rev()
+                                    .map(|(source, _)| source.path())
+                                    .chain(
+                                        [id, included_id]
+                                            .map(|file| sources.get(&file).unwrap().path()),
+                                    );
+                                format!("exceeded {limit} ({})", format_chain(chain))
+                            }
+                            None => {
+                                n_files += 1;
+                                n_bytes += len;
+                                sources.record_include(included_id, id, include.stmt_range());
+                                includes.add_edge(id, (included_id, include.stmt_range()));
+                                queue.push(included_id);
+                                continue;
+                            }
+                        }
                     }
-                }
+                    Err(e) => e.to_string(),
+                };
+                let range = include.path_range();
+                parsed_files.get_mut(&id).unwrap().1.push(
+                    Diagnostic::error(id, range, error).with_code(DiagnosticCode::IncludeError),
+                );
             }
         }
 
@@ -167,6 +321,8 @@ impl ParseContext {
             sources: sources.into_inner(),
             parsed_files,
             graph: includes,
+            max_include_depth: limits.max_depth,
+            options: options.clone(),
         })
     }
 
@@ -184,18 +340,30 @@ impl ParseContext {
             .flat_map(|(_, (_, errs))| errs.iter())
             .cloned()
             .collect::<Vec<_>>();
-        let include_errors = self.graph.validate(self.root_id());
+        let include_errors = self.graph.validate(self.root_id(), self.max_include_depth);
         // record any errors:
         for IncludeError {
             file, range, kind, ..
         } in &include_errors
         {
             // find statement
+            let format_ids = |chain: &[FileId]| {
+                format_chain(chain.iter().map(|id| self.sources.get(id).unwrap().path()))
+            };
             let message = match kind {
-                IncludeErrorKind::Cycle => "cyclical include statement",
-                IncludeErrorKind::ToDeep => "exceded maximum include depth",
+                IncludeErrorKind::Cycle(chain) => {
+                    format!("cyclical include statement ({})", format_ids(chain))
+                }
+                IncludeErrorKind::TooDeep(chain) => format!(
+                    "exceeded maximum include depth of {} ({})",
+                    self.max_include_depth,
+                    format_ids(chain)
+                ),
             };
-            all_errors.push(Diagnostic::error(*file, range.clone(), message));
+            all_errors.push(
+                Diagnostic::error(*file, range.clone(), message)
+                    .with_code(DiagnosticCode::IncludeError),
+            );
         }
 
         let mut map = SourceMap::default();
@@ -205,6 +373,8 @@ impl ParseContext {
                 root,
                 map: Arc::new(map),
                 sources: self.sources,
+                root_id: self.root_id,
+                options: self.options,
             },
             all_errors,
         )
@@ -269,26 +439,34 @@ impl IncludeGraph {
     /// If the result is non-empty, each returned error should be converted to
     /// d to diagnostics by the caller, and those statements should
     /// not be resolved when building the final tree.
-    fn validate(&self, root: FileId) -> Vec<IncludeError> {
+    fn validate(&self, root: FileId, max_depth: usize) -> Vec<IncludeError> {
         let edges = match self.nodes.get(&root) {
             None => return Vec::new(),
             Some(edges) => edges,
         };
 
         let mut stack = vec![(root, edges, 0_usize)];
-        let mut seen = HashSet::new();
+        let mut seen = HashSet::from([root]);
         let mut bad_edges = Vec::new();
 
         while let Some((node, edges, cur_edge)) = stack.pop() {
             if let Some((child, stmt)) = edges.get(cur_edge) {
                 // push parent, advancing idx
                 stack.push((node, edges, cur_edge + 1));
-                if stack.len() >= MAX_INCLUDE_DEPTH - 1 {
+                let chain = || {
+                    stack
+                        .iter()
+                        .map(|(ancestor, _, _)| *ancestor)
+                        .chain(std::iter::once(*child))
+                        .collect()
+                };
+                if stack.len() > max_depth {
+                    let chain = chain();
                     bad_edges.push(IncludeError {
                         file: node,
                         statement_idx: cur_edge,
                         range: stmt.clone(),
-                        kind: IncludeErrorKind::ToDeep,
+                        kind: IncludeErrorKind::TooDeep(chain),
                     });
                     continue;
                 }
@@ -304,7 +482,7 @@ impl IncludeGraph {
                         file: node,
                         statement_idx: cur_edge,
                         range: stmt.clone(),
-                        kind: IncludeErrorKind::Cycle,
+                        kind: IncludeErrorKind::Cycle(chain()),
                     });
                 }
             }
@@ -313,12 +491,26 @@ impl IncludeGraph {
     }
 }
 
+fn format_chain<'a>(chain: impl Iterator<Item = &'a OsStr>) -> String {
+    let mut result = String::new();
+    for (i, path) in chain.enumerate() {
+        if i > 0 {
+            result.push_str(" -> ");
+        }
+        result.push_str(&Path::new(path).display().to_string());
+    }
+    result
+}
+
 /// Parse a single source file.
 pub(crate) fn parse_src(
     src: &Source,
     glyph_map: Option<&GlyphMap>,
+    options: &ParserOptions,
 ) -> (Node, Vec<Diagnostic>, Vec<IncludeStatement>) {
-    let mut sink = AstSink::new(src.text(), src.id(), glyph_map);
+    let mut sink = AstSink::new(src.text(), src.id(), glyph_map)
+        .with_strict_glyph_names(options.strict_glyph_names)
+        .with_max_errors(options.max_errors);
     {
         let mut parser = Parser::new(src.text(), &mut sink);
         super::grammar::root(&mut parser);
@@ -365,9 +557,28 @@ mod tests {
         graph.add_edge(c, (d, statement.range()));
         graph.add_edge(d, (b, statement.range()));
 
-        let result = graph.validate(a);
+        let result = graph.validate(a, DEFAULT_MAX_INCLUDE_DEPTH);
         assert_eq!(result[0].file, d);
         assert_eq!(result[0].range, 0..18);
+        assert!(
+            matches!(&result[0].kind, IncludeErrorKind::Cycle(chain) if chain == &[a, b, c, d, b])
+        );
+    }
+
+    #[test]
+    fn missing_semi_edit() {
+        let fea = "include(a.fea)\nlanguagesystem DFLT dflt\n";
+        let (_, errs) = crate::parse::parse_string(fea);
+        assert_eq!(errs.len(), 2);
+        let mut fixed = fea.to_string();
+        for err in errs.iter().rev() {
+            let edit = err.edit.as_ref().unwrap();
+            assert_eq!(edit.replacement, ";");
+            fixed.replace_range(edit.span.range(), &edit.replacement);
+        }
+        assert_eq!(fixed, "include(a.fea);\nlanguagesystem DFLT dflt;\n");
+        let (_, errs) = crate::parse::parse_string(fixed);
+        assert!(errs.is_empty(), "{errs:?}");
     }
 
     #[test]
@@ -375,19 +586,133 @@ mod tests {
         let parse = ParseContext::parse(
             "a".into(),
             None,
+            &Default::default(),
+            &Default::default(),
             Box::new(|path: &OsStr| match path.to_str().unwrap() {
                 "a" => Ok("include(bb);".into()),
                 "bb" => Ok("include(a);".into()),
-                _ => Err(SourceLoadError::new(
-                    path.to_owned(),
-                    std::io::Error::new(std::io::ErrorKind::NotFound, "oh no"),
-                )),
+                _ => Err(SourceLoadError::NotFound {
+                    path: path.to_owned(),
+                }),
             }),
         )
         .unwrap();
         let (resolved, errs) = parse.generate_parse_tree();
         assert_eq!(errs.len(), 1);
-        assert_eq!(resolved.root.text_len(), "include(bb);".len());
+        assert_eq!(errs[0].text(), "cyclical include statement (a -> bb -> a)");
+        // the statement in 'bb' that closes the cycle is left unresolved
+        assert_eq!(resolved.root.text_len(), "include(a);".len());
+    }
+
+    #[test]
+    fn diagnostic_include_chain() {
+        let parse = ParseContext::parse(
+            "a".into(),
+            None,
+            &Default::default(),
+            &Default::default(),
+            Box::new(|path: &OsStr| match path.to_str().unwrap() {
+                "a" => Ok("# a\ninclude(b);".into()),
+                "b" => Ok("include(c);".into()),
+                "c" => Ok("languagesystem DFLT;".into()),
+                _ => Err(SourceLoadError::NotFound {
+                    path: path.to_owned(),
+                }),
+            }),
+        )
+        .unwrap();
+        let (tree, errs) = parse.generate_parse_tree();
+        assert_eq!(errs.len(), 1);
+        let formatted = tree.format_diagnostic(&errs[0], false);
+        let header = formatted.lines().take(4).collect::<Vec<_>>();
+        assert_eq!(
+            header,
+            [
+                "error: expected tag, found ;",
+                "in c at 1:19",
+                "included from b at 1:0",
+                "included from a at 2:0"
+            ]
+        );
+        assert!(
+            formatted.contains("1 | languagesystem DFLT;"),
+            "{formatted}"
+        );
+    }
+
+    fn parse_with_limits(limits: IncludeLimits) -> Vec<String> {
+        let parse = ParseContext::parse(
+            "a".into(),
+            None,
+            &Default::default(),
+            &limits,
+            Box::new(|path: &OsStr| match path.to_str().unwrap() {
+                "a" => Ok("include(b);\ninclude(c);".into()),
+                "b" => Ok("include(d);".into()),
+                "c" => Ok("languagesystem DFLT dflt;".into()),
+                "d" => Ok("# d".into()),
+                _ => Err(SourceLoadError::NotFound {
+                    path: path.to_owned(),
+                }),
+            }),
+        )
+        .unwrap();
+        let (_, errs) = parse.generate_parse_tree();
+        errs.iter().map(|err| err.text().to_string()).collect()
+    }
+
+    #[test]
+    fn include_limits() {
+        assert!(parse_with_limits(Default::default()).is_empty());
+        assert_eq!(
+            parse_with_limits(IncludeLimits::new().max_depth(1)),
+            ["exceeded maximum include depth of 1 (a -> b -> d)"]
+        );
+        assert_eq!(
+            parse_with_limits(IncludeLimits::new().max_files(3)),
+            ["exceeded maximum number of included files (a -> b -> d)"]
+        );
+        // 'a' and 'b' fit, but 'c' does not
+        assert_eq!(
+            parse_with_limits(IncludeLimits::new().max_bytes(40)),
+            ["exceeded maximum total size of included files (a -> c)"]
+        );
+    }
+
+    #[test]
+    fn max_errors() {
+        let parse = |options: &ParserOptions| {
+            let parse = ParseContext::parse(
+                "a".into(),
+                None,
+                options,
+                &Default::default(),
+                Box::new(|path: &OsStr| match path.to_str().unwrap() {
+                    "a" => Ok("include(b);\nlanguagesystem;\nlanguagesystem;".into()),
+                    "b" => Ok("languagesystem;\nlanguagesystem;\nlanguagesystem;".into()),
+                    _ => Err(SourceLoadError::NotFound {
+                        path: path.to_owned(),
+                    }),
+                }),
+            )
+            .unwrap();
+            let (_, errs) = parse.generate_parse_tree();
+            errs.iter()
+                .map(|err| err.text().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(parse(&Default::default()).len(), 5);
+        // the limit is shared between files; 'a' is parsed first
+        let errs = parse(&ParserOptions::new().max_errors(3));
+        assert_eq!(errs.len(), 4, "{errs:?}");
+        assert_eq!(
+            errs.iter()
+                .filter(|err| err.as_str() == "expected tag, found ;")
+                .count(),
+            3
+        );
+        assert!(errs.contains(&"too many errors; 2 more diagnostics were not reported".into()));
     }
 
     #[test]
@@ -405,14 +730,13 @@ mod tests {
         let parse = ParseContext::parse(
             "file_a".into(),
             None,
+            &Default::default(),
+            &Default::default(),
             Box::new(|path: &OsStr| match path.to_str().unwrap() {
                 "file_a" => Ok(file_a.into()),
                 "b" => Ok(file_b.into()),
                 "c" => Ok(file_c.into()),
-                _ => Err(SourceLoadError::new(
-                    path.into(),
-                    std::io::Error::new(std::io::ErrorKind::NotFound, "oh no"),
-                )),
+                _ => Err(SourceLoadError::NotFound { path: path.into() }),
             }),
         )
         .unwrap();
@@ -3,6 +3,7 @@ use super::{glyph, gpos, gsub, metrics};
 
 use crate::parse::Parser;
 use crate::token_tree::Kind;
+use crate::DiagnosticCode;
 
 const LABEL_RECOVERY: TokenSet = TokenSet::new(&[LexemeKind::UseExtensionKw, LexemeKind::LBrace]);
 
@@ -74,14 +75,20 @@ pub(crate) fn lookup_block(parser: &mut Parser, recovery: TokenSet) {
 /// returns true if we advanced the parser.
 pub(crate) fn statement(parser: &mut Parser, recovery: TokenSet, in_lookup: bool) -> bool {
     let start_pos = parser.nth_range(0).start;
+    let errors_before = parser.error_count();
     match parser.nth(0).kind.to_token_kind() {
         Kind::PosKw | Kind::SubKw | Kind::RsubKw | Kind::IgnoreKw | Kind::EnumKw => {
-            pos_or_sub_rule(parser, recovery)
+            pos_or_sub_rule(parser, recovery);
+            parser.resync_after_error(errors_before, recovery);
         }
         Kind::NamedGlyphClass => {
-            glyph::named_glyph_class_decl(parser, TokenSet::TOP_LEVEL.union(recovery))
+            glyph::named_glyph_class_decl(parser, TokenSet::TOP_LEVEL.union(recovery));
+            parser.resync_after_error(errors_before, recovery);
+        }
+        Kind::MarkClassKw => {
+            super::mark_class(parser);
+            parser.resync_after_error(errors_before, recovery);
         }
-        Kind::MarkClassKw => super::mark_class(parser),
         Kind::SubtableKw => parser.in_node(Kind::SubtableNode, |parser| {
             parser.eat_raw();
             parser.expect_recover(Kind::Semi, recovery);
@@ -92,12 +99,17 @@ pub(crate) fn statement(parser: &mut Parser, recovery: TokenSet, in_lookup: bool
         }
         Kind::IncludeKw => super::include(parser),
         Kind::LookupKw => super::lookup_block_or_reference(parser, recovery),
-        Kind::LookupflagKw => lookupflag(parser, recovery),
+        Kind::LookupflagKw => {
+            lookupflag(parser, recovery);
+            parser.resync_after_error(errors_before, recovery);
+        }
         Kind::ScriptKw => {
             super::eat_script(parser, recovery);
+            parser.resync_after_error(errors_before, recovery);
         }
         Kind::LanguageKw => {
             super::eat_language(parser, recovery);
+            parser.resync_after_error(errors_before, recovery);
         }
         Kind::FeatureKw => {
             // aalt only
@@ -121,8 +133,19 @@ pub(crate) fn statement(parser: &mut Parser, recovery: TokenSet, in_lookup: bool
         }
         Kind::CvParametersKw => cv_parameters(parser, recovery),
         Kind::FeatureNamesKw => feature_names(parser, recovery),
+        Kind::ExcludeDfltKw | Kind::IncludeDfltKw | Kind::RequiredKw => {
+            let token = parser.current_token_text().to_owned();
+            parser.err_and_bump(format!(
+                "'{token}' must follow the tag in a 'language' statement, e.g. 'language DEU {token};'"
+            ));
+            parser.eat_until(recovery.union(TokenSet::SEMI));
+            parser.eat(Kind::Semi);
+        }
         Kind::Semi => {
-            parser.warn("';' should only follow a statement");
+            parser.warn(
+                DiagnosticCode::StraySemicolon,
+                "';' should only follow a statement",
+            );
             parser.eat_raw();
         }
 
@@ -66,6 +66,7 @@ pub(crate) fn expect_named_or_unnamed_glyph_class(parser: &mut Parser, recovery:
 }
 
 // [ a b a-z @hi \0-\40 ]
+// [ @hi - @there & @everyone ]
 pub(crate) fn eat_glyph_class_list(parser: &mut Parser, recovery: TokenSet) -> bool {
     let recovery = recovery.add(Kind::RSquare);
     if !parser.matches(0, Kind::LSquare) {
@@ -86,16 +87,30 @@ pub(crate) fn eat_glyph_class_list(parser: &mut Parser, recovery: TokenSet) -> b
 }
 
 fn glyph_class_list_member(parser: &mut Parser, recovery: TokenSet) -> bool {
+    const OPERATOR: TokenSet = TokenSet::new(&[Kind::Hyphen, Kind::Ampersand]);
+
     if parser.eat(Kind::NamedGlyphClass) {
         return true;
     }
+    // '- @class' or '& @class': class arithmetic, which is only accepted
+    // by the compiler if it is enabled
+    if parser.matches(0, OPERATOR) {
+        parser.in_node(AstKind::GlyphClassOperationNode, |parser| {
+            assert!(parser.eat(OPERATOR));
+            parser.expect_recover(Kind::NamedGlyphClass, recovery);
+        });
+        return true;
+    }
     // a glyphname
     // a glyph development name
     // an escaped glyph name
     // an escaped CID
 
-    let looks_like_range = parser.matches(1, Kind::Hyphen)
-        || (parser.matches(0, Kind::Backslash) && parser.matches(2, Kind::Hyphen));
+    let looks_like_range = (parser.matches(1, Kind::Hyphen)
+        && !parser.matches(2, Kind::NamedGlyphClass))
+        || (parser.matches(0, Kind::Backslash)
+            && parser.matches(2, Kind::Hyphen)
+            && !parser.matches(3, Kind::NamedGlyphClass));
     if looks_like_range {
         parser.in_node(AstKind::GlyphRange, |parser| {
             glyph_range(parser, recovery.add(Kind::RSquare));
@@ -204,7 +219,7 @@ mod tests {
     use super::*;
     use crate::parse::FileId;
     use crate::token_tree::AstSink;
-    use crate::{GlyphMap, GlyphName};
+    use crate::{Diagnostic, GlyphMap, GlyphName, Level, Node, SuggestedEdit};
 
     #[test]
     fn name_like() {
@@ -277,4 +292,92 @@ mod tests {
         assert_eq!(cursor.next_token().unwrap().kind, AstKind::GlyphName);
         assert_eq!(cursor.next_token().unwrap().kind, AstKind::RSquare);
     }
+
+    fn parse_class_list(fea: &str, glyphs: &[&str], strict: bool) -> (Node, Vec<Diagnostic>) {
+        let glyphs: GlyphMap = glyphs.iter().cloned().map(GlyphName::from).collect();
+        let mut sink =
+            AstSink::new(fea, FileId::CURRENT_FILE, Some(&glyphs)).with_strict_glyph_names(strict);
+        let mut parser = Parser::new(fea, &mut sink);
+        eat_glyph_class_list(&mut parser, TokenSet::EMPTY);
+        let (node, errs, _) = sink.finish();
+        (node, errs)
+    }
+
+    fn glyph_names(node: &Node) -> Vec<&str> {
+        node.iter_tokens()
+            .filter(|t| t.kind == AstKind::GlyphName)
+            .map(|t| t.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn class_operations() {
+        let (node, errs) = parse_class_list("[a - @b & @c]", &["a"], false);
+        assert!(errs.is_empty(), "{errs:?}");
+        let kinds = node
+            .iter_children()
+            .map(|child| child.kind())
+            .filter(|kind| !kind.is_trivia())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                AstKind::LSquare,
+                AstKind::GlyphName,
+                AstKind::GlyphClassOperationNode,
+                AstKind::GlyphClassOperationNode,
+                AstKind::RSquare
+            ]
+        );
+
+        let (_, errs) = parse_class_list("[a - b]", &["a"], false);
+        assert!(errs.is_empty(), "{errs:?}");
+        let (_, errs) = parse_class_list("[@a - b]", &["a", "b"], false);
+        assert_eq!(errs.len(), 1);
+    }
+
+    #[test]
+    fn ambiguous_glyph_name() {
+        let fea = "[a-b]";
+        let glyphs = ["a", "b", "a-b"];
+        let (node, errs) = parse_class_list(fea, &glyphs, false);
+        assert_eq!(glyph_names(&node), ["a-b"]);
+        assert_eq!(errs.len(), 1);
+        assert_eq!(errs[0].level, Level::Warning);
+        assert!(errs[0].text().contains("'a - b'"), "{}", errs[0].text());
+        // the suggested fix is to escape the name
+        let edit = errs[0].edit.as_ref().unwrap();
+        assert_eq!(SuggestedEdit::apply_all(fea, [edit]).0, "[\\a-b]");
+
+        let (_, errs) = parse_class_list(fea, &glyphs, true);
+        assert_eq!(errs.len(), 1);
+        assert!(errs[0].is_error());
+    }
+
+    #[test]
+    fn escaped_glyph_name() {
+        let fea = "[\\a-b]";
+        let (node, errs) = parse_class_list(fea, &["a", "b", "a-b"], true);
+        assert!(errs.is_empty(), "{errs:?}");
+        assert_eq!(glyph_names(&node), ["a-b"]);
+
+        // an escaped name is never a range, even if the glyph doesn't exist
+        let (node, errs) = parse_class_list(fea, &["a", "b"], true);
+        assert!(errs.is_empty(), "{errs:?}");
+        assert_eq!(glyph_names(&node), ["a-b"]);
+    }
+
+    #[test]
+    fn unknown_name_or_range() {
+        let (_, errs) = parse_class_list("[a-c]", &["a", "b"], false);
+        assert_eq!(
+            errs[0].text(),
+            "'a-c' is not a glyph in the font, and is not a range because 'c' is not in the font"
+        );
+        let (_, errs) = parse_class_list("[x-y]", &["a", "b"], false);
+        assert_eq!(
+            errs[0].text(),
+            "'x-y' is not a glyph in the font, and is not a range because neither 'x' nor 'y' is in the font"
+        );
+    }
 }
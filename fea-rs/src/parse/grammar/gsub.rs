@@ -172,6 +172,16 @@ fn parse_rsub(parser: &mut Parser, recovery: TokenSet) -> AstKind {
         parser.expect_semi();
         return AstKind::GsubNode;
     }
+    // lookups are not allowed here, but we parse them so that we can
+    // report a more helpful error during validation.
+    while parser.eat(Kind::LookupKw) {
+        if !parser.eat(Kind::Ident) {
+            parser.err("expected named lookup");
+            parser.eat_until(recovery);
+            parser.expect_semi();
+            return AstKind::GsubNode;
+        }
+    }
 
     super::greedy(glyph::eat_glyph_or_glyph_class)(parser, recovery);
 
@@ -77,7 +77,8 @@ pub(crate) fn eat_value_record(parser: &mut Parser, recovery: TokenSet) -> bool
         || (parser.matches(0, Kind::LAngle)
             && parser.matches(
                 1,
-                TokenSet::new(&[Kind::Number, Kind::NullKw]).union(TokenSet::IDENT_LIKE),
+                TokenSet::new(&[Kind::Number, Kind::LParen, Kind::NullKw])
+                    .union(TokenSet::IDENT_LIKE),
             ));
 
     if !looks_like_record {
@@ -207,7 +208,7 @@ fn expect_axis_location(parser: &mut Parser) -> bool {
     true
 }
 
-fn expect_device(parser: &mut Parser, recovery: TokenSet) -> bool {
+pub(crate) fn expect_device(parser: &mut Parser, recovery: TokenSet) -> bool {
     let result = eat_device(parser, recovery);
     if !result {
         parser.err_recover("expected device record", recovery);
@@ -333,6 +334,15 @@ mod tests {
         assert!(errstr.contains("expected metric"));
     }
 
+    #[test]
+    fn value_record_starting_with_variable_metric() {
+        let fea = "<(wght=200:10 wght=1000:20) 0 0 0>";
+        let (_out, _err, errstr) = debug_parse_output(fea, |parser| {
+            assert!(eat_value_record(parser, TokenSet::EMPTY));
+        });
+        assert!(errstr.is_empty(), "{errstr}");
+    }
+
     #[test]
     fn empty_metric() {
         let empty_metric = "()";
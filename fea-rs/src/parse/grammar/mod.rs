@@ -1,6 +1,7 @@
 use super::lexer::{Kind, TokenSet};
 use super::Parser;
 use crate::token_tree::Kind as AstKind;
+use crate::{DiagnosticCode, Level};
 
 mod feature;
 mod glyph;
@@ -25,8 +26,27 @@ pub fn root(parser: &mut Parser) {
     parser.finish_node();
 }
 
+/// Entry point for reparsing statements in the body of a feature or lookup block.
+///
+/// The `recovery` and `in_lookup` arguments must match those used when the
+/// block was originally parsed. Returns `false` if the parser stopped before
+/// the end of the input, for instance because it found a closing brace.
+pub(crate) fn block_statements(parser: &mut Parser, recovery: TokenSet, in_lookup: bool) -> bool {
+    parser.start_node(AstKind::SourceFile);
+    while !parser.at_eof() && !parser.matches(0, Kind::RBrace) {
+        if !feature::statement(parser, recovery, in_lookup) {
+            break;
+        }
+    }
+    let finished = parser.at_eof();
+    parser.eat_trivia();
+    parser.finish_node();
+    finished
+}
+
 fn top_level_element(parser: &mut Parser) {
     parser.eat_trivia();
+    let errors_before = parser.error_count();
 
     if parser.at_eof() {
         // noop
@@ -37,23 +57,28 @@ fn top_level_element(parser: &mut Parser) {
     } else if parser.matches(0, Kind::LookupKw) {
         lookup_block_or_reference(parser, TokenSet::TOP_LEVEL)
     } else if parser.matches(0, Kind::LanguagesystemKw) {
-        language_system(parser)
+        language_system(parser);
+        parser.resync_after_error(errors_before, TokenSet::TOP_LEVEL);
     } else if parser.matches(0, Kind::FeatureKw) {
         feature::feature(parser)
     } else if parser.matches(0, Kind::MarkClassKw) {
-        mark_class(parser)
+        mark_class(parser);
+        parser.resync_after_error(errors_before, TokenSet::TOP_LEVEL);
     } else if parser.matches(0, Kind::AnchorDefKw) {
-        anchor_def(parser)
+        anchor_def(parser);
+        parser.resync_after_error(errors_before, TokenSet::TOP_LEVEL);
     } else if parser.matches(0, Kind::AnonKw) {
         anonymous(parser)
     } else if parser.matches(0, Kind::NamedGlyphClass) {
-        glyph::named_glyph_class_decl(parser, TokenSet::TOP_LEVEL)
+        glyph::named_glyph_class_decl(parser, TokenSet::TOP_LEVEL);
+        parser.resync_after_error(errors_before, TokenSet::TOP_LEVEL);
     } else if parser.matches(0, Kind::ConditionSetKw) {
         variations::condition_set(parser)
     } else if parser.matches(0, Kind::VariationKw) {
         variations::variation(parser)
     } else if parser.matches(0, Kind::ValueRecordDefKw) {
-        value_record_def(parser, TokenSet::TOP_LEVEL)
+        value_record_def(parser, TokenSet::TOP_LEVEL);
+        parser.resync_after_error(errors_before, TokenSet::TOP_LEVEL);
     } else {
         parser.err_and_bump(format!(
             "Unexpected token '{}', expected global keyword.",
@@ -97,7 +122,11 @@ fn include(parser: &mut Parser) {
             return advance_to_top_level(parser);
         }
         if !parser.eat(Kind::Semi) {
-            parser.warn_before_ws("include statement is missing ';'");
+            parser.missing_semi(
+                Level::Warning,
+                Some(DiagnosticCode::IncludeMissingSemicolon),
+                "include statement is missing ';'",
+            );
         }
     }
 
@@ -112,7 +141,7 @@ fn table(parser: &mut Parser) {
 //or     lookup <label>;
 fn lookup_block_or_reference(parser: &mut Parser, recovery: TokenSet) {
     assert!(parser.matches(0, Kind::LookupKw));
-    if parser.matches(2, Kind::LBrace) {
+    if parser.matches(2, TokenSet::new(&[Kind::LBrace, Kind::UseExtensionKw])) {
         feature::lookup_block(parser, recovery.union(TokenSet::STATEMENT));
     } else if parser.matches(2, Kind::Semi) {
         parser.in_node(AstKind::LookupRefNode, |parser| {
@@ -138,6 +167,12 @@ fn eat_script(parser: &mut Parser, recovery: TokenSet) -> bool {
     parser.in_node(AstKind::ScriptNode, |parser| {
         assert!(parser.eat(Kind::ScriptKw));
         parser.expect_tag(recovery.union(TokenSet::SEMI));
+        while parser.matches(0, TokenSet::LANGUAGE_FLAGS) {
+            let token = parser.current_token_text().to_owned();
+            parser.err_and_bump(format!(
+                "'{token}' is only valid in a 'language' statement, e.g. 'language DEU {token};'"
+            ));
+        }
         parser.expect_semi();
     });
     true
@@ -150,9 +185,22 @@ fn eat_language(parser: &mut Parser, recovery: TokenSet) -> bool {
     parser.in_node(AstKind::LanguageNode, |parser| {
         assert!(parser.eat(Kind::LanguageKw));
         parser.expect_tag(recovery.union(TokenSet::SEMI));
-        parser.eat(Kind::ExcludeDfltKw);
-        parser.eat(Kind::IncludeDfltKw);
-        parser.eat(Kind::RequiredKw);
+        // language <tag> [exclude_dflt|include_dflt] [required];
+        let has_dflt = parser.eat(TokenSet::DFLT_FLAGS);
+        if has_dflt && parser.matches(0, TokenSet::DFLT_FLAGS) {
+            parser.err_and_bump("only one of 'include_dflt' or 'exclude_dflt' is allowed");
+        }
+        if parser.eat(Kind::RequiredKw) {
+            while parser.matches(0, TokenSet::LANGUAGE_FLAGS) {
+                let token = parser.current_token_text().to_owned();
+                let msg = if parser.matches(0, Kind::RequiredKw) {
+                    "duplicate 'required'".to_string()
+                } else {
+                    format!("'{token}' must come before 'required'")
+                };
+                parser.err_and_bump(msg);
+            }
+        }
         parser.expect_semi();
     });
     true
@@ -334,7 +382,14 @@ fn debug_parse_output(
         if !err_str.is_empty() {
             err_str.push('\n');
         }
-        crate::util::highlighting::write_diagnostic(&mut err_str, err, &source, Some(80), true);
+        crate::util::highlighting::write_diagnostic(
+            &mut err_str,
+            err,
+            &source,
+            &[],
+            Some(80),
+            true,
+        );
     }
     (node.into(), errs, err_str)
 }
@@ -369,4 +424,27 @@ mod tests {
         );
         assert_eq!(value_def.name().as_str(), "foo");
     }
+
+    #[test]
+    fn skip_rest_of_bad_statement() {
+        let fea = "languagesystem DFLT dflt extra;\n\
+                   feature kern { pos a b 10 20; pos c d 5; } kern;";
+        let (out, errors, errstr) = debug_parse_output(fea, root);
+        assert_eq!(errors.len(), 2, "{errstr}");
+        assert!(errors.iter().all(|err| err.text() == "Expected ';'"));
+
+        // the statement after the bad one is still parsed
+        let out = typed::Root::cast(&out).unwrap();
+        let feature = out.iter().find_map(typed::Feature::cast).unwrap();
+        let rules = feature.statements().filter(|item| item.as_node().is_some());
+        assert_eq!(rules.count(), 2);
+    }
+
+    #[test]
+    fn bad_statement_with_semi_does_not_skip_next() {
+        let fea = "feature liga { sub a by; oops; } liga;";
+        let (_out, errors, errstr) = debug_parse_output(fea, root);
+        assert_eq!(errors.len(), 2, "{errstr}");
+        assert!(errors[1].text().contains("'oops' Not valid"), "{errstr}");
+    }
 }
@@ -168,7 +168,7 @@ mod base {
 }
 
 mod gdef {
-    use super::super::glyph;
+    use super::super::{glyph, metrics};
     use super::*;
 
     const GDEF_KEYWORDS: TokenSet = TokenSet::new(&[
@@ -212,10 +212,20 @@ mod gdef {
                 }
                 parser.expect_semi();
             })
-            // unimplemented (in spec)
+            // not implemented in the spec; each caret is a coordinate followed
+            // by a device table
         } else if parser.matches(0, Kind::LigatureCaretByDevKw) {
-            parser.in_node(AstKind::TableEntryNode, |parser| {
-                parser.eat_until(eat_until)
+            parser.in_node(AstKind::GdefLigatureCaretNode, |parser| {
+                assert!(parser.eat(Kind::LigatureCaretByDevKw));
+                glyph::expect_glyph_or_glyph_class(parser, recovery);
+                let recovery = recovery.add(Kind::Number);
+                if parser.expect_recover(Kind::Number, recovery) {
+                    metrics::expect_device(parser, recovery);
+                    while parser.eat(Kind::Number) {
+                        metrics::expect_device(parser, recovery);
+                    }
+                }
+                parser.expect_semi();
             })
         } else if parser.matches(0, CARET_POS_OR_IDX) {
             parser.in_node(AstKind::GdefLigatureCaretNode, |parser| {
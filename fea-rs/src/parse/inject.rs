@@ -0,0 +1,478 @@
+//! Adding generated statements to an existing parse tree.
+//!
+//! Tools that augment user FEA (for instance with generated kerning) can
+//! describe the new statements with a [`StatementBuilder`] and add them with
+//! [`ParseTree::insert_statements`], instead of concatenating strings and
+//! parsing everything again.
+//!
+//! The generated text is added to the tree's sources as a separate source, in
+//! the same way as an included file, so diagnostics in generated statements
+//! point at the generated text, and positions in the original sources are
+//! unchanged.
+
+use std::{fmt::Write, sync::Arc};
+
+use smol_str::SmolStr;
+use write_fonts::types::Tag;
+
+use super::{grammar, FileId, ParseTree, Parser, Source};
+use crate::{
+    decompile::escape_glyph_name,
+    token_tree::{
+        typed::{self, AstNode},
+        AstSink,
+    },
+    Diagnostic, GlyphMap, GlyphName, Kind, Node, NodeOrToken, TokenSet,
+};
+
+const INDENT: &str = "    ";
+
+/// The name given to the source containing inserted statements.
+const GENERATED_PATH: &str = "<generated>";
+
+/// A glyph or a class of glyphs, used in the rules of a [`StatementBuilder`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Glyphs {
+    /// A single glyph
+    Glyph(GlyphName),
+    /// A literal class, such as `[a b c]`
+    Class(Vec<GlyphName>),
+    /// A named glyph class, such as `@lower`; the name does not include the `@`
+    NamedClass(SmolStr),
+}
+
+/// Where to insert statements in a [`ParseTree`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InsertionPoint {
+    /// At the end of the root source
+    End,
+    /// At the end of the first top-level block for this feature
+    Feature(Tag),
+    /// At the end of the top-level lookup block with this name
+    Lookup(SmolStr),
+}
+
+/// An error that prevents statements from being inserted.
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum InsertError {
+    /// There is no top-level block for this feature.
+    #[error("no block for feature '{0}'")]
+    MissingFeature(Tag),
+    /// There is no top-level lookup block with this name.
+    #[error("no lookup block named '{0}'")]
+    MissingLookup(SmolStr),
+    /// The statements contain an unbalanced closing brace.
+    #[error("generated statements contain an unbalanced '}}'")]
+    UnbalancedBrace,
+    /// The statements contain an `include` statement.
+    #[error("include statements cannot be inserted")]
+    Include,
+}
+
+/// Builds FEA statements, for adding to a [`ParseTree`].
+///
+/// Each method appends a statement. Statements are only parsed when they are
+/// inserted, and any errors are reported then.
+#[derive(Clone, Debug, Default)]
+pub struct StatementBuilder {
+    text: String,
+    depth: usize,
+}
+
+impl StatementBuilder {
+    /// Create a new, empty builder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `true` if no statements have been added.
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// Add a statement, written as FEA source.
+    ///
+    /// This can be used for any statement that does not have a dedicated
+    /// method; it should be a complete statement, including the trailing `;`.
+    pub fn statement(&mut self, text: &str) -> &mut Self {
+        for line in text.lines() {
+            for _ in 0..self.depth {
+                self.text.push_str(INDENT);
+            }
+            self.text.push_str(line);
+            self.text.push('\n');
+        }
+        self
+    }
+
+    /// Add a named glyph class definition; `name` does not include the `@`.
+    pub fn glyph_class(&mut self, name: &str, glyphs: &[GlyphName]) -> &mut Self {
+        let glyphs = Glyphs::Class(glyphs.to_vec());
+        self.statement(&format!("@{name} = {glyphs};"))
+    }
+
+    /// Add a feature block, with the statements added by `body`.
+    pub fn feature(&mut self, tag: Tag, body: impl FnOnce(&mut Self)) -> &mut Self {
+        let tag = tag.to_string();
+        let tag = tag.trim_end();
+        self.block(&format!("feature {tag}"), tag, body)
+    }
+
+    /// Add a named lookup block, with the statements added by `body`.
+    pub fn lookup(&mut self, name: &str, body: impl FnOnce(&mut Self)) -> &mut Self {
+        self.block(&format!("lookup {name}"), name, body)
+    }
+
+    /// Add a reference to a named lookup.
+    pub fn lookup_ref(&mut self, name: &str) -> &mut Self {
+        self.statement(&format!("lookup {name};"))
+    }
+
+    /// Add a substitution rule, such as `sub f i by f_i;`.
+    ///
+    /// The type of the rule depends on the number of items on each side; an
+    /// empty `replacement` is written as `by NULL`.
+    pub fn sub(&mut self, target: &[Glyphs], replacement: &[Glyphs]) -> &mut Self {
+        let replacement = match replacement {
+            [] => "NULL".to_string(),
+            items => join(items),
+        };
+        self.statement(&format!("sub {} by {replacement};", join(target)))
+    }
+
+    /// Add a positioning rule that adjusts the advance of the first item.
+    ///
+    /// With one item this is a single adjustment, and with two it is a pair
+    /// adjustment.
+    pub fn pos(&mut self, glyphs: &[Glyphs], advance: i16) -> &mut Self {
+        self.statement(&format!("pos {} {advance};", join(glyphs)))
+    }
+
+    fn block(&mut self, open: &str, label: &str, body: impl FnOnce(&mut Self)) -> &mut Self {
+        self.statement(&format!("{open} {{"));
+        self.depth += 1;
+        body(self);
+        self.depth -= 1;
+        self.statement(&format!("}} {label};"))
+    }
+
+    /// The text of the statements, indented for a block at `depth`.
+    fn text_at_depth(&self, depth: usize) -> String {
+        let mut result = String::with_capacity(self.text.len());
+        for line in self.text.lines() {
+            for _ in 0..depth {
+                result.push_str(INDENT);
+            }
+            result.push_str(line);
+            result.push('\n');
+        }
+        result
+    }
+}
+
+impl std::fmt::Display for Glyphs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Glyphs::Glyph(name) => f.write_str(&escape_glyph_name(name)),
+            Glyphs::Class(glyphs) => {
+                f.write_char('[')?;
+                for (i, name) in glyphs.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(' ')?;
+                    }
+                    f.write_str(&escape_glyph_name(name))?;
+                }
+                f.write_char(']')
+            }
+            Glyphs::NamedClass(name) => write!(f, "@{name}"),
+        }
+    }
+}
+
+impl From<&str> for Glyphs {
+    fn from(name: &str) -> Self {
+        Glyphs::Glyph(name.into())
+    }
+}
+
+fn join(items: &[Glyphs]) -> String {
+    items
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub(crate) fn insert(
+    tree: &ParseTree,
+    at: &InsertionPoint,
+    statements: &StatementBuilder,
+    glyph_map: Option<&GlyphMap>,
+) -> Result<(ParseTree, Vec<Diagnostic>), InsertError> {
+    let root = tree.root();
+    // the index of the block in the root, and of its closing brace in the block
+    let block = match at {
+        InsertionPoint::End => None,
+        InsertionPoint::Feature(tag) => Some(
+            find_block(root, Kind::FeatureNode, |node| {
+                node.iter_children()
+                    .find_map(typed::Tag::cast)
+                    .and_then(|t| t.parse().ok())
+                    == Some(*tag)
+            })
+            .ok_or(InsertError::MissingFeature(*tag))?,
+        ),
+        InsertionPoint::Lookup(name) => Some(
+            find_block(root, Kind::LookupBlockNode, |node| {
+                node.iter_children()
+                    .find(|t| t.kind() == Kind::Label)
+                    .and_then(NodeOrToken::token_text)
+                    == Some(name.as_str())
+            })
+            .ok_or_else(|| InsertError::MissingLookup(name.clone()))?,
+        ),
+    };
+
+    let text = statements.text_at_depth(block.is_some() as usize);
+    let source = Source::new(GENERATED_PATH, Arc::from(text.as_str()));
+    let (new_node, diagnostics) = parse(&text, source.id(), at, glyph_map)?;
+    let new_len = new_node.text_len();
+    let new_children = new_node.iter_children().cloned();
+
+    let (new_root, insert_pos) = match block {
+        None => {
+            let len = root.iter_children().count();
+            (
+                root.splice_children(len..len, new_children, false),
+                root.text_len(),
+            )
+        }
+        Some((block_idx, brace_idx)) => {
+            let block = root
+                .iter_children()
+                .nth(block_idx)
+                .unwrap()
+                .as_node()
+                .unwrap();
+            let insert_pos = block.iter_children().nth(brace_idx).unwrap().range().start;
+            let new_block = block.splice_children(brace_idx..brace_idx, new_children, false);
+            (
+                root.splice_children(block_idx..block_idx + 1, [new_block.into()], false),
+                insert_pos,
+            )
+        }
+    };
+
+    let mut map = (*tree.map).clone();
+    map.insert_source(insert_pos, new_len, source.id());
+    let mut sources = (*tree.sources).clone();
+    sources.add(GENERATED_PATH.into(), source);
+    Ok((
+        ParseTree {
+            root: new_root,
+            sources: Arc::new(sources),
+            map: Arc::new(map),
+            root_id: tree.root_id,
+            options: tree.options.clone(),
+        },
+        diagnostics,
+    ))
+}
+
+/// Find the first top-level block of this kind matching `predicate`.
+///
+/// Returns the index of the block in the root and the index of its closing
+/// brace, which must exist.
+fn find_block(
+    root: &Node,
+    kind: Kind,
+    predicate: impl Fn(&Node) -> bool,
+) -> Option<(usize, usize)> {
+    root.iter_children().enumerate().find_map(|(idx, child)| {
+        let node = child.as_node().filter(|node| node.kind() == kind)?;
+        if !predicate(node) {
+            return None;
+        }
+        let brace = node
+            .iter_children()
+            .position(|child| child.kind() == Kind::RBrace)?;
+        Some((idx, brace))
+    })
+}
+
+fn parse(
+    text: &str,
+    file: FileId,
+    at: &InsertionPoint,
+    glyph_map: Option<&GlyphMap>,
+) -> Result<(Node, Vec<Diagnostic>), InsertError> {
+    let mut sink = AstSink::new(text, file, glyph_map);
+    let finished = {
+        let mut parser = Parser::new(text, &mut sink);
+        match at {
+            InsertionPoint::End => {
+                grammar::root(&mut parser);
+                true
+            }
+            InsertionPoint::Feature(_) => {
+                grammar::block_statements(&mut parser, TokenSet::FEATURE_STATEMENT, false)
+            }
+            InsertionPoint::Lookup(_) => grammar::block_statements(
+                &mut parser,
+                TokenSet::TOP_LEVEL.union(TokenSet::STATEMENT),
+                true,
+            ),
+        }
+    };
+    let (node, diagnostics, includes) = sink.finish();
+    if !finished {
+        return Err(InsertError::UnbalancedBrace);
+    }
+    if !includes.is_empty() {
+        return Err(InsertError::Include);
+    }
+    Ok((node, diagnostics))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+
+    use super::*;
+    use crate::{compile::Compiler, Level};
+
+    const FEA: &str = "\
+languagesystem DFLT dflt;
+feature liga {
+    sub f i by f_i;
+} liga;
+lookup kerning {
+    pos a b 5;
+} kerning;
+";
+
+    fn parse_fea(fea: &'static str) -> ParseTree {
+        let resolver = move |_: &OsStr| Ok(Arc::<str>::from(fea));
+        let (tree, diagnostics) =
+            crate::parse::parse_root("root.fea".into(), None, resolver).unwrap();
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+        tree
+    }
+
+    // every token in the tree should match the text it maps to in its source
+    fn check_source_map(tree: &ParseTree) {
+        for token in tree.root().iter_tokens().filter(|t| !t.as_str().is_empty()) {
+            let (file, range) = tree.source_map().resolve_range(token.range());
+            let text = tree.get_source(file).unwrap().text();
+            assert_eq!(&text[range], token.as_str());
+        }
+    }
+
+    fn tree_text(tree: &ParseTree) -> String {
+        tree.root().iter_tokens().map(|t| t.as_str()).collect()
+    }
+
+    #[test]
+    fn insert_into_blocks() {
+        let tree = parse_fea(FEA);
+        let mut liga = StatementBuilder::new();
+        liga.sub(&["f".into(), "f".into()], &["f_f".into()]);
+        let (tree, diagnostics) = tree
+            .insert_statements(&InsertionPoint::Feature(Tag::new(b"liga")), &liga, None)
+            .unwrap();
+        assert!(diagnostics.is_empty());
+        let mut kern = StatementBuilder::new();
+        kern.pos(
+            &["a".into(), Glyphs::Class(vec!["c".into(), "d".into()])],
+            -10,
+        );
+        let (tree, diagnostics) = tree
+            .insert_statements(&InsertionPoint::Lookup("kerning".into()), &kern, None)
+            .unwrap();
+        assert!(diagnostics.is_empty());
+        let mut end = StatementBuilder::new();
+        end.glyph_class("lower", &["a".into(), "b".into()])
+            .feature(Tag::new(b"kern"), |b| {
+                b.lookup_ref("kerning")
+                    .pos(&[Glyphs::NamedClass("lower".into())], 5);
+            });
+        let (tree, diagnostics) = tree
+            .insert_statements(&InsertionPoint::End, &end, None)
+            .unwrap();
+        assert!(diagnostics.is_empty());
+
+        assert_eq!(
+            tree_text(&tree),
+            "\
+languagesystem DFLT dflt;
+feature liga {
+    sub f i by f_i;
+    sub f f by f_f;
+} liga;
+lookup kerning {
+    pos a b 5;
+    pos a [c d] -10;
+} kerning;
+@lower = [a b];
+feature kern {
+    lookup kerning;
+    pos @lower 5;
+} kern;
+"
+        );
+        check_source_map(&tree);
+        // the original source is unchanged
+        assert_eq!(tree.get_source(tree.root_id()).unwrap().text(), FEA);
+
+        let glyph_map: GlyphMap = ["a", "b", "c", "d", "f", "i", "f_i", "f_f"]
+            .into_iter()
+            .map(GlyphName::new)
+            .collect();
+        let compilation = Compiler::new("root.fea", &glyph_map)
+            .compile_tree(&tree)
+            .unwrap();
+        let gsub = compilation.gsub.unwrap();
+        assert_eq!(gsub.lookup_list.lookups.len(), 1);
+        let gpos = compilation.gpos.unwrap();
+        assert_eq!(gpos.feature_list.feature_records.len(), 1);
+    }
+
+    #[test]
+    fn errors_in_generated_statements() {
+        let tree = parse_fea(FEA);
+        let mut builder = StatementBuilder::new();
+        builder.statement("sub a by;");
+        let (tree, diagnostics) = tree
+            .insert_statements(&InsertionPoint::Feature(Tag::new(b"liga")), &builder, None)
+            .unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].level, Level::Error);
+        let formatted = tree.format_diagnostic(&diagnostics[0], false);
+        assert!(formatted.contains(GENERATED_PATH), "{formatted}");
+        check_source_map(&tree);
+    }
+
+    #[test]
+    fn invalid_insertions() {
+        let tree = parse_fea(FEA);
+        let mut builder = StatementBuilder::new();
+        builder.sub(&["a".into()], &["b".into()]);
+        assert_eq!(
+            tree.insert_statements(&InsertionPoint::Feature(Tag::new(b"smcp")), &builder, None)
+                .unwrap_err(),
+            InsertError::MissingFeature(Tag::new(b"smcp"))
+        );
+        assert_eq!(
+            tree.insert_statements(&InsertionPoint::Lookup("nope".into()), &builder, None)
+                .unwrap_err(),
+            InsertError::MissingLookup("nope".into())
+        );
+        let mut builder = StatementBuilder::new();
+        builder.statement("} liga; feature smcp {");
+        assert_eq!(
+            tree.insert_statements(&InsertionPoint::Feature(Tag::new(b"liga")), &builder, None)
+                .unwrap_err(),
+            InsertError::UnbalancedBrace
+        );
+    }
+}
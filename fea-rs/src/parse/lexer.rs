@@ -12,9 +12,13 @@
 mod lexeme;
 mod token_set;
 
+use std::ops::Range;
+
 pub(crate) use lexeme::{Kind, Lexeme};
 pub use token_set::TokenSet;
 
+use crate::Kind as AstKind;
+
 const EOF: u8 = 0x0;
 
 pub(crate) struct Lexer<'a> {
@@ -106,6 +110,7 @@ impl<'a> Lexer<'a> {
             b'<' => Kind::LAngle,
             b'>' => Kind::RAngle,
             b'\'' => Kind::SingleQuote,
+            b'&' => Kind::Ampersand,
             b'n' | b'u' | b'd' if self.after_number_or_float => Kind::NumberSuffix,
             _ if self.in_path.in_path() => self.path(),
             _ => self.ident(),
@@ -256,6 +261,56 @@ impl<'a> Lexer<'a> {
     }
 }
 
+/// A single token produced by the lexer.
+///
+/// See [`tokenize`][super::tokenize] for more information.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawToken<'a> {
+    /// The kind of this token.
+    pub kind: AstKind,
+    /// The range of this token in the input, in bytes.
+    pub range: Range<usize>,
+    /// The text of this token.
+    pub text: &'a str,
+}
+
+/// An iterator over the raw tokens in a source.
+///
+/// This is returned by [`tokenize`][super::tokenize].
+pub struct RawTokens<'a> {
+    lexer: Lexer<'a>,
+}
+
+impl<'a> RawTokens<'a> {
+    pub(crate) fn new(text: &'a str) -> Self {
+        RawTokens {
+            lexer: Lexer::new(text),
+        }
+    }
+}
+
+impl<'a> Iterator for RawTokens<'a> {
+    type Item = RawToken<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.lexer.pos;
+        let lexeme = self.lexer.next_token();
+        let kind = match lexeme.kind {
+            Kind::Eof => return None,
+            // these are errors that are normally reported by the parser
+            Kind::StringUnterminated => AstKind::StringUnterminated,
+            Kind::HexEmpty => AstKind::HexEmpty,
+            other => other.to_token_kind(),
+        };
+        let range = start..start + lexeme.len;
+        Some(RawToken {
+            kind,
+            text: &self.lexer.input[range.clone()],
+            range,
+        })
+    }
+}
+
 #[cfg(test)]
 pub(crate) fn tokenize(text: &str) -> Vec<Lexeme> {
     iter_tokens(text).collect()
@@ -275,7 +330,7 @@ pub(crate) fn iter_tokens(text: &str) -> impl Iterator<Item = Lexeme> + '_ {
 
 // [\ , ' - ; < = > @ \ ( ) [ ] { }]
 fn is_special(byte: u8) -> bool {
-    (39..=45).contains(&byte)
+    (38..=45).contains(&byte)
         || (59..=64).contains(&byte)
         || (91..=93).contains(&byte)
         || byte == 123
@@ -344,6 +399,33 @@ mod tests {
         assert_eq!(token_strs[12], "FLOAT(-1.)");
     }
 
+    #[test]
+    fn raw_tokens() {
+        let fea = "sub a' by \\sub; # hi\n\"open";
+        let tokens = RawTokens::new(fea)
+            .map(|token| (token.kind, token.range, token.text))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            [
+                (AstKind::SubKw, 0..3, "sub"),
+                (AstKind::Whitespace, 3..4, " "),
+                (AstKind::Ident, 4..5, "a"),
+                (AstKind::SingleQuote, 5..6, "'"),
+                (AstKind::Whitespace, 6..7, " "),
+                (AstKind::ByKw, 7..9, "by"),
+                (AstKind::Whitespace, 9..10, " "),
+                (AstKind::Backslash, 10..11, "\\"),
+                (AstKind::Ident, 11..14, "sub"),
+                (AstKind::Semi, 14..15, ";"),
+                (AstKind::Whitespace, 15..16, " "),
+                (AstKind::Comment, 16..20, "# hi"),
+                (AstKind::Whitespace, 20..21, "\n"),
+                (AstKind::StringUnterminated, 21..26, "\"open"),
+            ]
+        );
+    }
+
     #[test]
     fn bad_numbers() {
         let fea = "-00 -0x1 -0x -ff";
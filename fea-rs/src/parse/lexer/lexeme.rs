@@ -57,6 +57,7 @@ pub enum Kind {
     LAngle,
     RAngle,
     SingleQuote,
+    Ampersand,
 
     NamedGlyphClass,
     Cid,
@@ -315,6 +316,7 @@ impl Kind {
             Self::LAngle => AstKind::LAngle,
             Self::RAngle => AstKind::RAngle,
             Self::SingleQuote => AstKind::SingleQuote,
+            Self::Ampersand => AstKind::Ampersand,
             Self::Comment => AstKind::Comment,
             Self::Path => AstKind::Path,
             Self::NamedGlyphClass => AstKind::NamedGlyphClass,
@@ -443,6 +445,7 @@ impl std::fmt::Display for Kind {
             Self::LAngle => write!(f, "<"),
             Self::RAngle => write!(f, ">"),
             Self::SingleQuote => write!(f, "'"),
+            Self::Ampersand => write!(f, "&"),
             Self::Comment => write!(f, "#"),
 
             Self::Path => write!(f, "Path"),
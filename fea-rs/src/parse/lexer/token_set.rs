@@ -13,6 +13,13 @@ impl TokenSet {
 
     pub(crate) const SEMI: TokenSet = TokenSet::new(&[Kind::Semi]);
 
+    /// The optional flags after the tag in a `language` statement.
+    pub(crate) const LANGUAGE_FLAGS: TokenSet =
+        TokenSet::new(&[Kind::ExcludeDfltKw, Kind::IncludeDfltKw, Kind::RequiredKw]);
+
+    pub(crate) const DFLT_FLAGS: TokenSet =
+        TokenSet::new(&[Kind::ExcludeDfltKw, Kind::IncludeDfltKw]);
+
     pub(crate) const SEMI_RBRACE: TokenSet = TokenSet::new(&[Kind::Semi, Kind::RBrace]);
 
     pub(crate) const TOP_LEVEL: TokenSet = TokenSet::new(&[
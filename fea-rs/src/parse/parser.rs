@@ -11,7 +11,7 @@ use super::{
 };
 use crate::token_tree::{AstSink, Kind};
 
-use crate::diagnostic::Diagnostic;
+use crate::diagnostic::{Diagnostic, DiagnosticCode, Level};
 
 const LOOKAHEAD: usize = 4;
 const LOOKAHEAD_MAX: usize = LOOKAHEAD - 1;
@@ -30,6 +30,8 @@ pub struct Parser<'a, 'b> {
     sink: &'b mut AstSink<'a>,
     text: &'a str,
     buf: [PendingToken; LOOKAHEAD],
+    // true if the last token consumed was a ';'
+    after_semi: bool,
 }
 
 /// A non-trivia token, as well as any trivia preceding that token.
@@ -73,6 +75,7 @@ impl<'b, 'a> Parser<'a, 'b> {
             sink,
             text,
             buf: [PendingToken::EMPTY; LOOKAHEAD],
+            after_semi: false,
         };
 
         // preload the buffer; this accumulates any errors
@@ -134,6 +137,7 @@ impl<'b, 'a> Parser<'a, 'b> {
             self.advance();
         }
         self.sink.token(kind, len);
+        self.after_semi = kind == Kind::Semi;
     }
 
     fn advance(&mut self) {
@@ -272,9 +276,29 @@ impl<'b, 'a> Parser<'a, 'b> {
         self.sink.error(err);
     }
 
+    /// The number of errors (not warnings) reported so far.
+    pub(crate) fn error_count(&self) -> usize {
+        self.sink.error_count()
+    }
+
+    /// Skip the remainder of a statement that contained an error.
+    ///
+    /// Without this, whatever is left of a malformed statement is reported
+    /// again as one or more invalid statements. If the statement was not
+    /// already terminated, we consume tokens up to and including the next ';',
+    /// stopping early at a closing brace or at any token in `recovery`.
+    pub(crate) fn resync_after_error(&mut self, errors_before: usize, recovery: TokenSet) {
+        if self.error_count() == errors_before || self.after_semi {
+            return;
+        }
+        self.eat_until(recovery.union(TokenSet::SEMI_RBRACE));
+        self.eat(LexemeKind::Semi);
+    }
+
     /// write a warning, do not advance
-    pub(crate) fn warn(&mut self, message: impl Into<String>) {
-        let err = Diagnostic::warning(FileId::CURRENT_FILE, self.nth_range(0), message);
+    pub(crate) fn warn(&mut self, code: DiagnosticCode, message: impl Into<String>) {
+        let err =
+            Diagnostic::warning(FileId::CURRENT_FILE, self.nth_range(0), message).with_code(code);
         self.sink.error(err);
     }
 
@@ -286,13 +310,21 @@ impl<'b, 'a> Parser<'a, 'b> {
         self.raw_error(pos..pos + 1, error);
     }
 
-    /// Write a *warning* before the whitespace of the associated token.
+    /// Report a missing semicolon, suggesting an edit to insert it.
     ///
-    /// This only exists so we can warn if a semi is missing after an include
-    /// statement (which is common in the wild)
-    pub(crate) fn warn_before_ws(&mut self, error: impl Into<String>) {
+    /// Like [`err_before_ws`][Self::err_before_ws], the location is before the
+    /// whitespace of the current token. This can be a warning, since a missing
+    /// semi after an include statement is common in the wild.
+    pub(crate) fn missing_semi(
+        &mut self,
+        level: Level,
+        code: Option<DiagnosticCode>,
+        message: impl Into<String>,
+    ) {
         let pos = self.buf[0].start_pos;
-        let diagnostic = Diagnostic::warning(FileId::CURRENT_FILE, pos..pos + 1, error);
+        let mut diagnostic = Diagnostic::new(level, FileId::CURRENT_FILE, pos..pos + 1, message)
+            .with_edit(pos..pos, ";");
+        diagnostic.code = code;
         self.sink.error(diagnostic);
     }
 
@@ -309,7 +341,7 @@ impl<'b, 'a> Parser<'a, 'b> {
     /// and we want to include whitespace in the range (i.e, it hugs the previous line).
     pub(crate) fn expect_semi(&mut self) -> bool {
         if !self.eat(LexemeKind::Semi) {
-            self.err_before_ws("Expected ';'");
+            self.missing_semi(Level::Error, None, "Expected ';'");
             return false;
         }
         true
@@ -0,0 +1,624 @@
+//! Updating a parse tree after an edit, without reparsing everything.
+//!
+//! When a source is edited, we find the smallest sequence of statements that
+//! contains the edit (descending into feature and lookup blocks where possible)
+//! and parse only the new text of those statements.
+//!
+//! Parsing is mostly context free at statement boundaries, but an edit can
+//! change how the *following* text is parsed; for instance by removing a
+//! closing brace. To detect this, we always also reparse the next statement
+//! after the edit, and check that its tree and diagnostics are unchanged. If
+//! they are not, we include it in the edited region and try again with the
+//! statement after that.
+
+use std::{ops::Range, sync::Arc};
+
+use super::{grammar, FileId, ParseTree, Parser, ParserOptions};
+use crate::{
+    token_tree::{AstSink, Kind},
+    Diagnostic, GlyphMap, Level, Node, NodeOrToken, TokenSet,
+};
+
+/// The result of a successful reparse.
+pub(crate) struct Reparse {
+    pub(crate) tree: ParseTree,
+    pub(crate) diagnostics: Vec<Diagnostic>,
+    /// The range in the original source that was reparsed.
+    #[cfg(test)]
+    pub(crate) reparsed_range: Range<usize>,
+}
+
+/// The grammar used for the children of a node.
+#[derive(Clone, Copy)]
+enum Grammar {
+    Root,
+    Statements { recovery: TokenSet, in_lookup: bool },
+}
+
+struct ReparseCtx<'a> {
+    file: FileId,
+    /// The original text of the edited source.
+    text: &'a str,
+    /// The range in the combined tree of the contiguous part of the source
+    /// that contains the edit.
+    chunk: Range<usize>,
+    /// The offset in the source of the start of `chunk`.
+    chunk_offset: usize,
+    /// `true` if `chunk` ends at the end of the source.
+    chunk_at_eof: bool,
+    /// The edited range, in the combined tree.
+    edit_range: Range<usize>,
+    new_text: &'a str,
+    glyph_map: Option<&'a GlyphMap>,
+    options: &'a ParserOptions,
+    /// The original diagnostics for this source.
+    diagnostics: Vec<&'a Diagnostic>,
+}
+
+/// A node with some of its children reparsed.
+struct ReparsedNode {
+    node: Node,
+    /// The replaced range, in the original combined tree.
+    range: Range<usize>,
+    /// Diagnostics for the reparsed text, relative to the edited source.
+    diagnostics: Vec<Diagnostic>,
+}
+
+pub(crate) fn reparse(
+    tree: &ParseTree,
+    file: FileId,
+    range: Range<usize>,
+    new_text: &str,
+    glyph_map: Option<&GlyphMap>,
+    diagnostics: &[Diagnostic],
+) -> Option<Reparse> {
+    let source = tree.get_source(file)?;
+    let text = source.text();
+    if range.start > range.end || text.get(range.clone()).is_none() {
+        return None;
+    }
+    let (chunk, chunk_offset) = tree.map.chunk_for_local_range(file, range.clone())?;
+    let edit_start = chunk.start + (range.start - chunk_offset);
+    let ctx = ReparseCtx {
+        file,
+        text,
+        chunk_at_eof: chunk_offset + chunk.len() == text.len(),
+        chunk,
+        chunk_offset,
+        edit_range: edit_start..edit_start + range.len(),
+        new_text,
+        glyph_map,
+        options: &tree.options,
+        diagnostics: diagnostics
+            .iter()
+            .filter(|diag| diag.message.file == file)
+            .collect(),
+    };
+
+    let reparsed = ctx.reparse_node(tree.root(), Grammar::Root)?;
+    let reparsed_range = ctx.to_local(reparsed.range.clone());
+    let delta = new_text.len() as isize - range.len() as isize;
+
+    let mut diagnostics = diagnostics
+        .iter()
+        .filter(|diag| diag.message.file != file || !in_range(diag, &reparsed_range))
+        .cloned()
+        .map(|mut diag| {
+            if diag.message.file == file && diag.span().start >= reparsed_range.end {
+                diag.shift(delta);
+            }
+            diag
+        })
+        .chain(reparsed.diagnostics)
+        .collect::<Vec<_>>();
+    diagnostics.sort_by_key(|diag| (diag.message.file, diag.span().start));
+    // which errors are reported depends on the errors before them, in every
+    // file; once the limit is reached, everything needs to be parsed again.
+    let n_errors = diagnostics.iter().filter(|diag| diag.is_error()).count();
+    if tree.options.max_errors.is_some_and(|max| n_errors >= max) {
+        return None;
+    }
+
+    let mut new_source = String::with_capacity(text.len() + new_text.len());
+    new_source.push_str(&text[..range.start]);
+    new_source.push_str(new_text);
+    new_source.push_str(&text[range.end..]);
+    let mut sources = (*tree.sources).clone();
+    sources.replace(source.with_text(new_source.into()));
+    let mut map = (*tree.map).clone();
+    map.adjust_for_edit(ctx.edit_range.clone(), new_text.len());
+
+    Some(Reparse {
+        tree: ParseTree {
+            root: reparsed.node,
+            sources: Arc::new(sources),
+            map: Arc::new(map),
+            root_id: tree.root_id,
+            options: tree.options.clone(),
+        },
+        diagnostics,
+        #[cfg(test)]
+        reparsed_range,
+    })
+}
+
+impl ReparseCtx<'_> {
+    fn to_local(&self, range: Range<usize>) -> Range<usize> {
+        let start = range.start - self.chunk.start + self.chunk_offset;
+        start..start + range.len()
+    }
+
+    fn contains(outer: &Range<usize>, inner: &Range<usize>) -> bool {
+        outer.start <= inner.start && inner.end <= outer.end
+    }
+
+    /// Reparse the children of `node` that contain the edit.
+    ///
+    /// Returns `None` if this cannot be done at this level.
+    fn reparse_node(&self, node: &Node, grammar: Grammar) -> Option<ReparsedNode> {
+        let children = node
+            .iter_children()
+            .map(|child| (child.range(), child))
+            .collect::<Vec<_>>();
+        let touches_edit = |range: &Range<usize>| {
+            range.start <= self.edit_range.end && self.edit_range.start <= range.end
+        };
+        let first = children.iter().position(|(range, _)| touches_edit(range))?;
+        let last = children
+            .iter()
+            .rposition(|(range, _)| touches_edit(range))?;
+
+        // the children that we're allowed to reparse
+        let allowed = match grammar {
+            Grammar::Root => {
+                let start = children
+                    .iter()
+                    .position(|(range, _)| Self::contains(&self.chunk, range))?;
+                let end = children[start..]
+                    .iter()
+                    .position(|(range, _)| !Self::contains(&self.chunk, range))
+                    .map(|len| start + len)
+                    .unwrap_or(children.len());
+                start..end
+            }
+            // only the statements between the braces
+            Grammar::Statements { .. } => {
+                let lbrace = children
+                    .iter()
+                    .position(|(_, child)| child.kind() == Kind::LBrace)?;
+                let rbrace = children
+                    .iter()
+                    .rposition(|(_, child)| child.kind() == Kind::RBrace)?;
+                lbrace + 1..rbrace
+            }
+        };
+        if first < allowed.start || last >= allowed.end {
+            return None;
+        }
+
+        if first == last {
+            let (range, child) = &children[first];
+            if let Some(child_grammar) = grammar.for_child(child) {
+                if Self::contains(&self.chunk, range) {
+                    let reparsed = self.reparse_node(child.as_node().unwrap(), child_grammar);
+                    if let Some(reparsed) = reparsed {
+                        return Some(ReparsedNode {
+                            node: node.splice_children(
+                                first..first + 1,
+                                [reparsed.node.into()],
+                                false,
+                            ),
+                            ..reparsed
+                        });
+                    }
+                }
+            }
+        }
+
+        // start after a node, so we know we're at a statement boundary
+        let mut first = first;
+        while first > allowed.start && children[first - 1].1.is_token() {
+            first -= 1;
+        }
+
+        let mut last = last;
+        loop {
+            let sentinel = (last + 1..allowed.end).find(|idx| !children[*idx].1.is_token());
+            let end = match sentinel {
+                Some(idx) => idx,
+                None if matches!(grammar, Grammar::Root)
+                    && self.chunk_at_eof
+                    && children[allowed.end - 1].0.end == self.chunk.end =>
+                {
+                    allowed.end - 1
+                }
+                // the parser may need to see text beyond the end of this node
+                None => return None,
+            };
+            let range = children[first].0.start..children[end].0.end;
+            let (new_node, diagnostics) = self.parse(range.clone(), grammar)?;
+            let unchanged = sentinel
+                .map(|idx| {
+                    self.sentinel_unchanged(&children[idx], range.start, &new_node, &diagnostics)
+                })
+                .unwrap_or(true);
+            if unchanged {
+                let new_children = new_node.iter_children().cloned();
+                return Some(ReparsedNode {
+                    node: node.splice_children(first..end + 1, new_children, new_node.error),
+                    range,
+                    diagnostics,
+                });
+            }
+            last = end;
+        }
+    }
+
+    /// Parse the range in the combined tree, with the edit applied.
+    fn parse(&self, range: Range<usize>, grammar: Grammar) -> Option<(Node, Vec<Diagnostic>)> {
+        let local_range = self.to_local(range.clone());
+        let local_edit = self.to_local(self.edit_range.clone());
+        let text = [
+            &self.text[local_range.start..local_edit.start],
+            self.new_text,
+            &self.text[local_edit.end..local_range.end],
+        ]
+        .concat();
+
+        let mut sink = AstSink::new(&text, self.file, self.glyph_map)
+            .with_strict_glyph_names(self.options.strict_glyph_names)
+            .with_max_errors(self.options.max_errors);
+        let finished = {
+            let mut parser = Parser::new(&text, &mut sink);
+            match grammar {
+                Grammar::Root => {
+                    grammar::root(&mut parser);
+                    true
+                }
+                Grammar::Statements {
+                    recovery,
+                    in_lookup,
+                } => grammar::block_statements(&mut parser, recovery, in_lookup),
+            }
+        };
+        let (node, mut diagnostics, includes) = sink.finish();
+        // new include statements would need to be resolved
+        if !finished || !includes.is_empty() {
+            return None;
+        }
+        for diag in diagnostics.iter_mut() {
+            diag.message.file = self.file;
+            diag.shift(local_range.start as isize);
+        }
+        Some((node, diagnostics))
+    }
+
+    /// `true` if the sentinel (the last reparsed item) has the same tree and
+    /// diagnostics as before the edit.
+    fn sentinel_unchanged(
+        &self,
+        (old_range, old): &(Range<usize>, &NodeOrToken),
+        reparse_start: usize,
+        new_node: &Node,
+        new_diagnostics: &[Diagnostic],
+    ) -> bool {
+        let Some(new) = new_node.iter_children().last() else {
+            return false;
+        };
+        // the new node's positions are relative to the start of the reparse
+        let new_start = self.to_local(reparse_start..reparse_start).start + new.range().start;
+        let new_local = new_start..new_start + new.text_len();
+        let old_local = self.to_local(old_range.clone());
+        old.same_structure(new)
+            && relative_diagnostics(self.diagnostics.iter().copied(), &old_local)
+                == relative_diagnostics(new_diagnostics, &new_local)
+    }
+}
+
+impl Grammar {
+    /// The grammar for a child node, if we can reparse its children.
+    fn for_child(self, child: &NodeOrToken) -> Option<Grammar> {
+        match (child.kind(), self) {
+            (Kind::FeatureNode, Grammar::Root) => Some(Grammar::Statements {
+                recovery: TokenSet::FEATURE_STATEMENT,
+                in_lookup: false,
+            }),
+            (Kind::LookupBlockNode, Grammar::Root) => Some(Grammar::Statements {
+                recovery: TokenSet::TOP_LEVEL.union(TokenSet::STATEMENT),
+                in_lookup: true,
+            }),
+            (Kind::LookupBlockNode, Grammar::Statements { in_lookup, .. }) if !in_lookup => {
+                Some(Grammar::Statements {
+                    recovery: TokenSet::FEATURE_STATEMENT.union(TokenSet::STATEMENT),
+                    in_lookup: true,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+// empty ranges (such as for a missing semicolon) belong to the item they follow
+fn in_range(diag: &Diagnostic, range: &Range<usize>) -> bool {
+    let span = diag.span();
+    if span.is_empty() {
+        range.start < span.start && span.start <= range.end
+    } else {
+        range.start <= span.start && span.start < range.end
+    }
+}
+
+fn relative_diagnostics<'a>(
+    diagnostics: impl IntoIterator<Item = &'a Diagnostic>,
+    range: &Range<usize>,
+) -> Vec<(Level, &'a str, Range<usize>)> {
+    let mut result = diagnostics
+        .into_iter()
+        .filter(|diag| in_range(diag, range))
+        .map(|diag| {
+            let span = diag.span();
+            (
+                diag.level,
+                diag.text(),
+                span.start - range.start..span.end - range.start,
+            )
+        })
+        .collect::<Vec<_>>();
+    result.sort_by(|a, b| (a.2.start, a.2.end, a.1).cmp(&(b.2.start, b.2.end, b.1)));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, ffi::OsStr};
+
+    use super::*;
+    use crate::parse::SourceLoadError;
+
+    static FEA: &str = "\
+languagesystem DFLT dflt;
+@lower = [a b c];
+
+feature liga {
+    sub f i by f_i;
+    sub f l by f_l;
+    lookup ligs {
+        sub f f by f_f;
+        sub f f i by f_f_i;
+    } ligs;
+} liga;
+
+# kerning
+feature kern {
+    pos a b 5;
+    pos @lower d -10;
+} kern;
+";
+
+    fn parse_files(files: &[(&str, &str)]) -> (ParseTree, Vec<Diagnostic>) {
+        let files: HashMap<_, _> = files
+            .iter()
+            .map(|(path, text)| (path.to_string(), Arc::<str>::from(*text)))
+            .collect();
+        let resolver = move |path: &OsStr| {
+            let path = path.to_str().unwrap();
+            files
+                .get(path)
+                .cloned()
+                .ok_or_else(|| SourceLoadError::new(path.into(), "not found"))
+        };
+        crate::parse::parse_root(files_root(), None, resolver).unwrap()
+    }
+
+    fn files_root() -> std::ffi::OsString {
+        "root.fea".into()
+    }
+
+    fn file_id(tree: &ParseTree, text: &str) -> FileId {
+        let offset = tree.root().text_len() - 1;
+        let mut id = tree.source_map().resolve_range(offset..offset + 1).0;
+        // find the file with this text
+        for token in tree.root().iter_tokens() {
+            let candidate = tree.source_map().resolve_range(token.range()).0;
+            if tree.get_source(candidate).unwrap().text() == text {
+                id = candidate;
+                break;
+            }
+        }
+        id
+    }
+
+    fn simple_diagnostics(diagnostics: &[Diagnostic]) -> Vec<(Level, String, Range<usize>)> {
+        let mut result = diagnostics
+            .iter()
+            .map(|diag| (diag.level, diag.text().to_string(), diag.span()))
+            .collect::<Vec<_>>();
+        result.sort_by_key(|(_, text, span)| (span.start, span.end, text.clone()));
+        result
+    }
+
+    // every token in the tree should match the text it maps to in its source
+    fn check_source_map(tree: &ParseTree) {
+        for token in tree.root().iter_tokens().filter(|t| !t.as_str().is_empty()) {
+            let (file, range) = tree.source_map().resolve_range(token.range());
+            let text = tree.get_source(file).unwrap().text();
+            assert_eq!(&text[range], token.as_str());
+        }
+    }
+
+    /// Apply an edit to one file and check that the result is the same as
+    /// parsing the edited files from scratch.
+    ///
+    /// Returns the range of the source that was reparsed, or `None` if the
+    /// edit could not be applied incrementally.
+    fn check_edit(
+        files: &[(&str, &str)],
+        edit_file: &str,
+        range: Range<usize>,
+        new_text: &str,
+    ) -> Option<String> {
+        let (tree, diagnostics) = parse_files(files);
+        let text = files.iter().find(|f| f.0 == edit_file).unwrap().1;
+        let file = file_id(&tree, text);
+        let reparse = reparse(&tree, file, range.clone(), new_text, None, &diagnostics)?;
+
+        let mut edited = text.to_string();
+        edited.replace_range(range.clone(), new_text);
+        let edited_files = files
+            .iter()
+            .map(|(path, text)| (*path, if *path == edit_file { &edited } else { *text }))
+            .collect::<Vec<_>>();
+        let (expected, expected_diagnostics) = parse_files(&edited_files);
+        let message = format!("{range:?} -> {new_text:?}");
+        assert_eq!(
+            reparse.tree.root().simple_parse_tree(),
+            expected.root().simple_parse_tree(),
+            "{message}"
+        );
+        assert_eq!(
+            simple_diagnostics(&reparse.diagnostics),
+            simple_diagnostics(&expected_diagnostics),
+            "{message}"
+        );
+        assert_eq!(reparse.tree.get_source(file).unwrap().text(), edited);
+        check_source_map(&reparse.tree);
+        Some(
+            edited[reparse.reparsed_range.start
+                ..reparse.reparsed_range.end + new_text.len() - range.len()]
+                .to_string(),
+        )
+    }
+
+    fn check_root_edit(text: &str, range: Range<usize>, new_text: &str) -> Option<String> {
+        check_edit(&[("root.fea", text)], "root.fea", range, new_text)
+    }
+
+    fn range_of(text: &str, needle: &str) -> Range<usize> {
+        let start = text.find(needle).unwrap();
+        start..start + needle.len()
+    }
+
+    #[test]
+    fn edit_one_statement() {
+        let range = range_of(FEA, "f_f;");
+        let reparsed = check_root_edit(FEA, range.start..range.start + 3, "f_f_f").unwrap();
+        // the edited statement, and the following statement
+        assert_eq!(
+            reparsed.trim(),
+            "sub f f by f_f_f;\n        sub f f i by f_f_i;"
+        );
+    }
+
+    #[test]
+    fn edit_last_statement_in_block() {
+        let range = range_of(FEA, "-10");
+        let reparsed = check_root_edit(FEA, range, "-20").unwrap();
+        // we can't reparse the last statement in a block by itself, so we
+        // reparse the whole block.
+        assert!(reparsed
+            .trim_start()
+            .starts_with("# kerning\nfeature kern {"));
+        assert!(reparsed.trim_end().ends_with("} kern;"));
+    }
+
+    #[test]
+    fn remove_closing_brace() {
+        let range = range_of(FEA, "} ligs;");
+        let reparsed = check_root_edit(FEA, range.start..range.start + 1, "").unwrap();
+        assert!(reparsed.ends_with("} kern;\n"), "{reparsed}");
+    }
+
+    #[test]
+    fn adding_include_is_not_incremental() {
+        let range = range_of(FEA, "# kerning");
+        assert!(check_root_edit(FEA, range, "include(kern.fea);").is_none());
+    }
+
+    #[test]
+    fn edit_included_file() {
+        let root =
+            "languagesystem DFLT dflt;\ninclude(liga.fea);\nfeature kern { pos a b 5; } kern;\n";
+        let liga = "feature liga {\n    sub f i by f_i;\n    sub f l by f_l;\n} liga;\n";
+        let files = [("root.fea", root), ("liga.fea", liga)];
+        let range = range_of(liga, "f_i");
+        let reparsed = check_edit(&files, "liga.fea", range, "f_i_i").unwrap();
+        assert_eq!(reparsed.trim(), "sub f i by f_i_i;\n    sub f l by f_l;");
+
+        // an edit in the root file, after the include
+        let range = range_of(root, "5");
+        assert!(check_edit(&files, "root.fea", range, "15").is_some());
+    }
+
+    // make lots of small edits, and check that the results are always the
+    // same as a full parse.
+    #[test]
+    fn many_edits() {
+        let boundaries = (0..=FEA.len()).filter(|i| FEA.is_char_boundary(*i));
+        for pos in boundaries {
+            for insert in [";", "}", "{", "#", " ", "\n", "feature", "lookup x;", "sub"] {
+                check_root_edit(FEA, pos..pos, insert);
+            }
+            if pos < FEA.len() {
+                check_root_edit(FEA, pos..pos + 1, "");
+                check_root_edit(FEA, pos..(pos + 8).min(FEA.len()), "");
+            }
+        }
+    }
+
+    fn parse_with_options(
+        text: &'static str,
+        glyph_map: &GlyphMap,
+        options: &ParserOptions,
+    ) -> (ParseTree, Vec<Diagnostic>) {
+        let resolver = move |_: &OsStr| Ok(Arc::<str>::from(text));
+        crate::parse::parse_root_with_options(files_root(), Some(glyph_map), options, resolver)
+            .unwrap()
+    }
+
+    #[test]
+    fn reparse_uses_parser_options() {
+        let glyph_map: GlyphMap = ["a", "b", "c", "a-b"]
+            .into_iter()
+            .map(crate::GlyphName::new)
+            .collect();
+        let text = "feature test {\n    sub a by b;\n    sub b by c;\n} test;\n";
+        let options = ParserOptions::new().strict_glyph_names(true);
+        let (tree, diagnostics) = parse_with_options(text, &glyph_map, &options);
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+        let file = tree.root_id();
+        let range = range_of(text, "sub a");
+        let (_, diagnostics) = tree
+            .reparse_range(
+                file,
+                range.end - 1..range.end,
+                "a-b",
+                Some(&glyph_map),
+                &diagnostics,
+            )
+            .unwrap();
+        let [diagnostic] = diagnostics.as_slice() else {
+            panic!("unexpected diagnostics: {diagnostics:?}");
+        };
+        assert_eq!(diagnostic.level, Level::Error);
+    }
+
+    #[test]
+    fn reparse_with_max_errors() {
+        let glyph_map: GlyphMap = ["a", "b", "c"]
+            .into_iter()
+            .map(crate::GlyphName::new)
+            .collect();
+        let text = "feature test {\n    sub a by b;\n    sub b by c;\n} test;\n";
+        let options = ParserOptions::new().max_errors(1);
+        let (tree, diagnostics) = parse_with_options(text, &glyph_map, &options);
+        let file = tree.root_id();
+        let reparse = |edit: &str, new_text: &str| {
+            let range = range_of(text, edit);
+            tree.reparse_range(file, range, new_text, Some(&glyph_map), &diagnostics)
+        };
+        // below the limit, edits are incremental
+        assert!(reparse("sub b by c", "sub c by a").is_some());
+        // once it is reached, everything needs to be parsed again
+        assert!(reparse("sub b by c", "sub b by").is_none());
+    }
+}
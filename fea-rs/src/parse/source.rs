@@ -10,7 +10,7 @@ use std::{
     sync::Arc,
 };
 
-use crate::{util, Diagnostic};
+use crate::{util, DetachedDiagnostic, Diagnostic};
 
 /// Uniquely identifies a source file.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Ord, PartialOrd)]
@@ -39,6 +39,8 @@ pub struct Source {
 pub struct SourceList {
     ids: HashMap<OsString, FileId>,
     sources: HashMap<FileId, Source>,
+    // file -> (the file that first included it, the range of the statement)
+    included_from: HashMap<FileId, (FileId, Range<usize>)>,
 }
 
 pub(crate) struct SourceLoader {
@@ -54,12 +56,30 @@ pub struct SourceMap {
     offsets: Vec<(Range<usize>, (FileId, usize))>,
 }
 
-/// An error that occurs when trying .to load a source.
+/// An error that occurs when trying to load a source.
+///
+/// This is returned when the root source cannot be loaded. If an included
+/// source cannot be loaded, this error is instead reported as a diagnostic
+/// on the include statement, and parsing continues.
 #[derive(Clone, Debug, thiserror::Error)]
-#[error("Failed to load source at '{}': '{cause}'", Path::new(.path.as_os_str()).display())]
-pub struct SourceLoadError {
-    cause: Arc<str>,
-    path: OsString,
+#[allow(missing_docs)]
+pub enum SourceLoadError {
+    /// The file does not exist.
+    #[error("Failed to load source at '{}': file not found", Path::new(.path.as_os_str()).display())]
+    NotFound { path: OsString },
+    /// The file exists, but is not valid UTF-8.
+    #[error("Failed to load source at '{}': file is not valid UTF-8", Path::new(.path.as_os_str()).display())]
+    InvalidUtf8 { path: OsString },
+    /// Some other error occurred while reading the file.
+    #[error("Failed to load source at '{}': '{cause}'", Path::new(.path.as_os_str()).display())]
+    Io {
+        path: OsString,
+        #[source]
+        cause: Arc<std::io::Error>,
+    },
+    /// An error reported by a custom [`SourceResolver`].
+    #[error("Failed to load source at '{}': '{cause}'", Path::new(.path.as_os_str()).display())]
+    Other { path: OsString, cause: Arc<str> },
 }
 
 /// A trait that abstracts resolving a path.
@@ -69,8 +89,9 @@ pub struct SourceLoadError {
 /// can pass a closure or another custom implementation of this trait into the
 /// appropriate parse functions.
 ///
-/// If you need a custom resolver, you can either implement this trait for some
-/// custom type, or you can use a closure with the signature,
+/// If your sources are in memory, you can use a [`MemoryResolver`]. If you need
+/// a custom resolver, you can either implement this trait for some custom type,
+/// or you can use a closure with the signature,
 /// `|&OsStr| -> Result<String, SourceLoadError>`.
 pub trait SourceResolver {
     /// Return the contents of the utf-8 encoded file at the provided path.
@@ -135,6 +156,7 @@ where
 /// This is the common case.
 pub struct FileSystemResolver {
     project_root: PathBuf,
+    search_paths: Vec<PathBuf>,
 }
 
 impl FileSystemResolver {
@@ -144,7 +166,20 @@ impl FileSystemResolver {
     /// cases, it is likely the directory containing the root feature file.
     /// If the path is empty (i.e. ""), the current working directory is assumed.
     pub fn new(project_root: PathBuf) -> Self {
-        Self { project_root }
+        Self {
+            project_root,
+            search_paths: Vec::new(),
+        }
+    }
+
+    /// Additional directories in which to look for included files.
+    ///
+    /// An include path is first looked up relative to the project root and
+    /// then relative to the including file; if it is not found in either,
+    /// these directories are tried in order.
+    pub fn with_search_paths(mut self, search_paths: impl IntoIterator<Item = PathBuf>) -> Self {
+        self.search_paths.extend(search_paths);
+        self
     }
 }
 
@@ -152,22 +187,69 @@ impl SourceResolver for FileSystemResolver {
     fn get_contents(&self, path: &OsStr) -> Result<Arc<str>, SourceLoadError> {
         std::fs::read_to_string(path)
             .map(Into::into)
-            .map_err(|cause| SourceLoadError::new(path.into(), cause))
+            .map_err(|cause| SourceLoadError::from_io(path.into(), cause))
     }
 
     fn resolve_raw_path(&self, path: &OsStr, included_from: Option<&OsStr>) -> OsString {
         let path = Path::new(path);
         let included_from = included_from.map(Path::new).and_then(Path::parent);
-        util::paths::resolve_path(path, &self.project_root, included_from).into_os_string()
+        util::paths::resolve_path(path, &self.project_root, included_from, &self.search_paths)
+            .into_os_string()
     }
 
     fn canonicalize(&self, path: &OsStr) -> Result<OsString, SourceLoadError> {
         std::fs::canonicalize(path)
-            .map_err(|io_err| SourceLoadError::new(path.into(), io_err))
+            .map_err(|io_err| SourceLoadError::from_io(path.into(), io_err))
             .map(PathBuf::into_os_string)
     }
 }
 
+/// An implementation of [`SourceResolver`] for sources held in memory.
+///
+/// Sources are looked up using the exact path that refers to them: the root
+/// path passed to the parser, or the argument of an `include` statement. No
+/// normalization is performed, so `include(a.fea)` and `include(./a.fea)`
+/// refer to different sources.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryResolver {
+    sources: HashMap<OsString, Arc<str>>,
+}
+
+impl MemoryResolver {
+    /// Create a new, empty resolver.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a source at the provided path, returning any previous contents.
+    pub fn insert(
+        &mut self,
+        path: impl Into<OsString>,
+        contents: impl Into<Arc<str>>,
+    ) -> Option<Arc<str>> {
+        self.sources.insert(path.into(), contents.into())
+    }
+}
+
+impl<P: Into<OsString>, S: Into<Arc<str>>> FromIterator<(P, S)> for MemoryResolver {
+    fn from_iter<T: IntoIterator<Item = (P, S)>>(iter: T) -> Self {
+        let mut resolver = Self::new();
+        for (path, contents) in iter {
+            resolver.insert(path, contents);
+        }
+        resolver
+    }
+}
+
+impl SourceResolver for MemoryResolver {
+    fn get_contents(&self, path: &OsStr) -> Result<Arc<str>, SourceLoadError> {
+        self.sources
+            .get(path)
+            .cloned()
+            .ok_or_else(|| SourceLoadError::NotFound { path: path.into() })
+    }
+}
+
 impl FileId {
     /// A reserved FileId used during parsing.
     pub(crate) const CURRENT_FILE: FileId = FileId(unsafe { NonZeroU32::new_unchecked(1) });
@@ -190,6 +272,16 @@ impl Source {
         }
     }
 
+    /// Create a copy of this source with new contents, keeping the same id.
+    pub(crate) fn with_text(&self, contents: Arc<str>) -> Self {
+        Source {
+            id: self.id,
+            path: self.path.clone(),
+            line_offsets: line_offsets(&contents),
+            contents,
+        }
+    }
+
     /// The raw text for this source
     pub fn text(&self) -> &str {
         &self.contents
@@ -280,6 +372,102 @@ impl SourceMap {
         let len = global_range.end - global_range.start;
         (*file, range_start..range_start + len)
     }
+
+    /// The inverse of [`resolve_range`](Self::resolve_range): map a range in
+    /// a source file to a range in the combined tree.
+    ///
+    /// Returns `None` if the range is not in this map.
+    pub(crate) fn global_range(
+        &self,
+        file: FileId,
+        local_range: Range<usize>,
+    ) -> Option<Range<usize>> {
+        self.offsets
+            .iter()
+            .find(|(chunk, (chunk_file, local_offset))| {
+                // a range may be empty and at the very end of a chunk
+                *chunk_file == file
+                    && (*local_offset..=*local_offset + chunk.len()).contains(&local_range.start)
+            })
+            .map(|(chunk, (_, local_offset))| {
+                let start = chunk.start + (local_range.start - local_offset);
+                start..start + local_range.len()
+            })
+    }
+
+    /// Find the contiguous chunk of the combined tree that contains the whole
+    /// of a range in a source file.
+    ///
+    /// Returns the range of the chunk in the combined tree and its offset in
+    /// the source file.
+    pub(crate) fn chunk_for_local_range(
+        &self,
+        file: FileId,
+        local_range: Range<usize>,
+    ) -> Option<(Range<usize>, usize)> {
+        self.offsets
+            .iter()
+            .find(|(chunk, (chunk_file, local_offset))| {
+                *chunk_file == file
+                    && *local_offset <= local_range.start
+                    && local_range.end <= *local_offset + chunk.len()
+            })
+            .map(|(chunk, (_, local_offset))| (chunk.clone(), *local_offset))
+    }
+
+    /// Update the map after `len` bytes from the start of `file` have been
+    /// inserted into the combined tree at `global_pos`.
+    ///
+    /// The chunk containing `global_pos`, if any, is split around the new text.
+    pub(crate) fn insert_source(&mut self, global_pos: usize, len: usize, file: FileId) {
+        let mut offsets = Vec::with_capacity(self.offsets.len() + 2);
+        for (chunk, (chunk_file, local_offset)) in self.offsets.drain(..) {
+            if chunk.end <= global_pos {
+                offsets.push((chunk, (chunk_file, local_offset)));
+            } else if chunk.start >= global_pos {
+                offsets.push((
+                    chunk.start + len..chunk.end + len,
+                    (chunk_file, local_offset),
+                ));
+            } else {
+                let split = global_pos - chunk.start;
+                offsets.push((chunk.start..global_pos, (chunk_file, local_offset)));
+                offsets.push((
+                    global_pos + len..chunk.end + len,
+                    (chunk_file, local_offset + split),
+                ));
+            }
+        }
+        let idx = offsets.partition_point(|(chunk, _)| chunk.start < global_pos);
+        if len > 0 {
+            offsets.insert(idx, (global_pos..global_pos + len, (file, 0)));
+        }
+        self.offsets = offsets;
+    }
+
+    /// Update the map after the text at `global_range` has been replaced with
+    /// text of length `new_len`.
+    ///
+    /// The range must be contained in a single chunk.
+    pub(crate) fn adjust_for_edit(&mut self, global_range: Range<usize>, new_len: usize) {
+        let Some(idx) = self.offsets.iter().position(|(chunk, _)| {
+            chunk.start <= global_range.start && global_range.end <= chunk.end
+        }) else {
+            return;
+        };
+        let (edited_chunk, (edited_file, edited_offset)) = self.offsets[idx].clone();
+        let shift = |pos: usize| pos + new_len - global_range.len();
+        for (i, (chunk, (file, local_offset))) in self.offsets.iter_mut().enumerate() {
+            if i == idx {
+                chunk.end = shift(chunk.end);
+            } else if chunk.start >= edited_chunk.end {
+                *chunk = shift(chunk.start)..shift(chunk.end);
+            }
+            if *file == edited_file && *local_offset > edited_offset {
+                *local_offset = shift(*local_offset);
+            }
+        }
+    }
 }
 
 impl SourceLoader {
@@ -298,6 +486,23 @@ impl SourceLoader {
         self.sources.get(id)
     }
 
+    /// Record that `file` was included by the statement at `range` in `parent`.
+    pub(crate) fn record_include(&mut self, file: FileId, parent: FileId, range: Range<usize>) {
+        self.sources.included_from.insert(file, (parent, range));
+    }
+
+    /// `true` if `file` has been recorded as included by another file.
+    pub(crate) fn is_included(&self, file: FileId) -> bool {
+        self.sources.included_from.contains_key(&file)
+    }
+
+    /// The include statements that lead to `file`.
+    ///
+    /// See [`SourceList::include_chain`].
+    pub(crate) fn include_chain(&self, file: FileId) -> Vec<(&Source, usize)> {
+        self.sources.include_chain(file)
+    }
+
     /// Attempt to load the source at the provided path.
     ///
     /// This uses the [`SourceResolver`] that was passed in at construction time,
@@ -337,32 +542,151 @@ impl SourceList {
         self.sources.get(id)
     }
 
-    fn add(&mut self, canonical_path: OsString, source: Source) {
+    /// Replace an existing source with one that has the same id.
+    pub(crate) fn replace(&mut self, source: Source) {
+        self.sources.insert(source.id, source);
+    }
+
+    pub(crate) fn add(&mut self, canonical_path: OsString, source: Source) {
         self.ids.insert(canonical_path, source.id);
         self.sources.insert(source.id, source);
     }
 
+    /// The include statements that lead to `file`.
+    ///
+    /// Each item is the including source and the position of the include
+    /// statement in that source, starting with the statement that included
+    /// `file` and ending with a statement in the root source. This is empty
+    /// for the root source.
+    pub(crate) fn include_chain(&self, file: FileId) -> Vec<(&Source, usize)> {
+        let mut chain = Vec::new();
+        let mut current = file;
+        while let Some((parent, range)) = self.included_from.get(&current) {
+            // a file is only recorded the first time it is included, so this
+            // can't cycle; but be defensive
+            if chain.len() > self.sources.len() {
+                break;
+            }
+            let Some(source) = self.get(parent) else {
+                break;
+            };
+            chain.push((source, range.start));
+            current = *parent;
+        }
+        chain
+    }
+
+    /// A description of the start of a range in the tree, in the form
+    /// 'path:line', for referring to another statement in a message.
+    pub(crate) fn describe_location(&self, source_map: &SourceMap, range: Range<usize>) -> String {
+        let (file, range) = source_map.resolve_range(range);
+        let source = self.get(&file).unwrap();
+        let (line, _) = source.line_col_for_offset(range.start);
+        format!("{}:{line}", source.path().to_string_lossy())
+    }
+
     /// Generate a string suitable for presenting a [`Diagnostic`] to the user.
     ///
     /// This associates the message with the appropriate source location and
-    /// syntax highlighting.
+    /// syntax highlighting. If the diagnostic is in an included file, the
+    /// chain of include statements leading to that file is also shown.
     pub(crate) fn format_diagnostic(&self, err: &Diagnostic, colorize: bool) -> String {
         let mut s = String::new();
         let source = self.get(&err.message.file).unwrap();
-        crate::util::highlighting::write_diagnostic(&mut s, err, source, None, colorize);
+        let chain = self.include_chain(err.message.file);
+        crate::util::highlighting::write_diagnostic(&mut s, err, source, &chain, None, colorize);
         s
     }
+
+    /// Copy a [`Diagnostic`] and its source text into a [`DetachedDiagnostic`].
+    pub(crate) fn detach_diagnostic(&self, err: &Diagnostic) -> DetachedDiagnostic {
+        let source = self.get(&err.message.file).unwrap();
+        DetachedDiagnostic::new(err, source, &self.include_chain(err.message.file))
+    }
 }
 
 impl SourceLoadError {
-    /// Create a new [`SourceLoadError`].
+    /// Create a new [`SourceLoadError::Other`].
     ///
     /// The `cause` argument should be some error type that communicates the
-    /// cause of the failure.
+    /// cause of the failure. If the cause is an [`std::io::Error`], prefer
+    /// [`SourceLoadError::from_io`], which preserves the kind of error.
     pub fn new(path: OsString, cause: impl std::fmt::Display) -> Self {
-        Self {
-            cause: cause.to_string().into(),
+        Self::Other {
             path,
+            cause: cause.to_string().into(),
         }
     }
+
+    /// Create a new error from an [`std::io::Error`] encountered while loading
+    /// the source at `path`.
+    pub fn from_io(path: OsString, cause: std::io::Error) -> Self {
+        match cause.kind() {
+            std::io::ErrorKind::NotFound => Self::NotFound { path },
+            // this is what `read_to_string` returns for bad UTF-8
+            std::io::ErrorKind::InvalidData => Self::InvalidUtf8 { path },
+            _ => Self::Io {
+                path,
+                cause: Arc::new(cause),
+            },
+        }
+    }
+
+    /// The path of the source that could not be loaded.
+    pub fn path(&self) -> &OsStr {
+        match self {
+            Self::NotFound { path }
+            | Self::InvalidUtf8 { path }
+            | Self::Io { path, .. }
+            | Self::Other { path, .. } => path,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_error_kinds() {
+        let resolver = FileSystemResolver::new(PathBuf::new());
+        let missing = resolver.get_contents("i_am_quite_unlikely_to_exist.fea".as_ref());
+        assert!(matches!(missing, Err(SourceLoadError::NotFound { .. })));
+
+        let path = std::env::temp_dir().join("fea-rs-load-error-kinds.fea");
+        std::fs::write(&path, b"languagesystem DFLT dflt; # \xff\xfe").unwrap();
+        let invalid = resolver.get_contents(path.as_os_str());
+        std::fs::remove_file(&path).unwrap();
+        let err = invalid.unwrap_err();
+        assert!(matches!(err, SourceLoadError::InvalidUtf8 { .. }));
+        assert_eq!(err.path(), path.as_os_str());
+    }
+
+    #[test]
+    fn memory_resolver() {
+        let resolver: MemoryResolver = [
+            (
+                "features.fea",
+                "languagesystem DFLT dflt;\ninclude(kern.fea);\n",
+            ),
+            ("kern.fea", "feature kern { pos a b 10; } kern;\n"),
+        ]
+        .into_iter()
+        .collect();
+        let (tree, diagnostics) =
+            crate::parse::parse_root("features.fea".into(), None, resolver.clone()).unwrap();
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+        // the included feature is part of the tree
+        assert!(tree
+            .typed_root()
+            .statements()
+            .any(|item| item.kind() == crate::Kind::FeatureNode));
+
+        let (_, diagnostics) =
+            crate::parse::parse_root("kern.fea".into(), None, resolver.clone()).unwrap();
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+
+        let missing = resolver.get_contents("nope.fea".as_ref());
+        assert!(matches!(missing, Err(SourceLoadError::NotFound { .. })));
+    }
 }
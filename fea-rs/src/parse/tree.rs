@@ -1,10 +1,12 @@
 //! the result of a parsing operation
 
-use std::sync::Arc;
+use std::{ops::Range, sync::Arc};
 
 use super::source::Source;
-use super::{FileId, SourceList, SourceMap};
-use crate::{token_tree::typed, Diagnostic, Node};
+use super::{
+    FileId, InsertError, InsertionPoint, ParserOptions, SourceList, SourceMap, StatementBuilder,
+};
+use crate::{token_tree::typed, DetachedDiagnostic, Diagnostic, GlyphMap, Node};
 
 /// A fully parsed feature file, with attached imports and a sourcemap.
 ///
@@ -13,12 +15,29 @@ use crate::{token_tree::typed, Diagnostic, Node};
 ///
 /// This is cheap to clone, so it can be attached to diagnostics, allowing them
 /// to print themselves where needed.
+///
+/// A tree is `Send` and `Sync`, so it can be parsed once and then shared
+/// between threads; for instance to compile each master of a variable font
+/// in parallel, with [`Compiler::compile_tree`].
+///
+/// One caveat: a tree returned by [`reparse_range`] or [`insert_statements`]
+/// shares its unchanged nodes with the original tree, and each node caches
+/// its position in whichever tree it was last traversed in. Each of the two
+/// trees can be used from multiple threads, but they should not be traversed
+/// at the same time as each other.
+///
+/// [`reparse_range`]: ParseTree::reparse_range
+/// [`insert_statements`]: ParseTree::insert_statements
+/// [`Compiler::compile_tree`]: crate::Compiler::compile_tree
 #[derive(Clone, Debug)]
 pub struct ParseTree {
     pub(crate) root: Node,
     // Arc so we can send across threads
     pub(crate) sources: Arc<SourceList>,
     pub(crate) map: Arc<SourceMap>,
+    pub(crate) root_id: FileId,
+    /// The options used to parse the tree, for reparsing after edits
+    pub(crate) options: ParserOptions,
 }
 
 impl ParseTree {
@@ -37,16 +56,96 @@ impl ParseTree {
         &self.map
     }
 
+    /// The id of the root source file
+    pub fn root_id(&self) -> FileId {
+        self.root_id
+    }
+
     /// Return the source for this id, if it exists in the source map
     pub fn get_source(&self, id: FileId) -> Option<&Source> {
         self.sources.get(&id)
     }
 
+    /// Update the tree after replacing the text at `range` in the source `file`
+    /// with `new_text`.
+    ///
+    /// Only the statements affected by the edit are reparsed, which makes this
+    /// much cheaper than parsing the whole source again; this is intended for
+    /// things like editors, which need to update the tree after each change.
+    ///
+    /// The `glyph_map` should be the one used to parse this tree, and
+    /// `diagnostics` should be the diagnostics produced when it was parsed.
+    /// Returns the new tree, and the diagnostics for the new tree.
+    ///
+    /// The edited text is parsed with the same [`ParserOptions`] as the tree.
+    ///
+    /// Returns `None` if the edit cannot be applied incrementally, for instance
+    /// if it adds an include statement, if it is not contained within a
+    /// single file, or if the maximum number of errors is reached. In this
+    /// case the caller should parse the sources again.
+    pub fn reparse_range(
+        &self,
+        file: FileId,
+        range: Range<usize>,
+        new_text: &str,
+        glyph_map: Option<&GlyphMap>,
+        diagnostics: &[Diagnostic],
+    ) -> Option<(ParseTree, Vec<Diagnostic>)> {
+        super::reparse::reparse(self, file, range, new_text, glyph_map, diagnostics)
+            .map(|reparse| (reparse.tree, reparse.diagnostics))
+    }
+
+    /// Add the statements in `statements` to the tree at the point `at`.
+    ///
+    /// The statements are parsed and spliced into the tree, without reparsing
+    /// any existing sources; see the [`StatementBuilder`] docs for how they are
+    /// constructed. The generated text is stored as a new source, so that any
+    /// diagnostics for it can be formatted.
+    ///
+    /// The `glyph_map` should be the one used to parse this tree. Returns the
+    /// new tree and the diagnostics for the inserted statements.
+    ///
+    /// [`StatementBuilder`]: super::StatementBuilder
+    pub fn insert_statements(
+        &self,
+        at: &InsertionPoint,
+        statements: &StatementBuilder,
+        glyph_map: Option<&GlyphMap>,
+    ) -> Result<(ParseTree, Vec<Diagnostic>), InsertError> {
+        super::inject::insert(self, at, statements, glyph_map)
+    }
+
     /// Generate a string suitable for presenting a [`Diagnostic`] to the user.
     ///
     /// This associates the message with the appropriate source location and
-    /// syntax highlighting.
+    /// syntax highlighting. If the diagnostic is in an included file, the
+    /// include statements that lead to that file are listed after its location.
     pub fn format_diagnostic(&self, err: &Diagnostic, colorize: bool) -> String {
         self.sources.format_diagnostic(err, colorize)
     }
+
+    /// Copy a [`Diagnostic`] and the source it refers to into a
+    /// [`DetachedDiagnostic`], which can outlive this tree.
+    pub fn detach_diagnostic(&self, err: &Diagnostic) -> DetachedDiagnostic {
+        self.sources.detach_diagnostic(err)
+    }
+
+    /// Generate a string presenting a number of [`Diagnostic`]s to the user.
+    ///
+    /// Diagnostics are written in the order provided, separated by a blank line.
+    /// This is the same format used when printing errors from the compiler.
+    ///
+    /// When `colorize` is `false`, the output is plain text that depends only
+    /// on the diagnostics and the sources, and it is suitable for comparing
+    /// against expected output in tests.
+    pub fn format_diagnostics(&self, diagnostics: &[Diagnostic], colorize: bool) -> String {
+        let mut result = String::new();
+        for (i, diagnostic) in diagnostics.iter().enumerate() {
+            if i > 0 {
+                result.push('\n');
+            }
+            result.push_str(&self.format_diagnostic(diagnostic, colorize));
+        }
+        result
+    }
 }
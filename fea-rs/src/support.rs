@@ -0,0 +1,812 @@
+//! A machine-readable summary of the FEA syntax supported by this crate.
+//!
+//! Along with the [`support_matrix`], this includes the crate [`version`] and
+//! a set of [`Capabilities`], so that downstream tools can detect what is
+//! available at runtime, and degrade gracefully when using older versions.
+//!
+//! This is organized around the sections of the [feature file specification][spec],
+//! and is intended to let downstream tools check whether some construct is
+//! supported before relying on it (as well as for generating documentation.)
+//!
+//! Each entry includes a short example snippet; these are compiled in our tests,
+//! which ensures that this table stays in sync with the actual implementation.
+//!
+//! [spec]: https://github.com/adobe-type-tools/afdko/blob/develop/docs/OpenTypeFeatureFileSpecification.md
+
+/// A section of the feature file specification.
+///
+/// This also includes a few constructs (such as the variable font syntax)
+/// that are extensions to the spec, implemented by fonttools.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[non_exhaustive]
+#[allow(missing_docs)]
+pub enum SpecSection {
+    // 2. Syntax
+    MetricsAndAnchors,
+    GlyphClasses,
+    // 3.
+    IncludeFiles,
+    // 4. Specifying features
+    LanguageSystem,
+    Feature,
+    ScriptAndLanguage,
+    LookupFlag,
+    Lookup,
+    MarkClass,
+    Subtable,
+    // 5. GSUB
+    GsubSingle,
+    GsubMultiple,
+    GsubAlternate,
+    GsubLigature,
+    GsubContextual,
+    GsubChainingContextual,
+    GsubExtension,
+    GsubReverseChaining,
+    // 6. GPOS
+    GposSingle,
+    GposPair,
+    GposCursive,
+    GposMarkToBase,
+    GposMarkToLigature,
+    GposMarkToMark,
+    GposContextual,
+    GposChainingContextual,
+    GposExtension,
+    // 8. Specially handled features
+    AllAlternates,
+    OpticalSize,
+    StylisticSetNames,
+    CharacterVariant,
+    // 9. Specifying or overriding table values
+    BaseTable,
+    GdefTable,
+    HeadTable,
+    HheaTable,
+    NameTable,
+    Os2Table,
+    VheaTable,
+    VmtxTable,
+    StatTable,
+    // 10.
+    AnonymousBlocks,
+    // extensions
+    FeatureVariations,
+    VariableMetrics,
+}
+
+/// How well a given [`SpecSection`] is supported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SupportLevel {
+    /// The construct is parsed and compiled.
+    Supported,
+    /// The construct is accepted, but its output is incomplete.
+    ///
+    /// The entry's `note` field describes what is missing.
+    Partial,
+    /// The construct will produce an error.
+    Unsupported,
+}
+
+/// A single entry in the [`support_matrix`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SupportEntry {
+    /// The section of the spec
+    pub section: SpecSection,
+    /// How well that section is supported
+    pub level: SupportLevel,
+    /// Additional information, such as known limitations
+    pub note: Option<&'static str>,
+    /// A canonical FEA snippet exercising this section
+    pub example: &'static str,
+}
+
+/// Return a list describing our support for each section of the FEA spec.
+///
+/// There is exactly one entry per [`SpecSection`], in the order of the spec.
+pub fn support_matrix() -> &'static [SupportEntry] {
+    SUPPORT_MATRIX
+}
+
+/// The version of this crate, e.g. `"0.18.0"`.
+pub fn version() -> &'static str {
+    env!("CARGO_PKG_VERSION")
+}
+
+/// Features of the compiler that downstream tools may want to check for.
+///
+/// Returned by [`capabilities`]. New fields will be added as new features
+/// are implemented.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// Variable value records and anchors, e.g. `(wght=200:-10 wght=900:-20)`
+    pub variable_value_records: bool,
+    /// `conditionset` and `variation` blocks
+    pub feature_variations: bool,
+    /// The `aalt` feature
+    pub aalt: bool,
+    /// Lookups with the `useExtension` keyword
+    pub extension_lookups: bool,
+    /// Lenient compilation; see [`Compiler::with_lenient_mode`]
+    ///
+    /// [`Compiler::with_lenient_mode`]: crate::Compiler::with_lenient_mode
+    pub lenient_mode: bool,
+    /// Limits on included files; see [`Compiler::with_include_limits`]
+    ///
+    /// [`Compiler::with_include_limits`]: crate::Compiler::with_include_limits
+    pub include_limits: bool,
+    /// Compiling a previously parsed tree; see [`Compiler::compile_tree`]
+    ///
+    /// [`Compiler::compile_tree`]: crate::Compiler::compile_tree
+    pub compile_parsed_tree: bool,
+    /// Building fonts for shaping tests; see [`Compilation::to_test_font`]
+    ///
+    /// [`Compilation::to_test_font`]: crate::compile::Compilation::to_test_font
+    pub test_fonts: bool,
+    /// Modifying lookups before serialization; see [`Compiler::with_lookup_hook`]
+    ///
+    /// [`Compiler::with_lookup_hook`]: crate::Compiler::with_lookup_hook
+    pub lookup_hooks: bool,
+    /// Using mark classes before they are defined; see [`Compiler::with_late_mark_classes`]
+    ///
+    /// [`Compiler::with_late_mark_classes`]: crate::Compiler::with_late_mark_classes
+    pub late_mark_classes: bool,
+}
+
+/// Return the [`Capabilities`] of this version of the crate.
+///
+/// Capabilities that correspond to a section of the spec are supported if
+/// that section is fully or partially supported in the [`support_matrix`].
+pub fn capabilities() -> Capabilities {
+    let supported = |section| {
+        SUPPORT_MATRIX
+            .iter()
+            .any(|entry| entry.section == section && entry.level != SupportLevel::Unsupported)
+    };
+    Capabilities {
+        variable_value_records: supported(SpecSection::VariableMetrics),
+        feature_variations: supported(SpecSection::FeatureVariations),
+        aalt: supported(SpecSection::AllAlternates),
+        extension_lookups: supported(SpecSection::GsubExtension)
+            && supported(SpecSection::GposExtension),
+        lenient_mode: true,
+        include_limits: true,
+        compile_parsed_tree: true,
+        test_fonts: true,
+        lookup_hooks: true,
+        late_mark_classes: true,
+    }
+}
+
+impl SpecSection {
+    /// The number of this section in the spec, e.g. `"5.a"`
+    ///
+    /// Returns `None` for constructs that are extensions to the spec.
+    pub fn number(self) -> Option<&'static str> {
+        let number = match self {
+            SpecSection::MetricsAndAnchors => "2.e",
+            SpecSection::GlyphClasses => "2.g",
+            SpecSection::IncludeFiles => "3",
+            SpecSection::LanguageSystem => "4.a",
+            SpecSection::Feature => "4.b",
+            SpecSection::ScriptAndLanguage => "4.b.ii",
+            SpecSection::LookupFlag => "4.d",
+            SpecSection::Lookup => "4.e",
+            SpecSection::MarkClass => "4.f",
+            SpecSection::Subtable => "4.g",
+            SpecSection::GsubSingle => "5.a",
+            SpecSection::GsubMultiple => "5.b",
+            SpecSection::GsubAlternate => "5.c",
+            SpecSection::GsubLigature => "5.d",
+            SpecSection::GsubContextual => "5.e",
+            SpecSection::GsubChainingContextual => "5.f",
+            SpecSection::GsubExtension => "5.g",
+            SpecSection::GsubReverseChaining => "5.h",
+            SpecSection::GposSingle => "6.a",
+            SpecSection::GposPair => "6.b",
+            SpecSection::GposCursive => "6.c",
+            SpecSection::GposMarkToBase => "6.d",
+            SpecSection::GposMarkToLigature => "6.e",
+            SpecSection::GposMarkToMark => "6.f",
+            SpecSection::GposContextual => "6.g",
+            SpecSection::GposChainingContextual => "6.h",
+            SpecSection::GposExtension => "6.i",
+            SpecSection::AllAlternates => "8.a",
+            SpecSection::OpticalSize => "8.b",
+            SpecSection::StylisticSetNames => "8.c",
+            SpecSection::CharacterVariant => "8.d",
+            SpecSection::BaseTable => "9.a",
+            SpecSection::GdefTable => "9.b",
+            SpecSection::HeadTable => "9.c",
+            SpecSection::HheaTable => "9.d",
+            SpecSection::NameTable => "9.e",
+            SpecSection::Os2Table => "9.f",
+            SpecSection::VheaTable => "9.g",
+            SpecSection::VmtxTable => "9.h",
+            SpecSection::StatTable => "9.i",
+            SpecSection::AnonymousBlocks => "10",
+            SpecSection::FeatureVariations | SpecSection::VariableMetrics => return None,
+        };
+        Some(number)
+    }
+
+    /// A short, human-readable name for this section.
+    pub fn name(self) -> &'static str {
+        match self {
+            SpecSection::MetricsAndAnchors => "Value records, anchors and device tables",
+            SpecSection::GlyphClasses => "Glyph classes",
+            SpecSection::IncludeFiles => "Including files",
+            SpecSection::LanguageSystem => "languagesystem",
+            SpecSection::Feature => "feature",
+            SpecSection::ScriptAndLanguage => "script and language",
+            SpecSection::LookupFlag => "lookupflag",
+            SpecSection::Lookup => "lookup",
+            SpecSection::MarkClass => "markClass",
+            SpecSection::Subtable => "subtable",
+            SpecSection::GsubSingle => "Single substitution",
+            SpecSection::GsubMultiple => "Multiple substitution",
+            SpecSection::GsubAlternate => "Alternate substitution",
+            SpecSection::GsubLigature => "Ligature substitution",
+            SpecSection::GsubContextual => "Contextual substitution",
+            SpecSection::GsubChainingContextual => "Chaining contextual substitution",
+            SpecSection::GsubExtension => "Extension substitution",
+            SpecSection::GsubReverseChaining => "Reverse chaining single substitution",
+            SpecSection::GposSingle => "Single adjustment positioning",
+            SpecSection::GposPair => "Pair adjustment positioning",
+            SpecSection::GposCursive => "Cursive attachment positioning",
+            SpecSection::GposMarkToBase => "Mark-to-Base attachment positioning",
+            SpecSection::GposMarkToLigature => "Mark-to-Ligature attachment positioning",
+            SpecSection::GposMarkToMark => "Mark-to-Mark attachment positioning",
+            SpecSection::GposContextual => "Contextual positioning",
+            SpecSection::GposChainingContextual => "Chaining contextual positioning",
+            SpecSection::GposExtension => "Extension positioning",
+            SpecSection::AllAlternates => "aalt",
+            SpecSection::OpticalSize => "size",
+            SpecSection::StylisticSetNames => "Stylistic set names",
+            SpecSection::CharacterVariant => "Character variants",
+            SpecSection::BaseTable => "BASE table",
+            SpecSection::GdefTable => "GDEF table",
+            SpecSection::HeadTable => "head table",
+            SpecSection::HheaTable => "hhea table",
+            SpecSection::NameTable => "name table",
+            SpecSection::Os2Table => "OS/2 table",
+            SpecSection::VheaTable => "vhea table",
+            SpecSection::VmtxTable => "vmtx table",
+            SpecSection::StatTable => "STAT table",
+            SpecSection::AnonymousBlocks => "Anonymous data blocks",
+            SpecSection::FeatureVariations => "conditionset and variation blocks",
+            SpecSection::VariableMetrics => "Variable metrics",
+        }
+    }
+}
+
+macro_rules! entry {
+    ($section:ident, $level:ident, $example:expr) => {
+        entry!($section, $level, None, $example)
+    };
+    ($section:ident, $level:ident, $note:expr, $example:expr) => {
+        SupportEntry {
+            section: SpecSection::$section,
+            level: SupportLevel::$level,
+            note: $note,
+            example: $example,
+        }
+    };
+}
+
+static SUPPORT_MATRIX: &[SupportEntry] = &[
+    entry!(
+        MetricsAndAnchors,
+        Supported,
+        "valueRecordDef <1 2 3 4> VR;
+anchorDef 100 200 ANC;
+markClass acutecomb <anchor ANC> @TOP;
+feature kern {
+    pos a <VR>;
+    pos one <0 0 10 0 <device 11 -1> <device NULL> <device 11 1, 12 2> <device NULL>>;
+    pos base b <anchor 10 10 contourpoint 2> mark @TOP;
+    pos base c <anchor 10 10 <device 11 1> <device NULL>> mark @TOP;
+} kern;"
+    ),
+    entry!(
+        GlyphClasses,
+        Supported,
+        "@lower = [a - e];
+@all = [@lower f];
+feature test {
+    sub @all by A;
+} test;"
+    ),
+    entry!(
+        IncludeFiles,
+        Supported,
+        "include(other.fea);
+feature test {
+    sub @INCLUDED by A;
+} test;"
+    ),
+    entry!(
+        LanguageSystem,
+        Supported,
+        "languagesystem DFLT dflt;
+languagesystem latn dflt;
+languagesystem latn TRK;"
+    ),
+    entry!(
+        Feature,
+        Supported,
+        "feature liga {
+    sub f i by f_i;
+} liga;"
+    ),
+    entry!(
+        ScriptAndLanguage,
+        Supported,
+        "languagesystem DFLT dflt;
+languagesystem latn dflt;
+feature locl {
+    script latn;
+    language TRK exclude_dflt;
+    sub i by A;
+    language ROM;
+    sub a by B;
+} locl;"
+    ),
+    entry!(
+        LookupFlag,
+        Supported,
+        "markClass acutecomb <anchor 0 500> @TOP;
+feature test {
+    lookupflag RightToLeft IgnoreMarks;
+    sub a by A;
+    lookupflag MarkAttachmentType @TOP;
+    sub b by B;
+    lookupflag UseMarkFilteringSet [acutecomb];
+    sub c by A;
+} test;"
+    ),
+    entry!(
+        Lookup,
+        Supported,
+        "lookup L1 {
+    sub a by A;
+} L1;
+feature test {
+    lookup L1;
+    lookup L2 {
+        sub b by B;
+    } L2;
+} test;"
+    ),
+    entry!(
+        MarkClass,
+        Supported,
+        "markClass [acutecomb gravecomb] <anchor 0 500> @TOP;
+feature mark {
+    pos base a <anchor 250 500> mark @TOP;
+} mark;"
+    ),
+    entry!(
+        Subtable,
+        Supported,
+        "feature kern {
+    pos a b -10;
+    subtable;
+    pos a c -20;
+} kern;"
+    ),
+    entry!(
+        GsubSingle,
+        Supported,
+        "feature test {
+    sub a by A;
+    sub [b c] by [B A];
+} test;"
+    ),
+    entry!(
+        GsubMultiple,
+        Supported,
+        "feature test {
+    sub f_i by f i;
+} test;"
+    ),
+    entry!(
+        GsubAlternate,
+        Supported,
+        "feature test {
+    sub a from [A b c];
+} test;"
+    ),
+    entry!(
+        GsubLigature,
+        Supported,
+        "feature test {
+    sub f i by f_i;
+} test;"
+    ),
+    entry!(
+        GsubContextual,
+        Supported,
+        "lookup L1 {
+    sub a by A;
+} L1;
+feature test {
+    sub a' lookup L1 b';
+} test;"
+    ),
+    entry!(
+        GsubChainingContextual,
+        Supported,
+        "feature test {
+    sub b a' c by A;
+    sub [b c] f' i' by f_i;
+    ignore sub a a';
+} test;"
+    ),
+    entry!(
+        GsubExtension,
+        Supported,
+        "lookup L1 useExtension {
+    sub a by A;
+} L1;
+feature test {
+    lookup L1;
+} test;"
+    ),
+    entry!(
+        GsubReverseChaining,
+        Supported,
+        "feature test {
+    rsub a b' c by B;
+} test;"
+    ),
+    entry!(
+        GposSingle,
+        Supported,
+        "feature kern {
+    pos a -10;
+    pos [b c] <0 0 10 0>;
+} kern;"
+    ),
+    entry!(
+        GposPair,
+        Supported,
+        "@left = [a b];
+@right = [c d];
+feature kern {
+    pos a b -20;
+    enum pos @left d -10;
+    pos @left @right -5;
+} kern;"
+    ),
+    entry!(
+        GposCursive,
+        Supported,
+        "feature curs {
+    pos cursive a <anchor 0 0> <anchor 100 0>;
+    pos cursive b <anchor NULL> <anchor 200 0>;
+} curs;"
+    ),
+    entry!(
+        GposMarkToBase,
+        Supported,
+        "markClass acutecomb <anchor 0 500> @TOP;
+markClass gravecomb <anchor 0 -10> @BOTTOM;
+feature mark {
+    pos base [a b] <anchor 250 500> mark @TOP <anchor 250 0> mark @BOTTOM;
+} mark;"
+    ),
+    entry!(
+        GposMarkToLigature,
+        Supported,
+        "markClass acutecomb <anchor 0 500> @TOP;
+feature mark {
+    pos ligature f_i <anchor 100 500> mark @TOP
+        ligComponent <anchor 300 500> mark @TOP;
+} mark;"
+    ),
+    entry!(
+        GposMarkToMark,
+        Supported,
+        "markClass acutecomb <anchor 0 500> @TOP;
+feature mkmk {
+    pos mark gravecomb <anchor 0 600> mark @TOP;
+} mkmk;"
+    ),
+    entry!(
+        GposContextual,
+        Supported,
+        "lookup L1 {
+    pos a 10;
+} L1;
+feature test {
+    pos a' lookup L1 b';
+} test;"
+    ),
+    entry!(
+        GposChainingContextual,
+        Supported,
+        "feature test {
+    pos b a' 10 c;
+    pos [b c] a' <0 0 10 0> d';
+    ignore pos a a';
+} test;"
+    ),
+    entry!(
+        GposExtension,
+        Supported,
+        "lookup L1 useExtension {
+    pos a 10;
+} L1;
+feature test {
+    lookup L1;
+} test;"
+    ),
+    entry!(
+        AllAlternates,
+        Supported,
+        "feature aalt {
+    feature salt;
+    sub b from [B c];
+} aalt;
+feature salt {
+    sub a by A;
+} salt;"
+    ),
+    entry!(
+        OpticalSize,
+        Supported,
+        "feature size {
+    parameters 10.0 3 80 139;
+    sizemenuname \"Small\";
+} size;"
+    ),
+    entry!(
+        StylisticSetNames,
+        Supported,
+        "feature ss01 {
+    featureNames {
+        name \"Alternate a\";
+        name 1 \"Alternate a\";
+    };
+    sub a by A;
+} ss01;"
+    ),
+    entry!(
+        CharacterVariant,
+        Supported,
+        "feature cv01 {
+    cvParameters {
+        FeatUILabelNameID {
+            name \"Alternate a\";
+        };
+        ParamUILabelNameID {
+            name \"Sharp\";
+        };
+        Character 0x61;
+    };
+    sub a by A;
+} cv01;"
+    ),
+    entry!(
+        BaseTable,
+        Partial,
+        Some("MinMax records are parsed, but not compiled"),
+        "table BASE {
+    HorizAxis.BaseTagList ideo romn;
+    HorizAxis.BaseScriptList latn romn -120 0;
+} BASE;"
+    ),
+    entry!(
+        GdefTable,
+        Supported,
+        "table GDEF {
+    GlyphClassDef [a b], [f_i], [acutecomb], ;
+    Attach a 1;
+    LigatureCaretByPos f_i 300;
+} GDEF;"
+    ),
+    entry!(
+        HeadTable,
+        Supported,
+        "table head {
+    FontRevision 1.1;
+} head;"
+    ),
+    entry!(
+        HheaTable,
+        Supported,
+        "table hhea {
+    CaretOffset 0;
+    Ascender 800;
+    Descender -200;
+    LineGap 200;
+} hhea;"
+    ),
+    entry!(
+        NameTable,
+        Partial,
+        Some(
+            "names in encodings other than Windows Unicode (platform 3, encoding 0, 1 \
+            or 10) and Mac Roman (platform 1, encoding 0) are skipped, with a warning"
+        ),
+        "table name {
+    nameid 9 \"Joe Designer\";
+    nameid 9 1 0 0 \"Joe Designer\";
+} name;"
+    ),
+    entry!(
+        Os2Table,
+        Supported,
+        "table OS/2 {
+    FSType 4;
+    Panose 2 15 0 0 2 2 8 2 9 4;
+    TypoAscender 800;
+    TypoDescender -200;
+    winAscent 900;
+    UnicodeRange 0 1;
+    CodePageRange 1252;
+    Vendor \"ADBE\";
+} OS/2;"
+    ),
+    entry!(
+        VheaTable,
+        Supported,
+        "table vhea {
+    VertTypoAscender 500;
+    VertTypoDescender -500;
+    VertTypoLineGap 1000;
+} vhea;"
+    ),
+    entry!(
+        VmtxTable,
+        Partial,
+        Some(
+            "only applied to an existing font with a vmtx table; \
+            see Compilation::apply_to_font"
+        ),
+        "table vmtx {
+    VertOriginY a 880;
+    VertAdvanceY a 1000;
+} vmtx;"
+    ),
+    entry!(
+        StatTable,
+        Supported,
+        "table STAT {
+    ElidedFallbackName { name \"Regular\"; };
+    DesignAxis wght 0 { name \"Weight\"; };
+    AxisValue {
+        location wght 400;
+        name \"Regular\";
+        flag ElidableAxisValueName;
+    };
+} STAT;"
+    ),
+    entry!(
+        AnonymousBlocks,
+        Supported,
+        Some("the contents are not compiled; see Compilation::anon_blocks"),
+        "anon sbit {
+    some arbitrary text;
+} sbit;"
+    ),
+    entry!(
+        FeatureVariations,
+        Supported,
+        Some("requires variation info to be provided to the compiler"),
+        "conditionset heavy {
+    wght 700 1000;
+} heavy;
+variation rvrn heavy {
+    sub a by A;
+} rvrn;"
+    ),
+    entry!(
+        VariableMetrics,
+        Partial,
+        Some(
+            "requires variation info to be provided to the compiler; \
+            not supported in the hhea, vhea and OS/2 tables"
+        ),
+        "feature kern {
+    pos a b (wght=200:-10 wght=900:-20);
+    pos c <0 (wght=200:0 wght=900:10) 0 0>;
+} kern;"
+    ),
+];
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, ffi::OsStr, sync::Arc};
+
+    use super::*;
+    use crate::{
+        compile::MockVariationInfo, parse::SourceLoadError, Compiler, GlyphMap, GlyphName,
+    };
+
+    static GLYPHS: &[&str] = &[
+        ".notdef",
+        "a",
+        "b",
+        "c",
+        "d",
+        "e",
+        "f",
+        "i",
+        "f_i",
+        "A",
+        "B",
+        "one",
+        "acutecomb",
+        "gravecomb",
+    ];
+
+    fn compile_example(example: &'static str) -> bool {
+        let glyph_map: GlyphMap = GLYPHS.iter().copied().map(GlyphName::new).collect();
+        let var_info = MockVariationInfo::new(&[("wght", 200, 200, 1000)]);
+        let resolver = move |path: &OsStr| -> Result<Arc<str>, SourceLoadError> {
+            match path.to_str() {
+                Some("other.fea") => Ok("@INCLUDED = [a b];".into()),
+                _ => Ok(example.into()),
+            }
+        };
+        Compiler::new("example.fea", &glyph_map)
+            .with_resolver(resolver)
+            .with_variable_info(&var_info)
+            .print_warnings(false)
+            .compile_binary()
+            .map_err(|e| eprintln!("{e}"))
+            .is_ok()
+    }
+
+    #[test]
+    fn one_entry_per_section() {
+        let mut seen = HashSet::new();
+        for entry in support_matrix() {
+            assert!(seen.insert(entry.section), "duplicate {:?}", entry.section);
+        }
+        // a crude check that we've got everything: the last variant is last
+        assert_eq!(
+            support_matrix().last().map(|entry| entry.section),
+            Some(SpecSection::VariableMetrics)
+        );
+        assert!(support_matrix()
+            .windows(2)
+            .all(|pair| pair[0].section < pair[1].section));
+    }
+
+    #[test]
+    fn examples_match_support_level() {
+        for entry in support_matrix() {
+            let compiled = compile_example(entry.example);
+            let expected = entry.level != SupportLevel::Unsupported;
+            assert_eq!(
+                compiled,
+                expected,
+                "{} ({:?}): expected {:?}",
+                entry.section.name(),
+                entry.section,
+                entry.level
+            );
+        }
+    }
+
+    #[test]
+    fn version_and_capabilities() {
+        assert_eq!(version(), env!("CARGO_PKG_VERSION"));
+        let capabilities = capabilities();
+        assert!(capabilities.variable_value_records);
+        assert!(capabilities.aalt);
+        assert!(capabilities.extension_lookups);
+    }
+
+    #[test]
+    fn partial_entries_have_notes() {
+        assert!(support_matrix()
+            .iter()
+            .filter(|entry| entry.level != SupportLevel::Supported)
+            .all(|entry| entry.note.is_some()));
+    }
+}
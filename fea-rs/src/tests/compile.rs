@@ -5,7 +5,7 @@ use std::path::{Path, PathBuf};
 use crate::{
     compile::{error::CompilerError, Compiler, MockVariationInfo, Opts},
     util::ttx::{self as test_utils, Report, TestCase, TestResult},
-    GlyphMap, GlyphName,
+    GlyphMap, GlyphName, ParseTree,
 };
 
 static ROOT_TEST_DIR: &str = "./test-data/compile-tests";
@@ -60,6 +60,95 @@ fn should_pass() -> Result<(), Report> {
     test_utils::finalize_results(results).into_error()
 }
 
+// parse each source once, and then compile it on multiple threads at once
+#[test]
+fn parallel_compile() {
+    const N_THREADS: usize = 4;
+
+    for (glyph_map, var_info, tests) in iter_test_groups(GOOD_DIR) {
+        let compile = |path: &Path, tree: &ParseTree| {
+            let mut compiler = Compiler::new(path, &glyph_map).print_warnings(false);
+            if test_utils::is_variable(path) {
+                compiler = compiler.with_variable_info(&var_info);
+            }
+            compiler
+                .compile_tree(tree)
+                .unwrap()
+                .to_binary(&glyph_map, Default::default())
+                .unwrap()
+        };
+
+        let trees = tests
+            .iter()
+            .map(|path| {
+                let (tree, diagnostics) =
+                    crate::parse::parse_root_file(path, Some(&glyph_map), None).unwrap();
+                assert!(!diagnostics.iter().any(|d| d.is_error()));
+                tree
+            })
+            .collect::<Vec<_>>();
+        let expected = tests
+            .iter()
+            .zip(&trees)
+            .map(|(path, tree)| compile(path, tree))
+            .collect::<Vec<_>>();
+
+        std::thread::scope(|scope| {
+            let handles = (0..N_THREADS)
+                .map(|_| {
+                    scope.spawn(|| {
+                        tests
+                            .iter()
+                            .zip(&trees)
+                            .map(|(path, tree)| compile(path, tree))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+            for handle in handles {
+                assert!(handle.join().unwrap() == expected);
+            }
+        });
+    }
+}
+
+// the kerning in this file mixes class, glyph, and enumerated pairs
+static KERNING_CORPUS: &str = "./test-data/real-files/plex_devanagari.fea";
+/// The maximum size of the compiled GPOS table for `KERNING_CORPUS`.
+///
+/// If the table gets smaller, this value can be lowered to match.
+const KERNING_CORPUS_GPOS_SIZE: usize = 42818;
+
+#[test]
+fn kerning_corpus_size() {
+    let path = Path::new(KERNING_CORPUS);
+    // there is no glyph order for this file, so use the glyphs it references
+    let text = std::fs::read_to_string(path).unwrap();
+    let (root, _) = crate::parse::parse_string(text);
+    let mut names = root
+        .iter_tokens()
+        .filter(|token| token.kind == crate::Kind::GlyphName)
+        .map(|token| token.text.trim_start_matches('\\'))
+        .collect::<Vec<_>>();
+    names.sort_unstable();
+    names.dedup();
+    let glyph_map: GlyphMap = std::iter::once(".notdef")
+        .chain(names)
+        .map(GlyphName::new)
+        .collect();
+
+    let compilation = Compiler::new(path, &glyph_map)
+        .print_warnings(false)
+        .compile()
+        .unwrap();
+    let gpos = write_fonts::dump_table(compilation.gpos.as_ref().unwrap()).unwrap();
+    assert!(
+        gpos.len() <= KERNING_CORPUS_GPOS_SIZE,
+        "GPOS grew from {KERNING_CORPUS_GPOS_SIZE} to {} bytes",
+        gpos.len()
+    );
+}
+
 fn iter_test_groups(
     test_dir: &str,
 ) -> impl Iterator<Item = (GlyphMap, MockVariationInfo, Vec<PathBuf>)> + '_ {
@@ -0,0 +1,59 @@
+//! snapshot tests of the diagnostics reported by the validation pass.
+//!
+//! Each file in the test directory is a minimal example of a single validation
+//! rule; the expected diagnostics (including warnings) are stored alongside it.
+
+use std::path::{Path, PathBuf};
+
+use crate::{
+    compile::{self, VariationInfo},
+    util::ttx::{self as test_utils, TestCase, TestResult},
+    GlyphMap, GlyphName,
+};
+
+static VALIDATION_TESTS: &str = "./test-data/validation-tests";
+static GLYPH_ORDER: &str = "./test-data/compile-tests/mini-latin/glyph_order.txt";
+static EXPECTED_EXTENSION: &str = "EXPECTED";
+
+#[test]
+fn validation_diagnostics() -> Result<(), test_utils::Report> {
+    let glyph_order = std::fs::read_to_string(GLYPH_ORDER).expect("failed to read glyph order");
+    let glyph_map: GlyphMap = glyph_order.lines().map(GlyphName::new).collect();
+    let results = test_utils::iter_fea_files(VALIDATION_TESTS)
+        .map(|path| run_validation_test(path, &glyph_map))
+        .collect();
+    test_utils::finalize_results(results).into_error()
+}
+
+fn run_validation_test(path: PathBuf, glyph_map: &GlyphMap) -> Result<PathBuf, TestCase> {
+    match std::panic::catch_unwind(|| validation_test_body(&path, glyph_map)) {
+        Err(_) => Err(TestCase {
+            path,
+            reason: TestResult::Panic,
+        }),
+        Ok(Err(reason)) => Err(TestCase { path, reason }),
+        Ok(Ok(())) => Ok(path),
+    }
+}
+
+fn validation_test_body(path: &Path, glyph_map: &GlyphMap) -> Result<(), TestResult> {
+    let (tree, errs) = crate::parse::parse_root_file(path, Some(glyph_map), None).unwrap();
+    if !errs.is_empty() {
+        return Err(TestResult::ParseFail(tree.format_diagnostics(&errs, false)));
+    }
+    let var_info = test_utils::make_var_info();
+    let var_info = test_utils::is_variable(path).then_some(&var_info as &dyn VariationInfo);
+    let diagnostics = compile::validate(&tree, glyph_map, var_info);
+    // every test should exercise some rule
+    if diagnostics.is_empty() {
+        return Err(TestResult::UnexpectedSuccess);
+    }
+
+    let output = tree.format_diagnostics(&diagnostics, false);
+    let result = test_utils::compare_to_expected_output(&output, path, EXPECTED_EXTENSION);
+    if result.is_err() && std::env::var(crate::util::WRITE_RESULTS_VAR).is_ok() {
+        let to_path = path.with_extension(EXPECTED_EXTENSION);
+        std::fs::write(to_path, &output).expect("failed to write output");
+    }
+    result.map_err(|e| e.reason)
+}
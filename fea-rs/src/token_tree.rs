@@ -1,11 +1,17 @@
 use std::fmt::Write;
 
-use std::{cell::Cell, ops::Range, sync::Arc};
+use std::{
+    ops::Range,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
 
 use smol_str::SmolStr;
 
 use crate::parse::{FileId, IncludeStatement};
-use crate::{diagnostic::Diagnostic, GlyphMap, Level};
+use crate::{diagnostic::Diagnostic, DiagnosticCode, GlyphMap, Level};
 
 use self::cursor::Cursor;
 use typed::AstNode as _;
@@ -16,6 +22,7 @@ mod rewrite;
 mod stack;
 mod token;
 pub mod typed;
+pub mod visit;
 
 use rewrite::ReparseCtx;
 pub use token::Kind;
@@ -35,7 +42,7 @@ pub struct Node {
     // NOTE: the absolute position within the tree is not known when the node
     // is created; this is updated (and correct) only when the node has been
     // accessed via a `Cursor`.
-    abs_pos: Cell<u32>,
+    abs_pos: AbsPos,
     text_len: u32,
     /// true if an error was encountered in this node.
     ///
@@ -52,11 +59,24 @@ pub struct Token {
     /// The [`Kind`] of this token
     pub kind: Kind,
     /// The absolute position in the source where this token starts
-    abs_pos: Cell<u32>,
+    abs_pos: AbsPos,
     /// The token text
     pub text: SmolStr,
 }
 
+/// The cached absolute position of a node or token.
+///
+/// This is written whenever a node is visited by a cursor. It is atomic so
+/// that a tree can be shared between threads; since a given node always has
+/// the same position within a given tree, concurrent traversals will only
+/// ever write the same value.
+///
+/// (Trees produced by incremental reparsing may share unchanged nodes with
+/// the tree they were derived from, at different offsets; those two trees
+/// should not be traversed concurrently.)
+#[derive(Default)]
+struct AbsPos(AtomicU32);
+
 /// Either a [`Node`] or a [`Token`].
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum NodeOrToken {
@@ -85,8 +105,17 @@ pub(crate) struct AstSink<'a> {
     reparse_buf: Vec<NodeOrToken>,
     glyph_map: Option<&'a GlyphMap>,
     errors: Vec<Diagnostic>,
+    // the number of hard errors, including any that were not reported
+    n_errors: usize,
+    // if set, diagnostics after this many errors are counted but not reported
+    max_errors: Option<usize>,
+    n_suppressed: usize,
+    first_suppressed: Option<Range<usize>>,
     include_statement_count: usize,
     cur_node_contains_error: bool,
+    // if true, glyph names that could also be ranges are errors
+    strict_glyph_names: bool,
+    after_backslash: bool,
 }
 
 //NOTE: the inner type is option because we reuse this in the `typed` module,
@@ -106,17 +135,37 @@ impl<'a> AstSink<'a> {
             builder: TreeBuilder::default(),
             glyph_map,
             errors: Vec::new(),
+            n_errors: 0,
+            max_errors: None,
+            n_suppressed: 0,
+            first_suppressed: None,
             cur_node_contains_error: false,
             include_statement_count: 0,
             reparse_buf: Default::default(),
+            strict_glyph_names: false,
+            after_backslash: false,
         }
     }
 
+    /// If `true`, glyph names that could also be read as a range are errors,
+    /// instead of warnings.
+    pub(crate) fn with_strict_glyph_names(mut self, strict: bool) -> Self {
+        self.strict_glyph_names = strict;
+        self
+    }
+
+    /// Stop reporting diagnostics after this many errors.
+    pub(crate) fn with_max_errors(mut self, max_errors: Option<usize>) -> Self {
+        self.max_errors = max_errors;
+        self
+    }
+
     pub(crate) fn token(&mut self, kind: Kind, len: usize) {
         let token_text = &self.text[self.text_pos..self.text_pos + len];
         let to_add = self.validate_token(kind, token_text);
         self.builder.push_raw(to_add);
         self.text_pos += len;
+        self.after_backslash = kind == Kind::Backslash;
     }
 
     pub(crate) fn start_node(&mut self, kind: Kind) {
@@ -140,19 +189,49 @@ impl<'a> AstSink<'a> {
         self.cur_node_contains_error
     }
 
+    /// The number of errors (not warnings) encountered so far.
+    ///
+    /// This includes errors that were not reported because of the error limit.
+    pub(crate) fn error_count(&self) -> usize {
+        self.n_errors
+    }
+
     pub(crate) fn error(&mut self, mut error: Diagnostic) {
         let is_hard_error = error.level == Level::Error;
-        error.message.file = self.file_id;
-        self.errors.push(error);
-        self.cur_node_contains_error = is_hard_error;
+        self.cur_node_contains_error |= is_hard_error;
+        if self.max_errors.is_some_and(|max| self.n_errors >= max) {
+            self.n_suppressed += 1;
+            self.first_suppressed
+                .get_or_insert_with(|| error.message.span.range());
+        } else {
+            error.message.file = self.file_id;
+            if is_hard_error {
+                error.code.get_or_insert(DiagnosticCode::ParseError);
+            }
+            self.errors.push(error);
+        }
+        self.n_errors += is_hard_error as usize;
     }
 
-    pub fn finish(self) -> (Node, Vec<Diagnostic>, Vec<IncludeStatement>) {
+    pub fn finish(mut self) -> (Node, Vec<Diagnostic>, Vec<IncludeStatement>) {
         let node = self.builder.finish();
         let mut includes = Vec::new();
         if self.include_statement_count > 0 {
             node.find_include_nodes(&mut includes, self.include_statement_count);
         }
+        if let Some(range) = self.first_suppressed.take() {
+            self.errors.push(
+                Diagnostic::error(
+                    self.file_id,
+                    range,
+                    format!(
+                        "too many errors; {} more diagnostics were not reported",
+                        self.n_suppressed
+                    ),
+                )
+                .with_code(DiagnosticCode::ParseError),
+            );
+        }
         (node, self.errors, includes)
     }
 
@@ -165,16 +244,25 @@ impl<'a> AstSink<'a> {
     ///
     /// We can perform additional validation here. Currently it is mostly for
     /// disambiguating glyph names that might be ranges.
+    ///
+    /// A name containing a hyphen is a glyph name if it is escaped with a
+    /// backslash or if a glyph with that name exists; otherwise it is a range.
     fn validate_token(&mut self, kind: Kind, text: &str) -> NodeOrToken {
         if kind == Kind::GlyphNameOrRange {
             if let Some(map) = self.glyph_map {
+                let range = self.text_pos..self.text_pos + text.len();
+                if self.after_backslash {
+                    return Token::new(Kind::GlyphName, text.into()).into();
+                }
                 if map.contains(text) {
+                    if let Ok(node) = try_split_range(text, map) {
+                        self.ambiguous_glyph_name(range, text, &node);
+                    }
                     return Token::new(Kind::GlyphName, text.into()).into();
                 }
                 match try_split_range(text, map) {
                     Ok(node) => return node.into(),
                     Err(message) => {
-                        let range = self.text_pos..self.text_pos + text.len();
                         self.error(Diagnostic::error(FileId::CURRENT_FILE, range, message));
                     }
                 }
@@ -183,6 +271,32 @@ impl<'a> AstSink<'a> {
         Token::new(kind, text.into()).into()
     }
 
+    // report a name that is a glyph, but which could also be a range.
+    fn ambiguous_glyph_name(&mut self, range: Range<usize>, text: &str, as_range: &Node) {
+        let mut names = as_range
+            .iter_tokens()
+            .filter(|t| t.kind == Kind::GlyphName)
+            .map(Token::as_str);
+        let (start, end) = (
+            names.next().unwrap_or_default(),
+            names.next().unwrap_or_default(),
+        );
+        let level = if self.strict_glyph_names {
+            Level::Error
+        } else {
+            Level::Warning
+        };
+        let message = format!(
+            "'{text}' could be a glyph name or the range '{start} - {end}'; it is treated as a glyph name. Write '\\{text}' to mark it as a glyph name, or '{start} - {end}' for a range."
+        );
+        let start = range.start;
+        self.error(
+            Diagnostic::new(level, FileId::CURRENT_FILE, range, message)
+                .with_code(DiagnosticCode::AmbiguousGlyphName)
+                .with_edit(start..start, "\\"),
+        );
+    }
+
     /// Called before finishing a node.
     ///
     /// This is an opportunity for us to rewrite this node's tree, which is
@@ -242,7 +356,7 @@ impl Node {
         let mut text_len = 0;
         for child in &mut children {
             if let NodeOrToken::Node(n) = child {
-                n.rel_pos += text_len;
+                n.rel_pos = text_len;
             }
             text_len += child.text_len() as u32;
         }
@@ -251,7 +365,7 @@ impl Node {
             kind,
             text_len,
             rel_pos: 0,
-            abs_pos: Cell::new(0),
+            abs_pos: AbsPos::default(),
             children: children.into(),
             error,
         }
@@ -291,6 +405,47 @@ impl Node {
         start..start + (self.text_len as usize)
     }
 
+    /// The chain of nodes leading from `target` up to this node.
+    ///
+    /// The first item is the parent of `target`, and the last is `self`. This
+    /// is empty if `target` is not a descendant of this node.
+    pub(crate) fn ancestors_of(&self, target: &NodeOrToken) -> Vec<&Node> {
+        let mut path = Vec::new();
+        if self.collect_path_to(target, &target.range(), &mut path) {
+            path.reverse();
+        }
+        path
+    }
+
+    // depth-first search for `target`, which must lie within `range`.
+    fn collect_path_to<'a>(
+        &'a self,
+        target: &NodeOrToken,
+        range: &Range<usize>,
+        path: &mut Vec<&'a Node>,
+    ) -> bool {
+        path.push(self);
+        for child in self.iter_children() {
+            let child_range = child.range();
+            if child_range.start > range.end {
+                break;
+            }
+            if child.is_same(target) {
+                return true;
+            }
+            if let NodeOrToken::Node(node) = child {
+                if child_range.start <= range.start
+                    && range.end <= child_range.end
+                    && node.collect_path_to(target, range, path)
+                {
+                    return true;
+                }
+            }
+        }
+        path.pop();
+        false
+    }
+
     /// Create a new tree, replacing the provided ranges with the provided
     /// nodes.
     ///
@@ -302,6 +457,18 @@ impl Node {
         edit::apply_edits(self, edits, skip_parent)
     }
 
+    /// Create a new node, replacing the children at the indices in `range`.
+    ///
+    /// If `error` is true, the new node is marked as containing an error.
+    pub(crate) fn splice_children(
+        &self,
+        range: Range<usize>,
+        replace_with: impl IntoIterator<Item = NodeOrToken>,
+        error: bool,
+    ) -> Node {
+        edit::splice_children(self, range, replace_with, error)
+    }
+
     fn find_include_nodes(&self, collect: &mut Vec<IncludeStatement>, num: usize) {
         for item in self.iter_children() {
             if let Some(node) = item.as_node() {
@@ -427,6 +594,48 @@ impl TreeBuilder {
     }
 }
 
+impl AbsPos {
+    fn get(&self) -> u32 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn set(&self, pos: u32) {
+        self.0.store(pos, Ordering::Relaxed)
+    }
+}
+
+impl Clone for AbsPos {
+    fn clone(&self) -> Self {
+        AbsPos(AtomicU32::new(self.get()))
+    }
+}
+
+impl PartialEq for AbsPos {
+    fn eq(&self, other: &Self) -> bool {
+        self.get() == other.get()
+    }
+}
+
+impl Eq for AbsPos {}
+
+impl PartialOrd for AbsPos {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for AbsPos {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.get().cmp(&other.get())
+    }
+}
+
+impl std::fmt::Debug for AbsPos {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.get().fmt(f)
+    }
+}
+
 impl NodeOrToken {
     pub(crate) fn set_abs_pos(&self, pos: usize) {
         match self {
@@ -479,6 +688,28 @@ impl NodeOrToken {
         }
     }
 
+    /// `true` if `self` and `other` are the same item in the same tree.
+    ///
+    /// Clones of a node share their children, so nodes are compared by
+    /// pointer; tokens are compared by kind, position and text.
+    fn is_same(&self, other: &NodeOrToken) -> bool {
+        match (self, other) {
+            (NodeOrToken::Node(a), NodeOrToken::Node(b)) => {
+                a.kind == b.kind && Arc::ptr_eq(&a.children, &b.children)
+            }
+            (NodeOrToken::Token(a), NodeOrToken::Token(b)) => {
+                a.kind == b.kind && a.range() == b.range() && a.text == b.text
+            }
+            _ => false,
+        }
+    }
+
+    /// `true` if `other` has the same kinds, text, and structure as `self`,
+    /// regardless of position.
+    pub(crate) fn same_structure(&self, other: &NodeOrToken) -> bool {
+        edit::same_structure(self, other)
+    }
+
     /// If this is a `Node`, return it
     pub fn into_node(self) -> Option<Node> {
         match self {
@@ -521,7 +752,7 @@ impl Token {
         Token {
             kind,
             text,
-            abs_pos: Cell::new(0),
+            abs_pos: AbsPos::default(),
         }
     }
 
@@ -570,10 +801,24 @@ fn try_split_range(text: &str, glyph_map: &GlyphMap) -> Result<Node, String> {
             builder.finish()
         })
         .ok_or_else(|| {
-            format!(
-                "'{}' is neither a known glyph or a range of known glyphs",
-                text
-            )
+            let missing = text
+                .split_once('-')
+                .filter(|(_, tail)| !tail.contains('-'))
+                .map(|(head, tail)| {
+                    match (glyph_map.contains(head), glyph_map.contains(tail)) {
+                        (false, false) => format!("neither '{head}' nor '{tail}' is in the font"),
+                        (false, true) => format!("'{head}' is not in the font"),
+                        _ => format!("'{tail}' is not in the font"),
+                    }
+                });
+            match missing {
+                Some(missing) => format!(
+                    "'{text}' is not a glyph in the font, and is not a range because {missing}"
+                ),
+                None => format!(
+                    "'{text}' is not a glyph in the font, and cannot be split into a range of glyphs in the font"
+                ),
+            }
         })
 }
 
@@ -621,4 +866,65 @@ mod tests {
         let reconstruct = root.iter_tokens().map(Token::as_str).collect::<String>();
         crate::assert_eq_str!(SAMPLE_FEA, reconstruct);
     }
+
+    #[test]
+    fn ancestors() {
+        use typed::AstNode;
+
+        fn find_nodes<'a>(node: &'a Node, kind: Kind, out: &mut Vec<&'a Node>) {
+            for child in node.iter_children() {
+                if let NodeOrToken::Node(child) = child {
+                    if child.kind == kind {
+                        out.push(child);
+                    }
+                    find_nodes(child, kind, out);
+                }
+            }
+        }
+
+        let fea = "feature liga {\n    lookup one {\n        sub a by b;\n    } one;\n    sub c by d;\n} liga;\n";
+        let (root, errs) = crate::parse::parse_string(fea);
+        assert!(errs.is_empty(), "{errs:?}");
+        let mut rules = Vec::new();
+        find_nodes(&root, Kind::GsubType1, &mut rules);
+        let rules = rules
+            .into_iter()
+            .map(|node| typed::Gsub1::try_from_node(node).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(rules.len(), 2);
+
+        let kinds = rules[0]
+            .ancestors(&root)
+            .into_iter()
+            .map(Node::kind)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [Kind::LookupBlockNode, Kind::FeatureNode, Kind::SourceFile]
+        );
+        assert_eq!(
+            rules[1].parent(&root).map(Node::kind),
+            Some(Kind::FeatureNode)
+        );
+        assert!(rules[1]
+            .find_ancestor::<typed::LookupBlock>(&root)
+            .is_none());
+        let feature = rules[0].find_ancestor::<typed::Feature>(&root).unwrap();
+        assert_eq!(feature.tag().text(), "liga");
+
+        // tokens work too
+        let glyph = root
+            .iter_tokens()
+            .find(|t| t.kind == Kind::GlyphName && t.text == "d")
+            .unwrap();
+        let glyph = typed::GlyphName::cast(&NodeOrToken::Token(glyph.clone())).unwrap();
+        assert_eq!(glyph.parent(&root).map(Node::kind), Some(Kind::GsubType1));
+        assert!(glyph.find_ancestor::<typed::Feature>(&root).is_some());
+
+        // the root has no ancestors, and nor does an item from another tree
+        let typed_root = typed::Root::try_from_node(&root).unwrap();
+        assert!(typed_root.ancestors(&root).is_empty());
+        let (other, _) = crate::parse::parse_string(fea);
+        assert!(rules[0].ancestors(&other).is_empty());
+    }
 }
@@ -1,11 +1,45 @@
 //! facilities for editing the AST.
-//!
-//! This is currently unused.
 
-#![allow(dead_code)]
 use std::ops::Range;
 
-use super::{cursor::Cursor, Node, TreeBuilder};
+use super::{cursor::Cursor, Node, NodeOrToken, TreeBuilder};
+
+/// Create a copy of `base`, replacing the children at the indices in `range`.
+///
+/// The new node has an error if `base` has one, or if `error` is true.
+pub(crate) fn splice_children(
+    base: &Node,
+    range: Range<usize>,
+    replace_with: impl IntoIterator<Item = NodeOrToken>,
+    error: bool,
+) -> Node {
+    let mut children = base.children.to_vec();
+    children.splice(range, replace_with);
+    Node::new(base.kind, children, base.error || error)
+}
+
+/// `true` if the two items have the same kinds, text, and structure.
+///
+/// Unlike `==`, this ignores the positions of the items.
+pub(crate) fn same_structure(one: &NodeOrToken, two: &NodeOrToken) -> bool {
+    match (one, two) {
+        (NodeOrToken::Token(one), NodeOrToken::Token(two)) => {
+            one.kind == two.kind && one.text == two.text
+        }
+        (NodeOrToken::Node(one), NodeOrToken::Node(two)) => {
+            one.kind == two.kind
+                && one.error == two.error
+                && one.text_len == two.text_len
+                && one.children.len() == two.children.len()
+                && one
+                    .children
+                    .iter()
+                    .zip(two.children.iter())
+                    .all(|(one, two)| same_structure(one, two))
+        }
+        _ => false,
+    }
+}
 
 /// if 'skip_parent' is true, then the children of inserted nodes are added
 /// but the inserted node itself is skipped.
@@ -5,7 +5,7 @@
 //! lookahead. Instead, when we encounter a mark glyph we parse the statement
 //! naively, and then reparse it again afterwards.
 
-use crate::{parse::FileId, Diagnostic, Level, NodeOrToken};
+use crate::{parse::FileId, Diagnostic, DiagnosticCode, Level, NodeOrToken};
 
 use super::{AstSink, Kind};
 
@@ -93,16 +93,23 @@ impl<'a, 'b> ReparseCtx<'a, 'b> {
         self.add_diagnostic(Level::Error, message);
     }
 
-    fn warn(&mut self, message: impl Into<String>) {
-        self.add_diagnostic(Level::Warning, message);
+    fn warn(&mut self, code: DiagnosticCode, message: impl Into<String>) {
+        let warning = self
+            .make_diagnostic(Level::Warning, message)
+            .with_code(code);
+        self.sink.error(warning)
     }
 
     fn add_diagnostic(&mut self, level: Level, message: impl Into<String>) {
+        let error = self.make_diagnostic(level, message);
+        self.sink.error(error)
+    }
+
+    fn make_diagnostic(&mut self, level: Level, message: impl Into<String>) -> Diagnostic {
         self.eat_trivia();
         let cur_len = self.nth(0).map(NodeOrToken::text_len).unwrap_or(0);
         let range = self.text_pos..self.text_pos + cur_len;
-        let error = Diagnostic::new(level, FileId::CURRENT_FILE, range, message);
-        self.sink.error(error)
+        Diagnostic::new(level, FileId::CURRENT_FILE, range, message)
     }
 
     fn err_and_bump(&mut self, message: impl Into<String>) {
@@ -264,7 +271,7 @@ fn expect_ignore_rule_statement(rewriter: &mut ReparseCtx) {
         // the degen case, where there are no mark glyphs and so we just make
         // the first non-marked glyph be the input sequence:
     } else {
-        rewriter.warn("No marked glyphs in sequence. This glyph will be treated as input, all others as lookahead.");
+        rewriter.warn(DiagnosticCode::UnmarkedContextualGlyphs, "No marked glyphs in sequence. This glyph will be treated as input, all others as lookahead.");
         rewriter.in_node(Kind::IgnoreRuleStatementNode, |rewriter| {
             rewriter.in_node(Kind::BacktrackSequence, |_| {}); // empty backtrack
             rewriter.in_node(Kind::ContextSequence, |rewriter| {
@@ -42,6 +42,7 @@ pub enum Kind {
     LAngle,
     RAngle,
     SingleQuote,
+    Ampersand,
 
     NamedGlyphClass,
     Cid,
@@ -222,6 +223,8 @@ pub enum Kind {
     AnchorDefNode,
     AnonBlockNode,
     GlyphClassDefNode,
+    // '- @class' or '& @class' in a glyph class
+    GlyphClassOperationNode,
     LanguageSystemNode,
     FeatureNode,
     SizeMenuNameNode,
@@ -302,6 +305,12 @@ impl Kind {
         )
     }
 
+    /// `true` if this is a keyword, including contextual keywords.
+    pub(crate) fn is_keyword(self) -> bool {
+        (Kind::TableKw..=Kind::CharacterKw).contains(&self)
+            || matches!(self, Kind::LigatureKw | Kind::BaseKw)
+    }
+
     pub(crate) fn is_trivia(self) -> bool {
         matches!(self, Kind::Comment | Kind::Whitespace | Kind::Backslash)
     }
@@ -338,6 +347,7 @@ impl std::fmt::Display for Kind {
             Self::LAngle => write!(f, "<"),
             Self::RAngle => write!(f, ">"),
             Self::SingleQuote => write!(f, "'"),
+            Self::Ampersand => write!(f, "&"),
             Self::Comment => write!(f, "#"),
 
             Self::Tag => write!(f, "Tag"),
@@ -500,6 +510,7 @@ impl std::fmt::Display for Kind {
             Self::DeviceNode => write!(f, "DeviceNode"),
             Self::AnonBlockNode => write!(f, "AnonBlockNode"),
             Self::GlyphClassDefNode => write!(f, "GlyphClassDefNode"),
+            Self::GlyphClassOperationNode => write!(f, "GlyphClassOperationNode"),
             Self::LanguageSystemNode => write!(f, "LanguageSystemNode"),
             Self::FeatureNode => write!(f, "FeatureNode"),
             Self::SizeMenuNameNode => write!(f, "SizeMenuNameNode"),
@@ -35,6 +35,51 @@ pub trait AstNode {
     fn iter(&self) -> ChildIter {
         Default::default()
     }
+
+    /// The underlying `Node`, if this item is a node.
+    fn node(&self) -> Option<&Node> {
+        None
+    }
+
+    /// The underlying `Token`, if this item is a token.
+    fn token(&self) -> Option<&Token> {
+        None
+    }
+
+    /// The ancestors of this item in the tree rooted at `root`, starting with
+    /// its parent and ending with `root`.
+    ///
+    /// Nodes do not store references to their parents, so this searches down
+    /// from `root`, which must be the tree this item was found in. If the
+    /// item is not found (or is `root` itself) the result is empty.
+    fn ancestors<'a>(&self, root: &'a Node) -> Vec<&'a Node> {
+        let target = match (self.node(), self.token()) {
+            (Some(node), _) => NodeOrToken::Node(node.clone()),
+            (_, Some(token)) => NodeOrToken::Token(token.clone()),
+            _ => return Vec::new(),
+        };
+        root.ancestors_of(&target)
+    }
+
+    /// The parent of this item in the tree rooted at `root`.
+    ///
+    /// See [`ancestors`][AstNode::ancestors] for details.
+    fn parent<'a>(&self, root: &'a Node) -> Option<&'a Node> {
+        self.ancestors(root).first().copied()
+    }
+
+    /// The nearest ancestor of this item that can be cast to `T`.
+    ///
+    /// For instance, this can be used to find the feature or lookup block
+    /// that encloses a given rule.
+    fn find_ancestor<T: AstNode>(&self, root: &Node) -> Option<T>
+    where
+        Self: Sized,
+    {
+        self.ancestors(root)
+            .into_iter()
+            .find_map(|node| T::cast(&NodeOrToken::Node(node.clone())))
+    }
 }
 
 /// Create a new AstNode wrapping a token.
@@ -58,12 +103,6 @@ macro_rules! ast_token {
             pub fn token(&self) -> &Token {
                 &self.inner
             }
-
-            // just used for the ast_enum macro
-            #[allow(dead_code)]
-            pub(crate) fn node_(&self) -> Option<&Node> {
-                None
-            }
         }
 
         impl AstNode for $typ {
@@ -79,6 +118,10 @@ macro_rules! ast_token {
             fn range(&self) -> std::ops::Range<usize> {
                 self.inner.range()
             }
+
+            fn token(&self) -> Option<&Token> {
+                Some(&self.inner)
+            }
         }
     };
 }
@@ -114,12 +157,6 @@ macro_rules! ast_node {
             pub fn node(&self) -> &Node {
                 &self.inner
             }
-
-            // just used for the ast_enum macro
-            #[allow(dead_code)]
-            pub(crate) fn node_(&self) -> Option<&Node> {
-                Some(&self.inner)
-            }
         }
 
         impl AstNode for $typ {
@@ -137,6 +174,10 @@ macro_rules! ast_node {
             fn iter(&self) -> ChildIter {
                 self.inner.iter_children()
             }
+
+            fn node(&self) -> Option<&Node> {
+                Some(&self.inner)
+            }
         }
     };
 }
@@ -171,14 +212,19 @@ macro_rules! ast_enum {
                     )*
                 }
             }
-        }
 
-        impl $typ {
-            #[allow(unused)]
-            pub(crate) fn node(&self) -> Option<&Node> {
+            fn node(&self) -> Option<&Node> {
+                match self {
+                    $(
+                        Self::$name(inner) => AstNode::node(inner),
+                    )*
+                }
+            }
+
+            fn token(&self) -> Option<&Token> {
                 match self {
                     $(
-                        Self::$name(inner) => inner.node_(),
+                        Self::$name(inner) => AstNode::token(inner),
                     )*
                 }
             }
@@ -203,7 +249,9 @@ ast_node!(MarkClassDef, Kind::MarkClassNode);
 ast_node!(Anchor, Kind::AnchorNode);
 ast_node!(AnchorDef, Kind::AnchorDefNode);
 ast_node!(ValueRecordDef, Kind::ValueRecordDefNode);
+ast_node!(AnonBlock, Kind::AnonBlockNode);
 ast_node!(GlyphClassLiteral, Kind::GlyphClass);
+ast_node!(GlyphClassOperation, Kind::GlyphClassOperationNode);
 ast_node!(LanguageSystem, Kind::LanguageSystemNode);
 ast_node!(Include, Kind::IncludeNode);
 ast_node!(Feature, Kind::FeatureNode);
@@ -423,6 +471,28 @@ impl Root {
     pub(crate) fn statements(&self) -> impl Iterator<Item = &NodeOrToken> {
         self.iter().filter(|t| !t.kind().is_trivia())
     }
+
+    /// The glyph class, mark class and named anchor definitions, in source order.
+    ///
+    /// This includes definitions inside feature, lookup and variation blocks.
+    pub(crate) fn definitions(&self) -> Vec<&NodeOrToken> {
+        fn collect<'a>(node: &'a Node, out: &mut Vec<&'a NodeOrToken>) {
+            for child in node.iter_children() {
+                match child.kind() {
+                    Kind::GlyphClassDefNode | Kind::MarkClassNode | Kind::AnchorDefNode => {
+                        out.push(child)
+                    }
+                    Kind::FeatureNode | Kind::LookupBlockNode | Kind::VariationNode => {
+                        collect(child.as_node().unwrap(), out)
+                    }
+                    _ => (),
+                }
+            }
+        }
+        let mut out = Vec::new();
+        collect(&self.inner, &mut out);
+        out
+    }
 }
 
 impl LanguageSystem {
@@ -486,6 +556,20 @@ impl GlyphClassLiteral {
     }
 }
 
+impl GlyphClassOperation {
+    /// `true` for `& @class`, and `false` for `- @class`.
+    pub(crate) fn is_intersection(&self) -> bool {
+        self.iter()
+            .next()
+            .is_some_and(|t| t.kind() == Kind::Ampersand)
+    }
+
+    /// The class on the right of the operator.
+    pub(crate) fn class(&self) -> Option<GlyphClassName> {
+        self.iter().find_map(GlyphClassName::cast)
+    }
+}
+
 impl Cid {
     pub(crate) fn parse(&self) -> u16 {
         self.inner.text.parse().expect("cid is already validated")
@@ -546,12 +630,41 @@ impl ValueRecordDef {
     }
 }
 
+impl AnonBlock {
+    /// The label of the block, e.g. `sbit` in `anon sbit { .. } sbit;`
+    pub(crate) fn label(&self) -> &Token {
+        self.iter()
+            .skip(1)
+            .find(|item| !item.kind().is_trivia())
+            .and_then(NodeOrToken::as_token)
+            .unwrap()
+    }
+
+    /// The raw text between the braces.
+    pub(crate) fn contents(&self) -> String {
+        let items = self.iter().collect::<Vec<_>>();
+        let start = items.iter().position(|item| item.kind() == Kind::LBrace);
+        let end = items.iter().rposition(|item| item.kind() == Kind::RBrace);
+        let (Some(start), Some(end)) = (start, end) else {
+            return String::new();
+        };
+        items[start + 1..end]
+            .iter()
+            .flat_map(|item| match item {
+                NodeOrToken::Token(token) => vec![token.as_str()],
+                NodeOrToken::Node(node) => node.iter_tokens().map(Token::as_str).collect(),
+            })
+            .collect()
+    }
+}
+
 impl AnchorDef {
     pub(crate) fn anchor(&self) -> Anchor {
         self.iter().find_map(Anchor::cast).unwrap()
     }
 
-    pub(crate) fn name(&self) -> &Token {
+    /// The name of the anchor being defined.
+    pub fn name(&self) -> &Token {
         self.find_token(Kind::Ident).expect("pre-validated")
     }
 }
@@ -589,7 +702,12 @@ impl Anchor {
         self.find_token(Kind::NullKw)
     }
 
-    pub(crate) fn name(&self) -> Option<&Token> {
+    /// The name of the anchor, if this is a reference to a named anchor
+    /// (such as `<anchor TOP>`).
+    ///
+    /// The definitions of named anchors are available after compilation, in
+    /// [`Compilation::anchors`](crate::compile::Compilation::anchors).
+    pub fn name(&self) -> Option<&Token> {
         self.find_token(Kind::Ident)
     }
 }
@@ -625,6 +743,22 @@ impl FloatLike {
     pub(crate) fn parse_fixed(&self) -> Fixed {
         Fixed::from_f64(self.parse() as _)
     }
+
+    /// Parse a size in decipoints, as used by the `size` feature.
+    ///
+    /// Integers are already in decipoints, and decimal numbers are in points
+    /// (so `10.0` is `100`). Returns `None` if the result doesn't fit in a `u16`.
+    pub(crate) fn parse_decipoints(&self) -> Option<u16> {
+        match self {
+            FloatLike::Number(n) => n.parse_unsigned(),
+            FloatLike::Float(f) => {
+                let decipoints = (f.parse() * 10.0).round();
+                (0.0..=u16::MAX as f32)
+                    .contains(&decipoints)
+                    .then_some(decipoints as u16)
+            }
+        }
+    }
 }
 
 impl Feature {
@@ -1082,7 +1216,8 @@ impl Device {
             .and_then(NodeOrToken::as_token)
     }
 
-    fn entries(&self) -> impl Iterator<Item = (Number, Number)> + '_ {
+    /// The (ppem, delta) pairs in this device record.
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (Number, Number)> + '_ {
         let mut iter = self
             .iter()
             .filter(|i| i.kind() == Kind::Number || i.kind() == Kind::Comma);
@@ -1099,10 +1234,18 @@ impl Device {
             return None;
         }
 
+        // as in feaLib, the entries can be in any order
+        let mut sorted = self
+            .entries()
+            .map(|(ppem, pix)| {
+                let ppem = ppem.parse_unsigned().expect("validated before now");
+                (ppem, pix.parse_signed())
+            })
+            .collect::<Vec<_>>();
+        sorted.sort_by_key(|(ppem, _)| *ppem);
+
         let mut entries = Vec::new();
-        for (ppem, pix) in self.entries() {
-            let ppem = ppem.parse_unsigned().expect("validated before now");
-            let pix = pix.parse_signed();
+        for (ppem, pix) in sorted {
             // if there are gaps in the range, add zeros
             if let Some(prev) = entries.last().map(|(pp, _)| *pp) {
                 for missing in (prev + 1)..ppem {
@@ -1340,11 +1483,27 @@ impl VendorRecord {
         self.find_token(Kind::String).unwrap()
     }
 
-    pub(crate) fn parse_tag(
-        &self,
-    ) -> Result<write_fonts::types::Tag, write_fonts::types::InvalidTag> {
-        let raw = self.value();
-        write_fonts::types::Tag::new_checked(raw.text.trim_matches('"').as_bytes())
+    /// Parse the vendor id, padding it with spaces if it is shorter than
+    /// four characters.
+    ///
+    /// On failure, returns a message suitable for reporting to the user.
+    pub(crate) fn parse_tag(&self) -> Result<write_fonts::types::Tag, String> {
+        let raw = self.value().text.trim_matches('"');
+        if let Some(c) = raw.chars().find(|c| !(' '..='~').contains(c)) {
+            return Err(format!(
+                "Vendor ID must contain only printable ASCII characters (found '{}')",
+                c.escape_default()
+            ));
+        }
+        write_fonts::types::Tag::new_checked(raw.as_bytes()).map_err(|e| match e {
+            write_fonts::types::InvalidTag::InvalidLength(0) => {
+                "Vendor ID must not be empty".to_string()
+            }
+            write_fonts::types::InvalidTag::InvalidLength(len) => {
+                format!("Vendor ID must be at most four characters (found {len})")
+            }
+            _ => "Vendor ID may only contain spaces as trailing padding".to_string(),
+        })
     }
 }
 
@@ -1526,40 +1685,40 @@ impl GdefAttach {
 }
 
 impl GdefLigatureCaret {
-    fn by_pos(&self) -> bool {
-        match self.iter().next().map(|t| t.kind()) {
-            Some(Kind::LigatureCaretByPosKw) => true,
-            Some(Kind::LigatureCaretByIndexKw) => false,
-            other => panic!("unexpected token in ligaturecaret {:?}", other),
-        }
-    }
-
     pub(crate) fn target(&self) -> GlyphOrClass {
         self.iter().find_map(GlyphOrClass::cast).unwrap()
     }
 
     pub(crate) fn values(&self) -> LigatureCaretValue {
-        if self.by_pos() {
-            LigatureCaretValue::Pos(LigatureCaretIter(self))
-        } else {
-            LigatureCaretValue::Index(LigatureCaretIter(self))
+        let iter = LigatureCaretIter(self);
+        match self.iter().next().map(|t| t.kind()) {
+            Some(Kind::LigatureCaretByPosKw) => LigatureCaretValue::Pos(iter),
+            Some(Kind::LigatureCaretByIndexKw) => LigatureCaretValue::Index(iter),
+            Some(Kind::LigatureCaretByDevKw) => LigatureCaretValue::Device(iter),
+            other => panic!("unexpected token in ligaturecaret {:?}", other),
         }
     }
 }
 
 // some helpers for handling the different caret representations; one is signed,
-// the other unsigned.
+// one is unsigned, and one has a device table for each value.
 pub(crate) struct LigatureCaretIter<'a>(&'a GdefLigatureCaret);
 
 impl LigatureCaretIter<'_> {
     pub(crate) fn values(&self) -> impl Iterator<Item = Number> + '_ {
         self.0.iter().filter_map(Number::cast)
     }
+
+    /// The (coordinate, device) pairs of a `LigatureCaretByDev` statement.
+    pub(crate) fn with_devices(&self) -> impl Iterator<Item = (Number, Device)> + '_ {
+        self.values().zip(self.0.iter().filter_map(Device::cast))
+    }
 }
 
 pub(crate) enum LigatureCaretValue<'a> {
     Pos(LigatureCaretIter<'a>),
     Index(LigatureCaretIter<'a>),
+    Device(LigatureCaretIter<'a>),
 }
 
 impl HeadTable {
@@ -0,0 +1,252 @@
+//! Walking the typed AST.
+//!
+//! The [`Visitor`] trait has a method for each kind of typed node, all of which
+//! do nothing by default; [`walk`] traverses a tree in order, calling the
+//! appropriate method for each node and token it finds.
+
+use crate::{Node, NodeOrToken};
+
+use super::typed::{self, AstNode};
+
+/// Whether [`walk`] should visit the children of a node.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Walk {
+    /// Visit the children of this node
+    #[default]
+    Continue,
+    /// Skip the children of this node
+    SkipChildren,
+}
+
+macro_rules! visitor {
+    (
+        nodes { $($node_method:ident($node:ident),)* }
+        tokens { $($token_method:ident($token:ident),)* }
+    ) => {
+        /// Callbacks for the nodes and tokens of the typed AST.
+        ///
+        /// Implement the methods for the items you are interested in, and pass
+        /// the visitor to [`walk`].
+        ///
+        /// The methods for nodes return a [`Walk`], which controls whether the
+        /// children of that node are visited. Once the children of a node have
+        /// been visited (or skipped), [`leave_node`][Visitor::leave_node] is
+        /// called, which can be used to track the enclosing block.
+        pub trait Visitor {
+            $(
+                #[doc = concat!("Called for each [`", stringify!($node), "`][typed::", stringify!($node), "] node.")]
+                fn $node_method(&mut self, _node: &typed::$node) -> Walk {
+                    Walk::Continue
+                }
+            )*
+
+            $(
+                #[doc = concat!("Called for each [`", stringify!($token), "`][typed::", stringify!($token), "] token.")]
+                fn $token_method(&mut self, _token: &typed::$token) {}
+            )*
+
+            /// Called after the children of any node have been visited.
+            fn leave_node(&mut self, _node: &Node) {}
+        }
+
+        fn visit_node(node: &Node, visitor: &mut impl Visitor) -> Walk {
+            $(
+                if let Some(node) = typed::$node::try_from_node(node) {
+                    return visitor.$node_method(&node);
+                }
+            )*
+            Walk::Continue
+        }
+
+        fn visit_token(token: &NodeOrToken, visitor: &mut impl Visitor) {
+            $(
+                if let Some(token) = typed::$token::cast(token) {
+                    return visitor.$token_method(&token);
+                }
+            )*
+        }
+    };
+}
+
+visitor! {
+    nodes {
+        visit_language_system(LanguageSystem),
+        visit_include(Include),
+        visit_glyph_class_def(GlyphClassDef),
+        visit_mark_class_def(MarkClassDef),
+        visit_anchor_def(AnchorDef),
+        visit_value_record_def(ValueRecordDef),
+        visit_anon_block(AnonBlock),
+        visit_condition_set(ConditionSet),
+        visit_feature(Feature),
+        visit_feature_variation(FeatureVariation),
+        visit_lookup_block(LookupBlock),
+        visit_lookup_ref(LookupRef),
+        visit_lookup_flag(LookupFlag),
+        visit_script(Script),
+        visit_language(Language),
+        visit_feature_ref(FeatureRef),
+        visit_feature_names(FeatureNames),
+        visit_cv_parameters(CvParameters),
+        visit_size_menu_name(SizeMenuName),
+        visit_parameters(Parameters),
+        visit_gsub1(Gsub1),
+        visit_gsub2(Gsub2),
+        visit_gsub3(Gsub3),
+        visit_gsub4(Gsub4),
+        visit_gsub5(Gsub5),
+        visit_gsub6(Gsub6),
+        visit_gsub8(Gsub8),
+        visit_gsub_ignore(GsubIgnore),
+        visit_gpos1(Gpos1),
+        visit_gpos2(Gpos2),
+        visit_gpos3(Gpos3),
+        visit_gpos4(Gpos4),
+        visit_gpos5(Gpos5),
+        visit_gpos6(Gpos6),
+        visit_gpos8(Gpos8),
+        visit_gpos_ignore(GposIgnore),
+        visit_glyph_class_literal(GlyphClassLiteral),
+        visit_glyph_range(GlyphRange),
+        visit_anchor(Anchor),
+        visit_value_record(ValueRecord),
+        visit_base_table(BaseTable),
+        visit_gdef_table(GdefTable),
+        visit_head_table(HeadTable),
+        visit_hhea_table(HheaTable),
+        visit_name_table(NameTable),
+        visit_os2_table(Os2Table),
+        visit_stat_table(StatTable),
+        visit_vhea_table(VheaTable),
+        visit_vmtx_table(VmtxTable),
+        visit_gdef_class_def(GdefClassDef),
+        visit_gdef_attach(GdefAttach),
+        visit_gdef_ligature_caret(GdefLigatureCaret),
+        visit_name_record(NameRecord),
+        visit_vmtx_entry(VmtxEntry),
+        visit_stat_design_axis(StatDesignAxis),
+        visit_stat_axis_value(StatAxisValue),
+    }
+    tokens {
+        visit_glyph_name(GlyphName),
+        visit_cid(Cid),
+        visit_glyph_class_name(GlyphClassName),
+        visit_tag(Tag),
+    }
+}
+
+/// Walk the tree rooted at `node`, calling the methods of `visitor`.
+///
+/// Nodes are visited in source order, with each node visited before its
+/// children. The glyphs at the ends of a [`GlyphRange`][typed::GlyphRange]
+/// are visited as tokens, but the glyphs between them are not.
+///
+/// ```
+/// use fea_rs::{typed, visit::{self, Visitor, Walk}};
+///
+/// // collect the glyphs referenced in feature blocks
+/// #[derive(Default)]
+/// struct FeatureGlyphs(Vec<String>);
+///
+/// impl Visitor for FeatureGlyphs {
+///     fn visit_glyph_class_def(&mut self, _node: &typed::GlyphClassDef) -> Walk {
+///         Walk::SkipChildren
+///     }
+///
+///     fn visit_glyph_name(&mut self, token: &typed::GlyphName) {
+///         self.0.push(token.text().to_string());
+///     }
+/// }
+///
+/// let (root, _) = fea_rs::parse::parse_string("@x = [a b]; feature liga { sub f i by f_i; } liga;");
+/// let mut glyphs = FeatureGlyphs::default();
+/// visit::walk(&root, &mut glyphs);
+/// assert_eq!(glyphs.0, ["f", "i", "f_i"]);
+/// ```
+pub fn walk(node: &Node, visitor: &mut impl Visitor) {
+    if visit_node(node, visitor) == Walk::Continue {
+        for child in node.iter_children() {
+            match child {
+                NodeOrToken::Node(child) => walk(child, visitor),
+                token => visit_token(token, visitor),
+            }
+        }
+    }
+    visitor.leave_node(node);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, Kind};
+
+    /// Records the glyphs used in each feature, and the names of lookups.
+    #[derive(Default)]
+    struct Collector {
+        feature: Option<String>,
+        glyphs: Vec<(String, String)>,
+        lookups: Vec<String>,
+    }
+
+    impl Visitor for Collector {
+        fn visit_feature(&mut self, node: &typed::Feature) -> Walk {
+            self.feature = node
+                .iter()
+                .find_map(typed::Tag::cast)
+                .map(|tag| tag.text().to_string());
+            Walk::Continue
+        }
+
+        fn visit_lookup_block(&mut self, node: &typed::LookupBlock) -> Walk {
+            let label = node
+                .node()
+                .iter_children()
+                .find(|t| t.kind() == Kind::Label);
+            self.lookups
+                .extend(label.and_then(|t| t.token_text()).map(Into::into));
+            Walk::SkipChildren
+        }
+
+        fn visit_glyph_name(&mut self, token: &typed::GlyphName) {
+            if let Some(feature) = &self.feature {
+                self.glyphs
+                    .push((feature.clone(), token.text().to_string()));
+            }
+        }
+
+        fn leave_node(&mut self, node: &Node) {
+            if node.kind() == Kind::FeatureNode {
+                self.feature = None;
+            }
+        }
+    }
+
+    #[test]
+    fn collect_feature_glyphs() {
+        let fea = "\
+@x = [a b];
+lookup skipped { sub x by y; } skipped;
+feature liga { sub f i by f_i; lookup inner { sub c by d; } inner; } liga;
+feature kern { pos [v w] @x -20; } kern;
+";
+        let (root, _) = parse::parse_string(fea);
+        let mut collector = Collector::default();
+        walk(&root, &mut collector);
+        let glyphs = collector
+            .glyphs
+            .iter()
+            .map(|(feature, glyph)| (feature.as_str(), glyph.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            glyphs,
+            [
+                ("liga", "f"),
+                ("liga", "i"),
+                ("liga", "f_i"),
+                ("kern", "v"),
+                ("kern", "w")
+            ]
+        );
+        assert_eq!(collector.lookups, ["skipped", "inner"]);
+    }
+}
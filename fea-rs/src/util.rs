@@ -7,7 +7,7 @@ pub mod pretty_diff;
 #[cfg(any(test, feature = "test"))]
 pub mod ttx;
 
-pub use highlighting::style_for_kind;
+pub use highlighting::{highlights, style_for_kind, HighlightKind};
 #[cfg(any(test, feature = "diff"))]
 pub use pretty_diff::write_line_diff;
 
@@ -17,3 +17,5 @@ pub static SPACES: &str = "
 pub(crate) static WRITE_RESULTS_VAR: &str = "FEA_WRITE_TEST_OUTPUT";
 #[cfg(any(test, feature = "test"))]
 pub(crate) static VERBOSE: &str = "FEA_VERBOSE";
+#[cfg(any(test, feature = "test"))]
+pub(crate) static UPDATE_EXPECT_VAR: &str = "FEA_UPDATE_EXPECT";
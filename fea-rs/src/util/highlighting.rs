@@ -1,8 +1,8 @@
 //! syntax highlighting functions
 
-use std::{fmt::Write, path::Path};
+use std::{fmt::Write, ops::Range, path::Path};
 
-use crate::{parse::Source, Diagnostic, Kind, Level};
+use crate::{parse::Source, Diagnostic, Kind, Level, Node};
 use ansi_term::{Colour, Style};
 
 /// Return the appropriate visual style for this token kind.
@@ -36,6 +36,60 @@ pub fn style_for_kind(kind: Kind) -> Style {
     }
 }
 
+/// A classification of a token, for syntax highlighting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HighlightKind {
+    /// A keyword, such as `feature` or `sub`
+    Keyword,
+    /// A tag, such as a feature, script, or language tag
+    Tag,
+    /// A glyph name or CID
+    GlyphName,
+    /// A named glyph class or mark class, such as `@LETTERS`
+    GlyphClass,
+    /// A label or a reference to one, such as the name of a lookup or anchor
+    Label,
+    /// A numeric literal
+    Number,
+    /// A string, or the path in an `include` statement
+    String,
+    /// A comment
+    Comment,
+}
+
+impl HighlightKind {
+    /// The highlight kind for a token of this kind, if it should be highlighted.
+    pub fn for_kind(kind: Kind) -> Option<Self> {
+        let highlight = match kind {
+            _ if kind.is_keyword() => HighlightKind::Keyword,
+            Kind::Tag => HighlightKind::Tag,
+            Kind::GlyphName | Kind::Cid => HighlightKind::GlyphName,
+            Kind::NamedGlyphClass => HighlightKind::GlyphClass,
+            Kind::Label | Kind::Ident => HighlightKind::Label,
+            Kind::Number | Kind::Octal | Kind::Hex | Kind::Float | Kind::NumberSuffix => {
+                HighlightKind::Number
+            }
+            Kind::String | Kind::StringUnterminated | Kind::Path => HighlightKind::String,
+            Kind::Comment => HighlightKind::Comment,
+            _ => return None,
+        };
+        Some(highlight)
+    }
+}
+
+/// Classify the tokens of a parsed node, for syntax highlighting.
+///
+/// This yields the range and kind of each token that should be highlighted,
+/// in order; whitespace, punctuation and unrecognized names are skipped.
+/// Ranges are relative to the start of `node`, so for the root of a
+/// [`ParseTree`][crate::ParseTree] with includes they are positions in the
+/// combined tree, and should be mapped back to a source file with its
+/// [`source_map`][crate::ParseTree::source_map].
+pub fn highlights(node: &Node) -> impl Iterator<Item = (Range<usize>, HighlightKind)> + '_ {
+    node.iter_tokens()
+        .filter_map(|token| HighlightKind::for_kind(token.kind).map(|kind| (token.range(), kind)))
+}
+
 //FIXME: get from terminal?
 const MAX_PRINT_WIDTH: usize = 100;
 
@@ -50,14 +104,19 @@ macro_rules! style_or_dont {
 }
 
 /// Given an error and a line's text, write a fancy error message.
+///
+/// `include_chain` is the list of include statements that lead to `source`,
+/// as (including source, offset of statement) pairs, starting with the
+/// innermost; see `SourceList::include_chain`.
 pub(crate) fn write_diagnostic(
     writer: &mut impl Write,
     err: &Diagnostic,
     source: &Source,
+    include_chain: &[(&Source, usize)],
     line_width: Option<usize>,
     colorized: bool,
 ) {
-    write_header(writer, err, source, colorized);
+    write_header(writer, err, source, include_chain, colorized);
 
     let line_width = line_width.unwrap_or(MAX_PRINT_WIDTH);
     let span = err.message.span.range();
@@ -65,17 +124,18 @@ pub(crate) fn write_diagnostic(
     let line_start = source.offset_for_line_number(line_n);
     let err_start = span.start - line_start;
 
-    // if a line is really long, we clip it
+    // if a line is really long, we clip it. Everything after this point only
+    // looks at the clipped text, so the cost does not depend on line length.
     let trim_start = if text.len() > line_width {
         const SLOP: usize = 10; // buffer before start of error when clipping
         let max_trim = (text.len()) - line_width;
-        err_start.saturating_sub(SLOP).min(max_trim)
+        floor_char_boundary(text, err_start.saturating_sub(SLOP).min(max_trim))
     } else {
         0
     };
 
-    let trim_end = (text.len() - trim_start).saturating_sub(line_width);
-    let text = &text[trim_start..text.len() - trim_end];
+    let trim_end = floor_char_boundary(text, (trim_start + line_width).min(text.len()));
+    let text = &text[trim_start..trim_end];
     let ellipsis = if trim_start == 0 { "" } else { "..." };
 
     let line_ws = text.bytes().take_while(u8::is_ascii_whitespace).count();
@@ -93,14 +153,20 @@ pub(crate) fn write_diagnostic(
     .unwrap();
     write!(writer, "{}{} |{} ", blue.prefix(), line_n, blue.suffix()).unwrap();
     writeln!(writer, "{ellipsis}{text}").unwrap();
-    let n_spaces = (span.start - line_start) - trim_start;
+    // the column of the error, in chars, relative to the clipped text
+    let err_offset = floor_char_boundary(text, err_start - trim_start);
+    let n_spaces = text[..err_offset].chars().count();
     // use the whitespace at the front of the line first, so that
     // we don't replace tabs with spaces
     let reuse_ws = n_spaces.min(line_ws);
     let extra_ws = n_spaces - reuse_ws;
 
-    let n_carets = span.end - span.start;
-    let n_carets = n_carets.min(CARETS.len());
+    let n_carets = source
+        .text()
+        .get(span.clone())
+        .map(|span_text| span_text.chars().take(CARETS.len()).count())
+        .unwrap_or(span.end - span.start)
+        .min(CARETS.len());
     let color = style_or_dont!(colorized, err.level.color());
 
     write!(
@@ -124,7 +190,13 @@ pub(crate) fn write_diagnostic(
     .unwrap();
 }
 
-fn write_header(writer: &mut impl Write, err: &Diagnostic, source: &Source, colorized: bool) {
+fn write_header(
+    writer: &mut impl Write,
+    err: &Diagnostic,
+    source: &Source,
+    include_chain: &[(&Source, usize)],
+    colorized: bool,
+) {
     let color = style_or_dont!(colorized, err.level.color());
     let text = err.level.label();
 
@@ -140,6 +212,15 @@ fn write_header(writer: &mut impl Write, err: &Diagnostic, source: &Source, colo
         Path::new(source.path()).display(),
     )
     .unwrap();
+    for (parent, offset) in include_chain {
+        let (line, column) = parent.line_col_for_offset(*offset);
+        writeln!(
+            writer,
+            "{pre}included from{suf} {} {pre}at{suf} {line}:{column}",
+            Path::new(parent.path()).display(),
+        )
+        .unwrap();
+    }
 }
 
 impl Level {
@@ -162,6 +243,15 @@ impl Level {
 
 static CARETS: &str = "^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^";
 
+// the largest char boundary in `text` that is <= `idx`
+fn floor_char_boundary(text: &str, idx: usize) -> usize {
+    let mut idx = idx.min(text.len());
+    while !text.is_char_boundary(idx) {
+        idx -= 1;
+    }
+    idx
+}
+
 pub(crate) fn decimal_digits(n: usize) -> usize {
     (n as f64).log10().floor() as usize + 1
 }
@@ -170,6 +260,39 @@ pub(crate) fn decimal_digits(n: usize) -> usize {
 mod tests {
     use super::*;
 
+    #[test]
+    fn highlight_tokens() {
+        let text =
+            "lookup l1 { sub @A f by f_i; } l1; # hi\nfeature liga { lookup l1; pos a 10; } liga;";
+        let (root, _) = crate::parse::parse_string(text);
+        let highlights = highlights(&root)
+            .map(|(range, kind)| (&text[range], kind))
+            .collect::<Vec<_>>();
+        use HighlightKind::*;
+        assert_eq!(
+            highlights,
+            [
+                ("lookup", Keyword),
+                ("l1", Label),
+                ("sub", Keyword),
+                ("@A", GlyphClass),
+                ("f", GlyphName),
+                ("by", Keyword),
+                ("f_i", GlyphName),
+                ("l1", Label),
+                ("# hi", Comment),
+                ("feature", Keyword),
+                ("liga", Tag),
+                ("lookup", Keyword),
+                ("l1", Label),
+                ("pos", Keyword),
+                ("a", GlyphName),
+                ("10", Number),
+                ("liga", Tag),
+            ]
+        );
+    }
+
     #[test]
     fn highlight_long_line() {
         static A_BAD_LINE: &str = "@COMBINING_MARKS = [ candrabindu-kannada nukta-kannada ssa-kannada.below.kssa ra-kannada.below rVocalicMatra-kannada rrVocalicMatra-kannada ailength-kannada ka-kannada.below kha-kannada.below ga-kannada.below gha-kannada.below nga-kannada.below ca-kannada.below cha-kannada.below ja-kannada.below jha-kannada.below nya-kannada.below tta-kannada.below ttha-kannada.below dda-kannada.below ddha-kannada.below nna-kannada.below ta-kannada.below tha-kannada.below da-kannada.below dha-kannada.below na-kannada.below pa-kannada.below pha-kannada.below ba-kannada.below bha-kannada.below ma-kannada.below ya-kannada.below la-kannada.below va-kannada.below sha-kannada.below ssa-kannada.below sa-kannada.below ha-kannada.below rra-kannada.below lla-kannada.below fa-kannada.below ka_ssa-kannada.below ta_ra-kannada.below ra-kannada.below.following rVocalicMatra-kannada.following rrVocalicMatra-kannada.following ailength-kannada.following ka-kannada.below.following kha-kannada.below.following ga-kannada.below.following gha-kannada.below.following nga-kannada.below.following ca-kannada.below.following cha-kannada.below.following ja-kannada.below.following jha-kannada.below.following nya-kannada.below.following tta-kannada.below.following ttha-kannada.below.following dda-kannada.below.following ddha-kannada.below.following nna-kannada.below.following ta-kannada.below.following tha-kannada.below.following da-kannada.below.following dha-kannada.below.following na-kannada.below.following pa-kannada.below.following pha-kannada.below.following ba-kannada.below.following bha-kannada.below.following ma-kannada.below.following ya-kannada.below.following la-kannada.below.following va-kannada.below.following sha-kannada.below.following ssa-kannada.below.following sa-kannada.below.following ha-kannada.below.following rra-kannada.below.following lla-kannada.below.following fa-kannada.below.following ka_ssa-kannada.below.following ta_ra-kannada.below.following ];";
@@ -177,6 +300,20 @@ mod tests {
         let source = Source::new("test", A_BAD_LINE.into());
         let err = Diagnostic::warning(source.id(), 200..220, "bad!");
         let mut write_to = String::new();
-        write_diagnostic(&mut write_to, &err, &source, None, true);
+        write_diagnostic(&mut write_to, &err, &source, &[], None, true);
+    }
+
+    #[test]
+    fn highlight_long_line_non_ascii() {
+        // clipping must not split a multi-byte character
+        let line = format!("@class = [{}];", "é".repeat(300));
+        let source = Source::new("test", line.into());
+        let err = Diagnostic::warning(source.id(), 402..404, "bad!");
+        let mut write_to = String::new();
+        write_diagnostic(&mut write_to, &err, &source, &[], None, false);
+        let lines = write_to.lines().collect::<Vec<_>>();
+        assert_eq!(lines[3], format!("1 | ...{}", "é".repeat(50)));
+        // carets are aligned by chars, not bytes
+        assert_eq!(lines[4], format!("  | {}^", " ".repeat(8)));
     }
 }
@@ -12,15 +12,9 @@ fn rebase_path(path: &Path, base: &Path) -> PathBuf {
 
     let mut components: Vec<_> = base.components().collect();
 
-    // This is disabled for tests so that we can test it using non-existent
-    // paths, which trigger the is_dir assert.
-    #[cfg(not(test))]
-    {
-        assert!(components.is_empty() || base.is_dir());
-        assert!(path.is_relative());
-    }
-
-    if components.is_empty() {
+    // this is purely lexical, so base does not need to exist; absolute paths
+    // are never rebased.
+    if components.is_empty() || !path.is_relative() {
         return path.to_path_buf();
     }
 
@@ -56,24 +50,29 @@ fn rebase_path(path: &Path, base: &Path) -> PathBuf {
 /// Given a relative path, resolve it to a specific path per [the spec][].
 ///
 /// The second argument is the root of the project, and the third argument is the
-/// path to the *including* file, if one exists.
+/// directory of the *including* file, if one exists. If the path is not found
+/// in either of these, each of `search_paths` is tried in order.
+///
+/// If the path cannot be found, it is returned unchanged.
 ///
 /// [the spec]: http://adobe-type-tools.github.io/afdko/OpenTypeFeatureFileSpecification.html#3-including-files
-pub(crate) fn resolve_path(path: &Path, root: &Path, parent: Option<&Path>) -> PathBuf {
+pub(crate) fn resolve_path(
+    path: &Path,
+    root: &Path,
+    parent: Option<&Path>,
+    search_paths: &[PathBuf],
+) -> PathBuf {
     if path.is_absolute() {
         log::info!("path {} is absolute", path.display());
         return path.to_path_buf();
     }
 
-    if root.join(path).exists() {
-        return rebase_path(path, root);
-    }
-    if let Some(parent) = parent {
-        if parent.join(path).exists() {
-            return rebase_path(path, parent);
-        }
-    }
-    path.to_owned()
+    std::iter::once(root)
+        .chain(parent)
+        .chain(search_paths.iter().map(PathBuf::as_path))
+        .find(|base| base.join(path).exists())
+        .map(|base| rebase_path(path, base))
+        .unwrap_or_else(|| path.to_owned())
 }
 
 #[cfg(test)]
@@ -105,6 +104,10 @@ mod tests {
             Path::new("font/includes/features.fea")
         );
 
+        // absolute paths are left alone
+        let path = Path::new("/font/features.fea");
+        assert_eq!(rebase_path(path, base), path);
+
         let empty_base = Path::new("");
         assert!(!empty_base.is_dir());
         let path = Path::new("font/includes/features.fea");
@@ -113,4 +116,19 @@ mod tests {
             Path::new("font/includes/features.fea")
         );
     }
+
+    #[test]
+    fn resolve_with_search_paths() {
+        let base = Path::new("./test-data/include-resolution-tests");
+        let root = base.join("dir1");
+        let search_paths = [base.join("missing"), base.to_path_buf()];
+
+        // the project root takes precedence
+        let resolved = resolve_path(Path::new("_included3.fea"), &root, None, &search_paths);
+        assert_eq!(resolved, root.join("_included3.fea"));
+        let resolved = resolve_path(Path::new("_included1.fea"), &root, None, &search_paths);
+        assert_eq!(resolved, base.join("_included1.fea"));
+        let resolved = resolve_path(Path::new("missing.fea"), &root, None, &search_paths);
+        assert_eq!(resolved, Path::new("missing.fea"));
+    }
 }
@@ -1,6 +1,7 @@
 //! utilities for compiling and comparing ttx
 
 use std::{
+    borrow::Cow,
     collections::HashMap,
     env::temp_dir,
     ffi::OsStr,
@@ -42,6 +43,38 @@ static IGNORED_TESTS: &[&str] = &[
 /// This can be set during debugging if you want to inspect the generated files.
 static TEMP_DIR_ENV: &str = "TTX_TEMP_DIR";
 
+/// The tables that are included when we compare ttx output.
+const TABLES_TO_COMPARE: &[&str] = &[
+    "head", "name", "BASE", "GDEF", "GSUB", "GPOS", "OS/2", "STAT", "hhea", "vhea",
+];
+
+/// A python script that compiles a FEA file with feaLib, and writes the ttx
+/// for the tables that we compare.
+///
+/// The font is saved and reloaded before being dumped, so that (as with our
+/// own output) the ttx reflects the compiled binary.
+///
+/// arguments: fea path, glyph order path, ttx output path, tables
+static FEALIB_SCRIPT: &str = r#"
+import io
+import sys
+from fontTools.ttLib import TTFont
+from fontTools.feaLib.builder import addOpenTypeFeatures
+
+fea_path, glyph_order_path, out_path, *tables = sys.argv[1:]
+with open(glyph_order_path, encoding="utf-8") as f:
+    glyph_order = f.read().splitlines()
+font = TTFont()
+font.setGlyphOrder(glyph_order)
+addOpenTypeFeatures(font, fea_path)
+data = io.BytesIO()
+font.save(data)
+data.seek(0)
+font = TTFont(data)
+font.setGlyphOrder(glyph_order)
+font.saveXML(out_path, tables=[tag for tag in tables if tag in font])
+"#;
+
 /// The combined results of this set of tests
 #[derive(Default, Serialize, Deserialize)]
 pub struct Report {
@@ -90,6 +123,9 @@ pub enum TestResult {
     /// A call to the `ttx` utility failed
     #[allow(missing_docs)]
     TtxFail { code: Option<i32>, std_err: String },
+    /// fonttools' feaLib failed to compile the input
+    #[allow(missing_docs)]
+    FeaLibFail { code: Option<i32>, std_err: String },
     /// The output did not match the expectation
     #[allow(missing_docs)]
     CompareFail {
@@ -146,6 +182,10 @@ impl<'a> Filter<'a> {
 ///
 /// `filter` is an optional comma-separated list of strings. If present, only
 /// tests which contain one of the strings in the list will be run.
+///
+/// If the `FEA_UPDATE_EXPECT` environment variable is set, any `.expected_diff`
+/// files that no longer match will be rewritten; see
+/// [`Report::bless_expected_diffs`].
 pub fn run_all_tests(fonttools_data_dir: impl AsRef<Path>, filter: Option<&String>) -> Report {
     let glyph_map = fonttools_test_glyph_order();
     let filter = Filter::new(filter);
@@ -156,7 +196,11 @@ pub fn run_all_tests(fonttools_data_dir: impl AsRef<Path>, filter: Option<&Strin
         .map(|path| run_test(path, &glyph_map, &var_info))
         .collect::<Vec<_>>();
 
-    finalize_results(result)
+    let mut report = finalize_results(result);
+    if std::env::var(super::UPDATE_EXPECT_VAR).is_ok() {
+        report.bless_expected_diffs();
+    }
+    report
 }
 
 /// Convert a vector of test results into a report.
@@ -244,17 +288,7 @@ pub(crate) fn run_test(
         if is_variable(&path) {
             compiler = compiler.with_variable_info(fvar);
         }
-
-        match compiler.compile_binary() {
-            // this means we have a test case that doesn't exist or something weird
-            Err(CompilerError::SourceLoad(err)) => panic!("{err}"),
-            Err(CompilerError::WriteFail(err)) => panic!("{err}"),
-            Err(CompilerError::ParseFail(errs)) => Err(TestResult::ParseFail(errs.to_string(true))),
-            Err(CompilerError::ValidationFail(errs) | CompilerError::CompilationFail(errs)) => {
-                Err(TestResult::CompileFail(errs.to_string(true)))
-            }
-            Ok(result) => compare_ttx(&result, &path),
-        }
+        compile_for_ttx(compiler).and_then(|result| compare_ttx(&result, &path, glyph_map))
     }) {
         Err(_) => Err(TestResult::Panic),
         Ok(Err(reason)) => Err(reason),
@@ -263,6 +297,91 @@ pub(crate) fn run_test(
     .map_err(|reason| TestCase { reason, path })
 }
 
+/// Compile the FEA file at `path` with both fea-rs and fonttools' feaLib,
+/// and compare the ttx output of the two.
+///
+/// This is intended for checking arbitrary user files, in order to report
+/// differences from fonttools. It requires `python3`, with fonttools
+/// installed, as well as the `ttx` executable. Variable fonts are not
+/// currently supported.
+///
+/// In the case of a [`TestResult::CompareFail`], the `expected` field contains
+/// the output of feaLib.
+pub fn compare_with_fealib(path: &Path, glyph_map: &GlyphMap) -> TestResult {
+    let result = match std::panic::catch_unwind(|| {
+        compile_for_ttx(
+            Compiler::new(path, glyph_map)
+                .print_warnings(false)
+                .with_opts(Opts::new().make_post_table(true)),
+        )
+    }) {
+        Err(_) => return TestResult::Panic,
+        Ok(Err(reason)) => return reason,
+        Ok(Ok(font_data)) => match run_ttx(&font_data, path, glyph_map) {
+            Ok(ttx) => ttx,
+            Err(reason) => return reason,
+        },
+    };
+    let expected = match compile_with_fealib(path, glyph_map) {
+        Ok(ttx) => ttx,
+        Err(reason) => return reason,
+    };
+    if expected == result {
+        return TestResult::Success;
+    }
+    let diff_percent = compute_diff_percentage(&expected, &result);
+    TestResult::CompareFail {
+        expected,
+        result,
+        diff_percent,
+    }
+}
+
+fn compile_for_ttx(compiler: Compiler) -> Result<Vec<u8>, TestResult> {
+    match compiler.compile_binary() {
+        // this means we have a test case that doesn't exist or something weird
+        Err(CompilerError::SourceLoad(err)) => panic!("{err}"),
+        Err(CompilerError::WriteFail(err)) => panic!("{err}"),
+        Err(CompilerError::ParseFail(errs)) => Err(TestResult::ParseFail(errs.to_string(true))),
+        Err(CompilerError::ValidationFail(errs) | CompilerError::CompilationFail(errs)) => {
+            Err(TestResult::CompileFail(errs.to_string(true)))
+        }
+        Ok(result) => Ok(result),
+    }
+}
+
+/// Compile with feaLib, returning the ttx of the tables we compare.
+fn compile_with_fealib(fea_path: &Path, glyph_map: &GlyphMap) -> Result<String, TestResult> {
+    let temp_path = get_temp_dir().join(get_temp_file_name(fea_path));
+    let glyph_order_path = temp_path.with_extension("txt");
+    let ttx_path = temp_path.with_extension("fealib.ttx");
+    let glyph_order = glyph_map
+        .reverse_map()
+        .values()
+        .map(|ident| ident.post_name().into_owned())
+        .collect::<Vec<_>>()
+        .join("\n");
+    std::fs::write(&glyph_order_path, glyph_order).unwrap();
+
+    let output = Command::new("python3")
+        .arg("-c")
+        .arg(FEALIB_SCRIPT)
+        .arg(fea_path)
+        .arg(&glyph_order_path)
+        .arg(&ttx_path)
+        .args(TABLES_TO_COMPARE)
+        .output()
+        .expect("failed to execute python3");
+    if !output.status.success() {
+        return Err(TestResult::FeaLibFail {
+            code: output.status.code(),
+            std_err: String::from_utf8_lossy(&output.stderr).into_owned(),
+        });
+    }
+    let result = std::fs::read_to_string(ttx_path).unwrap();
+    Ok(rewrite_glyph_names(&rewrite_ttx(&result), glyph_map))
+}
+
 /// Convert diagnostics to a printable string
 pub fn stringify_diagnostics(root: &ParseTree, diagnostics: &[Diagnostic]) -> String {
     let mut out = String::new();
@@ -304,44 +423,16 @@ fn get_temp_file_name(in_file: &Path) -> PathBuf {
     Path::new(&format!("{stem}_{millis}")).with_extension("ttf")
 }
 
-fn compare_ttx(font_data: &[u8], fea_path: &Path) -> Result<(), TestResult> {
+fn compare_ttx(font_data: &[u8], fea_path: &Path, glyph_map: &GlyphMap) -> Result<(), TestResult> {
     let ttx_path = fea_path.with_extension("ttx");
     let expected_diff_path = fea_path.with_extension("expected_diff");
-    let temp_path = get_temp_dir().join(get_temp_file_name(fea_path));
-    std::fs::write(&temp_path, font_data).unwrap();
-
-    const TO_WRITE: &[&str] = &[
-        "head", "name", "BASE", "GDEF", "GSUB", "GPOS", "OS/2", "STAT", "hhea", "vhea",
-    ];
-
-    let mut cmd = Command::new("ttx");
-    for table in TO_WRITE {
-        cmd.arg("-t").arg(table);
-    }
-    let status = cmd
-        .arg(&temp_path)
-        .output()
-        .unwrap_or_else(|_| panic!("failed to execute for path {}", fea_path.display()));
-    if !status.status.success() {
-        let std_err = String::from_utf8_lossy(&status.stderr).into_owned();
-        return Err(TestResult::TtxFail {
-            code: status.status.code(),
-            std_err,
-        });
-    }
-
-    let ttx_out_path = temp_path.with_extension("ttx");
-    assert!(ttx_out_path.exists());
-
-    let result = std::fs::read_to_string(ttx_out_path).unwrap();
-
-    let result = rewrite_ttx(&result);
+    let result = run_ttx(font_data, fea_path, glyph_map)?;
 
     let expected = ttx_path
         .exists()
         .then(|| std::fs::read_to_string(&ttx_path).unwrap())
         .unwrap_or_default();
-    let expected = rewrite_ttx(&expected);
+    let expected = rewrite_glyph_names(&rewrite_ttx(&expected), glyph_map);
 
     if expected_diff_path.exists() {
         let expected_diff = std::fs::read_to_string(&expected_diff_path).unwrap();
@@ -372,18 +463,61 @@ fn compare_ttx(font_data: &[u8], fea_path: &Path) -> Result<(), TestResult> {
     })
 }
 
+/// Write the font to a temporary file, and return the (rewritten) ttx of the
+/// tables we compare.
+fn run_ttx(font_data: &[u8], fea_path: &Path, glyph_map: &GlyphMap) -> Result<String, TestResult> {
+    let temp_path = get_temp_dir().join(get_temp_file_name(fea_path));
+    std::fs::write(&temp_path, font_data).unwrap();
+
+    let mut cmd = Command::new("ttx");
+    for table in TABLES_TO_COMPARE {
+        cmd.arg("-t").arg(table);
+    }
+    let status = cmd
+        .arg(&temp_path)
+        .output()
+        .unwrap_or_else(|_| panic!("failed to execute for path {}", fea_path.display()));
+    if !status.status.success() {
+        let std_err = String::from_utf8_lossy(&status.stderr).into_owned();
+        return Err(TestResult::TtxFail {
+            code: status.status.code(),
+            std_err,
+        });
+    }
+
+    let ttx_out_path = temp_path.with_extension("ttx");
+    assert!(ttx_out_path.exists());
+
+    let result = std::fs::read_to_string(ttx_out_path).unwrap();
+    Ok(rewrite_glyph_names(&rewrite_ttx(&result), glyph_map))
+}
+
 // we want to be able to add a comment when we save an 'expected diff', so that
 // a future reader can understand our justification
 fn diffs_are_equal_ignoring_comments(one: &str, two: &str) -> bool {
     strip_comments(one) == strip_comments(two)
 }
 
+fn is_comment_or_blank(line: &str) -> bool {
+    let line = line.trim();
+    line.is_empty() || line.starts_with('#')
+}
+
+// combine the leading comments of an existing expected diff with a new diff
+fn bless_diff(old: &str, new: &str) -> String {
+    let mut result = String::new();
+    let header = old.lines().take_while(|line| is_comment_or_blank(line));
+    let body = new.lines().skip_while(|line| is_comment_or_blank(line));
+    for line in header.chain(body) {
+        result.push_str(line);
+        result.push('\n');
+    }
+    result
+}
+
 fn strip_comments(s: &str) -> String {
     s.lines()
-        .skip_while(|line| {
-            let line = line.trim();
-            line.is_empty() || line.starts_with('#')
-        })
+        .skip_while(|line| is_comment_or_blank(line))
         .collect()
 }
 
@@ -429,6 +563,52 @@ fn rewrite_ttx(input: &str) -> String {
     out
 }
 
+/// Replace fonttools' fallback glyph names with the names in the glyph map.
+///
+/// If a font's glyph names are unavailable, fonttools names each glyph 'glyph'
+/// followed by its id, zero-padded to (at least) five digits. We map these
+/// back to the names (or CIDs) in the glyph map, so that output is comparable
+/// regardless of how the glyphs were named. Names that are themselves in the
+/// glyph map are left alone.
+fn rewrite_glyph_names(input: &str, glyph_map: &GlyphMap) -> String {
+    let names = glyph_map.reverse_map().into_values().collect::<Vec<_>>();
+    let rewrite_name = |name: &str| -> Option<Cow<str>> {
+        let digits = name.strip_prefix("glyph")?;
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        if glyph_map.contains(name) {
+            return None;
+        }
+        names
+            .get(digits.parse::<usize>().ok()?)
+            .map(GlyphIdent::post_name)
+    };
+
+    let mut out = String::with_capacity(input.len());
+    // glyph names only appear in attribute values, either alone or as a
+    // comma-separated list (e.g. ligature components)
+    for (i, chunk) in input.split('"').enumerate() {
+        if i > 0 {
+            out.push('"');
+        }
+        if i % 2 == 0 || !chunk.contains("glyph") {
+            out.push_str(chunk);
+            continue;
+        }
+        for (j, name) in chunk.split(',').enumerate() {
+            if j > 0 {
+                out.push(',');
+            }
+            match rewrite_name(name) {
+                Some(new_name) => out.push_str(&new_name),
+                None => out.push_str(name),
+            }
+        }
+    }
+    out
+}
+
 fn write_lines(f: &mut impl Write, lines: &[&str], line_num: usize, prefix: char) {
     writeln!(f, "L{}", line_num).unwrap();
     for line in lines {
@@ -551,6 +731,34 @@ impl Report {
         }
     }
 
+    /// Rewrite any `.expected_diff` files that did not match the actual diff.
+    ///
+    /// This is intended for when the output has changed intentionally. Any
+    /// comments at the top of the existing file (explaining why the diff is
+    /// acceptable) are preserved. The updated tests are marked as successful,
+    /// and a summary of the updated files is printed to stderr.
+    ///
+    /// Returns the paths of the files that were updated.
+    pub fn bless_expected_diffs(&mut self) -> Vec<PathBuf> {
+        let mut updated = Vec::new();
+        for case in self.results.iter_mut() {
+            if let TestResult::ExpectedDiffFail { expected, result } = &case.reason {
+                let path = case.path.with_extension("expected_diff");
+                std::fs::write(&path, bless_diff(expected, result)).unwrap();
+                case.reason = TestResult::Success;
+                updated.push(path);
+            }
+        }
+
+        if !updated.is_empty() {
+            eprintln!("updated {} expected_diff file(s):", updated.len());
+            for path in &updated {
+                eprintln!("  {}", path.display());
+            }
+        }
+        updated
+    }
+
     /// Return a type that can print comparison results
     pub fn compare_printer<'a, 'b: 'a>(&'b self, old: &'a Report) -> impl std::fmt::Debug + 'a {
         ReportComparePrinter { old, new: self }
@@ -576,6 +784,7 @@ impl Report {
                 TestResult::CompileFail(_) => summary.compile += 1,
                 TestResult::UnexpectedSuccess
                 | TestResult::TtxFail { .. }
+                | TestResult::FeaLibFail { .. }
                 | TestResult::ExpectedDiffFail { .. } => summary.other += 1,
                 TestResult::CompareFail { diff_percent, .. } => {
                     summary.compare += 1;
@@ -596,6 +805,7 @@ impl TestResult {
             Self::CompileFail(_) => 4,
             Self::UnexpectedSuccess => 6,
             Self::TtxFail { .. } => 10,
+            Self::FeaLibFail { .. } => 11,
             Self::ExpectedDiffFail { .. } => 15,
             Self::CompareFail { .. } => 50,
         }
@@ -759,6 +969,9 @@ impl Display for ReasonPrinter<'_> {
             TestResult::TtxFail { code, std_err } => {
                 write!(f, "ttx failure ({:?}) stderr:\n{}", code, std_err)
             }
+            TestResult::FeaLibFail { code, std_err } => {
+                write!(f, "feaLib failure ({:?}) stderr:\n{}", code, std_err)
+            }
             TestResult::ExpectedDiffFail { expected, result } => {
                 if self.verbose {
                     writeln!(f, "expected diff fail")?;
@@ -818,3 +1031,38 @@ impl Display for ReportSummary {
         write!(f, "passed {passed}/{total} tests: ({panic} panics {parse} unparsed {compile} compile) {perc:.2}% avg diff")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrite_fallback_glyph_names() {
+        let glyph_map: GlyphMap = [".notdef", "a", "b", "glyph00003"]
+            .into_iter()
+            .map(GlyphIdent::from)
+            .chain([800_u16, 801].into_iter().map(GlyphIdent::Cid))
+            .collect();
+        let input = r#"<Substitution in="glyph00001" out="glyph2"/>
+<Glyph value="glyph00003"/>
+<Ligature components="glyph00004,glyph000005" glyph="glyph123456"/>
+<Glyph value="glyphs"/> glyph00001
+"#;
+        let expected = r#"<Substitution in="a" out="b"/>
+<Glyph value="glyph00003"/>
+<Ligature components="cid00800,cid00801" glyph="glyph123456"/>
+<Glyph value="glyphs"/> glyph00001
+"#;
+        assert_eq!(rewrite_glyph_names(input, &glyph_map), expected);
+    }
+
+    #[test]
+    fn bless_diff_keeps_comments() {
+        let old = "# generated\n#\n# Note: a good reason\nL1\n>  old\n";
+        let new = plain_text_diff("same\nleft\n", "same\nright\n");
+        let blessed = bless_diff(old, &new);
+        assert!(blessed.starts_with("# generated\n#\n# Note: a good reason\nL1\n"));
+        assert!(diffs_are_equal_ignoring_comments(&blessed, &new));
+        assert!(!diffs_are_equal_ignoring_comments(&blessed, old));
+    }
+}